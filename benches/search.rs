@@ -0,0 +1,43 @@
+// 置き場所の探索の速さ。スレッド 1 本と、全部のコアを使ったときを比べる
+use criterion::{criterion_group, criterion_main, Criterion};
+use rayon::ThreadPoolBuilder;
+
+use tetris::ai::{moves, search, Lookahead, Weights};
+use tetris::sim::GameState;
+
+// 穴のあるせり上がりを何段か入れた、中盤くらいの盤面
+fn midgame() -> GameState {
+  let mut state = GameState::new(vec![6, 7, 3, 2, 4, 5, 1]);
+  state.receive_garbage(&[0, 3, 3, 7, 9, 1]);
+  state
+}
+
+fn bench_search(c: &mut Criterion) {
+  let state = midgame();
+  let weights = Weights::default();
+  c.bench_function("moves", |b| b.iter(|| moves(&state)));
+
+  let single = ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+  let all = ThreadPoolBuilder::new().build().unwrap();
+  let lookaheads = [
+    Lookahead::default(),
+    Lookahead {
+      depth: 3,
+      width: 4,
+      hold: true,
+    },
+  ];
+  for lookahead in lookaheads.iter() {
+    let name = format!("search depth {} width {}", lookahead.depth, lookahead.width);
+    c.bench_function(&format!("{} / 1 thread", name), |b| {
+      b.iter(|| single.install(|| search(&state, &weights, lookahead)))
+    });
+    c.bench_function(
+      &format!("{} / {} threads", name, all.current_num_threads()),
+      |b| b.iter(|| all.install(|| search(&state, &weights, lookahead))),
+    );
+  }
+}
+
+criterion_group!(benches, bench_search);
+criterion_main!(benches);
@@ -0,0 +1,118 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{save, AppState};
+
+const ACCESSIBILITY_FILE: &str = "accessibility.ron";
+// 選べる文字の大きさの倍率
+pub const UI_SCALES: [f32; 3] = [1., 1.5, 2.];
+// 枠を太くしたときに、マスの間を広げる分 (マスの幅に対する割合)
+const THICK_BORDER: f32 = 0.1;
+// くっきり見える色 (操作中, 積まれたもの) と、ゴーストの色
+pub const HIGH_CONTRAST_BLOCKS: (Color, Color) = (Color::rgb(1.0, 0.85, 0.0), Color::WHITE);
+pub const HIGH_CONTRAST_GHOST: Color = Color::rgba(1.0, 0.85, 0.0, 0.6);
+
+// 目の見えにくい人向けの表示の設定。プロフィールごとに保存する
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Accessibility {
+  // ブロックを背景とはっきり分かれる色にし、背景は黒にする
+  pub high_contrast: bool,
+  // マスの間の線を太くする
+  pub thick_borders: bool,
+  // HUD とメニューの文字の倍率。UI_SCALES のどれか
+  pub ui_scale: f32,
+}
+
+impl Default for Accessibility {
+  fn default() -> Self {
+    Self {
+      high_contrast: false,
+      thick_borders: false,
+      ui_scale: 1.,
+    }
+  }
+}
+
+impl Accessibility {
+  pub fn load() -> Self {
+    save::load(ACCESSIBILITY_FILE)
+  }
+
+  pub fn store(&self) {
+    if let Err(e) = save::store(ACCESSIBILITY_FILE, self) {
+      error!("failed to save accessibility settings: {}", e);
+    }
+  }
+
+  // ブロックを塗らずに空けておくマスの幅の割合を、ふだんより増やす分
+  pub fn border(&self) -> f32 {
+    if self.thick_borders {
+      THICK_BORDER
+    } else {
+      0.
+    }
+  }
+
+  // 文字の倍率を 1 段大きく (up が false なら小さく) する。端では止まる
+  pub fn step_scale(&mut self, up: bool) {
+    let i = UI_SCALES
+      .iter()
+      .position(|&s| s >= self.ui_scale)
+      .unwrap_or(UI_SCALES.len() - 1);
+    let i = if up {
+      (i + 1).min(UI_SCALES.len() - 1)
+    } else {
+      i.saturating_sub(1)
+    };
+    self.ui_scale = UI_SCALES[i];
+  }
+}
+
+// 文字を作ったときの大きさ。倍率を変えたらここから計算し直す
+struct BaseFontSize(Vec<f32>);
+
+pub struct AccessibilityPlugin;
+
+impl Plugin for AccessibilityPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    // main で ProfileList::load 済みなので、選択中のプロフィールから読める
+    app
+      .insert_resource(Accessibility::load())
+      .add_system_set(
+        SystemSet::on_exit(AppState::Title).with_system(reload_accessibility.system()),
+      )
+      .add_system(scale_text.system());
+  }
+}
+
+fn reload_accessibility(mut accessibility: ResMut<Accessibility>) {
+  *accessibility = Accessibility::load();
+}
+
+// 画面の文字をすべて設定の倍率で描く
+fn scale_text(
+  mut commands: Commands,
+  accessibility: Res<Accessibility>,
+  mut new_query: Query<(Entity, &mut Text), Without<BaseFontSize>>,
+  mut query: Query<(&BaseFontSize, &mut Text)>,
+) {
+  let scale = accessibility.ui_scale;
+  for (entity, mut text) in new_query.iter_mut() {
+    let base: Vec<f32> = text.sections.iter().map(|s| s.style.font_size).collect();
+    if scale != 1. {
+      for section in text.sections.iter_mut() {
+        section.style.font_size *= scale;
+      }
+    }
+    commands.entity(entity).insert(BaseFontSize(base));
+  }
+  if !accessibility.is_changed() {
+    return;
+  }
+  for (base, mut text) in query.iter_mut() {
+    for (section, &size) in text.sections.iter_mut().zip(base.0.iter()) {
+      section.style.font_size = size * scale;
+    }
+  }
+}
@@ -0,0 +1,494 @@
+use std::collections::BTreeSet;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::accessibility::{Accessibility, HIGH_CONTRAST_BLOCKS, HIGH_CONTRAST_GHOST};
+use crate::config::Config;
+use crate::menu::MenuInput;
+use crate::skins::{next_skin, SkinLibrary, SKIN_DIR};
+use crate::stats::Statistics;
+use crate::{
+  overlay_text, save, AppState, BlockStacked, Fonts, GameClock, GameReset, LinesCleared, Materials,
+};
+
+const SAVE_FILE: &str = "achievements.ron";
+const TOTAL_LINES: u32 = 100;
+const SPRINT_LINES: u32 = 40;
+const SPRINT_SECONDS: f64 = 60.;
+const COMBO: u32 = 10;
+// 動きを減らす設定のとき、背景色が 1 秒に変わる量 (各色 0.0 から 1.0)
+const BACKGROUND_FADE: f32 = 0.2;
+// ふだんのゴーストの色
+const GHOST: Color = Color::rgba(0.7, 0.7, 0.7, 0.3);
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Serialize, Deserialize)]
+pub enum Achievement {
+  FirstTetris,
+  HundredLines,
+  // 1ゲームで 40 ライン消すまでが 60 秒未満
+  Sprint,
+  // 10 回続けてライン消去
+  Combo,
+}
+
+impl Achievement {
+  pub const ALL: [Achievement; 4] = [
+    Achievement::FirstTetris,
+    Achievement::HundredLines,
+    Achievement::Sprint,
+    Achievement::Combo,
+  ];
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      Achievement::FirstTetris => "First Tetris",
+      Achievement::HundredLines => "Centurion",
+      Achievement::Sprint => "Sprinter",
+      Achievement::Combo => "Chain Reaction",
+    }
+  }
+
+  pub fn description(&self) -> String {
+    match self {
+      Achievement::FirstTetris => "clear 4 lines at once".to_string(),
+      Achievement::HundredLines => format!("clear {} lines in total", TOTAL_LINES),
+      Achievement::Sprint => format!(
+        "clear {} lines in under {:.0}s",
+        SPRINT_LINES, SPRINT_SECONDS
+      ),
+      Achievement::Combo => format!("clear lines with {} pieces in a row", COMBO),
+    }
+  }
+
+  pub fn reward(&self) -> Cosmetic {
+    match self {
+      Achievement::FirstTetris => Cosmetic::Skin(Skin::Neon),
+      Achievement::HundredLines => Cosmetic::Theme(Theme::Midnight),
+      Achievement::Sprint => Cosmetic::Track(Track::Sprint),
+      Achievement::Combo => Cosmetic::Skin(Skin::Gold),
+    }
+  }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Cosmetic {
+  Skin(Skin),
+  Theme(Theme),
+  Track(Track),
+}
+
+impl Cosmetic {
+  pub fn name(&self) -> String {
+    match self {
+      Cosmetic::Skin(skin) => format!("{:?} blocks", skin),
+      Cosmetic::Theme(theme) => format!("{:?} theme", theme),
+      Cosmetic::Track(track) => format!("{:?} music", track),
+    }
+  }
+
+  fn is_unlocked(&self, unlocked: &BTreeSet<Achievement>) -> bool {
+    Achievement::ALL
+      .iter()
+      .filter(|a| a.reward() == *self)
+      .all(|a| unlocked.contains(a))
+  }
+}
+
+// ブロックの色 (操作中, 積まれたもの)
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum Skin {
+  #[default]
+  Classic,
+  Neon,
+  Gold,
+}
+
+impl Skin {
+  const ALL: [Skin; 3] = [Skin::Classic, Skin::Neon, Skin::Gold];
+
+  fn colors(&self) -> (Color, Color) {
+    match self {
+      Skin::Classic => (Color::rgb(0.7, 0.7, 0.7), Color::rgb(0.1, 0.1, 0.1)),
+      Skin::Neon => (Color::rgb(0.2, 0.9, 1.0), Color::rgb(0.1, 0.3, 0.4)),
+      Skin::Gold => (Color::rgb(1.0, 0.8, 0.2), Color::rgb(0.35, 0.28, 0.1)),
+    }
+  }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum Theme {
+  #[default]
+  Dark,
+  Midnight,
+}
+
+impl Theme {
+  const ALL: [Theme; 2] = [Theme::Dark, Theme::Midnight];
+
+  fn background(&self) -> Color {
+    match self {
+      Theme::Dark => Color::rgb(0.04, 0.04, 0.04),
+      Theme::Midnight => Color::rgb(0.02, 0.03, 0.12),
+    }
+  }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum Track {
+  #[default]
+  Theme,
+  Sprint,
+}
+
+impl Track {
+  const ALL: [Track; 2] = [Track::Theme, Track::Sprint];
+
+  // assets/ からの相対パス
+  pub fn path(&self) -> &'static str {
+    match self {
+      Track::Theme => "music/theme.mp3",
+      Track::Sprint => "music/sprint.mp3",
+    }
+  }
+}
+
+// 解除済みの中から次のものを選ぶ
+fn cycle<T: Copy + PartialEq>(
+  all: &[T],
+  current: T,
+  wrap: fn(T) -> Cosmetic,
+  unlocked: &BTreeSet<Achievement>,
+) -> T {
+  let i = all.iter().position(|&c| c == current).unwrap_or(0);
+  (1..=all.len())
+    .map(|n| all[(i + n) % all.len()])
+    .find(|&c| wrap(c).is_unlocked(unlocked))
+    .unwrap_or(current)
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Cosmetics {
+  pub skin: Skin,
+  pub theme: Theme,
+  pub track: Track,
+}
+
+pub struct AchievementUnlocked(pub Achievement);
+
+// プロフィールごとの解除状況と見た目の選択
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Achievements {
+  pub unlocked: BTreeSet<Achievement>,
+  pub total_lines: u32,
+  pub cosmetics: Cosmetics,
+  // skins/ から入れたスキン。あれば同梱のブロックの色より優先する
+  pub custom_skin: Option<String>,
+}
+
+impl Achievements {
+  fn store(&self) {
+    if let Err(e) = save::store(SAVE_FILE, self) {
+      error!("failed to save achievements: {}", e);
+    }
+  }
+}
+
+// 1ゲーム分の進み具合から条件を満たした実績を探す
+#[derive(Default)]
+pub struct AchievementChecker {
+  lines: u32,
+  combo: u32,
+  // 最後に積んだミノでまだラインを消していない
+  pending_lock: bool,
+}
+
+impl AchievementChecker {
+  pub fn record_lock(&mut self) {
+    if self.pending_lock {
+      self.combo = 0;
+    }
+    self.pending_lock = true;
+  }
+
+  // elapsed はゲーム開始からの秒数、total_lines はこの消去を含めた通算
+  pub fn record_clear(&mut self, lines: u32, elapsed: f64, total_lines: u32) -> Vec<Achievement> {
+    self.pending_lock = false;
+    self.combo += 1;
+    self.lines += lines;
+    let mut res = vec![];
+    if lines >= 4 {
+      res.push(Achievement::FirstTetris);
+    }
+    if total_lines >= TOTAL_LINES {
+      res.push(Achievement::HundredLines);
+    }
+    if self.lines >= SPRINT_LINES && elapsed < SPRINT_SECONDS {
+      res.push(Achievement::Sprint);
+    }
+    if self.combo >= COMBO {
+      res.push(Achievement::Combo);
+    }
+    res
+  }
+}
+
+struct AchievementsScreen;
+struct AchievementsText;
+
+pub struct AchievementsPlugin;
+
+impl Plugin for AchievementsPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(save::load::<Achievements>(SAVE_FILE))
+      .insert_resource(AchievementChecker::default())
+      .add_event::<AchievementUnlocked>()
+      .add_system_set(SystemSet::on_exit(AppState::Title).with_system(reload_achievements.system()))
+      .add_system_set(SystemSet::on_enter(AppState::Playing).with_system(restart_checker.system()))
+      .add_system(check_achievements.system())
+      .insert_resource(Background(Theme::default().background()))
+      .add_system(apply_cosmetics.system())
+      .add_system(fade_background.system())
+      .add_system_set(
+        SystemSet::on_enter(AppState::Achievements)
+          .with_system(reload_achievements.system())
+          .with_system(setup_achievements.system()),
+      )
+      .add_system_set(
+        SystemSet::on_update(AppState::Achievements).with_system(achievements_input.system()),
+      )
+      .add_system_set(
+        SystemSet::on_exit(AppState::Achievements).with_system(close_achievements.system()),
+      );
+  }
+}
+
+// プロフィールが変わったかもしれないので読み直す
+fn reload_achievements(mut achievements: ResMut<Achievements>) {
+  *achievements = save::load(SAVE_FILE);
+}
+
+fn restart_checker(mut checker: ResMut<AchievementChecker>) {
+  *checker = AchievementChecker::default();
+}
+
+fn check_achievements(
+  clock: Res<GameClock>,
+  stats: Res<Statistics>,
+  mut checker: ResMut<AchievementChecker>,
+  mut achievements: ResMut<Achievements>,
+  mut unlocked_events: EventWriter<AchievementUnlocked>,
+  mut stacked_events: EventReader<BlockStacked>,
+  mut cleared_events: EventReader<LinesCleared>,
+  mut reset_events: EventReader<GameReset>,
+) {
+  if reset_events.iter().count() > 0 {
+    *checker = AchievementChecker::default();
+  }
+  for _ in stacked_events.iter() {
+    checker.record_lock();
+  }
+  let elapsed = clock.0 - stats.start;
+  let mut changed = false;
+  for event in cleared_events.iter() {
+    achievements.total_lines += event.0;
+    changed = true;
+    let total_lines = achievements.total_lines;
+    for achievement in checker.record_clear(event.0, elapsed, total_lines) {
+      if achievements.unlocked.insert(achievement) {
+        info!(
+          "achievement unlocked: {} ({})",
+          achievement.name(),
+          achievement.reward().name()
+        );
+        unlocked_events.send(AchievementUnlocked(achievement));
+      }
+    }
+  }
+  if changed {
+    achievements.store();
+  }
+}
+
+// 背景色を変えるときに目指す色。動きを減らす設定ならゆっくり近づける
+struct Background(Color);
+
+// current の各色を target へ最大 step だけ近づける
+pub fn fade_toward(current: Color, target: Color, step: f32) -> Color {
+  let near = |from: f32, to: f32| from + (to - from).clamp(-step, step);
+  Color::rgba(
+    near(current.r(), target.r()),
+    near(current.g(), target.g()),
+    near(current.b(), target.b()),
+    near(current.a(), target.a()),
+  )
+}
+
+// くっきりした色にする設定のときは、見た目の褒美より優先する
+fn apply_cosmetics(
+  achievements: Res<Achievements>,
+  accessibility: Res<Accessibility>,
+  library: Res<SkinLibrary>,
+  materials: Option<Res<Materials>>,
+  mut color_materials: ResMut<Assets<ColorMaterial>>,
+  mut background: ResMut<Background>,
+) {
+  let changed = achievements.is_changed() || accessibility.is_changed() || library.is_changed();
+  let materials = match materials {
+    Some(materials) if changed || materials.is_added() => materials,
+    _ => return,
+  };
+  let cosmetics = achievements.cosmetics;
+  let high_contrast = accessibility.high_contrast;
+  let custom = match achievements.custom_skin.as_deref() {
+    Some(name) if !high_contrast => library.get(name),
+    _ => None,
+  };
+  let (active, stacked) = match custom {
+    _ if high_contrast => HIGH_CONTRAST_BLOCKS,
+    Some(skin) => (skin.active, skin.stacked),
+    None => cosmetics.skin.colors(),
+  };
+  let texture = custom.and_then(|skin| skin.texture.clone());
+  if let Some(material) = color_materials.get_mut(&materials.gray_block) {
+    material.color = active;
+    material.texture = texture.clone();
+  }
+  if let Some(material) = color_materials.get_mut(&materials.white_block) {
+    material.color = stacked;
+    material.texture = texture;
+  }
+  if let Some(material) = color_materials.get_mut(&materials.ghost_block) {
+    material.color = if high_contrast {
+      HIGH_CONTRAST_GHOST
+    } else {
+      GHOST
+    };
+  }
+  background.0 = match custom.and_then(|skin| skin.background) {
+    _ if high_contrast => Color::BLACK,
+    Some(color) => color,
+    None => cosmetics.theme.background(),
+  };
+}
+
+fn fade_background(
+  time: Res<Time>,
+  config: Res<Config>,
+  background: Res<Background>,
+  mut clear_color: ResMut<ClearColor>,
+) {
+  if clear_color.0 == background.0 {
+    return;
+  }
+  clear_color.0 = if config.reduce_motion {
+    fade_toward(
+      clear_color.0,
+      background.0,
+      BACKGROUND_FADE * time.delta_seconds(),
+    )
+  } else {
+    background.0
+  };
+}
+
+fn setup_achievements(mut commands: Commands, fonts: Res<Fonts>, materials: Res<Materials>) {
+  let mut text = overlay_text(&fonts);
+  text.style.position = Rect {
+    top: Val::Px(120.0),
+    left: Val::Px(30.0),
+    ..Default::default()
+  };
+  commands
+    .spawn_bundle(NodeBundle {
+      style: Style {
+        position_type: PositionType::Absolute,
+        size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+        ..Default::default()
+      },
+      material: materials.panel.clone(),
+      ..Default::default()
+    })
+    .insert(AchievementsScreen)
+    .with_children(|parent| {
+      parent.spawn_bundle(text).insert(AchievementsText);
+    });
+}
+
+fn achievements_input(
+  keyboard_input: Res<Input<KeyCode>>,
+  menu: Res<MenuInput>,
+  library: Res<SkinLibrary>,
+  mut achievements: ResMut<Achievements>,
+  mut state: ResMut<State<AppState>>,
+  mut query: Query<&mut Text, With<AchievementsText>>,
+) {
+  if menu.back || keyboard_input.just_pressed(KeyCode::A) {
+    state.pop().unwrap();
+    return;
+  }
+  let unlocked = achievements.unlocked.clone();
+  let mut cosmetics = achievements.cosmetics;
+  if keyboard_input.just_pressed(KeyCode::Key1) {
+    cosmetics.skin = cycle(&Skin::ALL, cosmetics.skin, Cosmetic::Skin, &unlocked);
+  } else if keyboard_input.just_pressed(KeyCode::Key2) {
+    cosmetics.theme = cycle(&Theme::ALL, cosmetics.theme, Cosmetic::Theme, &unlocked);
+  } else if keyboard_input.just_pressed(KeyCode::Key3) {
+    cosmetics.track = cycle(&Track::ALL, cosmetics.track, Cosmetic::Track, &unlocked);
+  }
+  if cosmetics != achievements.cosmetics {
+    achievements.cosmetics = cosmetics;
+    achievements.store();
+  }
+  if keyboard_input.just_pressed(KeyCode::Key4) {
+    achievements.custom_skin = next_skin(&library.names(), achievements.custom_skin.as_deref());
+    achievements.store();
+  }
+  // 消えたスキンを選んだままなら、同梱のものに戻っていると分かるように出す
+  let custom = match achievements.custom_skin.as_deref() {
+    Some(name) if library.get(name).is_some() => name.to_string(),
+    Some(name) => format!("{} (missing)", name),
+    None => "none".to_string(),
+  };
+
+  let mut value = format!(
+    "ACHIEVEMENTS  {}/{}\n\n",
+    unlocked.len(),
+    Achievement::ALL.len()
+  );
+  for achievement in Achievement::ALL.iter() {
+    let mark = if unlocked.contains(achievement) {
+      "[x]"
+    } else {
+      "[ ]"
+    };
+    value += &format!(
+      "{} {}\n    {}\n    unlocks {}\n",
+      mark,
+      achievement.name(),
+      achievement.description(),
+      achievement.reward().name()
+    );
+  }
+  value += &format!(
+    "\n[1] blocks: {:?}\n[2] theme: {:?}\n[3] music: {:?}\n[4] custom skin: {}  ({} in {}/, [F5] rescan)\n\n[Esc] back",
+    cosmetics.skin,
+    cosmetics.theme,
+    cosmetics.track,
+    custom,
+    library.skins.len(),
+    SKIN_DIR
+  );
+  for mut text in query.iter_mut() {
+    text.sections[0].value = value.clone();
+  }
+}
+
+fn close_achievements(mut commands: Commands, query: Query<Entity, With<AchievementsScreen>>) {
+  for entity in query.iter() {
+    commands.entity(entity).despawn_recursive();
+  }
+}
@@ -0,0 +1,607 @@
+use std::collections::{HashSet, VecDeque};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use rayon::prelude::*;
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+
+use crate::board::Board;
+use crate::sim::{move_piece, Action, GameState, Piece};
+use crate::{spawn_cells, Position};
+
+// tetris-train が書き出す、自己対戦で調べた重み
+pub const TRAINED_WEIGHTS: &str = "save/bot_weights.ron";
+// 重みと振る舞いを書いたボットを置くところ。.ron と .json を読む
+pub const BOT_DIR: &str = "bots";
+
+// 盤面評価の重み。値は El-Tetris 系の定番の組み合わせ
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Weights {
+  pub height: f32,
+  pub lines: f32,
+  pub holes: f32,
+  pub bumpiness: f32,
+  // 4 ライン消し 1 回ごとの上乗せ。前に書き出した重みには無いので 0 で読む
+  #[serde(default)]
+  pub tetris: f32,
+}
+
+impl Default for Weights {
+  fn default() -> Self {
+    STYLES[0].1
+  }
+}
+
+impl Weights {
+  pub fn load(path: &Path) -> Result<Self, String> {
+    fs::read_to_string(path)
+      .map_err(|e| e.to_string())
+      .and_then(|s| ron::de::from_str::<Self>(&s).map_err(|e| e.to_string()))
+      .and_then(|weights| weights.check().map(|_| weights))
+      .map_err(|e| format!("{}: {}", path.display(), e))
+  }
+
+  // RON は NaN や inf も読めてしまうので、有限でない重みは断る
+  pub fn check(&self) -> Result<(), String> {
+    let fields = [
+      ("height", self.height),
+      ("lines", self.lines),
+      ("holes", self.holes),
+      ("bumpiness", self.bumpiness),
+      ("tetris", self.tetris),
+    ];
+    match fields.iter().find(|(_, w)| !w.is_finite()) {
+      Some((name, w)) => Err(format!(
+        "weight {} must be a finite number, not {}",
+        name, w
+      )),
+      None => Ok(()),
+    }
+  }
+
+  pub fn store(&self, path: &Path) -> io::Result<()> {
+    let s = ron::ser::to_string_pretty(self, PrettyConfig::new()).map_err(io::Error::other)?;
+    if let Some(dir) = path.parent() {
+      fs::create_dir_all(dir)?;
+    }
+    fs::write(path, s)
+  }
+}
+
+// 対戦で選べるボットの性格。重みだけを変える
+pub const STYLES: [(&str, Weights); 4] = [
+  (
+    "balanced",
+    Weights {
+      height: -0.51,
+      lines: 0.76,
+      holes: -0.36,
+      bumpiness: -0.18,
+      tetris: 1.0,
+    },
+  ),
+  // 低く保って、こまめに消す
+  (
+    "digger",
+    Weights {
+      height: -0.9,
+      lines: 1.2,
+      holes: -0.5,
+      bumpiness: -0.12,
+      tetris: 0.3,
+    },
+  ),
+  // 平らに高く積んで、まとめて消す
+  (
+    "stacker",
+    Weights {
+      height: -0.2,
+      lines: 0.3,
+      holes: -0.6,
+      bumpiness: -0.3,
+      tetris: 2.5,
+    },
+  ),
+  // 穴をほとんど気にしない
+  (
+    "sloppy",
+    Weights {
+      height: -0.3,
+      lines: 0.2,
+      holes: -0.08,
+      bumpiness: -0.05,
+      tetris: 0.5,
+    },
+  ),
+];
+
+// ライン消去後の盤面と、消したライン数と 4 ライン消しの回数から点数をつける (大きいほど良い)
+pub fn evaluate(board: &Board, lines: u32, tetrises: u32, weights: &Weights) -> f32 {
+  let aggregate_height: i32 = board.column_heights().iter().sum();
+
+  weights.height * aggregate_height as f32
+    + weights.lines * lines as f32
+    + weights.tetris * tetrises as f32
+    + weights.holes * board.holes() as f32
+    + weights.bumpiness * board.bumpiness() as f32
+}
+
+// これより高く積んだら危ない。せり上がりが来ていればその分も足して比べる
+const DANGER_HEIGHT: i32 = 10;
+
+// 盤面の高さと来ているせり上がりで切り替える、置き方の方針
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Strategy {
+  // 高くなったので、何ラインでも消して下げる
+  Downstack,
+  // せり上がりが来ているので、消して相殺する
+  Cancel,
+  // 余裕があるので、溝を残して 4 ライン消しを狙う
+  BuildTetris,
+}
+
+impl Strategy {
+  pub fn choose(board: &Board, incoming: u32) -> Self {
+    Rules::default().choose(board, incoming)
+  }
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      Strategy::Downstack => "downstack",
+      Strategy::Cancel => "cancel",
+      Strategy::BuildTetris => "build tetris",
+    }
+  }
+
+  // 性格の重みを方針に合わせて変える
+  pub fn adjust(&self, weights: &Weights) -> Weights {
+    match self {
+      Strategy::Downstack => Weights {
+        height: weights.height * 2.,
+        lines: weights.lines * 2.,
+        tetris: 0.,
+        ..*weights
+      },
+      Strategy::Cancel => Weights {
+        lines: weights.lines * 2.,
+        ..*weights
+      },
+      // 少しのライン消しや溝で凸凹になるのは気にしない
+      Strategy::BuildTetris => Weights {
+        lines: weights.lines * 0.25,
+        bumpiness: weights.bumpiness * 0.5,
+        ..*weights
+      },
+    }
+  }
+}
+
+// 方針の切り替え方。書いていない項目は組み込みのボットと同じ
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Rules {
+  // これより高く積んだら掘り下げる
+  pub danger_height: i32,
+  // せり上がりが来たら相殺を急ぐ
+  pub cancel: bool,
+  // 決めてあれば、盤面によらずこの方針で置く
+  pub always: Option<Strategy>,
+}
+
+impl Default for Rules {
+  fn default() -> Self {
+    Rules {
+      danger_height: DANGER_HEIGHT,
+      cancel: true,
+      always: None,
+    }
+  }
+}
+
+impl Rules {
+  pub fn choose(&self, board: &Board, incoming: u32) -> Strategy {
+    if let Some(strategy) = self.always {
+      strategy
+    } else if board.max_height() + incoming as i32 >= self.danger_height {
+      Strategy::Downstack
+    } else if incoming > 0 && self.cancel {
+      Strategy::Cancel
+    } else {
+      Strategy::BuildTetris
+    }
+  }
+}
+
+// 対戦で選べるボット 1 人分。bots/ のファイルはこの形で書く
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Personality {
+  // 書いていなければファイル名を使う
+  #[serde(default)]
+  pub name: String,
+  pub weights: Weights,
+  #[serde(default)]
+  pub rules: Rules,
+}
+
+impl Personality {
+  pub fn new(name: &str, weights: Weights) -> Self {
+    Personality {
+      name: name.to_string(),
+      weights,
+      rules: Rules::default(),
+    }
+  }
+
+  pub fn parse(s: &str, json: bool) -> Result<Self, String> {
+    let personality: Self = if json {
+      serde_json::from_str(s).map_err(|e| e.to_string())?
+    } else {
+      ron::de::from_str(s).map_err(|e| e.to_string())?
+    };
+    personality.weights.check()?;
+    Ok(personality)
+  }
+
+  pub fn load(path: &Path) -> Result<Self, String> {
+    let json = path.extension().is_some_and(|ext| ext == "json");
+    let mut personality = fs::read_to_string(path)
+      .map_err(|e| e.to_string())
+      .and_then(|s| Self::parse(&s, json))
+      .map_err(|e| format!("{}: {}", path.display(), e))?;
+    if personality.name.is_empty() {
+      let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("bot");
+      personality.name = stem.to_string();
+    }
+    Ok(personality)
+  }
+}
+
+// dir にあるボットを名前順に読む。読めなかったファイルは理由を返す
+pub fn load_bots(dir: &Path) -> (Vec<Personality>, Vec<String>) {
+  let mut paths: Vec<_> = match fs::read_dir(dir) {
+    Ok(entries) => entries
+      .filter_map(|entry| entry.ok())
+      .map(|entry| entry.path())
+      .filter(|path| {
+        let ext = path.extension().and_then(|ext| ext.to_str());
+        ext == Some("ron") || ext == Some("json")
+      })
+      .collect(),
+    // 置いていなければ組み込みのボットだけ
+    Err(_) => vec![],
+  };
+  paths.sort();
+
+  let mut bots = vec![];
+  let mut errors = vec![];
+  for path in paths {
+    match Personality::load(&path) {
+      Ok(bot) => bots.push(bot),
+      Err(e) => errors.push(e),
+    }
+  }
+  (bots, errors)
+}
+
+// 今のミノの置き場所のうち評価が最も高いもの
+pub fn best_placement(
+  board: &Board,
+  cells: &[Position],
+  weights: &Weights,
+) -> Option<Vec<Position>> {
+  board
+    .placements(cells)
+    .into_iter()
+    .map(|placement| {
+      let mut next = board.clone();
+      next.place(&placement);
+      let lines = next.clear_full_rows();
+      (
+        evaluate(&next, lines, (lines >= 4) as u32, weights),
+        placement,
+      )
+    })
+    .max_by(|(a, _), (b, _)| a.total_cmp(b))
+    .map(|(_, placement)| placement)
+}
+
+// 選べる性格。tetris-train で調べた重みがあれば "trained" として足し、
+// bots/ に置いたボットを後ろに並べる。読めなかったファイルは理由を返す
+pub fn bot_styles() -> (Vec<Personality>, Vec<String>) {
+  let mut styles: Vec<Personality> = STYLES
+    .iter()
+    .map(|&(name, weights)| Personality::new(name, weights))
+    .collect();
+  if let Ok(weights) = Weights::load(Path::new(TRAINED_WEIGHTS)) {
+    styles.push(Personality::new("trained", weights));
+  }
+  let (bots, errors) = load_bots(Path::new(BOT_DIR));
+  styles.extend(bots);
+  (styles, errors)
+}
+
+// 置き場所 1 つ。そこへ動かす操作 (最後は HardDrop) と、置いた後の状態
+#[derive(Clone, Debug)]
+pub struct Move {
+  pub actions: Vec<Action>,
+  pub state: GameState,
+}
+
+// 横に動かして回す操作。下へはまとめて落とす
+const SHIFTS: [Action; 4] = [
+  Action::Left,
+  Action::Right,
+  Action::RotateCw,
+  Action::RotateCcw,
+];
+
+// 探索の 1 歩。下まで落とすのは何マス落ちても 1 歩と数える
+#[derive(Clone, Copy)]
+enum Step {
+  Move(Action),
+  Drop(i32),
+}
+
+// 今のミノを置ける場所をすべて列挙する。回転の壁蹴りや、下まで落としてから横へ入れる置き方も含む。
+// 同じ場所へ置く操作は一番短いものだけ残す
+pub fn moves(state: &GameState) -> Vec<Move> {
+  let active = match state.active.as_ref() {
+    Some(active) if !state.game_over => active,
+    _ => return vec![],
+  };
+  // 幅優先で動かす。親の番号をたどれば操作が分かる
+  let mut nodes: Vec<(Piece, Option<(usize, Step)>)> = vec![(active.clone(), None)];
+  let mut seen: HashSet<Piece> = HashSet::new();
+  seen.insert(active.clone());
+  let mut result = vec![];
+  let mut i = 0;
+  while i < nodes.len() {
+    let piece = nodes[i].0.clone();
+    let mut steps = vec![];
+    for &action in SHIFTS.iter() {
+      if let Some(next) = move_piece(&state.board, &piece, action) {
+        steps.push((next, Step::Move(action)));
+      }
+    }
+    let dropped = state.board.drop(&piece.cells);
+    let fall = piece.cells[0].y - dropped[0].y;
+    if fall > 0 {
+      let next = Piece {
+        cells: dropped,
+        ..piece.clone()
+      };
+      steps.push((next, Step::Drop(fall)));
+    } else {
+      // 下に動けないので、ここで固定する
+      let mut placed = state.clone();
+      placed.active = Some(piece.clone());
+      result.push(Move {
+        actions: actions_to(&nodes, i),
+        state: placed.apply(Action::HardDrop),
+      });
+    }
+    for (next, step) in steps {
+      if seen.insert(next.clone()) {
+        nodes.push((next, Some((i, step))));
+      }
+    }
+    i += 1;
+  }
+  // 同じマスに置く手は、回転の状態が違っても 1 つにまとめる
+  let mut landed: HashSet<Vec<u16>> = HashSet::new();
+  result.retain(|m| landed.insert(m.state.board.rows().to_vec()));
+  result
+}
+
+// 今のミノを、穴を増やさずに置ける場所があるか
+pub fn fits_cleanly(state: &GameState) -> bool {
+  let holes = state.board.holes();
+  moves(state).iter().any(|m| m.state.board.holes() <= holes)
+}
+
+// 置き間違い。placed に置いて穴が増えたが、出てきた位置から穴を増やさずに置ける場所があった
+pub fn is_misdrop(board: &Board, block_idx: u32, placed: &[Position]) -> bool {
+  let mut after = board.clone();
+  after.place(placed);
+  after.clear_full_rows();
+  if after.holes() <= board.holes() {
+    return false;
+  }
+  fits_cleanly(&GameState {
+    board: board.clone(),
+    active: Some(Piece {
+      block_idx,
+      rotation: 0,
+      cells: spawn_cells(block_idx),
+    }),
+    ..GameState::new(vec![])
+  })
+}
+
+// nodes[i] で固定するまでの操作。最後に落とすだけなら HardDrop に任せる
+fn actions_to(nodes: &[(Piece, Option<(usize, Step)>)], i: usize) -> Vec<Action> {
+  let mut steps = vec![];
+  let mut at = i;
+  while let Some((parent, step)) = nodes[at].1 {
+    steps.push(step);
+    at = parent;
+  }
+  if let Some(Step::Drop(_)) = steps.first() {
+    steps.remove(0);
+  }
+  let mut actions = vec![];
+  for step in steps.into_iter().rev() {
+    match step {
+      Step::Move(action) => actions.push(action),
+      Step::Drop(fall) => actions.extend((0..fall).map(|_| Action::SoftDrop)),
+    }
+  }
+  actions.push(Action::HardDrop);
+  actions
+}
+
+// 先読みの設定。depth 個のミノ (今のミノを含む) まで置いてみて、
+// 各段では評価の高い width 個の盤面だけを残して先を調べる
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Lookahead {
+  pub depth: usize,
+  pub width: usize,
+  // ホールドと入れ替えた置き方も試す
+  pub hold: bool,
+}
+
+impl Default for Lookahead {
+  fn default() -> Self {
+    Self {
+      depth: 1,
+      width: 1,
+      hold: false,
+    }
+  }
+}
+
+// 残している途中の盤面。操作は今のミノの分だけ覚えておけばよい
+struct Beam {
+  score: f32,
+  tetrises: u32,
+  actions: Vec<Action>,
+  state: GameState,
+}
+
+// そのまま置く手と、ホールドと入れ替えてから置く手
+fn expand(state: &GameState, hold: bool) -> Vec<Move> {
+  let mut all = moves(state);
+  if hold && !state.hold_used {
+    let held = state.apply(Action::Hold);
+    for mut m in moves(&held) {
+      m.actions.insert(0, Action::Hold);
+      all.push(m);
+    }
+  }
+  all
+}
+
+// 置いた後の点数。消したラインと 4 ライン消しは探索を始めてからの合計で数える
+fn score(placed: &GameState, lines: u32, tetrises: u32, weights: &Weights) -> f32 {
+  // 置いたら詰む手は、ほかに無いときだけ選ぶ
+  if placed.game_over {
+    return f32::MIN;
+  }
+  evaluate(&placed.board, placed.lines - lines, tetrises, weights)
+}
+
+fn tetrises_between(before: &GameState, after: &GameState) -> u32 {
+  (after.lines - before.lines >= 4) as u32
+}
+
+// 評価の高い順に width 個残す。同点なら先に見つけた短い操作を残す
+fn prune(mut beams: Vec<Beam>, width: usize) -> Vec<Beam> {
+  beams.sort_by(|a, b| b.score.total_cmp(&a.score));
+  beams.truncate(width.max(1));
+  beams
+}
+
+// ビームサーチで先のミノまで置いてみて、最後の盤面の評価が最も高くなる今の操作と点数。
+// 段ごとに、残した盤面を並べて広げる
+pub fn search(
+  state: &GameState,
+  weights: &Weights,
+  lookahead: &Lookahead,
+) -> Option<(f32, Vec<Action>)> {
+  let lines = state.lines;
+  let first: Vec<Beam> = expand(state, lookahead.hold)
+    .into_par_iter()
+    .map(|m| {
+      let tetrises = tetrises_between(state, &m.state);
+      Beam {
+        score: score(&m.state, lines, tetrises, weights),
+        tetrises,
+        actions: m.actions,
+        state: m.state,
+      }
+    })
+    .collect();
+  let mut beams = prune(first, lookahead.width);
+  for _ in 1..lookahead.depth {
+    let next: Vec<Beam> = beams
+      .par_iter()
+      .filter(|beam| !beam.state.game_over)
+      .flat_map_iter(|beam| {
+        expand(&beam.state, lookahead.hold)
+          .into_iter()
+          .map(move |m| {
+            let tetrises = beam.tetrises + tetrises_between(&beam.state, &m.state);
+            Beam {
+              score: score(&m.state, lines, tetrises, weights),
+              tetrises,
+              actions: beam.actions.clone(),
+              state: m.state,
+            }
+          })
+      })
+      .collect();
+    // キューが尽きたり、どれも詰んだりしたら、そこまでの結果で決める
+    if next.is_empty() {
+      break;
+    }
+    beams = prune(next, lookahead.width);
+  }
+  beams
+    .into_iter()
+    .next()
+    .map(|beam| (beam.score, beam.actions))
+}
+
+// 今のミノだけを見て、落とすまでの操作のうち評価が最も高いもの (最後は HardDrop)
+pub fn plan(state: &GameState, weights: &Weights) -> Option<Vec<Action>> {
+  search(state, weights, &Lookahead::default()).map(|(_, actions)| actions)
+}
+
+// 盤面を見て 1 手ずつ操作を返すボット。ミノが変わったら、その時の状況に合う方針で置き場所を決め直す
+pub struct Bot {
+  pub weights: Weights,
+  pub rules: Rules,
+  pub lookahead: Lookahead,
+  actions: VecDeque<Action>,
+  // 置き場所を決めたときに置いてあったミノの数
+  pieces: Option<u32>,
+  strategy: Option<Strategy>,
+}
+
+impl Bot {
+  pub fn new(weights: Weights) -> Self {
+    Bot {
+      weights,
+      rules: Rules::default(),
+      lookahead: Lookahead::default(),
+      actions: VecDeque::new(),
+      pieces: None,
+      strategy: None,
+    }
+  }
+
+  // 今の置き場所を決めたときの方針
+  pub fn strategy(&self) -> Option<Strategy> {
+    self.strategy
+  }
+
+  // incoming は相殺されずに次に積んだときせり上がる分
+  pub fn next_action(&mut self, state: &GameState, incoming: u32) -> Option<Action> {
+    if state.game_over {
+      return None;
+    }
+    if self.pieces != Some(state.pieces) {
+      self.pieces = Some(state.pieces);
+      let strategy = self.rules.choose(&state.board, incoming);
+      self.strategy = Some(strategy);
+      let weights = strategy.adjust(&self.weights);
+      self.actions = search(state, &weights, &self.lookahead)
+        .map(|(_, actions)| actions)
+        .unwrap_or_default()
+        .into();
+    }
+    self.actions.pop_front()
+  }
+}
@@ -0,0 +1,94 @@
+use bevy::prelude::*;
+
+use crate::board::Board;
+use crate::garbage;
+use crate::layout::{Anchor, Panel};
+use crate::stats::Statistics;
+use crate::{overlay_text, Fonts, Position, StackedBlock};
+
+// 積み方の練習用に盤面の指標を表示するパネル
+#[derive(Default)]
+pub struct Analysis {
+  pub visible: bool,
+  pub holes: u32,
+  pub bumpiness: i32,
+  pub max_height: i32,
+  // せり上がりがないモードでは None
+  pub garbage_efficiency: Option<f32>,
+}
+
+struct AnalysisText;
+
+pub struct AnalysisPlugin;
+
+impl Plugin for AnalysisPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(Analysis::default())
+      .add_startup_system_to_stage(StartupStage::PostStartup, setup_analysis.system())
+      .add_system(analysis_toggle.system())
+      .add_system(analysis_update.system())
+      .add_system(analysis_ui.system());
+  }
+}
+
+fn setup_analysis(mut commands: Commands, fonts: Res<Fonts>) {
+  commands
+    .spawn_bundle(overlay_text(&fonts))
+    .insert(AnalysisText)
+    .insert(Anchor(Panel::Stats));
+}
+
+fn analysis_toggle(keyboard_input: Res<Input<KeyCode>>, mut analysis: ResMut<Analysis>) {
+  if keyboard_input.just_pressed(KeyCode::Tab) {
+    analysis.visible = !analysis.visible;
+  }
+}
+
+// ブロックが積まれた・消えたときだけ計算し直す
+fn analysis_update(
+  mut analysis: ResMut<Analysis>,
+  stats: Res<Statistics>,
+  added_query: Query<Entity, Added<StackedBlock>>,
+  removed: RemovedComponents<StackedBlock>,
+  stacked_block_query: Query<&Position, With<StackedBlock>>,
+) {
+  if added_query.iter().next().is_none() && removed.iter().next().is_none() && !stats.is_changed() {
+    return;
+  }
+  let board = Board::from_cells(stacked_block_query.iter());
+  analysis.holes = board.holes();
+  analysis.bumpiness = board.bumpiness();
+  analysis.max_height = board.max_height();
+  analysis.garbage_efficiency = if stats.received > 0 {
+    Some(garbage::efficiency(
+      stats.received,
+      stats.garbage_left,
+      stats.lines(),
+    ))
+  } else {
+    None
+  };
+}
+
+fn analysis_ui(analysis: Res<Analysis>, mut query: Query<&mut Text, With<AnalysisText>>) {
+  if !analysis.is_changed() {
+    return;
+  }
+  let value = if analysis.visible {
+    let efficiency = match analysis.garbage_efficiency {
+      Some(efficiency) => format!("{:.0}%", efficiency * 100.),
+      None => "-".to_string(),
+    };
+    format!(
+      "holes: {}\nbumpiness: {}\nmax height: {}\ngarbage efficiency: {}",
+      analysis.holes, analysis.bumpiness, analysis.max_height, efficiency
+    )
+  } else {
+    String::new()
+  };
+
+  for mut text in query.iter_mut() {
+    text.sections[0].value = value.clone();
+  }
+}
@@ -0,0 +1,142 @@
+use std::fs;
+use std::path::Path;
+
+use bevy::prelude::*;
+
+use crate::audio::{AudioChannel, PlaySound};
+use crate::config::Config;
+use crate::stats::LINES_PER_LEVEL;
+use crate::{BlockStacked, GameReset, LinesCleared};
+
+// assets/ からの相対パス
+const VOICE_DIR: &str = "voices";
+// 声が重ならないように、一度しゃべったらしばらく黙る
+const COOLDOWN: f32 = 1.5;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Callout {
+  Tetris,
+  TSpinDouble,
+  BackToBack,
+  LevelUp,
+}
+
+impl Callout {
+  fn file_name(&self) -> &'static str {
+    match self {
+      Callout::Tetris => "tetris.mp3",
+      Callout::TSpinDouble => "t_spin_double.mp3",
+      Callout::BackToBack => "back_to_back.mp3",
+      Callout::LevelUp => "level_up.mp3",
+    }
+  }
+}
+
+#[derive(Default)]
+pub struct CalloutTracker {
+  lines: u32,
+  // 直前のライン消去がテトリスか T-spin だったか
+  last_was_difficult: bool,
+  // 最後に置いたミノが T-spin だったか
+  t_spin: bool,
+}
+
+impl CalloutTracker {
+  pub fn record_stack(&mut self, t_spin: bool) {
+    self.t_spin = t_spin;
+  }
+
+  // ライン消去に対してしゃべる内容。重なったときは先頭を優先する
+  pub fn record_clear(&mut self, lines: u32) -> Vec<Callout> {
+    let mut res = vec![];
+    let t_spin = self.t_spin && lines > 0;
+    if lines >= 4 || t_spin {
+      if self.last_was_difficult {
+        res.push(Callout::BackToBack);
+      }
+      if lines >= 4 {
+        res.push(Callout::Tetris);
+      }
+    }
+    if t_spin && lines == 2 {
+      res.push(Callout::TSpinDouble);
+    }
+    if (self.lines + lines) / LINES_PER_LEVEL > self.lines / LINES_PER_LEVEL {
+      res.push(Callout::LevelUp);
+    }
+    self.last_was_difficult = lines >= 4 || t_spin;
+    self.t_spin = false;
+    self.lines += lines;
+    res
+  }
+}
+
+struct Announcer {
+  tracker: CalloutTracker,
+  cooldown: Timer,
+}
+
+// assets/voices 以下のディレクトリ名 (名前順)
+pub fn voice_packs() -> Vec<String> {
+  let mut packs: Vec<String> = match fs::read_dir(Path::new("assets").join(VOICE_DIR)) {
+    Ok(entries) => entries
+      .filter_map(|entry| entry.ok())
+      .filter(|entry| entry.path().is_dir())
+      .filter_map(|entry| entry.file_name().into_string().ok())
+      .collect(),
+    Err(_) => vec![],
+  };
+  packs.sort();
+  packs
+}
+
+pub struct AnnouncerPlugin;
+
+impl Plugin for AnnouncerPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    let mut cooldown = Timer::from_seconds(COOLDOWN, false);
+    // 最初の一言はすぐしゃべれるようにしておく
+    cooldown.tick(cooldown.duration());
+    app
+      .insert_resource(Announcer {
+        tracker: CalloutTracker::default(),
+        cooldown,
+      })
+      .add_system(announce.system());
+  }
+}
+
+fn announce(
+  time: Res<Time>,
+  config: Res<Config>,
+  mut announcer: ResMut<Announcer>,
+  mut sound_events: EventWriter<PlaySound>,
+  mut stacked_events: EventReader<BlockStacked>,
+  mut cleared_events: EventReader<LinesCleared>,
+  mut reset_events: EventReader<GameReset>,
+) {
+  if reset_events.iter().count() > 0 {
+    announcer.tracker = CalloutTracker::default();
+  }
+  announcer.cooldown.tick(time.delta());
+
+  // ライン消去は固定の次のフレームに届くので、先に固定を見ておく
+  for event in stacked_events.iter() {
+    announcer.tracker.record_stack(event.t_spin);
+  }
+  for event in cleared_events.iter() {
+    let callouts = announcer.tracker.record_clear(event.0);
+    let (pack, callout) = match (config.audio.voice_pack.as_ref(), callouts.first()) {
+      (Some(pack), Some(callout)) => (pack, callout),
+      _ => continue,
+    };
+    if !announcer.cooldown.finished() {
+      continue;
+    }
+    announcer.cooldown.reset();
+    sound_events.send(PlaySound {
+      channel: AudioChannel::Announcer,
+      path: format!("{}/{}/{}", VOICE_DIR, pack, callout.file_name()),
+    });
+  }
+}
@@ -0,0 +1,271 @@
+use bevy::prelude::*;
+
+use crate::ai::{self, Weights};
+use crate::board::Board;
+use crate::config::Config;
+use crate::layout::Layout;
+use crate::rotation::KickTable;
+use crate::sim::{Action, GameState, Piece};
+use crate::{
+  apply_action, ActiveBlock, AppState, GameReset, Label, Materials, PieceMoved, PieceRotated,
+  Position, PrimitiveBlock, Size, StackedBlock,
+};
+
+// 選べるスローモーションの倍率
+pub const SLOW_MOTIONS: [f32; 4] = [1., 0.75, 0.5, 0.25];
+
+// マウスで列を選んでクリックで置く初心者向けの操作
+pub struct MouseAssist {
+  pub enabled: bool,
+}
+
+struct PreviewCell;
+
+// 表示中の予告を出したときの列とミノと盤面。どれかが変わったときだけ出し直す
+#[derive(Default)]
+struct PreviewKey(Option<(i32, u32, Vec<u16>)>);
+
+// このゲームで使った補助。結果に出して、補助なしの記録と見分けられるようにする
+#[derive(Default)]
+pub struct AssistUsed {
+  pub slow_motion: bool,
+  pub auto_hold: bool,
+}
+
+impl AssistUsed {
+  pub fn describe(&self) -> Option<String> {
+    let mut names = vec![];
+    if self.slow_motion {
+      names.push("slow motion");
+    }
+    if self.auto_hold {
+      names.push("auto hold");
+    }
+    if names.is_empty() {
+      None
+    } else {
+      Some(format!("assisted: {}", names.join(", ")))
+    }
+  }
+}
+
+// 重力の 1 回分を進めるか。倍率ぶんずつ貯めて、1 に届いたら進める
+pub fn slowed_tick(progress: &mut f32, factor: f32) -> bool {
+  *progress += factor.clamp(0., 1.);
+  if *progress >= 1. {
+    *progress -= 1.;
+    true
+  } else {
+    false
+  }
+}
+
+// 次の (forward が false なら前の) スローモーションの倍率
+pub fn cycle_slow_motion(factor: f32, forward: bool) -> f32 {
+  let len = SLOW_MOTIONS.len();
+  let i = SLOW_MOTIONS.iter().position(|&s| s == factor).unwrap_or(0);
+  let next = if forward {
+    (i + 1) % len
+  } else {
+    (i + len - 1) % len
+  };
+  SLOW_MOTIONS[next]
+}
+
+pub struct MouseAssistPlugin;
+
+impl Plugin for MouseAssistPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(MouseAssist { enabled: false })
+      .insert_resource(AssistUsed::default())
+      .add_system_set(SystemSet::on_enter(AppState::Playing).with_system(reset_assists.system()))
+      .add_system_set(SystemSet::on_update(AppState::Playing).with_system(track_assists.system()))
+      .insert_resource(PreviewKey::default())
+      .add_system_set(
+        SystemSet::on_update(AppState::Playing).with_system(
+          assist_click
+            .system()
+            .after(Label::Input)
+            .before(Label::Movement),
+        ),
+      )
+      .add_system(assist_toggle.system())
+      .add_system(assist_preview.system());
+  }
+}
+
+// 一番左のマスが column に来る置き場所
+fn placement_at_column(board: &Board, cells: &[Position], column: i32) -> Option<Vec<Position>> {
+  board
+    .placements(cells)
+    .into_iter()
+    .find(|placement| placement.iter().map(|p| p.x).min() == Some(column))
+}
+
+// 操作中のミノを column に置く手のうち、回転も含めて評価が最も高いもの。
+// 置いたマスと、そこへ動かす操作 (最後は HardDrop) を返す
+pub fn best_move_at_column(
+  state: &GameState,
+  column: i32,
+  weights: &Weights,
+) -> Option<(Vec<Position>, Vec<Action>)> {
+  ai::moves(state)
+    .into_iter()
+    .filter_map(|m| {
+      let mut moved = state.clone();
+      for &action in m.actions.iter().filter(|&&a| a != Action::HardDrop) {
+        moved = moved.apply(action);
+      }
+      let placed = state.board.drop(&moved.active?.cells);
+      if placed.iter().map(|p| p.x).min() != Some(column) {
+        return None;
+      }
+      let lines = m.state.lines - state.lines;
+      let score = ai::evaluate(&m.state.board, lines, (lines >= 4) as u32, weights);
+      Some((score, placed, m.actions))
+    })
+    .max_by(|(a, ..), (b, ..)| a.total_cmp(b))
+    .map(|(_, placed, actions)| (placed, actions))
+}
+
+// 盤面のミノを sim の状態にする。キューは見ない
+fn current_state(board: Board, active_block: &ActiveBlock, cells: Vec<Position>) -> GameState {
+  let mut state = GameState::new(vec![]);
+  state.board = board;
+  state.active = Some(Piece {
+    block_idx: active_block.block_idx,
+    rotation: active_block.rotation,
+    cells,
+  });
+  state
+}
+
+fn hovered_column(windows: &Windows, layout: &Layout) -> Option<i32> {
+  let window = windows.get_primary()?;
+  window
+    .cursor_position()
+    .and_then(|cursor| layout.cell_at(cursor))
+    .map(|position| position.x)
+}
+
+fn reset_assists(mut used: ResMut<AssistUsed>) {
+  *used = AssistUsed::default();
+}
+
+fn track_assists(
+  config: Res<Config>,
+  mut used: ResMut<AssistUsed>,
+  mut reset_events: EventReader<GameReset>,
+) {
+  if reset_events.iter().count() > 0 {
+    *used = AssistUsed::default();
+  }
+  if config.assist.slow_motion < 1. {
+    used.slow_motion = true;
+  }
+}
+
+fn assist_toggle(keyboard_input: Res<Input<KeyCode>>, mut assist: ResMut<MouseAssist>) {
+  if keyboard_input.just_pressed(KeyCode::M) {
+    assist.enabled = !assist.enabled;
+  }
+}
+
+// クリックした列へ、一番良い回転で置く。キーで動かしたときと同じ処理を通す
+fn assist_click(
+  windows: Res<Windows>,
+  layout: Res<Layout>,
+  mouse_input: Res<Input<MouseButton>>,
+  assist: Res<MouseAssist>,
+  kick_table: Res<KickTable>,
+  mut active_block: ResMut<ActiveBlock>,
+  mut primitive_block_query: Query<&mut Position, (With<PrimitiveBlock>, Without<StackedBlock>)>,
+  stacked_block_query: Query<&Position, With<StackedBlock>>,
+  mut moved_events: EventWriter<PieceMoved>,
+  mut rotated_events: EventWriter<PieceRotated>,
+) {
+  if !assist.enabled || !active_block.is_on() || !mouse_input.just_pressed(MouseButton::Left) {
+    return;
+  }
+  let column = match hovered_column(&windows, &layout) {
+    Some(column) => column,
+    None => return,
+  };
+  let board = Board::from_cells(stacked_block_query.iter());
+  let cells: Vec<Position> = primitive_block_query
+    .iter_mut()
+    .map(|p| p.clone())
+    .collect();
+  let state = current_state(board.clone(), &active_block, cells);
+  let actions = match best_move_at_column(&state, column, &Weights::default()) {
+    Some((_, actions)) => actions,
+    None => return,
+  };
+  for action in actions {
+    apply_action(
+      &mut primitive_block_query,
+      &board,
+      &kick_table,
+      &mut active_block,
+      action,
+      &mut moved_events,
+      &mut rotated_events,
+    );
+  }
+}
+
+// 今の向きで落とした場所と、一番良い回転で置く場所 (強調) を出す
+fn assist_preview(
+  mut commands: Commands,
+  materials: Res<Materials>,
+  windows: Res<Windows>,
+  layout: Res<Layout>,
+  assist: Res<MouseAssist>,
+  active_block: Res<ActiveBlock>,
+  mut preview_key: ResMut<PreviewKey>,
+  primitive_block_query: Query<&Position, (With<PrimitiveBlock>, Without<StackedBlock>)>,
+  stacked_block_query: Query<&Position, With<StackedBlock>>,
+  preview_query: Query<Entity, With<PreviewCell>>,
+) {
+  let column = if assist.enabled && active_block.is_on() {
+    hovered_column(&windows, &layout)
+  } else {
+    None
+  };
+  let board = Board::from_cells(stacked_block_query.iter());
+  let key = column.map(|column| (column, active_block.block_idx, board.rows().to_vec()));
+  if key == preview_key.0 {
+    return;
+  }
+  preview_key.0 = key;
+  for entity in preview_query.iter() {
+    commands.entity(entity).despawn();
+  }
+  let column = match column {
+    Some(column) => column,
+    None => return,
+  };
+
+  let cells: Vec<Position> = primitive_block_query.iter().cloned().collect();
+  let mut previews = vec![];
+  if let Some(placement) = placement_at_column(&board, &cells, column) {
+    previews.push((placement, materials.ghost_block.clone()));
+  }
+  let state = current_state(board, &active_block, cells);
+  if let Some((placement, _)) = best_move_at_column(&state, column, &Weights::default()) {
+    previews.push((placement, materials.hint_block.clone()));
+  }
+  for (placement, material) in previews {
+    for position in placement {
+      commands
+        .spawn_bundle(SpriteBundle {
+          material: material.clone(),
+          ..Default::default()
+        })
+        .insert(PreviewCell)
+        .insert(position)
+        .insert(Size::square(0.8));
+    }
+  }
+}
@@ -0,0 +1,169 @@
+use serde::{Deserialize, Serialize};
+
+// 消したライン数ごとの攻撃 (0 から 4 ライン)
+const LINE_ATTACK: [u32; 5] = [0, 0, 1, 2, 4];
+// 続けて消した回数ごとの上乗せ。これより長く続いたら最後の値
+const COMBO_BONUS: [u32; 12] = [0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5];
+const BACK_TO_BACK_BONUS: u32 = 1;
+
+// ルールで選ぶ攻撃の表
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum AttackTable {
+  #[default]
+  Guideline,
+  // 消したライン数だけで決める。コンボと Back-to-Back の上乗せは無し
+  Classic,
+}
+
+impl AttackTable {
+  pub const ALL: [AttackTable; 2] = [AttackTable::Guideline, AttackTable::Classic];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      AttackTable::Guideline => "guideline",
+      AttackTable::Classic => "classic",
+    }
+  }
+
+  pub fn attack(self, lines: u32, combo: u32, back_to_back: bool) -> u32 {
+    match self {
+      AttackTable::Guideline => attack(lines, combo, back_to_back),
+      AttackTable::Classic => LINE_ATTACK[lines.min(4) as usize],
+    }
+  }
+}
+
+// combo はこの消去より前に続けて消した回数
+pub fn attack(lines: u32, combo: u32, back_to_back: bool) -> u32 {
+  if lines == 0 {
+    return 0;
+  }
+  let base = LINE_ATTACK[lines.min(4) as usize];
+  let combo = COMBO_BONUS[(combo as usize).min(COMBO_BONUS.len() - 1)];
+  let b2b = if back_to_back { BACK_TO_BACK_BONUS } else { 0 };
+  base + combo + b2b
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Clear {
+  pub lines: u32,
+  pub attack: u32,
+  pub combo: u32,
+  pub back_to_back: bool,
+  // コンボにもならないシングル・ダブル
+  pub wasteful: bool,
+}
+
+// 1ゲーム分の攻撃の記録
+#[derive(Default)]
+pub struct AttackTracker {
+  pub table: AttackTable,
+  combo: u32,
+  // 最後に積んだミノでまだラインを消していない
+  pending_lock: bool,
+  last_was_tetris: bool,
+  pub lines: u32,
+  pub attack: u32,
+  pub wasteful: u32,
+}
+
+impl AttackTracker {
+  pub fn record_lock(&mut self) {
+    if self.pending_lock {
+      self.combo = 0;
+    }
+    self.pending_lock = true;
+  }
+
+  pub fn record_clear(&mut self, lines: u32) -> Clear {
+    let combo = self.combo;
+    self.pending_lock = false;
+    let back_to_back = lines >= 4 && self.last_was_tetris;
+    let clear = Clear {
+      lines,
+      attack: self.table.attack(lines, combo, back_to_back),
+      combo,
+      back_to_back,
+      wasteful: lines <= 2 && combo == 0,
+    };
+    self.combo = combo + 1;
+    self.last_was_tetris = lines >= 4;
+    self.lines += lines;
+    self.attack += clear.attack;
+    if clear.wasteful {
+      self.wasteful += 1;
+    }
+    clear
+  }
+
+  // 続けて消している回数。消さずに積んだことは次に積んだときに分かる
+  pub fn combo(&self) -> u32 {
+    self.combo
+  }
+
+  // 1ラインあたりの攻撃 (APL)
+  pub fn attack_per_line(&self) -> f32 {
+    if self.lines == 0 {
+      return 0.;
+    }
+    self.attack as f32 / self.lines as f32
+  }
+}
+
+// 攻撃の送り先の決め方
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Targeting {
+  Random,
+  // 最後に自分を攻撃してきた相手
+  Attacker,
+  // 一番ラインを消している相手
+  MostLines,
+}
+
+impl Targeting {
+  pub const ALL: [Targeting; 3] = [Targeting::Random, Targeting::Attacker, Targeting::MostLines];
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      Targeting::Random => "random",
+      Targeting::Attacker => "attacker",
+      Targeting::MostLines => "most lines",
+    }
+  }
+
+  pub fn next(&self) -> Targeting {
+    let i = Self::ALL.iter().position(|t| t == self).unwrap_or(0);
+    Self::ALL[(i + 1) % Self::ALL.len()]
+  }
+}
+
+// 生き残っている相手から送り先を選ぶ。roll は Random のときと、候補が無いときの代わりに使う
+pub fn choose_target(
+  rule: Targeting,
+  from: usize,
+  alive: &[bool],
+  lines: &[u32],
+  last_attacker: Option<usize>,
+  roll: usize,
+) -> Option<usize> {
+  let candidates: Vec<usize> = (0..alive.len())
+    .filter(|&i| i != from && alive[i])
+    .collect();
+  if candidates.is_empty() {
+    return None;
+  }
+  let random = candidates[roll % candidates.len()];
+  match rule {
+    Targeting::Random => Some(random),
+    Targeting::Attacker => Some(
+      last_attacker
+        .filter(|a| candidates.contains(a))
+        .unwrap_or(random),
+    ),
+    // 同じなら番号の小さい方
+    Targeting::MostLines => candidates
+      .iter()
+      .copied()
+      .max_by_key(|&i| (lines[i], std::cmp::Reverse(i))),
+  }
+}
@@ -0,0 +1,598 @@
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy::window::WindowFocused;
+use rodio::source::SineWave;
+use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink, Source};
+use serde::{Deserialize, Serialize};
+
+use crate::accessibility::Accessibility;
+use crate::achievements::Achievements;
+use crate::announcer;
+use crate::assist::cycle_slow_motion;
+use crate::config::Config;
+use crate::menu::{MenuInput, MenuList};
+use crate::profile::{Binding, Controls, KeyBindings, Preset, Resolve};
+use crate::{AppState, BlockStacked, Fonts, GameMode, LinesCleared, Materials};
+
+const ASSET_DIR: &str = "assets";
+const VOLUME_STEP: f32 = 0.1;
+const LOCK_SOUND: &str = "sounds/lock.mp3";
+const CLEAR_SOUND: &str = "sounds/clear.mp3";
+// 合成した合図の音量。ファイルの効果音と並べて耳障りにならない程度
+const TONE_AMPLITUDE: f32 = 0.2;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum AudioChannel {
+  Music,
+  Sfx,
+  Announcer,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct AudioSettings {
+  pub master: f32,
+  pub music: f32,
+  pub sfx: f32,
+  pub announcer: f32,
+  // ウィンドウが裏に回ったら音を消す
+  pub mute_on_focus_loss: bool,
+  // assets/voices 以下の実況音声のディレクトリ名。None なら実況なし
+  #[serde(default)]
+  pub voice_pack: Option<String>,
+  // 出てきたミノの種類を短い旋律で知らせる
+  #[serde(default)]
+  pub piece_cues: bool,
+  // 動かすたびに、着地するまでの高さを音の高さで知らせる
+  #[serde(default)]
+  pub landing_cues: bool,
+}
+
+impl Default for AudioSettings {
+  fn default() -> Self {
+    Self {
+      master: 0.8,
+      music: 0.6,
+      sfx: 0.8,
+      announcer: 1.0,
+      mute_on_focus_loss: true,
+      voice_pack: Some("default".to_string()),
+      piece_cues: false,
+      landing_cues: false,
+    }
+  }
+}
+
+impl AudioSettings {
+  pub fn volume(&self, channel: AudioChannel) -> f32 {
+    let volume = match channel {
+      AudioChannel::Music => self.music,
+      AudioChannel::Sfx => self.sfx,
+      AudioChannel::Announcer => self.announcer,
+    };
+    self.master * volume
+  }
+}
+
+// 鳴らしたい音。path は assets/ からの相対パス
+pub struct PlaySound {
+  pub channel: AudioChannel,
+  pub path: String,
+}
+
+// 音声ファイルを使わずに合成して鳴らす音の並び (周波数 Hz, 秒)。効果音の音量で鳴らす
+pub struct PlayTones(pub Vec<(u32, f32)>);
+
+// rodio の出力をチャンネルごとの Sink で鳴らす。OutputStream が Send でないので NonSend
+struct Mixer {
+  stream: Option<(OutputStream, OutputStreamHandle)>,
+  sinks: Vec<(AudioChannel, Sink)>,
+  muted: bool,
+}
+
+impl Mixer {
+  fn new() -> Self {
+    let stream = match OutputStream::try_default() {
+      Ok(stream) => Some(stream),
+      Err(e) => {
+        warn!("no audio output, sounds are disabled: {}", e);
+        None
+      }
+    };
+    Mixer {
+      stream,
+      sinks: vec![],
+      muted: false,
+    }
+  }
+
+  fn channel_volume(&self, settings: &AudioSettings, channel: AudioChannel) -> f32 {
+    if self.muted {
+      0.
+    } else {
+      settings.volume(channel)
+    }
+  }
+
+  fn apply_volume(&self, settings: &AudioSettings) {
+    for (channel, sink) in self.sinks.iter() {
+      sink.set_volume(self.channel_volume(settings, *channel));
+    }
+  }
+
+  fn play(&mut self, settings: &AudioSettings, sound: &PlaySound) -> Result<(), String> {
+    let handle = match self.stream.as_ref() {
+      Some((_, handle)) => handle,
+      None => return Ok(()),
+    };
+    let path = Path::new(ASSET_DIR).join(&sound.path);
+    // 音声ファイルは任意なので、置かれていなければ何もしない
+    if !path.exists() {
+      return Ok(());
+    }
+    let bytes = fs::read(path).map_err(|e| e.to_string())?;
+    let source = Decoder::new(Cursor::new(bytes)).map_err(|e| e.to_string())?;
+    let sink = Sink::try_new(handle).map_err(|e| e.to_string())?;
+    sink.set_volume(self.channel_volume(settings, sound.channel));
+    if sound.channel == AudioChannel::Music {
+      // 曲は1つだけ流してループさせる
+      self
+        .sinks
+        .retain(|(channel, _)| *channel != AudioChannel::Music);
+      sink.append(source.buffered().repeat_infinite());
+    } else {
+      sink.append(source);
+    }
+    self.sinks.push((sound.channel, sink));
+    Ok(())
+  }
+
+  fn play_tones(&mut self, settings: &AudioSettings, tones: &PlayTones) -> Result<(), String> {
+    let handle = match self.stream.as_ref() {
+      Some((_, handle)) => handle,
+      None => return Ok(()),
+    };
+    let sink = Sink::try_new(handle).map_err(|e| e.to_string())?;
+    sink.set_volume(self.channel_volume(settings, AudioChannel::Sfx));
+    for &(freq, seconds) in tones.0.iter() {
+      let tone = SineWave::new(freq)
+        .take_duration(Duration::from_secs_f32(seconds))
+        .amplify(TONE_AMPLITUDE);
+      sink.append(tone);
+    }
+    self.sinks.push((AudioChannel::Sfx, sink));
+    Ok(())
+  }
+}
+
+// 設定画面の行
+const ROWS: [&str; 25] = [
+  "master",
+  "music",
+  "sfx",
+  "announcer",
+  "voice pack",
+  "mute on focus loss",
+  "piece type cues",
+  "landing pitch cues",
+  "reduce flashing/motion",
+  "high contrast",
+  "thick borders",
+  "text size",
+  "key preset",
+  "slow motion",
+  "auto hold",
+  "column guides",
+  "speed",
+  "rumble",
+  "tick rate",
+  "left key",
+  "right key",
+  "soft drop key",
+  "rotate cw key",
+  "rotate ccw key",
+  "hold key",
+];
+// ここから Binding::ALL の順にキーの割り当て
+const FIRST_BINDING_ROW: usize = 19;
+
+// キーを割り当て中の操作と、押したキーが重なった別の操作
+#[derive(Clone, Copy)]
+struct Rebinding {
+  binding: Binding,
+  conflict: Option<(KeyCode, Binding)>,
+}
+
+#[derive(Default)]
+struct SettingsPage {
+  row: usize,
+  // 選べる実況音声。先頭の None は「なし」
+  voice_packs: Vec<Option<String>>,
+  rebinding: Option<Rebinding>,
+}
+
+struct SettingsScreen;
+struct SettingsText;
+
+pub struct AudioPlugin;
+
+impl Plugin for AudioPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_non_send_resource(Mixer::new())
+      .insert_resource(SettingsPage::default())
+      .add_event::<PlaySound>()
+      .add_event::<PlayTones>()
+      .add_system_set(SystemSet::on_enter(AppState::Playing).with_system(start_music.system()))
+      .add_system(game_sounds.system())
+      .add_system(play_sounds.system())
+      .add_system(apply_volume.system())
+      .add_system(focus_mute.system())
+      .add_system(open_settings.system())
+      .add_system_set(SystemSet::on_enter(AppState::Settings).with_system(setup_settings.system()))
+      .add_system_set(SystemSet::on_update(AppState::Settings).with_system(settings_input.system()))
+      .add_system_set(SystemSet::on_exit(AppState::Settings).with_system(close_settings.system()));
+  }
+}
+
+fn start_music(achievements: Res<Achievements>, mut sound_events: EventWriter<PlaySound>) {
+  sound_events.send(PlaySound {
+    channel: AudioChannel::Music,
+    path: achievements.cosmetics.track.path().to_string(),
+  });
+}
+
+fn game_sounds(
+  mut sound_events: EventWriter<PlaySound>,
+  mut stacked_events: EventReader<BlockStacked>,
+  mut cleared_events: EventReader<LinesCleared>,
+) {
+  let stacked = stacked_events.iter().count();
+  let cleared = cleared_events.iter().count();
+  let path = if cleared > 0 {
+    CLEAR_SOUND
+  } else if stacked > 0 {
+    LOCK_SOUND
+  } else {
+    return;
+  };
+  sound_events.send(PlaySound {
+    channel: AudioChannel::Sfx,
+    path: path.to_string(),
+  });
+}
+
+fn play_sounds(
+  mut mixer: NonSendMut<Mixer>,
+  config: Res<Config>,
+  mut sound_events: EventReader<PlaySound>,
+  mut tone_events: EventReader<PlayTones>,
+) {
+  // 鳴り終わった Sink を片付ける
+  mixer.sinks.retain(|(_, sink)| !sink.empty());
+  for sound in sound_events.iter() {
+    if let Err(e) = mixer.play(&config.audio, sound) {
+      warn!("failed to play {}: {}", sound.path, e);
+    }
+  }
+  for tones in tone_events.iter() {
+    if let Err(e) = mixer.play_tones(&config.audio, tones) {
+      warn!("failed to play tones: {}", e);
+    }
+  }
+}
+
+fn apply_volume(mixer: NonSend<Mixer>, config: Res<Config>) {
+  if config.is_changed() {
+    mixer.apply_volume(&config.audio);
+  }
+}
+
+fn focus_mute(
+  mut mixer: NonSendMut<Mixer>,
+  config: Res<Config>,
+  mut focused_events: EventReader<WindowFocused>,
+) {
+  for event in focused_events.iter().filter(|e| e.id.is_primary()) {
+    mixer.muted = config.audio.mute_on_focus_loss && !event.focused;
+    mixer.apply_volume(&config.audio);
+  }
+}
+
+// F2 でどの画面からでも設定を開く
+fn open_settings(keyboard_input: Res<Input<KeyCode>>, mut state: ResMut<State<AppState>>) {
+  if !keyboard_input.just_pressed(KeyCode::F2) {
+    return;
+  }
+  let result = if *state.current() == AppState::Settings {
+    state.pop()
+  } else {
+    state.push(AppState::Settings)
+  };
+  if let Err(e) = result {
+    warn!("cannot toggle settings: {:?}", e);
+  }
+}
+
+fn setup_settings(
+  mut commands: Commands,
+  fonts: Res<Fonts>,
+  materials: Res<Materials>,
+  mut page: ResMut<SettingsPage>,
+) {
+  page.rebinding = None;
+  page.voice_packs = std::iter::once(None)
+    .chain(announcer::voice_packs().into_iter().map(Some))
+    .collect();
+  commands
+    .spawn_bundle(NodeBundle {
+      style: Style {
+        position_type: PositionType::Absolute,
+        size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+        padding: Rect::all(Val::Px(40.0)),
+        flex_direction: FlexDirection::ColumnReverse,
+        ..Default::default()
+      },
+      material: materials.panel.clone(),
+      ..Default::default()
+    })
+    .insert(SettingsScreen)
+    .with_children(|parent| {
+      parent
+        .spawn_bundle(TextBundle {
+          text: Text::with_section(
+            "",
+            TextStyle {
+              font: fonts.main.clone(),
+              font_size: 16.0,
+              color: Color::WHITE,
+            },
+            Default::default(),
+          ),
+          ..Default::default()
+        })
+        .insert(SettingsText)
+        .insert(MenuList::new(2, ROWS.len()));
+    });
+}
+
+fn slider(value: f32) -> String {
+  let filled = (value * 10.).round() as usize;
+  format!(
+    "[{}{}] {:>3}%",
+    "#".repeat(filled),
+    "-".repeat(10 - filled),
+    (value * 100.).round()
+  )
+}
+
+// 割り当て中は矢印も Enter も割り当てるキーとして読む。Esc は取り消し
+fn rebind_input(
+  keyboard_input: &Input<KeyCode>,
+  menu: &MenuInput,
+  bindings: &mut KeyBindings,
+  rebinding: Rebinding,
+) -> Option<Rebinding> {
+  match rebinding.conflict {
+    Some((key, _)) => {
+      if menu.confirm {
+        bindings.rebind_resolving(rebinding.binding, key, Resolve::Swap);
+      } else if keyboard_input.just_pressed(KeyCode::Delete) {
+        bindings.rebind_resolving(rebinding.binding, key, Resolve::Clear);
+      } else if !menu.back {
+        return Some(rebinding);
+      }
+      None
+    }
+    None if menu.back => None,
+    None => {
+      // F2 は設定画面を閉じるので割り当てない
+      let key = keyboard_input
+        .get_just_pressed()
+        .copied()
+        .find(|&key| key != KeyCode::F2);
+      let key = match key {
+        Some(key) => key,
+        None => return Some(rebinding),
+      };
+      match bindings.rebind(rebinding.binding, key) {
+        Ok(()) => None,
+        Err(other) => Some(Rebinding {
+          conflict: Some((key, other)),
+          ..rebinding
+        }),
+      }
+    }
+  }
+}
+
+fn rebind_prompt(rebinding: &Rebinding) -> String {
+  match rebinding.conflict {
+    Some((key, other)) => format!(
+      "{:?} is already used by {}\n[Enter] swap  [Del] clear it from {}  [Esc] cancel",
+      key,
+      other.name(),
+      other.name()
+    ),
+    None => format!("press a key for {}  [Esc] cancel", rebinding.binding.name()),
+  }
+}
+
+// 選んでいる行の設定を step の向きに変える。キーの行は割り当てを始める
+fn change_setting(
+  page: &mut SettingsPage,
+  step: f32,
+  config: &mut Config,
+  accessibility: &mut Accessibility,
+  controls: &mut Controls,
+) {
+  if step == 0. {
+    return;
+  }
+  if page.row >= FIRST_BINDING_ROW {
+    page.rebinding = Some(Rebinding {
+      binding: Binding::ALL[page.row - FIRST_BINDING_ROW],
+      conflict: None,
+    });
+  } else if page.row >= 8 {
+    match page.row {
+      8 => config.reduce_motion = !config.reduce_motion,
+      9 => accessibility.high_contrast = !accessibility.high_contrast,
+      10 => accessibility.thick_borders = !accessibility.thick_borders,
+      11 => accessibility.step_scale(step > 0.),
+      12 => controls.cycle_preset(step > 0.),
+      13 => config.assist.slow_motion = cycle_slow_motion(config.assist.slow_motion, step > 0.),
+      14 => config.assist.auto_hold = !config.assist.auto_hold,
+      15 => config.column_guides = !config.column_guides,
+      16 => config.speed_table = config.speed_table.cycle(step > 0.),
+      17 => config.rumble.intensity = (config.rumble.intensity + step).clamp(0., 1.),
+      _ => config.tick_rate = config.tick_rate.cycle(step > 0.),
+    }
+  } else {
+    let audio = &mut config.audio;
+    let volume = match page.row {
+      0 => Some(&mut audio.master),
+      1 => Some(&mut audio.music),
+      2 => Some(&mut audio.sfx),
+      3 => Some(&mut audio.announcer),
+      _ => None,
+    };
+    match volume {
+      Some(volume) => *volume = (*volume + step).clamp(0., 1.),
+      None if page.row == 4 => {
+        let packs = &page.voice_packs;
+        let current = packs.iter().position(|p| *p == audio.voice_pack);
+        let next = match current {
+          Some(i) if step > 0. => (i + 1) % packs.len(),
+          Some(i) => (i + packs.len() - 1) % packs.len(),
+          None => 0,
+        };
+        audio.voice_pack = packs[next].clone();
+      }
+      None if page.row == 5 => audio.mute_on_focus_loss = !audio.mute_on_focus_loss,
+      None if page.row == 6 => audio.piece_cues = !audio.piece_cues,
+      None => audio.landing_cues = !audio.landing_cues,
+    }
+  }
+}
+
+// 決定とクリックは右を押したのと同じに、戻るは F2 と同じに扱う
+fn settings_input(
+  mut keyboard_input: ResMut<Input<KeyCode>>,
+  menu: Res<MenuInput>,
+  mut page: ResMut<SettingsPage>,
+  mut config: ResMut<Config>,
+  mut accessibility: ResMut<Accessibility>,
+  mut controls: ResMut<Controls>,
+  mut state: ResMut<State<AppState>>,
+  mut query: Query<(&mut Text, &MenuList), With<SettingsText>>,
+) {
+  if let Some(rebinding) = page.rebinding {
+    page.rebinding = rebind_input(&keyboard_input, &menu, &mut controls.bindings, rebinding);
+  } else if menu.back {
+    // 下の画面が同じフレームで Esc を拾わないように
+    keyboard_input.reset(KeyCode::Escape);
+    if let Err(e) = state.pop() {
+      warn!("cannot close settings: {:?}", e);
+    }
+    return;
+  } else {
+    let (pointed, hovered) = query
+      .iter_mut()
+      .next()
+      .map_or((None, None), |(_, list)| (list.pointed, list.hovered));
+    page.row = menu.select(page.row, ROWS.len(), pointed);
+    let mut step = menu.step() as f32 * VOLUME_STEP;
+    if let Some(row) = menu.chosen(page.row, hovered) {
+      page.row = row;
+      step = VOLUME_STEP;
+    }
+    change_setting(
+      &mut page,
+      step,
+      &mut config,
+      &mut accessibility,
+      &mut controls,
+    );
+  }
+
+  let audio = &config.audio;
+  let on_off = |b: bool| if b { "on" } else { "off" };
+  let mut values = vec![
+    slider(audio.master),
+    slider(audio.music),
+    slider(audio.sfx),
+    slider(audio.announcer),
+    audio.voice_pack.as_deref().unwrap_or("off").to_string(),
+    on_off(audio.mute_on_focus_loss).to_string(),
+    on_off(audio.piece_cues).to_string(),
+    on_off(audio.landing_cues).to_string(),
+    on_off(config.reduce_motion).to_string(),
+    on_off(accessibility.high_contrast).to_string(),
+    on_off(accessibility.thick_borders).to_string(),
+    format!("{}x", accessibility.ui_scale),
+    Preset::matching(&controls.bindings)
+      .map_or("custom", |p| p.name())
+      .to_string(),
+    format!("{}x", config.assist.slow_motion),
+    on_off(config.assist.auto_hold).to_string(),
+    on_off(config.column_guides).to_string(),
+    config.speed_table.name().to_string(),
+    slider(config.rumble.intensity),
+    format!("{} Hz", config.tick_rate.hz()),
+  ];
+  values.extend(Binding::ALL.iter().map(|&binding| {
+    let keys = controls.bindings.keys(binding);
+    let names: Vec<String> = keys.iter().map(|key| format!("{:?}", key)).collect();
+    if names.is_empty() {
+      "(none)".to_string()
+    } else {
+      names.join(", ")
+    }
+  }));
+  let rows: Vec<String> = ROWS
+    .iter()
+    .zip(values.iter())
+    .enumerate()
+    .map(|(i, (name, value))| {
+      let cursor = if i == page.row { '>' } else { ' ' };
+      format!("{} {:<20} {}", cursor, name, value)
+    })
+    .collect();
+  let footer = match (page.rebinding.as_ref(), controls.bindings.validate()) {
+    (Some(rebinding), _) => rebind_prompt(rebinding),
+    (None, Err(e)) => format!(
+      "! {}\nkeys are not saved until this is fixed\n[Up/Down] select  [Enter] rebind  [F2] [Esc] close",
+      e
+    ),
+    (None, Ok(())) => {
+      "[Up/Down] select  [Left/Right] change  [Enter] rebind key\n[F2] [Esc] close".to_string()
+    }
+  };
+  let value = format!("SETTINGS\n\n{}\n\n{}", rows.join("\n"), footer);
+  for (mut text, _) in query.iter_mut() {
+    text.sections[0].value = value.clone();
+  }
+}
+
+fn close_settings(
+  mut commands: Commands,
+  config: Res<Config>,
+  accessibility: Res<Accessibility>,
+  controls: Res<Controls>,
+  mode: Res<GameMode>,
+  query: Query<Entity, With<SettingsScreen>>,
+) {
+  for entity in query.iter() {
+    commands.entity(entity).despawn_recursive();
+  }
+  config.store();
+  accessibility.store();
+  // パーティーでは番ごとに配置を差し替えているので、プロフィールには書かない
+  if *mode != GameMode::Party {
+    controls.store();
+  }
+}
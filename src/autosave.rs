@@ -0,0 +1,181 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::hold::Hold;
+use crate::{
+  overlay_text, save, spawn_primitive_block, spawn_stacked_block, ActiveBlock, AppState, Fonts,
+  Label, Materials, PiecePhase, PieceQueue, Position, PrimitiveBlock, StackedBlock,
+};
+
+const SAVE_FILE: &str = "autosave.ron";
+// 書き込みの間隔 (秒)
+const SAVE_INTERVAL: f32 = 2.0;
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ActivePiece {
+  pub block_idx: u32,
+  pub rotation: u8,
+  pub cells: Vec<(i32, i32)>,
+}
+
+// 途中のゲームを再開するのに必要な分だけの状態
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct Snapshot {
+  pub stacked: Vec<(i32, i32)>,
+  pub active: Option<ActivePiece>,
+  pub hold: Option<u32>,
+  pub hold_used: bool,
+  pub queue: Vec<u32>,
+}
+
+// 前回落ちたときのセーブ。再開を選ぶとプレイ開始時に盤面へ戻す
+#[derive(Default)]
+struct Resume {
+  found: Option<Snapshot>,
+  accepted: bool,
+}
+
+struct AutosaveTimer(Timer);
+
+struct ResumeText;
+
+pub struct AutosavePlugin;
+
+impl Plugin for AutosavePlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(Resume {
+        found: save::load_opt(SAVE_FILE),
+        accepted: false,
+      })
+      .insert_resource(AutosaveTimer(Timer::from_seconds(SAVE_INTERVAL, true)))
+      .add_system_set(SystemSet::on_exit(AppState::Title).with_system(reload_resume.system()))
+      .add_system_set(SystemSet::on_enter(AppState::Setup).with_system(setup_resume.system()))
+      .add_system_set(SystemSet::on_update(AppState::Setup).with_system(resume_input.system()))
+      .add_system_set(SystemSet::on_exit(AppState::Setup).with_system(close_resume.system()))
+      .add_system_set(
+        SystemSet::on_enter(AppState::Playing)
+          .with_system(restore_game.system().before(Label::Spawn)),
+      )
+      .add_system_set(SystemSet::on_update(AppState::Playing).with_system(autosave.system()))
+      .add_system_set(SystemSet::on_enter(AppState::Results).with_system(discard.system()));
+  }
+}
+
+fn reload_resume(mut resume: ResMut<Resume>) {
+  resume.found = save::load_opt(SAVE_FILE);
+}
+
+fn setup_resume(mut commands: Commands, fonts: Res<Fonts>, resume: Res<Resume>) {
+  if resume.found.is_none() {
+    return;
+  }
+  let mut text = overlay_text(&fonts);
+  text.style.position = Rect {
+    top: Val::Px(360.0),
+    left: Val::Px(40.0),
+    ..Default::default()
+  };
+  text.text.sections[0].value =
+    "An interrupted game was found.\n\n[Y] resume it\n[N] discard it".to_string();
+  commands.spawn_bundle(text).insert(ResumeText);
+}
+
+fn resume_input(
+  mut commands: Commands,
+  keyboard_input: Res<Input<KeyCode>>,
+  mut resume: ResMut<Resume>,
+  mut state: ResMut<State<AppState>>,
+  query: Query<Entity, With<ResumeText>>,
+) {
+  if resume.found.is_none() {
+    return;
+  }
+  if keyboard_input.just_pressed(KeyCode::Y) {
+    resume.accepted = true;
+    state.set(AppState::Playing).unwrap();
+  } else if keyboard_input.just_pressed(KeyCode::N) {
+    resume.found = None;
+    if let Err(e) = save::remove(SAVE_FILE) {
+      error!("failed to remove autosave: {}", e);
+    }
+    for entity in query.iter() {
+      commands.entity(entity).despawn();
+    }
+  }
+}
+
+fn close_resume(mut commands: Commands, query: Query<Entity, With<ResumeText>>) {
+  for entity in query.iter() {
+    commands.entity(entity).despawn();
+  }
+}
+
+fn restore_game(
+  mut commands: Commands,
+  materials: Res<Materials>,
+  mut resume: ResMut<Resume>,
+  mut active_block: ResMut<ActiveBlock>,
+  mut piece_queue: ResMut<PieceQueue>,
+  mut hold: ResMut<Hold>,
+) {
+  let snapshot = match resume.found.take() {
+    Some(snapshot) if resume.accepted => snapshot,
+    _ => return,
+  };
+  for &(x, y) in snapshot.stacked.iter() {
+    spawn_stacked_block(&mut commands, &materials, Position { x, y });
+  }
+  if let Some(active) = snapshot.active {
+    for &(x, y) in active.cells.iter() {
+      spawn_primitive_block(&mut commands, &materials, Position { x, y });
+    }
+    active_block.block_idx = active.block_idx;
+    active_block.rotation = active.rotation;
+    active_block.phase = PiecePhase::Falling;
+  }
+  piece_queue.queue = snapshot.queue.into_iter().collect();
+  hold.block_idx = snapshot.hold;
+  hold.used = snapshot.hold_used;
+}
+
+fn autosave(
+  time: Res<Time>,
+  mut timer: ResMut<AutosaveTimer>,
+  active_block: Res<ActiveBlock>,
+  piece_queue: Res<PieceQueue>,
+  hold: Res<Hold>,
+  stacked_query: Query<&Position, With<StackedBlock>>,
+  primitive_query: Query<&Position, (With<PrimitiveBlock>, Without<StackedBlock>)>,
+) {
+  if !timer.0.tick(time.delta()).just_finished() {
+    return;
+  }
+  let cells: Vec<(i32, i32)> = primitive_query.iter().map(|p| (p.x, p.y)).collect();
+  let snapshot = Snapshot {
+    stacked: stacked_query.iter().map(|p| (p.x, p.y)).collect(),
+    active: if active_block.is_on() && !cells.is_empty() {
+      Some(ActivePiece {
+        block_idx: active_block.block_idx,
+        rotation: active_block.rotation,
+        cells,
+      })
+    } else {
+      None
+    },
+    hold: hold.block_idx,
+    hold_used: hold.used,
+    queue: piece_queue.queue.iter().copied().collect(),
+  };
+  if let Err(e) = save::store(SAVE_FILE, &snapshot) {
+    error!("failed to autosave: {}", e);
+  }
+}
+
+// ゲームが最後まで終わったら再開する必要は無い
+fn discard(mut resume: ResMut<Resume>) {
+  resume.found = None;
+  if let Err(e) = save::remove(SAVE_FILE) {
+    error!("failed to remove autosave: {}", e);
+  }
+}
@@ -0,0 +1,1471 @@
+use bevy::prelude::*;
+use rand::Rng;
+
+use crate::attack::{choose_target, AttackTracker, Targeting};
+use crate::board::Board;
+use crate::caster::caster_slots;
+use crate::config::Config;
+use crate::detach::{self, Detached};
+use crate::finale::{finale_cell, FinaleCell, FINALE_SECONDS};
+use crate::garbage::{GarbageConfig, GarbageGenerator, GarbageQueue};
+use crate::momentum::{momentum_text, Momentum};
+use crate::profile::{tag_color, AutoShift, Handling};
+use crate::protocol::{Handicap, NameTag, Snapshot, MAX_PREVIEWS, MAX_START_GARBAGE, QUICK_CHAT};
+use crate::randomizer::{PieceQueue, RandomizerKind};
+use crate::rumble::{Pulse, Rumble};
+use crate::sim::{Action, GameState, Piece};
+use crate::speed::TickRate;
+use crate::victory::{victory_conditions, MatchView, Standing, VictoryCondition};
+use crate::{
+  block_name, overlay_text, AppState, Direction, Fonts, Materials, ARENA_HEIGHT, ARENA_WIDTH,
+};
+
+pub const MAX_PLAYERS: usize = 4;
+const MIN_PLAYERS: usize = 2;
+// 次に出すミノをいくつ先まで用意しておくか
+const QUEUE_LEN: usize = 5;
+// 1マス落ちるまでの秒数と、下を押しているときの倍率
+const GRAVITY: f32 = 0.8;
+const SOFT_DROP_FACTOR: f32 = 20.0;
+// 盤面どうしの間隔 (マス)
+const GAP: f32 = 2.0;
+// チャットを盤面の上に出しておく秒数と、続けて送れるまでの秒数
+const CHAT_SECONDS: f32 = 3.0;
+const CHAT_COOLDOWN: f32 = 1.0;
+// 通信対戦で QUICK_CHAT を送るキー。0 で相手のチャットを消す
+const CHAT_KEYS: [KeyCode; 8] = [
+  KeyCode::Key1,
+  KeyCode::Key2,
+  KeyCode::Key3,
+  KeyCode::Key4,
+  KeyCode::Key5,
+  KeyCode::Key6,
+  KeyCode::Key7,
+  KeyCode::Key8,
+];
+
+// 自分の攻撃の送り先。自動の決め方か、決まった相手
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Aim {
+  Auto(Targeting),
+  Player(usize),
+}
+
+impl Aim {
+  // 自動の決め方を順に回したあと、生き残っている相手を順に選ぶ
+  pub fn next(&self, from: usize, alive: &[bool]) -> Aim {
+    let mut options: Vec<Aim> = Targeting::ALL.iter().map(|&t| Aim::Auto(t)).collect();
+    options.extend(
+      (0..alive.len())
+        .filter(|&i| i != from && alive[i])
+        .map(Aim::Player),
+    );
+    match options.iter().position(|aim| aim == self) {
+      Some(i) => options[(i + 1) % options.len()],
+      None => options[0],
+    }
+  }
+
+  // 選んだ相手が脱落していたらランダムに送る
+  pub fn target(
+    &self,
+    from: usize,
+    alive: &[bool],
+    lines: &[u32],
+    last_attacker: Option<usize>,
+    roll: usize,
+  ) -> Option<usize> {
+    match *self {
+      Aim::Player(to) if to != from && alive.get(to) == Some(&true) => Some(to),
+      Aim::Player(_) => choose_target(Targeting::Random, from, alive, lines, last_attacker, roll),
+      Aim::Auto(rule) => choose_target(rule, from, alive, lines, last_attacker, roll),
+    }
+  }
+
+  // 送る前から分かる送り先。ランダムは送るまで決まらない
+  fn preview(
+    &self,
+    from: usize,
+    alive: &[bool],
+    lines: &[u32],
+    last_attacker: Option<usize>,
+  ) -> Option<usize> {
+    match *self {
+      Aim::Auto(Targeting::Random) => None,
+      Aim::Auto(Targeting::Attacker) if last_attacker.is_none() => None,
+      _ => self.target(from, alive, lines, last_attacker, 0),
+    }
+  }
+
+  fn name(&self) -> String {
+    match self {
+      Aim::Auto(rule) => rule.name().to_string(),
+      Aim::Player(to) => format!("P{}", to + 1),
+    }
+  }
+}
+
+// キーボードを分け合うときの割り当て
+struct KeyMap {
+  left: KeyCode,
+  right: KeyCode,
+  soft_drop: KeyCode,
+  hard_drop: KeyCode,
+  rotate_cw: KeyCode,
+  rotate_ccw: KeyCode,
+  hold: KeyCode,
+  target: KeyCode,
+}
+
+const KEYMAPS: [KeyMap; MAX_PLAYERS] = [
+  KeyMap {
+    left: KeyCode::A,
+    right: KeyCode::D,
+    soft_drop: KeyCode::S,
+    hard_drop: KeyCode::W,
+    rotate_cw: KeyCode::E,
+    rotate_ccw: KeyCode::Q,
+    hold: KeyCode::LShift,
+    target: KeyCode::R,
+  },
+  KeyMap {
+    left: KeyCode::Left,
+    right: KeyCode::Right,
+    soft_drop: KeyCode::Down,
+    hard_drop: KeyCode::Up,
+    rotate_cw: KeyCode::Period,
+    rotate_ccw: KeyCode::Comma,
+    hold: KeyCode::RShift,
+    target: KeyCode::Slash,
+  },
+  KeyMap {
+    left: KeyCode::Numpad4,
+    right: KeyCode::Numpad6,
+    soft_drop: KeyCode::Numpad5,
+    hard_drop: KeyCode::Numpad8,
+    rotate_cw: KeyCode::Numpad9,
+    rotate_ccw: KeyCode::Numpad7,
+    hold: KeyCode::Numpad0,
+    target: KeyCode::NumpadAdd,
+  },
+  KeyMap {
+    left: KeyCode::J,
+    right: KeyCode::L,
+    soft_drop: KeyCode::K,
+    hard_drop: KeyCode::I,
+    rotate_cw: KeyCode::O,
+    rotate_ccw: KeyCode::U,
+    hold: KeyCode::H,
+    target: KeyCode::Y,
+  },
+];
+
+const CONTROLS_HELP: &str = "P1  A/D/S  W drop  Q/E rotate  LShift hold  R target\n\
+P2  arrows  Up drop  ,/. rotate  RShift hold  / target\n\
+P3  numpad 4/6/5  8 drop  7/9 rotate  0 hold  + target\n\
+P4  J/L/K  I drop  U/O rotate  H hold  Y target\n\
+gamepad N also controls player N+1 (North to change target)";
+
+struct Player {
+  state: GameState,
+  // state.queue より先のミノ
+  pieces: PieceQueue,
+  garbage: GarbageGenerator,
+  tracker: AttackTracker,
+  auto_shift: AutoShift,
+  gravity: Timer,
+  incoming: GarbageQueue,
+  sent: u32,
+  tetrises: u32,
+  aim: Aim,
+  last_attacker: Option<usize>,
+  // ランダムのときに表示する、最後に送った相手
+  last_target: Option<usize>,
+  // 通信先が動かしていて、届いた盤面を描くだけ
+  remote: bool,
+  // 見える次のミノの数
+  previews: u8,
+}
+
+impl Player {
+  // ミノの並びは全員同じにする
+  fn new(seed: u64, aim: Aim) -> Self {
+    let mut pieces = PieceQueue::new(RandomizerKind::Bag.build(seed));
+    let queue: Vec<u32> = (0..=QUEUE_LEN).map(|_| pieces.pop()).collect();
+    Player {
+      state: GameState::new(queue),
+      pieces,
+      garbage: GarbageGenerator::new(&GarbageConfig::default(), rand::random()),
+      tracker: AttackTracker::default(),
+      auto_shift: AutoShift::default(),
+      gravity: Timer::from_seconds(GRAVITY, true),
+      incoming: GarbageQueue::default(),
+      sent: 0,
+      tetrises: 0,
+      aim,
+      last_attacker: None,
+      last_target: None,
+      remote: false,
+      previews: MAX_PREVIEWS,
+    }
+  }
+
+  fn apply(&mut self, action: Action) {
+    self.state = self.state.apply(action);
+    while self.state.queue.len() < QUEUE_LEN {
+      let next = self.pieces.pop();
+      self.state.queue.push_back(next);
+    }
+  }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Phase {
+  Setup,
+  Playing,
+  Over,
+}
+
+// 1台の画面を分け合う対戦。通信対戦では 2 人目を通信先が動かす
+pub struct Battle {
+  phase: Phase,
+  count: usize,
+  targeting: Targeting,
+  conditions: Vec<Box<dyn VictoryCondition>>,
+  // conditions のうち今の対戦で使うもの
+  condition: usize,
+  players: Vec<Player>,
+  elapsed: f32,
+  // 脱落した順
+  eliminated: Vec<usize>,
+  // 決着が付いたときの 1 位から順の並び
+  placings: Vec<usize>,
+  // 決着してからの秒数。演出が終わるまで結果は出さない
+  finale: f32,
+  // 直近に送った攻撃。観戦している人にも流れが分かるように棒で出す
+  momentum: Momentum,
+  online: bool,
+  // 通信先へまだ送っていない攻撃
+  outgoing: u32,
+  // 通信先から受け取った攻撃の合計。途中で届かなかった分を State で埋める
+  received: u32,
+  // 通信が切れて相手を待っている間は進めない
+  paused: bool,
+  // 接続の状況
+  status: Option<String>,
+  // 対戦サーバーでの自分の席。盤面はすべてサーバーから届き、手元は操作を送るだけ
+  seat: Option<usize>,
+  // サーバーへまだ送っていない操作
+  inputs: Vec<Action>,
+  // 盤面の上に出しているチャット
+  bubbles: Vec<Bubble>,
+  // 通信先へまだ送っていないチャットの番号
+  chat: Vec<u8>,
+  chat_muted: bool,
+  // 通信対戦の開始条件。0 が自分、1 が相手
+  handicaps: [Handicap; 2],
+  // 通信対戦のホストで、開始条件を決められる
+  host: bool,
+  // ホストが変えた開始条件を、まだ相手に送っていない
+  handicap_changed: bool,
+  // 通信対戦で盤面の上と結果に出す名札。自分の分と、届いた相手の分
+  local_tag: Option<NameTag>,
+  tags: Vec<Option<NameTag>>,
+  // ボットどうしの対戦を眺める。盤面は外から届き、決着も外で決める
+  exhibition: bool,
+  // 配信向けの配置。2 人の盤面を大きく並べ、実況の欄を横に出す
+  caster: bool,
+}
+
+struct Bubble {
+  player: usize,
+  phrase: usize,
+  left: f32,
+}
+
+impl Default for Battle {
+  fn default() -> Self {
+    Self {
+      phase: Phase::Setup,
+      count: MAX_PLAYERS,
+      targeting: Targeting::Random,
+      conditions: victory_conditions(),
+      condition: 0,
+      players: vec![],
+      elapsed: 0.,
+      eliminated: vec![],
+      placings: vec![],
+      finale: 0.,
+      momentum: Momentum::default(),
+      online: false,
+      outgoing: 0,
+      received: 0,
+      paused: false,
+      status: None,
+      seat: None,
+      inputs: vec![],
+      bubbles: vec![],
+      chat: vec![],
+      chat_muted: false,
+      handicaps: [Handicap::default(); 2],
+      host: false,
+      handicap_changed: false,
+      local_tag: None,
+      tags: vec![],
+      exhibition: false,
+      caster: false,
+    }
+  }
+}
+
+impl Battle {
+  fn start(&mut self) {
+    let seed = rand::random();
+    let aim = Aim::Auto(self.targeting);
+    self.players = (0..self.count).map(|_| Player::new(seed, aim)).collect();
+    for i in 0..self.count {
+      let handicap = self.handicap(i);
+      let player = &mut self.players[i];
+      player.previews = handicap.previews;
+      let holes: Vec<i32> = (0..handicap.garbage)
+        .map(|_| player.garbage.next_hole())
+        .collect();
+      player.state.receive_garbage(&holes);
+    }
+    self.elapsed = 0.;
+    self.eliminated.clear();
+    self.placings.clear();
+    self.momentum.reset(self.count);
+    self.phase = Phase::Playing;
+  }
+
+  // 自分と通信先の 2 人で始める。対戦サーバーでは全員の盤面が届く
+  pub fn start_online(&mut self, condition: usize) {
+    self.online = true;
+    if self.seat.is_none() {
+      self.count = MIN_PLAYERS;
+    }
+    self.condition = condition.min(self.conditions.len() - 1);
+    self.outgoing = 0;
+    self.received = 0;
+    self.paused = false;
+    self.inputs.clear();
+    self.start();
+    for (i, player) in self.players.iter_mut().enumerate() {
+      player.remote = i != 0 || self.seat.is_some();
+    }
+  }
+
+  // 2 人ともボットが動かす対戦を始める
+  pub fn start_exhibition(&mut self, condition: usize) {
+    self.exhibition = true;
+    self.count = MIN_PLAYERS;
+    self.condition = condition.min(self.conditions.len() - 1);
+    self.start();
+    for player in self.players.iter_mut() {
+      player.remote = true;
+    }
+  }
+
+  pub fn set_exhibition(&mut self, status: String, elapsed: f32) {
+    self.exhibition = true;
+    self.status = Some(status);
+    self.elapsed = elapsed;
+  }
+
+  pub fn finish(&mut self, placings: Vec<usize>) {
+    self.placings = placings;
+    self.phase = Phase::Over;
+    self.finale = 0.;
+  }
+
+  // 決着の演出を見せている間
+  fn in_finale(&self) -> bool {
+    self.phase == Phase::Over && self.finale < FINALE_SECONDS
+  }
+
+  pub fn reset(&mut self) {
+    self.phase = Phase::Setup;
+  }
+
+  pub fn join_server(&mut self, seat: usize, players: usize) {
+    self.online = true;
+    self.seat = Some(seat);
+    self.count = players.clamp(MIN_PLAYERS, MAX_PLAYERS);
+  }
+
+  pub fn on_server(&self) -> bool {
+    self.seat.is_some()
+  }
+
+  pub fn take_inputs(&mut self) -> Vec<Action> {
+    std::mem::take(&mut self.inputs)
+  }
+
+  pub fn take_chat(&mut self) -> Vec<u8> {
+    std::mem::take(&mut self.chat)
+  }
+
+  // 届いたチャットを送り主の盤面の上に出す。1 対 1 では相手は 2 人目
+  pub fn receive_chat(&mut self, seat: u8, phrase: u8) {
+    let player = self.seat.map_or(1, |_| seat as usize);
+    // 新しい版にしか無い定型文は出さない
+    if self.chat_muted || phrase as usize >= QUICK_CHAT.len() {
+      return;
+    }
+    self.show_bubble(player, phrase as usize);
+  }
+
+  pub fn set_local_tag(&mut self, tag: NameTag) {
+    self.local_tag = Some(tag);
+  }
+
+  // 届いた名札を送り主の盤面に付ける。1 対 1 では相手は 2 人目
+  pub fn receive_tag(&mut self, seat: u8, tag: NameTag) {
+    let player = self.seat.map_or(1, |_| seat as usize);
+    if player >= MAX_PLAYERS {
+      return;
+    }
+    if self.tags.len() <= player {
+      self.tags.resize(player + 1, None);
+    }
+    self.tags[player] = Some(tag);
+  }
+
+  // 別の相手とつながったら、前の相手の名札は外す
+  pub fn clear_tags(&mut self) {
+    self.tags.clear();
+  }
+
+  fn tag(&self, player: usize) -> Option<&NameTag> {
+    if !self.online {
+      return None;
+    }
+    if player == self.seat.unwrap_or(0) {
+      self.local_tag.as_ref()
+    } else {
+      self.tags.get(player).and_then(Option::as_ref)
+    }
+  }
+
+  // 名札があれば番号の後ろに名前を付ける
+  fn player_name(&self, player: usize) -> String {
+    match self.tag(player) {
+      Some(tag) if !tag.name.is_empty() => format!("P{} {}", player + 1, tag.name),
+      _ => format!("P{}", player + 1),
+    }
+  }
+
+  fn show_bubble(&mut self, player: usize, phrase: usize) {
+    self.bubbles.retain(|b| b.player != player);
+    self.bubbles.push(Bubble {
+      player,
+      phrase,
+      left: CHAT_SECONDS,
+    });
+  }
+
+  pub fn condition_index(&self) -> usize {
+    self.condition
+  }
+
+  pub fn is_playing(&self) -> bool {
+    self.phase == Phase::Playing
+  }
+
+  pub fn is_over(&self) -> bool {
+    self.phase == Phase::Over
+  }
+
+  pub fn is_setup(&self) -> bool {
+    self.phase == Phase::Setup
+  }
+
+  pub fn is_caster(&self) -> bool {
+    self.caster
+  }
+
+  // 2 人の対戦のときだけ使える
+  pub fn set_caster(&mut self, caster: bool) {
+    self.caster = caster;
+  }
+
+  fn uses_caster_layout(&self) -> bool {
+    self.caster && self.players.len() == MIN_PLAYERS
+  }
+
+  pub fn set_online(&mut self, status: String, host: bool) {
+    self.online = true;
+    self.host = host;
+    self.status = Some(status);
+  }
+
+  // 開始条件は 1 対 1 の通信対戦だけで使う
+  fn handicap(&self, player: usize) -> Handicap {
+    match self.handicaps.get(player) {
+      Some(&handicap) if self.online && self.seat.is_none() => handicap,
+      _ => Handicap::default(),
+    }
+  }
+
+  pub fn handicaps(&self) -> [Handicap; 2] {
+    self.handicaps
+  }
+
+  pub fn take_handicap_changed(&mut self) -> bool {
+    std::mem::take(&mut self.handicap_changed)
+  }
+
+  // ホストから届いた開始条件。ホストにとっての相手が自分
+  pub fn receive_handicap(&mut self, host: Handicap, guest: Handicap) {
+    self.handicaps = [guest, host];
+  }
+
+  // 相手が開始条件に対応していなければ、同じ条件で遊ぶ
+  pub fn clear_handicaps(&mut self) {
+    self.handicaps = [Handicap::default(); 2];
+  }
+
+  pub fn set_paused(&mut self, paused: bool) {
+    self.paused = paused;
+  }
+
+  // 戻ってこなかった通信先の負けにする。決着は次のフレームで付く
+  pub fn forfeit_remote(&mut self) {
+    self.paused = false;
+    if self.phase != Phase::Playing {
+      return;
+    }
+    for player in self.players.iter_mut().filter(|p| p.remote) {
+      player.state.game_over = true;
+    }
+  }
+
+  pub fn take_outgoing(&mut self) -> u32 {
+    std::mem::take(&mut self.outgoing)
+  }
+
+  // 通信先から届いた攻撃。相殺や、せり上がるタイミングは手元と同じ
+  pub fn receive_garbage(&mut self, lines: u32) {
+    self.received = self.received.saturating_add(lines);
+    if let Some(player) = self.players.first_mut() {
+      player.incoming.push(lines);
+      player.last_attacker = Some(1);
+    }
+  }
+
+  pub fn local_snapshot(&self, with_active: bool) -> Snapshot {
+    let player = &self.players[0];
+    let active = player.state.active.as_ref().filter(|_| with_active);
+    Snapshot {
+      pieces: player.state.pieces,
+      lines: player.state.lines,
+      tetrises: player.tetrises,
+      sent: player.sent,
+      game_over: player.state.game_over,
+      rows: player.state.board.rows().to_vec(),
+      active: active.map(|piece| {
+        let cells = piece.cells.iter().map(|p| (p.x, p.y)).collect();
+        (piece.block_idx, cells)
+      }),
+    }
+  }
+
+  pub fn apply_snapshot(&mut self, snapshot: Snapshot) {
+    if snapshot.sent > self.received {
+      self.receive_garbage(snapshot.sent - self.received);
+    }
+    if let Some(i) = self.players.iter().position(|p| p.remote) {
+      self.apply_seat(i, snapshot);
+    }
+  }
+
+  // 対戦サーバーから届いた、席 seat の盤面
+  pub fn apply_seat(&mut self, seat: usize, snapshot: Snapshot) {
+    let player = match self.players.get_mut(seat) {
+      Some(player) => player,
+      None => return,
+    };
+    player.state.board = Board::from_rows(snapshot.rows);
+    player.state.active = snapshot.active.map(|(block_idx, cells)| Piece {
+      block_idx,
+      rotation: 0,
+      cells: cells
+        .into_iter()
+        .map(|(x, y)| crate::Position { x, y })
+        .collect(),
+    });
+    player.state.pieces = snapshot.pieces;
+    player.state.lines = snapshot.lines;
+    player.state.game_over = snapshot.game_over;
+    player.tetrises = snapshot.tetrises;
+    player.sent = snapshot.sent;
+  }
+
+  fn alive(&self) -> Vec<bool> {
+    self.players.iter().map(|p| !p.state.game_over).collect()
+  }
+
+  fn lines(&self) -> Vec<u32> {
+    self.players.iter().map(|p| p.state.lines).collect()
+  }
+
+  // それぞれの攻撃が今どこへ向かうか
+  fn targets(&self) -> Vec<Option<usize>> {
+    let alive = self.alive();
+    let lines = self.lines();
+    self
+      .players
+      .iter()
+      .enumerate()
+      .map(|(i, p)| {
+        if p.state.game_over {
+          return None;
+        }
+        p.aim
+          .preview(i, &alive, &lines, p.last_attacker)
+          .or(p.last_target.filter(|&to| alive[to]))
+      })
+      .collect()
+  }
+
+  fn condition(&self) -> &dyn VictoryCondition {
+    self.conditions[self.condition].as_ref()
+  }
+
+  fn standings(&self) -> Vec<Standing> {
+    self
+      .players
+      .iter()
+      .map(|p| Standing {
+        alive: !p.state.game_over,
+        lines: p.state.lines,
+        tetrises: p.tetrises,
+      })
+      .collect()
+  }
+
+  // 勝敗の判定に渡す途中経過
+  fn with_view<T>(&self, f: impl FnOnce(&MatchView) -> T) -> T {
+    let standings = self.standings();
+    f(&MatchView {
+      elapsed: self.elapsed,
+      standings: &standings,
+      eliminated: &self.eliminated,
+    })
+  }
+}
+
+struct BattleText;
+struct BattleBoard(usize);
+// 盤面の上に出す、攻撃の送り先と狙ってきている相手
+struct BattleLabel(usize);
+struct BattleBubble(usize);
+struct BattleNext(usize);
+struct BattleCell {
+  player: usize,
+  x: i32,
+  y: i32,
+}
+
+pub struct BattlePlugin;
+
+impl Plugin for BattlePlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(Battle::default())
+      .add_system_set(SystemSet::on_enter(AppState::Battle).with_system(setup_battle.system()))
+      .add_system_set(
+        SystemSet::on_update(AppState::Battle)
+          .with_system(battle_menu.system())
+          .with_system(battle_play.system())
+          .with_system(battle_chat.system())
+          .with_system(battle_layout.system())
+          .with_system(battle_finale.system())
+          .with_system(battle_momentum.system())
+          .with_system(battle_render.system())
+          .with_system(battle_ui.system()),
+      );
+  }
+}
+
+fn setup_battle(mut commands: Commands, fonts: Res<Fonts>, materials: Res<Materials>) {
+  commands
+    .spawn_bundle(overlay_text(&fonts))
+    .insert(BattleText);
+  for player in 0..MAX_PLAYERS {
+    commands
+      .spawn_bundle(SpriteBundle {
+        material: materials.panel.clone(),
+        visible: Visible {
+          is_visible: false,
+          is_transparent: true,
+        },
+        ..Default::default()
+      })
+      .insert(BattleBoard(player));
+    commands
+      .spawn_bundle(Text2dBundle {
+        text: Text::with_section(
+          "",
+          TextStyle {
+            font: fonts.main.clone(),
+            font_size: 16.0,
+            color: Color::WHITE,
+          },
+          TextAlignment {
+            vertical: VerticalAlign::Center,
+            horizontal: HorizontalAlign::Center,
+          },
+        ),
+        ..Default::default()
+      })
+      .insert(BattleLabel(player));
+    commands
+      .spawn_bundle(Text2dBundle {
+        text: Text::with_section(
+          "",
+          TextStyle {
+            font: fonts.main.clone(),
+            font_size: 24.0,
+            color: Color::YELLOW,
+          },
+          TextAlignment {
+            vertical: VerticalAlign::Center,
+            horizontal: HorizontalAlign::Center,
+          },
+        ),
+        ..Default::default()
+      })
+      .insert(BattleBubble(player));
+    commands
+      .spawn_bundle(Text2dBundle {
+        text: Text::with_section(
+          "",
+          TextStyle {
+            font: fonts.main.clone(),
+            font_size: 16.0,
+            color: Color::WHITE,
+          },
+          TextAlignment {
+            vertical: VerticalAlign::Center,
+            horizontal: HorizontalAlign::Center,
+          },
+        ),
+        ..Default::default()
+      })
+      .insert(BattleNext(player));
+    for y in 0..ARENA_HEIGHT as i32 {
+      for x in 0..ARENA_WIDTH as i32 {
+        commands
+          .spawn_bundle(SpriteBundle {
+            material: materials.transparent.clone(),
+            ..Default::default()
+          })
+          .insert(BattleCell { player, x, y });
+      }
+    }
+  }
+}
+
+fn battle_menu(
+  keyboard_input: Res<Input<KeyCode>>,
+  buttons: Res<Input<GamepadButton>>,
+  mut battle: ResMut<Battle>,
+) {
+  if battle.exhibition {
+    return;
+  }
+  // 通信対戦の開始は通信の方で決める。ホストは始まる前に開始条件を変えられる
+  if battle.online {
+    if battle.host && battle.seat.is_none() && battle.phase != Phase::Playing {
+      handicap_menu(&keyboard_input, &mut battle);
+    }
+    return;
+  }
+  let start = keyboard_input.just_pressed(KeyCode::Return)
+    || (0..MAX_PLAYERS)
+      .map(Gamepad)
+      .any(|pad| buttons.just_pressed(GamepadButton(pad, GamepadButtonType::Start)));
+  match battle.phase {
+    Phase::Setup => {
+      let keys = [KeyCode::Key2, KeyCode::Key3, KeyCode::Key4];
+      for (i, &key) in keys.iter().enumerate() {
+        if keyboard_input.just_pressed(key) {
+          battle.count = MIN_PLAYERS + i;
+        }
+      }
+      if keyboard_input.just_pressed(KeyCode::T) {
+        battle.targeting = battle.targeting.next();
+      }
+      if keyboard_input.just_pressed(KeyCode::V) {
+        battle.condition = (battle.condition + 1) % battle.conditions.len();
+      }
+      if start {
+        battle.start();
+      }
+    }
+    Phase::Playing => {}
+    // 演出の途中で押したら飛ばして結果を出す
+    Phase::Over if battle.in_finale() => {
+      if start || keyboard_input.just_pressed(KeyCode::Escape) {
+        battle.finale = FINALE_SECONDS;
+      }
+    }
+    Phase::Over => {
+      if start {
+        battle.start();
+      } else if keyboard_input.just_pressed(KeyCode::Escape) {
+        battle.phase = Phase::Setup;
+      }
+    }
+  }
+}
+
+// Z/X で自分、C/V で相手のせり上がりと見える次のミノを変える
+fn handicap_menu(keyboard_input: &Input<KeyCode>, battle: &mut Battle) {
+  let keys = [(KeyCode::Z, KeyCode::X), (KeyCode::C, KeyCode::V)];
+  for (i, &(garbage, previews)) in keys.iter().enumerate() {
+    let handicap = &mut battle.handicaps[i];
+    if keyboard_input.just_pressed(garbage) {
+      handicap.garbage = (handicap.garbage + 1) % (MAX_START_GARBAGE + 1);
+      battle.handicap_changed = true;
+    }
+    if keyboard_input.just_pressed(previews) {
+      handicap.previews = (handicap.previews + MAX_PREVIEWS) % (MAX_PREVIEWS + 1);
+      battle.handicap_changed = true;
+    }
+  }
+}
+
+fn describe_handicap(handicap: &Handicap) -> String {
+  format!(
+    "{} garbage, {} previews",
+    handicap.garbage, handicap.previews
+  )
+}
+
+fn battle_play(
+  time: Res<Time>,
+  config: Res<Config>,
+  keyboard_input: Res<Input<KeyCode>>,
+  buttons: Res<Input<GamepadButton>>,
+  mut battle: ResMut<Battle>,
+  mut rumble: ResMut<Rumble>,
+) {
+  if battle.phase != Phase::Playing || battle.paused || battle.exhibition {
+    return;
+  }
+  let alive = battle.alive();
+  let mut attacks = vec![];
+
+  // 対戦サーバーでは 1P の操作を自分の席の操作として送る。重力はサーバーが進める
+  if let Some(seat) = battle.seat {
+    let mut inputs = vec![];
+    if let Some(player) = battle.players.get_mut(seat) {
+      if !player.state.game_over {
+        let (actions, soft_drop) = read_actions(
+          0,
+          &keyboard_input,
+          &buttons,
+          &mut player.auto_shift,
+          &time,
+          config.tick_rate,
+        );
+        if actions.contains(&Action::HardDrop) {
+          rumble.pulse(0, Pulse::HardDrop);
+        }
+        inputs = actions;
+        let delta = time.delta().mul_f32(SOFT_DROP_FACTOR);
+        if soft_drop && player.gravity.tick(delta).just_finished() {
+          inputs.push(Action::SoftDrop);
+        }
+      }
+    }
+    battle.inputs.extend(inputs);
+  }
+
+  for (i, player) in battle.players.iter_mut().enumerate() {
+    if player.state.game_over || player.remote {
+      continue;
+    }
+    let keys = &KEYMAPS[i];
+    let pad = GamepadButton(Gamepad(i), GamepadButtonType::North);
+    if keyboard_input.just_pressed(keys.target) || buttons.just_pressed(pad) {
+      player.aim = player.aim.next(i, &alive);
+    }
+    let before = player.state.pieces;
+    let lines_before = player.state.lines;
+
+    let (actions, soft_drop) = read_actions(
+      i,
+      &keyboard_input,
+      &buttons,
+      &mut player.auto_shift,
+      &time,
+      config.tick_rate,
+    );
+    if actions.contains(&Action::HardDrop) {
+      rumble.pulse(i, Pulse::HardDrop);
+    }
+    for action in actions {
+      player.apply(action);
+    }
+    let mut delta = time.delta();
+    if soft_drop {
+      delta = delta.mul_f32(SOFT_DROP_FACTOR);
+    }
+    if player.state.pieces == before && player.gravity.tick(delta).just_finished() {
+      // 落ちられなければその場で固定する
+      let active = player.state.active.clone();
+      player.apply(Action::SoftDrop);
+      if player.state.active == active {
+        player.apply(Action::HardDrop);
+      }
+    }
+
+    if player.state.pieces == before {
+      continue;
+    }
+    player.gravity.reset();
+    player.tracker.record_lock();
+    let lines = player.state.lines - lines_before;
+    if lines == 0 {
+      // ラインを消さずに積んだので、来ている分をせり上げる
+      let rows = player.incoming.take();
+      let holes: Vec<i32> = (0..rows).map(|_| player.garbage.next_hole()).collect();
+      player.state.receive_garbage(&holes);
+      if rows > 0 {
+        rumble.pulse(i, Pulse::Garbage(rows));
+      }
+      continue;
+    }
+    rumble.pulse(i, Pulse::Clear(lines));
+    let clear = player.tracker.record_clear(lines);
+    if lines >= 4 {
+      player.tetrises += 1;
+    }
+    // 攻撃はまず来ているせり上がりの相殺に使う
+    let sent = player.incoming.cancel(clear.attack);
+    if sent > 0 {
+      attacks.push((i, sent));
+    }
+  }
+
+  for (from, amount) in attacks {
+    let alive = battle.alive();
+    let lines = battle.lines();
+    let last_attacker = battle.players[from].last_attacker;
+    let roll = rand::thread_rng().gen_range(0..MAX_PLAYERS);
+    let aim = battle.players[from].aim;
+    if let Some(to) = aim.target(from, &alive, &lines, last_attacker, roll) {
+      battle.players[from].sent += amount;
+      battle.players[from].last_target = Some(to);
+      if battle.players[to].remote {
+        battle.outgoing += amount;
+        continue;
+      }
+      battle.players[to].incoming.push(amount);
+      battle.players[to].last_attacker = Some(from);
+    }
+  }
+
+  for i in 0..battle.players.len() {
+    if battle.players[i].state.game_over && !battle.eliminated.contains(&i) {
+      battle.eliminated.push(i);
+    }
+  }
+  battle.elapsed += time.delta_seconds();
+  if let Some(placings) = battle.with_view(|view| battle.condition().check(view)) {
+    battle.finish(placings);
+  }
+}
+
+// 通信対戦の定型チャット。自分の分は自分の盤面の上に出す
+fn battle_chat(
+  time: Res<Time>,
+  keyboard_input: Res<Input<KeyCode>>,
+  mut config: ResMut<Config>,
+  mut battle: ResMut<Battle>,
+  mut cooldown: Local<f32>,
+) {
+  let dt = time.delta_seconds();
+  for bubble in battle.bubbles.iter_mut() {
+    bubble.left -= dt;
+  }
+  battle.bubbles.retain(|b| b.left > 0.);
+  battle.chat_muted = config.mute_chat;
+  // 消したら、出ている相手のチャットもすぐ消す
+  let me = battle.seat.unwrap_or(0);
+  if battle.chat_muted {
+    battle.bubbles.retain(|b| b.player == me);
+  }
+  if !battle.online {
+    return;
+  }
+  if keyboard_input.just_pressed(KeyCode::Key0) {
+    config.mute_chat = !config.mute_chat;
+    config.store();
+  }
+  *cooldown -= dt;
+  let phrase = CHAT_KEYS
+    .iter()
+    .position(|&key| keyboard_input.just_pressed(key));
+  if let (Some(phrase), true) = (phrase, *cooldown <= 0.) {
+    *cooldown = CHAT_COOLDOWN;
+    battle.show_bubble(me, phrase);
+    battle.chat.push(phrase as u8);
+  }
+}
+
+// プレイヤー i の手元の操作を押された順に並べる。協力プレイでも使う。下を押している間は soft drop が true
+pub fn read_actions(
+  i: usize,
+  keyboard_input: &Input<KeyCode>,
+  buttons: &Input<GamepadButton>,
+  auto_shift: &mut AutoShift,
+  time: &Time,
+  rate: TickRate,
+) -> (Vec<Action>, bool) {
+  let keys = &KEYMAPS[i];
+  let pad = Gamepad(i);
+  let pressed = |key: KeyCode, button: GamepadButtonType| {
+    keyboard_input.pressed(key) || buttons.pressed(GamepadButton(pad, button))
+  };
+  let just_pressed = |key: KeyCode, button: GamepadButtonType| {
+    keyboard_input.just_pressed(key) || buttons.just_pressed(GamepadButton(pad, button))
+  };
+  let mut actions = vec![];
+  let direction = match (
+    pressed(keys.left, GamepadButtonType::DPadLeft),
+    pressed(keys.right, GamepadButtonType::DPadRight),
+  ) {
+    (true, false) => Some(Direction::Left),
+    (false, true) => Some(Direction::Right),
+    _ => None,
+  };
+  if auto_shift.update(direction, time.delta_seconds(), &Handling::default(), rate) {
+    match direction {
+      Some(Direction::Left) => actions.push(Action::Left),
+      _ => actions.push(Action::Right),
+    }
+  }
+  if just_pressed(keys.rotate_cw, GamepadButtonType::South) {
+    actions.push(Action::RotateCw);
+  }
+  if just_pressed(keys.rotate_ccw, GamepadButtonType::West) {
+    actions.push(Action::RotateCcw);
+  }
+  if just_pressed(keys.hold, GamepadButtonType::LeftTrigger) {
+    actions.push(Action::Hold);
+  }
+  if just_pressed(keys.hard_drop, GamepadButtonType::DPadUp) {
+    actions.push(Action::HardDrop);
+  }
+  let soft_drop = pressed(keys.soft_drop, GamepadButtonType::DPadDown);
+  (actions, soft_drop)
+}
+
+// 盤面 1 つ分の中心とマスの大きさ
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct BoardSlot {
+  pub center: Vec2,
+  pub cell: f32,
+}
+
+// size のウィンドウの真ん中 (origin) に n 人分の盤面を横に並べる
+fn row_slots(n: usize, size: Vec2, origin: Vec2) -> Vec<BoardSlot> {
+  let columns = n.max(1) as f32 * (ARENA_WIDTH as f32 + GAP);
+  let cell = (size.x / columns).min(size.y / (ARENA_HEIGHT as f32 + GAP));
+  (0..n)
+    .map(|i| BoardSlot {
+      center: origin
+        + Vec2::new(
+          -columns * cell / 2.
+            + (i as f32 * (ARENA_WIDTH as f32 + GAP) + GAP / 2.) * cell
+            + ARENA_WIDTH as f32 * cell / 2.,
+          0.,
+        ),
+      cell,
+    })
+    .collect()
+}
+
+// 人数に合わせた盤面の置き場所。相手の盤面を別ウィンドウに出すときは、本体には自分だけを置く
+pub fn board_slots(count: usize, main: Vec2, detached: Option<Vec2>) -> Vec<BoardSlot> {
+  match detached {
+    Some(size) if count > 1 => {
+      let mut slots = row_slots(1, main, Vec2::ZERO);
+      slots.extend(row_slots(count - 1, size, Vec2::new(detach::ORIGIN_X, 0.)));
+      slots
+    }
+    _ => row_slots(count, main, Vec2::ZERO),
+  }
+}
+
+// 人数に合わせて盤面を横に並べる
+fn battle_layout(
+  windows: Res<Windows>,
+  battle: Res<Battle>,
+  detached: Res<Detached>,
+  mut last: Local<Option<(Vec2, Option<Vec2>, usize, bool)>>,
+  mut board_query: Query<(&BattleBoard, &mut Sprite, &mut Transform, &mut Visible)>,
+  mut cell_query: Query<(&BattleCell, &mut Sprite, &mut Transform), Without<BattleBoard>>,
+  mut label_query: Query<
+    (&BattleLabel, &mut Transform, &mut Visible),
+    (Without<BattleBoard>, Without<BattleCell>),
+  >,
+  mut bubble_query: Query<
+    (&BattleBubble, &mut Transform, &mut Visible),
+    (
+      Without<BattleBoard>,
+      Without<BattleCell>,
+      Without<BattleLabel>,
+    ),
+  >,
+  mut next_query: Query<
+    (&BattleNext, &mut Transform, &mut Visible),
+    (
+      Without<BattleBoard>,
+      Without<BattleCell>,
+      Without<BattleLabel>,
+      Without<BattleBubble>,
+    ),
+  >,
+) {
+  let window = match windows.get_primary() {
+    Some(window) => window,
+    None => return,
+  };
+  let count = if battle.phase == Phase::Setup {
+    0
+  } else {
+    battle.players.len()
+  };
+  let main = Vec2::new(window.width(), window.height());
+  let side = detached.opponents_size(&windows);
+  let caster = battle.uses_caster_layout() && count == MIN_PLAYERS;
+  let key = (main, side, count, caster);
+  if *last == Some(key) {
+    return;
+  }
+  *last = Some(key);
+
+  let slots = if caster {
+    caster_slots(main)
+  } else {
+    board_slots(count, main, side)
+  };
+  let slot = |player: usize| slots.get(player).copied();
+  let size = |slot: BoardSlot| {
+    (
+      ARENA_WIDTH as f32 * slot.cell,
+      ARENA_HEIGHT as f32 * slot.cell,
+    )
+  };
+
+  for (board, mut sprite, mut transform, mut visible) in board_query.iter_mut() {
+    visible.is_visible = board.0 < count;
+    if let Some(slot) = slot(board.0) {
+      let (width, height) = size(slot);
+      sprite.size = Vec2::new(width, height);
+      transform.translation = slot.center.extend(0.);
+    }
+  }
+  for (label, mut transform, mut visible) in label_query.iter_mut() {
+    visible.is_visible = label.0 < count;
+    if let Some(slot) = slot(label.0) {
+      let (_, height) = size(slot);
+      transform.translation =
+        (slot.center + Vec2::new(0., (height + GAP / 2. * slot.cell) / 2.)).extend(2.);
+    }
+  }
+  // チャットは盤面の上の方に、ブロックより手前に出す
+  for (bubble, mut transform, mut visible) in bubble_query.iter_mut() {
+    visible.is_visible = bubble.0 < count;
+    if let Some(slot) = slot(bubble.0) {
+      let (_, height) = size(slot);
+      transform.translation = (slot.center + Vec2::new(0., height / 4.)).extend(3.);
+    }
+  }
+  // 次のミノは盤面の下に出す
+  for (next, mut transform, mut visible) in next_query.iter_mut() {
+    visible.is_visible = next.0 < count;
+    if let Some(slot) = slot(next.0) {
+      let (_, height) = size(slot);
+      transform.translation =
+        (slot.center - Vec2::new(0., (height + GAP / 2. * slot.cell) / 2.)).extend(2.);
+    }
+  }
+  for (pos, mut sprite, mut transform) in cell_query.iter_mut() {
+    if let Some(slot) = slot(pos.player) {
+      let (width, height) = size(slot);
+      sprite.size = Vec2::splat(slot.cell);
+      transform.translation = (slot.center
+        + Vec2::new(
+          -width / 2. + (pos.x as f32 + 0.5) * slot.cell,
+          -height / 2. + (pos.y as f32 + 0.5) * slot.cell,
+        ))
+      .extend(1.);
+    }
+  }
+}
+
+// 手元の攻撃も、通信先やサーバーから届いた分も、送った合計の増え方で拾う
+fn battle_momentum(mut battle: ResMut<Battle>) {
+  if battle.phase != Phase::Playing {
+    return;
+  }
+  let sent: Vec<u32> = battle.players.iter().map(|player| player.sent).collect();
+  if battle.momentum.is_current(&sent) {
+    return;
+  }
+  let now = battle.elapsed;
+  battle.momentum.observe(now, &sent);
+}
+
+fn battle_finale(time: Res<Time>, mut battle: ResMut<Battle>) {
+  if battle.in_finale() {
+    battle.finale += time.delta_seconds();
+  }
+}
+
+fn battle_render(
+  battle: Res<Battle>,
+  config: Res<Config>,
+  materials: Res<Materials>,
+  mut query: Query<(&BattleCell, &mut Handle<ColorMaterial>)>,
+) {
+  if !battle.is_changed() {
+    return;
+  }
+  let winner = battle.placings.first().copied();
+  let tops: Vec<i32> = battle
+    .players
+    .iter()
+    .map(|player| player.state.board.max_height())
+    .collect();
+  for (pos, mut material) in query.iter_mut() {
+    let player = match battle.players.get(pos.player) {
+      Some(player) if battle.phase != Phase::Setup => player,
+      _ => {
+        if *material != materials.transparent {
+          *material = materials.transparent.clone();
+        }
+        continue;
+      }
+    };
+    let cell = crate::Position { x: pos.x, y: pos.y };
+    let is_active = player
+      .state
+      .active
+      .as_ref()
+      .is_some_and(|active| active.cells.contains(&cell));
+    let filled = player.state.board.is_filled(&cell);
+    let finale = if battle.phase == Phase::Over {
+      finale_cell(
+        battle.finale,
+        winner == Some(pos.player),
+        tops[pos.player],
+        (pos.x, pos.y),
+        filled,
+      )
+    } else {
+      FinaleCell::Keep
+    };
+    let next = match finale {
+      FinaleCell::Dim => &materials.dim_block,
+      FinaleCell::Gone => &materials.transparent,
+      // 点滅を止めているときは光らせない
+      FinaleCell::Sparkle if !config.reduce_motion => &materials.sparkle,
+      _ if is_active && !player.state.game_over => &materials.chart_bar,
+      _ if filled => &materials.gray_block,
+      _ => &materials.transparent,
+    };
+    if *material != *next {
+      *material = next.clone();
+    }
+  }
+}
+
+fn handicap_text(battle: &Battle) -> String {
+  if battle.seat.is_some() {
+    return String::new();
+  }
+  let [mine, theirs] = battle.handicaps;
+  if battle.host {
+    format!(
+      "you: {}  [Z/X]\nopponent: {}  [C/V]",
+      describe_handicap(&mine),
+      describe_handicap(&theirs)
+    )
+  } else {
+    format!(
+      "set by host  you: {}  opponent: {}",
+      describe_handicap(&mine),
+      describe_handicap(&theirs)
+    )
+  }
+}
+
+fn battle_ui(
+  battle: Res<Battle>,
+  mut query: Query<&mut Text, With<BattleText>>,
+  mut label_query: Query<(&BattleLabel, &mut Text), Without<BattleText>>,
+  mut bubble_query: Query<(&BattleBubble, &mut Text), (Without<BattleText>, Without<BattleLabel>)>,
+  mut next_query: Query<
+    (&BattleNext, &mut Text),
+    (
+      Without<BattleText>,
+      Without<BattleLabel>,
+      Without<BattleBubble>,
+    ),
+  >,
+) {
+  // 通信先の次のミノは届かないので出さない
+  for (next, mut text) in next_query.iter_mut() {
+    let value = match battle.players.get(next.0) {
+      Some(player) if !player.remote && battle.phase == Phase::Playing => {
+        let pieces: Vec<String> = player
+          .state
+          .queue
+          .iter()
+          .take(player.previews as usize)
+          .map(|&idx| block_name(idx).to_string())
+          .collect();
+        format!("next  {}", pieces.join(" "))
+      }
+      _ => String::new(),
+    };
+    if text.sections[0].value != value {
+      text.sections[0].value = value;
+    }
+  }
+
+  for (bubble, mut text) in bubble_query.iter_mut() {
+    let value = battle
+      .bubbles
+      .iter()
+      .find(|b| b.player == bubble.0)
+      .map_or("", |b| QUICK_CHAT[b.phrase]);
+    if text.sections[0].value != value {
+      text.sections[0].value = value.to_string();
+    }
+  }
+
+  // 盤面の上に、誰に送るかと誰に狙われているかを出す。名札があれば名前とその色で
+  let targets = battle.targets();
+  for (label, mut text) in label_query.iter_mut() {
+    let i = label.0;
+    let mut value = String::new();
+    if let Some(player) = battle.players.get(i) {
+      let name = battle.player_name(i);
+      value = match targets[i] {
+        _ if player.state.game_over => format!("{}  KO", name),
+        Some(to) => format!("{} \u{25b6} P{}", name, to + 1),
+        None => format!("{} \u{25b6} ?", name),
+      };
+      let attackers: Vec<String> = (0..targets.len())
+        .filter(|&from| targets[from] == Some(i))
+        .map(|from| format!("P{}", from + 1))
+        .collect();
+      if !attackers.is_empty() {
+        value += &format!("   \u{25c0} {}", attackers.join(" "));
+      }
+    }
+    if text.sections[0].value != value {
+      text.sections[0].value = value;
+    }
+    let color = battle.tag(i).map_or(Color::WHITE, tag_color);
+    if text.sections[0].style.color != color {
+      text.sections[0].style.color = color;
+    }
+  }
+
+  let mut value = match battle.phase {
+    Phase::Setup if battle.online => {
+      let mut value = "ONLINE BATTLE".to_string();
+      if let Some(seat) = battle.seat {
+        value += &format!("  you are P{} of {}", seat + 1, battle.count);
+      }
+      format!(
+        "{}\nwin by {}\n{}\n\n{}\n[1-8] quick chat  [0] mute chat",
+        value,
+        battle.condition().name(),
+        handicap_text(&battle),
+        CONTROLS_HELP.lines().next().unwrap_or_default(),
+      )
+    }
+    Phase::Setup if battle.exhibition => "AI EXHIBITION".to_string(),
+    Phase::Setup => format!(
+      "BATTLE\nplayers {}  [2-4]\ndefault targeting {}  [T]\nwin by {}  [V]\n\n{}\n\n[Enter] start",
+      battle.count,
+      battle.targeting.name(),
+      battle.condition().name(),
+      CONTROLS_HELP,
+    ),
+    Phase::Playing => {
+      let mut value = format!(
+        "BATTLE  {}  {}\n{}",
+        battle.condition().name(),
+        battle.with_view(|view| battle.condition().status(view)),
+        momentum_text(&battle.momentum, battle.elapsed),
+      );
+      // 配信向けの配置では、1 人ずつの数字は盤面の横の欄に出す
+      let players = if battle.uses_caster_layout() {
+        &battle.players[..0]
+      } else {
+        &battle.players[..]
+      };
+      for (i, player) in players.iter().enumerate() {
+        value += &format!(
+          "\n{}  lines {}  tetrises {}  sent {}  incoming {}  target {}",
+          battle.player_name(i),
+          player.state.lines,
+          player.tetrises,
+          player.sent,
+          player.incoming.rows(),
+          player.aim.name(),
+        );
+        if player.state.game_over {
+          value += "  KO";
+        }
+      }
+      value
+    }
+    Phase::Over if battle.in_finale() => match battle.placings.first() {
+      Some(&winner) => format!("{} WINS", battle.player_name(winner)),
+      None => "GAME SET".to_string(),
+    },
+    Phase::Over => {
+      let mut value = format!("RESULTS  {}", battle.condition().name());
+      for (place, &i) in battle.placings.iter().enumerate() {
+        let player = &battle.players[i];
+        value += &format!(
+          "\n{}. {}  lines {}  tetrises {}  sent {}",
+          place + 1,
+          battle.player_name(i),
+          player.state.lines,
+          player.tetrises,
+          player.sent,
+        );
+      }
+      if battle.online {
+        format!("{}\n\n{}\n[Enter] rematch", value, handicap_text(&battle))
+      } else {
+        value + "\n\n[Enter] rematch  [Esc] setup"
+      }
+    }
+  };
+  if let Some(status) = battle.status.as_ref() {
+    value += &format!("\n\n{}", status);
+    if battle.chat_muted {
+      value += "  (chat muted)";
+    }
+  }
+  for mut text in query.iter_mut() {
+    if text.sections[0].value != value {
+      text.sections[0].value = value.clone();
+    }
+  }
+}
@@ -0,0 +1,259 @@
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::master::Master;
+use crate::sprint::SPRINT_LINES;
+use crate::stats::Statistics;
+use crate::{overlay_text, save, AppState, Fonts, GameClock, GameMode, GameReset};
+
+const SAVE_FILE: &str = "bests.ron";
+
+// モードごとに何を自己ベストとして比べるか
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Metric {
+  // 40 ラインを消すまでの秒数。短いほどよい
+  Time,
+  Lines,
+  Score,
+}
+
+impl Metric {
+  pub fn for_mode(mode: GameMode) -> Option<Metric> {
+    match mode {
+      GameMode::Sprint => Some(Metric::Time),
+      GameMode::Marathon => Some(Metric::Lines),
+      GameMode::Master => Some(Metric::Score),
+      _ => None,
+    }
+  }
+
+  fn better(self, a: f64, b: f64) -> bool {
+    match self {
+      Metric::Time => a < b,
+      _ => a > b,
+    }
+  }
+
+  pub fn format(self, value: f64) -> String {
+    match self {
+      Metric::Time => format!("{:.2}s", value),
+      Metric::Lines => format!("{} lines", value),
+      Metric::Score => format!("{} pts", value),
+    }
+  }
+
+  // ペースの差の単位
+  fn unit(self) -> &'static str {
+    match self {
+      Metric::Score => "pts",
+      _ => "lines",
+    }
+  }
+}
+
+// 自己ベストと、そのゲームで進んだ時刻の記録
+#[derive(Clone, Default, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Best {
+  pub value: f64,
+  // (ゲーム開始からの秒数, その時点のライン数か得点)
+  pub pace: Vec<(f64, u32)>,
+}
+
+// プロフィールに保存するモードごとの自己ベスト
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PersonalBests {
+  pub modes: BTreeMap<String, Best>,
+}
+
+fn mode_key(mode: GameMode) -> String {
+  format!("{:?}", mode)
+}
+
+// 遊んでいるゲームの進み具合と、始めたときの自己ベスト
+#[derive(Default)]
+pub struct PbRun {
+  pub metric: Option<Metric>,
+  pub best: Option<Best>,
+  pub pace: Vec<(f64, u32)>,
+}
+
+// その時刻までに進んでいたライン数か得点
+pub fn progress_at(pace: &[(f64, u32)], elapsed: f64) -> u32 {
+  pace
+    .iter()
+    .take_while(|&&(t, _)| t <= elapsed)
+    .last()
+    .map_or(0, |&(_, n)| n)
+}
+
+impl PbRun {
+  pub fn new(metric: Option<Metric>, best: Option<Best>) -> Self {
+    PbRun {
+      metric,
+      best,
+      pace: vec![],
+    }
+  }
+
+  pub fn progress(&self) -> u32 {
+    self.pace.last().map_or(0, |&(_, n)| n)
+  }
+
+  // ライン数か得点が変わったときに呼ぶ
+  pub fn record(&mut self, elapsed: f64, progress: u32) {
+    self.pace.push((elapsed, progress));
+  }
+
+  // このゲームの記録。スプリントは 40 ライン消していなければ無し
+  pub fn result(&self) -> Option<f64> {
+    let last = self.progress();
+    match self.metric? {
+      Metric::Time => self
+        .pace
+        .iter()
+        .find(|&&(_, n)| n >= SPRINT_LINES)
+        .map(|&(t, _)| t),
+      _ if last > 0 => Some(last as f64),
+      _ => None,
+    }
+  }
+
+  pub fn is_new_best(&self) -> bool {
+    match (self.metric, self.result(), self.best.as_ref()) {
+      (Some(metric), Some(value), Some(best)) => metric.better(value, best.value),
+      (Some(_), Some(_), None) => true,
+      _ => false,
+    }
+  }
+
+  // 同じ経過時間の自己ベストと比べて、どれだけ進んでいるか
+  pub fn delta(&self, elapsed: f64) -> Option<i64> {
+    let best = self.best.as_ref()?;
+    Some(progress_at(&self.pace, elapsed) as i64 - progress_at(&best.pace, elapsed) as i64)
+  }
+
+  pub fn summary(&self) -> Option<String> {
+    let metric = self.metric?;
+    let best = self.best.as_ref().map(|b| metric.format(b.value));
+    match (self.is_new_best(), best) {
+      (true, Some(best)) => Some(format!(
+        "NEW PERSONAL BEST  {} (was {})",
+        metric.format(self.result()?),
+        best
+      )),
+      (true, None) => Some(format!(
+        "NEW PERSONAL BEST  {}",
+        metric.format(self.result()?)
+      )),
+      (false, Some(best)) => Some(format!("personal best {}", best)),
+      (false, None) => None,
+    }
+  }
+}
+
+struct PbText;
+
+pub struct BestsPlugin;
+
+impl Plugin for BestsPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(PbRun::default())
+      .add_startup_system_to_stage(StartupStage::PostStartup, setup_bests.system())
+      .add_system_set(SystemSet::on_enter(AppState::Playing).with_system(load_best.system()))
+      .add_system_set(SystemSet::on_enter(AppState::Results).with_system(record_best.system()))
+      .add_system(pb_update.system())
+      .add_system(pb_ui.system());
+  }
+}
+
+fn setup_bests(mut commands: Commands, fonts: Res<Fonts>) {
+  let mut text = overlay_text(&fonts);
+  text.style.position = Rect {
+    top: Val::Px(84.0),
+    left: Val::Px(5.0),
+    ..Default::default()
+  };
+  commands.spawn_bundle(text).insert(PbText);
+}
+
+fn load_best(mode: Res<GameMode>, mut run: ResMut<PbRun>) {
+  let mut bests = save::load::<PersonalBests>(SAVE_FILE);
+  *run = PbRun::new(
+    Metric::for_mode(*mode),
+    bests.modes.remove(&mode_key(*mode)),
+  );
+}
+
+fn pb_update(
+  clock: Res<GameClock>,
+  state: Res<State<AppState>>,
+  stats: Res<Statistics>,
+  master: Option<Res<Master>>,
+  mut run: ResMut<PbRun>,
+  mut reset_events: EventReader<GameReset>,
+) {
+  if reset_events.iter().count() > 0 {
+    run.pace.clear();
+  }
+  if *state.current() != AppState::Playing {
+    return;
+  }
+  let progress = match run.metric {
+    Some(Metric::Score) => master.map_or(0, |m| m.score),
+    Some(_) => stats.lines(),
+    None => return,
+  };
+  if progress != run.progress() {
+    run.record(clock.0 - stats.start, progress);
+  }
+}
+
+// 結果画面では始めたときの自己ベストと比べたいので、PbRun は書き換えずに保存だけする
+fn record_best(mode: Res<GameMode>, run: Res<PbRun>) {
+  let value = match run.result() {
+    Some(value) if run.is_new_best() => value,
+    _ => return,
+  };
+  let mut bests = save::load::<PersonalBests>(SAVE_FILE);
+  bests.modes.insert(
+    mode_key(*mode),
+    Best {
+      value,
+      pace: run.pace.clone(),
+    },
+  );
+  if let Err(e) = save::store(SAVE_FILE, &bests) {
+    error!("failed to save personal bests: {}", e);
+  }
+}
+
+fn pb_ui(
+  clock: Res<GameClock>,
+  state: Res<State<AppState>>,
+  stats: Res<Statistics>,
+  run: Res<PbRun>,
+  mut query: Query<&mut Text, With<PbText>>,
+) {
+  let playing = matches!(state.current(), AppState::Playing | AppState::Paused);
+  let elapsed = clock.0 - stats.start;
+  // スプリントは自己ベストのゴーストと並べて自前で差を出している
+  let value = match (run.metric, run.best.as_ref(), run.delta(elapsed)) {
+    (Some(Metric::Time), _, _) => String::new(),
+    (Some(metric), Some(best), Some(delta)) if playing => format!(
+      "PB {}  {:+} {} vs pace",
+      metric.format(best.value),
+      delta,
+      metric.unit()
+    ),
+    _ => String::new(),
+  };
+  for mut text in query.iter_mut() {
+    if text.sections[0].value != value {
+      text.sections[0].value = value.clone();
+    }
+  }
+}
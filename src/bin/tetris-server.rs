@@ -0,0 +1,421 @@
+// 対戦サーバー。tetris-server [port] [players]
+// 盤面はすべてサーバーで動かし、手元のゲームからは操作だけを受け取る
+use std::fs;
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use tetris::connection::Connection;
+use tetris::protocol::{
+  negotiate, Agreement, Hello, Message, NameTag, Snapshot, DEFAULT_PORT, FEATURE_ACTIVE_PIECE,
+  FEATURE_CHAT, FEATURE_PING, FEATURE_PROFILE, FEATURE_SERVER, MIN_PROTOCOL_VERSION,
+  PROTOCOL_VERSION, QUICK_CHAT,
+};
+use tetris::server::Match;
+
+// 手元の画面に並べられる人数
+const MIN_PLAYERS: usize = 2;
+const MAX_PLAYERS: usize = 4;
+const TICK: Duration = Duration::from_millis(16);
+// 盤面が変わらなくてもこの間隔で送る。手元はしばらく何も届かないと切れたとみなす
+const KEEPALIVE_SECONDS: f32 = 1.0;
+const RECORD_DIR: &str = "logs/server";
+// つないだまま Hello を送ってこない相手は、この時間で切る
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+// Hello を待っている相手がこれだけいたら、新しくつないできた相手は断る
+const MAX_PENDING: usize = 8;
+
+struct Client {
+  connection: Connection,
+  address: SocketAddr,
+  // Hello を受け取るまでは None
+  agreement: Option<Agreement>,
+  connected_at: Instant,
+  seat: Option<usize>,
+  // 決着のあと、次の対戦を始めてよいと言った
+  ready: bool,
+  // 断ったので、Reject を送ったら切る
+  refused: bool,
+  // 手元から届いた名札。後から入った人にも配る
+  tag: Option<NameTag>,
+}
+
+impl Client {
+  fn has(&self, feature: u32) -> bool {
+    self.agreement.is_some_and(|a| a.has(feature))
+  }
+}
+
+struct Server {
+  players: usize,
+  clients: Vec<Client>,
+  game: Option<Match>,
+  last_sent: Vec<Option<Snapshot>>,
+  since_keepalive: f32,
+}
+
+fn main() {
+  let args: Vec<String> = std::env::args().skip(1).collect();
+  let port = match args.first().map(|s| s.parse::<u16>()) {
+    Some(Ok(port)) => port,
+    Some(Err(_)) => exit_with_usage(),
+    None => DEFAULT_PORT,
+  };
+  let players = match args.get(1).map(|s| s.parse::<usize>()) {
+    Some(Ok(n)) if (MIN_PLAYERS..=MAX_PLAYERS).contains(&n) => n,
+    Some(_) => exit_with_usage(),
+    None => MIN_PLAYERS,
+  };
+  let listener = match listen(port) {
+    Ok(listener) => listener,
+    Err(e) => {
+      eprintln!("cannot listen on port {}: {}", port, e);
+      std::process::exit(1);
+    }
+  };
+  println!("hosting {}-player matches on port {}", players, port);
+
+  let mut server = Server {
+    players,
+    clients: vec![],
+    game: None,
+    last_sent: vec![],
+    since_keepalive: 0.,
+  };
+  let mut last = Instant::now();
+  loop {
+    server.accept(&listener);
+    let now = Instant::now();
+    server.step(now.duration_since(last).as_secs_f32());
+    last = now;
+    thread::sleep(TICK);
+  }
+}
+
+fn exit_with_usage() -> ! {
+  eprintln!(
+    "usage: tetris-server [port] [players {}-{}]",
+    MIN_PLAYERS, MAX_PLAYERS
+  );
+  std::process::exit(2);
+}
+
+fn listen(port: u16) -> io::Result<TcpListener> {
+  let listener = TcpListener::bind(("0.0.0.0", port))?;
+  listener.set_nonblocking(true)?;
+  Ok(listener)
+}
+
+// サーバーが話せる版と機能。操作だけ受け取るので、つなぎ直しには対応しない
+fn server_hello() -> Hello {
+  Hello {
+    version: PROTOCOL_VERSION,
+    min_version: MIN_PROTOCOL_VERSION,
+    features: FEATURE_ACTIVE_PIECE | FEATURE_SERVER | FEATURE_CHAT | FEATURE_PING | FEATURE_PROFILE,
+  }
+}
+
+impl Server {
+  fn accept(&mut self, listener: &TcpListener) {
+    loop {
+      match listener.accept() {
+        Ok((stream, address)) => match Connection::new(stream) {
+          Ok(mut connection) if self.pending() >= MAX_PENDING => {
+            println!("{} refused: too many pending handshakes", address);
+            connection.send(&Message::Reject {
+              reason: "the server is busy; try again later".to_string(),
+            });
+            let _ = connection.pump();
+          }
+          Ok(mut connection) => {
+            println!("{} connected", address);
+            connection.send(&Message::Hello(server_hello()));
+            self.clients.push(Client {
+              connection,
+              address,
+              agreement: None,
+              connected_at: Instant::now(),
+              seat: None,
+              ready: false,
+              refused: false,
+              tag: None,
+            });
+          }
+          Err(e) => eprintln!("{}: {}", address, e),
+        },
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+        Err(e) => {
+          eprintln!("accept failed: {}", e);
+          return;
+        }
+      }
+    }
+  }
+
+  // まだ Hello が届いていない相手の数
+  fn pending(&self) -> usize {
+    self
+      .clients
+      .iter()
+      .filter(|c| c.agreement.is_none() && !c.refused)
+      .count()
+  }
+
+  fn step(&mut self, dt: f32) {
+    let mut i = 0;
+    while i < self.clients.len() {
+      match self.clients[i].connection.pump() {
+        Ok(messages) => {
+          for message in messages {
+            self.receive(i, message);
+          }
+          i += 1;
+        }
+        Err(e) => self.drop_client(i, &e),
+      }
+    }
+    for i in 0..self.clients.len() {
+      let client = &self.clients[i];
+      if client.agreement.is_none()
+        && !client.refused
+        && client.connected_at.elapsed() >= HANDSHAKE_TIMEOUT
+      {
+        self.reject(i, "no handshake in time".to_string());
+      }
+    }
+    self.clients.retain(|c| !c.refused);
+
+    self.start_if_ready();
+    if let Some(game) = self.game.as_mut() {
+      let was_over = game.is_over();
+      game.tick(dt);
+      if game.is_over() && !was_over {
+        finish(game);
+      }
+    }
+    self.since_keepalive += dt;
+    let keepalive = self.since_keepalive >= KEEPALIVE_SECONDS;
+    if keepalive {
+      self.since_keepalive = 0.;
+    }
+    self.broadcast(keepalive);
+  }
+
+  fn receive(&mut self, i: usize, message: Message) {
+    if self.clients[i].refused {
+      return;
+    }
+    if self.clients[i].agreement.is_none() {
+      return self.handshake(i, message);
+    }
+    let seat = match self.clients[i].seat {
+      Some(seat) => seat,
+      None => return,
+    };
+    match message {
+      Message::Input { action } => {
+        if let Some(game) = self.game.as_mut() {
+          if let Err(e) = game.input(seat, action) {
+            eprintln!("{}: ignored {:?}: {}", self.clients[i].address, action, e);
+          }
+        }
+      }
+      Message::Ping { id } => self.clients[i].connection.send(&Message::Pong { id }),
+      // 送り主の席を付けて、ほかの人に配る
+      Message::Chat { phrase, .. } if (phrase as usize) < QUICK_CHAT.len() => {
+        let chat = Message::Chat {
+          seat: seat as u8,
+          phrase,
+        };
+        for (j, other) in self.clients.iter_mut().enumerate() {
+          let chats = other.has(FEATURE_CHAT);
+          if j != i && other.seat.is_some() && chats {
+            other.connection.send(&chat);
+          }
+        }
+      }
+      // 名札も送り主の席を付けて配る
+      Message::Profile { tag, .. } => {
+        let profile = Message::Profile {
+          seat: seat as u8,
+          tag: tag.clone(),
+        };
+        self.clients[i].tag = Some(tag);
+        for (j, other) in self.clients.iter_mut().enumerate() {
+          if j != i && other.seat.is_some() && other.has(FEATURE_PROFILE) {
+            other.connection.send(&profile);
+          }
+        }
+      }
+      // 決着後に Enter を押した
+      Message::Start { .. } => self.clients[i].ready = true,
+      _ => {}
+    }
+  }
+
+  fn handshake(&mut self, i: usize, message: Message) {
+    let remote = match message {
+      Message::Hello(remote) => remote,
+      _ => return self.reject(i, "expected a handshake".to_string()),
+    };
+    let agreement = match negotiate(&server_hello(), &remote) {
+      Ok(agreement) => agreement,
+      Err(reason) => return self.reject(i, reason),
+    };
+    self.clients[i].agreement = Some(agreement);
+    if !agreement.has(FEATURE_SERVER) {
+      return self.reject(
+        i,
+        "this game cannot play on a server; update this game".to_string(),
+      );
+    }
+    // 対戦中に抜けた席は、その対戦が終わるまで空けておく
+    let playing = self.game.as_ref().is_some_and(|g| !g.is_over());
+    let taken: Vec<usize> = self.clients.iter().filter_map(|c| c.seat).collect();
+    let seat = (0..self.players).find(|s| !taken.contains(s));
+    match seat {
+      Some(seat) if !playing => {
+        let client = &mut self.clients[i];
+        client.seat = Some(seat);
+        // 決着の後から入った人は、そのまま次の対戦に加わる
+        client.ready = true;
+        client.connection.send(&Message::Welcome {
+          seat: seat as u8,
+          players: self.players as u8,
+        });
+        println!("{} takes seat {}", client.address, seat + 1);
+        self.send_tags(i);
+      }
+      _ => self.reject(i, "the server is full".to_string()),
+    }
+  }
+
+  // 先に座っている人の名札を、入ってきた人に送る
+  fn send_tags(&mut self, i: usize) {
+    if !self.clients[i].has(FEATURE_PROFILE) {
+      return;
+    }
+    let tags: Vec<Message> = self
+      .clients
+      .iter()
+      .enumerate()
+      .filter(|&(j, _)| j != i)
+      .filter_map(|(_, other)| match (other.seat, other.tag.as_ref()) {
+        (Some(seat), Some(tag)) => Some(Message::Profile {
+          seat: seat as u8,
+          tag: tag.clone(),
+        }),
+        _ => None,
+      })
+      .collect();
+    for tag in tags.iter() {
+      self.clients[i].connection.send(tag);
+    }
+  }
+
+  fn reject(&mut self, i: usize, reason: String) {
+    let client = &mut self.clients[i];
+    println!("{} refused: {}", client.address, reason);
+    client.connection.send(&Message::Reject { reason });
+    let _ = client.connection.pump();
+    client.refused = true;
+  }
+
+  fn drop_client(&mut self, i: usize, reason: &str) {
+    let client = self.clients.remove(i);
+    println!("{} left: {}", client.address, reason);
+    if let (Some(seat), Some(game)) = (client.seat, self.game.as_mut()) {
+      let was_over = game.is_over();
+      game.forfeit(seat);
+      if game.is_over() && !was_over {
+        finish(game);
+      }
+    }
+  }
+
+  // 席が埋まったら始める。2 戦目からは全員が Enter を押してから
+  fn start_if_ready(&mut self) {
+    let seated: Vec<&Client> = self.clients.iter().filter(|c| c.seat.is_some()).collect();
+    if seated.len() < self.players {
+      return;
+    }
+    let ready = match self.game.as_ref() {
+      None => true,
+      Some(game) => game.is_over() && seated.iter().all(|c| c.ready),
+    };
+    if !ready {
+      return;
+    }
+    let seed = rand::random();
+    self.game = Some(Match::new(self.players, seed));
+    self.last_sent = vec![None; self.players];
+    for client in self.clients.iter_mut().filter(|c| c.seat.is_some()) {
+      client.ready = false;
+      client.connection.send(&Message::Start { condition: 0 });
+    }
+    println!("match started (seed {})", seed);
+  }
+
+  // 変わった盤面を全員に送る。待っている間は席の案内を送り直す
+  fn broadcast(&mut self, keepalive: bool) {
+    let mut messages = vec![];
+    match self.game.as_ref() {
+      Some(game) => {
+        for seat in 0..game.players() {
+          let snapshot = game.snapshot(seat);
+          if keepalive || self.last_sent[seat].as_ref() != Some(&snapshot) {
+            self.last_sent[seat] = Some(snapshot.clone());
+            messages.push(Message::Seat {
+              seat: seat as u8,
+              snapshot,
+            });
+          }
+        }
+      }
+      None if keepalive => {
+        for client in self.clients.iter_mut() {
+          if let Some(seat) = client.seat {
+            client.connection.send(&Message::Welcome {
+              seat: seat as u8,
+              players: self.players as u8,
+            });
+          }
+        }
+      }
+      None => {}
+    }
+    for client in self.clients.iter_mut().filter(|c| c.seat.is_some()) {
+      for message in messages.iter() {
+        client.connection.send(message);
+      }
+    }
+  }
+}
+
+// 決着が付いた対戦を記録に残す
+fn finish(game: &Match) {
+  let placings: Vec<String> = game
+    .record()
+    .placings
+    .iter()
+    .map(|seat| format!("P{}", seat + 1))
+    .collect();
+  println!("match over: {}", placings.join(" > "));
+  match write_record(game) {
+    Ok(path) => println!("wrote replay to {}", path.display()),
+    Err(e) => eprintln!("failed to write replay: {}", e),
+  }
+}
+
+fn write_record(game: &Match) -> io::Result<PathBuf> {
+  fs::create_dir_all(RECORD_DIR)?;
+  let secs = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map_or(0, |d| d.as_secs());
+  let path = PathBuf::from(RECORD_DIR).join(format!("match-{}.json", secs));
+  let json = serde_json::to_string(game.record()).map_err(io::Error::other)?;
+  fs::write(&path, json)?;
+  Ok(path)
+}
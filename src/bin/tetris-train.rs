@@ -0,0 +1,61 @@
+// ボットの評価の重みを自己対戦で調べる。tetris-train [rounds] [candidates] [games]
+// 結果はゲームのボットが読み込む save/bot_weights.ron に書き出す
+use std::path::Path;
+use std::time::Instant;
+
+use tetris::ai::{Weights, TRAINED_WEIGHTS};
+use tetris::train::{train, TrainConfig};
+
+fn main() {
+  let args: Vec<String> = std::env::args().skip(1).collect();
+  let mut config = TrainConfig {
+    seed: rand::random(),
+    ..TrainConfig::default()
+  };
+  let counts = [
+    &mut config.rounds,
+    &mut config.candidates,
+    &mut config.games,
+  ];
+  for (arg, count) in args.iter().zip(counts) {
+    match arg.parse::<u32>() {
+      Ok(n) if n > 0 => *count = n,
+      _ => exit_with_usage(),
+    }
+  }
+  if args.len() > 3 {
+    exit_with_usage();
+  }
+
+  // 前に調べた重みがあればその続きから
+  let path = Path::new(TRAINED_WEIGHTS);
+  let start = Weights::load(path).unwrap_or_default();
+  println!(
+    "{} rounds of {} candidates x {} games, starting from {:?}",
+    config.rounds, config.candidates, config.games, start
+  );
+  let started = Instant::now();
+  let best = train(start, &config, |round| {
+    let mark = if round.improved { "new best" } else { "kept" };
+    println!(
+      "round {}: {} (win rate {:.0}%)  {:?}",
+      round.round + 1,
+      mark,
+      round.win_rate * 100.,
+      round.best
+    );
+  });
+  println!("done in {:.0} s", started.elapsed().as_secs_f32());
+  match best.store(path) {
+    Ok(()) => println!("wrote {}", path.display()),
+    Err(e) => {
+      eprintln!("failed to write {}: {}", path.display(), e);
+      std::process::exit(1);
+    }
+  }
+}
+
+fn exit_with_usage() -> ! {
+  eprintln!("usage: tetris-train [rounds] [candidates] [games]");
+  std::process::exit(2);
+}
@@ -0,0 +1,229 @@
+use crate::{Position, ARENA_WIDTH};
+
+// 盤面を行ごとのビットマスクで持つ。AI や補助機能から Query を介さずに使う
+#[derive(Clone, Default, PartialEq, Eq, Hash, Debug)]
+pub struct Board {
+  rows: Vec<u16>,
+}
+
+impl Board {
+  pub fn from_cells<'a>(cells: impl IntoIterator<Item = &'a Position>) -> Self {
+    let mut board = Board::default();
+    for cell in cells {
+      board.fill(cell);
+    }
+    board
+  }
+
+  // 下の行から順のビットマスク。通信で盤面を送るのに使う
+  pub fn from_rows(rows: Vec<u16>) -> Self {
+    let full = (1u16 << ARENA_WIDTH) - 1;
+    Board {
+      rows: rows.into_iter().map(|row| row & full).collect(),
+    }
+  }
+
+  pub fn rows(&self) -> &[u16] {
+    &self.rows
+  }
+
+  // 壁と床は埋まっているものとして扱う
+  pub fn is_filled(&self, pos: &Position) -> bool {
+    if pos.x < 0 || pos.x >= ARENA_WIDTH as i32 || pos.y < 0 {
+      return true;
+    }
+    self
+      .rows
+      .get(pos.y as usize)
+      .is_some_and(|row| row & (1 << pos.x) != 0)
+  }
+
+  // T ミノの中心の斜め 4 マスのうち 3 マス以上が壁か床かブロック。
+  // 最後の操作が回転だったかは呼ぶ側で確かめる
+  pub fn is_t_spin(&self, cells: &[Position]) -> bool {
+    // 中心は他の 3 マスすべてと隣り合うマス
+    let center = cells.iter().find(|c| {
+      cells
+        .iter()
+        .filter(|p| (p.x - c.x).abs() + (p.y - c.y).abs() == 1)
+        .count()
+        == 3
+    });
+    let center = match center {
+      Some(center) => center,
+      None => return false,
+    };
+    [(-1, -1), (1, -1), (-1, 1), (1, 1)]
+      .iter()
+      .filter(|(dx, dy)| {
+        self.is_filled(&Position {
+          x: center.x + dx,
+          y: center.y + dy,
+        })
+      })
+      .count()
+      >= 3
+  }
+
+  pub fn fill(&mut self, pos: &Position) {
+    if pos.x < 0 || pos.x >= ARENA_WIDTH as i32 || pos.y < 0 {
+      return;
+    }
+    let y = pos.y as usize;
+    if self.rows.len() <= y {
+      self.rows.resize(y + 1, 0);
+    }
+    self.rows[y] |= 1 << pos.x;
+  }
+
+  pub fn fits(&self, cells: &[Position]) -> bool {
+    cells.iter().all(|cell| !self.is_filled(cell))
+  }
+
+  // 1マスずつ横に動かす。途中でぶつかる場合は None
+  pub fn shift(&self, cells: &[Position], dx: i32) -> Option<Vec<Position>> {
+    let step = dx.signum();
+    let mut current = cells.to_vec();
+    for _ in 0..dx.abs() {
+      let next: Vec<Position> = current
+        .iter()
+        .map(|p| Position {
+          x: p.x + step,
+          y: p.y,
+        })
+        .collect();
+      if !self.fits(&next) {
+        return None;
+      }
+      current = next;
+    }
+    Some(current)
+  }
+
+  // そのまま真下に落として接地した位置
+  pub fn drop(&self, cells: &[Position]) -> Vec<Position> {
+    let mut current = cells.to_vec();
+    loop {
+      let next: Vec<Position> = current
+        .iter()
+        .map(|p| Position { x: p.x, y: p.y - 1 })
+        .collect();
+      if !self.fits(&next) {
+        return current;
+      }
+      current = next;
+    }
+  }
+
+  // 今の高さから横移動して真下に落とせる置き場所をすべて列挙する (左から順)
+  pub fn placements(&self, cells: &[Position]) -> Vec<Vec<Position>> {
+    let mut res = vec![];
+    for dx in -(ARENA_WIDTH as i32)..=(ARENA_WIDTH as i32) {
+      let shifted = match self.shift(cells, dx) {
+        Some(shifted) => shifted,
+        None => continue,
+      };
+      res.push(self.drop(&shifted));
+    }
+    res
+  }
+
+  pub fn place(&mut self, cells: &[Position]) {
+    for cell in cells {
+      self.fill(cell);
+    }
+  }
+
+  // 揃った行を消して、消した行数を返す
+  pub fn clear_full_rows(&mut self) -> u32 {
+    let full = (1u16 << ARENA_WIDTH) - 1;
+    let before = self.rows.len();
+    self.rows.retain(|&row| row != full);
+    (before - self.rows.len()) as u32
+  }
+
+  // 下からせり上がりの行を入れる。holes は下の行から順に各行の穴の列
+  pub fn add_garbage(&mut self, holes: &[i32]) {
+    let full = (1u16 << ARENA_WIDTH) - 1;
+    for &hole in holes.iter().rev() {
+      self.rows.insert(0, full & !(1 << hole));
+    }
+  }
+
+  // 各列の一番上のマスの高さ (空の列は 0)
+  pub fn column_heights(&self) -> [i32; ARENA_WIDTH as usize] {
+    let mut heights = [0; ARENA_WIDTH as usize];
+    for (y, row) in self.rows.iter().enumerate() {
+      for (x, height) in heights.iter_mut().enumerate() {
+        if row & (1 << x) != 0 {
+          *height = y as i32 + 1;
+        }
+      }
+    }
+    heights
+  }
+
+  // 列ごとの、上を塞がれた空きマスの数
+  pub fn column_holes(&self) -> [u32; ARENA_WIDTH as usize] {
+    let heights = self.column_heights();
+    let mut holes = [0; ARENA_WIDTH as usize];
+    for (x, &height) in heights.iter().enumerate() {
+      for y in 0..height {
+        if !self.is_filled(&Position { x: x as i32, y }) {
+          holes[x] += 1;
+        }
+      }
+    }
+    holes
+  }
+
+  // 上を塞がれた空きマスの数
+  pub fn holes(&self) -> u32 {
+    self.column_holes().iter().sum()
+  }
+
+  // 隣り合う列の高さの差の合計
+  pub fn bumpiness(&self) -> i32 {
+    self
+      .column_heights()
+      .windows(2)
+      .map(|w| (w[0] - w[1]).abs())
+      .sum()
+  }
+
+  pub fn max_height(&self) -> i32 {
+    self.column_heights().iter().copied().max().unwrap_or(0)
+  }
+
+  // 実行や環境によらない 64 ビットのハッシュ (FNV-1a)。上の空行は無視する
+  pub fn stable_hash(&self) -> u64 {
+    let len = self
+      .rows
+      .iter()
+      .rposition(|&row| row != 0)
+      .map_or(0, |y| y + 1);
+    fnv1a(self.rows[..len].iter().flat_map(|row| row.to_le_bytes()))
+  }
+
+  // 埋まっているマスの一覧
+  pub fn cells(&self) -> Vec<Position> {
+    let mut cells = vec![];
+    for (y, row) in self.rows.iter().enumerate() {
+      for x in 0..ARENA_WIDTH as i32 {
+        if row & (1 << x) != 0 {
+          cells.push(Position { x, y: y as i32 });
+        }
+      }
+    }
+    cells
+  }
+}
+
+pub fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u64 {
+  let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+  for byte in bytes {
+    hash ^= byte as u64;
+    hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+  }
+  hash
+}
@@ -0,0 +1,195 @@
+use bevy::prelude::*;
+
+use crate::battle::{Battle, BoardSlot};
+use crate::sim::GameState;
+use crate::{block_name, AppState, Fonts, ARENA_HEIGHT, ARENA_WIDTH};
+
+// 大会の配信向けの観戦画面。2 つの盤面を大きく並べ、それぞれの外側に名前と次のミノ、
+// ホールド、来ているせり上がり、攻撃のグラフを出す
+
+// 盤面の外側の欄の幅と、盤面どうしの間 (マス)
+const PANEL_CELLS: f32 = 7.0;
+const GAP_CELLS: f32 = 2.0;
+// 攻撃のグラフの 1 本あたりの秒数と、並べる本数
+const GRAPH_SECONDS: f32 = 5.0;
+const GRAPH_BARS: usize = 12;
+const GRAPH_LEVELS: [char; 8] = [
+  '\u{2581}', '\u{2582}', '\u{2583}', '\u{2584}', '\u{2585}', '\u{2586}', '\u{2587}', '\u{2588}',
+];
+// せり上がりの目盛りの最大
+const METER_MAX: u32 = 12;
+const NEXT_PIECES: usize = 5;
+
+// 2 人の盤面を、外側に欄を空けて真ん中に寄せる
+pub fn caster_slots(main: Vec2) -> Vec<BoardSlot> {
+  let columns = 2. * (ARENA_WIDTH as f32 + PANEL_CELLS) + GAP_CELLS;
+  let cell = (main.x / columns).min(main.y / (ARENA_HEIGHT as f32 + GAP_CELLS));
+  let offset = (ARENA_WIDTH as f32 + GAP_CELLS) / 2. * cell;
+  vec![
+    BoardSlot {
+      center: Vec2::new(-offset, 0.),
+      cell,
+    },
+    BoardSlot {
+      center: Vec2::new(offset, 0.),
+      cell,
+    },
+  ]
+}
+
+// 実況の欄に出す 1 人分
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct CasterSeat {
+  pub name: String,
+  pub hold: Option<u32>,
+  pub queue: Vec<u32>,
+  pub incoming: u32,
+  pub lines: u32,
+  pub sent: u32,
+  // GRAPH_SECONDS ごとに送った段数
+  pub attack: Vec<u32>,
+}
+
+impl CasterSeat {
+  // elapsed 秒の盤面と、それまでに送った合計を写す
+  pub fn update(&mut self, name: &str, state: &GameState, incoming: u32, sent: u32, elapsed: f32) {
+    if self.name != name {
+      self.name = name.to_string();
+    }
+    self.hold = state.hold;
+    self.queue = state.queue.iter().take(NEXT_PIECES).copied().collect();
+    self.incoming = incoming;
+    self.lines = state.lines;
+    let bucket = (elapsed / GRAPH_SECONDS) as usize;
+    if self.attack.len() <= bucket {
+      self.attack.resize(bucket + 1, 0);
+    }
+    self.attack[bucket] += sent.saturating_sub(self.sent);
+    self.sent = sent;
+  }
+}
+
+// 眺めている対戦の、席の順の実況の欄
+#[derive(Default)]
+pub struct CasterView {
+  pub seats: Vec<CasterSeat>,
+}
+
+impl CasterView {
+  // 新しい対戦を始めたら前のグラフを捨てる
+  pub fn reset(&mut self) {
+    self.seats.clear();
+  }
+
+  pub fn seat_mut(&mut self, seat: usize) -> &mut CasterSeat {
+    if self.seats.len() <= seat {
+      self.seats.resize(seat + 1, CasterSeat::default());
+    }
+    &mut self.seats[seat]
+  }
+}
+
+pub fn garbage_meter(incoming: u32) -> String {
+  let mut meter = "#".repeat(incoming.min(METER_MAX) as usize);
+  if incoming > METER_MAX {
+    meter.push('+');
+  }
+  meter
+}
+
+// 直近の GRAPH_BARS 本を、いちばん多い本を最大にして並べる
+pub fn attack_graph(attack: &[u32]) -> String {
+  let recent = &attack[attack.len().saturating_sub(GRAPH_BARS)..];
+  let most = recent.iter().copied().max().unwrap_or(0).max(1);
+  let top = GRAPH_LEVELS.len() as u32 - 1;
+  recent
+    .iter()
+    .map(|&lines| GRAPH_LEVELS[(lines * top).div_ceil(most) as usize])
+    .collect()
+}
+
+pub fn caster_panel(seat: &CasterSeat) -> String {
+  let next: Vec<String> = seat
+    .queue
+    .iter()
+    .map(|&idx| block_name(idx).to_string())
+    .collect();
+  format!(
+    "{}\nlines {}  sent {}\n\nHOLD  {}\n\nNEXT\n{}\n\nGARBAGE {}\n{}\n\nATTACK\n{}",
+    seat.name,
+    seat.lines,
+    seat.sent,
+    seat.hold.map_or('-', block_name),
+    next.join("\n"),
+    seat.incoming,
+    garbage_meter(seat.incoming),
+    attack_graph(&seat.attack),
+  )
+}
+
+struct CasterPanel(usize);
+
+pub struct CasterPlugin;
+
+impl Plugin for CasterPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(CasterView::default())
+      .add_system_set(SystemSet::on_enter(AppState::Battle).with_system(setup_caster.system()))
+      .add_system_set(SystemSet::on_update(AppState::Battle).with_system(caster_ui.system()));
+  }
+}
+
+fn setup_caster(mut commands: Commands, fonts: Res<Fonts>) {
+  for seat in 0..2 {
+    commands
+      .spawn_bundle(Text2dBundle {
+        text: Text::with_section(
+          "",
+          TextStyle {
+            font: fonts.main.clone(),
+            font_size: 16.0,
+            color: Color::WHITE,
+          },
+          TextAlignment {
+            vertical: VerticalAlign::Center,
+            horizontal: HorizontalAlign::Center,
+          },
+        ),
+        ..Default::default()
+      })
+      .insert(CasterPanel(seat));
+  }
+}
+
+// 欄は盤面の外側に置き、文字の大きさはマスに合わせる
+fn caster_ui(
+  windows: Res<Windows>,
+  battle: Res<Battle>,
+  caster: Res<CasterView>,
+  mut query: Query<(&CasterPanel, &mut Text, &mut Transform)>,
+) {
+  let window = match windows.get_primary() {
+    Some(window) => window,
+    None => return,
+  };
+  let slots = caster_slots(Vec2::new(window.width(), window.height()));
+  for (panel, mut text, mut transform) in query.iter_mut() {
+    let seat = caster
+      .seats
+      .get(panel.0)
+      .filter(|_| battle.is_caster() && !battle.is_setup());
+    let value = seat.map_or(String::new(), caster_panel);
+    if text.sections[0].value != value {
+      text.sections[0].value = value;
+    }
+    let slot = slots[panel.0];
+    let side = if panel.0 == 0 { -1. } else { 1. };
+    let x = slot.center.x + side * (ARENA_WIDTH as f32 + PANEL_CELLS) / 2. * slot.cell;
+    transform.translation = Vec3::new(x, slot.center.y, 2.);
+    let font_size = (slot.cell * 0.7).clamp(12., 28.);
+    if text.sections[0].style.font_size != font_size {
+      text.sections[0].style.font_size = font_size;
+    }
+  }
+}
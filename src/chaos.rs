@@ -0,0 +1,171 @@
+use bevy::prelude::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::rules::{Modifier, Modifiers, Ruleset};
+use crate::{overlay_text, AppState, Fonts, GameClock, GameReset, StackedBlock};
+
+// 30 秒ごとに修飾を取り替え、替わったときは大きく名前を出す
+pub const CHAOS_INTERVAL: f64 = 30.;
+const BANNER_SECONDS: f64 = 2.5;
+
+// カオスモードで次にかける修飾を選ぶ
+pub struct Chaos {
+  rng: StdRng,
+  next_at: f64,
+  banner_until: f64,
+  pub current: Option<Modifier>,
+  // ここまでにかけた修飾の数
+  pub count: u32,
+}
+
+impl Chaos {
+  // 始めてすぐに 1 つめをかける
+  pub fn new(seed: u64, now: f64) -> Self {
+    Chaos {
+      rng: StdRng::seed_from_u64(seed),
+      next_at: now,
+      banner_until: 0.,
+      current: None,
+      count: 0,
+    }
+  }
+
+  // 時間が来ていたら次の修飾を返す。同じものは続けて選ばない
+  pub fn update(&mut self, now: f64) -> Option<Modifier> {
+    if now < self.next_at {
+      return None;
+    }
+    let choices: Vec<Modifier> = Modifier::ALL
+      .iter()
+      .copied()
+      .filter(|&m| Some(m) != self.current)
+      .collect();
+    let next = choices[self.rng.gen_range(0..choices.len())];
+    self.current = Some(next);
+    self.count += 1;
+    self.next_at = now + CHAOS_INTERVAL;
+    self.banner_until = now + BANNER_SECONDS;
+    Some(next)
+  }
+
+  pub fn time_left(&self, now: f64) -> f64 {
+    (self.next_at - now).max(0.)
+  }
+
+  pub fn banner_visible(&self, now: f64) -> bool {
+    now < self.banner_until
+  }
+}
+
+struct ChaosText;
+struct ChaosBanner;
+
+pub struct ChaosPlugin;
+
+impl Plugin for ChaosPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(Chaos::new(0, 0.))
+      .add_startup_system_to_stage(StartupStage::PostStartup, setup_chaos.system())
+      .add_system_set(SystemSet::on_enter(AppState::Playing).with_system(chaos_start.system()))
+      .add_system_set(SystemSet::on_update(AppState::Playing).with_system(chaos_switch.system()))
+      .add_system(chaos_reset.system())
+      .add_system(stack_visibility.system())
+      .add_system(chaos_ui.system());
+  }
+}
+
+fn setup_chaos(mut commands: Commands, fonts: Res<Fonts>) {
+  commands
+    .spawn_bundle(overlay_text(&fonts))
+    .insert(ChaosText);
+  let mut banner = overlay_text(&fonts);
+  banner.style.position = Rect {
+    top: Val::Px(160.0),
+    left: Val::Px(40.0),
+    ..Default::default()
+  };
+  banner.text.sections[0].style.font_size = 40.0;
+  banner.text.sections[0].style.color = Color::rgb(1.0, 0.8, 0.2);
+  commands.spawn_bundle(banner).insert(ChaosBanner);
+}
+
+fn chaos_start(
+  clock: Res<GameClock>,
+  mut chaos: ResMut<Chaos>,
+  mut modifiers: ResMut<Modifiers>,
+  mut ruleset: ResMut<Ruleset>,
+) {
+  *chaos = Chaos::new(rand::random(), clock.0);
+  modifiers.switch(&mut ruleset, None);
+}
+
+fn chaos_reset(
+  clock: Res<GameClock>,
+  mut chaos: ResMut<Chaos>,
+  mut modifiers: ResMut<Modifiers>,
+  mut ruleset: ResMut<Ruleset>,
+  mut reset_events: EventReader<GameReset>,
+) {
+  if reset_events.iter().count() > 0 {
+    *chaos = Chaos::new(rand::random(), clock.0);
+    modifiers.switch(&mut ruleset, None);
+  }
+}
+
+fn chaos_switch(
+  clock: Res<GameClock>,
+  mut chaos: ResMut<Chaos>,
+  mut modifiers: ResMut<Modifiers>,
+  mut ruleset: ResMut<Ruleset>,
+) {
+  if let Some(next) = chaos.update(clock.0) {
+    modifiers.switch(&mut ruleset, Some(next));
+  }
+}
+
+fn stack_visibility(modifiers: Res<Modifiers>, mut query: Query<&mut Visible, With<StackedBlock>>) {
+  let visible = !modifiers.is_on(Modifier::InvisibleStack);
+  for mut v in query.iter_mut() {
+    if v.is_visible != visible {
+      v.is_visible = visible;
+    }
+  }
+}
+
+fn chaos_ui(
+  clock: Res<GameClock>,
+  state: Res<State<AppState>>,
+  chaos: Res<Chaos>,
+  mut text_query: Query<&mut Text, (With<ChaosText>, Without<ChaosBanner>)>,
+  mut banner_query: Query<&mut Text, With<ChaosBanner>>,
+) {
+  let now = clock.0;
+  let playing = matches!(state.current(), AppState::Playing | AppState::Paused);
+  let (value, banner) = match chaos.current {
+    Some(modifier) if playing => (
+      format!(
+        "CHAOS  {}\nnext in {:.0}s",
+        modifier.name(),
+        chaos.time_left(now)
+      ),
+      if chaos.banner_visible(now) {
+        modifier.name().to_string()
+      } else {
+        String::new()
+      },
+    ),
+    _ => (String::new(), String::new()),
+  };
+  for mut text in text_query.iter_mut() {
+    if text.sections[0].value != value {
+      text.sections[0].value = value.clone();
+    }
+  }
+  for mut text in banner_query.iter_mut() {
+    if text.sections[0].value != banner {
+      text.sections[0].value = banner.clone();
+    }
+  }
+}
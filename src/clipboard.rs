@@ -0,0 +1,73 @@
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+// クリップボード用の crate は入れず、OS に付いているコマンドに渡す。上から順に試す
+#[cfg(target_os = "macos")]
+const COPY_COMMANDS: &[&[&str]] = &[&["pbcopy"]];
+#[cfg(windows)]
+const COPY_COMMANDS: &[&[&str]] = &[&["clip"]];
+#[cfg(all(unix, not(target_os = "macos")))]
+const COPY_COMMANDS: &[&[&str]] = &[
+  &["wl-copy"],
+  &["xclip", "-selection", "clipboard"],
+  &["xsel", "--clipboard", "--input"],
+];
+#[cfg(target_os = "macos")]
+const PASTE_COMMANDS: &[&[&str]] = &[&["pbpaste"]];
+#[cfg(windows)]
+const PASTE_COMMANDS: &[&[&str]] = &[&["powershell", "-NoProfile", "-Command", "Get-Clipboard"]];
+#[cfg(all(unix, not(target_os = "macos")))]
+const PASTE_COMMANDS: &[&[&str]] = &[
+  &["wl-paste", "--no-newline"],
+  &["xclip", "-selection", "clipboard", "-o"],
+  &["xsel", "--clipboard", "--output"],
+];
+
+fn run_with_input(args: &[&str], text: &str) -> io::Result<()> {
+  let mut child = Command::new(args[0])
+    .args(&args[1..])
+    .stdin(Stdio::piped())
+    .stdout(Stdio::null())
+    .stderr(Stdio::null())
+    .spawn()?;
+  // 閉じないと相手が入力の終わりを待ち続ける
+  if let Some(mut stdin) = child.stdin.take() {
+    stdin.write_all(text.as_bytes())?;
+  }
+  let status = child.wait()?;
+  if status.success() {
+    Ok(())
+  } else {
+    Err(io::Error::other(format!("{} failed", args[0])))
+  }
+}
+
+pub fn copy(text: &str) -> io::Result<()> {
+  let mut last = io::Error::new(io::ErrorKind::NotFound, "no clipboard command");
+  for args in COPY_COMMANDS.iter() {
+    match run_with_input(args, text) {
+      Ok(()) => return Ok(()),
+      Err(e) => last = e,
+    }
+  }
+  Err(last)
+}
+
+pub fn paste() -> io::Result<String> {
+  let mut last = io::Error::new(io::ErrorKind::NotFound, "no clipboard command");
+  for args in PASTE_COMMANDS.iter() {
+    let output = Command::new(args[0])
+      .args(&args[1..])
+      .stdin(Stdio::null())
+      .stderr(Stdio::null())
+      .output();
+    match output {
+      Ok(output) if output.status.success() => {
+        return Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+      }
+      Ok(_) => last = io::Error::other(format!("{} failed", args[0])),
+      Err(e) => last = e,
+    }
+  }
+  Err(last)
+}
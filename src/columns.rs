@@ -0,0 +1,117 @@
+use bevy::prelude::*;
+
+use crate::board::Board;
+use crate::layout::Layout;
+use crate::{Fonts, Position, StackedBlock, ARENA_WIDTH};
+
+// F8 で、盤面の下に列ごとの高さと穴の数を出す。練習用と、盤面の数え方の確かめ用
+const TOGGLE_KEY: KeyCode = KeyCode::F8;
+const FONT_SIZE: f32 = 14.0;
+
+// 列ごとに「高さ」と「穴」の 2 行。穴のない列は空けておく
+pub fn column_labels(board: &Board) -> Vec<String> {
+  let holes = board.column_holes();
+  board
+    .column_heights()
+    .iter()
+    .zip(holes.iter())
+    .map(|(height, &holes)| {
+      if holes > 0 {
+        format!("{}\n{}", height, holes)
+      } else {
+        format!("{}\n ", height)
+      }
+    })
+    .collect()
+}
+
+#[derive(Default)]
+struct ColumnsOverlay {
+  visible: bool,
+  labels: Vec<String>,
+}
+
+struct ColumnLabel(i32);
+
+pub struct ColumnsPlugin;
+
+impl Plugin for ColumnsPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(ColumnsOverlay::default())
+      .add_startup_system_to_stage(StartupStage::PostStartup, setup_columns.system())
+      .add_system(columns_toggle.system())
+      .add_system(columns_update.system())
+      .add_system(columns_ui.system());
+  }
+}
+
+fn setup_columns(mut commands: Commands, fonts: Res<Fonts>) {
+  for x in 0..ARENA_WIDTH as i32 {
+    commands
+      .spawn_bundle(Text2dBundle {
+        text: Text::with_section(
+          "",
+          TextStyle {
+            font: fonts.main.clone(),
+            font_size: FONT_SIZE,
+            color: Color::WHITE,
+          },
+          TextAlignment {
+            vertical: VerticalAlign::Top,
+            horizontal: HorizontalAlign::Center,
+          },
+        ),
+        ..Default::default()
+      })
+      .insert(ColumnLabel(x));
+  }
+}
+
+fn columns_toggle(keyboard_input: Res<Input<KeyCode>>, mut overlay: ResMut<ColumnsOverlay>) {
+  if keyboard_input.just_pressed(TOGGLE_KEY) {
+    overlay.visible = !overlay.visible;
+  }
+}
+
+// ブロックが積まれた・動いた・消えたときだけ数え直す
+fn columns_update(
+  mut overlay: ResMut<ColumnsOverlay>,
+  changed_query: Query<Entity, (With<StackedBlock>, Changed<Position>)>,
+  removed: RemovedComponents<StackedBlock>,
+  stacked_block_query: Query<&Position, With<StackedBlock>>,
+) {
+  if !overlay.labels.is_empty()
+    && changed_query.iter().next().is_none()
+    && removed.iter().next().is_none()
+  {
+    return;
+  }
+  let labels = column_labels(&Board::from_cells(stacked_block_query.iter()));
+  if overlay.labels != labels {
+    overlay.labels = labels;
+  }
+}
+
+// 盤面の下に余白がなければ、ウィンドウの下端に寄せて最下段に重ねる
+fn columns_ui(
+  overlay: Res<ColumnsOverlay>,
+  layout: Res<Layout>,
+  mut query: Query<(&ColumnLabel, &mut Text, &mut Transform)>,
+) {
+  if !overlay.is_changed() && !layout.is_changed() {
+    return;
+  }
+  let top = (layout.board_bottom - layout.height / 2.).max(FONT_SIZE * 2. - layout.height / 2.);
+  for (label, mut text, mut transform) in query.iter_mut() {
+    let value = match overlay.labels.get(label.0 as usize) {
+      Some(value) if overlay.visible => value.clone(),
+      _ => String::new(),
+    };
+    if text.sections[0].value != value {
+      text.sections[0].value = value;
+    }
+    let x = layout.world_position(&Position { x: label.0, y: 0 }).x;
+    transform.translation = Vec3::new(x, top, 1.);
+  }
+}
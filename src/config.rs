@@ -0,0 +1,279 @@
+use bevy::prelude::*;
+use bevy::window::{WindowCloseRequested, WindowMoved, WindowResized};
+use bevy::winit::WinitWindows;
+use serde::{Deserialize, Serialize};
+
+use crate::audio::AudioSettings;
+use crate::save;
+use crate::speed::{SpeedTable, TickRate};
+
+const CONFIG_FILE: &str = "config.ron";
+// ドラッグ中に毎フレーム書き込まないよう、最後の変更から少し待って保存する
+const SAVE_DELAY: f32 = 1.0;
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct WindowGeometry {
+  pub width: f32,
+  pub height: f32,
+  // 仮想デスクトップ上の左上の位置。None なら OS に任せる
+  pub position: Option<(i32, i32)>,
+  // 最後にウィンドウがあったモニタの名前
+  pub monitor: Option<String>,
+}
+
+impl Default for WindowGeometry {
+  fn default() -> Self {
+    Self {
+      width: 400.,
+      height: 800.,
+      position: None,
+      monitor: None,
+    }
+  }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PauseSettings {
+  // ウィンドウが裏に回ったら一時停止する
+  pub on_focus_loss: bool,
+  // この秒数だけ入力が無ければ一時停止する。0 なら無効
+  pub idle_seconds: f64,
+}
+
+impl Default for PauseSettings {
+  fn default() -> Self {
+    Self {
+      on_focus_loss: true,
+      idle_seconds: 60.,
+    }
+  }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AssistSettings {
+  // 重力と接地の猶予が進む速さの倍率。1 より小さいとゆっくりになる
+  pub slow_motion: f32,
+  // 穴を作らずに置けないミノは、ホールドの方が置けるなら自動でホールドする
+  pub auto_hold: bool,
+}
+
+impl Default for AssistSettings {
+  fn default() -> Self {
+    Self {
+      slow_motion: 1.,
+      auto_hold: false,
+    }
+  }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RestartSettings {
+  // R をこの秒数だけ押し続けたらやり直す。うっかり押したくらいでは消えないように。0 なら無効
+  pub hold_seconds: f32,
+}
+
+impl Default for RestartSettings {
+  fn default() -> Self {
+    Self { hold_seconds: 0.3 }
+  }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RumbleSettings {
+  // コントローラーの振動の強さ。0 なら振動しない
+  pub intensity: f32,
+}
+
+impl Default for RumbleSettings {
+  fn default() -> Self {
+    Self { intensity: 0.7 }
+  }
+}
+
+// 盤面の拡大率と、ウィンドウの中で盤面をずらす量 (ピクセル)。配信で横に何かを重ねる余白を作る用
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ViewSettings {
+  pub zoom: f32,
+  pub offset: (f32, f32),
+}
+
+impl Default for ViewSettings {
+  fn default() -> Self {
+    Self {
+      zoom: 1.,
+      offset: (0., 0.),
+    }
+  }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Config {
+  #[serde(default)]
+  pub window: WindowGeometry,
+  #[serde(default)]
+  pub audio: AudioSettings,
+  #[serde(default)]
+  pub pause: PauseSettings,
+  // assets/kicks/<名前>.ron のキックテーブルで SRS を上書きする
+  #[serde(default)]
+  pub kick_table: Option<String>,
+  // 通信対戦で相手のチャットとエモートを出さない
+  #[serde(default)]
+  pub mute_chat: bool,
+  // 点滅や揺れ、急な色の変化を止めて、ゆっくりした変化に置き換える。演出はどれもこれを見る
+  #[serde(default)]
+  pub reduce_motion: bool,
+  // 今のミノが掛かっている列を、盤面の上から下まで薄く塗る
+  #[serde(default)]
+  pub column_guides: bool,
+  #[serde(default)]
+  pub assist: AssistSettings,
+  // 重力の速さの表と、Custom のときに使うレベルごとの 1 段落ちるまでの秒数
+  #[serde(default)]
+  pub speed_table: SpeedTable,
+  #[serde(default)]
+  pub custom_gravity: Vec<f32>,
+  #[serde(default)]
+  pub view: ViewSettings,
+  #[serde(default)]
+  pub restart: RestartSettings,
+  #[serde(default)]
+  pub rumble: RumbleSettings,
+  // 入力と重力を進める刻み。リフレッシュレートの高い画面で操作の遅れを減らす
+  #[serde(default)]
+  pub tick_rate: TickRate,
+}
+
+impl Config {
+  pub fn load() -> Self {
+    save::load_shared(CONFIG_FILE)
+  }
+
+  pub fn store(&self) {
+    if let Err(e) = save::store_shared(CONFIG_FILE, self) {
+      error!("failed to save config: {}", e);
+    }
+  }
+}
+
+struct ConfigSaveTimer(Option<Timer>);
+
+pub struct ConfigPlugin;
+
+impl Plugin for ConfigPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(ConfigSaveTimer(None))
+      .add_system(restore_window_position.system())
+      .add_system(track_window_geometry.system())
+      .add_system(save_config.system());
+  }
+}
+
+// 矩形 (x, y, w, h) の中に点が入っているか
+fn contains(rect: (i32, i32, u32, u32), point: (i32, i32)) -> bool {
+  let (x, y, w, h) = rect;
+  point.0 >= x && point.0 < x + w as i32 && point.1 >= y && point.1 < y + h as i32
+}
+
+// 前回のウィンドウ位置に戻す。モニタ構成が変わっていたら画面内に収める
+fn restore_window_position(
+  mut done: Local<bool>,
+  config: Res<Config>,
+  winit_windows: Res<WinitWindows>,
+  mut windows: ResMut<Windows>,
+) {
+  if *done {
+    return;
+  }
+  let window = match windows.get_primary_mut() {
+    Some(window) => window,
+    None => return,
+  };
+  let winit_window = match winit_windows.get_window(window.id()) {
+    Some(winit_window) => winit_window,
+    None => return,
+  };
+  *done = true;
+
+  let position = match config.window.position {
+    Some(position) => position,
+    None => return,
+  };
+  let monitors: Vec<_> = winit_window
+    .available_monitors()
+    .map(|monitor| {
+      let p = monitor.position();
+      let s = monitor.size();
+      (monitor.name(), (p.x, p.y, s.width, s.height))
+    })
+    .collect();
+  // 保存した位置がどのモニタにも無ければ、同じ名前のモニタの左上に出す
+  let target = monitors
+    .iter()
+    .find(|&&(_, rect)| contains(rect, position))
+    .map(|_| position)
+    .or_else(|| {
+      monitors
+        .iter()
+        .find(|(name, _)| name.is_some() && *name == config.window.monitor)
+        .map(|&(_, (x, y, _, _))| (x, y))
+    });
+  match target {
+    Some((x, y)) => window.set_position(IVec2::new(x, y)),
+    None => info!(
+      "saved window position {:?} is off screen, ignoring",
+      position
+    ),
+  }
+}
+
+fn track_window_geometry(
+  mut config: ResMut<Config>,
+  mut timer: ResMut<ConfigSaveTimer>,
+  winit_windows: Res<WinitWindows>,
+  windows: Res<Windows>,
+  mut resized_events: EventReader<WindowResized>,
+  mut moved_events: EventReader<WindowMoved>,
+) {
+  let mut geometry = config.window.clone();
+  for event in resized_events.iter().filter(|e| e.id.is_primary()) {
+    geometry.width = event.width;
+    geometry.height = event.height;
+  }
+  for event in moved_events.iter().filter(|e| e.id.is_primary()) {
+    geometry.position = Some((event.position.x, event.position.y));
+    // 別のモニタへ移したときのために毎回取り直す
+    geometry.monitor = windows
+      .get_primary()
+      .and_then(|window| winit_windows.get_window(window.id()))
+      .and_then(|winit_window| winit_window.current_monitor())
+      .and_then(|monitor| monitor.name());
+  }
+  if geometry != config.window {
+    config.window = geometry;
+    timer.0 = Some(Timer::from_seconds(SAVE_DELAY, false));
+  }
+}
+
+fn save_config(
+  time: Res<Time>,
+  config: Res<Config>,
+  mut timer: ResMut<ConfigSaveTimer>,
+  mut close_events: EventReader<WindowCloseRequested>,
+) {
+  let is_closing = close_events.iter().count() > 0;
+  let is_due = match timer.0.as_mut() {
+    Some(t) => t.tick(time.delta()).finished() || is_closing,
+    None => false,
+  };
+  if is_due {
+    config.store();
+    timer.0 = None;
+  }
+}
@@ -0,0 +1,64 @@
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+use crate::protocol::{frame, unframe, Message};
+
+// 止まらないソケットに、送りきれなかった分と読みかけの分を溜めておく
+pub struct Connection {
+  stream: TcpStream,
+  incoming: Vec<u8>,
+  outgoing: Vec<u8>,
+  // 診断用に、これまでに書き出した・読んだバイト数
+  pub bytes_sent: u64,
+  pub bytes_received: u64,
+}
+
+impl Connection {
+  pub fn new(stream: TcpStream) -> io::Result<Self> {
+    stream.set_nonblocking(true)?;
+    stream.set_nodelay(true)?;
+    Ok(Connection {
+      stream,
+      incoming: vec![],
+      outgoing: vec![],
+      bytes_sent: 0,
+      bytes_received: 0,
+    })
+  }
+
+  pub fn send(&mut self, message: &Message) {
+    self.outgoing.extend(frame(message));
+  }
+
+  // 溜まった分を書き出して、届いた分を読む。切れていたら理由を返す
+  pub fn pump(&mut self) -> Result<Vec<Message>, String> {
+    while !self.outgoing.is_empty() {
+      match self.stream.write(&self.outgoing) {
+        Ok(0) => return Err("connection closed".to_string()),
+        Ok(n) => {
+          self.outgoing.drain(..n);
+          self.bytes_sent += n as u64;
+        }
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+        Err(e) => return Err(e.to_string()),
+      }
+    }
+    let mut buffer = [0; 4096];
+    loop {
+      match self.stream.read(&mut buffer) {
+        Ok(0) => return Err("opponent disconnected".to_string()),
+        Ok(n) => {
+          self.incoming.extend_from_slice(&buffer[..n]);
+          self.bytes_received += n as u64;
+        }
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+        Err(e) => return Err(e.to_string()),
+      }
+    }
+    let mut messages = vec![];
+    while let Some(message) = unframe(&mut self.incoming)? {
+      messages.push(message);
+    }
+    Ok(messages)
+  }
+}
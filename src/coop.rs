@@ -0,0 +1,467 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use crate::battle::read_actions;
+use crate::config::Config;
+use crate::profile::AutoShift;
+use crate::randomizer::{Randomizer, RandomizerKind};
+use crate::rotation::{rotate_with, KickTable};
+use crate::rumble::{Pulse, Rumble};
+use crate::sim::{Action, Piece};
+use crate::{
+  block_name, overlay_text, spawn_cells, AppState, Fonts, Materials, Position, ARENA_HEIGHT,
+};
+
+// 2 人で並んで積む、ふつうの 2 倍の幅の盤面
+pub const COOP_WIDTH: i32 = 20;
+pub const COOP_PLAYERS: usize = 2;
+const QUEUE_LEN: usize = 5;
+// 1マス落ちるまでの秒数と、下を押しているときの倍率
+const GRAVITY: f32 = 0.8;
+const SOFT_DROP_FACTOR: f32 = 20.0;
+// 盤面のまわりに空けるマス
+const GAP: f32 = 2.0;
+// 消した段数ごとの得点
+const CLEAR_SCORES: [u32; 5] = [0, 100, 300, 500, 800];
+
+const CONTROLS_HELP: &str = "P1  A/D/S  W drop  Q/E rotate\n\
+P2  arrows  Up drop  ,/. rotate\n\
+gamepad N also controls player N+1";
+
+// 下の行から順のビットマスク。Board は 16 列までしか持てないので別に持つ
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct WideBoard {
+  rows: Vec<u32>,
+}
+
+impl WideBoard {
+  // 壁と床は埋まっているものとして扱う
+  pub fn is_filled(&self, pos: &Position) -> bool {
+    if pos.x < 0 || pos.x >= COOP_WIDTH || pos.y < 0 {
+      return true;
+    }
+    self
+      .rows
+      .get(pos.y as usize)
+      .is_some_and(|row| row & (1 << pos.x) != 0)
+  }
+
+  pub fn place(&mut self, cells: &[Position]) {
+    for pos in cells {
+      if pos.x < 0 || pos.x >= COOP_WIDTH || pos.y < 0 {
+        continue;
+      }
+      let y = pos.y as usize;
+      if self.rows.len() <= y {
+        self.rows.resize(y + 1, 0);
+      }
+      self.rows[y] |= 1 << pos.x;
+    }
+  }
+
+  // 揃った行を消して、消した行数を返す
+  pub fn clear_full_rows(&mut self) -> u32 {
+    let full = (1u32 << COOP_WIDTH) - 1;
+    let before = self.rows.len();
+    self.rows.retain(|&row| row != full);
+    (before - self.rows.len()) as u32
+  }
+}
+
+pub struct CoopPlayer {
+  pub piece: Option<Piece>,
+  pub queue: VecDeque<u32>,
+  randomizer: Box<dyn Randomizer>,
+  auto_shift: AutoShift,
+  gravity: Timer,
+  pub pieces: u32,
+  pub lines: u32,
+}
+
+impl CoopPlayer {
+  fn new(seed: u64) -> Self {
+    let mut randomizer = RandomizerKind::Bag.build(seed);
+    let queue = (0..QUEUE_LEN).map(|_| randomizer.next()).collect();
+    CoopPlayer {
+      piece: None,
+      queue,
+      randomizer,
+      auto_shift: AutoShift::default(),
+      gravity: Timer::from_seconds(GRAVITY, true),
+      pieces: 0,
+      lines: 0,
+    }
+  }
+}
+
+// 2 人がそれぞれのミノを同時に動かし、ラインと得点は分け合う
+pub struct Coop {
+  pub board: WideBoard,
+  pub players: Vec<CoopPlayer>,
+  pub lines: u32,
+  pub score: u32,
+  pub game_over: bool,
+  kicks: KickTable,
+}
+
+impl Coop {
+  // ミノの並びは人ごとに別にする
+  pub fn new(seed: u64) -> Self {
+    let mut coop = Coop {
+      board: WideBoard::default(),
+      players: (0..COOP_PLAYERS)
+        .map(|i| CoopPlayer::new(seed.wrapping_add(i as u64)))
+        .collect(),
+      lines: 0,
+      score: 0,
+      game_over: false,
+      kicks: KickTable::srs(),
+    };
+    for player in 0..COOP_PLAYERS {
+      coop.spawn(player);
+    }
+    coop
+  }
+
+  // 壁、積まれたブロック、もう 1 人の動かしているミノのどれにも重ならないか
+  pub fn fits(&self, player: usize, cells: &[Position]) -> bool {
+    cells.iter().all(|cell| {
+      !self.board.is_filled(cell)
+        && self
+          .players
+          .iter()
+          .enumerate()
+          .filter(|&(i, _)| i != player)
+          .all(|(_, other)| other.piece.as_ref().is_none_or(|p| !p.cells.contains(cell)))
+    })
+  }
+
+  // 人ごとに自分の半分の真ん中に出す。積まれたブロックで出せなければ 2 人とも終わり。
+  // もう 1 人のミノが出る場所にいるときは、どくまで待つ
+  fn spawn(&mut self, player: usize) {
+    let block_idx = self.players[player].queue.front().copied().unwrap_or(1);
+    let offset = player as i32 * COOP_WIDTH / COOP_PLAYERS as i32 + 1;
+    let cells: Vec<Position> = spawn_cells(block_idx)
+      .into_iter()
+      .map(|c| Position {
+        x: c.x + offset,
+        y: c.y,
+      })
+      .collect();
+    if cells.iter().any(|c| self.board.is_filled(c)) {
+      self.game_over = true;
+      return;
+    }
+    if !self.fits(player, &cells) {
+      return;
+    }
+    let p = &mut self.players[player];
+    p.queue.pop_front();
+    let next = p.randomizer.next();
+    p.queue.push_back(next);
+    p.piece = Some(Piece {
+      block_idx,
+      rotation: 0,
+      cells,
+    });
+  }
+
+  fn moved(&self, player: usize, action: Action) -> Option<Piece> {
+    let piece = self.players[player].piece.as_ref()?;
+    let shifted = |dx: i32, dy: i32| -> Vec<Position> {
+      piece
+        .cells
+        .iter()
+        .map(|p| Position {
+          x: p.x + dx,
+          y: p.y + dy,
+        })
+        .collect()
+    };
+    let (cells, rotation) = match action {
+      Action::Left => (shifted(-1, 0), piece.rotation),
+      Action::Right => (shifted(1, 0), piece.rotation),
+      Action::SoftDrop => (shifted(0, -1), piece.rotation),
+      Action::RotateCw | Action::RotateCcw => {
+        let (cells, rotation, _) = rotate_with(
+          |c| self.fits(player, c),
+          &self.kicks,
+          &piece.cells,
+          piece.block_idx,
+          piece.rotation,
+          action == Action::RotateCw,
+        )?;
+        (cells, rotation)
+      }
+      Action::HardDrop | Action::Hold => return None,
+    };
+    if !self.fits(player, &cells) {
+      return None;
+    }
+    Some(Piece {
+      block_idx: piece.block_idx,
+      rotation,
+      cells,
+    })
+  }
+
+  // 操作を 1 つ適用する。固定したら true。ホールドは無い
+  pub fn apply(&mut self, player: usize, action: Action) -> bool {
+    if self.game_over {
+      return false;
+    }
+    if action == Action::HardDrop {
+      while let Some(piece) = self.moved(player, Action::SoftDrop) {
+        self.players[player].piece = Some(piece);
+      }
+      self.lock(player);
+      return true;
+    }
+    if let Some(piece) = self.moved(player, action) {
+      self.players[player].piece = Some(piece);
+    }
+    false
+  }
+
+  // 重力で 1 段落とす。落ちられなければその場で固定して true。出るのを待っていたら出し直す
+  pub fn fall(&mut self, player: usize) -> bool {
+    if self.game_over {
+      return false;
+    }
+    if self.players[player].piece.is_none() {
+      self.spawn(player);
+      return false;
+    }
+    match self.moved(player, Action::SoftDrop) {
+      Some(piece) => {
+        self.players[player].piece = Some(piece);
+        false
+      }
+      None => {
+        self.lock(player);
+        true
+      }
+    }
+  }
+
+  fn lock(&mut self, player: usize) {
+    let piece = match self.players[player].piece.take() {
+      Some(piece) => piece,
+      None => return,
+    };
+    self.board.place(&piece.cells);
+    let lines = self.board.clear_full_rows();
+    let p = &mut self.players[player];
+    p.pieces += 1;
+    p.lines += lines;
+    self.lines += lines;
+    self.score += CLEAR_SCORES[lines.min(4) as usize];
+    // もう 1 人のミノはその場に残すが、上の行が下りてきて重なったら重ならない所まで押し上げる
+    if lines > 0 {
+      for other in (0..self.players.len()).filter(|&i| i != player) {
+        self.lift(other);
+      }
+    }
+    self.spawn(player);
+  }
+
+  fn lift(&mut self, player: usize) {
+    let mut piece = match self.players[player].piece.take() {
+      Some(piece) => piece,
+      None => return,
+    };
+    while !self.fits(player, &piece.cells)
+      && piece.cells.iter().all(|c| c.y < ARENA_HEIGHT as i32 + 4)
+    {
+      for cell in piece.cells.iter_mut() {
+        cell.y += 1;
+      }
+    }
+    self.players[player].piece = Some(piece);
+  }
+}
+
+struct CoopText;
+struct CoopBoard;
+struct CoopCell {
+  x: i32,
+  y: i32,
+}
+
+pub struct CoopPlugin;
+
+impl Plugin for CoopPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(Coop::new(rand::random()))
+      .add_system_set(SystemSet::on_enter(AppState::Battle).with_system(setup_coop.system()))
+      .add_system_set(
+        SystemSet::on_update(AppState::Battle)
+          .with_system(coop_play.system())
+          .with_system(coop_layout.system())
+          .with_system(coop_render.system())
+          .with_system(coop_ui.system()),
+      );
+  }
+}
+
+fn setup_coop(mut commands: Commands, fonts: Res<Fonts>, materials: Res<Materials>) {
+  commands.spawn_bundle(overlay_text(&fonts)).insert(CoopText);
+  commands
+    .spawn_bundle(SpriteBundle {
+      material: materials.panel.clone(),
+      ..Default::default()
+    })
+    .insert(CoopBoard);
+  for y in 0..ARENA_HEIGHT as i32 {
+    for x in 0..COOP_WIDTH {
+      commands
+        .spawn_bundle(SpriteBundle {
+          material: materials.transparent.clone(),
+          ..Default::default()
+        })
+        .insert(CoopCell { x, y });
+    }
+  }
+}
+
+fn coop_play(
+  time: Res<Time>,
+  config: Res<Config>,
+  keyboard_input: Res<Input<KeyCode>>,
+  buttons: Res<Input<GamepadButton>>,
+  mut coop: ResMut<Coop>,
+  mut rumble: ResMut<Rumble>,
+) {
+  if coop.game_over {
+    if keyboard_input.just_pressed(KeyCode::Return) {
+      *coop = Coop::new(rand::random());
+    }
+    return;
+  }
+  for i in 0..coop.players.len() {
+    let (actions, soft_drop) = read_actions(
+      i,
+      &keyboard_input,
+      &buttons,
+      &mut coop.players[i].auto_shift,
+      &time,
+      config.tick_rate,
+    );
+    if actions.contains(&Action::HardDrop) {
+      rumble.pulse(i, Pulse::HardDrop);
+    }
+    let lines = coop.players[i].lines;
+    let mut locked = false;
+    for action in actions {
+      locked |= coop.apply(i, action);
+    }
+    let mut delta = time.delta();
+    if soft_drop {
+      delta = delta.mul_f32(SOFT_DROP_FACTOR);
+    }
+    if locked {
+      coop.players[i].gravity.reset();
+    } else if coop.players[i].gravity.tick(delta).just_finished() {
+      coop.fall(i);
+    }
+    let cleared = coop.players[i].lines - lines;
+    if cleared > 0 {
+      rumble.pulse(i, Pulse::Clear(cleared));
+    }
+  }
+}
+
+fn coop_layout(
+  windows: Res<Windows>,
+  mut last: Local<Option<(f32, f32)>>,
+  mut board_query: Query<(&mut Sprite, &mut Transform), With<CoopBoard>>,
+  mut cell_query: Query<(&CoopCell, &mut Sprite, &mut Transform), Without<CoopBoard>>,
+) {
+  let window = match windows.get_primary() {
+    Some(window) => window,
+    None => return,
+  };
+  let key = (window.width(), window.height());
+  if *last == Some(key) {
+    return;
+  }
+  *last = Some(key);
+
+  let cell =
+    (window.width() / (COOP_WIDTH as f32 + GAP)).min(window.height() / (ARENA_HEIGHT as f32 + GAP));
+  let board_width = COOP_WIDTH as f32 * cell;
+  let board_height = ARENA_HEIGHT as f32 * cell;
+  for (mut sprite, mut transform) in board_query.iter_mut() {
+    sprite.size = Vec2::new(board_width, board_height);
+    transform.translation = Vec3::ZERO;
+  }
+  for (pos, mut sprite, mut transform) in cell_query.iter_mut() {
+    sprite.size = Vec2::splat(cell);
+    transform.translation = Vec3::new(
+      -board_width / 2. + (pos.x as f32 + 0.5) * cell,
+      -board_height / 2. + (pos.y as f32 + 0.5) * cell,
+      1.,
+    );
+  }
+}
+
+fn coop_render(
+  coop: Res<Coop>,
+  materials: Res<Materials>,
+  mut query: Query<(&CoopCell, &mut Handle<ColorMaterial>)>,
+) {
+  if !coop.is_changed() {
+    return;
+  }
+  // 自分のミノがどちらか分かるよう、人ごとに色を変える
+  let fills = [&materials.chart_bar, &materials.hint_block];
+  for (pos, mut material) in query.iter_mut() {
+    let cell = Position { x: pos.x, y: pos.y };
+    let owner = coop.players.iter().position(|p| {
+      p.piece
+        .as_ref()
+        .is_some_and(|piece| piece.cells.contains(&cell))
+    });
+    let next = match owner {
+      Some(i) => fills[i % fills.len()],
+      None if coop.board.is_filled(&cell) => &materials.gray_block,
+      None => &materials.transparent,
+    };
+    if *material != *next {
+      *material = next.clone();
+    }
+  }
+}
+
+fn coop_ui(coop: Res<Coop>, mut query: Query<&mut Text, With<CoopText>>) {
+  if !coop.is_changed() {
+    return;
+  }
+  let mut value = format!("CO-OP  lines {}  score {}", coop.lines, coop.score);
+  for (i, player) in coop.players.iter().enumerate() {
+    let next: Vec<String> = player
+      .queue
+      .iter()
+      .map(|&idx| block_name(idx).to_string())
+      .collect();
+    value += &format!(
+      "\nP{}  pieces {}  lines {}  next {}",
+      i + 1,
+      player.pieces,
+      player.lines,
+      next.join(" ")
+    );
+  }
+  if coop.game_over {
+    value += "\n\nGAME OVER  [Enter] play again";
+  } else {
+    value += &format!("\n\n{}", CONTROLS_HELP);
+  }
+  for mut text in query.iter_mut() {
+    if text.sections[0].value != value {
+      text.sections[0].value = value.clone();
+    }
+  }
+}
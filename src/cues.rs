@@ -0,0 +1,98 @@
+use bevy::prelude::*;
+
+use crate::audio::PlayTones;
+use crate::board::Board;
+use crate::config::Config;
+use crate::{
+  ActiveBlock, PieceMoved, PieceRotated, Position, PrimitiveBlock, StackedBlock, ARENA_HEIGHT,
+};
+
+// 旋律の 1 音と、着地の合図の長さ (秒)
+const NOTE_SECONDS: f32 = 0.07;
+const LANDING_SECONDS: f32 = 0.04;
+// 盤面のいちばん上から落とすときの合図の高さ。近いほど半音ずつ上がる
+const LANDING_LOW: f32 = 220.;
+
+// 旋律に使う音 (Hz)
+const C5: u32 = 523;
+const E5: u32 = 659;
+const G5: u32 = 784;
+const A5: u32 = 880;
+const C6: u32 = 1047;
+
+// ミノごとの短い旋律。上がる・下がる・往復と音の数で聞き分けられるようにする
+pub fn piece_motif(block_idx: u32) -> Vec<u32> {
+  match block_idx {
+    // O
+    1 => vec![C5, C5],
+    // Z と S は向きが逆
+    2 => vec![G5, C5],
+    3 => vec![C5, G5],
+    // L と J も向きが逆
+    4 => vec![C5, E5, G5],
+    5 => vec![G5, E5, C5],
+    // T
+    6 => vec![E5, A5, E5],
+    // I
+    7 => vec![C5, E5, G5, C6],
+    _ => vec![],
+  }
+}
+
+// 着地するまでの段数を音の高さにする。近いほど高い
+pub fn landing_pitch(distance: i32) -> u32 {
+  let steps = (ARENA_HEIGHT as i32 - distance.clamp(0, ARENA_HEIGHT as i32)) as f32;
+  (LANDING_LOW * 2f32.powf(steps / 12.)).round() as u32
+}
+
+// 目の見えにくい人向けに、ミノの種類と着地までの高さを音で知らせる
+pub struct CuesPlugin;
+
+impl Plugin for CuesPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .add_system(piece_cue.system())
+      .add_system(landing_cue.system());
+  }
+}
+
+fn piece_cue(
+  config: Res<Config>,
+  active_block: Res<ActiveBlock>,
+  spawned_query: Query<(), Added<PrimitiveBlock>>,
+  mut tone_events: EventWriter<PlayTones>,
+) {
+  if !config.audio.piece_cues || spawned_query.iter().next().is_none() {
+    return;
+  }
+  let motif = piece_motif(active_block.block_idx);
+  if !motif.is_empty() {
+    tone_events.send(PlayTones(
+      motif.into_iter().map(|freq| (freq, NOTE_SECONDS)).collect(),
+    ));
+  }
+}
+
+// 自分で動かしたときだけ鳴らす。自然落下では鳴らさない
+fn landing_cue(
+  config: Res<Config>,
+  active_block: Res<ActiveBlock>,
+  mut moved_events: EventReader<PieceMoved>,
+  mut rotated_events: EventReader<PieceRotated>,
+  primitive_block_query: Query<&Position, With<PrimitiveBlock>>,
+  stacked_block_query: Query<&Position, With<StackedBlock>>,
+  mut tone_events: EventWriter<PlayTones>,
+) {
+  let moved = moved_events.iter().count() + rotated_events.iter().count() > 0;
+  if !moved || !config.audio.landing_cues || !active_block.is_on() {
+    return;
+  }
+  let cells: Vec<Position> = primitive_block_query.iter().cloned().collect();
+  let board = Board::from_cells(stacked_block_query.iter());
+  let landed = board.drop(&cells);
+  let distance = match (cells.first(), landed.first()) {
+    (Some(cell), Some(landed)) => cell.y - landed.y,
+    _ => return,
+  };
+  tone_events.send(PlayTones(vec![(landing_pitch(distance), LANDING_SECONDS)]));
+}
@@ -0,0 +1,82 @@
+use bevy::prelude::*;
+use tetris::randomizer::DealStats;
+
+use crate::{block_name, overlay_text, Fonts, PieceQueue};
+
+// F6 で、起動してから配ったミノの数と出なかった間隔を出す。ランダマイザーの確かめや練習用
+const TOGGLE_KEY: KeyCode = KeyCode::F6;
+// いちばん多いミノの棒の長さ
+const BAR_WIDTH: u32 = 20;
+
+// ミノごとに 1 行。個数の棒と、いちばん長く出なかった間隔
+pub fn deal_text(stats: &DealStats) -> String {
+  let most = stats.counts.iter().copied().max().unwrap_or(0).max(1);
+  let mut lines = vec![format!("DEALT  {} pieces", stats.total())];
+  for (i, &count) in stats.counts.iter().enumerate() {
+    let bar = "#".repeat((count * BAR_WIDTH / most) as usize);
+    lines.push(format!(
+      "{} {:>4} {:<width$} drought {}",
+      block_name(i as u32 + 1),
+      count,
+      bar,
+      stats.droughts[i],
+      width = BAR_WIDTH as usize
+    ));
+  }
+  lines.join("\n")
+}
+
+#[derive(Default)]
+struct DealsOverlay {
+  visible: bool,
+}
+
+struct DealsText;
+
+pub struct DealsPlugin;
+
+impl Plugin for DealsPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(DealsOverlay::default())
+      .add_startup_system_to_stage(StartupStage::PostStartup, setup_deals.system())
+      .add_system(deals_toggle.system())
+      .add_system(deals_ui.system());
+  }
+}
+
+fn setup_deals(mut commands: Commands, fonts: Res<Fonts>) {
+  let mut text = overlay_text(&fonts);
+  text.style.position = Rect {
+    top: Val::Px(200.0),
+    right: Val::Px(5.0),
+    ..Default::default()
+  };
+  commands.spawn_bundle(text).insert(DealsText);
+}
+
+fn deals_toggle(keyboard_input: Res<Input<KeyCode>>, mut overlay: ResMut<DealsOverlay>) {
+  if keyboard_input.just_pressed(TOGGLE_KEY) {
+    overlay.visible = !overlay.visible;
+  }
+}
+
+fn deals_ui(
+  overlay: Res<DealsOverlay>,
+  piece_queue: Res<PieceQueue>,
+  mut query: Query<&mut Text, With<DealsText>>,
+) {
+  if !overlay.is_changed() && !piece_queue.is_changed() {
+    return;
+  }
+  let value = if overlay.visible {
+    deal_text(&piece_queue.dealt)
+  } else {
+    String::new()
+  };
+  for mut text in query.iter_mut() {
+    if text.sections[0].value != value {
+      text.sections[0].value = value.clone();
+    }
+  }
+}
@@ -0,0 +1,287 @@
+use bevy::prelude::*;
+use bevy::render::camera::{ActiveCameras, Camera};
+use bevy::render::pass::{
+  LoadOp, Operations, PassDescriptor, RenderPassDepthStencilAttachmentDescriptor, TextureAttachment,
+};
+use bevy::render::render_graph::{
+  base::MainPass, CameraNode, PassNode, RenderGraph, WindowSwapChainNode, WindowTextureNode,
+};
+use bevy::render::texture::{
+  Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsage,
+};
+use bevy::window::{CreateWindow, WindowId};
+
+use crate::hud::{hud_text, HudSettings};
+use crate::layout::{Anchor, Panel};
+use crate::stats::Statistics;
+use crate::{block_name, AppState, Fonts, GameClock};
+
+// 2 つ目のウィンドウで、統計か相手の盤面を出す。配信や観戦のときに本体のウィンドウを盤面だけにする
+const DETACH_KEY: KeyCode = KeyCode::F7;
+const CAMERA: &str = "detached";
+// 2 つ目のウィンドウのカメラが見るワールド座標。本体のカメラには映らないくらい遠くに置く
+pub const ORIGIN_X: f32 = 100_000.0;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DetachView {
+  Stats,
+  Opponents,
+}
+
+// F7 を押すたびに 統計 → 相手の盤面 → 戻す の順に切り替える
+pub fn next_view(view: Option<DetachView>) -> Option<DetachView> {
+  match view {
+    None => Some(DetachView::Stats),
+    Some(DetachView::Stats) => Some(DetachView::Opponents),
+    Some(DetachView::Opponents) => None,
+  }
+}
+
+// 2 つ目のウィンドウに出す統計。HUD の数字を全部と、ライン数とミノごとの個数
+pub fn detached_stats_text(stats: &Statistics, elapsed: f64) -> String {
+  let pieces: Vec<String> = stats
+    .pieces
+    .iter()
+    .enumerate()
+    .map(|(i, n)| format!("{} {}", block_name(i as u32 + 1), n))
+    .collect();
+  format!(
+    "{}\nlines {}  level {}\n{}",
+    hud_text(&HudSettings::default(), stats, 0, elapsed),
+    stats.lines(),
+    stats.level() + 1,
+    pieces.join("  ")
+  )
+}
+
+#[derive(Default)]
+pub struct Detached {
+  pub view: Option<DetachView>,
+  window: Option<WindowId>,
+  // 描画の経路とカメラを作り終えた
+  ready: bool,
+}
+
+impl Detached {
+  // 相手の盤面を並べる 2 つ目のウィンドウの大きさ。開いていなければ None
+  pub fn opponents_size(&self, windows: &Windows) -> Option<Vec2> {
+    if self.view != Some(DetachView::Opponents) || !self.ready {
+      return None;
+    }
+    let window = windows.get(self.window?)?;
+    Some(Vec2::new(window.width(), window.height()))
+  }
+}
+
+struct DetachedText;
+
+pub struct DetachPlugin;
+
+impl Plugin for DetachPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(Detached::default())
+      .add_startup_system_to_stage(StartupStage::PostStartup, setup_detach.system())
+      .add_system(detach_input.system())
+      .add_system(setup_detached_window.system())
+      .add_system(hide_detached_panels.system())
+      .add_system(detached_ui.system());
+  }
+}
+
+fn setup_detach(mut commands: Commands, fonts: Res<Fonts>) {
+  commands
+    .spawn_bundle(Text2dBundle {
+      text: Text::with_section(
+        "",
+        TextStyle {
+          font: fonts.main.clone(),
+          font_size: 24.0,
+          color: Color::WHITE,
+        },
+        TextAlignment {
+          vertical: VerticalAlign::Center,
+          horizontal: HorizontalAlign::Center,
+        },
+      ),
+      transform: Transform::from_xyz(ORIGIN_X, 0., 0.),
+      ..Default::default()
+    })
+    .insert(DetachedText);
+}
+
+// bevy はどのウィンドウを閉じてもゲームごと終わるので、戻すときは閉じずに最小化する
+fn detach_input(
+  keyboard_input: Res<Input<KeyCode>>,
+  mut detached: ResMut<Detached>,
+  mut windows: ResMut<Windows>,
+  mut create_window_events: EventWriter<CreateWindow>,
+) {
+  if !keyboard_input.just_pressed(DETACH_KEY) {
+    return;
+  }
+  detached.view = next_view(detached.view);
+  match detached.window.and_then(|id| windows.get_mut(id)) {
+    Some(window) => window.set_minimized(detached.view.is_none()),
+    None if detached.window.is_none() => {
+      let id = WindowId::new();
+      create_window_events.send(CreateWindow {
+        id,
+        descriptor: WindowDescriptor {
+          title: "Tetris - detached".to_string(),
+          width: 600.,
+          height: 600.,
+          ..Default::default()
+        },
+      });
+      detached.window = Some(id);
+    }
+    None => {}
+  }
+}
+
+// ウィンドウができるのは CreateWindow の次のフレーム以降なので、できてから描画の経路をつなぐ
+fn setup_detached_window(
+  mut commands: Commands,
+  windows: Res<Windows>,
+  msaa: Res<Msaa>,
+  mut detached: ResMut<Detached>,
+  mut active_cameras: ResMut<ActiveCameras>,
+  mut render_graph: ResMut<RenderGraph>,
+) {
+  let window_id = match detached.window {
+    Some(id) if !detached.ready && windows.get(id).is_some() => id,
+    _ => return,
+  };
+  detached.ready = true;
+
+  render_graph.add_node("detached_swap_chain", WindowSwapChainNode::new(window_id));
+  render_graph.add_node(
+    "detached_depth_texture",
+    WindowTextureNode::new(
+      window_id,
+      TextureDescriptor {
+        format: TextureFormat::Depth32Float,
+        usage: TextureUsage::OUTPUT_ATTACHMENT,
+        sample_count: msaa.samples,
+        ..Default::default()
+      },
+    ),
+  );
+  render_graph.add_system_node("detached_camera", CameraNode::new(CAMERA));
+  let mut pass = PassNode::<&MainPass>::new(PassDescriptor {
+    color_attachments: vec![msaa.color_attachment_descriptor(
+      TextureAttachment::Input("color_attachment".to_string()),
+      TextureAttachment::Input("color_resolve_target".to_string()),
+      Operations {
+        load: LoadOp::Clear(Color::rgb(0.04, 0.04, 0.04)),
+        store: true,
+      },
+    )],
+    depth_stencil_attachment: Some(RenderPassDepthStencilAttachmentDescriptor {
+      attachment: TextureAttachment::Input("depth".to_string()),
+      depth_ops: Some(Operations {
+        load: LoadOp::Clear(1.0),
+        store: true,
+      }),
+      stencil_ops: None,
+    }),
+    sample_count: msaa.samples,
+  });
+  pass.add_camera(CAMERA);
+  active_cameras.add(CAMERA);
+  render_graph.add_node("detached_pass", pass);
+
+  let edges = render_graph
+    .add_slot_edge(
+      "detached_swap_chain",
+      WindowSwapChainNode::OUT_TEXTURE,
+      "detached_pass",
+      if msaa.samples > 1 {
+        "color_resolve_target"
+      } else {
+        "color_attachment"
+      },
+    )
+    .and_then(|_| {
+      render_graph.add_slot_edge(
+        "detached_depth_texture",
+        WindowTextureNode::OUT_TEXTURE,
+        "detached_pass",
+        "depth",
+      )
+    })
+    .and_then(|_| render_graph.add_node_edge("detached_camera", "detached_pass"));
+  if let Err(e) = edges {
+    error!("failed to set up the detached window: {:?}", e);
+    return;
+  }
+  if msaa.samples > 1 {
+    render_graph.add_node(
+      "detached_multi_sampled_color_attachment",
+      WindowTextureNode::new(
+        window_id,
+        TextureDescriptor {
+          size: Extent3d {
+            depth: 1,
+            width: 1,
+            height: 1,
+          },
+          mip_level_count: 1,
+          sample_count: msaa.samples,
+          dimension: TextureDimension::D2,
+          format: TextureFormat::default(),
+          usage: TextureUsage::OUTPUT_ATTACHMENT,
+        },
+      ),
+    );
+    if let Err(e) = render_graph.add_slot_edge(
+      "detached_multi_sampled_color_attachment",
+      WindowSwapChainNode::OUT_TEXTURE,
+      "detached_pass",
+      "color_attachment",
+    ) {
+      error!("failed to set up the detached window: {:?}", e);
+    }
+  }
+
+  let mut camera = OrthographicCameraBundle::new_2d();
+  camera.camera = Camera {
+    name: Some(CAMERA.to_string()),
+    window: window_id,
+    ..camera.camera
+  };
+  camera.transform.translation.x = ORIGIN_X;
+  commands.spawn_bundle(camera);
+}
+
+// 統計を 2 つ目のウィンドウに出している間は、本体の HUD と統計のパネルを隠す
+fn hide_detached_panels(detached: Res<Detached>, mut query: Query<(&Anchor, &mut Visible)>) {
+  for (anchor, mut visible) in query.iter_mut() {
+    let shown =
+      detached.view != Some(DetachView::Stats) || !matches!(anchor.0, Panel::Hud | Panel::Stats);
+    if visible.is_visible != shown {
+      visible.is_visible = shown;
+    }
+  }
+}
+
+fn detached_ui(
+  clock: Res<GameClock>,
+  state: Res<State<AppState>>,
+  detached: Res<Detached>,
+  stats: Res<Statistics>,
+  mut query: Query<&mut Text, With<DetachedText>>,
+) {
+  let playing = matches!(state.current(), AppState::Playing | AppState::Paused);
+  let value = if detached.view == Some(DetachView::Stats) && playing {
+    detached_stats_text(&stats, clock.0 - stats.start)
+  } else {
+    String::new()
+  };
+  for mut text in query.iter_mut() {
+    if text.sections[0].value != value {
+      text.sections[0].value = value.clone();
+    }
+  }
+}
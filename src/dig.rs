@@ -0,0 +1,155 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+
+use crate::garbage::{GarbageConfig, GarbageGenerator};
+use crate::{
+  overlay_text, save, spawn_stacked_block, AppState, Fonts, GameReset, Label, LinesCleared,
+  Materials, Position,
+};
+
+const SAVE_FILE: &str = "garbage.ron";
+const DIG_ROWS: i32 = 10;
+
+// せり上がりとして置いたブロック
+struct Garbage;
+
+// Playing に入ったので、次の PreUpdate でせり上がりを置く
+#[derive(Default)]
+struct GarbagePending(bool);
+
+struct DigText;
+struct DigSetupText;
+
+pub struct DigPlugin;
+
+impl Plugin for DigPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(save::load::<GarbageConfig>(SAVE_FILE))
+      .init_resource::<GarbagePending>()
+      .add_startup_system_to_stage(StartupStage::PostStartup, setup_dig.system())
+      .add_system_set(SystemSet::on_exit(AppState::Title).with_system(reload_dig_options.system()))
+      .add_system_set(SystemSet::on_enter(AppState::Setup).with_system(setup_dig_options.system()))
+      .add_system_set(SystemSet::on_update(AppState::Setup).with_system(dig_options_input.system()))
+      .add_system_set(SystemSet::on_exit(AppState::Setup).with_system(close_dig_options.system()))
+      .add_system_set(SystemSet::on_enter(AppState::Playing).with_system(queue_garbage.system()))
+      .add_system_set(SystemSet::on_update(AppState::Playing).with_system(dig_check.system()))
+      // 結果画面からのやり直しは GameReset と Playing が同時に来るので、置くのはここだけにして
+      // reset_game が盤面を片付けた後に一度だけ置く
+      .add_system_to_stage(
+        CoreStage::PreUpdate,
+        spawn_garbage.system().after(Label::Reset),
+      )
+      .add_system(dig_ui.system());
+  }
+}
+
+fn setup_dig(mut commands: Commands, fonts: Res<Fonts>) {
+  commands.spawn_bundle(overlay_text(&fonts)).insert(DigText);
+}
+
+fn reload_dig_options(mut config: ResMut<GarbageConfig>) {
+  *config = save::load(SAVE_FILE);
+}
+
+fn setup_dig_options(mut commands: Commands, fonts: Res<Fonts>) {
+  let mut text = overlay_text(&fonts);
+  text.style.position = Rect {
+    top: Val::Px(360.0),
+    left: Val::Px(40.0),
+    ..Default::default()
+  };
+  commands.spawn_bundle(text).insert(DigSetupText);
+}
+
+fn dig_options_input(
+  keyboard_input: Res<Input<KeyCode>>,
+  mut config: ResMut<GarbageConfig>,
+  mut query: Query<&mut Text, With<DigSetupText>>,
+) {
+  if keyboard_input.just_pressed(KeyCode::X) {
+    let patterns = GarbageConfig::PATTERNS;
+    let next = patterns
+      .iter()
+      .position(|&p| p == config.pattern)
+      .map_or(0, |i| (i + 1) % patterns.len());
+    config.pattern = patterns[next];
+  }
+  let value = format!("DIG\ngarbage: {}\n\n[X] hole pattern", config.describe());
+  for mut text in query.iter_mut() {
+    text.sections[0].value = value.clone();
+  }
+}
+
+fn close_dig_options(
+  mut commands: Commands,
+  config: Res<GarbageConfig>,
+  query: Query<Entity, With<DigSetupText>>,
+) {
+  for entity in query.iter() {
+    commands.entity(entity).despawn();
+  }
+  if let Err(e) = save::store(SAVE_FILE, &*config) {
+    error!("failed to save garbage config: {}", e);
+  }
+}
+
+fn queue_garbage(mut pending: ResMut<GarbagePending>) {
+  pending.0 = true;
+}
+
+fn spawn_garbage(
+  mut commands: Commands,
+  materials: Res<Materials>,
+  config: Res<GarbageConfig>,
+  mut pending: ResMut<GarbagePending>,
+  mut reset_events: EventReader<GameReset>,
+) {
+  if reset_events.iter().count() == 0 && !pending.0 {
+    return;
+  }
+  pending.0 = false;
+  let mut generator = GarbageGenerator::new(&config, rand::random());
+  for y in 0..DIG_ROWS {
+    for cell in generator.row(y) {
+      let entity = spawn_stacked_block(&mut commands, &materials, cell);
+      commands.entity(entity).insert(Garbage);
+    }
+  }
+}
+
+// ライン消去の次のフレーム (despawn が反映された後) にせり上がりが残っているか調べる
+fn dig_check(
+  mut pending: Local<bool>,
+  mut state: ResMut<State<AppState>>,
+  mut cleared_events: EventReader<LinesCleared>,
+  garbage_query: Query<&Garbage>,
+) {
+  if *pending {
+    *pending = false;
+    if garbage_query.iter().next().is_none() {
+      state.overwrite_set(AppState::Results).unwrap();
+    }
+  }
+  if cleared_events.iter().count() > 0 {
+    *pending = true;
+  }
+}
+
+fn dig_ui(
+  config: Res<GarbageConfig>,
+  garbage_query: Query<&Position, With<Garbage>>,
+  mut query: Query<&mut Text, With<DigText>>,
+) {
+  let rows: HashSet<i32> = garbage_query.iter().map(|p| p.y).collect();
+  let value = format!(
+    "DIG  garbage left: {}/{}\n{}\n[hold R] retry",
+    rows.len(),
+    DIG_ROWS,
+    config.describe()
+  );
+  for mut text in query.iter_mut() {
+    text.sections[0].value = value.clone();
+  }
+}
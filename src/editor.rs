@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+
+use crate::layout::Layout;
+use crate::puzzle::{Puzzle, PuzzleGoal, PUZZLE_DIR};
+use crate::snapshot::BoardImported;
+use crate::{
+  block_idx_from_name, block_name, overlay_text, Fonts, Materials, PieceQueue, Position, Size,
+};
+
+const PIECE_KEYS: [(KeyCode, char); 7] = [
+  (KeyCode::O, 'O'),
+  (KeyCode::Z, 'Z'),
+  (KeyCode::S, 'S'),
+  (KeyCode::L, 'L'),
+  (KeyCode::J, 'J'),
+  (KeyCode::T, 'T'),
+  (KeyCode::I, 'I'),
+];
+
+// エディタで塗ったマス。StackedBlock ではないのでライン消去の対象にならない
+struct EditorCell;
+
+struct EditorState {
+  cells: HashMap<Position, Entity>,
+  queue: Vec<u32>,
+  goal: PuzzleGoal,
+  message: String,
+}
+
+struct EditorText;
+
+pub struct EditorPlugin;
+
+impl Plugin for EditorPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(EditorState {
+        cells: HashMap::new(),
+        queue: vec![],
+        goal: PuzzleGoal::ClearBoard,
+        message: String::new(),
+      })
+      .add_startup_system_to_stage(StartupStage::PostStartup, setup_editor.system())
+      .add_system(editor_mouse.system())
+      .add_system(editor_keys.system())
+      .add_system(editor_import.system())
+      .add_system(editor_ui.system());
+  }
+}
+
+fn setup_editor(mut commands: Commands, fonts: Res<Fonts>, mut piece_queue: ResMut<PieceQueue>) {
+  commands
+    .spawn_bundle(overlay_text(&fonts))
+    .insert(EditorText);
+  // 編集中はミノを落とさない
+  piece_queue.queue.clear();
+  piece_queue.fixed = true;
+}
+
+fn spawn_cell(commands: &mut Commands, materials: &Materials, position: Position) -> Entity {
+  commands
+    .spawn_bundle(SpriteBundle {
+      material: materials.white_block.clone(),
+      ..Default::default()
+    })
+    .insert(EditorCell)
+    .insert(position)
+    .insert(Size::square(0.8))
+    .id()
+}
+
+fn editor_mouse(
+  mut commands: Commands,
+  materials: Res<Materials>,
+  windows: Res<Windows>,
+  layout: Res<Layout>,
+  mouse_input: Res<Input<MouseButton>>,
+  mut state: ResMut<EditorState>,
+) {
+  let window = windows.get_primary().unwrap();
+  let position = match window
+    .cursor_position()
+    .and_then(|cursor| layout.cell_at(cursor))
+  {
+    Some(position) => position,
+    None => return,
+  };
+
+  if mouse_input.pressed(MouseButton::Left) && !state.cells.contains_key(&position) {
+    let entity = spawn_cell(&mut commands, &materials, position.clone());
+    state.cells.insert(position, entity);
+  } else if mouse_input.pressed(MouseButton::Right) {
+    if let Some(entity) = state.cells.remove(&position) {
+      commands.entity(entity).despawn();
+    }
+  }
+}
+
+fn next_goal(goal: PuzzleGoal) -> PuzzleGoal {
+  match goal {
+    PuzzleGoal::ClearBoard => PuzzleGoal::ClearLines(1),
+    PuzzleGoal::ClearLines(n) if n < 4 => PuzzleGoal::ClearLines(n + 1),
+    PuzzleGoal::ClearLines(_) => PuzzleGoal::ClearBoard,
+  }
+}
+
+// custom_01.ron, custom_02.ron, ... のうち空いている最初の名前
+fn free_puzzle_path(dir: &Path) -> (String, PathBuf) {
+  (1..)
+    .map(|i| {
+      (
+        format!("Custom {:02}", i),
+        dir.join(format!("custom_{:02}.ron", i)),
+      )
+    })
+    .find(|(_, path)| !path.exists())
+    .unwrap()
+}
+
+fn save_puzzle(state: &EditorState) -> Result<PathBuf, String> {
+  if state.queue.is_empty() {
+    return Err("queue is empty".to_string());
+  }
+  if state.goal == PuzzleGoal::ClearBoard && state.cells.is_empty() {
+    return Err("board is empty".to_string());
+  }
+
+  let dir = Path::new(PUZZLE_DIR);
+  fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+  let (name, path) = free_puzzle_path(dir);
+  let puzzle = Puzzle {
+    name,
+    cells: state.cells.keys().cloned().collect(),
+    queue: state.queue.clone(),
+    goal: state.goal,
+  };
+  fs::write(&path, puzzle.to_ron()).map_err(|e| e.to_string())?;
+  Ok(path)
+}
+
+fn editor_keys(
+  mut commands: Commands,
+  keyboard_input: Res<Input<KeyCode>>,
+  mut state: ResMut<EditorState>,
+) {
+  for &(key, name) in PIECE_KEYS.iter() {
+    if keyboard_input.just_pressed(key) {
+      state.queue.extend(block_idx_from_name(name));
+    }
+  }
+
+  if keyboard_input.just_pressed(KeyCode::Back) {
+    state.queue.pop();
+  } else if keyboard_input.just_pressed(KeyCode::G) {
+    state.goal = next_goal(state.goal);
+  } else if keyboard_input.just_pressed(KeyCode::C) {
+    for (_, entity) in state.cells.drain() {
+      commands.entity(entity).despawn();
+    }
+    state.queue.clear();
+  } else if keyboard_input.just_pressed(KeyCode::Return) {
+    state.message = match save_puzzle(&state) {
+      Ok(path) => format!("saved {}", path.display()),
+      Err(e) => format!("cannot save: {}", e),
+    };
+  }
+}
+
+// 読み込んだ盤面で塗り直す。キューと目標はそのまま
+fn editor_import(
+  mut commands: Commands,
+  materials: Res<Materials>,
+  mut state: ResMut<EditorState>,
+  mut imported_events: EventReader<BoardImported>,
+) {
+  let cells = match imported_events.iter().last() {
+    Some(event) => event.0.clone(),
+    None => return,
+  };
+  for (_, entity) in state.cells.drain() {
+    commands.entity(entity).despawn();
+  }
+  for cell in cells {
+    let entity = spawn_cell(&mut commands, &materials, cell.clone());
+    state.cells.insert(cell, entity);
+  }
+}
+
+fn editor_ui(state: Res<EditorState>, mut query: Query<&mut Text, With<EditorText>>) {
+  let queue: String = state.queue.iter().map(|&idx| block_name(idx)).collect();
+  let value = format!(
+    "EDITOR\nqueue: {}\ngoal: {}\n{}\n\n[LMB] paint  [RMB] erase\n[OZSLJTI] add piece  [BS] remove\n[G] goal  [C] clear  [Enter] save  [F4] paste board",
+    queue,
+    state.goal.describe(),
+    state.message
+  );
+
+  for mut text in query.iter_mut() {
+    text.sections[0].value = value.clone();
+  }
+}
@@ -0,0 +1,272 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::rotation::state_name;
+use crate::speed::TickRate;
+use crate::{
+  block_name, ActiveBlock, AppState, BlockStacked, GameClock, GameMode, GameReset, LinesCleared,
+  PieceFell, PieceMoved, PieceQueue, PieceRotated, PrimitiveBlock,
+};
+
+const LOG_DIR: &str = "logs";
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LogEvent {
+  Spawn {
+    piece: char,
+  },
+  Move {
+    dx: i32,
+    dy: i32,
+  },
+  // 自然落下。リプレイで操作中のミノを追うのに使う
+  Fall,
+  Rotate {
+    from: char,
+    to: char,
+    kick: (i32, i32),
+  },
+  Lock {
+    piece: char,
+    cells: Vec<(i32, i32)>,
+  },
+  Clear {
+    lines: u32,
+    kind: String,
+  },
+}
+
+pub fn clear_kind(lines: u32) -> &'static str {
+  match lines {
+    1 => "single",
+    2 => "double",
+    3 => "triple",
+    _ => "tetris",
+  }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct LogRecord {
+  // ゲーム開始からの秒数
+  pub time: f64,
+  #[serde(flatten)]
+  pub event: LogEvent,
+}
+
+// リプレイを見ながら付けたメモ
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Note {
+  pub time: f64,
+  pub text: String,
+}
+
+// 1ゲーム分の操作と結果。ゲーム終了時に JSON で書き出す
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct GameLog {
+  pub mode: String,
+  // 開始時刻 (UNIX 秒)
+  pub started_at: u64,
+  // ランダマイザーの種
+  #[serde(default)]
+  pub seed: u64,
+  // 遊んだときの tick の刻み。同じ刻みで進めないと同じ動きにならない
+  #[serde(default)]
+  pub tick_rate: TickRate,
+  #[serde(skip)]
+  start: f64,
+  pub events: Vec<LogRecord>,
+  // 時刻順
+  #[serde(default)]
+  pub notes: Vec<Note>,
+}
+
+impl GameLog {
+  fn restart(&mut self, mode: GameMode, seed: u64, tick_rate: TickRate, now: f64) {
+    *self = GameLog {
+      mode: format!("{:?}", mode),
+      started_at: SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs()),
+      seed,
+      tick_rate,
+      start: now,
+      events: vec![],
+      notes: vec![],
+    };
+  }
+
+  pub fn push(&mut self, now: f64, event: LogEvent) {
+    self.events.push(LogRecord {
+      time: now - self.start,
+      event,
+    });
+  }
+
+  // 最後に置いた n 個のミノと、それで消えた段数。古い順
+  pub fn recent_drops(&self, n: usize) -> Vec<(char, u32)> {
+    let mut drops: Vec<(char, u32)> = vec![];
+    for record in self.events.iter() {
+      match &record.event {
+        LogEvent::Lock { piece, .. } => drops.push((*piece, 0)),
+        // 消えたのは直前に置いたミノのおかげ
+        LogEvent::Clear { lines, .. } => {
+          if let Some(last) = drops.last_mut() {
+            last.1 = *lines;
+          }
+        }
+        _ => {}
+      }
+    }
+    let skip = drops.len().saturating_sub(n);
+    drops.split_off(skip)
+  }
+
+  // 同じ時刻のメモは付けた順に並べる
+  pub fn add_note(&mut self, time: f64, text: String) {
+    let i = self.notes.partition_point(|note| note.time <= time);
+    self.notes.insert(i, Note { time, text });
+  }
+
+  pub fn to_json(&self) -> serde_json::Result<String> {
+    serde_json::to_string_pretty(self)
+  }
+
+  // 書き出した中で一番新しいログ
+  pub fn load_latest() -> Option<GameLog> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(LOG_DIR)
+      .ok()?
+      .filter_map(|entry| entry.ok().map(|e| e.path()))
+      .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+      .collect();
+    // ファイル名が開始時刻から始まるので名前順で新しいものが最後に来る
+    paths.sort();
+    let path = paths.pop()?;
+    let json = fs::read_to_string(&path).ok()?;
+    match serde_json::from_str(&json) {
+      Ok(log) => Some(log),
+      Err(e) => {
+        warn!("failed to read {}: {}", path.display(), e);
+        None
+      }
+    }
+  }
+
+  // 同じゲームは同じファイルに書くので、メモを足したときは上書きになる
+  pub fn export(&self) -> io::Result<PathBuf> {
+    let json = self.to_json().map_err(io::Error::other)?;
+    fs::create_dir_all(LOG_DIR)?;
+    let path = PathBuf::from(LOG_DIR).join(format!("{}-{}.json", self.started_at, self.mode));
+    fs::write(&path, json)?;
+    Ok(path)
+  }
+}
+
+pub struct EventLogPlugin;
+
+impl Plugin for EventLogPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(GameLog::default())
+      .add_system_set(SystemSet::on_enter(AppState::Playing).with_system(log_start.system()))
+      .add_system_set(SystemSet::on_enter(AppState::Results).with_system(log_export.system()))
+      .add_system(log_record.system());
+  }
+}
+
+fn log_start(
+  clock: Res<GameClock>,
+  config: Res<Config>,
+  mode: Res<GameMode>,
+  piece_queue: Res<PieceQueue>,
+  mut log: ResMut<GameLog>,
+) {
+  log.restart(*mode, piece_queue.seed, config.tick_rate, clock.0);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn log_record(
+  clock: Res<GameClock>,
+  config: Res<Config>,
+  mode: Res<GameMode>,
+  piece_queue: Res<PieceQueue>,
+  active_block: Res<ActiveBlock>,
+  mut log: ResMut<GameLog>,
+  spawned_query: Query<(), Added<PrimitiveBlock>>,
+  mut moved_events: EventReader<PieceMoved>,
+  mut fell_events: EventReader<PieceFell>,
+  mut rotated_events: EventReader<PieceRotated>,
+  mut stacked_events: EventReader<BlockStacked>,
+  mut cleared_events: EventReader<LinesCleared>,
+  mut reset_events: EventReader<GameReset>,
+) {
+  let now = clock.0;
+  if reset_events.iter().count() > 0 {
+    log.restart(*mode, piece_queue.seed, config.tick_rate, now);
+  }
+
+  if spawned_query.iter().next().is_some() && active_block.block_idx > 0 {
+    log.push(
+      now,
+      LogEvent::Spawn {
+        piece: block_name(active_block.block_idx),
+      },
+    );
+  }
+  for event in moved_events.iter() {
+    log.push(
+      now,
+      LogEvent::Move {
+        dx: event.dx,
+        dy: event.dy,
+      },
+    );
+  }
+  for _ in fell_events.iter() {
+    log.push(now, LogEvent::Fall);
+  }
+  for event in rotated_events.iter() {
+    log.push(
+      now,
+      LogEvent::Rotate {
+        from: state_name(event.from),
+        to: state_name(event.to),
+        kick: event.kick,
+      },
+    );
+  }
+  for event in stacked_events.iter() {
+    log.push(
+      now,
+      LogEvent::Lock {
+        piece: block_name(event.block_idx),
+        cells: event.cells.iter().map(|p| (p.x, p.y)).collect(),
+      },
+    );
+  }
+  for event in cleared_events.iter() {
+    log.push(
+      now,
+      LogEvent::Clear {
+        lines: event.0,
+        kind: clear_kind(event.0).to_string(),
+      },
+    );
+  }
+}
+
+fn log_export(log: Res<GameLog>) {
+  if log.events.is_empty() {
+    return;
+  }
+  match log.export() {
+    Ok(path) => info!("wrote event log to {}", path.display()),
+    Err(e) => error!("failed to write event log: {}", e),
+  }
+}
@@ -0,0 +1,91 @@
+use crate::ai::{Bot, Lookahead, Rules, Strategy, Weights};
+use crate::server::Match;
+use crate::victory::VictoryCondition;
+
+// 1倍速でボットが 1 手動かすまでの秒数
+const ACTION_SECONDS: f32 = 0.05;
+// 一度に進める時間。速く回しても、重力や操作の数の制限はこの刻みで守る
+const STEP: f32 = 1. / 60.;
+// 結果だけ出すときに、決着が付かなければここで打ち切る
+pub const TIME_LIMIT: f32 = 600.;
+
+// ボットどうしの対戦。盤面の動かし方は対戦サーバーと同じ
+pub struct Exhibition {
+  game: Match,
+  bots: Vec<Bot>,
+  // 次の手までの秒数
+  wait: f32,
+  // STEP に満たずに持ち越した時間
+  pending: f32,
+}
+
+impl Exhibition {
+  pub fn new(weights: &[Weights], seed: u64, condition: Box<dyn VictoryCondition>) -> Self {
+    Exhibition {
+      game: Match::with_condition(weights.len(), seed, condition),
+      bots: weights.iter().map(|&w| Bot::new(w)).collect(),
+      wait: 0.,
+      pending: 0.,
+    }
+  }
+
+  // ボットがどこまで先を読むか
+  pub fn set_lookahead(&mut self, lookahead: Lookahead) {
+    for bot in self.bots.iter_mut() {
+      bot.lookahead = lookahead;
+    }
+  }
+
+  // 席の順に、方針の切り替え方を決める
+  pub fn set_rules(&mut self, rules: &[Rules]) {
+    for (bot, &rules) in self.bots.iter_mut().zip(rules) {
+      bot.rules = rules;
+    }
+  }
+
+  // それぞれのボットが今のミノで取っている方針
+  pub fn strategies(&self) -> Vec<Option<Strategy>> {
+    self.bots.iter().map(|bot| bot.strategy()).collect()
+  }
+
+  pub fn game(&self) -> &Match {
+    &self.game
+  }
+
+  // dt 秒ぶん進める。倍速で見るときは dt を倍にして渡す
+  pub fn advance(&mut self, dt: f32) {
+    self.pending += dt;
+    while self.pending >= STEP && !self.game.is_over() {
+      self.pending -= STEP;
+      self.step();
+    }
+  }
+
+  // 画面に出さずに決着まで進める。TIME_LIMIT までに付かなければ None
+  pub fn run(&mut self) -> Option<Vec<usize>> {
+    while !self.game.is_over() && self.game.elapsed() < TIME_LIMIT {
+      self.step();
+    }
+    Some(self.game.record().placings.clone()).filter(|p| !p.is_empty())
+  }
+
+  fn step(&mut self) {
+    self.wait -= STEP;
+    if self.wait <= 0. {
+      self.wait += ACTION_SECONDS;
+      let game = &mut self.game;
+      for (seat, bot) in self.bots.iter_mut().enumerate() {
+        // 見えている分より深く読むときは、その先のミノも並べて渡す
+        let mut state = game.state(seat).clone();
+        if bot.lookahead.depth > state.queue.len() {
+          state.queue = game.preview(seat, bot.lookahead.depth).into();
+        }
+        if let Some(action) = bot.next_action(&state, game.incoming(seat)) {
+          // 決着の後や脱落した席の操作は受け付けられないだけ
+          let _ = game.input(seat, action);
+        }
+      }
+    }
+    self.game.tick(STEP);
+  }
+}
@@ -0,0 +1,86 @@
+// 対戦の決着の演出。負けた盤面は灰色になってから上の段から順に崩れ、勝った盤面はきらめく。
+// 盤面のマスの色の付け方だけを決め、描くのは battle_render
+use crate::ARENA_HEIGHT;
+
+// (始まる秒, 場面) の台本どおりに進み、最後の場面で結果を出す
+const SCRIPT: [(f32, Scene); 3] = [
+  (0.0, Scene::Grey),
+  (0.4, Scene::Crumble),
+  (0.4 + ROW_SECONDS * ARENA_HEIGHT as f32, Scene::Results),
+];
+// 1 段崩れるごとの秒数
+const ROW_SECONDS: f32 = 0.06;
+// きらめきを付け替える間隔
+const SPARKLE_SECONDS: f32 = 0.08;
+
+pub const FINALE_SECONDS: f32 = SCRIPT[SCRIPT.len() - 1].0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Scene {
+  Grey,
+  Crumble,
+  Results,
+}
+
+// 決着してから elapsed 秒の場面
+pub fn scene(elapsed: f32) -> Scene {
+  SCRIPT
+    .iter()
+    .rev()
+    .find(|&&(start, _)| elapsed >= start)
+    .map_or(Scene::Grey, |&(_, scene)| scene)
+}
+
+// 盤面のマスの見せ方
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FinaleCell {
+  // いつもどおり
+  Keep,
+  Dim,
+  Gone,
+  Sparkle,
+}
+
+// top は負けた盤面のいちばん高いブロックの高さ。そこから下へ 1 段ずつ崩す
+pub fn finale_cell(
+  elapsed: f32,
+  winner: bool,
+  top: i32,
+  (x, y): (i32, i32),
+  filled: bool,
+) -> FinaleCell {
+  let scene = scene(elapsed);
+  if winner {
+    return if scene != Scene::Results && sparkles(elapsed, x, y, filled) {
+      FinaleCell::Sparkle
+    } else {
+      FinaleCell::Keep
+    };
+  }
+  if !filled {
+    return FinaleCell::Keep;
+  }
+  match scene {
+    Scene::Grey => FinaleCell::Dim,
+    Scene::Crumble => {
+      // 崩れ始めた瞬間にいちばん上の段が落ちる
+      let crumbled = ((elapsed - SCRIPT[1].0) / ROW_SECONDS) as i32 + 1;
+      if y >= top - crumbled {
+        FinaleCell::Gone
+      } else {
+        FinaleCell::Dim
+      }
+    }
+    Scene::Results => FinaleCell::Gone,
+  }
+}
+
+// マスと時刻から決まる擬似乱数で、ブロックのあるマスほどよく光る
+fn sparkles(elapsed: f32, x: i32, y: i32, filled: bool) -> bool {
+  let step = (elapsed / SPARKLE_SECONDS) as u32;
+  let hash = (x as u32).wrapping_mul(73_856_093)
+    ^ (y as u32).wrapping_mul(19_349_663)
+    ^ step.wrapping_mul(83_492_791);
+  let odds = if filled { 4 } else { 29 };
+  hash.is_multiple_of(odds)
+}
@@ -0,0 +1,141 @@
+use bevy::app::Events;
+use bevy::prelude::*;
+
+use crate::{
+  overlay_text, ActiveBlock, AppState, BlockStacked, Fonts, GameReset, LinesCleared, LockDelay,
+  PieceMoved, PieceRotated,
+};
+
+// 開発用のコマ送り。F9 で止めて、止めている間は F10 で 1 コマずつ進める
+// `cargo run --features frame-step` のときだけ入る
+const FREEZE_KEY: KeyCode = KeyCode::F9;
+const STEP_KEY: KeyCode = KeyCode::F10;
+
+#[derive(Default)]
+pub struct FrameStep {
+  // Stepping から Playing に 1 コマだけ戻している
+  pub advancing: bool,
+  // 止めてから進めたコマ数
+  pub ticks: u32,
+  // 同じフレームの中で状態が変わるたびにキーを拾い直さないように、最後に扱った時刻
+  handled_at: f64,
+}
+
+struct FrameStepText;
+
+pub struct FrameStepPlugin;
+
+impl Plugin for FrameStepPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(FrameStep::default())
+      .add_startup_system_to_stage(StartupStage::PostStartup, setup_frame_step.system())
+      .add_system(frame_step_input.system())
+      .add_system(frame_step_ui.system());
+  }
+}
+
+fn setup_frame_step(mut commands: Commands, fonts: Res<Fonts>) {
+  let mut text = overlay_text(&fonts);
+  text.style.position = Rect {
+    top: Val::Px(5.0),
+    right: Val::Px(5.0),
+    ..Default::default()
+  };
+  text.text.sections[0].style.color = Color::rgb(0.4, 1.0, 0.4);
+  commands.spawn_bundle(text).insert(FrameStepText);
+}
+
+// 進めるときは Playing に戻して、そのフレームの処理が 1 回通ったらまた止める
+fn frame_step_input(
+  time: Res<Time>,
+  keyboard_input: Res<Input<KeyCode>>,
+  mut step: ResMut<FrameStep>,
+  mut state: ResMut<State<AppState>>,
+) {
+  let now = time.seconds_since_startup();
+  let result = match state.current() {
+    AppState::Playing if step.advancing => {
+      step.advancing = false;
+      step.ticks += 1;
+      state.push(AppState::Stepping)
+    }
+    _ if step.handled_at == now => return,
+    AppState::Playing if keyboard_input.just_pressed(FREEZE_KEY) => {
+      step.handled_at = now;
+      step.ticks = 0;
+      state.push(AppState::Stepping)
+    }
+    AppState::Stepping if keyboard_input.just_pressed(FREEZE_KEY) => {
+      step.handled_at = now;
+      state.pop()
+    }
+    AppState::Stepping if keyboard_input.just_pressed(STEP_KEY) => {
+      step.handled_at = now;
+      step.advancing = true;
+      state.pop()
+    }
+    _ => return,
+  };
+  if let Err(e) = result {
+    warn!("frame step: {:?}", e);
+  }
+}
+
+fn frame_step_ui(
+  state: Res<State<AppState>>,
+  step: Res<FrameStep>,
+  active_block: Res<ActiveBlock>,
+  lock_delay: Res<LockDelay>,
+  stacked: Res<Events<BlockStacked>>,
+  cleared: Res<Events<LinesCleared>>,
+  moved: Res<Events<PieceMoved>>,
+  rotated: Res<Events<PieceRotated>>,
+  reset: Res<Events<GameReset>>,
+  // 止めたコマで送られたイベント。次のフレームには消えるので止めたときに覚えておく
+  mut pending: Local<String>,
+  mut query: Query<&mut Text, With<FrameStepText>>,
+) {
+  if step.is_changed() {
+    let counts = [
+      ("stacked", stacked.iter_current_update_events().count()),
+      ("cleared", cleared.iter_current_update_events().count()),
+      ("moved", moved.iter_current_update_events().count()),
+      ("rotated", rotated.iter_current_update_events().count()),
+      ("reset", reset.iter_current_update_events().count()),
+    ];
+    let names: Vec<String> = counts
+      .iter()
+      .filter(|&&(_, n)| n > 0)
+      .map(|(name, n)| format!("{} x{}", name, n))
+      .collect();
+    *pending = if names.is_empty() {
+      "none".to_string()
+    } else {
+      names.join(", ")
+    };
+  }
+  let value = if *state.current() == AppState::Stepping {
+    format!(
+      "FRAME STEP  tick {}\nevents: {}\npiece: {:?}  rotation {}\nlock delay: {:.3}s / {} resets{}\n[F10] step  [F9] resume",
+      step.ticks,
+      *pending,
+      active_block.phase,
+      active_block.rotation,
+      lock_delay.timer.elapsed_secs(),
+      lock_delay.resets,
+      if lock_delay.is_grounded() {
+        "  grounded"
+      } else {
+        ""
+      },
+    )
+  } else {
+    String::new()
+  };
+  for mut text in query.iter_mut() {
+    if text.sections[0].value != value {
+      text.sections[0].value = value.clone();
+    }
+  }
+}
@@ -0,0 +1,141 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::{Position, ARENA_WIDTH};
+
+// せり上がりの穴の開き方
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum HolePattern {
+  // 全部の行で同じ列
+  Same,
+  // n 行ごとに別の列へ移る
+  ShiftEvery(u32),
+  // 1行ごとにランダム
+  Random,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct GarbageConfig {
+  pub pattern: HolePattern,
+}
+
+impl Default for GarbageConfig {
+  fn default() -> Self {
+    Self {
+      pattern: HolePattern::ShiftEvery(4),
+    }
+  }
+}
+
+impl GarbageConfig {
+  pub const PATTERNS: [HolePattern; 4] = [
+    HolePattern::Same,
+    HolePattern::ShiftEvery(4),
+    HolePattern::ShiftEvery(2),
+    HolePattern::Random,
+  ];
+
+  pub fn describe(&self) -> String {
+    match self.pattern {
+      HolePattern::Same => "same hole".to_string(),
+      HolePattern::ShiftEvery(n) => format!("hole shifts every {} rows", n),
+      HolePattern::Random => "random holes".to_string(),
+    }
+  }
+}
+
+// せり上がった行のうち掘った行数 (received - left) が、消したライン数に占める割合
+pub fn efficiency(received: u32, left: u32, lines: u32) -> f32 {
+  if lines == 0 {
+    return 0.;
+  }
+  received.saturating_sub(left) as f32 / lines as f32
+}
+
+// 来ているせり上がり。攻撃はまず相殺に使い、ラインを消さずに積んだときに残りをまとめてせり上げる
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct GarbageQueue {
+  rows: u32,
+  // これまでにせり上げた段数の合計
+  received: u32,
+}
+
+impl GarbageQueue {
+  pub fn push(&mut self, rows: u32) {
+    self.rows = self.rows.saturating_add(rows);
+  }
+
+  // 相殺されずに次に積んだときせり上がる分
+  pub fn rows(&self) -> u32 {
+    self.rows
+  }
+
+  pub fn received(&self) -> u32 {
+    self.received
+  }
+
+  // 攻撃で来ている分を相殺し、相殺しきれずに相手へ送る分を返す
+  pub fn cancel(&mut self, attack: u32) -> u32 {
+    let cancel = attack.min(self.rows);
+    self.rows -= cancel;
+    attack - cancel
+  }
+
+  // ラインを消さずに積んだ。来ている分をすべて取り出す
+  pub fn take(&mut self) -> u32 {
+    let rows = std::mem::take(&mut self.rows);
+    self.received += rows;
+    rows
+  }
+}
+
+pub struct GarbageGenerator {
+  rng: StdRng,
+  pattern: HolePattern,
+  hole: i32,
+  rows: u32,
+}
+
+impl GarbageGenerator {
+  pub fn new(config: &GarbageConfig, seed: u64) -> Self {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let hole = rng.gen_range(0..ARENA_WIDTH as i32);
+    GarbageGenerator {
+      rng,
+      pattern: config.pattern,
+      hole,
+      rows: 0,
+    }
+  }
+
+  // 今の穴とは違う列
+  fn other_column(&mut self) -> i32 {
+    let offset = self.rng.gen_range(1..ARENA_WIDTH as i32);
+    (self.hole + offset) % ARENA_WIDTH as i32
+  }
+
+  pub fn next_hole(&mut self) -> i32 {
+    let is_first = self.rows == 0;
+    self.rows += 1;
+    if is_first {
+      return self.hole;
+    }
+    self.hole = match self.pattern {
+      HolePattern::Same => self.hole,
+      HolePattern::ShiftEvery(n) if (self.rows - 1).is_multiple_of(n.max(1)) => self.other_column(),
+      HolePattern::ShiftEvery(_) => self.hole,
+      HolePattern::Random => self.rng.gen_range(0..ARENA_WIDTH as i32),
+    };
+    self.hole
+  }
+
+  // 高さ y の行を穴1つ開けて埋めたマス
+  pub fn row(&mut self, y: i32) -> Vec<Position> {
+    let hole = self.next_hole();
+    (0..ARENA_WIDTH as i32)
+      .filter(|&x| x != hole)
+      .map(|x| Position { x, y })
+      .collect()
+  }
+}
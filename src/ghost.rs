@@ -0,0 +1,95 @@
+use bevy::prelude::*;
+
+use crate::board::Board;
+use crate::config::Config;
+use crate::layout::Layout;
+use crate::rules::Ruleset;
+use crate::{ActiveBlock, Materials, Position, PrimitiveBlock, Size, StackedBlock, ARENA_HEIGHT};
+
+// 今のミノをそのまま落としたときの位置を薄く表示する
+struct GhostCell;
+// 今のミノが掛かっている列の縦の帯
+struct ColumnGuide;
+
+// ミノが掛かっている列。左から順に 1 つずつ
+pub fn guide_columns(cells: &[Position]) -> Vec<i32> {
+  let mut columns: Vec<i32> = cells.iter().map(|p| p.x).collect();
+  columns.sort_unstable();
+  columns.dedup();
+  columns
+}
+
+pub struct GhostPlugin;
+
+impl Plugin for GhostPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .add_system(ghost_update.system())
+      .add_system(column_guide_update.system());
+  }
+}
+
+fn ghost_update(
+  mut commands: Commands,
+  materials: Res<Materials>,
+  ruleset: Res<Ruleset>,
+  active_block: Res<ActiveBlock>,
+  primitive_block_query: Query<&Position, With<PrimitiveBlock>>,
+  stacked_block_query: Query<&Position, With<StackedBlock>>,
+  ghost_query: Query<Entity, With<GhostCell>>,
+) {
+  for entity in ghost_query.iter() {
+    commands.entity(entity).despawn();
+  }
+  if !ruleset.ghost || !active_block.is_on() {
+    return;
+  }
+
+  let board = Board::from_cells(stacked_block_query.iter());
+  let cells: Vec<Position> = primitive_block_query.iter().cloned().collect();
+  for position in board.drop(&cells) {
+    commands
+      .spawn_bundle(SpriteBundle {
+        material: materials.ghost_block.clone(),
+        ..Default::default()
+      })
+      .insert(GhostCell)
+      .insert(position)
+      .insert(Size::square(0.8));
+  }
+}
+
+fn column_guide_update(
+  mut commands: Commands,
+  config: Res<Config>,
+  layout: Res<Layout>,
+  materials: Res<Materials>,
+  active_block: Res<ActiveBlock>,
+  primitive_block_query: Query<&Position, With<PrimitiveBlock>>,
+  guide_query: Query<Entity, With<ColumnGuide>>,
+) {
+  for entity in guide_query.iter() {
+    commands.entity(entity).despawn();
+  }
+  if !config.column_guides || !active_block.is_on() {
+    return;
+  }
+
+  let cells: Vec<Position> = primitive_block_query.iter().cloned().collect();
+  // 盤面の高さいっぱいの帯なので、Position ではなく真ん中の座標に直接置く
+  let middle = (ARENA_HEIGHT as f32 - 1.) / 2. * layout.cell;
+  for x in guide_columns(&cells) {
+    let bottom = layout.world_position(&Position { x, y: 0 });
+    commands
+      .spawn_bundle(SpriteBundle {
+        material: materials.column_guide.clone(),
+        transform: Transform::from_xyz(bottom.x, bottom.y + middle, 0.),
+        ..Default::default()
+      })
+      .insert(ColumnGuide)
+      .insert(Size {
+        width: 1.,
+        height: ARENA_HEIGHT as f32,
+      });
+  }
+}
@@ -0,0 +1,64 @@
+use bevy::prelude::*;
+
+use crate::ai::{best_placement, Weights};
+use crate::board::Board;
+use crate::{ActiveBlock, Materials, Position, PrimitiveBlock, Size, StackedBlock};
+
+const HINT_SECONDS: f32 = 1.5;
+
+struct HintCell {
+  timer: Timer,
+}
+
+// H キーで AI の最善手を少しの間表示する
+pub struct HintPlugin;
+
+impl Plugin for HintPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .add_system(hint_input.system())
+      .add_system(hint_fade.system());
+  }
+}
+
+fn hint_input(
+  mut commands: Commands,
+  keyboard_input: Res<Input<KeyCode>>,
+  materials: Res<Materials>,
+  active_block: Res<ActiveBlock>,
+  primitive_block_query: Query<&Position, With<PrimitiveBlock>>,
+  stacked_block_query: Query<&Position, With<StackedBlock>>,
+  hint_query: Query<Entity, With<HintCell>>,
+) {
+  if !keyboard_input.just_pressed(KeyCode::H) || !active_block.is_on() {
+    return;
+  }
+  for entity in hint_query.iter() {
+    commands.entity(entity).despawn();
+  }
+
+  let board = Board::from_cells(stacked_block_query.iter());
+  let cells: Vec<Position> = primitive_block_query.iter().cloned().collect();
+  if let Some(placement) = best_placement(&board, &cells, &Weights::default()) {
+    for position in placement {
+      commands
+        .spawn_bundle(SpriteBundle {
+          material: materials.hint_block.clone(),
+          ..Default::default()
+        })
+        .insert(HintCell {
+          timer: Timer::from_seconds(HINT_SECONDS, false),
+        })
+        .insert(position)
+        .insert(Size::square(0.8));
+    }
+  }
+}
+
+fn hint_fade(mut commands: Commands, time: Res<Time>, mut query: Query<(Entity, &mut HintCell)>) {
+  for (entity, mut hint) in query.iter_mut() {
+    if hint.timer.tick(time.delta()).just_finished() {
+      commands.entity(entity).despawn();
+    }
+  }
+}
@@ -0,0 +1,345 @@
+use bevy::prelude::*;
+
+use crate::ai::fits_cleanly;
+use crate::assist::AssistUsed;
+use crate::board::Board;
+use crate::config::Config;
+use crate::layout::{Anchor, Layout, Panel};
+use crate::profile::{any_just_pressed, Controls};
+use crate::rules::{Ruleset, MAX_PREVIEWS};
+use crate::sim::{GameState, Piece};
+use crate::{
+  overlay_text, spawn_cells, spawn_piece, trim_bounding_box, ActiveBlock, AppState, BlockStacked,
+  Fonts, GameReset, Materials, PieceQueue, Position, PrimitiveBlock, StackedBlock,
+  TETORIMINO_ARRAY,
+};
+
+// ホールド中のミノ。一度ホールドしたら次に積むまで使えない
+#[derive(Default)]
+pub struct Hold {
+  pub block_idx: Option<u32>,
+  pub used: bool,
+}
+
+// プレビューの枠は4x4マス
+const PREVIEW_CELLS: usize = 4;
+// プレビューの1マスの大きさ (横にパネルを並べるとき、重ねるとき)
+const SIDE_CELL: f32 = 12.0;
+const COLLAPSED_CELL: f32 = 8.0;
+
+// ラベルとプレビューの枠をまとめた NEXT / HOLD の欄
+struct PreviewPanel;
+// 欄の中の何番目の枠か。ホールドは 0 だけ
+struct PreviewBox {
+  panel: Panel,
+  slot: usize,
+}
+struct PreviewCell;
+// Display::None でも文字は描かれるので Visible で隠す
+struct PreviewLabel(Panel);
+
+pub struct HoldPlugin;
+
+impl Plugin for HoldPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(Hold::default())
+      .add_startup_system_to_stage(StartupStage::PostStartup, setup_queue_ui.system())
+      .add_system_set(
+        SystemSet::on_update(AppState::Playing)
+          .with_system(hold_input.system())
+          .with_system(auto_hold.system()),
+      )
+      .add_system(hold_reset.system())
+      .add_system(queue_ui.system());
+  }
+}
+
+// 位置は Layout に合わせて layout::anchor_panels が決める
+fn setup_queue_ui(mut commands: Commands, fonts: Res<Fonts>, materials: Res<Materials>) {
+  for &(panel, label, slots) in [
+    (Panel::Next, "next", MAX_PREVIEWS),
+    (Panel::Hold, "hold", 1),
+  ]
+  .iter()
+  {
+    let mut text = overlay_text(&fonts);
+    text.style = Style {
+      margin: Rect::all(Val::Px(2.0)),
+      ..Default::default()
+    };
+    text.text.sections[0].value = label.to_string();
+    commands
+      .spawn_bundle(NodeBundle {
+        style: Style {
+          position_type: PositionType::Absolute,
+          flex_direction: FlexDirection::ColumnReverse,
+          align_items: AlignItems::Center,
+          ..Default::default()
+        },
+        material: materials.transparent.clone(),
+        ..Default::default()
+      })
+      .insert(PreviewPanel)
+      .insert(Anchor(panel))
+      .with_children(|parent| {
+        parent.spawn_bundle(text).insert(PreviewLabel(panel));
+        for slot in 0..slots {
+          parent
+            .spawn_bundle(NodeBundle {
+              style: Style {
+                margin: Rect::all(Val::Px(2.0)),
+                ..Default::default()
+              },
+              material: materials.transparent.clone(),
+              ..Default::default()
+            })
+            .insert(PreviewBox { panel, slot })
+            .with_children(|parent| {
+              for _ in 0..4 {
+                parent
+                  .spawn_bundle(NodeBundle {
+                    style: Style {
+                      position_type: PositionType::Absolute,
+                      ..Default::default()
+                    },
+                    material: materials.transparent.clone(),
+                    ..Default::default()
+                  })
+                  .insert(PreviewCell);
+              }
+            });
+        }
+      });
+  }
+}
+
+fn hold_input(
+  mut commands: Commands,
+  keyboard_input: Res<Input<KeyCode>>,
+  controls: Res<Controls>,
+  materials: Res<Materials>,
+  ruleset: Res<Ruleset>,
+  mut hold: ResMut<Hold>,
+  mut active_block: ResMut<ActiveBlock>,
+  mut piece_queue: ResMut<PieceQueue>,
+  primitive_block_query: Query<Entity, With<PrimitiveBlock>>,
+) {
+  if !ruleset.hold || hold.used || !active_block.is_on() {
+    return;
+  }
+  if !any_just_pressed(&keyboard_input, &controls.bindings.hold) {
+    return;
+  }
+  swap_hold(
+    &mut commands,
+    &materials,
+    &mut hold,
+    &mut active_block,
+    &mut piece_queue,
+    &primitive_block_query,
+  );
+}
+
+fn swap_hold(
+  commands: &mut Commands,
+  materials: &Materials,
+  hold: &mut Hold,
+  active_block: &mut ActiveBlock,
+  piece_queue: &mut PieceQueue,
+  primitive_block_query: &Query<Entity, With<PrimitiveBlock>>,
+) {
+  // 初めてのホールドはキューから次のミノを出す
+  let next = match hold.block_idx.or_else(|| piece_queue.pop()) {
+    Some(idx) => idx,
+    None => return,
+  };
+  for entity in primitive_block_query.iter() {
+    commands.entity(entity).despawn();
+  }
+  hold.block_idx = Some(active_block.block_idx);
+  hold.used = true;
+  spawn_piece(commands, materials, active_block, next);
+}
+
+// 出てきたミノが穴を作らずに置けず、ホールドから出すミノなら置けるときは入れ替える
+fn auto_hold(
+  mut commands: Commands,
+  config: Res<Config>,
+  materials: Res<Materials>,
+  ruleset: Res<Ruleset>,
+  mut hold: ResMut<Hold>,
+  mut used: ResMut<AssistUsed>,
+  mut active_block: ResMut<ActiveBlock>,
+  mut piece_queue: ResMut<PieceQueue>,
+  spawned_query: Query<(), Added<PrimitiveBlock>>,
+  cell_query: Query<&Position, With<PrimitiveBlock>>,
+  stacked_block_query: Query<&Position, With<StackedBlock>>,
+  primitive_block_query: Query<Entity, With<PrimitiveBlock>>,
+) {
+  let ready = config.assist.auto_hold && ruleset.hold && !hold.used && active_block.is_on();
+  if !ready || spawned_query.iter().next().is_none() {
+    return;
+  }
+  let board = Board::from_cells(stacked_block_query.iter());
+  let state = |block_idx: u32, rotation: u8, cells: Vec<Position>| GameState {
+    board: board.clone(),
+    active: Some(Piece {
+      block_idx,
+      rotation,
+      cells,
+    }),
+    ..GameState::new(vec![])
+  };
+  let cells = cell_query.iter().cloned().collect();
+  if fits_cleanly(&state(active_block.block_idx, active_block.rotation, cells)) {
+    return;
+  }
+  // 入れ替えても穴ができるなら、そのまま置いてもらう
+  let other = match hold
+    .block_idx
+    .or_else(|| piece_queue.peek(1).first().copied())
+  {
+    Some(idx) => idx,
+    None => return,
+  };
+  if !fits_cleanly(&state(other, 0, spawn_cells(other))) {
+    return;
+  }
+  used.auto_hold = true;
+  swap_hold(
+    &mut commands,
+    &materials,
+    &mut hold,
+    &mut active_block,
+    &mut piece_queue,
+    &primitive_block_query,
+  );
+}
+
+fn hold_reset(
+  mut hold: ResMut<Hold>,
+  mut stacked_events: EventReader<BlockStacked>,
+  mut reset_events: EventReader<GameReset>,
+) {
+  if stacked_events.iter().count() > 0 {
+    hold.used = false;
+  }
+  if reset_events.iter().count() > 0 {
+    *hold = Hold::default();
+  }
+}
+
+// プレビューの枠の中でミノの各マスを置く位置 (枠の左下から)。
+// 行列の余白に関係なく、出現時の向きで枠の中央に来るようにする
+pub fn preview_offsets(block_idx: u32, cell: f32) -> Vec<Vec2> {
+  let block = trim_bounding_box(&TETORIMINO_ARRAY[(block_idx - 1) as usize]);
+  let (height, width) = block.dim();
+  let left = (PREVIEW_CELLS as f32 - width as f32) * cell / 2.;
+  let bottom = (PREVIEW_CELLS as f32 - height as f32) * cell / 2.;
+  block
+    .indexed_iter()
+    .filter(|&(_, &v)| v != 0)
+    .map(|((row, col), _)| {
+      Vec2::new(
+        left + col as f32 * cell,
+        bottom + (height - 1 - row) as f32 * cell,
+      )
+    })
+    .collect()
+}
+
+fn queue_ui(
+  ruleset: Res<Ruleset>,
+  layout: Res<Layout>,
+  hold: Res<Hold>,
+  materials: Res<Materials>,
+  mut piece_queue: ResMut<PieceQueue>,
+  mut panel_query: Query<(&Anchor, &mut Style), With<PreviewPanel>>,
+  mut label_query: Query<(&PreviewLabel, &mut Visible)>,
+  mut box_query: Query<(&PreviewBox, &mut Style, &Children), Without<PreviewPanel>>,
+  mut cell_query: Query<
+    (&mut Style, &mut Handle<ColorMaterial>),
+    (
+      With<PreviewCell>,
+      Without<PreviewBox>,
+      Without<PreviewPanel>,
+    ),
+  >,
+) {
+  // 横にパネルを並べるときは縦に、重ねるときは横に並べる
+  let (direction, cell) = if layout.side_panels {
+    (FlexDirection::ColumnReverse, SIDE_CELL)
+  } else {
+    (FlexDirection::Row, COLLAPSED_CELL)
+  };
+  let next = piece_queue.peek(ruleset.previews);
+
+  let shown = |panel: Panel| match panel {
+    Panel::Hold => ruleset.hold,
+    _ => ruleset.previews > 0,
+  };
+  for (label, mut visible) in label_query.iter_mut() {
+    if visible.is_visible != shown(label.0) {
+      visible.is_visible = shown(label.0);
+    }
+  }
+  for (anchor, mut style) in panel_query.iter_mut() {
+    let shown = shown(anchor.0);
+    let display = if shown { Display::Flex } else { Display::None };
+    if style.display != display || style.flex_direction != direction {
+      style.display = display;
+      style.flex_direction = direction;
+    }
+  }
+
+  for (preview, mut style, children) in box_query.iter_mut() {
+    let block_idx = match preview.panel {
+      Panel::Hold => hold.block_idx,
+      _ => next.get(preview.slot).copied(),
+    };
+    let display = match (preview.panel, preview.slot < ruleset.previews) {
+      (Panel::Hold, _) | (_, true) => Display::Flex,
+      _ => Display::None,
+    };
+    let size = Size::new(
+      Val::Px(cell * PREVIEW_CELLS as f32),
+      Val::Px(cell * PREVIEW_CELLS as f32),
+    );
+    if style.display != display || style.size != size {
+      style.display = display;
+      style.size = size;
+    }
+
+    let mut offsets = block_idx.map_or(vec![], |idx| preview_offsets(idx, cell));
+    // 盤面を反転しているときはプレビューのミノも反転する
+    if layout.mirrored {
+      for offset in offsets.iter_mut() {
+        offset.x = (PREVIEW_CELLS - 1) as f32 * cell - offset.x;
+      }
+    }
+    for (i, &child) in children.iter().enumerate() {
+      if let Ok((mut style, mut material)) = cell_query.get_mut(child) {
+        let (position, next_material) = match offsets.get(i) {
+          Some(offset) => (
+            Rect {
+              left: Val::Px(offset.x),
+              bottom: Val::Px(offset.y),
+              ..Default::default()
+            },
+            &materials.gray_block,
+          ),
+          None => (style.position, &materials.transparent),
+        };
+        let size = Size::new(Val::Px(cell - 1.), Val::Px(cell - 1.));
+        if style.position != position || style.size != size {
+          style.position = position;
+          style.size = size;
+        }
+        if *material != *next_material {
+          *material = next_material.clone();
+        }
+      }
+    }
+  }
+}
@@ -0,0 +1,214 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::attack::AttackTracker;
+use crate::layout::{Anchor, Panel};
+use crate::stats::Statistics;
+use crate::{
+  overlay_text, save, AppState, BlockStacked, Fonts, GameClock, GameReset, LinesCleared,
+};
+
+const SAVE_FILE: &str = "hud.ron";
+
+// 一時停止中に出すクイック設定のキー。pause はこれらで再開しない
+pub const HUD_KEYS: [KeyCode; 5] = [
+  KeyCode::Key1,
+  KeyCode::Key2,
+  KeyCode::Key3,
+  KeyCode::Key4,
+  KeyCode::Key5,
+];
+
+// プレイ中に出す数字ごとの表示。プロフィールごとに保存する
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HudSettings {
+  pub timer: bool,
+  pub score: bool,
+  pub combo: bool,
+  pub pps: bool,
+  pub pieces: bool,
+}
+
+impl Default for HudSettings {
+  fn default() -> Self {
+    HudSettings {
+      timer: true,
+      score: true,
+      combo: true,
+      pps: true,
+      pieces: true,
+    }
+  }
+}
+
+impl HudSettings {
+  pub const NAMES: [&'static str; 5] = ["timer", "score", "combo", "PPS", "pieces"];
+
+  // NAMES と同じ並び
+  pub fn flags(&self) -> [bool; 5] {
+    [self.timer, self.score, self.combo, self.pps, self.pieces]
+  }
+
+  pub fn toggle(&mut self, i: usize) {
+    match i {
+      0 => self.timer = !self.timer,
+      1 => self.score = !self.score,
+      2 => self.combo = !self.combo,
+      3 => self.pps = !self.pps,
+      4 => self.pieces = !self.pieces,
+      _ => {}
+    }
+  }
+}
+
+// 表示を選んだ数字だけを 1 行ずつ並べる
+pub fn hud_text(settings: &HudSettings, stats: &Statistics, combo: u32, elapsed: f64) -> String {
+  let pieces: u32 = stats.pieces.iter().sum();
+  let mut lines = vec![];
+  if settings.timer {
+    lines.push(format!("{}:{:05.2}", (elapsed / 60.) as u32, elapsed % 60.));
+  }
+  if settings.score {
+    lines.push(format!("score {}", stats.score()));
+  }
+  if settings.combo && combo > 1 {
+    lines.push(format!("{} combo", combo));
+  }
+  if settings.pps {
+    let pps = if elapsed > 0. {
+      pieces as f64 / elapsed
+    } else {
+      0.
+    };
+    lines.push(format!("{:.2} PPS", pps));
+  }
+  if settings.pieces {
+    lines.push(format!("{} pieces", pieces));
+  }
+  lines.join("\n")
+}
+
+// HUD のコンボ表示用。攻撃の計算は使わない
+#[derive(Default)]
+struct HudCombo(AttackTracker);
+
+struct HudText;
+struct HudMenuText;
+
+pub struct HudPlugin;
+
+impl Plugin for HudPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(save::load::<HudSettings>(SAVE_FILE))
+      .insert_resource(HudCombo::default())
+      .add_startup_system_to_stage(StartupStage::PostStartup, setup_hud.system())
+      .add_system_set(SystemSet::on_exit(AppState::Title).with_system(reload_hud.system()))
+      .add_system_set(SystemSet::on_enter(AppState::Playing).with_system(hud_start.system()))
+      .add_system_set(SystemSet::on_enter(AppState::Paused).with_system(setup_hud_menu.system()))
+      .add_system_set(SystemSet::on_update(AppState::Paused).with_system(hud_menu_input.system()))
+      .add_system_set(SystemSet::on_exit(AppState::Paused).with_system(close_hud_menu.system()))
+      .add_system(hud_record.system())
+      .add_system(hud_ui.system());
+  }
+}
+
+fn setup_hud(mut commands: Commands, fonts: Res<Fonts>) {
+  commands
+    .spawn_bundle(overlay_text(&fonts))
+    .insert(HudText)
+    .insert(Anchor(Panel::Hud));
+}
+
+// タイトル画面でプロフィールが変わったかもしれないので読み直す
+fn reload_hud(mut settings: ResMut<HudSettings>) {
+  *settings = save::load(SAVE_FILE);
+}
+
+fn hud_start(mut combo: ResMut<HudCombo>) {
+  *combo = HudCombo::default();
+}
+
+fn hud_record(
+  mut combo: ResMut<HudCombo>,
+  mut stacked_events: EventReader<BlockStacked>,
+  mut cleared_events: EventReader<LinesCleared>,
+  mut reset_events: EventReader<GameReset>,
+) {
+  if reset_events.iter().count() > 0 {
+    *combo = HudCombo::default();
+  }
+  for _ in stacked_events.iter() {
+    combo.0.record_lock();
+  }
+  for event in cleared_events.iter() {
+    combo.0.record_clear(event.0);
+  }
+}
+
+fn setup_hud_menu(mut commands: Commands, fonts: Res<Fonts>) {
+  let mut text = overlay_text(&fonts);
+  text.style.position = Rect {
+    top: Val::Px(320.0),
+    left: Val::Px(40.0),
+    ..Default::default()
+  };
+  commands.spawn_bundle(text).insert(HudMenuText);
+}
+
+fn hud_menu_input(
+  keyboard_input: Res<Input<KeyCode>>,
+  mut settings: ResMut<HudSettings>,
+  mut query: Query<&mut Text, With<HudMenuText>>,
+) {
+  for (i, key) in HUD_KEYS.iter().enumerate() {
+    if keyboard_input.just_pressed(*key) {
+      settings.toggle(i);
+      if let Err(e) = save::store(SAVE_FILE, &*settings) {
+        error!("failed to save hud settings: {}", e);
+      }
+    }
+  }
+  let mut value = "HUD".to_string();
+  for (i, (name, on)) in HudSettings::NAMES
+    .iter()
+    .zip(settings.flags().iter())
+    .enumerate()
+  {
+    let on_off = if *on { "on" } else { "off" };
+    value += &format!("\n[{}] {}: {}", i + 1, name, on_off);
+  }
+  for mut text in query.iter_mut() {
+    if text.sections[0].value != value {
+      text.sections[0].value = value.clone();
+    }
+  }
+}
+
+fn close_hud_menu(mut commands: Commands, query: Query<Entity, With<HudMenuText>>) {
+  for entity in query.iter() {
+    commands.entity(entity).despawn();
+  }
+}
+
+fn hud_ui(
+  clock: Res<GameClock>,
+  state: Res<State<AppState>>,
+  settings: Res<HudSettings>,
+  stats: Res<Statistics>,
+  combo: Res<HudCombo>,
+  mut query: Query<&mut Text, With<HudText>>,
+) {
+  let value = if matches!(state.current(), AppState::Playing | AppState::Paused) {
+    let elapsed = clock.0 - stats.start;
+    hud_text(&settings, &stats, combo.0.combo(), elapsed)
+  } else {
+    String::new()
+  };
+  for mut text in query.iter_mut() {
+    if text.sections[0].value != value {
+      text.sections[0].value = value.clone();
+    }
+  }
+}
@@ -0,0 +1,56 @@
+use bevy::prelude::*;
+
+use crate::config::Config;
+use crate::rotation::{KickTable, RotationSystem};
+use crate::rules::Ruleset;
+
+pub struct KickTablePlugin;
+
+impl Plugin for KickTablePlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .add_startup_system(load_kick_table.system())
+      .add_system(switch_kick_table.system());
+  }
+}
+
+// SRS のときだけ config.ron のキックテーブルで上書きする。読めなければ SRS のまま
+fn kick_table(rotation: RotationSystem, config: &Config) -> KickTable {
+  if rotation != RotationSystem::Srs {
+    return KickTable::for_system(rotation);
+  }
+  match config.kick_table.as_deref().map(KickTable::load) {
+    Some(Ok(table)) => table,
+    Some(Err(e)) => {
+      warn!("ignoring kick table {}", e);
+      KickTable::srs()
+    }
+    None => KickTable::srs(),
+  }
+}
+
+// ログが出せるようになってから読み込む
+fn load_kick_table(mut commands: Commands, config: Res<Config>, ruleset: Res<Ruleset>) {
+  let table = kick_table(ruleset.rotation, &config);
+  info!("rotation system: {}", table.name);
+  commands.insert_resource(table);
+}
+
+// ルールの回転法か config.ron の指定が変わったら作り直す
+fn switch_kick_table(
+  mut current: Local<Option<(RotationSystem, Option<String>)>>,
+  config: Res<Config>,
+  ruleset: Res<Ruleset>,
+  mut table: ResMut<KickTable>,
+) {
+  let next = (ruleset.rotation, config.kick_table.clone());
+  if current.as_ref() == Some(&next) {
+    return;
+  }
+  // 起動時は load_kick_table が読んだものを使う
+  if current.is_some() {
+    *table = kick_table(ruleset.rotation, &config);
+    info!("rotation system: {}", table.name);
+  }
+  *current = Some(next);
+}
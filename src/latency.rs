@@ -0,0 +1,221 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use bevy::app::Events;
+use bevy::input::keyboard::KeyboardInput;
+use bevy::input::ElementState;
+use bevy::prelude::*;
+use bevy::render::RenderStage;
+
+use crate::{overlay_text, BlockStacked, Fonts, PieceMoved, PieceRotated};
+
+// Ctrl+Shift+L で、キーを押してから盤面が変わるまでと、その絵を描き終えるまでの時間を測る。
+// システムの順番や描画のバッファの設定を詰める開発用で、どこにも案内しない
+const TOGGLE_KEY: KeyCode = KeyCode::L;
+// 覚えておく回数
+const MAX_SAMPLES: usize = 240;
+// これより待っても盤面が変わらなければ、動けなかった操作として数えない
+const MAX_WAIT: Duration = Duration::from_millis(250);
+// 切り替えに使うので、押しても測り始めない
+const MODIFIERS: [KeyCode; 4] = [
+  KeyCode::LControl,
+  KeyCode::RControl,
+  KeyCode::LShift,
+  KeyCode::RShift,
+];
+
+// 直近の測った時間 (ミリ秒)
+#[derive(Default)]
+pub struct LatencySamples {
+  samples: VecDeque<f32>,
+}
+
+// (最小, 平均, 95 パーセンタイル, 最大)
+pub type LatencySummary = (f32, f32, f32, f32);
+
+impl LatencySamples {
+  pub fn push(&mut self, ms: f32) {
+    if self.samples.len() == MAX_SAMPLES {
+      self.samples.pop_front();
+    }
+    self.samples.push_back(ms);
+  }
+
+  pub fn len(&self) -> usize {
+    self.samples.len()
+  }
+
+  pub fn summary(&self) -> Option<LatencySummary> {
+    if self.samples.is_empty() {
+      return None;
+    }
+    let mut sorted: Vec<f32> = self.samples.iter().copied().collect();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let len = sorted.len();
+    let average = sorted.iter().sum::<f32>() / len as f32;
+    let p95 = sorted[((len - 1) as f32 * 0.95).round() as usize];
+    Some((sorted[0], average, p95, sorted[len - 1]))
+  }
+}
+
+fn summary_line(name: &str, samples: &LatencySamples) -> String {
+  match samples.summary() {
+    Some((min, average, p95, max)) => format!(
+      "{:<8} min {:5.1}  avg {:5.1}  p95 {:5.1}  max {:5.1} ms  ({})",
+      name,
+      min,
+      average,
+      p95,
+      max,
+      samples.len()
+    ),
+    None => format!("{:<8} -", name),
+  }
+}
+
+// 盤面と描画の 2 行と、盤面が変わらなかったキーの数
+pub fn latency_text(board: &LatencySamples, present: &LatencySamples, missed: u32) -> String {
+  format!(
+    "INPUT LATENCY\n{}\n{}\nno change {}",
+    summary_line("board", board),
+    summary_line("present", present),
+    missed
+  )
+}
+
+#[derive(Default)]
+struct LatencyProbe {
+  enabled: bool,
+  // 盤面が変わるのを待っているキーを受け取った時刻
+  pressed: Option<Instant>,
+  // 盤面は変わって、描き終えるのを待っているキーの時刻
+  changed: Option<Instant>,
+  board: LatencySamples,
+  present: LatencySamples,
+  missed: u32,
+}
+
+struct LatencyText;
+
+pub struct LatencyPlugin;
+
+impl Plugin for LatencyPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(LatencyProbe::default())
+      .add_startup_system_to_stage(StartupStage::PostStartup, setup_latency.system())
+      .add_system_to_stage(CoreStage::First, mark_key_press.system())
+      .add_system_to_stage(CoreStage::PostUpdate, mark_board_change.system())
+      .add_system_to_stage(RenderStage::PostRender, mark_present.system())
+      .add_system(latency_toggle.system())
+      .add_system(latency_ui.system());
+  }
+}
+
+fn setup_latency(mut commands: Commands, fonts: Res<Fonts>) {
+  let mut text = overlay_text(&fonts);
+  text.style.position = Rect {
+    bottom: Val::Px(80.0),
+    left: Val::Px(5.0),
+    ..Default::default()
+  };
+  commands.spawn_bundle(text).insert(LatencyText);
+}
+
+fn latency_toggle(keyboard_input: Res<Input<KeyCode>>, mut probe: ResMut<LatencyProbe>) {
+  let pressed = |a, b| keyboard_input.pressed(a) || keyboard_input.pressed(b);
+  let modifiers =
+    pressed(KeyCode::LControl, KeyCode::RControl) && pressed(KeyCode::LShift, KeyCode::RShift);
+  if !modifiers || !keyboard_input.just_pressed(TOGGLE_KEY) {
+    return;
+  }
+  if probe.enabled {
+    info!(
+      "{}",
+      latency_text(&probe.board, &probe.present, probe.missed)
+    );
+    *probe = LatencyProbe::default();
+  } else {
+    *probe = LatencyProbe {
+      enabled: true,
+      ..Default::default()
+    };
+  }
+}
+
+// winit は受け取った時刻を付けないので、フレームのいちばん初めに見た時刻を受け取った時刻とする
+fn mark_key_press(
+  mut keyboard_events: EventReader<KeyboardInput>,
+  mut probe: ResMut<LatencyProbe>,
+) {
+  let pressed = keyboard_events.iter().any(|event| {
+    event.state == ElementState::Pressed
+      && event
+        .key_code
+        .is_some_and(|key| !MODIFIERS.contains(&key) && key != TOGGLE_KEY)
+  });
+  if !probe.enabled || !pressed {
+    return;
+  }
+  let now = Instant::now();
+  match probe.pressed {
+    Some(at) if now - at <= MAX_WAIT => {}
+    Some(_) => {
+      probe.missed += 1;
+      probe.pressed = Some(now);
+    }
+    None => probe.pressed = Some(now),
+  }
+}
+
+// 自然落下は操作ではないので、動かした・回した・置いたときだけを盤面の変化とする
+fn mark_board_change(
+  moved_events: Res<Events<PieceMoved>>,
+  rotated_events: Res<Events<PieceRotated>>,
+  stacked_events: Res<Events<BlockStacked>>,
+  mut probe: ResMut<LatencyProbe>,
+) {
+  let at = match probe.pressed {
+    Some(at) => at,
+    None => return,
+  };
+  let changed = moved_events.iter_current_update_events().count() > 0
+    || rotated_events.iter_current_update_events().count() > 0
+    || stacked_events.iter_current_update_events().count() > 0;
+  if !changed {
+    return;
+  }
+  let elapsed = at.elapsed();
+  probe.pressed = None;
+  if elapsed > MAX_WAIT {
+    probe.missed += 1;
+    return;
+  }
+  probe.board.push(elapsed.as_secs_f32() * 1000.);
+  probe.changed = Some(at);
+}
+
+// 描画の後。スワップチェーンに渡し終えた時刻で、画面に映る時刻はモニタしだい
+fn mark_present(mut probe: ResMut<LatencyProbe>) {
+  // 待っていないフレームで変更を知らせない
+  if let Some(at) = probe.changed {
+    probe.present.push(at.elapsed().as_secs_f32() * 1000.);
+    probe.changed = None;
+  }
+}
+
+fn latency_ui(probe: Res<LatencyProbe>, mut query: Query<&mut Text, With<LatencyText>>) {
+  if !probe.is_changed() {
+    return;
+  }
+  let value = if probe.enabled {
+    latency_text(&probe.board, &probe.present, probe.missed)
+  } else {
+    String::new()
+  };
+  for mut text in query.iter_mut() {
+    if text.sections[0].value != value {
+      text.sections[0].value = value.clone();
+    }
+  }
+}
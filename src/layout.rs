@@ -0,0 +1,301 @@
+use bevy::prelude::*;
+use bevy::render::camera::Camera;
+use bevy::render::render_graph::base::camera::CAMERA_2D;
+
+use crate::config::{Config, ViewSettings};
+use crate::rules::Ruleset;
+use crate::{cursor_to_position, AppState, Position, ARENA_HEIGHT, ARENA_WIDTH};
+
+// 横に並べるパネル1つ分の幅
+pub const PANEL_WIDTH: f32 = 140.0;
+const MARGIN: f32 = 10.0;
+// 狭いときに画面の端から離す距離
+const EDGE: f32 = 5.0;
+// 一時停止中に盤面を拡大・縮小、移動する刻みと範囲
+const ZOOM_STEP: f32 = 0.1;
+const MIN_ZOOM: f32 = 0.5;
+const MAX_ZOOM: f32 = 2.0;
+const PAN_STEP: f32 = 20.0;
+// 一時停止中の表示の調整に使うキー。pause はこれらで再開しない
+pub const VIEW_KEYS: [KeyCode; 7] = [
+  KeyCode::Minus,
+  KeyCode::Equals,
+  KeyCode::Left,
+  KeyCode::Right,
+  KeyCode::Up,
+  KeyCode::Down,
+  KeyCode::Key0,
+];
+
+// 盤面の横に置く UI
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Panel {
+  Hold,
+  Next,
+  Stats,
+  // 最近置いたミノの並び
+  History,
+  // タイマーや PPS などの数字
+  Hud,
+}
+
+pub struct Anchor(pub Panel);
+
+// ウィンドウの大きさから決めた盤面の位置とマスの大きさ
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Layout {
+  pub width: f32,
+  pub height: f32,
+  pub cell: f32,
+  // ウィンドウ左下からの盤面の左下の位置
+  pub board_left: f32,
+  pub board_bottom: f32,
+  // 盤面の左右にパネルを並べる余裕があるか
+  pub side_panels: bool,
+  // 盤面を左右反転して描く
+  pub mirrored: bool,
+  // カメラで掛ける拡大率と、ウィンドウ中央からのずらし量。マスのワールド座標はこれに関係なく決まる
+  pub zoom: f32,
+  pub offset: Vec2,
+}
+
+impl Default for Layout {
+  fn default() -> Self {
+    Layout::compute(400., 800.)
+  }
+}
+
+impl Layout {
+  pub fn compute(width: f32, height: f32) -> Self {
+    let cell_by_height = height / ARENA_HEIGHT as f32;
+    let side_panels = width >= cell_by_height * ARENA_WIDTH as f32 + 2. * PANEL_WIDTH;
+    // 細長いウィンドウでは幅に合わせて縮め、パネルは盤面の上に重ねる
+    let cell = if side_panels {
+      cell_by_height
+    } else {
+      cell_by_height.min(width / ARENA_WIDTH as f32)
+    };
+    Layout {
+      width,
+      height,
+      cell,
+      board_left: ((width - cell * ARENA_WIDTH as f32) / 2.).floor(),
+      board_bottom: ((height - cell * ARENA_HEIGHT as f32) / 2.).floor(),
+      side_panels,
+      mirrored: false,
+      zoom: 1.,
+      offset: Vec2::ZERO,
+    }
+  }
+
+  fn center(&self) -> Vec2 {
+    Vec2::new(self.width, self.height) / 2.
+  }
+
+  // 拡大とずらしを掛ける前のウィンドウ座標 (左下原点) から、画面に映る位置へ
+  pub fn zoomed(&self, p: Vec2) -> Vec2 {
+    (p - self.center()) * self.zoom + self.center() + self.offset
+  }
+
+  pub fn unzoomed(&self, p: Vec2) -> Vec2 {
+    (p - self.center() - self.offset) / self.zoom + self.center()
+  }
+
+  // 描くときの列。反転しているときは右端から数える
+  fn screen_x(&self, x: i32) -> i32 {
+    if self.mirrored {
+      ARENA_WIDTH as i32 - 1 - x
+    } else {
+      x
+    }
+  }
+
+  fn board_right(&self) -> f32 {
+    self.board_left + self.cell * ARENA_WIDTH as f32
+  }
+
+  // マスの中心のワールド座標 (ウィンドウ中央が原点)
+  pub fn world_position(&self, pos: &Position) -> Vec2 {
+    Vec2::new(
+      self.board_left + (self.screen_x(pos.x) as f32 + 0.5) * self.cell - self.width / 2.,
+      self.board_bottom + (pos.y as f32 + 0.5) * self.cell - self.height / 2.,
+    )
+  }
+
+  // ウィンドウ座標 (左下原点) のカーソルの下にあるマス
+  pub fn cell_at(&self, cursor: Vec2) -> Option<Position> {
+    cursor_to_position(
+      self.unzoomed(cursor) - Vec2::new(self.board_left, self.board_bottom),
+      self.cell * ARENA_WIDTH as f32,
+      self.cell * ARENA_HEIGHT as f32,
+    )
+    .map(|pos| Position {
+      x: self.screen_x(pos.x),
+      y: pos.y,
+    })
+  }
+
+  pub fn panel_position(&self, panel: Panel) -> Rect<Val> {
+    let (top, bottom, left, right) = match (panel, self.side_panels) {
+      (Panel::Hold, true) => (
+        Some(120.0),
+        None,
+        Some(self.board_left - PANEL_WIDTH + MARGIN),
+        None,
+      ),
+      (Panel::Next, true) => (Some(MARGIN), None, Some(self.board_right() + MARGIN), None),
+      (Panel::Stats, true) => (None, Some(MARGIN), Some(self.board_right() + MARGIN), None),
+      (Panel::History, true) => (
+        None,
+        Some(MARGIN),
+        None,
+        Some(self.width - self.board_left + MARGIN),
+      ),
+      (Panel::Hud, true) => (
+        Some(260.0),
+        None,
+        Some(self.board_left - PANEL_WIDTH + MARGIN),
+        None,
+      ),
+      (Panel::Hold, false) => (None, Some(EDGE), Some(EDGE), None),
+      (Panel::Next, false) => (None, Some(EDGE), None, Some(EDGE)),
+      (Panel::Stats, false) => (Some(EDGE), None, None, Some(EDGE)),
+      (Panel::Hud, false) => (Some(120.0), None, Some(EDGE), None),
+      (Panel::History, false) => (
+        None,
+        Some(EDGE),
+        Some(((self.width - PANEL_WIDTH) / 2.).floor()),
+        None,
+      ),
+    };
+    // パネルも盤面と一緒に動かす。大きさはそのまま
+    let screen = |x: f32, y: f32| self.zoomed(Vec2::new(x, y));
+    let px = |v: Option<f32>| v.map_or(Val::Undefined, Val::Px);
+    Rect {
+      top: px(top.map(|v| self.height - screen(0., self.height - v).y)),
+      bottom: px(bottom.map(|v| screen(0., v).y)),
+      left: px(left.map(|v| screen(v, 0.).x)),
+      right: px(right.map(|v| self.width - screen(self.width - v, 0.).x)),
+    }
+  }
+
+  // 盤面を映すカメラ。ずらした分だけ逆に動かし、拡大した分だけ狭く映す
+  pub fn camera_transform(&self, camera: &Transform) -> Transform {
+    Transform {
+      translation: (-self.offset / self.zoom).extend(camera.translation.z),
+      scale: Vec3::new(1. / self.zoom, 1. / self.zoom, 1.),
+      ..*camera
+    }
+  }
+}
+
+// 拡大率を刻みに揃えて範囲に収める
+pub fn step_zoom(zoom: f32, steps: i32) -> f32 {
+  let zoom = ((zoom / ZOOM_STEP).round() + steps as f32) * ZOOM_STEP;
+  zoom.clamp(MIN_ZOOM, MAX_ZOOM)
+}
+
+pub struct LayoutPlugin;
+
+impl Plugin for LayoutPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(Layout::default())
+      .add_system_to_stage(CoreStage::PreUpdate, update_layout.system())
+      .add_system(mirror_layout.system())
+      .add_system(view_layout.system())
+      .add_system_set(SystemSet::on_update(AppState::Paused).with_system(view_input.system()))
+      .add_system(move_camera.system())
+      .add_system(anchor_panels.system());
+  }
+}
+
+fn update_layout(windows: Res<Windows>, mut layout: ResMut<Layout>) {
+  let window = match windows.get_primary() {
+    Some(window) => window,
+    None => return,
+  };
+  let next = Layout {
+    mirrored: layout.mirrored,
+    zoom: layout.zoom,
+    offset: layout.offset,
+    ..Layout::compute(window.width(), window.height())
+  };
+  if *layout != next {
+    *layout = next;
+  }
+}
+
+fn mirror_layout(ruleset: Res<Ruleset>, mut layout: ResMut<Layout>) {
+  if ruleset.is_changed() && layout.mirrored != ruleset.mirror {
+    layout.mirrored = ruleset.mirror;
+  }
+}
+
+fn view_layout(config: Res<Config>, mut layout: ResMut<Layout>) {
+  let view = &config.view;
+  let offset = Vec2::new(view.offset.0, view.offset.1);
+  if config.is_changed() && (layout.zoom != view.zoom || layout.offset != offset) {
+    layout.zoom = view.zoom;
+    layout.offset = offset;
+  }
+}
+
+// 一時停止中に [-] [=] で拡大・縮小、矢印キーで盤面を動かす。[0] で元に戻す
+fn view_input(
+  keyboard_input: Res<Input<KeyCode>>,
+  layout: Res<Layout>,
+  mut config: ResMut<Config>,
+) {
+  let mut view = config.view.clone();
+  if keyboard_input.just_pressed(KeyCode::Minus) {
+    view.zoom = step_zoom(view.zoom, -1);
+  }
+  if keyboard_input.just_pressed(KeyCode::Equals) {
+    view.zoom = step_zoom(view.zoom, 1);
+  }
+  let (dx, dy) = [
+    (KeyCode::Left, (-PAN_STEP, 0.)),
+    (KeyCode::Right, (PAN_STEP, 0.)),
+    (KeyCode::Up, (0., PAN_STEP)),
+    (KeyCode::Down, (0., -PAN_STEP)),
+  ]
+  .iter()
+  .filter(|(key, _)| keyboard_input.just_pressed(*key))
+  .fold((0., 0.), |(x, y), (_, (dx, dy))| (x + dx, y + dy));
+  // 盤面の中心がウィンドウの外に出ない範囲でずらす
+  let (half_w, half_h) = (layout.width / 2., layout.height / 2.);
+  view.offset = (
+    (view.offset.0 + dx).clamp(-half_w, half_w),
+    (view.offset.1 + dy).clamp(-half_h, half_h),
+  );
+  if keyboard_input.just_pressed(KeyCode::Key0) {
+    view = ViewSettings::default();
+  }
+  if view != config.view {
+    config.view = view;
+    config.store();
+  }
+}
+
+fn move_camera(layout: Res<Layout>, mut query: Query<(&Camera, &mut Transform)>) {
+  if !layout.is_changed() {
+    return;
+  }
+  for (camera, mut transform) in query.iter_mut() {
+    if camera.name.as_deref() == Some(CAMERA_2D) {
+      *transform = layout.camera_transform(&transform);
+    }
+  }
+}
+
+fn anchor_panels(
+  layout: Res<Layout>,
+  mut query: Query<(&Anchor, &mut Style, ChangeTrackers<Anchor>)>,
+) {
+  for (anchor, mut style, tracker) in query.iter_mut() {
+    if layout.is_changed() || tracker.is_changed() {
+      style.position = layout.panel_position(anchor.0);
+    }
+  }
+}
@@ -0,0 +1,72 @@
+// 画面や入力を持たない、盤面と対戦の規則だけの部分。ゲーム本体と対戦サーバーで共有する
+#[macro_use]
+extern crate lazy_static;
+
+pub mod ai;
+pub mod attack;
+pub mod board;
+pub mod connection;
+pub mod exhibition;
+pub mod garbage;
+pub mod protocol;
+pub mod randomizer;
+pub mod rotation;
+pub mod server;
+pub mod sim;
+pub mod train;
+pub mod victory;
+pub mod wire;
+
+use std::collections::HashMap;
+
+pub const ARENA_WIDTH: u32 = 10;
+pub const ARENA_HEIGHT: u32 = 20;
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct Position {
+  pub x: i32,
+  pub y: i32,
+}
+
+// BLOCKMAP のキー順に対応するミノの名前
+pub const BLOCK_NAMES: [char; 7] = ['O', 'Z', 'S', 'L', 'J', 'T', 'I'];
+
+pub fn block_idx_from_name(name: char) -> Option<u32> {
+  BLOCK_NAMES
+    .iter()
+    .position(|&c| c == name.to_ascii_uppercase())
+    .map(|i| i as u32 + 1)
+}
+
+pub fn block_name(block_idx: u32) -> char {
+  BLOCK_NAMES[(block_idx - 1) as usize]
+}
+
+lazy_static! {
+  pub static ref BLOCKMAP: HashMap<u32, Vec<Position>> = {
+    let mut m = HashMap::new();
+    m.insert(1, vec![Position { x: 0, y: 0 }, Position { x: 1, y: 0 }, Position { x: 0, y: 1 }, Position { x: 1, y: 1 }]); // square
+    m.insert(2, vec![Position { x: -1, y: 1 }, Position { x: 0, y: 1 }, Position { x: 0, y: 0 }, Position { x: 1, y: 0 }]); // S字
+    m.insert(3, vec![Position { x: -1, y: 0 }, Position { x: 0, y: 0 }, Position { x: 0, y: 1 }, Position { x: 1, y: 1 }]); // 逆S字
+    m.insert(4, vec![Position { x: 1, y: 0 }, Position { x: 0, y: 0 }, Position { x: 0, y: 1 }, Position { x: 0, y: 2 }]); // L字
+    m.insert(5, vec![Position { x: -1, y: 0 }, Position { x: 0, y: 0 }, Position { x: 0, y: 1 }, Position { x: 0, y: 2 }]); // 逆L字
+    m.insert(6, vec![Position { x: -1, y: 0 }, Position { x: 0, y: 0 }, Position { x: 1, y: 0 }, Position { x: 0, y: 1 }]); // T字
+    m.insert(7, vec![Position { x: 0, y: 0 }, Position { x: 0, y: 1 }, Position { x: 0, y: 2 }, Position { x: 0, y: 3 }]); // I字
+    m
+  };
+}
+
+// 出現位置でのミノのマス
+pub fn spawn_cells(idx: u32) -> Vec<Position> {
+  let base_position_x = 3;
+  let base_position_y = (ARENA_HEIGHT - 1) as i32;
+  BLOCKMAP.get(&idx).map_or(vec![], |positions| {
+    positions
+      .iter()
+      .map(|position| Position {
+        x: position.x + base_position_x,
+        y: position.y + base_position_y,
+      })
+      .collect()
+  })
+}
@@ -0,0 +1,49 @@
+use bevy::prelude::*;
+
+use crate::{LockDelay, Materials};
+
+// 接地中に固定までの残り時間を画面上端の細いバーで表示する
+struct LockBar;
+
+pub struct LockBarPlugin;
+
+impl Plugin for LockBarPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .add_startup_system_to_stage(StartupStage::PostStartup, setup_lock_bar.system())
+      .add_system(lock_bar_update.system());
+  }
+}
+
+fn setup_lock_bar(mut commands: Commands, materials: Res<Materials>) {
+  commands
+    .spawn_bundle(NodeBundle {
+      style: Style {
+        position_type: PositionType::Absolute,
+        position: Rect {
+          top: Val::Px(0.0),
+          left: Val::Px(0.0),
+          ..Default::default()
+        },
+        size: Size::new(Val::Percent(0.0), Val::Px(4.0)),
+        ..Default::default()
+      },
+      material: materials.lock_bar.clone(),
+      ..Default::default()
+    })
+    .insert(LockBar);
+}
+
+fn lock_bar_update(lock_delay: Res<LockDelay>, mut query: Query<&mut Style, With<LockBar>>) {
+  if !lock_delay.is_changed() {
+    return;
+  }
+  let width = if lock_delay.is_grounded() {
+    lock_delay.remaining() * 100.
+  } else {
+    0.
+  };
+  for mut style in query.iter_mut() {
+    style.size.width = Val::Percent(width);
+  }
+}
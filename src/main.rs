@@ -1,59 +1,397 @@
+// bevy の system は引数・Query の型が長くなりがちなので許容する
+#![allow(clippy::too_many_arguments, clippy::type_complexity)]
+
+mod accessibility;
+mod achievements;
+mod analysis;
+mod announcer;
+mod assist;
+mod audio;
+mod autosave;
+mod battle;
+mod bests;
+mod caster;
+mod chaos;
+mod clipboard;
+mod columns;
+mod config;
+mod coop;
+mod cues;
+mod deals;
+mod detach;
+mod dig;
+mod editor;
+mod eventlog;
+mod finale;
+#[cfg(feature = "frame-step")]
+mod framestep;
+mod ghost;
+mod hint;
+mod hold;
+mod hud;
+mod kicks;
+mod latency;
+mod layout;
+mod lockbar;
+#[cfg(test)]
 mod main_test;
+mod master;
+mod menu;
+mod mission;
+mod momentum;
+mod online;
+mod party;
+mod pause;
+mod practice;
+mod profile;
+mod puzzle;
+mod replay;
+mod restart;
+mod results;
+mod rules;
+mod rumble;
+mod save;
+mod share;
+mod skins;
+mod snapshot;
+mod spectate;
+mod speed;
+mod splits;
+mod sprint;
+mod stats;
+mod summary;
+mod survival;
+mod ticker;
+mod toast;
+mod validate;
+mod versus;
 
 #[macro_use]
 extern crate lazy_static;
 
-use std::collections::HashMap;
+use std::collections::VecDeque;
 use std::hash::Hash;
+use std::time::Duration;
 
-use bevy::core::FixedTimestep;
+use bevy::ecs::schedule::ShouldRun;
 use bevy::prelude::*;
 use ndarray::prelude::*;
 use rand::prelude::random;
+use tetris::{
+  ai, attack, board, connection, exhibition, garbage, protocol, randomizer, rotation, sim, victory,
+};
+use tetris::{
+  block_idx_from_name, block_name, spawn_cells, Position, ARENA_HEIGHT, ARENA_WIDTH, BLOCKMAP,
+  BLOCK_NAMES,
+};
 
-const ARENA_WIDTH: u32 = 10;
-const ARENA_HEIGHT: u32 = 20;
-const BLOCK_RESPAWN_DELAY: f64 = 1.;
+const GRAVITY_STEP: f64 = 0.5;
+const LOCK_DELAY: f32 = 0.5;
+// 接地中に動かして猶予を延ばせる回数
+const LOCK_RESETS: u32 = 15;
+// 固定したミノを白く光らせる長さ
+const LOCK_HIGHLIGHT: f32 = 0.15;
+// せり上がりで積んだブロックを押し上げて見せる長さ
+const GARBAGE_RISE: f32 = 0.1;
 
 // region: Resources
 struct Materials {
   gray_block: Handle<ColorMaterial>,
   white_block: Handle<ColorMaterial>,
+  ghost_block: Handle<ColorMaterial>,
+  hint_block: Handle<ColorMaterial>,
+  lock_bar: Handle<ColorMaterial>,
+  panel: Handle<ColorMaterial>,
+  transparent: Handle<ColorMaterial>,
+  chart_bar: Handle<ColorMaterial>,
+  chart_sent: Handle<ColorMaterial>,
+  chart_received: Handle<ColorMaterial>,
+  column_guide: Handle<ColorMaterial>,
+  // 対戦で負けた盤面と、勝った盤面のきらめき
+  dim_block: Handle<ColorMaterial>,
+  sparkle: Handle<ColorMaterial>,
 }
-struct MainWindow {
-  w: u32,
-  h: u32,
-}
-impl Default for MainWindow {
-  fn default() -> Self {
-    Self { w: 400, h: 800 }
-  }
+struct Fonts {
+  main: Handle<Font>,
 }
 struct ActiveBlock {
-  is_on: bool,
+  phase: PiecePhase,
   direction: Direction,
-  position: Position,
   block_idx: u32,
+  // 回転の状態 (0: 出現時, 1: 右, 2: 逆, 3: 左)
+  rotation: u8,
+  // 最後に動かしたのが回転だった。T-spin の判定に使う
+  spun: bool,
+}
+impl ActiveBlock {
+  // 操作できるミノが盤面にある
+  fn is_on(&self) -> bool {
+    matches!(self.phase, PiecePhase::Falling | PiecePhase::Grounded)
+  }
+}
+// 操作中のミノの一生。進めるのは lock_pipeline だけで、
+// 他からはミノを出し直したり盤面を片付けたりしたときに置き直すだけ
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum PiecePhase {
+  // キューから次のミノを出す。キューが空ならここで待つ
+  Spawning,
+  // 操作中で接地していない
+  Falling,
+  // 接地して LockDelay の猶予を数えている
+  Grounded,
+  // 猶予が尽きたので、このフレームで積まれたブロックに置き換える
+  Locking,
+  // 積んだブロックが反映された次のフレームで揃った行を消す。since は固定した時刻
+  Clearing { since: f64 },
+  // 次のミノが出るまでの待ち (ARE とライン消去の待ち)
+  SpawnDelay { until: f64 },
+}
+// 一時停止から戻った GameClock の時刻。すぐに落ちないよう重力を1回分待たせる
+struct ResumeTime(f64);
+// プレイ中だけ進む時計。一時停止やメニューの間は止まるので、ゲームの時間はすべてこれで測る
+#[derive(Default)]
+struct GameClock(f64);
+// 接地してから固定されるまでの猶予
+struct LockDelay {
+  timer: Timer,
+  resets: u32,
+  // 前のフレームで接地していたマス。動いたら猶予をリセットする
+  cells: Vec<Position>,
+}
+impl Default for LockDelay {
+  fn default() -> Self {
+    Self {
+      timer: Timer::from_seconds(LOCK_DELAY, false),
+      resets: 0,
+      cells: vec![],
+    }
+  }
+}
+impl LockDelay {
+  fn is_grounded(&self) -> bool {
+    !self.cells.is_empty()
+  }
+
+  // 残りの猶予の割合 (1.0 から 0.0 へ減っていく)
+  fn remaining(&self) -> f32 {
+    1. - self.timer.percent()
+  }
+}
+struct PieceQueue {
+  queue: VecDeque<u32>,
+  // true の間はキューが空になってもランダムに補充しない
+  fixed: bool,
+  // 空でなければランダムの代わりにこの並びを繰り返す
+  pattern: Vec<u32>,
+  pattern_pos: usize,
+  // ランダマイザーの種。リプレイの共有用に覚えておく
+  seed: u64,
+  randomizer: Box<dyn randomizer::Randomizer>,
+  // 起動してから配ったミノ。ゲームをやり直しても消さない
+  dealt: randomizer::DealStats,
+}
+impl Default for PieceQueue {
+  fn default() -> Self {
+    let seed = random();
+    Self {
+      queue: VecDeque::new(),
+      fixed: false,
+      pattern: vec![],
+      pattern_pos: 0,
+      seed,
+      randomizer: randomizer::RandomizerKind::default().build(seed),
+      dealt: randomizer::DealStats::default(),
+    }
+  }
+}
+impl PieceQueue {
+  // 新しい種でランダマイザーを作り直す
+  fn reseed(&mut self, ruleset: &rules::Ruleset) {
+    self.restart(ruleset, random());
+  }
+
+  // 種からランダマイザーを作り直し、覗いてあったミノは捨てる。同じ種なら同じ並びで出てくる
+  fn restart(&mut self, ruleset: &rules::Ruleset, seed: u64) {
+    self.seed = seed;
+    self.randomizer = ruleset.build_randomizer(seed);
+    // パズルの決まった並びはそのまま
+    if !self.fixed {
+      self.queue.clear();
+    }
+  }
+
+  fn fill(&mut self, len: usize) {
+    while self.queue.len() < len && !self.fixed {
+      let idx = if self.pattern.is_empty() {
+        self.randomizer.next()
+      } else {
+        let idx = self.pattern[self.pattern_pos % self.pattern.len()];
+        self.pattern_pos += 1;
+        idx
+      };
+      self.queue.push_back(idx);
+    }
+  }
+
+  // 出てくるミノの並びを差し替える。空ならランダムに戻す
+  fn set_pattern(&mut self, pattern: Vec<u32>) {
+    self.pattern = pattern;
+    self.pattern_pos = 0;
+    self.queue.clear();
+  }
+
+  fn pop(&mut self) -> Option<u32> {
+    self.fill(1);
+    let idx = self.queue.pop_front()?;
+    self.dealt.record(idx);
+    Some(idx)
+  }
+
+  // 次に出てくるミノを n 個まで
+  fn peek(&mut self, n: usize) -> Vec<u32> {
+    self.fill(n);
+    self.queue.iter().take(n).copied().collect()
+  }
+}
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum GameMode {
+  Marathon,
+  Mission,
+  Puzzle,
+  Editor,
+  // ミノの並びを指定して練習する
+  Practice,
+  // 下に積まれたせり上がりを掘る
+  Dig,
+  // 40 ラインを消すまでのタイムを競う
+  Sprint,
+  // 段位を上げながら 100 ライン消し、見えないスタッフロールを生き延びる
+  Master,
+  // 1 つのキーボードで、同じ盤面を数ミノずつ交代で操作する
+  Party,
+  // 30 秒ごとにルールの修飾が替わる
+  Chaos,
+  // 2 人が幅の広い 1 つの盤面に、それぞれのミノを同時に積む
+  Coop,
+  // 決まった APM で来続けるせり上がりをしのぐ
+  Survival,
+  // ガベージを送ってくるボットとの練習試合
+  Versus,
+  // 1台で最大4人まで対戦する
+  Battle,
+  // 通信で 1 対 1 の対戦をする
+  Online,
+  // ボットどうしの対戦を眺める
+  Exhibition,
+}
+impl GameMode {
+  fn from_args() -> Self {
+    match std::env::args().nth(1).as_deref() {
+      Some("mission") => GameMode::Mission,
+      Some("puzzle") => GameMode::Puzzle,
+      Some("editor") => GameMode::Editor,
+      Some("practice") => GameMode::Practice,
+      Some("dig") => GameMode::Dig,
+      Some("sprint") => GameMode::Sprint,
+      Some("master") => GameMode::Master,
+      Some("party") => GameMode::Party,
+      Some("chaos") => GameMode::Chaos,
+      Some("coop") => GameMode::Coop,
+      Some("survival") => GameMode::Survival,
+      Some("versus") => GameMode::Versus,
+      Some("battle") => GameMode::Battle,
+      Some("online") => GameMode::Online,
+      Some("exhibition") => GameMode::Exhibition,
+      _ => GameMode::Marathon,
+    }
+  }
+
+  // ヒントなどの練習用機能を使えるモード
+  fn is_training(&self) -> bool {
+    matches!(
+      self,
+      GameMode::Mission | GameMode::Puzzle | GameMode::Practice
+    )
+  }
+}
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum AppState {
+  // プロフィールを選ぶタイトル画面
+  Title,
+  // ルール設定画面
+  Setup,
+  Playing,
+  // ゲーム終了後の結果画面
+  Results,
+  // 他の画面の上に重ねる設定画面
+  Settings,
+  // Playing の上に重ねる一時停止画面
+  Paused,
+  // タイトル画面の上に重ねる実績の一覧
+  Achievements,
+  // タイトル画面の上に重ねるリプレイの再生画面
+  Replay,
+  // 複数人の対戦。通常のゲームとは別に盤面を持つ
+  Battle,
+  // Playing の上に重ねるコマ送りの停止。開発用
+  #[cfg(feature = "frame-step")]
+  Stepping,
 }
-struct StackTime(f64);
 // endregion: Resource
 
+// region: Event
+struct BlockStacked {
+  block_idx: u32,
+  cells: Vec<Position>,
+  t_spin: bool,
+}
+struct LinesCleared(u32);
+struct GameReset;
+// プレイヤーの操作で動いた量 (自然落下は含まない)
+struct PieceMoved {
+  dx: i32,
+  dy: i32,
+}
+// 自然落下で1段下がった
+struct PieceFell;
+struct PieceRotated {
+  from: u8,
+  to: u8,
+  // キックテーブルのどのずらし方で回れたか
+  kick: (i32, i32),
+}
+// せり上がりで下に足した段数。盤面はもう上がっていて、描くときだけ追いかける
+struct GarbageRaised(u32);
+// endregion: Event
+
 // region: Component
 struct PrimitiveBlock {}
 struct StackedBlock;
-#[derive(Clone, Eq, PartialEq, Hash, Debug)]
-pub struct Position {
-  x: i32,
-  y: i32,
+// 固定したばかりのマス。残りの秒数だけ白から積んだブロックの色に戻していく
+struct LockHighlight {
+  remaining: f32,
 }
-impl PartialEq<&Position> for Mut<'_, Position> {
-  fn eq(&self, other: &&Position) -> bool {
-    self.x.eq(&other.x) && self.y.eq(&other.y)
-  }
+// せり上がりの描き方。上がる前の高さから rows 段ぶん下にずらして描き始め、0 まで戻す
+#[derive(Default)]
+struct GarbageRise {
+  rows: f32,
+  remaining: f32,
 }
-impl PartialEq<Mut<'_, Position>> for &Position {
-  fn eq(&self, other: &Mut<Position>) -> bool {
-    self.x.eq(&other.x) && self.y.eq(&other.y)
+impl GarbageRise {
+  // 途中で次が来たら、今ずれている分に足して数え直す
+  fn start(&mut self, rows: u32) {
+    self.rows = self.offset() + rows as f32;
+    self.remaining = GARBAGE_RISE;
+  }
+
+  fn advance(&mut self, dt: f32) {
+    self.remaining = (self.remaining - dt).max(0.);
+  }
+
+  // 今描くときに下にずらす段数。終わりに向けてゆっくりにする
+  fn offset(&self) -> f32 {
+    let t = (self.remaining / GARBAGE_RISE).clamp(0., 1.);
+    self.rows * t * t
   }
 }
 struct Size {
@@ -72,7 +410,6 @@ impl Size {
 enum Direction {
   Neutral,
   Left,
-  Up,
   Right,
   Down,
 }
@@ -80,105 +417,356 @@ enum Direction {
 
 #[derive(SystemLabel, Debug, Hash, PartialEq, Eq, Clone)]
 enum Label {
+  Spawn,
   Input,
   Movement,
   Transpose,
   Stack,
-  Destroy,
+  Translation,
+  // 盤面を片付ける GameReset の処理。片付けた後に盤面を作り直すものはこの後に置く
+  Reset,
 }
 
 fn main() {
-  App::build()
+  let mode = GameMode::from_args();
+  // セーブファイルの読み込みより先にプロフィールを決める
+  let profiles = profile::ProfileList::load();
+  let config = config::Config::load();
+  let mut app = App::build();
+  app
     .insert_resource(WindowDescriptor {
       title: "Tetris".to_string(),
-      width: 400.0,
-      height: 800.0,
+      width: config.window.width,
+      height: config.window.height,
       ..Default::default()
     }) // Windowの設定
     .insert_resource(ClearColor(Color::rgb(0.04, 0.04, 0.04)))
-    .insert_resource(MainWindow::default())
+    .insert_resource(config)
+    .insert_resource(profiles)
     .insert_resource(ActiveBlock {
-      is_on: false,
+      phase: PiecePhase::Spawning,
       direction: Direction::Neutral,
-      position: Position {
-        x: 3,
-        y: ARENA_WIDTH as i32 - 1,
-      },
       block_idx: 0,
+      rotation: 0,
+      spun: false,
+    })
+    .insert_resource(ResumeTime(0.))
+    .insert_resource(GameClock::default())
+    .insert_resource(LockDelay::default())
+    .insert_resource(PieceQueue::default())
+    .insert_resource(mode)
+    .add_event::<BlockStacked>()
+    .add_event::<LinesCleared>()
+    .add_event::<GameReset>()
+    .add_event::<PieceMoved>()
+    .add_event::<PieceFell>()
+    .add_event::<PieceRotated>()
+    .add_event::<GarbageRaised>()
+    .insert_resource(rules::load_ruleset(mode))
+    .add_state(match mode {
+      GameMode::Editor => AppState::Playing,
+      GameMode::Battle | GameMode::Online | GameMode::Exhibition | GameMode::Coop => {
+        AppState::Battle
+      }
+      _ => AppState::Title,
     })
-    .insert_resource(StackTime(0.))
     .add_startup_system(setup.system())
-    .add_startup_stage("game_setup", SystemStage::single(spawn_block.system()))
-    .add_system(
-      block_movement_input
-        .system()
-        .label(Label::Input)
-        .before(Label::Movement),
+    .add_system_set(
+      SystemSet::on_enter(AppState::Playing).with_system(spawn_block.system().label(Label::Spawn)),
     )
-    // .add_sysem(block_transpose.system().label(Label::Transpose))
     .add_system_set(
-      SystemSet::new()
-        .with_run_criteria(FixedTimestep::step(0.5))
+      SystemSet::on_update(AppState::Playing)
+        .with_system(
+          block_movement_input
+            .system()
+            .label(Label::Input)
+            .before(Label::Movement),
+        )
         .with_system(
-          block_free_fall
+          lock_pipeline
             .system()
-            .label(Label::Movement)
-            .after(Label::Input)
-            .after(Label::Transpose),
+            .label(Label::Stack)
+            .after(Label::Movement),
         )
         .with_system(
-          block_movement
+          block_transpose
             .system()
-            .label(Label::Movement)
-            .after(Label::Input)
-            .after(Label::Transpose),
-        ),
+            .label(Label::Transpose)
+            .after(Label::Input),
+        )
+        .with_system(top_out.system().after(Label::Stack))
+        .with_system(block_movement.system()),
     )
-    .add_system(
-      stack_block
-        .system()
-        .label(Label::Stack)
-        .after(Label::Movement),
+    .add_system_set(gravity_systems())
+    .add_system_to_stage(CoreStage::PreUpdate, advance_game_clock.system())
+    .add_system_to_stage(
+      CoreStage::PreUpdate,
+      reset_game.system().label(Label::Reset),
     )
-    .add_system(
-      destroy_block
-        .system()
-        .label(Label::Destroy)
-        .after(Label::Stack),
-    )
-    .add_system(respawn_block.system().after(Label::Destroy))
-    .add_system(block_movement.system())
     .add_system_set_to_stage(
       CoreStage::PostUpdate,
       SystemSet::new()
-        .with_system(position_translation.system())
-        .with_system(size_scaling.system()),
+        .with_system(position_translation.system().label(Label::Translation))
+        .with_system(size_scaling.system())
+        .with_system(lock_highlight.system())
+        .with_system(garbage_rise.system().after(Label::Translation)),
     )
     .add_plugins(DefaultPlugins)
-    .run();
+    .add_plugin(config::ConfigPlugin)
+    .add_plugin(layout::LayoutPlugin)
+    .add_plugin(profile::ProfilePlugin)
+    .add_plugin(accessibility::AccessibilityPlugin)
+    .add_plugin(skins::SkinsPlugin)
+    .add_plugin(achievements::AchievementsPlugin)
+    .add_plugin(toast::ToastPlugin)
+    .add_plugin(audio::AudioPlugin)
+    .add_plugin(cues::CuesPlugin)
+    .add_plugin(announcer::AnnouncerPlugin)
+    .add_plugin(pause::PausePlugin)
+    .add_plugin(rules::RulesPlugin)
+    .add_plugin(hold::HoldPlugin)
+    .add_plugin(kicks::KickTablePlugin)
+    .add_plugin(replay::ReplayPlugin)
+    .add_plugin(ghost::GhostPlugin)
+    .add_plugin(lockbar::LockBarPlugin)
+    .add_plugin(stats::StatsPlugin)
+    .add_plugin(bests::BestsPlugin)
+    .add_plugin(hud::HudPlugin)
+    .add_plugin(speed::SpeedPlugin)
+    .add_plugin(ticker::TickerPlugin)
+    .add_plugin(results::ResultsPlugin)
+    .add_plugin(restart::RestartPlugin)
+    .add_plugin(rumble::RumblePlugin)
+    .add_plugin(menu::MenuPlugin)
+    .add_plugin(eventlog::EventLogPlugin)
+    .add_plugin(assist::MouseAssistPlugin)
+    .add_plugin(analysis::AnalysisPlugin)
+    .add_plugin(deals::DealsPlugin)
+    .add_plugin(columns::ColumnsPlugin)
+    .add_plugin(latency::LatencyPlugin)
+    .add_plugin(snapshot::SnapshotPlugin)
+    .add_plugin(detach::DetachPlugin);
+  match mode {
+    GameMode::Mission => {
+      app.add_plugin(mission::MissionPlugin);
+    }
+    GameMode::Puzzle => {
+      app.add_plugin(puzzle::PuzzlePlugin);
+    }
+    GameMode::Editor => {
+      app.add_plugin(editor::EditorPlugin);
+    }
+    GameMode::Practice => {
+      app.add_plugin(practice::PracticePlugin);
+    }
+    GameMode::Dig => {
+      app.add_plugin(dig::DigPlugin);
+    }
+    GameMode::Sprint => {
+      app.add_plugin(sprint::SprintPlugin);
+    }
+    GameMode::Master => {
+      app
+        .add_plugin(master::MasterPlugin)
+        .add_plugin(splits::SplitsPlugin);
+    }
+    GameMode::Party => {
+      app.add_plugin(party::PartyPlugin);
+    }
+    GameMode::Chaos => {
+      app.add_plugin(chaos::ChaosPlugin);
+    }
+    GameMode::Coop => {
+      app.add_plugin(coop::CoopPlugin);
+    }
+    GameMode::Survival => {
+      app.add_plugin(survival::SurvivalPlugin);
+    }
+    GameMode::Versus => {
+      app.add_plugin(versus::VersusPlugin);
+    }
+    GameMode::Battle => {
+      app.add_plugin(battle::BattlePlugin);
+    }
+    GameMode::Online => {
+      app
+        .add_plugin(battle::BattlePlugin)
+        .add_plugin(online::OnlinePlugin);
+    }
+    GameMode::Exhibition => {
+      app
+        .add_plugin(battle::BattlePlugin)
+        .add_plugin(spectate::SpectatePlugin)
+        .add_plugin(caster::CasterPlugin);
+    }
+    GameMode::Marathon => {
+      // 他のモードは盤面以外の進行状況があるので、途中からの再開はマラソンだけ
+      app
+        .add_plugin(autosave::AutosavePlugin)
+        .add_plugin(splits::SplitsPlugin);
+    }
+  }
+  if mode.is_training() {
+    app.add_plugin(hint::HintPlugin);
+  }
+  #[cfg(feature = "frame-step")]
+  app.add_plugin(framestep::FrameStepPlugin);
+  // 盤面と entity のずれを早めに見つけるため、debug ビルドでは毎フレーム確かめる
+  #[cfg(debug_assertions)]
+  app.add_plugin(validate::ValidatePlugin);
+  app.run();
+}
+
+// プレイ中のフレームの分だけ GameClock を進める
+fn advance_game_clock(time: Res<Time>, state: Res<State<AppState>>, mut clock: ResMut<GameClock>) {
+  if *state.current() == AppState::Playing {
+    clock.0 += time.delta_seconds_f64();
+  }
+}
+
+// 今のレベルの重力を tick ごとにためて判定を出す。急降下も同じ道を通り、速いときは 1 フレームに何段も落とす
+fn gravity_timestep(
+  state: Res<State<AppState>>,
+  time: Res<Time>,
+  config: Res<config::Config>,
+  curve: Res<speed::GravityCurve>,
+  stats: Res<stats::Statistics>,
+  modifiers: Res<rules::Modifiers>,
+  active_block: Res<ActiveBlock>,
+  clock: Res<GameClock>,
+  resume_time: Res<ResumeTime>,
+  #[cfg(feature = "frame-step")] frame_step: Res<framestep::FrameStep>,
+  // (重力のたまり, このフレームで残っている段数, 最後に足したフレームの時刻)
+  mut timer: Local<(speed::DropAccumulator, u32, f64)>,
+) -> ShouldRun {
+  let (drop, rows, frame) = &mut *timer;
+  if *state.current() != AppState::Playing {
+    drop.reset();
+    *rows = 0;
+    return ShouldRun::No;
+  }
+  let now = time.seconds_since_startup();
+  // 同じフレームの中で判定し直すときは足さない
+  if *frame != now {
+    *frame = now;
+    // 一時停止から戻った直後は自然落下だけ待たせる
+    let gravity = if clock.0 < resume_time.0 + GRAVITY_STEP {
+      speed::Gravity(0)
+    } else {
+      curve
+        .gravity(stats.level())
+        .scaled(modifiers.gravity_scale())
+    };
+    let gravity = if active_block.direction == Direction::Down {
+      gravity.max(speed::SOFT_DROP)
+    } else {
+      gravity
+    };
+    *rows = drop.advance(time.delta_seconds(), gravity, config.tick_rate);
+    // コマ送りでは 1 コマにつき必ず 1 段落とす
+    #[cfg(feature = "frame-step")]
+    if frame_step.advancing {
+      *rows = 1;
+    }
+  }
+  if *rows > 0 {
+    *rows -= 1;
+    ShouldRun::YesAndCheckAgain
+  } else {
+    ShouldRun::No
+  }
 }
 
-fn setup(mut commands: Commands, mut materials: ResMut<Assets<ColorMaterial>>) {
+// 重力で落とすシステム。1 フレームに落ちる段数だけ繰り返し走るので、横移動はここに入れない
+fn gravity_systems() -> SystemSet {
+  SystemSet::new()
+    .with_run_criteria(gravity_timestep.system().chain(while_playing.system()))
+    .with_system(
+      block_free_fall
+        .system()
+        .label(Label::Movement)
+        .after(Label::Input)
+        .after(Label::Transpose),
+    )
+}
+
+// 重力の判定をプレイ中だけ通す。スローモーションのときは倍率に合わせて間引く
+fn while_playing(
+  In(should_run): In<ShouldRun>,
+  state: Res<State<AppState>>,
+  config: Res<config::Config>,
+  mut progress: Local<f32>,
+) -> ShouldRun {
+  if *state.current() != AppState::Playing {
+    return ShouldRun::No;
+  }
+  match should_run {
+    ShouldRun::Yes | ShouldRun::YesAndCheckAgain
+      if !assist::slowed_tick(&mut progress, config.assist.slow_motion) =>
+    {
+      if should_run == ShouldRun::Yes {
+        ShouldRun::No
+      } else {
+        ShouldRun::NoAndCheckAgain
+      }
+    }
+    _ => should_run,
+  }
+}
+
+fn setup(
+  mut commands: Commands,
+  mut materials: ResMut<Assets<ColorMaterial>>,
+  asset_server: Res<AssetServer>,
+) {
   commands.spawn_bundle(OrthographicCameraBundle::new_2d());
+  commands.spawn_bundle(UiCameraBundle::default());
   commands.insert_resource(Materials {
     gray_block: materials.add(Color::rgb(0.7, 0.7, 0.7).into()),
     white_block: materials.add(Color::rgb(0.1, 0.1, 0.1).into()),
+    ghost_block: materials.add(Color::rgba(0.7, 0.7, 0.7, 0.3).into()),
+    hint_block: materials.add(Color::rgba(0.3, 0.9, 0.4, 0.5).into()),
+    lock_bar: materials.add(Color::rgb(0.9, 0.5, 0.2).into()),
+    panel: materials.add(Color::rgba(0.0, 0.0, 0.0, 0.85).into()),
+    transparent: materials.add(Color::NONE.into()),
+    chart_bar: materials.add(Color::rgb(0.4, 0.7, 0.9).into()),
+    chart_sent: materials.add(Color::rgb(0.4, 0.9, 0.5).into()),
+    chart_received: materials.add(Color::rgb(0.9, 0.4, 0.4).into()),
+    column_guide: materials.add(Color::rgba(1.0, 1.0, 1.0, 0.06).into()),
+    dim_block: materials.add(Color::rgb(0.3, 0.3, 0.3).into()),
+    sparkle: materials.add(Color::rgb(1.0, 0.95, 0.6).into()),
+  });
+  commands.insert_resource(Fonts {
+    main: asset_server.load("fonts/DejaVuSans.ttf"),
   });
 }
 
-lazy_static! {
-  pub static ref BLOCKMAP: HashMap<u32, Vec<Position>> = {
-    let mut m = HashMap::new();
-    m.insert(1, vec![Position { x: 0, y: 0 }, Position { x: 1, y: 0 }, Position { x: 0, y: 1 }, Position { x: 1, y: 1 }]); // square
-    m.insert(2, vec![Position { x: -1, y: 1 }, Position { x: 0, y: 1 }, Position { x: 0, y: 0 }, Position { x: 1, y: 0 }]); // S字
-    m.insert(3, vec![Position { x: -1, y: 0 }, Position { x: 0, y: 0 }, Position { x: 0, y: 1 }, Position { x: 1, y: 1 }]); // 逆S字
-    m.insert(4, vec![Position { x: 1, y: 0 }, Position { x: 0, y: 0 }, Position { x: 0, y: 1 }, Position { x: 0, y: 2 }]); // L字
-    m.insert(5, vec![Position { x: -1, y: 0 }, Position { x: 0, y: 0 }, Position { x: 0, y: 1 }, Position { x: 0, y: 2 }]); // 逆L字
-    m.insert(6, vec![Position { x: -1, y: 0 }, Position { x: 0, y: 0 }, Position { x: 1, y: 0 }, Position { x: 0, y: 1 }]); // T字
-    m.insert(7, vec![Position { x: 0, y: 0 }, Position { x: 0, y: 1 }, Position { x: 0, y: 2 }, Position { x: 0, y: 3 }]); // I字
-    m
-  };
+// 画面左上に重ねて表示するテキスト
+fn overlay_text(fonts: &Fonts) -> TextBundle {
+  TextBundle {
+    style: Style {
+      position_type: PositionType::Absolute,
+      position: Rect {
+        top: Val::Px(5.0),
+        left: Val::Px(5.0),
+        ..Default::default()
+      },
+      ..Default::default()
+    },
+    text: Text::with_section(
+      "",
+      TextStyle {
+        font: fonts.main.clone(),
+        font_size: 16.0,
+        color: Color::WHITE,
+      },
+      Default::default(),
+    ),
+    ..Default::default()
+  }
+}
 
+lazy_static! {
   pub static ref TETORIMINO_ARRAY: Vec<Array2<u32>> = {
     vec![
       arr2(&[
@@ -220,6 +808,23 @@ lazy_static! {
   };
 }
 
+// 空の行と列を落として、ミノのマスだけを囲む大きさにする
+fn trim_bounding_box(block: &Array2<u32>) -> Array2<u32> {
+  let rows: Vec<usize> = (0..block.nrows())
+    .filter(|&r| block.row(r).iter().any(|&v| v != 0))
+    .collect();
+  let cols: Vec<usize> = (0..block.ncols())
+    .filter(|&c| block.column(c).iter().any(|&v| v != 0))
+    .collect();
+  match (rows.first(), rows.last(), cols.first(), cols.last()) {
+    (Some(&top), Some(&bottom), Some(&left), Some(&right)) => {
+      block.slice(s![top..=bottom, left..=right]).to_owned()
+    }
+    _ => Array2::zeros((0, 0)),
+  }
+}
+
+#[allow(dead_code)]
 fn generate_tetorimino_positions(base_position: &Position, block: &Array2<u32>) -> Vec<Position> {
   let mut res = vec![];
 
@@ -250,79 +855,104 @@ fn spawn_block(
   mut commands: Commands,
   materials: Res<Materials>,
   mut active_block: ResMut<ActiveBlock>,
+  mut piece_queue: ResMut<PieceQueue>,
 ) {
-  if !active_block.is_on {
-    let idx = (random::<f32>() * BLOCKMAP.keys().len() as f32) as u32;
-    if let Some(positions) = BLOCKMAP.get(&idx) {
-      let base_position_x = 3;
-      let base_position_y = (ARENA_HEIGHT - 1) as i32;
-
-      for position in positions.iter() {
-        commands
-          .spawn_bundle(SpriteBundle {
-            material: materials.gray_block.clone(),
-            sprite: Sprite::new(Vec2::new(10.0, 10.0)),
-            ..Default::default()
-          })
-          .insert(PrimitiveBlock {})
-          .insert(Position {
-            x: position.x + base_position_x,
-            y: position.y + base_position_y,
-          })
-          .insert(Size::square(0.8));
-      }
-    }
-    active_block.is_on = true;
+  if !active_block.is_on() {
+    let idx = match piece_queue.pop() {
+      Some(idx) => idx,
+      None => return,
+    };
+    spawn_piece(&mut commands, &materials, &mut active_block, idx);
   }
 }
 
-fn respawn_block(
-  commands: Commands,
-  materials: Res<Materials>,
-  active_block: ResMut<ActiveBlock>,
-  time: Res<Time>,
-  stack_time: ResMut<StackTime>,
+// 指定したミノを出現位置に出す
+fn spawn_piece(
+  commands: &mut Commands,
+  materials: &Materials,
+  active_block: &mut ActiveBlock,
+  idx: u32,
 ) {
-  let now = time.seconds_since_startup();
-  if !active_block.is_on && now > stack_time.0 + BLOCK_RESPAWN_DELAY {
-    spawn_block(commands, materials, active_block);
+  if BLOCKMAP.contains_key(&idx) {
+    for position in spawn_cells(idx) {
+      spawn_primitive_block(commands, materials, position);
+    }
+    active_block.block_idx = idx;
+    active_block.rotation = 0;
+    active_block.spun = false;
   }
+  active_block.phase = PiecePhase::Falling;
+}
+
+fn spawn_primitive_block(commands: &mut Commands, materials: &Materials, position: Position) {
+  commands
+    .spawn_bundle(SpriteBundle {
+      material: materials.gray_block.clone(),
+      sprite: Sprite::new(Vec2::new(10.0, 10.0)),
+      ..Default::default()
+    })
+    .insert(PrimitiveBlock {})
+    .insert(position)
+    .insert(Size::square(0.8));
 }
 
 fn block_movement_input(
+  time: Res<Time>,
+  config: Res<config::Config>,
   keyboard_input: Res<Input<KeyCode>>,
+  controls: Res<profile::Controls>,
+  ruleset: Res<rules::Ruleset>,
+  mut auto_shift: Local<profile::AutoShift>,
   mut active_block: ResMut<ActiveBlock>,
 ) {
-  let dir: Direction = if keyboard_input.just_pressed(KeyCode::Left) {
-    Direction::Left
-  } else if keyboard_input.just_pressed(KeyCode::Right) {
-    Direction::Right
-  } else if keyboard_input.pressed(KeyCode::Down) {
-    // 急降下
-    Direction::Down
-  } else if keyboard_input.just_pressed(KeyCode::Up) {
-    Direction::Up
+  let bindings = &controls.bindings;
+  // 入力を反転すると、左のキーが盤面の右への移動になる。反転した盤面では見た目の左
+  let (left, right) = if ruleset.mirror_input {
+    (&bindings.right, &bindings.left)
   } else {
-    Direction::Neutral
+    (&bindings.left, &bindings.right)
+  };
+  let held = if profile::any_pressed(&keyboard_input, left) {
+    Some(Direction::Left)
+  } else if profile::any_pressed(&keyboard_input, right) {
+    Some(Direction::Right)
+  } else {
+    None
+  };
+  let shift = auto_shift.update(
+    held,
+    time.delta_seconds(),
+    &controls.handling,
+    config.tick_rate,
+  );
+  let dir: Direction = match held {
+    Some(dir) if shift => dir,
+    Some(_) => Direction::Neutral,
+    // 急降下
+    None if profile::any_pressed(&keyboard_input, &bindings.soft_drop) => Direction::Down,
+    None => Direction::Neutral,
   };
   active_block.direction = dir;
 }
 
-fn move_tetoriminos(mut t: Query<&mut Position, Without<StackedBlock>>, diff: &Position) {
+fn move_tetoriminos(
+  mut t: Query<&mut Position, (With<PrimitiveBlock>, Without<StackedBlock>)>,
+  diff: &Position,
+) {
   for mut position in t.iter_mut() {
     position.x += diff.x;
     position.y += diff.y;
   }
 }
 
+// 重力で 1 段落とす。急降下で落ちたときは自分で動かした扱いにする
 fn block_free_fall(
-  mut query: Query<&mut Position, Without<StackedBlock>>,
+  mut query: Query<&mut Position, (With<PrimitiveBlock>, Without<StackedBlock>)>,
   stacked_block_query: Query<(&StackedBlock, &Position), Without<PrimitiveBlock>>,
-  active_block: Res<ActiveBlock>,
+  mut active_block: ResMut<ActiveBlock>,
+  mut moved_events: EventWriter<PieceMoved>,
+  mut fell_events: EventWriter<PieceFell>,
 ) {
-  if active_block.direction == Direction::Down {
-    return;
-  }
   let mut collision_flag = false;
   for position in query.iter_mut() {
     let is_collision = |pos: &Position| -> bool {
@@ -334,203 +964,523 @@ fn block_free_fall(
       x: position.x,
       y: position.y - 1,
     };
-    if position.y <= 0 && !is_collision(&p) {
+    if position.y <= 0 || is_collision(&p) {
       collision_flag = true;
     }
   }
   let p = Position { x: 0, y: -1 };
-  if !collision_flag {
+  if !collision_flag && query.iter_mut().next().is_some() {
+    if active_block.direction == Direction::Down {
+      moved_events.send(PieceMoved { dx: 0, dy: -1 });
+    } else {
+      fell_events.send(PieceFell);
+    }
+    active_block.spun = false;
     move_tetoriminos(query, &p);
   }
 }
 
 fn block_movement(
-  mut primitive_block_query: Query<&mut Position, Without<StackedBlock>>,
-  active_block: ResMut<ActiveBlock>,
+  mut primitive_block_query: Query<&mut Position, (With<PrimitiveBlock>, Without<StackedBlock>)>,
+  mut active_block: ResMut<ActiveBlock>,
   stacked_block_query: Query<&Position, With<StackedBlock>>,
+  mut moved_events: EventWriter<PieceMoved>,
 ) {
-  let is_collision = |pos: &Position| -> bool {
-    stacked_block_query
-      .iter()
-      .any(|stacked_pos| stacked_pos == pos)
+  let action = match active_block.direction {
+    Direction::Left => sim::Action::Left,
+    Direction::Right => sim::Action::Right,
+    _ => return,
   };
-  let direction = active_block.direction;
+  let board = board::Board::from_cells(stacked_block_query.iter());
+  shift_piece(
+    &mut primitive_block_query,
+    &board,
+    &mut active_block,
+    action,
+    &mut moved_events,
+  );
+}
 
-  let mut collision_flag = false;
-  if direction == Direction::Left {
-    for primitive_block_position in primitive_block_query.iter_mut() {
-      let pos = Position {
-        x: primitive_block_position.x - 1,
-        y: primitive_block_position.y,
-      };
-      if is_collision(&pos) || primitive_block_position.x <= 0 {
-        collision_flag = true;
-      }
-    }
-  } else if direction == Direction::Right {
-    for primitive_block_position in primitive_block_query.iter_mut() {
-      let pos = Position {
-        x: primitive_block_position.x + 1,
-        y: primitive_block_position.y,
-      };
-      if is_collision(&pos) || primitive_block_position.x >= (ARENA_WIDTH - 1) as i32 {
-        collision_flag = true;
-      }
-    }
-  } else if direction == Direction::Down {
-    for primitive_block_position in primitive_block_query.iter_mut() {
-      let pos = Position {
-        x: primitive_block_position.x,
-        y: primitive_block_position.y - 1,
-      };
-      if is_collision(&pos) || primitive_block_position.y <= 0 {
-        collision_flag = true;
+// 操作中のミノを 1 マス横か下へ動かす。動けたら PieceMoved を送る
+fn shift_piece(
+  primitive_block_query: &mut Query<&mut Position, (With<PrimitiveBlock>, Without<StackedBlock>)>,
+  board: &board::Board,
+  active_block: &mut ActiveBlock,
+  action: sim::Action,
+  moved_events: &mut EventWriter<PieceMoved>,
+) -> bool {
+  if !active_block.is_on() {
+    return false;
+  }
+  let diff = match action {
+    sim::Action::Left => Position { x: -1, y: 0 },
+    sim::Action::Right => Position { x: 1, y: 0 },
+    sim::Action::SoftDrop => Position { x: 0, y: -1 },
+    _ => return false,
+  };
+  let moved: Vec<Position> = primitive_block_query
+    .iter_mut()
+    .map(|p| Position {
+      x: p.x + diff.x,
+      y: p.y + diff.y,
+    })
+    .collect();
+  if moved.is_empty() || !board.fits(&moved) {
+    return false;
+  }
+  for (mut position, cell) in primitive_block_query.iter_mut().zip(moved) {
+    *position = cell;
+  }
+  moved_events.send(PieceMoved {
+    dx: diff.x,
+    dy: diff.y,
+  });
+  active_block.spun = false;
+  true
+}
+
+// 操作中のミノを回す。壁や積まれたブロックはキックテーブルに従って避ける
+fn rotate_piece(
+  primitive_block_query: &mut Query<&mut Position, (With<PrimitiveBlock>, Without<StackedBlock>)>,
+  board: &board::Board,
+  kick_table: &rotation::KickTable,
+  active_block: &mut ActiveBlock,
+  clockwise: bool,
+  rotated_events: &mut EventWriter<PieceRotated>,
+) -> bool {
+  if !active_block.is_on() {
+    return false;
+  }
+  let cells: Vec<Position> = primitive_block_query
+    .iter_mut()
+    .map(|p| p.clone())
+    .collect();
+  let (rotated, state, kick) = match rotation::rotate(
+    board,
+    kick_table,
+    &cells,
+    active_block.block_idx,
+    active_block.rotation,
+    clockwise,
+  ) {
+    Some(rotated) => rotated,
+    None => return false,
+  };
+  for (mut position, cell) in primitive_block_query.iter_mut().zip(rotated) {
+    *position = cell;
+  }
+  rotated_events.send(PieceRotated {
+    from: active_block.rotation,
+    to: state,
+    kick,
+  });
+  active_block.rotation = state;
+  active_block.spun = true;
+  true
+}
+
+// sim の操作を 1 つ、キーで動かしたときと同じ処理で盤面のミノに当てる。
+// HardDrop は下まで 1 段ずつ落としてから、猶予を待たずに固定する
+fn apply_action(
+  primitive_block_query: &mut Query<&mut Position, (With<PrimitiveBlock>, Without<StackedBlock>)>,
+  board: &board::Board,
+  kick_table: &rotation::KickTable,
+  active_block: &mut ActiveBlock,
+  action: sim::Action,
+  moved_events: &mut EventWriter<PieceMoved>,
+  rotated_events: &mut EventWriter<PieceRotated>,
+) -> bool {
+  match action {
+    sim::Action::Left | sim::Action::Right | sim::Action::SoftDrop => shift_piece(
+      primitive_block_query,
+      board,
+      active_block,
+      action,
+      moved_events,
+    ),
+    sim::Action::RotateCw | sim::Action::RotateCcw => rotate_piece(
+      primitive_block_query,
+      board,
+      kick_table,
+      active_block,
+      action == sim::Action::RotateCw,
+      rotated_events,
+    ),
+    sim::Action::HardDrop => {
+      if !active_block.is_on() {
+        return false;
       }
+      while shift_piece(
+        primitive_block_query,
+        board,
+        active_block,
+        sim::Action::SoftDrop,
+        moved_events,
+      ) {}
+      active_block.phase = PiecePhase::Locking;
+      true
     }
+    sim::Action::Hold => false,
   }
+}
 
-  if !collision_flag {
-    if direction == Direction::Left {
-      let diff = Position { x: -1, y: 0 };
-      move_tetoriminos(primitive_block_query, &diff);
-    } else if direction == Direction::Right {
-      let diff = Position { x: 1, y: 0 };
-      move_tetoriminos(primitive_block_query, &diff);
-    } else if direction == Direction::Down {
-      let diff = Position { x: 0, y: -1 };
-      move_tetoriminos(primitive_block_query, &diff);
-    }
-  }
-}
-
-// fn block_transpose(
-//   keyboard_input: Res<Input<KeyCode>>,
-//   mut active_block: ResMut<ActiveBlock>,
-//   mut primitive_block_query: Query<&mut Position, With<PrimitiveBlock>>,
-// ) {
-//   if keyboard_input.pressed(KeyCode::A) {
-//     active_block.theta += consts::FRAC_PI_4;
-//     for mut position in primitive_block_query.iter_mut() {
-//       position.x = (position.x as f64 * -active_block.theta.sin()) as i32;
-//       position.y = (position.y as f64 * active_block.theta.cos()) as i32;
-//     }
-//   } else if keyboard_input.pressed(KeyCode::D) {
-//     active_block.theta -= consts::FRAC_PI_4;
-//     for mut position in primitive_block_query.iter_mut() {
-//       position.x = position.x * active_block.theta.sin() as i32;
-//       position.y = position.y * -active_block.theta.cos() as i32;
-//     }
-//   }
-// }
-
-fn size_scaling(window: Res<MainWindow>, mut q: Query<(&Size, &mut Sprite)>) {
-  for (sprite_size, mut sprite) in q.iter_mut() {
+// 既定では上/X で右回転、Z/左Ctrl で左回転
+fn block_transpose(
+  keyboard_input: Res<Input<KeyCode>>,
+  controls: Res<profile::Controls>,
+  kick_table: Res<rotation::KickTable>,
+  ruleset: Res<rules::Ruleset>,
+  mut active_block: ResMut<ActiveBlock>,
+  mut primitive_block_query: Query<&mut Position, (With<PrimitiveBlock>, Without<StackedBlock>)>,
+  stacked_block_query: Query<&Position, With<StackedBlock>>,
+  mut rotated_events: EventWriter<PieceRotated>,
+) {
+  let bindings = &controls.bindings;
+  let clockwise = if profile::any_just_pressed(&keyboard_input, &bindings.rotate_cw) {
+    true
+  } else if profile::any_just_pressed(&keyboard_input, &bindings.rotate_ccw) {
+    false
+  } else {
+    return;
+  };
+  // 入力を反転すると回転の向きも入れ替わる。反転した盤面では見た目の右回り
+  let clockwise = clockwise != ruleset.mirror_input;
+  let board = board::Board::from_cells(stacked_block_query.iter());
+  rotate_piece(
+    &mut primitive_block_query,
+    &board,
+    &kick_table,
+    &mut active_block,
+    clockwise,
+    &mut rotated_events,
+  );
+}
+
+// 変わったマスだけ塗り直す。盤面の大きさや表示の設定が変わったときは全部
+fn size_scaling(
+  layout: Res<layout::Layout>,
+  accessibility: Res<accessibility::Accessibility>,
+  modifiers: Res<rules::Modifiers>,
+  mut q: QuerySet<(
+    Query<(&Size, &mut Sprite, Option<&PrimitiveBlock>)>,
+    Query<(&Size, &mut Sprite, Option<&PrimitiveBlock>), Changed<Size>>,
+  )>,
+) {
+  // 枠を太くする設定なら、そのぶんマスを小さく塗る
+  let border = accessibility.border();
+  let resize = |sprite_size: &Size, sprite: &mut Sprite, falling: Option<&PrimitiveBlock>| {
+    let scale = if falling.is_some() {
+      modifiers.piece_scale()
+    } else {
+      1.
+    };
     sprite.size = Vec2::new(
-      sprite_size.width / ARENA_WIDTH as f32 * window.w as f32,
-      sprite_size.height / ARENA_HEIGHT as f32 * window.h as f32,
+      (sprite_size.width - border) * layout.cell * scale,
+      (sprite_size.height - border) * layout.cell * scale,
     );
+  };
+  if layout.is_changed() || accessibility.is_changed() || modifiers.is_changed() {
+    for (sprite_size, mut sprite, falling) in q.q0_mut().iter_mut() {
+      resize(sprite_size, &mut sprite, falling);
+    }
+  } else {
+    for (sprite_size, mut sprite, falling) in q.q1_mut().iter_mut() {
+      resize(sprite_size, &mut sprite, falling);
+    }
   }
 }
 
-fn position_translation(windows: Res<Windows>, mut q: Query<(&Position, &mut Transform)>) {
-  fn convert(pos: f32, bound_window: f32, bound_game: f32) -> f32 {
-    let tile_size = bound_window / bound_game;
-    pos / bound_game * bound_window - (bound_window / 2.) + (tile_size / 2.)
+// 動いたマスだけ置き直す。Layout はウィンドウの大きさか左右反転が変わったときだけ変わる
+fn position_translation(
+  layout: Res<layout::Layout>,
+  mut q: QuerySet<(
+    Query<(&Position, &mut Transform)>,
+    Query<(&Position, &mut Transform), Changed<Position>>,
+  )>,
+) {
+  if layout.is_changed() {
+    for (pos, mut transform) in q.q0_mut().iter_mut() {
+      transform.translation = layout.world_position(pos).extend(0.0);
+    }
+  } else {
+    for (pos, mut transform) in q.q1_mut().iter_mut() {
+      transform.translation = layout.world_position(pos).extend(0.0);
+    }
   }
-  let window = windows.get_primary().unwrap();
-  for (pos, mut transform) in q.iter_mut() {
-    transform.translation = Vec3::new(
-      convert(pos.x as f32, window.width() as f32, ARENA_WIDTH as f32),
-      convert(pos.y as f32, window.height() as f32, ARENA_HEIGHT as f32),
-      0.0,
-    );
+}
+
+// 盤面の更新はそのままで、描く位置だけを上がる前から追いつかせる
+fn garbage_rise(
+  time: Res<Time>,
+  config: Res<config::Config>,
+  layout: Res<layout::Layout>,
+  mut rise: Local<GarbageRise>,
+  mut raised_events: EventReader<GarbageRaised>,
+  mut query: Query<(&Position, &mut Transform), With<StackedBlock>>,
+) {
+  let rows: u32 = raised_events.iter().map(|raised| raised.0).sum();
+  if rows > 0 && !config.reduce_motion {
+    rise.start(rows);
+  }
+  if rise.remaining <= 0. {
+    return;
+  }
+  rise.advance(time.delta_seconds());
+  let offset = Vec2::new(0., rise.offset() * layout.cell);
+  for (position, mut transform) in query.iter_mut() {
+    transform.translation = (layout.world_position(position) - offset).extend(0.0);
   }
 }
 
-fn stack_block(
+// 光り始めは白で、残りが 0 になると積んだブロックの色
+fn lock_highlight_color(stacked: Color, remaining: f32) -> Color {
+  let t = (remaining / LOCK_HIGHLIGHT).clamp(0., 1.);
+  let mix = |base: f32| base + (1. - base) * t;
+  Color::rgba(
+    mix(stacked.r()),
+    mix(stacked.g()),
+    mix(stacked.b()),
+    stacked.a(),
+  )
+}
+
+// 光っている間はマスごとにマテリアルを持ち、戻ったら共有のものに付け替える
+fn lock_highlight(
   mut commands: Commands,
-  materials: Res<Materials>,
-  mut active_block: ResMut<ActiveBlock>,
-  primitive_block_query: Query<(Entity, &Position), With<PrimitiveBlock>>,
-  stacked_block_query: Query<&Position, With<StackedBlock>>,
   time: Res<Time>,
-  mut stack_time: ResMut<StackTime>,
+  config: Res<config::Config>,
+  materials: Res<Materials>,
+  mut color_materials: ResMut<Assets<ColorMaterial>>,
+  mut query: Query<(Entity, &mut LockHighlight, &mut Handle<ColorMaterial>)>,
 ) {
-  let mut stack = || {
-    for (entity, primitive_block_position) in primitive_block_query.iter() {
-      // despawn active block
-      commands.entity(entity).despawn();
-
-      // spawn stacked block
-      commands
-        .spawn_bundle(SpriteBundle {
-          material: materials.white_block.clone(),
-          ..Default::default()
-        })
-        .insert(StackedBlock)
-        .insert(Position {
-          x: primitive_block_position.x,
-          y: primitive_block_position.y,
-        })
-        .insert(Size::square(0.8));
-    }
-
-    active_block.is_on = false;
-    stack_time.0 = time.seconds_since_startup();
+  let (stacked, texture) = match color_materials.get(&materials.white_block) {
+    Some(material) => (material.color, material.texture.clone()),
+    None => return,
   };
+  for (entity, mut highlight, mut handle) in query.iter_mut() {
+    highlight.remaining -= time.delta_seconds();
+    // 動きを減らす設定のときは光らせない
+    if highlight.remaining <= 0. || config.reduce_motion {
+      *handle = materials.white_block.clone();
+      commands.entity(entity).remove::<LockHighlight>();
+      continue;
+    }
+    let color = lock_highlight_color(stacked, highlight.remaining);
+    if *handle == materials.white_block {
+      *handle = color_materials.add(ColorMaterial {
+        color,
+        texture: texture.clone(),
+      });
+    } else if let Some(material) = color_materials.get_mut(&*handle) {
+      material.color = color;
+    }
+  }
+}
 
-  let is_collision = |pos: &Position| -> bool {
-    stacked_block_query
-      .iter()
-      .any(|stacked_pos| stacked_pos == pos)
-  };
+fn spawn_stacked_block(
+  commands: &mut Commands,
+  materials: &Materials,
+  position: Position,
+) -> Entity {
+  commands
+    .spawn_bundle(SpriteBundle {
+      material: materials.white_block.clone(),
+      ..Default::default()
+    })
+    .insert(StackedBlock)
+    .insert(position)
+    .insert(Size::square(0.8))
+    .id()
+}
 
-  // いずれかのアクティブブロックが地面に接地
-  if primitive_block_query.iter().any(|(_, p)| p.y <= 0) {
-    stack();
-    return;
+// 盤面の左下を原点とした座標からマスを求める。ウィンドウ上の位置は Layout::cell_at
+fn cursor_to_position(cursor: Vec2, window_width: f32, window_height: f32) -> Option<Position> {
+  let x = (cursor.x / window_width * ARENA_WIDTH as f32).floor() as i32;
+  let y = (cursor.y / window_height * ARENA_HEIGHT as f32).floor() as i32;
+  if x < 0 || x >= ARENA_WIDTH as i32 || y < 0 || y >= ARENA_HEIGHT as i32 {
+    return None;
   }
+  Some(Position { x, y })
+}
 
-  let mut collision_flag = false;
-  for (_, primitive_block_position) in primitive_block_query.iter() {
-    let position = Position {
-      x: primitive_block_position.x,
-      y: primitive_block_position.y - 1,
+// 操作中のミノを 出現 → 落下 → 接地 → 固定 → ライン消去 → 出現待ち と進める。
+// 段階は ActiveBlock.phase の 1 か所だけで持つので、同じミノを 2 度固定したり出し忘れたりしない
+fn lock_pipeline(
+  mut commands: Commands,
+  materials: Res<Materials>,
+  time: Res<Time>,
+  clock: Res<GameClock>,
+  config: Res<config::Config>,
+  ruleset: Res<rules::Ruleset>,
+  mut active_block: ResMut<ActiveBlock>,
+  mut piece_queue: ResMut<PieceQueue>,
+  mut lock_delay: ResMut<LockDelay>,
+  primitive_block_query: Query<(Entity, &Position), (With<PrimitiveBlock>, Without<StackedBlock>)>,
+  mut stacked_block_query: Query<
+    (Entity, &mut Position),
+    (With<StackedBlock>, Without<PrimitiveBlock>),
+  >,
+  mut stacked_events: EventWriter<BlockStacked>,
+  mut cleared_events: EventWriter<LinesCleared>,
+) {
+  let now = clock.0;
+  if let PiecePhase::Clearing { since } = active_block.phase {
+    let cleared = clear_lines(&mut commands, &mut stacked_block_query, &mut cleared_events);
+    active_block.phase = PiecePhase::SpawnDelay {
+      until: since + ruleset.entry_seconds(cleared > 0, config.tick_rate),
     };
-    if is_collision(&position) {
-      collision_flag = true
+  }
+  if let PiecePhase::SpawnDelay { until } = active_block.phase {
+    if now >= until {
+      active_block.phase = PiecePhase::Spawning;
+    }
+  }
+  if active_block.phase == PiecePhase::Spawning {
+    // 出したミノはコマンドが反映される次のフレームから動かす
+    if let Some(idx) = piece_queue.pop() {
+      spawn_piece(&mut commands, &materials, &mut active_block, idx);
+    }
+    return;
+  }
+
+  if active_block.is_on() {
+    let cells: Vec<Position> = primitive_block_query
+      .iter()
+      .map(|(_, p)| p.clone())
+      .collect();
+    let stacked: Vec<Position> = stacked_block_query
+      .iter_mut()
+      .map(|(_, p)| p.clone())
+      .collect();
+    let board = board::Board::from_cells(stacked.iter());
+    // いずれかのマスの真下が床か積まれたブロック
+    let grounded = cells
+      .iter()
+      .any(|p| board.is_filled(&Position { x: p.x, y: p.y - 1 }));
+    if !grounded {
+      lock_delay.timer.reset();
+      lock_delay.cells.clear();
+      active_block.phase = PiecePhase::Falling;
+      return;
+    }
+    // 接地したまま動いたら猶予を延ばす (回数に上限あり)
+    if lock_delay.is_grounded() && lock_delay.cells != cells && lock_delay.resets < LOCK_RESETS {
+      lock_delay.timer.reset();
+      lock_delay.resets += 1;
+    }
+    lock_delay.cells = cells;
+    active_block.phase = PiecePhase::Grounded;
+    // 猶予の長さはルールで決める
+    let duration = Duration::from_secs_f32(ruleset.lock_delay.max(0.));
+    if lock_delay.timer.duration() != duration {
+      lock_delay.timer.set_duration(duration);
+    }
+
+    // スローモーションのときは猶予もゆっくり減らす
+    let slow_motion = config.assist.slow_motion.clamp(0., 1.);
+    if lock_delay
+      .timer
+      .tick(time.delta().mul_f32(slow_motion))
+      .finished()
+    {
+      active_block.phase = PiecePhase::Locking;
     }
   }
 
-  if collision_flag {
-    stack();
+  if active_block.phase == PiecePhase::Locking {
+    let mut cells = vec![];
+    for (entity, position) in primitive_block_query.iter() {
+      commands.entity(entity).despawn();
+      let stacked = spawn_stacked_block(&mut commands, &materials, position.clone());
+      commands.entity(stacked).insert(LockHighlight {
+        remaining: LOCK_HIGHLIGHT,
+      });
+      cells.push(position.clone());
+    }
+    // 回して入れた T ミノの中心の斜め 4 マスのうち 3 マスが埋まっていれば T-spin
+    let stacked: Vec<Position> = stacked_block_query
+      .iter_mut()
+      .map(|(_, p)| p.clone())
+      .collect();
+    let t_spin = active_block.block_idx == 6
+      && active_block.spun
+      && board::Board::from_cells(stacked.iter()).is_t_spin(&cells);
+    stacked_events.send(BlockStacked {
+      block_idx: active_block.block_idx,
+      cells,
+      t_spin,
+    });
+    *lock_delay = LockDelay::default();
+    active_block.phase = PiecePhase::Clearing { since: now };
   }
 }
 
-fn destroy_block(
-  mut commands: Commands,
-  mut query: Query<(Entity, &mut Position), With<StackedBlock>>,
+// 出てきたミノが積まれたブロックと重なったらゲーム終了
+fn top_out(
+  mut state: ResMut<State<AppState>>,
+  spawned_query: Query<&Position, Added<PrimitiveBlock>>,
+  stacked_block_query: Query<&Position, With<StackedBlock>>,
 ) {
-  for h in 0..ARENA_HEIGHT {
-    let mut entities = Vec::new();
-    for (entity, position) in query.iter_mut() {
-      if position.y == h as i32 {
-        entities.push(entity);
-      }
+  let is_blocked = spawned_query.iter().any(|p| {
+    stacked_block_query
+      .iter()
+      .any(|stacked_pos| stacked_pos == p)
+  });
+  if is_blocked {
+    state.overwrite_set(AppState::Results).unwrap();
+  }
+}
+
+// 揃った行を消し、その上のブロックを消した行数だけ下げる。消した行数を返す
+fn clear_lines(
+  commands: &mut Commands,
+  query: &mut Query<(Entity, &mut Position), (With<StackedBlock>, Without<PrimitiveBlock>)>,
+  cleared_events: &mut EventWriter<LinesCleared>,
+) -> u32 {
+  let mut counts = [0; ARENA_HEIGHT as usize];
+  for (_, position) in query.iter_mut() {
+    if position.y >= 0 && position.y < ARENA_HEIGHT as i32 {
+      counts[position.y as usize] += 1;
     }
-    if entities.len() == ARENA_WIDTH as usize {
+  }
+  let full_rows: Vec<i32> = (0..ARENA_HEIGHT as i32)
+    .filter(|&h| counts[h as usize] == ARENA_WIDTH)
+    .collect();
+  if full_rows.is_empty() {
+    return 0;
+  }
+
+  for (entity, mut position) in query.iter_mut() {
+    if full_rows.contains(&position.y) {
       // blocksにあるBlockを削除
-      for &entity in entities.iter() {
-        commands.entity(entity).despawn();
-      }
-      // hより高いBlockをすべて高さを-1する
-      for (_, mut position) in query.iter_mut() {
-        if position.y > h as i32 {
-          position.y -= 1;
-        }
-      }
-    } else if entities.len() == 0 {
-      return;
+      commands.entity(entity).despawn();
+    } else {
+      // 消した行より高いBlockを消した行数だけ下げる
+      position.y -= full_rows.iter().filter(|&&h| h < position.y).count() as i32;
     }
   }
+  cleared_events.send(LinesCleared(full_rows.len() as u32));
+  full_rows.len() as u32
+}
+
+fn reset_game(
+  mut commands: Commands,
+  mut reset_events: EventReader<GameReset>,
+  config: Res<config::Config>,
+  ruleset: Res<rules::Ruleset>,
+  mut active_block: ResMut<ActiveBlock>,
+  mut lock_delay: ResMut<LockDelay>,
+  clock: Res<GameClock>,
+  query: Query<Entity, Or<(With<PrimitiveBlock>, With<StackedBlock>)>>,
+) {
+  if reset_events.iter().count() == 0 {
+    return;
+  }
+  for entity in query.iter() {
+    commands.entity(entity).despawn();
+  }
+  active_block.phase = PiecePhase::SpawnDelay {
+    until: clock.0 + ruleset.entry_seconds(false, config.tick_rate),
+  };
+  *lock_delay = LockDelay::default();
 }
@@ -12,3 +12,2704 @@ fn test_generate_tetorimino_positions() {
     generate_tetorimino_positions(&Position { x: 0, y: 0 }, &arr2(&[[1, 1], [1, 1]]))
   );
 }
+
+#[test]
+fn test_mission_clear_lines_with_piece() {
+  use mission::{MissionProgress, Objective};
+
+  let objective = Objective::ClearLines {
+    lines: 2,
+    block_idx: Some(6),
+  };
+  let mut progress = MissionProgress::default();
+  progress.record_stack(7, false);
+  progress.record_clear(&objective, 2);
+  assert!(!progress.is_complete(&objective, 0.));
+  progress.record_stack(6, false);
+  progress.record_clear(&objective, 1);
+  assert!(!progress.is_complete(&objective, 0.));
+  progress.record_stack(6, false);
+  progress.record_clear(&objective, 2);
+  assert!(progress.is_complete(&objective, 0.));
+}
+
+#[test]
+fn test_mission_t_spin_and_level() {
+  use mission::{MissionProgress, Objective};
+
+  // T ミノで 2 行消しても、T-spin でなければ数えない
+  let objective = Objective::TSpin { lines: 2 };
+  let mut progress = MissionProgress::default();
+  progress.record_stack(6, false);
+  progress.record_clear(&objective, 2);
+  assert!(!progress.is_complete(&objective, 0.));
+  progress.record_stack(6, true);
+  progress.record_clear(&objective, 1);
+  assert!(!progress.is_complete(&objective, 0.));
+  progress.record_stack(6, true);
+  progress.record_clear(&objective, 2);
+  assert!(progress.is_complete(&objective, 0.));
+
+  // 目標のレベルに上がってから数える
+  let objective = Objective::SurviveAtLevel {
+    seconds: 60.,
+    level: 10,
+  };
+  let mut progress = MissionProgress::default();
+  progress.record_level(&objective, 9, 100.);
+  assert!(!progress.is_complete(&objective, 200.));
+  progress.record_level(&objective, 10, 200.);
+  progress.record_level(&objective, 11, 230.);
+  assert!(!progress.is_complete(&objective, 259.));
+  assert!(progress.is_complete(&objective, 260.));
+}
+
+#[test]
+fn test_puzzle_parse() {
+  let puzzle = puzzle::Puzzle::parse(
+    r#"(
+      name: "test",
+      rows: [
+        "X.........",
+        "XXXX..XXXX",
+      ],
+      queue: "Ot",
+    )"#,
+  )
+  .unwrap();
+  assert_eq!("test", puzzle.name);
+  assert_eq!(9, puzzle.cells.len());
+  assert!(puzzle.cells.contains(&Position { x: 0, y: 1 }));
+  assert!(!puzzle.cells.contains(&Position { x: 4, y: 0 }));
+  assert_eq!(vec![1, 6], puzzle.queue);
+  assert_eq!(puzzle::PuzzleGoal::ClearBoard, puzzle.goal);
+
+  let reparsed = puzzle::Puzzle::parse(&puzzle.to_ron()).unwrap();
+  assert_eq!(puzzle.cells, reparsed.cells);
+  assert_eq!(puzzle.queue, reparsed.queue);
+
+  assert!(puzzle::Puzzle::parse(r#"(name: "bad", rows: ["XXX"], queue: "O")"#).is_err());
+  assert!(puzzle::Puzzle::parse(r#"(name: "bad", rows: [], queue: "Q")"#).is_err());
+}
+
+#[test]
+fn test_cursor_to_position() {
+  assert_eq!(
+    Some(Position { x: 0, y: 0 }),
+    cursor_to_position(Vec2::new(0., 0.), 400., 800.)
+  );
+  assert_eq!(
+    Some(Position { x: 5, y: 10 }),
+    cursor_to_position(Vec2::new(200., 400.), 400., 800.)
+  );
+  assert_eq!(
+    Some(Position { x: 9, y: 19 }),
+    cursor_to_position(Vec2::new(399., 799.), 400., 800.)
+  );
+  assert_eq!(None, cursor_to_position(Vec2::new(400., 10.), 400., 800.));
+}
+
+#[test]
+fn test_board_placements() {
+  // 左端に高さ2の柱
+  let board = board::Board::from_cells(&[Position { x: 0, y: 0 }, Position { x: 0, y: 1 }]);
+  let square = vec![
+    Position { x: 3, y: 19 },
+    Position { x: 4, y: 19 },
+    Position { x: 3, y: 20 },
+    Position { x: 4, y: 20 },
+  ];
+  let placements = board.placements(&square);
+  assert_eq!(9, placements.len());
+  assert_eq!(
+    vec![
+      Position { x: 0, y: 2 },
+      Position { x: 1, y: 2 },
+      Position { x: 0, y: 3 },
+      Position { x: 1, y: 3 }
+    ],
+    placements[0]
+  );
+  assert_eq!(0, placements[1][0].y);
+  assert!(board.is_filled(&Position { x: -1, y: 5 }));
+  assert!(!board.is_filled(&Position { x: 0, y: 2 }));
+}
+
+#[test]
+fn test_best_placement_fills_well() {
+  let mut cells = vec![];
+  for y in 0..4 {
+    for x in 0..9 {
+      cells.push(Position { x, y });
+    }
+  }
+  let board = board::Board::from_cells(&cells);
+  assert_eq!(0, board.holes());
+  assert_eq!(4, board.column_heights()[0]);
+  assert_eq!(4, board.bumpiness());
+  assert_eq!(4, board.max_height());
+
+  let bar: Vec<Position> = (19..23).map(|y| Position { x: 3, y }).collect();
+  let placement = ai::best_placement(&board, &bar, &ai::Weights::default()).unwrap();
+  assert!(placement.iter().all(|p| p.x == 9 && p.y < 4));
+}
+
+#[test]
+fn test_piece_queue_peek() {
+  let mut piece_queue = PieceQueue::default();
+  let next = piece_queue.peek(5);
+  assert_eq!(5, next.len());
+  assert_eq!(Some(next[0]), piece_queue.pop());
+  assert_eq!(next[1..].to_vec(), piece_queue.peek(4));
+
+  // 同じ種で引き直すと、最初から同じ並びで出てくる
+  let ruleset = rules::Ruleset::for_mode(GameMode::Marathon);
+  piece_queue.restart(&ruleset, 42);
+  let first = piece_queue.peek(14);
+  piece_queue.pop();
+  piece_queue.restart(&ruleset, piece_queue.seed);
+  assert_eq!(first, piece_queue.peek(14));
+  piece_queue.reseed(&ruleset);
+  assert_ne!(42, piece_queue.seed);
+
+  let mut fixed = PieceQueue {
+    queue: vec![1, 7].into_iter().collect(),
+    fixed: true,
+    ..Default::default()
+  };
+  assert_eq!(vec![1, 7], fixed.peek(5));
+  assert_eq!(Some(1), fixed.pop());
+  assert_eq!(Some(7), fixed.pop());
+  assert_eq!(None, fixed.pop());
+}
+
+#[test]
+fn test_deal_stats() {
+  use deals::deal_text;
+
+  // O, I, O, Z と配ると、Z は最後まで 3 回、S は一度も、I は最後の 2 回出ていない
+  let mut piece_queue = PieceQueue {
+    queue: vec![1, 7, 1, 2].into_iter().collect(),
+    fixed: true,
+    ..Default::default()
+  };
+  while piece_queue.pop().is_some() {}
+  let dealt = &piece_queue.dealt;
+  assert_eq!(4, dealt.total());
+  assert_eq!([2, 1, 0, 0, 0, 0, 1], dealt.counts);
+  assert_eq!([1, 0, 4, 4, 4, 4, 2], dealt.since);
+  assert_eq!([1, 3, 4, 4, 4, 4, 2], dealt.droughts);
+
+  let text = deal_text(dealt);
+  assert!(text.starts_with("DEALT  4 pieces\nO    2 ####################"));
+  assert!(text.ends_with("I    1 ##########           drought 2"));
+}
+
+#[test]
+fn test_stats_clears_per_minute() {
+  let stats = stats::Statistics {
+    clears: vec![(5., 1), (20., 2), (25., 1), (70., 4)],
+    ..Default::default()
+  };
+  // 30 秒区切りなので1分あたりに直すと2倍
+  assert_eq!(vec![8., 0., 8.], stats.clears_per_minute(30., 75.));
+  assert_eq!(vec![3., 5.], results::downsample(&[1., 3., 2., 5., 4.], 2));
+}
+
+#[test]
+fn test_stats_timeline() {
+  use stats::{Sample, Statistics};
+
+  // 1 秒ごとに 1 つ。フレームが飛んだ分は今の値で埋める
+  let mut stats = Statistics::default();
+  stats.sample(0., 0);
+  assert!(!stats.sample_due(0.9));
+  stats.sent = 2;
+  stats.received = 1;
+  stats.sample(3.2, 4);
+  let sample = Sample {
+    height: 4,
+    sent: 2,
+    received: 1,
+  };
+  assert_eq!(4, stats.timeline.len());
+  assert_eq!(Sample::default(), stats.timeline[0]);
+  assert!(stats.timeline[1..].iter().all(|s| *s == sample));
+  assert!(stats.sample_due(4.));
+}
+
+#[test]
+fn test_announcer_callouts() {
+  use announcer::{Callout, CalloutTracker};
+
+  let mut tracker = CalloutTracker::default();
+  assert_eq!(vec![Callout::Tetris], tracker.record_clear(4));
+  assert_eq!(
+    vec![Callout::BackToBack, Callout::Tetris],
+    tracker.record_clear(4)
+  );
+  // 10 ライン目でレベルアップ
+  assert_eq!(vec![Callout::LevelUp], tracker.record_clear(2));
+  assert_eq!(vec![Callout::Tetris], tracker.record_clear(4));
+
+  // T-spin double は、テトリスの後なら back to back も付く
+  tracker.record_stack(true);
+  assert_eq!(
+    vec![Callout::BackToBack, Callout::TSpinDouble],
+    tracker.record_clear(2)
+  );
+  tracker.record_stack(false);
+  assert!(tracker.record_clear(2).is_empty());
+  // 20 ライン目なのでレベルアップも続けて言う
+  tracker.record_stack(true);
+  assert_eq!(
+    vec![Callout::TSpinDouble, Callout::LevelUp],
+    tracker.record_clear(2)
+  );
+}
+
+#[test]
+fn test_t_spin_corners() {
+  // T ミノが下向きに入った穴。中心の斜めは下の 2 マスと右上が埋まっている
+  let board = board::Board::from_rows(vec![0b1111111011, 0b1111110000, 0b0000001000]);
+  let t_down = [
+    Position { x: 1, y: 1 },
+    Position { x: 2, y: 1 },
+    Position { x: 3, y: 1 },
+    Position { x: 2, y: 0 },
+  ];
+  assert!(board.is_t_spin(&t_down));
+  // 空の盤面では斜めはどれも空いている
+  assert!(!board::Board::default().is_t_spin(&t_down));
+  // T ミノ以外の形は中心が無い
+  let i_piece: Vec<Position> = (0..4).map(|x| Position { x, y: 5 }).collect();
+  assert!(!board.is_t_spin(&i_piece));
+}
+
+#[test]
+fn test_piece_queue_pattern() {
+  let mut piece_queue = PieceQueue::default();
+  piece_queue.set_pattern(practice::parse_sequence("TI").unwrap());
+  assert_eq!(vec![6, 7, 6, 7, 6], piece_queue.peek(5));
+  assert_eq!(Some(6), piece_queue.pop());
+
+  assert!(practice::parse_sequence("TX").is_none());
+}
+
+#[test]
+fn test_randomizer_bags() {
+  use randomizer::RandomizerKind;
+
+  let mut bag = RandomizerKind::Bag.build(1);
+  for _ in 0..10 {
+    let mut pieces: Vec<u32> = (0..7).map(|_| bag.next()).collect();
+    pieces.sort_unstable();
+    assert_eq!((1..=7).collect::<Vec<_>>(), pieces);
+  }
+
+  let mut double_bag = RandomizerKind::DoubleBag.build(2);
+  for _ in 0..10 {
+    let mut counts = [0; 7];
+    for _ in 0..14 {
+      counts[(double_bag.next() - 1) as usize] += 1;
+    }
+    assert_eq!([2; 7], counts);
+  }
+
+  let mut memoryless = RandomizerKind::Memoryless.build(3);
+  let mut counts = [0; 7];
+  for _ in 0..7000 {
+    counts[(memoryless.next() - 1) as usize] += 1;
+  }
+  assert!(counts.iter().all(|&n| n > 800 && n < 1200));
+}
+
+#[test]
+fn test_piece_queue_lookahead() {
+  use randomizer::{PieceQueue, RandomizerKind};
+  use sim::Action;
+  use tetris::server::Match;
+
+  // 袋を 2 つ以上またいで覗いても、引いたときと同じ並び
+  let mut queue = PieceQueue::new(RandomizerKind::Bag.build(5));
+  let peeked = queue.peek(16);
+  assert_eq!(peeked[..3].to_vec(), queue.peek(3));
+  let popped: Vec<u32> = (0..16).map(|_| queue.pop()).collect();
+  let mut bag = RandomizerKind::Bag.build(5);
+  let drawn: Vec<u32> = (0..16).map(|_| bag.next()).collect();
+  assert_eq!(peeked, popped);
+  assert_eq!(drawn, popped);
+
+  // 見えている次のミノが先頭に来て、その先も置いた後に出てくる順と合う
+  let mut game = Match::new(2, 9);
+  let preview = game.preview(0, 8);
+  assert_eq!(
+    game.state(0).queue.iter().copied().collect::<Vec<_>>(),
+    preview[..game.state(0).queue.len()].to_vec()
+  );
+  let mut spawned = vec![];
+  for _ in 0..8 {
+    game.input(0, Action::HardDrop).unwrap();
+    game.tick(0.1);
+    spawned.push(game.state(0).active.as_ref().unwrap().block_idx);
+  }
+  assert_eq!(preview, spawned);
+}
+
+#[test]
+fn test_randomizer_tgm_history() {
+  use randomizer::RandomizerKind;
+
+  for seed in 0..20 {
+    let first = RandomizerKind::TgmHistory.build(seed).next();
+    assert!(![1, 2, 3].contains(&first), "first piece {}", first);
+  }
+
+  // 引き直しがあるので同じミノが続くことはまれ
+  let mut tgm = RandomizerKind::TgmHistory.build(4);
+  let pieces: Vec<u32> = (0..10000).map(|_| tgm.next()).collect();
+  let repeats = pieces.windows(2).filter(|w| w[0] == w[1]).count();
+  assert!(repeats < 500, "{} repeats", repeats);
+}
+
+#[test]
+fn test_garbage_one_hole_per_row() {
+  use garbage::{GarbageConfig, GarbageGenerator, HolePattern};
+
+  let holes = |pattern| -> Vec<i32> {
+    let mut generator = GarbageGenerator::new(&GarbageConfig { pattern }, 5);
+    (0..12)
+      .map(|y| {
+        let row = generator.row(y);
+        assert_eq!(ARENA_WIDTH as usize - 1, row.len());
+        assert!(row.iter().all(|p| p.y == y));
+        (0..ARENA_WIDTH as i32)
+          .find(|&x| !row.iter().any(|p| p.x == x))
+          .unwrap()
+      })
+      .collect()
+  };
+
+  let same = holes(HolePattern::Same);
+  assert!(same.iter().all(|&x| x == same[0]));
+
+  let shifted = holes(HolePattern::ShiftEvery(3));
+  for (i, chunk) in shifted.chunks(3).enumerate() {
+    assert!(chunk.iter().all(|&x| x == chunk[0]));
+    if i > 0 {
+      assert_ne!(shifted[(i - 1) * 3], chunk[0]);
+    }
+  }
+
+  let random = holes(HolePattern::Random);
+  assert!(random.iter().all(|&x| x >= 0 && x < ARENA_WIDTH as i32));
+}
+
+#[test]
+fn test_rotation_kicks() {
+  use board::Board;
+  use rotation::{rotate, KickTable};
+
+  let cells = |v: &[(i32, i32)]| -> Vec<Position> {
+    let mut cells: Vec<Position> = v.iter().map(|&(x, y)| Position { x, y }).collect();
+    cells.sort_by_key(|p| (p.y, p.x));
+    cells
+  };
+  let sorted = |mut v: Vec<Position>| -> Vec<Position> {
+    v.sort_by_key(|p| (p.y, p.x));
+    v
+  };
+  let srs = KickTable::srs();
+  let empty = Board::default();
+
+  // 4回まわすと元に戻る
+  let t = cells(&[(4, 5), (5, 5), (6, 5), (5, 6)]);
+  let mut current = (t.clone(), 0);
+  for _ in 0..4 {
+    let (cells, state, _) = rotate(&empty, &srs, &current.0, 6, current.1, true).unwrap();
+    current = (cells, state);
+  }
+  assert_eq!((t, 0), (sorted(current.0), current.1));
+
+  // 床に埋まる分は上にずらす
+  let t = cells(&[(4, 0), (5, 0), (6, 0), (5, 1)]);
+  let (rotated, state, kick) = rotate(&empty, &srs, &t, 6, 0, true).unwrap();
+  assert_eq!((1, (-1, 1)), (state, kick));
+  assert_eq!(cells(&[(4, 0), (4, 1), (5, 1), (4, 2)]), sorted(rotated));
+
+  // 左の壁際の I は右にずらす
+  let i = cells(&[(0, 0), (0, 1), (0, 2), (0, 3)]);
+  let (rotated, _, _) = rotate(&empty, &srs, &i, 7, 0, true).unwrap();
+  assert_eq!(cells(&[(0, 2), (1, 2), (2, 2), (3, 2)]), sorted(rotated));
+
+  // キックが (0, 0) だけなら回れない
+  let no_kicks = KickTable::parse(r#"(name: "none", pieces: {'I': {"0>R": [(0, 0)]}})"#).unwrap();
+  assert_eq!(None, rotate(&empty, &no_kicks, &i, 7, 0, true));
+
+  let invalid = [
+    r#"(name: "x", pieces: {'Q': {"0>R": [(0, 0)]}})"#,
+    r#"(name: "x", pieces: {'T': {"0>2": [(0, 0)]}})"#,
+    r#"(name: "x", pieces: {'T': {"0>R": []}})"#,
+    r#"(name: "x", pieces: {'T': {"0>R": [(4, 0)]}})"#,
+  ];
+  for src in invalid.iter() {
+    assert!(KickTable::parse(src).is_err(), "{}", src);
+  }
+  let srs_plus = std::fs::read_to_string("assets/kicks/srs_plus.ron").unwrap();
+  assert_eq!("SRS+", KickTable::parse(&srs_plus).unwrap().name);
+}
+
+#[test]
+fn test_event_log_json() {
+  use eventlog::{GameLog, LogEvent};
+
+  let mut log = GameLog::default();
+  log.push(0.5, LogEvent::Spawn { piece: 'T' });
+  log.push(
+    1.,
+    LogEvent::Rotate {
+      from: '0',
+      to: 'R',
+      kick: (-1, 1),
+    },
+  );
+  log.push(
+    2.,
+    LogEvent::Clear {
+      lines: 4,
+      kind: eventlog::clear_kind(4).to_string(),
+    },
+  );
+  let json: serde_json::Value = serde_json::from_str(&log.to_json().unwrap()).unwrap();
+  let events = json["events"].as_array().unwrap();
+  assert_eq!(3, events.len());
+  assert_eq!("spawn", events[0]["type"]);
+  assert_eq!("T", events[0]["piece"]);
+  assert_eq!(serde_json::json!([-1, 1]), events[1]["kick"]);
+  assert_eq!("tetris", events[2]["kind"]);
+  assert_eq!(2., events[2]["time"]);
+}
+
+#[test]
+fn test_profile_auto_shift() {
+  use profile::{AutoShift, Handling, ProfileList};
+  use speed::TickRate;
+
+  let handling = Handling {
+    das_ms: 200,
+    arr_ms: 100,
+  };
+  let rate = TickRate::Hz60;
+  let mut shift = AutoShift::default();
+  let moves: Vec<bool> = (0..8)
+    .map(|_| shift.update(Some(Direction::Left), 0.05, &handling, rate))
+    .collect();
+  // 押した瞬間に1回、0.2 秒後から 0.1 秒ごと
+  assert_eq!(
+    vec![true, false, false, false, true, false, true, false],
+    moves
+  );
+  assert!(!shift.update(None, 0.05, &handling, rate));
+  assert!(shift.update(Some(Direction::Right), 0.05, &handling, rate));
+
+  let mut list = ProfileList::default();
+  assert!(list.add("Alice"));
+  assert!(!list.add("alice"));
+  assert!(!list.add("../etc"));
+  assert!(!list.add(""));
+  assert_eq!("Alice", list.current_name());
+  assert!(list.remove_current());
+  assert!(!list.remove_current());
+  assert_eq!("Player", list.current_name());
+}
+
+#[test]
+fn test_achievement_checker() {
+  use achievements::{Achievement, AchievementChecker};
+
+  let mut checker = AchievementChecker::default();
+  checker.record_lock();
+  assert_eq!(vec![Achievement::FirstTetris], checker.record_clear(4, 1., 4));
+
+  // ラインを消さずに積むとコンボが切れる
+  checker.record_lock();
+  checker.record_lock();
+  let mut unlocked = vec![];
+  for i in 0..10 {
+    checker.record_lock();
+    unlocked = checker.record_clear(1, 10. + i as f64, 20);
+  }
+  assert_eq!(vec![Achievement::Combo], unlocked);
+
+  let mut checker = AchievementChecker::default();
+  for _ in 0..9 {
+    checker.record_clear(4, 50., 100);
+  }
+  checker.record_lock();
+  let unlocked = checker.record_clear(4, 59., 140);
+  assert!(unlocked.contains(&Achievement::HundredLines));
+  assert!(unlocked.contains(&Achievement::Sprint));
+  assert!(!checker.record_clear(1, 61., 141).contains(&Achievement::Sprint));
+}
+
+#[test]
+fn test_toast_visibility() {
+  use toast::toast_visibility;
+
+  assert_eq!(0., toast_visibility(0.));
+  assert!((toast_visibility(0.15) - 0.5).abs() < 1e-5);
+  assert_eq!(1., toast_visibility(1.));
+  assert!((toast_visibility(2.95) - 0.5).abs() < 1e-5);
+  assert_eq!(0., toast_visibility(5.));
+}
+
+#[test]
+fn test_slow_motion() {
+  use assist::{cycle_slow_motion, slowed_tick, AssistUsed};
+
+  // 0.5 倍なら重力は 2 回に 1 回だけ進む
+  let mut progress = 0.;
+  let ticks: Vec<bool> = (0..6).map(|_| slowed_tick(&mut progress, 0.5)).collect();
+  assert_eq!(vec![false, true, false, true, false, true], ticks);
+  let mut progress = 0.;
+  assert!((0..5).all(|_| slowed_tick(&mut progress, 1.)));
+
+  assert_eq!(0.75, cycle_slow_motion(1., true));
+  assert_eq!(0.25, cycle_slow_motion(1., false));
+  assert_eq!(1., cycle_slow_motion(0.25, true));
+
+  assert_eq!(None, AssistUsed::default().describe());
+  let used = AssistUsed {
+    slow_motion: true,
+    auto_hold: true,
+  };
+  assert_eq!(
+    Some("assisted: slow motion, auto hold".to_string()),
+    used.describe()
+  );
+}
+
+#[test]
+fn test_assist_best_rotation() {
+  use assist::best_move_at_column;
+  use sim::{Action, GameState};
+
+  // I ミノは縦で出てくる。空の盤面なら横に回して寝かせるのが一番良い
+  let state = GameState::new(vec![7]);
+  let (mut placed, actions) = best_move_at_column(&state, 0, &ai::Weights::default()).unwrap();
+  placed.sort_by_key(|p| p.x);
+  let bottom: Vec<Position> = (0..4).map(|x| Position { x, y: 0 }).collect();
+  assert_eq!(bottom, placed);
+  assert!(actions.contains(&Action::RotateCw) || actions.contains(&Action::RotateCcw));
+  assert_eq!(Some(&Action::HardDrop), actions.last());
+
+  // 左端の 4 段の井戸には縦のまま入れて 4 ライン消す
+  let mut state = GameState::new(vec![7]);
+  state.board = board::Board::from_rows(vec![0b11_1111_1110; 4]);
+  let (placed, actions) = best_move_at_column(&state, 0, &ai::Weights::default()).unwrap();
+  assert!(placed.iter().all(|p| p.x == 0));
+  let placed_state = actions
+    .iter()
+    .fold(state, |state, &action| state.apply(action));
+  assert_eq!(4, placed_state.lines);
+
+  // 盤面の外の列は None
+  assert!(best_move_at_column(&placed_state, 20, &ai::Weights::default()).is_none());
+}
+
+#[test]
+fn test_auto_hold() {
+  use ai::fits_cleanly;
+  use sim::GameState;
+
+  // 1 マスおきに埋まった床には、O はどこに置いても穴ができ、I は縦にすれば収まる
+  let cells: Vec<Position> = (0..ARENA_WIDTH as i32)
+    .filter(|x| x % 2 == 1)
+    .map(|x| Position { x, y: 0 })
+    .collect();
+  let board = board::Board::from_cells(&cells);
+  let with = |block_idx: u32| GameState {
+    board: board.clone(),
+    ..GameState::new(vec![block_idx])
+  };
+  assert!(!fits_cleanly(&with(1)));
+  assert!(fits_cleanly(&with(7)));
+  assert!(fits_cleanly(&GameState::new(vec![1])));
+}
+
+#[test]
+fn test_guide_columns() {
+  use ghost::guide_columns;
+
+  // 縦の I は 1 列、横の T は 3 列
+  let i: Vec<Position> = (0..4).map(|y| Position { x: 4, y }).collect();
+  assert_eq!(vec![4], guide_columns(&i));
+  let t = [
+    Position { x: 5, y: 1 },
+    Position { x: 3, y: 1 },
+    Position { x: 4, y: 1 },
+    Position { x: 4, y: 2 },
+  ];
+  assert_eq!(vec![3, 4, 5], guide_columns(&t));
+  assert!(guide_columns(&[]).is_empty());
+}
+
+#[test]
+fn test_drop_ticker() {
+  use eventlog::{GameLog, LogEvent};
+  use ticker::{ticker_text, TICKER_LEN};
+
+  let mut log = GameLog::default();
+  let pieces = "TOISZLJTOIS";
+  for (i, piece) in pieces.chars().enumerate() {
+    log.push(i as f64, LogEvent::Spawn { piece });
+    log.push(
+      i as f64,
+      LogEvent::Lock {
+        piece,
+        cells: vec![],
+      },
+    );
+    if piece == 'I' {
+      log.push(
+        i as f64,
+        LogEvent::Clear {
+          lines: 4,
+          kind: "tetris".to_string(),
+        },
+      );
+    }
+  }
+  // 最初の T はもう流れている
+  let drops = log.recent_drops(TICKER_LEN);
+  assert_eq!(TICKER_LEN, drops.len());
+  assert_eq!(('O', 0), drops[0]);
+  assert_eq!(('I', 4), drops[1]);
+  assert_eq!("O I4 S Z L\nJ T O I4 S", ticker_text(&drops));
+  assert_eq!(
+    "",
+    ticker_text(&GameLog::default().recent_drops(TICKER_LEN))
+  );
+}
+
+#[test]
+fn test_gravity_curve() {
+  use speed::{
+    DropAccumulator, Gravity, GravityCurve, SpeedTable, TickRate, G, SOFT_DROP, TICK_SECONDS,
+  };
+
+  // いつもの表はレベルによらず同じ速さ
+  let standard = GravityCurve::new(SpeedTable::Standard, &[]);
+  let half_second = Gravity::from_seconds_per_row(GRAVITY_STEP as f32);
+  assert_eq!(half_second, standard.gravity(0));
+  assert_eq!(half_second, standard.gravity(20));
+  // 0.5 秒に 1 段は 1/30 G
+  assert_eq!((G as f32 / 30.).round() as u32, half_second.0);
+
+  // どの表もレベルが上がると遅くはならない
+  for &table in SpeedTable::ALL.iter() {
+    let curve = GravityCurve::new(table, &[1., 0.5, 0.25]);
+    for level in 0..40 {
+      assert!(curve.gravity(level + 1) >= curve.gravity(level));
+    }
+  }
+  // TGM の最初は 4/256 G、最後は 20G
+  let tgm = GravityCurve::new(SpeedTable::Tgm, &[]);
+  assert_eq!(Gravity(G / 64), tgm.gravity(0));
+  assert_eq!(Gravity(20 * G), tgm.gravity(99));
+
+  // 自作の表は表の先では最後の速さのまま。壊れた表ならいつもの速さ
+  let custom = GravityCurve::new(SpeedTable::Custom, &[1., 0.5]);
+  assert_eq!(Gravity::from_seconds_per_row(0.5), custom.gravity(7));
+  assert_eq!(standard, GravityCurve::new(SpeedTable::Custom, &[1., 0.]));
+
+  // 1/16 G は 16 tick で 1 段、20G は 1 tick で盤面の高さまで
+  let mut drop = DropAccumulator::default();
+  let slow = Gravity(G / 16);
+  let rate = TickRate::Hz60;
+  assert_eq!(0, drop.advance(TICK_SECONDS * 15.5, slow, rate));
+  assert_eq!(1, drop.advance(TICK_SECONDS, slow, rate));
+  assert_eq!(0, drop.advance(TICK_SECONDS / 2., slow, rate));
+  assert_eq!(20, drop.advance(TICK_SECONDS, Gravity(20 * G), rate));
+  assert_eq!(
+    ARENA_HEIGHT,
+    drop.advance(TICK_SECONDS * 10., Gravity(20 * G), rate)
+  );
+  // 急降下は 1 tick に 1 段
+  drop.reset();
+  assert_eq!(
+    3,
+    drop.advance(TICK_SECONDS * 3.5, SOFT_DROP.max(slow), rate)
+  );
+
+  assert_eq!(SpeedTable::Custom, SpeedTable::Standard.cycle(false));
+  assert_eq!(SpeedTable::Tgm, SpeedTable::Standard.cycle(true));
+}
+
+#[test]
+fn test_master_grade() {
+  use master::{Master, GRADES, ROLL_SECONDS, SECTIONS};
+
+  // テトリスだけで 100 ライン消せば S9。区間ごとにタイムが残る
+  let mut master = Master::new(0.);
+  for i in 0..25 {
+    master.record_clear(4, (i + 1) as f64 * 10.);
+  }
+  assert_eq!(SECTIONS, master.splits.len());
+  assert_eq!("S9", GRADES[master.grade()]);
+  assert!(master.in_roll());
+  // スタッフロールを生き延びて GM
+  assert!(!master.update_roll(250. + ROLL_SECONDS / 2.));
+  assert!(master.update_roll(250. + ROLL_SECONDS));
+  assert_eq!("GM", GRADES[master.grade()]);
+
+  // シングルばかりでは段位はあまり上がらず、遅ければ GM の条件から外れる
+  let mut slow = Master::new(0.);
+  for i in 0..100 {
+    slow.record_clear(1, (i + 1) as f64 * 10.);
+  }
+  assert!(!slow.on_track);
+  assert!(slow.grade() < master.grade());
+  slow.update_roll(1000. + ROLL_SECONDS);
+  assert_ne!("GM", GRADES[slow.grade()]);
+}
+
+#[test]
+fn test_section_splits() {
+  use splits::{section_splits, split_delta, BestSplits};
+
+  // 4 ラインずつ消すと、10 ライン目と 20 ライン目を越えたところで区切る
+  let clears: Vec<(f64, u32)> = (1..=6).map(|i| (i as f64 * 10., 4)).collect();
+  assert_eq!(vec![30., 20.], section_splits(&clears));
+  assert!(section_splits(&[]).is_empty());
+
+  let mut best = BestSplits(vec![25., 25.]);
+  assert!(best.merge(&[30., 20., 40.]));
+  assert_eq!(BestSplits(vec![25., 20., 40.]), best);
+  assert!(!best.merge(&[26.]));
+
+  assert_eq!("+5.0", split_delta(30., Some(25.)));
+  assert_eq!("-1.5", split_delta(20., Some(21.5)));
+  assert_eq!("", split_delta(20., None));
+}
+
+#[test]
+fn test_hot_seat_turns() {
+  use party::HotSeat;
+
+  // 2 ミノずつ交代する。消したラインは最後に置いた人の分
+  let mut seat = HotSeat::new(3, 2);
+  assert!(!seat.record_piece());
+  assert!(seat.record_piece());
+  assert_eq!(1, seat.turn);
+  seat.record_lines(2);
+  seat.record_piece();
+  seat.record_lines(1);
+  seat.record_piece();
+  seat.record_piece();
+  seat.record_piece();
+  assert_eq!(0, seat.turn);
+  assert_eq!(vec![2, 2, 2], seat.pieces);
+  assert_eq!(vec![2, 1, 0], seat.lines);
+  assert_ne!(seat.preset(0), seat.preset(1));
+
+  // 人数と交代までの数は使える範囲に収める
+  let clamped = HotSeat::new(9, 0);
+  assert_eq!(party::MAX_PLAYERS, clamped.players);
+  assert_eq!(1, clamped.pieces_per_turn);
+}
+
+#[test]
+fn test_coop_board() {
+  use coop::{Coop, COOP_WIDTH};
+  use sim::Action;
+
+  let mut coop = Coop::new(1);
+  // 2 人のミノはそれぞれの半分に出る
+  let xs = |coop: &Coop, i: usize| -> Vec<i32> {
+    let piece = coop.players[i].piece.as_ref().unwrap();
+    piece.cells.iter().map(|c| c.x).collect()
+  };
+  assert!(xs(&coop, 0).iter().all(|&x| x < COOP_WIDTH / 2));
+  assert!(xs(&coop, 1).iter().all(|&x| x >= COOP_WIDTH / 2));
+
+  // 1P を右へ寄せ続けても 2P のミノには重ならない
+  for _ in 0..COOP_WIDTH {
+    coop.apply(0, Action::Right);
+  }
+  let p1 = coop.players[0].piece.clone().unwrap();
+  let p2 = coop.players[1].piece.clone().unwrap();
+  assert!(p1.cells.iter().all(|c| !p2.cells.contains(c)));
+  assert!(coop.fits(0, &p1.cells));
+
+  // 固定すると次のミノが出て、置いた数は人ごとに数える
+  assert!(coop.apply(1, Action::HardDrop));
+  assert_eq!(1, coop.players[1].pieces);
+  assert_eq!(0, coop.players[0].pieces);
+  assert!(!coop.game_over);
+
+  // 出る場所に 1P のミノがいたら、2P はどくまで待つ
+  if coop.players[1].piece.is_none() {
+    for _ in 0..COOP_WIDTH {
+      coop.apply(0, Action::Left);
+    }
+    coop.fall(1);
+  }
+  assert!(coop.players[1].piece.is_some());
+}
+
+#[test]
+fn test_key_presets() {
+  use profile::{Controls, Preset};
+
+  // どの配置でも、1 つのキーに 2 つの操作は割り当てない
+  for preset in Preset::ALL.iter() {
+    let b = preset.bindings();
+    let keys: Vec<KeyCode> = [
+      &b.left,
+      &b.right,
+      &b.soft_drop,
+      &b.rotate_cw,
+      &b.rotate_ccw,
+      &b.hold,
+    ]
+    .iter()
+    .flat_map(|keys| keys.iter().copied())
+    .collect();
+    for (i, key) in keys.iter().enumerate() {
+      assert!(!keys[i + 1..].contains(key), "{:?} {:?}", preset, key);
+    }
+    assert_eq!(Some(*preset), Preset::matching(&b));
+  }
+
+  let mut controls = Controls::default();
+  controls.cycle_preset(true);
+  assert_eq!(Some(Preset::LeftHand), Preset::matching(&controls.bindings));
+  controls.cycle_preset(false);
+  controls.cycle_preset(false);
+  assert_eq!(Some(Preset::Numpad), Preset::matching(&controls.bindings));
+  // 手で書き換えた配置からは最初の配置に戻る
+  controls.bindings.hold = vec![KeyCode::Space];
+  assert_eq!(None, Preset::matching(&controls.bindings));
+  controls.cycle_preset(true);
+  assert_eq!(Some(Preset::Standard), Preset::matching(&controls.bindings));
+}
+
+#[test]
+fn test_key_rebinding() {
+  use profile::{Binding, KeyBindings, Resolve};
+
+  let mut b = KeyBindings::default();
+  assert_eq!(Ok(()), b.validate());
+  // 空いているキーは 1 つ目のキーと入れ替える
+  assert_eq!(Ok(()), b.rebind(Binding::Hold, KeyCode::Space));
+  assert_eq!(vec![KeyCode::Space, KeyCode::LShift], b.hold);
+  // 使われているキーは変えずに、使っている操作を返す
+  assert_eq!(Err(Binding::RotateCcw), b.rebind(Binding::Hold, KeyCode::Z));
+  assert_eq!(vec![KeyCode::Space, KeyCode::LShift], b.hold);
+  assert_eq!(Some(Binding::RotateCcw), b.owner(KeyCode::Z, Binding::Hold));
+
+  // 入れ替えると、相手は手放したキーを受け取る
+  b.rebind_resolving(Binding::Hold, KeyCode::Z, Resolve::Swap);
+  assert_eq!(vec![KeyCode::Z, KeyCode::LShift], b.hold);
+  assert_eq!(vec![KeyCode::Space, KeyCode::LControl], b.rotate_ccw);
+  assert_eq!(Ok(()), b.validate());
+
+  // 外すと、相手にキーが無くなれば保存できない
+  b.rebind_resolving(Binding::Left, KeyCode::Right, Resolve::Clear);
+  assert_eq!(vec![KeyCode::Right], b.left);
+  assert!(b.right.is_empty());
+  assert_eq!(Err("right has no key".to_string()), b.validate());
+
+  let mut b = KeyBindings::default();
+  b.hold.push(KeyCode::Up);
+  assert_eq!(
+    vec![(KeyCode::Up, Binding::RotateCw, Binding::Hold)],
+    b.conflicts()
+  );
+  assert!(b.validate().is_err());
+}
+
+#[test]
+fn test_audio_cues() {
+  use cues::{landing_pitch, piece_motif};
+
+  // 7 種類すべて違う旋律で、知らないミノは鳴らさない
+  let motifs: Vec<Vec<u32>> = (1..=7).map(piece_motif).collect();
+  for (i, motif) in motifs.iter().enumerate() {
+    assert!(!motif.is_empty());
+    assert!(!motifs[i + 1..].contains(motif));
+  }
+  assert!(piece_motif(0).is_empty());
+
+  // 着地に近いほど高く、盤面の外は端の高さ
+  assert!(landing_pitch(0) > landing_pitch(1));
+  assert!(landing_pitch(1) > landing_pitch(19));
+  assert_eq!(220, landing_pitch(20));
+  assert_eq!(landing_pitch(20), landing_pitch(30));
+  assert_eq!(landing_pitch(0), landing_pitch(-1));
+}
+
+#[test]
+fn test_accessibility_scale() {
+  use accessibility::Accessibility;
+
+  // 文字の倍率は段ごとに変わり、端で止まる
+  let mut accessibility = Accessibility::default();
+  accessibility.step_scale(false);
+  assert_eq!(1., accessibility.ui_scale);
+  accessibility.step_scale(true);
+  assert_eq!(1.5, accessibility.ui_scale);
+  accessibility.step_scale(true);
+  accessibility.step_scale(true);
+  assert_eq!(2., accessibility.ui_scale);
+
+  // 前の版のファイルや手で書いた中途半端な値でも読める
+  let old: Accessibility = ron::de::from_str("(high_contrast: true)").unwrap();
+  assert!(old.high_contrast && old.border() == 0.);
+  let mut odd: Accessibility = ron::de::from_str("(ui_scale: 1.2)").unwrap();
+  odd.step_scale(true);
+  assert_eq!(2., odd.ui_scale);
+}
+
+#[test]
+fn test_fade_toward() {
+  use achievements::fade_toward;
+
+  // 1 回に動くのは各色 step まで。行き過ぎずに目標で止まる
+  let from = Color::rgb(0., 0.5, 1.);
+  let to = Color::rgb(0.1, 0.5, 0.);
+  let once = fade_toward(from, to, 0.25);
+  assert!((once.r() - 0.1).abs() < 1e-6);
+  assert_eq!(0.5, once.g());
+  assert!((once.b() - 0.75).abs() < 1e-6);
+  let mut color = from;
+  for _ in 0..4 {
+    color = fade_toward(color, to, 0.25);
+  }
+  assert_eq!(to, color);
+}
+
+#[test]
+fn test_layout() {
+  use layout::Layout;
+
+  let narrow = Layout::compute(400., 800.);
+  assert_eq!(40., narrow.cell);
+  assert!(!narrow.side_panels);
+
+  // 横長なら盤面を中央に置き、左右にパネルを並べる
+  let wide = Layout::compute(1600., 800.);
+  assert_eq!(40., wide.cell);
+  assert!(wide.side_panels);
+  assert_eq!(600., wide.board_left);
+
+  let pos = Position { x: 3, y: 7 };
+  let world = wide.world_position(&pos);
+  let cursor = world + Vec2::new(800., 400.);
+  assert_eq!(Some(pos), wide.cell_at(cursor));
+  assert_eq!(None, wide.cell_at(Vec2::new(100., 400.)));
+}
+
+#[test]
+fn test_mirrored_layout() {
+  use layout::Layout;
+
+  // 反転すると左端の列が右端に描かれ、カーソルからは元の列に戻る
+  let plain = Layout::compute(1600., 800.);
+  let mirrored = Layout {
+    mirrored: true,
+    ..plain
+  };
+  let left = Position { x: 0, y: 5 };
+  let right = Position {
+    x: ARENA_WIDTH as i32 - 1,
+    y: 5,
+  };
+  assert_eq!(plain.world_position(&right), mirrored.world_position(&left));
+  let cursor = mirrored.world_position(&left) + Vec2::new(800., 400.);
+  assert_eq!(Some(left), mirrored.cell_at(cursor));
+}
+
+#[test]
+fn test_zoomed_layout() {
+  use layout::{step_zoom, Layout};
+
+  // 拡大してずらしても、マスの位置は変えずにカーソルから同じマスに戻る
+  let plain = Layout::compute(1600., 800.);
+  let zoomed = Layout {
+    zoom: 1.5,
+    offset: Vec2::new(-200., 40.),
+    ..plain
+  };
+  let pos = Position { x: 2, y: 9 };
+  assert_eq!(plain.world_position(&pos), zoomed.world_position(&pos));
+  let cursor = zoomed.zoomed(zoomed.world_position(&pos) + Vec2::new(800., 400.));
+  assert_eq!(Some(pos), zoomed.cell_at(cursor));
+  assert_eq!(None, plain.cell_at(cursor));
+
+  // カメラは逆向きに動かして狭く映す
+  let camera = zoomed.camera_transform(&Transform::from_xyz(0., 0., 999.9));
+  assert_eq!(Vec3::new(200. / 1.5, -40. / 1.5, 999.9), camera.translation);
+  assert_eq!(1. / 1.5, camera.scale.x);
+
+  assert_eq!(1.1, step_zoom(1., 1));
+  assert_eq!(2., step_zoom(1.95, 3));
+  assert_eq!(0.5, step_zoom(0.5, -1));
+}
+
+#[test]
+fn test_chaos_modifiers() {
+  use chaos::{Chaos, CHAOS_INTERVAL};
+  use rules::{Modifier, Modifiers, Ruleset};
+
+  // 30 秒ごとに前と違う修飾に替わる
+  let mut chaos = Chaos::new(7, 0.);
+  let first = chaos.update(0.).unwrap();
+  assert_eq!(None, chaos.update(CHAOS_INTERVAL - 1.));
+  let second = chaos.update(CHAOS_INTERVAL).unwrap();
+  assert_ne!(first, second);
+  assert_eq!(2, chaos.count);
+
+  // 修飾を替えても、外せば元のルールに戻る
+  let base = Ruleset::for_mode(GameMode::Chaos);
+  let mut ruleset = base;
+  let mut modifiers = Modifiers::default();
+  modifiers.switch(&mut ruleset, Some(Modifier::HiddenNext));
+  assert_eq!(0, ruleset.previews);
+  modifiers.switch(&mut ruleset, Some(Modifier::Mirror));
+  assert_eq!(base.previews, ruleset.previews);
+  assert!(ruleset.mirror && ruleset.mirror_input);
+  modifiers.switch(&mut ruleset, Some(Modifier::FastGravity));
+  assert!(modifiers.gravity_scale() > 1.);
+  modifiers.switch(&mut ruleset, None);
+  assert_eq!(base, ruleset);
+  assert_eq!(1., modifiers.gravity_scale());
+}
+
+#[test]
+fn test_survival_garbage() {
+  use survival::{Survival, SurvivalConfig, SAMPLE_SECONDS};
+
+  // 60 APM なら 1 秒に 1 ライン。端数は次に持ち越す
+  let mut survival = Survival::new(&SurvivalConfig { apm: 60 }, 1);
+  survival.tick(0.5);
+  assert_eq!(0, survival.incoming.rows());
+  survival.tick(2.);
+  assert_eq!(2, survival.incoming.rows());
+
+  // テトリスの攻撃で相殺し、残りは積んだときにせり上がる
+  survival.tick(2.);
+  survival.record_lock();
+  survival.record_clear(4);
+  assert_eq!(0, survival.incoming.rows());
+  survival.tick(SAMPLE_SECONDS);
+  assert_eq!(5, survival.incoming.take());
+  assert_eq!(0, survival.incoming.rows());
+  assert_eq!(vec![0.], survival.samples);
+
+  // 盤面に残った段数から掘った割合を出す
+  survival.garbage_rows = 3;
+  assert_eq!(2, survival.dug());
+  assert_eq!(0.5, survival.efficiency());
+
+  // せり上がりが盤面から消えた分だけ数え、消したラインがなければ 0
+  assert_eq!(0.5, garbage::efficiency(5, 3, 4));
+  assert_eq!(0., garbage::efficiency(5, 8, 4));
+  assert_eq!(0., garbage::efficiency(5, 0, 0));
+
+  // 攻撃はまず相殺に使い、残りを送る。せり上げた分は received に足す
+  let mut queue = garbage::GarbageQueue::default();
+  queue.push(3);
+  assert_eq!(0, queue.cancel(2));
+  assert_eq!(3, queue.cancel(4));
+  assert_eq!(0, queue.rows());
+  queue.push(2);
+  queue.push(1);
+  assert_eq!(3, queue.take());
+  assert_eq!(0, queue.take());
+  assert_eq!(3, queue.received());
+}
+
+#[test]
+fn test_board_text() {
+  use snapshot::board_text;
+
+  // 上の行から並べ、操作中のミノはその名前で書く
+  let board = board::Board::from_rows(vec![0b11_1111_1101, 0b00_0000_0001]);
+  let active = vec![
+    Position { x: 4, y: 2 },
+    Position { x: 5, y: 2 },
+    Position { x: 4, y: 3 },
+    Position { x: 5, y: 3 },
+  ];
+  assert_eq!(
+    "....OO....\n....OO....\nX.........\nX.XXXXXXXX",
+    board_text(&board, &active, Some('O'))
+  );
+  assert_eq!(
+    "..........",
+    board_text(&board::Board::default(), &[], None)
+  );
+}
+
+#[test]
+fn test_parse_board() {
+  use snapshot::{board_text, parse_board};
+
+  // 書き出した文字はそのまま読み戻せる。ミノの名前の文字も埋まったマスになる
+  let board = board::Board::from_rows(vec![0b11_1111_1101, 0b00_0000_0001]);
+  let text = board_text(&board, &[Position { x: 4, y: 2 }], Some('T'));
+  let cells = parse_board(&format!("```\n{}\n```\n", text)).unwrap();
+  assert_eq!(11, cells.len());
+  assert!(cells.contains(&Position { x: 4, y: 2 }));
+  assert_eq!(
+    board,
+    board::Board::from_cells(cells.iter().filter(|p| p.y < 2))
+  );
+
+  // 幅や知らない文字は読み込まない
+  assert!(parse_board("XX..\n").is_err());
+  assert!(parse_board("X....?....").is_err());
+  assert!(parse_board(&"..........\n".repeat(ARENA_HEIGHT as usize + 1)).is_err());
+  assert!(parse_board("").is_err());
+
+  // fumen は最初のページの盤面だけ
+  assert_eq!(Ok(vec![]), parse_board("v115@vhAAgH"));
+  let cells = parse_board("v115@chI8JeAgH").unwrap();
+  assert_eq!(
+    (1..10).map(|x| Position { x, y: 0 }).collect::<Vec<_>>(),
+    cells
+  );
+  assert!(parse_board("v115@ch").is_err());
+}
+
+#[test]
+fn test_personal_best() {
+  use bests::{Best, Metric, PbRun};
+
+  // マラソンは消したライン数が多いほどよい
+  let best = Best {
+    value: 12.,
+    pace: vec![(10., 4), (20., 8), (30., 12)],
+  };
+  let mut run = PbRun::new(Metric::for_mode(GameMode::Marathon), Some(best.clone()));
+  run.record(5., 4);
+  assert_eq!(Some(4), run.delta(5.));
+  assert_eq!(Some(0), run.delta(12.));
+  run.record(25., 12);
+  assert!(!run.is_new_best());
+  run.record(40., 16);
+  assert!(run.is_new_best());
+  assert_eq!(Some(16.), run.result());
+
+  // スプリントは 40 ライン消したときのタイムで、短いほどよい
+  let mut run = PbRun::new(
+    Metric::for_mode(GameMode::Sprint),
+    Some(Best {
+      value: 60.,
+      pace: vec![],
+    }),
+  );
+  run.record(30., 20);
+  assert_eq!(None, run.result());
+  assert!(!run.is_new_best());
+  run.record(55., 40);
+  assert_eq!(Some(55.), run.result());
+  assert!(run.is_new_best());
+
+  // 比べる記録の無いモードは何もしない
+  let run = PbRun::new(Metric::for_mode(GameMode::Puzzle), None);
+  assert_eq!(None, run.summary());
+}
+
+#[test]
+fn test_hud_text() {
+  use hud::{hud_text, HudSettings};
+
+  let stats = stats::Statistics {
+    pieces: [3, 3, 3, 3, 3, 3, 2],
+    clears: vec![(10., 4), (20., 4), (30., 4), (40., 4)],
+    ..Default::default()
+  };
+  // 10 ラインを超えるとレベル 1 なので得点が 2 倍になる
+  assert_eq!(800 * 3 + 1600, stats.score());
+
+  let mut settings = HudSettings::default();
+  assert_eq!(
+    "1:05.00\nscore 4000\n3 combo\n0.31 PPS\n20 pieces",
+    hud_text(&settings, &stats, 3, 65.)
+  );
+  // 切った数字は出さない。コンボは 2 連続から
+  settings.toggle(0);
+  settings.toggle(1);
+  settings.toggle(4);
+  assert_eq!("0.31 PPS", hud_text(&settings, &stats, 1, 65.));
+  assert_eq!([false, false, true, true, false], settings.flags());
+}
+
+#[test]
+fn test_check_board() {
+  use validate::check_board;
+  let cell = |x, y| Position { x, y };
+  let piece = vec![cell(4, 5), cell(5, 5), cell(4, 6), cell(5, 6)];
+  let stacked = vec![cell(0, 0), cell(1, 0), cell(9, 0)];
+  assert!(check_board(&stacked, &piece, true, false).is_empty());
+  assert!(check_board(&stacked, &[], false, false).is_empty());
+
+  // 同じマスに 2 つ積まれている
+  let doubled = vec![cell(0, 0), cell(0, 0)];
+  let errors = check_board(&doubled, &piece, true, false);
+  assert!(errors
+    .iter()
+    .any(|e| e.contains("two stacked cells at (0, 0)")));
+  assert!(errors.iter().any(|e| e.contains("board grid has 1 cells")));
+
+  // 操作中のミノのマス数
+  assert_eq!(
+    check_board(&stacked, &piece[..3], true, false),
+    vec!["active piece has 3 cells, expected 4".to_string()]
+  );
+  assert_eq!(
+    check_board(&stacked, &piece, false, false),
+    vec!["active piece has 4 cells, expected 0".to_string()]
+  );
+
+  // 盤面の外と、積まれたブロックとの重なり
+  let errors = check_board(&[cell(10, 0), cell(4, 5)], &piece, true, false);
+  assert!(errors.iter().any(|e| e.contains("(10, 0) is outside")));
+  assert!(errors.iter().any(|e| e.contains("(4, 5) overlaps")));
+  // 出たばかりで重なったのは top_out に任せる
+  assert!(check_board(&[cell(4, 5)], &piece, true, true).is_empty());
+}
+
+#[test]
+fn test_entry_delays() {
+  use rules::{Ruleset, Timing};
+  use speed::TickRate;
+
+  let ruleset = Ruleset::for_mode(GameMode::Marathon);
+  assert_eq!(Some(Timing::Guideline), Timing::of(&ruleset));
+  assert!((ruleset.entry_seconds(false, TickRate::Hz60) - 0.1).abs() < 1e-6);
+  assert!((ruleset.entry_seconds(true, TickRate::Hz60) - 0.6).abs() < 1e-6);
+  // tick を細かくしても待つ実時間は同じ。端数はその tick に丸める
+  assert!((ruleset.entry_seconds(true, TickRate::Hz240) - 0.6).abs() < 1e-6);
+  let classic = Ruleset {
+    spawn_delay_ms: 167,
+    ..ruleset
+  };
+  assert!((classic.entry_seconds(false, TickRate::Hz60) - 10. / 60.).abs() < 1e-6);
+  assert!((classic.entry_seconds(false, TickRate::Hz240) - 40. / 240.).abs() < 1e-6);
+
+  // 以前の 1 秒待ちも選べる。書き換えた値はプリセット扱いしない
+  let relaxed = Ruleset {
+    spawn_delay_ms: 1000,
+    clear_delay_ms: 0,
+    ..ruleset
+  };
+  assert_eq!(Some(Timing::Relaxed), Timing::of(&relaxed));
+  assert!((relaxed.entry_seconds(true, TickRate::Hz120) - 1.).abs() < 1e-6);
+  assert_eq!(
+    None,
+    Timing::of(&Ruleset {
+      spawn_delay_ms: 1,
+      ..ruleset
+    })
+  );
+
+  // 待ちを持たない古い rules.ron は guideline で読む
+  let old: Ruleset =
+    ron::from_str("(previews: 3, hold: true, ghost: false, mirror: true)").unwrap();
+  assert_eq!(Some(Timing::Guideline), Timing::of(&old));
+  assert_eq!(3, old.previews);
+  // tick 数で持っていた頃の待ちはミリ秒と取り違えないよう読まない
+  let ticks: Ruleset =
+    ron::from_str("(previews: 3, hold: true, ghost: false, spawn_delay: 60)").unwrap();
+  assert_eq!(Some(Timing::Guideline), Timing::of(&ticks));
+}
+
+#[test]
+fn test_rule_presets() {
+  use attack::{AttackTable, AttackTracker};
+  use rotation::{KickTable, RotationSystem};
+  use rules::{Preset, Ruleset};
+  use stats::{Scoring, Statistics};
+
+  let marathon = Ruleset::for_mode(GameMode::Marathon);
+  assert_eq!(Preset::Guideline, Preset::of(GameMode::Marathon, &marathon));
+  // 古い rules.ron は guideline のルールで読む
+  let old: Ruleset = ron::from_str("(previews: 5, hold: true, ghost: true)").unwrap();
+  assert_eq!(Preset::Guideline, Preset::of(GameMode::Marathon, &old));
+
+  // ゴーストと左右反転はプリセットを選んでもそのまま
+  let base = Ruleset {
+    ghost: false,
+    mirror: true,
+    ..marathon
+  };
+  let classic = Preset::Classic.apply(GameMode::Marathon, &base, &marathon);
+  assert_eq!(Preset::Classic, Preset::of(GameMode::Marathon, &classic));
+  assert!(!classic.ghost && classic.mirror && !classic.mirror_input && !classic.hold);
+  assert_eq!(RotationSystem::Classic, classic.rotation);
+  let tgm = Preset::Tgm.apply(GameMode::Marathon, &base, &marathon);
+  assert_eq!(Preset::Tgm, Preset::of(GameMode::Marathon, &tgm));
+  // 1 つでも変えれば Custom。プレビューを出さないモードでは出さない
+  let custom = Ruleset {
+    lock_delay: 1.0,
+    ..tgm
+  };
+  assert_eq!(Preset::Custom, Preset::of(GameMode::Marathon, &custom));
+  let puzzle = Preset::Custom.apply(GameMode::Puzzle, &base, &custom);
+  assert_eq!(
+    (0, false, 1.0),
+    (puzzle.previews, puzzle.hold, puzzle.lock_delay)
+  );
+
+  // 床に埋まる T は SRS だけ上にずらして回せる
+  let empty = board::Board::default();
+  let floor_t: Vec<Position> = [(4, 0), (5, 0), (6, 0), (5, 1)]
+    .iter()
+    .map(|&(x, y)| Position { x, y })
+    .collect();
+  let air_t: Vec<Position> = floor_t
+    .iter()
+    .map(|p| Position { x: p.x, y: p.y + 5 })
+    .collect();
+  for (system, on_floor) in [
+    (RotationSystem::Srs, true),
+    (RotationSystem::Ars, false),
+    (RotationSystem::Classic, false),
+  ] {
+    let table = KickTable::for_system(system);
+    let rotate = |cells: &[Position]| rotation::rotate(&empty, &table, cells, 6, 0, true);
+    assert_eq!(on_floor, rotate(&floor_t).is_some(), "{:?}", system);
+    assert!(rotate(&air_t).is_some(), "{:?}", system);
+  }
+
+  // 得点と攻撃の表
+  let mut stats = Statistics::default();
+  stats.clears.push((1., 4));
+  assert_eq!(800, stats.score());
+  stats.scoring = Scoring::Classic;
+  assert_eq!(1200, stats.score());
+  let mut tracker = AttackTracker::default();
+  tracker.table = AttackTable::Classic;
+  tracker.record_lock();
+  tracker.record_clear(4);
+  tracker.record_lock();
+  assert_eq!(4, tracker.record_clear(4).attack);
+  assert_eq!(6, AttackTable::Guideline.attack(4, 2, true));
+}
+
+#[test]
+fn test_detached_window() {
+  use battle::board_slots;
+  use detach::{detached_stats_text, next_view, DetachView, ORIGIN_X};
+
+  assert_eq!(Some(DetachView::Stats), next_view(None));
+  assert_eq!(
+    Some(DetachView::Opponents),
+    next_view(Some(DetachView::Stats))
+  );
+  assert_eq!(None, next_view(Some(DetachView::Opponents)));
+
+  let stats = stats::Statistics {
+    pieces: [1, 0, 0, 0, 0, 0, 2],
+    clears: vec![(10., 1)],
+    ..Default::default()
+  };
+  let text = detached_stats_text(&stats, 30.);
+  assert!(text.contains("lines 1  level 1"));
+  assert!(text.contains("O 1  Z 0") && text.ends_with("I 2"));
+
+  // 1 つのウィンドウでは左右対称に並べる
+  let main = Vec2::new(800., 1200.);
+  let slots = board_slots(2, main, None);
+  assert_eq!(2, slots.len());
+  assert!((slots[0].center.x + slots[1].center.x).abs() < 1e-3);
+
+  // 相手は別ウィンドウの真ん中に、本体は自分の盤面だけを大きく
+  let slots = board_slots(2, main, Some(Vec2::new(400., 600.)));
+  assert!(slots[0].center.abs_diff_eq(Vec2::ZERO, 1e-3));
+  assert!(slots[1].center.abs_diff_eq(Vec2::new(ORIGIN_X, 0.), 1e-2));
+  assert!(slots[0].cell > board_slots(2, main, None)[0].cell);
+}
+
+#[test]
+fn test_user_skins() {
+  use skins::{next_skin, parse_skin};
+
+  let skin =
+    parse_skin("(active: (1.0, 0.5, 0.0), stacked: (0.2, 0.2, 0.2), texture: Some(\"wood.png\"))")
+      .unwrap();
+  assert_eq!((1.0, 0.5, 0.0), skin.active);
+  assert_eq!(None, skin.background);
+  assert_eq!(Some("wood.png".to_string()), skin.texture);
+  assert!(parse_skin("(active: (1.0, 0.5, 0.0))").is_err());
+
+  // 選ばない → 名前順 → また選ばない。消えたスキンからは最初へ
+  let names = vec!["candy".to_string(), "wood".to_string()];
+  assert_eq!(Some("candy".to_string()), next_skin(&names, None));
+  assert_eq!(Some("wood".to_string()), next_skin(&names, Some("candy")));
+  assert_eq!(None, next_skin(&names, Some("wood")));
+  assert_eq!(Some("candy".to_string()), next_skin(&names, Some("gone")));
+  assert_eq!(None, next_skin(&[], None));
+}
+
+#[test]
+fn test_misdrop() {
+  use ai::is_misdrop;
+  use board::Board;
+
+  let o = |x: i32, y: i32| {
+    vec![
+      Position { x, y },
+      Position { x: x + 1, y },
+      Position { x, y: y + 1 },
+      Position { x: x + 1, y: y + 1 },
+    ]
+  };
+  // 左端に 1 マスだけある盤面に O を重ねて穴を作るのは置き間違い。床に置けば穴はできない
+  let board = Board::from_cells(&[Position { x: 0, y: 0 }]);
+  assert!(is_misdrop(&board, 1, &o(0, 1)));
+  assert!(!is_misdrop(&board, 1, &o(2, 0)));
+
+  // 一段目が市松なら O はどこに置いても穴を作るので、数えない
+  let checkered: Vec<Position> = (0..ARENA_WIDTH as i32)
+    .step_by(2)
+    .map(|x| Position { x, y: 0 })
+    .collect();
+  let board = Board::from_cells(checkered.iter());
+  assert!(!is_misdrop(&board, 1, &o(0, 1)));
+}
+
+#[test]
+fn test_render_summary() {
+  use stats::Statistics;
+  use summary::{render_summary, summary_lines, IMAGE_HEIGHT, IMAGE_WIDTH};
+
+  let font = Font::try_from_bytes(std::fs::read("assets/fonts/DejaVuSans.ttf").unwrap()).unwrap();
+  let stats = Statistics::default();
+  let lines = summary_lines(GameMode::Sprint, 75.5, &stats, 0xbeef);
+  assert_eq!("SPRINT", lines[0]);
+  assert_eq!("time 1:15.50", lines[1]);
+  assert_eq!("seed 000000000000beef", lines[7]);
+
+  // 左下のマスが画像の盤面の左下に来る
+  let cells = vec![(Position { x: 0, y: 0 }, [255, 0, 0])];
+  let image = render_summary(&font.font, &cells, &lines, [10, 10, 10]);
+  assert_eq!((IMAGE_WIDTH, IMAGE_HEIGHT), image.dimensions());
+  assert_eq!([255, 0, 0, 255], image.get_pixel(30, IMAGE_HEIGHT - 30).0);
+  assert_eq!([26, 26, 26, 255], image.get_pixel(30, 30).0);
+  assert_eq!([10, 10, 10, 255], image.get_pixel(5, 5).0);
+  // 右の欄に文字が描かれている
+  let text = (IMAGE_WIDTH - 260..IMAGE_WIDTH)
+    .flat_map(|x| (20..60).map(move |y| (x, y)))
+    .any(|(x, y)| image.get_pixel(x, y).0[0] > 128);
+  assert!(text);
+}
+
+#[test]
+fn test_quick_restart_hold() {
+  use restart::{quick_restart_enabled, RestartHold};
+
+  // 0.3 秒押し続けた 1 回だけやり直す。離すと数え直す
+  let mut hold = RestartHold::default();
+  assert!(!hold.update(true, 0.2, 0.3));
+  assert!((hold.progress(0.3) - 2. / 3.).abs() < 1e-6);
+  assert!(!hold.update(false, 0.2, 0.3));
+  assert_eq!(0., hold.progress(0.3));
+  assert!(!hold.update(true, 0.2, 0.3));
+  assert!(hold.update(true, 0.2, 0.3));
+  assert!(!hold.update(true, 1.0, 0.3));
+  assert!(!hold.update(false, 0.1, 0.3));
+  assert!(hold.update(true, 0.5, 0.3));
+  // 0 秒にすると使わない
+  let mut off = RestartHold::default();
+  assert!(!off.update(true, 10., 0.));
+
+  assert!(quick_restart_enabled(GameMode::Sprint));
+  assert!(!quick_restart_enabled(GameMode::Online));
+}
+
+#[test]
+fn test_lock_highlight_color() {
+  let stacked = Color::rgb(0.1, 0.2, 0.3);
+  assert_eq!(lock_highlight_color(stacked, LOCK_HIGHLIGHT), Color::WHITE);
+  assert_eq!(lock_highlight_color(stacked, 0.), stacked);
+  assert_eq!(lock_highlight_color(stacked, -1.), stacked);
+  let half = lock_highlight_color(stacked, LOCK_HIGHLIGHT / 2.);
+  assert!((half.r() - 0.55).abs() < 1e-5);
+  assert!((half.b() - 0.65).abs() < 1e-5);
+}
+
+#[test]
+fn test_column_labels() {
+  let board = board::Board::from_cells(
+    [
+      Position { x: 0, y: 0 },
+      Position { x: 0, y: 2 },
+      Position { x: 1, y: 1 },
+      Position { x: 3, y: 0 },
+    ]
+    .iter(),
+  );
+  assert_eq!(board.column_holes()[..4], [1, 1, 0, 0]);
+  assert_eq!(board.holes(), 2);
+  let labels = columns::column_labels(&board);
+  assert_eq!(labels.len(), ARENA_WIDTH as usize);
+  assert_eq!(labels[0], "3\n1");
+  assert_eq!(labels[1], "2\n1");
+  assert_eq!(labels[2], "0\n ");
+  assert_eq!(labels[3], "1\n ");
+}
+
+#[test]
+fn test_rumble_pulses() {
+  use rumble::{pulse_magnitude, Pulse};
+
+  let (tetris, _) = Pulse::Clear(4).shape();
+  let (single, _) = Pulse::Clear(1).shape();
+  let (drop, _) = Pulse::HardDrop.shape();
+  assert!(tetris > single && single > drop);
+  assert_eq!(Pulse::Garbage(20).shape().0, 0.8);
+  assert_eq!(pulse_magnitude(Pulse::Clear(4), 1.), u16::MAX);
+  assert_eq!(pulse_magnitude(Pulse::Clear(4), 0.), 0);
+  assert_eq!(pulse_magnitude(Pulse::Clear(4), 2.), u16::MAX);
+  assert!(pulse_magnitude(Pulse::HardDrop, 0.5) < pulse_magnitude(Pulse::HardDrop, 1.));
+}
+
+#[test]
+fn test_menu_navigation() {
+  use menu::{menu_row, MenuInput};
+
+  let down = MenuInput {
+    down: true,
+    ..Default::default()
+  };
+  let up = MenuInput {
+    up: true,
+    ..Default::default()
+  };
+  assert_eq!(down.select(2, 3, None), 0);
+  assert_eq!(up.select(0, 3, None), 2);
+  // マウスより矢印キーを優先し、範囲外の行は無視する
+  assert_eq!(down.select(0, 3, Some(2)), 1);
+  let idle = MenuInput::default();
+  assert_eq!(idle.select(0, 3, Some(2)), 2);
+  assert_eq!(idle.select(1, 3, Some(5)), 1);
+  assert_eq!(idle.select(0, 0, None), 0);
+
+  let click = MenuInput {
+    clicked: true,
+    ..Default::default()
+  };
+  assert_eq!(click.chosen(0, Some(2)), Some(2));
+  assert_eq!(click.chosen(0, None), None);
+  let confirm = MenuInput {
+    confirm: true,
+    ..Default::default()
+  };
+  assert_eq!(confirm.chosen(1, None), Some(1));
+  assert_eq!(
+    MenuInput {
+      left: true,
+      ..Default::default()
+    }
+    .step(),
+    -1
+  );
+
+  // 上端 100、1 行 20 で、見出し 2 行の後に 3 項目
+  assert_eq!(menu_row(100., 20., 110., 2, 3), None);
+  assert_eq!(menu_row(100., 20., 70., 2, 3), None);
+  assert_eq!(menu_row(100., 20., 55., 2, 3), Some(0));
+  assert_eq!(menu_row(100., 20., 1., 2, 3), Some(2));
+  assert_eq!(menu_row(100., 20., -5., 2, 3), None);
+
+  assert!(results::results_menu_text(1)
+    .lines()
+    .nth(1)
+    .unwrap()
+    .starts_with("> retry"));
+}
+
+#[test]
+fn test_tick_rate() {
+  use speed::{DropAccumulator, Gravity, TickRate, G, TICK_SECONDS};
+
+  // ミリ秒はいちばん近い tick 数になる
+  assert_eq!(10, TickRate::Hz60.ticks(170));
+  assert_eq!(41, TickRate::Hz240.ticks(170));
+  assert_eq!(0, TickRate::Hz120.ticks(0));
+  assert_eq!(Some(TickRate::Hz120), TickRate::from_hz(120));
+  assert_eq!(None, TickRate::from_hz(144));
+  assert_eq!(TickRate::Hz60, TickRate::Hz240.cycle(true));
+
+  // 端数は次に持ち越す
+  let mut pending = TICK_SECONDS * 2.5;
+  assert_eq!(5, TickRate::Hz120.take_ticks(&mut pending));
+  assert_eq!(0, TickRate::Hz120.take_ticks(&mut pending));
+
+  // 刻みを細かくしても 1/4 G は 4 コマで 1 段
+  for &rate in TickRate::ALL.iter() {
+    let mut drop = DropAccumulator::default();
+    let dt = 1. / rate.hz() as f32;
+    let ticks = (rate.hz() / 60 * 4) as usize;
+    let rows: u32 = (0..ticks)
+      .map(|_| drop.advance(dt, Gravity(G / 4), rate))
+      .sum();
+    assert_eq!(1, rows);
+    assert_eq!(0, drop.advance(dt, Gravity(G / 4), rate));
+  }
+
+  // DAS は 240 Hz でも同じ実時間で始まる。入力は 1/240 秒刻みで拾える
+  let handling = profile::Handling::default();
+  let mut shift = profile::AutoShift::default();
+  let dt = 1. / 240.;
+  assert!(shift.update(Some(Direction::Left), dt, &handling, TickRate::Hz240));
+  let first = (1..100)
+    .find(|_| shift.update(Some(Direction::Left), dt, &handling, TickRate::Hz240))
+    .unwrap();
+  assert_eq!(41, first);
+}
+
+#[test]
+fn test_shift_once_per_frame_at_20g() {
+  use bevy::app::Events;
+  use speed::{Gravity, GravityCurve, G};
+
+  // 20G では重力のシステムが 1 フレームに何度も走る。横移動はそれでも 1 列だけ
+  let mut world = World::default();
+  world.insert_resource(State::new(AppState::Playing));
+  world.insert_resource(Time::default());
+  world.insert_resource(config::Config::default());
+  world.insert_resource(GravityCurve(vec![Gravity(20 * G)]));
+  world.insert_resource(stats::Statistics::default());
+  world.insert_resource(rules::Modifiers::default());
+  world.insert_resource(ActiveBlock {
+    phase: PiecePhase::Falling,
+    direction: Direction::Right,
+    block_idx: 7,
+    rotation: 0,
+    spun: false,
+  });
+  world.insert_resource(GameClock(100.));
+  world.insert_resource(ResumeTime(0.));
+  world.insert_resource(Events::<PieceMoved>::default());
+  world.insert_resource(Events::<PieceFell>::default());
+  #[cfg(feature = "frame-step")]
+  world.insert_resource(framestep::FrameStep::default());
+  let cells = spawn_cells(7);
+  for cell in cells.iter() {
+    world.spawn().insert(PrimitiveBlock {}).insert(cell.clone());
+  }
+  let mut stage = SystemStage::parallel()
+    .with_system(block_movement.system())
+    .with_system_set(gravity_systems());
+
+  // 最初の update では経過時間が 0 なので、少し待ってから 1 フレーム進める
+  world.get_resource_mut::<Time>().unwrap().update();
+  std::thread::sleep(Duration::from_millis(20));
+  world.get_resource_mut::<Time>().unwrap().update();
+  stage.run(&mut world);
+
+  let moved: Vec<Position> = world
+    .query_filtered::<&Position, With<PrimitiveBlock>>()
+    .iter(&world)
+    .cloned()
+    .collect();
+  let xs: Vec<i32> = moved.iter().map(|p| p.x).collect();
+  assert_eq!(cells.iter().map(|p| p.x + 1).collect::<Vec<_>>(), xs);
+  assert_eq!(Some(0), moved.iter().map(|p| p.y).min());
+}
+
+#[test]
+fn test_latency_summary() {
+  use latency::{latency_text, LatencySamples};
+
+  let mut board = LatencySamples::default();
+  assert_eq!(None, board.summary());
+  for ms in (1..=20).rev() {
+    board.push(ms as f32);
+  }
+  // 最小, 平均, 95 パーセンタイル, 最大
+  assert_eq!(Some((1., 10.5, 19., 20.)), board.summary());
+  // 古いものから捨てる
+  for _ in 0..240 {
+    board.push(2.);
+  }
+  assert_eq!(240, board.len());
+  assert_eq!(Some((2., 2., 2., 2.)), board.summary());
+
+  let text = latency_text(&board, &LatencySamples::default(), 3);
+  assert!(text.contains("board    min   2.0"));
+  assert!(text.contains("present  -"));
+  assert!(text.ends_with("no change 3"));
+}
+
+#[test]
+fn test_drought_cap() {
+  use randomizer::{with_drought_cap, RandomizerKind};
+
+  // どの 7 個の並びにも棒 (7) が入る
+  let mut capped = with_drought_cap(RandomizerKind::Memoryless.build(9), Some(7));
+  let pieces: Vec<u32> = (0..2000).map(|_| capped.next()).collect();
+  assert!(pieces.windows(7).all(|w| w.contains(&7)));
+  // 棒以外も出る
+  assert!(pieces.iter().any(|&idx| idx != 7));
+
+  // 付けなければ元のランダマイザーのまま
+  let mut plain = with_drought_cap(RandomizerKind::Memoryless.build(9), None);
+  let mut memoryless = RandomizerKind::Memoryless.build(9);
+  assert!((0..50).all(|_| plain.next() == memoryless.next()));
+
+  let mut ruleset = rules::Ruleset::for_mode(GameMode::Marathon);
+  assert_eq!(None, ruleset.drought_cap_note());
+  ruleset.drought_cap = Some(12);
+  let mut built = ruleset.build_randomizer(3);
+  let pieces: Vec<u32> = (0..500).map(|_| built.next()).collect();
+  assert!(pieces.windows(12).all(|w| w.contains(&7)));
+  assert_eq!(
+    Some("drought cap: I within every 12 pieces".to_string()),
+    ruleset.drought_cap_note()
+  );
+}
+
+#[test]
+fn test_garbage_rise() {
+  let mut rise = GarbageRise::default();
+  assert_eq!(0., rise.offset());
+  // 2 段来たら 2 段下から描き始めて、GARBAGE_RISE 秒で追いつく
+  rise.start(2);
+  assert_eq!(2., rise.offset());
+  rise.advance(GARBAGE_RISE / 2.);
+  assert_eq!(0.5, rise.offset());
+  // 途中で次が来たら、残りのずれに足す
+  rise.start(1);
+  assert_eq!(1.5, rise.offset());
+  rise.advance(GARBAGE_RISE * 2.);
+  assert_eq!(0., rise.offset());
+  assert_eq!(0., rise.remaining);
+}
+
+#[test]
+fn test_finale_script() {
+  use finale::{finale_cell, scene, FinaleCell, Scene, FINALE_SECONDS};
+
+  assert_eq!(Scene::Grey, scene(0.));
+  assert_eq!(Scene::Crumble, scene(0.5));
+  assert_eq!(Scene::Results, scene(FINALE_SECONDS));
+
+  // 負けた盤面は灰色になり、上の段から崩れる
+  let loser = |elapsed: f32, y: i32| finale_cell(elapsed, false, 10, (0, y), true);
+  assert_eq!(FinaleCell::Dim, loser(0.1, 9));
+  assert_eq!(FinaleCell::Gone, loser(0.45, 9));
+  assert_eq!(FinaleCell::Dim, loser(0.45, 8));
+  assert_eq!(FinaleCell::Gone, loser(0.72, 4));
+  assert_eq!(FinaleCell::Dim, loser(0.72, 3));
+  assert_eq!(FinaleCell::Gone, loser(FINALE_SECONDS, 0));
+  assert_eq!(
+    FinaleCell::Keep,
+    finale_cell(0.1, false, 10, (0, 12), false)
+  );
+
+  // 勝った盤面はどこかが光り、結果が出たら止まる
+  let sparkles = (0..10)
+    .flat_map(|x| (0..5).map(move |y| (x, y)))
+    .filter(|&cell| finale_cell(0.5, true, 5, cell, true) == FinaleCell::Sparkle)
+    .count();
+  assert!(sparkles > 0 && sparkles < 50);
+  assert!((0..10).all(|x| finale_cell(FINALE_SECONDS, true, 5, (x, 0), true) == FinaleCell::Keep));
+}
+
+#[test]
+fn test_momentum_bar() {
+  use momentum::{momentum_text, tug_bar, Momentum};
+
+  // 2 人なら真ん中から綱引き
+  assert_eq!("111222", tug_bar(&[0.5, 0.5], 6));
+  assert_eq!("111112", tug_bar(&[0.8, 0.2], 6));
+  assert_eq!("11223", tug_bar(&[0.4, 0.4, 0.2], 5));
+
+  let mut momentum = Momentum::default();
+  momentum.reset(2);
+  assert_eq!(vec![0.5, 0.5], momentum.shares(0.));
+  momentum.observe(10., &[4, 0]);
+  momentum.observe(20., &[4, 4]);
+  momentum.observe(25., &[8, 4]);
+  assert!(momentum.is_current(&[8, 4]));
+  // 30 秒で 12 段なら 8 段と 4 段
+  assert_eq!(16., momentum.apm(0, 30.));
+  assert_eq!(8., momentum.apm(1, 30.));
+  // 古い攻撃は流れから外れる
+  momentum.observe(45., &[8, 4]);
+  assert_eq!(vec![0.5, 0.5], momentum.shares(45.));
+  momentum.observe(55., &[8, 4]);
+  assert_eq!(
+    "[111111111111111111111111]  APM P1 8.0  P2 0.0",
+    momentum_text(&momentum, 55.)
+  );
+}
+
+#[test]
+fn test_caster_panel() {
+  use caster::{attack_graph, caster_panel, caster_slots, garbage_meter, CasterView};
+  use sim::GameState;
+
+  // 2 つの盤面は真ん中を挟んで左右対称で、外側に欄が入る
+  let slots = caster_slots(Vec2::new(1280., 720.));
+  assert_eq!(slots.len(), 2);
+  assert_eq!(slots[0].center, -slots[1].center);
+  assert_eq!(slots[0].cell, slots[1].cell);
+  let outer = slots[1].center.x + ARENA_WIDTH as f32 / 2. * slots[1].cell;
+  assert!(outer < 640.);
+  assert!(ARENA_HEIGHT as f32 * slots[0].cell <= 720.);
+
+  assert_eq!(garbage_meter(0), "");
+  assert_eq!(garbage_meter(3), "###");
+  assert_eq!(garbage_meter(15), format!("{}+", "#".repeat(12)));
+
+  // いちばん多い本が最大になり、少しでも送った本は空にしない
+  assert_eq!(attack_graph(&[]), "");
+  assert_eq!(attack_graph(&[0, 0]), "\u{2581}\u{2581}");
+  assert_eq!(attack_graph(&[0, 1, 7]), "\u{2581}\u{2582}\u{2588}");
+  assert_eq!(attack_graph(&[9; 20]).chars().count(), 12);
+
+  // 送った合計の増えた分を、その時刻の 5 秒ごとの本に足す
+  let state = GameState::new(vec![0, 1, 2, 3, 4, 5, 6]);
+  let mut view = CasterView::default();
+  view.seat_mut(1).update("bot", &state, 4, 2, 1.);
+  view.seat_mut(1).update("bot", &state, 4, 5, 3.);
+  view.seat_mut(1).update("bot", &state, 4, 9, 11.);
+  assert_eq!(view.seats.len(), 2);
+  let seat = &view.seats[1];
+  assert_eq!(seat.attack, vec![5, 0, 4]);
+  assert_eq!(seat.sent, 9);
+  assert_eq!(seat.queue.len(), 5);
+  let panel = caster_panel(seat);
+  assert!(panel.starts_with("bot\n"));
+  assert!(panel.contains("GARBAGE 4\n####\n"));
+
+  view.reset();
+  assert!(view.seats.is_empty());
+}
+
+#[test]
+fn test_name_tags() {
+  use profile::{tag_color, TAG_PALETTE};
+  use protocol::{decode, encode, Message, NameTag, MAX_TAG_NAME, TAG_COLORS};
+  use tetris::wire::write_varint;
+
+  let profile = Message::Profile {
+    seat: 2,
+    tag: NameTag::new("anraku", 3),
+  };
+  let bytes = vec![14, 2, 3, 6, b'a', b'n', b'r', b'a', b'k', b'u'];
+  assert_eq!(bytes, encode(&profile));
+  assert_eq!(Ok(profile), decode(&bytes));
+  assert!(decode(&[14, 0, 0, 4, b'a']).is_err());
+  // 長さに u64::MAX を書いた名前や長すぎる名前は落とさずに断る
+  let mut huge = vec![14, 0, 0];
+  write_varint(&mut huge, u64::MAX);
+  huge.push(b'a');
+  assert!(decode(&huge).is_err());
+  let mut long = vec![14, 0, 0];
+  write_varint(&mut long, 1000);
+  long.extend(vec![b'a'; 1000]);
+  assert!(decode(&long).is_err());
+  let mut reject = vec![1];
+  write_varint(&mut reject, u64::MAX - 1);
+  assert!(decode(&reject).is_err());
+
+  // 届いた名前は改行などを落として切り詰め、知らない色は最初の色にする
+  let tag = NameTag::new(" long\nname with spaces and more ", TAG_COLORS);
+  assert_eq!(tag.name.chars().count(), MAX_TAG_NAME);
+  assert!(tag.name.starts_with("longname with"));
+  assert_eq!(tag.color, 0);
+  assert_eq!(
+    Ok(Message::Profile {
+      seat: 0,
+      tag: NameTag::new("\u{3042}", 0),
+    }),
+    decode(&[14, 0, 200, 3, 0xe3, 0x81, 0x82])
+  );
+
+  assert_eq!(TAG_PALETTE.len(), TAG_COLORS as usize);
+  assert_eq!(tag_color(&NameTag::new("x", 1)), TAG_PALETTE[1].1);
+}
+
+#[test]
+fn test_trim_bounding_box() {
+  // T字は下の空行が落ちる
+  assert_eq!(
+    arr2(&[[0, 1, 0], [1, 1, 1]]),
+    trim_bounding_box(&TETORIMINO_ARRAY[5])
+  );
+  // I字は左右の空列が落ちる
+  assert_eq!((4, 1), trim_bounding_box(&TETORIMINO_ARRAY[6]).dim());
+  assert_eq!((0, 0), trim_bounding_box(&Array2::zeros((3, 3))).dim());
+
+  // プレビューでは枠の中央に来る
+  use hold::preview_offsets;
+  let mut offsets = preview_offsets(1, 10.);
+  offsets.sort_by(|a, b| (a.x, a.y).partial_cmp(&(b.x, b.y)).unwrap());
+  assert_eq!(
+    vec![
+      Vec2::new(10., 10.),
+      Vec2::new(10., 20.),
+      Vec2::new(20., 10.),
+      Vec2::new(20., 20.)
+    ],
+    offsets
+  );
+  let offsets = preview_offsets(6, 10.);
+  assert!(offsets.iter().all(|o| o.y == 10. || o.y == 20.));
+  assert!(offsets.contains(&Vec2::new(15., 10.)));
+  assert!(offsets.contains(&Vec2::new(15., 20.)));
+}
+
+#[test]
+fn test_replay_seek() {
+  use eventlog::{GameLog, LogEvent};
+  use replay::Replay;
+
+  // 半分ずつ埋めて1行消すのを繰り返し、キーフレームをまたいでシークする
+  let mut log = GameLog::default();
+  for i in 0..40 {
+    let time = i as f64;
+    log.push(time, LogEvent::Spawn { piece: 'I' });
+    let left = if i % 2 == 0 { 0 } else { 5 };
+    let cells = (left..left + 5).map(|x| (x, 0)).collect();
+    log.push(time + 0.5, LogEvent::Lock { piece: 'I', cells });
+    if i % 2 == 1 {
+      log.push(
+        time + 0.5,
+        LogEvent::Clear {
+          lines: 1,
+          kind: eventlog::clear_kind(1).to_string(),
+        },
+      );
+    }
+  }
+  let replay = Replay::new(log);
+  assert_eq!(39.5, replay.duration());
+
+  let frame = replay.frame(replay.events_until(30.7));
+  assert_eq!(31, frame.state.pieces);
+  assert_eq!(15, frame.state.lines);
+  assert_eq!(5, frame.state.board.cells().len());
+  assert_eq!(Some('I'), frame.piece);
+  assert_eq!(frame, replay.frame(replay.events_until(30.9)));
+
+  // 同じ時刻の Lock と Clear は1回のコマ送りでまとめて進む
+  assert_eq!(31.5, replay.step(31.2, true));
+  assert_eq!(32., replay.step(31.5, true));
+  assert_eq!(31., replay.step(31.5, false));
+  assert_eq!(0., replay.step(0., false));
+
+  // ミノ単位では固定した直後へ飛ぶ。消したラインも同じ時刻なので反映されている
+  assert_eq!(31.5, replay.lock_step(31.2, true));
+  assert_eq!(32.5, replay.lock_step(31.5, true));
+  assert_eq!(30.5, replay.lock_step(31.5, false));
+  assert_eq!(30.5, replay.lock_step(31.2, false));
+  assert_eq!(0., replay.lock_step(0.5, false));
+  assert_eq!(39.5, replay.lock_step(39.5, true));
+  let frame = replay.frame(replay.events_until(replay.lock_step(31.2, true)));
+  assert_eq!(32, frame.state.pieces);
+  assert_eq!(16, frame.state.lines);
+
+  // 操作中のミノも sim で動かすので、途中の時刻では落ちている途中のミノが見える
+  let mut log = GameLog::default();
+  log.push(0., LogEvent::Spawn { piece: 'T' });
+  log.push(0.1, LogEvent::Move { dx: -1, dy: 0 });
+  log.push(0.2, LogEvent::Fall);
+  log.push(0.3, LogEvent::Move { dx: 0, dy: -1 });
+  log.push(
+    0.4,
+    LogEvent::Rotate {
+      from: '0',
+      to: 'R',
+      kick: (0, 0),
+    },
+  );
+  let expected = [
+    sim::Action::Left,
+    sim::Action::SoftDrop,
+    sim::Action::SoftDrop,
+    sim::Action::RotateCw,
+  ]
+  .iter()
+  .fold(sim::GameState::new(vec![6]), |state, &action| {
+    state.apply(action)
+  });
+  let landed = expected
+    .board
+    .drop(&expected.active.as_ref().unwrap().cells);
+  let cells = landed.iter().map(|p| (p.x, p.y)).collect();
+  log.push(0.5, LogEvent::Lock { piece: 'T', cells });
+  let replay = Replay::new(log);
+  let frame = replay.frame(replay.events_until(0.45));
+  assert_eq!(expected.active, frame.state.active);
+  assert!(replay
+    .frame(replay.events_until(0.05))
+    .state
+    .active
+    .is_some());
+  let frame = replay.frame(replay.events_until(0.5));
+  assert_eq!(None, frame.state.active);
+  assert_eq!(1, frame.state.pieces);
+  assert_eq!(4, frame.state.board.cells().len());
+
+  // メモはその時刻から少しの間だけ出す
+  let mut log = replay.log.clone();
+  log.add_note(10.0, "here".to_string());
+  let replay = Replay::new(log);
+  assert!(replay.notes_at(9.9).is_empty());
+  assert_eq!(1, replay.notes_at(10.0).len());
+  assert_eq!(1, replay.notes_at(12.9).len());
+  assert!(replay.notes_at(13.0).is_empty());
+}
+
+#[test]
+fn test_share_round_trip() {
+  use eventlog::{GameLog, LogEvent};
+
+  let mut log = GameLog::default();
+  log.mode = "Marathon".to_string();
+  log.seed = u64::MAX - 1;
+  log.tick_rate = speed::TickRate::Hz240;
+  log.push(0.25, LogEvent::Spawn { piece: 'L' });
+  log.push(0.5, LogEvent::Move { dx: -1, dy: 0 });
+  log.push(
+    0.75,
+    LogEvent::Rotate {
+      from: 'L',
+      to: '0',
+      kick: (1, -2),
+    },
+  );
+  log.push(
+    301.5,
+    LogEvent::Lock {
+      piece: 'L',
+      cells: vec![(0, 0), (1, 0), (2, 0), (2, 1)],
+    },
+  );
+  log.push(
+    301.5,
+    LogEvent::Clear {
+      lines: 1,
+      kind: eventlog::clear_kind(1).to_string(),
+    },
+  );
+  // メモは付けた順ではなく時刻順に並ぶ
+  log.add_note(120.0, "T-spin を見逃した".to_string());
+  log.add_note(2.5, "opener".to_string());
+  assert_eq!(
+    vec![2.5, 120.0],
+    log.notes.iter().map(|n| n.time).collect::<Vec<_>>()
+  );
+
+  let code = share::encode(&log);
+  assert!(code.starts_with("tetris:"));
+  let decoded = share::decode(&format!(" {}\n", code)).unwrap();
+  assert_eq!(log.mode, decoded.mode);
+  assert_eq!(log.seed, decoded.seed);
+  assert_eq!(log.tick_rate, decoded.tick_rate);
+  assert_eq!(log.events, decoded.events);
+  assert_eq!(log.notes, decoded.notes);
+
+  assert!(share::decode("tetris:AAAA").is_err());
+  assert!(share::decode(&code[..code.len() - 4]).is_err());
+
+  // 細工した文字列は落ちずに断る
+  use tetris::wire::write_varint;
+  let pack = |bytes: &[u8]| {
+    format!(
+      "tetris:{}",
+      base64::encode_config(
+        miniz_oxide::deflate::compress_to_vec(bytes, 9),
+        base64::URL_SAFE_NO_PAD
+      )
+    )
+  };
+  // 時刻の差分を足すとあふれる
+  let mut bytes = vec![3, 0, 0, 0, 2];
+  write_varint(&mut bytes, u64::MAX);
+  bytes.extend_from_slice(&[0, b'T']);
+  write_varint(&mut bytes, 1);
+  bytes.extend_from_slice(&[0, b'T', 0, 60]);
+  assert!(share::decode(&pack(&bytes)).is_err());
+  // メモの長さが u64::MAX
+  let mut bytes = vec![3, 0, 0, 0, 0, 1, 0];
+  write_varint(&mut bytes, u64::MAX);
+  bytes.push(b'a');
+  assert!(share::decode(&pack(&bytes)).is_err());
+}
+
+#[test]
+fn test_sprint_lead() {
+  use sprint::lead;
+
+  assert_eq!("even", lead(12, 12));
+  assert_eq!("3 ahead", lead(15, 12));
+  assert_eq!("2 behind", lead(10, 12));
+}
+
+#[test]
+fn test_sim_apply() {
+  use sim::{Action, GameState};
+
+  // I を左右の端から順に落として1ライン消す
+  let state = GameState::new(vec![7, 7, 7, 1, 6]);
+  let flat = |state: &GameState| {
+    let state = state.apply(Action::RotateCw);
+    assert_eq!(1, state.active.as_ref().unwrap().rotation);
+    state
+  };
+  let mut s = flat(&state);
+  assert_eq!(state, flat(&state).apply(Action::RotateCcw));
+  let dropped = state.apply(Action::SoftDrop);
+  assert_eq!(
+    state.active.as_ref().unwrap().cells[0].y - 1,
+    dropped.active.as_ref().unwrap().cells[0].y
+  );
+  for _ in 0..5 {
+    s = s.apply(Action::Left);
+  }
+  s = s.apply(Action::HardDrop);
+  assert_eq!(1, s.pieces);
+  assert_eq!(Some(7), s.active.as_ref().map(|p| p.block_idx));
+
+  // 同じ操作なら同じハッシュ。順番が違っても結果が同じなら一致する
+  let a = s.apply(Action::Left).apply(Action::Right);
+  assert_eq!(s.stable_hash(), a.stable_hash());
+  assert_ne!(s.stable_hash(), state.stable_hash());
+  assert_ne!(s.board.stable_hash(), state.board.stable_hash());
+
+  // ホールドは積むまで1回だけ
+  let held = s.apply(Action::Hold);
+  assert_eq!(Some(7), held.hold);
+  assert_eq!(held, held.apply(Action::Hold));
+
+  // 空の盤面のハッシュは空行があっても変わらない
+  let mut board = board::Board::default();
+  let empty = board.stable_hash();
+  board.place(&(0..10).map(|x| Position { x, y: 3 }).collect::<Vec<_>>());
+  assert_ne!(empty, board.stable_hash());
+  board.clear_full_rows();
+  assert_eq!(empty, board.stable_hash());
+  assert_eq!(0xcbf2_9ce4_8422_2325, empty);
+}
+
+#[test]
+fn test_attack_tracker() {
+  use attack::{attack, AttackTracker};
+
+  assert_eq!(0, attack(1, 0, false));
+  assert_eq!(4, attack(4, 0, false));
+  assert_eq!(5, attack(4, 0, true));
+  assert_eq!(1 + 2, attack(2, 4, false));
+  assert_eq!(5, attack(1, 100, false));
+
+  let mut tracker = AttackTracker::default();
+  tracker.record_lock();
+  let single = tracker.record_clear(1);
+  assert!(single.wasteful);
+  assert_eq!(0, single.attack);
+
+  // 続けて消すとコンボになり、無駄とは見なさない
+  tracker.record_lock();
+  let combo = tracker.record_clear(1);
+  assert_eq!(1, combo.combo);
+  assert!(!combo.wasteful);
+
+  tracker.record_lock();
+  tracker.record_lock();
+  assert_eq!(4, tracker.record_clear(4).attack);
+  tracker.record_lock();
+  tracker.record_lock();
+  let b2b = tracker.record_clear(4);
+  assert!(b2b.back_to_back);
+  assert_eq!(5, b2b.attack);
+  assert_eq!("back-to-back tetris  +5", versus::describe_clear(&b2b));
+
+  assert_eq!(10, tracker.lines);
+  assert_eq!(1, tracker.wasteful);
+  assert!((tracker.attack_per_line() - 0.9).abs() < 1e-5);
+}
+
+#[test]
+fn test_battle_garbage_and_targeting() {
+  use attack::{choose_target, Targeting};
+  use battle::Aim;
+
+  let mut state = sim::GameState::new(vec![1, 1]);
+  state = state.apply(sim::Action::HardDrop);
+  state.receive_garbage(&[0, 9]);
+  assert_eq!(4, state.board.max_height());
+  assert!(!state.board.is_filled(&Position { x: 0, y: 0 }));
+  assert!(!state.board.is_filled(&Position { x: 9, y: 1 }));
+  assert!(state.board.is_filled(&Position { x: 9, y: 0 }));
+  assert!(!state.game_over);
+  // 天井を越えたら負け
+  state.receive_garbage(&[0; ARENA_HEIGHT as usize]);
+  assert!(state.game_over);
+
+  let alive = [true, false, true, true];
+  let lines = [0, 40, 3, 3];
+  assert_eq!(
+    Some(2),
+    choose_target(Targeting::MostLines, 0, &alive, &lines, None, 0)
+  );
+  assert_eq!(
+    Some(3),
+    choose_target(Targeting::Attacker, 0, &alive, &lines, Some(3), 0)
+  );
+  // 脱落した相手からの攻撃は返せないのでランダムになる
+  assert_eq!(
+    Some(3),
+    choose_target(Targeting::Attacker, 0, &alive, &lines, Some(1), 1)
+  );
+  assert_eq!(
+    Some(2),
+    choose_target(Targeting::Random, 3, &alive, &lines, None, 1)
+  );
+  assert_eq!(
+    Some(0),
+    choose_target(Targeting::Random, 3, &alive, &lines, None, 0)
+  );
+  assert_eq!(
+    None,
+    choose_target(Targeting::Random, 0, &[true, false], &lines, None, 0)
+  );
+
+  // 自動の決め方を回したあと、生き残っている相手を選べる
+  let mut aim = Aim::Auto(Targeting::MostLines);
+  aim = aim.next(0, &alive);
+  assert_eq!(Aim::Player(2), aim);
+  assert_eq!(Some(2), aim.target(0, &alive, &lines, None, 1));
+  aim = aim.next(0, &alive);
+  assert_eq!(Aim::Player(3), aim);
+  assert_eq!(Aim::Auto(Targeting::Random), aim.next(0, &alive));
+  // 選んだ相手が脱落していたらランダム
+  assert_eq!(Some(3), Aim::Player(1).target(0, &alive, &lines, None, 1));
+}
+
+#[test]
+fn test_victory_conditions() {
+  use victory::{FirstToTetrises, LastStanding, MatchView, MostLines, Standing, VictoryCondition};
+
+  let mut standings = vec![
+    Standing {
+      alive: true,
+      lines: 10,
+      tetrises: 1,
+    },
+    Standing {
+      alive: false,
+      lines: 30,
+      tetrises: 6,
+    },
+    Standing {
+      alive: true,
+      lines: 20,
+      tetrises: 2,
+    },
+  ];
+  let eliminated = [1];
+  let view = |elapsed, standings: &[Standing]| -> [Option<Vec<usize>>; 3] {
+    let view = MatchView {
+      elapsed,
+      standings,
+      eliminated: &eliminated,
+    };
+    [
+      LastStanding.check(&view),
+      MostLines { seconds: 180.0 }.check(&view),
+      FirstToTetrises { count: 7 }.check(&view),
+    ]
+  };
+
+  assert_eq!([None, None, None], view(60.0, &standings));
+  // 時間切れならそれまでのライン数で並べる。脱落していても数える
+  assert_eq!(Some(vec![1, 2, 0]), view(180.0, &standings)[1]);
+
+  standings[2].tetrises = 7;
+  assert_eq!(Some(vec![2, 1, 0]), view(60.0, &standings)[2]);
+
+  // 1人になったらどの決め方でも終わる
+  standings[2].tetrises = 2;
+  standings[0].alive = false;
+  let eliminated = [1, 0];
+  let view = MatchView {
+    elapsed: 60.0,
+    standings: &standings,
+    eliminated: &eliminated,
+  };
+  assert_eq!(Some(vec![2, 0, 1]), LastStanding.check(&view));
+  assert_eq!(
+    Some(vec![2, 0, 1]),
+    FirstToTetrises { count: 7 }.check(&view)
+  );
+  assert_eq!(
+    Some(vec![1, 2, 0]),
+    MostLines { seconds: 180.0 }.check(&view)
+  );
+}
+
+#[test]
+fn test_protocol_fixtures() {
+  use protocol::{decode, encode, frame, negotiate, unframe, Hello, Message, Snapshot};
+  use tetris::wire::write_varint;
+
+  // 既に出回っている版と話せるよう、バイト列は変えない
+  let hello = Message::Hello(Hello {
+    version: 1,
+    min_version: 1,
+    features: 1,
+  });
+  let hello_bytes = vec![0, b'T', b'T', b'R', b'S', 1, 0, 1, 0, 1, 0, 0, 0];
+  assert_eq!(hello_bytes, encode(&hello));
+  assert_eq!(Ok(hello.clone()), decode(&hello_bytes));
+  assert_eq!(vec![2, 2], encode(&Message::Start { condition: 2 }));
+  assert_eq!(vec![4, 3], encode(&Message::Garbage { lines: 3 }));
+  // 1 通で盤面の高さを超えるせり上がりは送ってこない。u32 を超える値も落とさずに断る
+  assert_eq!(Ok(Message::Garbage { lines: 20 }), decode(&[4, 20]));
+  assert!(decode(&[4, 21]).is_err());
+  let mut huge = vec![4];
+  write_varint(&mut huge, u32::MAX as u64);
+  assert!(decode(&huge).is_err());
+  let mut huge = vec![4];
+  write_varint(&mut huge, u64::MAX);
+  assert!(decode(&huge).is_err());
+  assert_eq!(vec![5, 0xac, 0x02], encode(&Message::Session { id: 300 }));
+  assert_eq!(Ok(Message::Resume { id: 300 }), decode(&[6, 0xac, 0x02]));
+
+  let state = Message::State(Snapshot {
+    pieces: 2,
+    lines: 1,
+    tetrises: 0,
+    sent: 0,
+    game_over: false,
+    rows: vec![0b11],
+    active: Some((1, vec![(4, 18), (5, 18)])),
+  });
+  let state_bytes = vec![3, 2, 1, 0, 0, 0, 1, 3, 0, 1, 1, 2, 4, 18, 5, 18];
+  assert_eq!(state_bytes, encode(&state));
+  assert_eq!(Ok(state.clone()), decode(&state_bytes));
+
+  // 途中までしか届いていなければ待つ
+  let mut buffer = frame(&hello);
+  buffer.extend(frame(&state));
+  let mut partial = buffer[..5].to_vec();
+  assert_eq!(Ok(None), unframe(&mut partial));
+  assert_eq!(Ok(Some(hello)), unframe(&mut buffer));
+  assert_eq!(Ok(Some(state)), unframe(&mut buffer));
+  assert!(buffer.is_empty());
+
+  assert!(decode(&[0, b'H', b'T', b'T', b'P', 1, 0, 1, 0, 0, 0, 0, 0]).is_err());
+  assert!(decode(&[99]).is_err());
+  assert_eq!(
+    vec![7, 1, 4],
+    encode(&Message::Welcome {
+      seat: 1,
+      players: 4
+    })
+  );
+  assert_eq!(
+    Ok(Message::Input {
+      action: sim::Action::HardDrop
+    }),
+    decode(&[8, 3])
+  );
+  assert!(decode(&[8, 7]).is_err());
+  assert_eq!(
+    Ok(Message::Chat { seat: 2, phrase: 1 }),
+    decode(&[10, 2, 1])
+  );
+  assert_eq!(vec![11, 0xac, 0x02], encode(&Message::Ping { id: 300 }));
+  assert_eq!(Ok(Message::Pong { id: 300 }), decode(&[12, 0xac, 0x02]));
+  let handicap = Message::Handicap {
+    host: protocol::Handicap {
+      garbage: 8,
+      previews: 5,
+    },
+    guest: protocol::Handicap {
+      garbage: 0,
+      previews: 1,
+    },
+  };
+  assert_eq!(vec![13, 8, 5, 0, 1], encode(&handicap));
+  // 決められる範囲を超えた値は丸める
+  assert_eq!(Ok(handicap), decode(&[13, 200, 9, 0, 1]));
+
+  // 古い方に合わせ、機能は両方にあるものだけ使う
+  let local = Hello {
+    version: 3,
+    min_version: 2,
+    features: 0b111,
+  };
+  let older = Hello {
+    version: 2,
+    min_version: 1,
+    features: 0b101,
+  };
+  let agreement = negotiate(&local, &older).unwrap();
+  assert_eq!(2, agreement.version);
+  assert_eq!(0b101, agreement.features);
+  let too_old = Hello {
+    version: 1,
+    min_version: 1,
+    features: 0,
+  };
+  assert!(negotiate(&local, &too_old)
+    .unwrap_err()
+    .contains("update the opponent's game"));
+  assert!(negotiate(&too_old, &local)
+    .unwrap_err()
+    .contains("update this game"));
+}
+
+#[test]
+fn test_server_match() {
+  use sim::Action;
+  use tetris::server::{replay, Match, MatchEvent, MAX_INPUTS_PER_TICK};
+
+  let mut game = Match::new(2, 7);
+  for _ in 0..3 {
+    game.input(0, Action::Left).unwrap();
+    game.input(0, Action::HardDrop).unwrap();
+    game.input(1, Action::RotateCw).unwrap();
+    game.tick(1.0);
+  }
+  // 人の手より速い操作は受け付けない
+  let flood = (0..=MAX_INPUTS_PER_TICK).map(|_| game.input(1, Action::Left));
+  assert!(flood.last().unwrap().is_err());
+  assert!(game.input(2, Action::Left).is_err());
+  for _ in 0..25 {
+    game.tick(1.0);
+  }
+
+  // 記録を当て直すと、サーバーと同じ盤面になる
+  let states = replay(game.record());
+  for (seat, state) in states.iter().enumerate() {
+    let snapshot = game.snapshot(seat);
+    assert_eq!(snapshot.rows, state.board.rows().to_vec());
+    assert_eq!(snapshot.pieces, state.pieces);
+  }
+  // 重力で落ちきったミノは固定される
+  assert!(states[0].pieces > 3);
+  assert!(states[1].pieces >= 1);
+
+  assert!(!game.is_over());
+  game.forfeit(1);
+  assert!(game.is_over());
+  assert_eq!(vec![0, 1], game.record().placings);
+  assert_eq!(
+    Some(&MatchEvent::Forfeit { seat: 1 }),
+    game.record().events.last().map(|(_, e)| e)
+  );
+  assert!(game.input(0, Action::Left).is_err());
+}
+
+#[test]
+fn test_ping_tracker() {
+  let mut pings = online::PingTracker::default();
+  assert_eq!(Some(0), pings.due(0.0));
+  // 間隔が空くまでは送らない
+  assert_eq!(None, pings.due(0.1));
+  pings.pong(0, 0.05);
+  assert_eq!(Some(1), pings.due(0.5));
+  pings.pong(1, 0.57);
+  assert_eq!(Some(2), pings.due(1.0));
+  // 知らない番号や二度目の返事は無視する
+  pings.pong(7, 1.1);
+  pings.pong(1, 1.1);
+  assert!((pings.last().unwrap() - 0.07).abs() < 1e-9);
+  assert!((pings.average().unwrap() - 0.06).abs() < 1e-9);
+  assert!((pings.jitter().unwrap() - 0.02).abs() < 1e-9);
+  // 返事の来ない Ping は時間が過ぎたら届かなかった分になる
+  assert_eq!(0, pings.lost);
+  assert_eq!(Some(3), pings.due(3.5));
+  assert_eq!(1, pings.lost);
+  assert_eq!(4, pings.sent);
+  assert!((pings.loss_percent() - 25.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_exhibition() {
+  use tetris::exhibition::Exhibition;
+  use victory::MostLines;
+
+  // 置き場所を決めたら、最後は必ず落とす
+  let state = sim::GameState::new(vec![7, 6, 1]);
+  let actions = ai::plan(&state, &ai::Weights::default()).unwrap();
+  assert_eq!(Some(&sim::Action::HardDrop), actions.last());
+
+  let weights = [ai::STYLES[0].1, ai::STYLES[3].1];
+  let mut game = Exhibition::new(&weights, 3, Box::new(MostLines { seconds: 20. }));
+  let placings = game.run().unwrap();
+  assert_eq!(2, placings.len());
+  assert!(game.game().elapsed() <= 20.1);
+  assert!(game.game().state(0).lines > 0);
+  assert!(game.game().state(1).pieces > 10);
+}
+
+#[test]
+fn test_bot_training() {
+  use rand::SeedableRng;
+  use tetris::train::{mutate, train, TrainConfig};
+
+  // 動かしても重みの符号は変わらない
+  let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+  let start = ai::Weights::default();
+  for _ in 0..50 {
+    let weights = mutate(&start, 1.0, &mut rng);
+    assert!(weights.height <= 0. && weights.holes <= 0. && weights.bumpiness <= 0.);
+    assert!(weights.lines >= 0. && weights.tetris >= 0.);
+  }
+
+  let config = TrainConfig {
+    rounds: 2,
+    candidates: 2,
+    games: 2,
+    seconds: 5.,
+    step: 0.2,
+    seed: 4,
+  };
+  let mut rounds = vec![];
+  let best = train(start, &config, |round| rounds.push(*round));
+  assert_eq!(2, rounds.len());
+  assert_eq!(best, rounds[1].best);
+  // 置き換えるのは勝ち越したときだけ
+  assert!(rounds.iter().all(|r| r.improved == (r.win_rate > 0.5)));
+
+  let path = std::env::temp_dir().join("tetris_test_bot_weights.ron");
+  best.store(&path).unwrap();
+  assert_eq!(Ok(best), ai::Weights::load(&path));
+  std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_move_search() {
+  use ai::{moves, search, Lookahead};
+  use rayon::ThreadPoolBuilder;
+
+  // 天井の下のすき間には、下まで落としてから横に滑り込ませる
+  let mut roof: Vec<Position> = (0..4).map(|x| Position { x, y: 1 }).collect();
+  roof.push(Position { x: 9, y: 0 });
+  let mut state = sim::GameState::new(vec![7, 6, 1]);
+  state.board = board::Board::from_cells(&roof);
+  let tucked = moves(&state)
+    .into_iter()
+    .find(|m| m.state.board.is_filled(&Position { x: 0, y: 0 }))
+    .unwrap();
+  assert!(tucked.actions.contains(&sim::Action::SoftDrop));
+  assert_eq!(Some(&sim::Action::HardDrop), tucked.actions.last());
+
+  // 並べて調べても、1 本で調べたときと同じ手を選ぶ
+  let weights = ai::Weights::default();
+  let lookahead = Lookahead {
+    depth: 3,
+    width: 4,
+    hold: true,
+  };
+  let single = ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+  let expected = single.install(|| search(&state, &weights, &lookahead));
+  assert!(expected.is_some());
+  assert_eq!(expected, search(&state, &weights, &lookahead));
+
+  // 右端の 4 段の溝は、ホールドして次の I で消す
+  let well: Vec<Position> = (0..4)
+    .flat_map(|y| (0..9).map(move |x| Position { x, y }))
+    .collect();
+  let mut state = sim::GameState::new(vec![1, 7, 1]);
+  state.board = board::Board::from_cells(&well);
+  let hold = Lookahead {
+    depth: 1,
+    width: 1,
+    hold: true,
+  };
+  let (_, actions) = search(&state, &weights, &hold).unwrap();
+  assert_eq!(sim::Action::Hold, actions[0]);
+  let placed = actions.iter().fold(state.clone(), |s, &a| s.apply(a));
+  assert_eq!(4, placed.lines);
+  let (_, actions) = search(&state, &weights, &Lookahead::default()).unwrap();
+  assert!(!actions.contains(&sim::Action::Hold));
+}
+
+#[test]
+fn test_bot_strategy() {
+  use ai::{Bot, Strategy};
+
+  // 余裕があれば 4 ライン消しを狙い、来ていれば相殺し、高ければ下げる
+  let empty = board::Board::default();
+  assert_eq!(Strategy::BuildTetris, Strategy::choose(&empty, 0));
+  assert_eq!(Strategy::Cancel, Strategy::choose(&empty, 3));
+  assert_eq!(Strategy::Downstack, Strategy::choose(&empty, 10));
+  let tall: Vec<Position> = (0..12).map(|y| Position { x: 0, y }).collect();
+  let tall = board::Board::from_cells(&tall);
+  assert_eq!(Strategy::Downstack, Strategy::choose(&tall, 0));
+
+  let weights = ai::Weights::default();
+  let downstack = Strategy::Downstack.adjust(&weights);
+  assert!(downstack.lines > weights.lines && downstack.height < weights.height);
+  assert!(Strategy::BuildTetris.adjust(&weights).lines < weights.lines);
+
+  let mut bot = Bot::new(weights);
+  assert_eq!(None, bot.strategy());
+  let state = sim::GameState::new(vec![7, 6, 1]);
+  assert!(bot.next_action(&state, 2).is_some());
+  assert_eq!(Some(Strategy::Cancel), bot.strategy());
+}
+
+#[test]
+fn test_bot_files() {
+  use ai::{Personality, Strategy};
+
+  // 書いていない振る舞いは組み込みのボットと同じ
+  let ron = "(weights: (height: -0.5, lines: 0.7, holes: -0.4, bumpiness: -0.2))";
+  let bot = Personality::parse(ron, false).unwrap();
+  assert_eq!("", bot.name);
+  assert_eq!(0., bot.weights.tetris);
+  assert_eq!(ai::Rules::default(), bot.rules);
+
+  let json = r#"{"name": "turtle", "weights": {"height": -1, "lines": 1, "holes": -1, "bumpiness": 0},
+    "rules": {"always": "Downstack"}}"#;
+  let bot = Personality::parse(json, true).unwrap();
+  assert_eq!("turtle", bot.name);
+  let empty = board::Board::default();
+  assert_eq!(Strategy::Downstack, bot.rules.choose(&empty, 0));
+
+  let stubborn = ai::Rules {
+    cancel: false,
+    ..Default::default()
+  };
+  assert_eq!(Strategy::BuildTetris, stubborn.choose(&empty, 3));
+
+  // 名前が無ければファイル名を使い、読めないファイルは理由を返す
+  let dir = std::env::temp_dir().join("tetris_test_bots");
+  let _ = std::fs::remove_dir_all(&dir);
+  std::fs::create_dir_all(&dir).unwrap();
+  std::fs::write(dir.join("b.ron"), ron).unwrap();
+  std::fs::write(dir.join("a.json"), json).unwrap();
+  std::fs::write(dir.join("c.ron"), "(weights: ())").unwrap();
+  // RON の NaN や inf の重みも読めないファイルとして扱う
+  std::fs::write(
+    dir.join("d.ron"),
+    "(weights: (height: inf, lines: 0.7, holes: NaN, bumpiness: -0.2))",
+  )
+  .unwrap();
+  std::fs::write(dir.join("notes.txt"), "not a bot").unwrap();
+  let (bots, errors) = ai::load_bots(&dir);
+  let names: Vec<&str> = bots.iter().map(|b| b.name.as_str()).collect();
+  assert_eq!(vec!["turtle", "b"], names);
+  assert_eq!(2, errors.len());
+  assert!(errors[0].contains("c.ron"));
+  assert!(errors[1].contains("d.ron") && errors[1].contains("height"));
+
+  let (bots, _) = ai::load_bots(std::path::Path::new("bots"));
+  assert!(bots.iter().any(|b| b.name == "tetris only"));
+}
@@ -0,0 +1,212 @@
+use bevy::prelude::*;
+
+use crate::{overlay_text, AppState, Fonts, GameClock, GameReset, LinesCleared, StackedBlock};
+
+// 10 ラインを 1 区間として 10 区間。終わると積んだブロックが見えないスタッフロールになる
+pub const SECTION_LINES: u32 = 10;
+pub const SECTIONS: usize = 10;
+pub const ROLL_SECONDS: f64 = 55.;
+pub const GRADES: [&str; 19] = [
+  "9", "8", "7", "6", "5", "4", "3", "2", "1", "S1", "S2", "S3", "S4", "S5", "S6", "S7", "S8",
+  "S9", "GM",
+];
+// 段位ごとに必要な得点 (9 から S9 まで)
+const GRADE_SCORES: [u32; 18] = [
+  0, 40, 80, 140, 200, 350, 550, 800, 1200, 1600, 2200, 3000, 4000, 5200, 6600, 8200, 10000, 12000,
+];
+const S9: usize = 17;
+const GM: usize = 18;
+// GM の条件。(区間, そこで必要な段位, 始めてからの秒数の上限)
+const GM_CHECKPOINTS: [(usize, usize, f64); 3] =
+  [(3, 7, 150.), (5, 10, 250.), (SECTIONS, S9, 500.)];
+
+// マスターモードの段位と区間ごとのタイム
+#[derive(Clone, Default, Debug)]
+pub struct Master {
+  pub lines: u32,
+  pub score: u32,
+  start: f64,
+  section_start: f64,
+  // 区間ごとにかかった秒数
+  pub splits: Vec<f64>,
+  // ここまで GM の条件を満たしているか
+  pub on_track: bool,
+  roll_start: Option<f64>,
+  pub roll_cleared: bool,
+}
+
+impl Master {
+  pub fn new(now: f64) -> Self {
+    Master {
+      start: now,
+      section_start: now,
+      on_track: true,
+      ..Default::default()
+    }
+  }
+
+  // ライン数が多いほど、進んでいるほど得点が多い。スタッフロール中は数えない
+  pub fn record_clear(&mut self, lines: u32, now: f64) {
+    if self.roll_start.is_some() {
+      return;
+    }
+    let level = self.lines * 10;
+    self.score += (level + lines).div_ceil(4) * lines;
+    self.lines += lines;
+    if self.splits.len() < SECTIONS && self.lines >= (self.splits.len() as u32 + 1) * SECTION_LINES
+    {
+      self.splits.push(now - self.section_start);
+      self.section_start = now;
+      let grade = self.grade();
+      for &(section, required, limit) in GM_CHECKPOINTS.iter() {
+        if self.splits.len() == section && (grade < required || now - self.start > limit) {
+          self.on_track = false;
+        }
+      }
+      if self.splits.len() == SECTIONS {
+        self.roll_start = Some(now);
+      }
+    }
+  }
+
+  pub fn in_roll(&self) -> bool {
+    self.roll_start.is_some() && !self.roll_cleared
+  }
+
+  // スタッフロールの残り秒数
+  pub fn roll_left(&self, now: f64) -> Option<f64> {
+    self
+      .roll_start
+      .map(|start| (ROLL_SECONDS - (now - start)).max(0.))
+  }
+
+  // スタッフロールを生き延びたら true
+  pub fn update_roll(&mut self, now: f64) -> bool {
+    if self.in_roll() && self.roll_left(now) == Some(0.) {
+      self.roll_cleared = true;
+      return true;
+    }
+    false
+  }
+
+  // GRADES の位置。GM は S9 で条件を満たしたままロールを生き延びたときだけ
+  pub fn grade(&self) -> usize {
+    let grade = GRADE_SCORES
+      .iter()
+      .rposition(|&s| self.score >= s)
+      .unwrap_or(0);
+    if grade == S9 && self.on_track && self.roll_cleared {
+      GM
+    } else {
+      grade
+    }
+  }
+
+  // 今の区間の経過秒数
+  pub fn section_time(&self, now: f64) -> f64 {
+    now - self.section_start
+  }
+
+  // 区間ごとのタイムは splits の方で自己ベストと並べて出す
+  pub fn summary(&self) -> String {
+    format!("grade {}  score {}", GRADES[self.grade()], self.score)
+  }
+}
+
+struct MasterText;
+
+pub struct MasterPlugin;
+
+impl Plugin for MasterPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(Master::default())
+      .add_startup_system_to_stage(StartupStage::PostStartup, setup_master.system())
+      .add_system_set(SystemSet::on_enter(AppState::Playing).with_system(master_start.system()))
+      .add_system_set(SystemSet::on_update(AppState::Playing).with_system(master_record.system()))
+      .add_system(master_reset.system())
+      .add_system(roll_visibility.system())
+      .add_system(master_ui.system());
+  }
+}
+
+fn setup_master(mut commands: Commands, fonts: Res<Fonts>) {
+  commands
+    .spawn_bundle(overlay_text(&fonts))
+    .insert(MasterText);
+}
+
+fn master_start(clock: Res<GameClock>, mut master: ResMut<Master>) {
+  *master = Master::new(clock.0);
+}
+
+fn master_reset(
+  clock: Res<GameClock>,
+  mut master: ResMut<Master>,
+  mut reset_events: EventReader<GameReset>,
+) {
+  if reset_events.iter().count() > 0 {
+    *master = Master::new(clock.0);
+  }
+}
+
+fn master_record(
+  clock: Res<GameClock>,
+  mut master: ResMut<Master>,
+  mut cleared_events: EventReader<LinesCleared>,
+  mut state: ResMut<State<AppState>>,
+) {
+  let now = clock.0;
+  for event in cleared_events.iter() {
+    master.record_clear(event.0, now);
+  }
+  if master.update_roll(now) {
+    state.overwrite_set(AppState::Results).unwrap();
+  }
+}
+
+// スタッフロールの間は積んだブロックを見せない。終わったら見せる
+fn roll_visibility(
+  state: Res<State<AppState>>,
+  master: Res<Master>,
+  mut query: Query<&mut Visible, With<StackedBlock>>,
+) {
+  let playing = matches!(state.current(), AppState::Playing | AppState::Paused);
+  let visible = !(playing && master.in_roll());
+  for mut v in query.iter_mut() {
+    if v.is_visible != visible {
+      v.is_visible = visible;
+    }
+  }
+}
+
+fn master_ui(
+  clock: Res<GameClock>,
+  state: Res<State<AppState>>,
+  master: Res<Master>,
+  mut query: Query<&mut Text, With<MasterText>>,
+) {
+  let now = clock.0;
+  let playing = matches!(state.current(), AppState::Playing | AppState::Paused);
+  let value = match master.roll_left(now) {
+    _ if !playing => String::new(),
+    Some(left) => format!(
+      "MASTER  grade {}\nSTAFF ROLL  {:.0}s",
+      GRADES[master.grade()],
+      left
+    ),
+    None => format!(
+      "MASTER  grade {}  {}/{}\nsection {}  {:.1}s",
+      GRADES[master.grade()],
+      master.lines,
+      SECTIONS as u32 * SECTION_LINES,
+      master.splits.len() + 1,
+      master.section_time(now)
+    ),
+  };
+  for mut text in query.iter_mut() {
+    if text.sections[0].value != value {
+      text.sections[0].value = value.clone();
+    }
+  }
+}
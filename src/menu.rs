@@ -0,0 +1,156 @@
+use bevy::input::InputSystem;
+use bevy::prelude::*;
+
+// メニューの操作。キーボードの矢印と Enter/Esc、コントローラーの十字キーと A/B を同じに扱う
+#[derive(Default, PartialEq)]
+pub struct MenuInput {
+  pub up: bool,
+  pub down: bool,
+  pub left: bool,
+  pub right: bool,
+  pub confirm: bool,
+  pub back: bool,
+  // マウスの左ボタンを押した。どの行かは MenuList.hovered で見る
+  pub clicked: bool,
+}
+
+impl MenuInput {
+  // 上下で 1 行ずつ動かし、端では反対側に回る。マウスを別の行に動かしたらそこを選ぶ
+  pub fn select(&self, row: usize, len: usize, pointed: Option<usize>) -> usize {
+    if len == 0 {
+      return 0;
+    }
+    if self.up {
+      (row + len - 1) % len
+    } else if self.down {
+      (row + 1) % len
+    } else {
+      pointed.filter(|&i| i < len).unwrap_or(row)
+    }
+  }
+
+  // 左右で -1 か 1。それ以外は 0
+  pub fn step(&self) -> i32 {
+    self.right as i32 - self.left as i32
+  }
+
+  // 決定した行。クリックはマウスが指している行だけを決める
+  pub fn chosen(&self, row: usize, hovered: Option<usize>) -> Option<usize> {
+    if self.confirm {
+      Some(row)
+    } else if self.clicked {
+      hovered
+    } else {
+      None
+    }
+  }
+}
+
+// 行を選べる文字。first 行目から rows 行がメニューの項目
+#[derive(Default)]
+pub struct MenuList {
+  pub first: usize,
+  pub rows: usize,
+  // マウスが指している項目
+  pub hovered: Option<usize>,
+  // このフレームでマウスが移った項目。止めたマウスがキーでの選択を奪わないように
+  pub pointed: Option<usize>,
+}
+
+impl MenuList {
+  pub fn new(first: usize, rows: usize) -> Self {
+    MenuList {
+      first,
+      rows,
+      hovered: None,
+      pointed: None,
+    }
+  }
+}
+
+// 文字の上端 top から下に数えて、y がメニューの何番目の項目に当たるか
+pub fn menu_row(top: f32, line_height: f32, y: f32, first: usize, rows: usize) -> Option<usize> {
+  if line_height <= 0. || y > top {
+    return None;
+  }
+  let line = ((top - y) / line_height) as usize;
+  line.checked_sub(first).filter(|&row| row < rows)
+}
+
+pub struct MenuPlugin;
+
+impl Plugin for MenuPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(MenuInput::default())
+      .add_system_to_stage(
+        CoreStage::PreUpdate,
+        read_menu_input.system().after(InputSystem),
+      )
+      .add_system_to_stage(CoreStage::PreUpdate, hover_menus.system());
+  }
+}
+
+fn read_menu_input(
+  keyboard_input: Res<Input<KeyCode>>,
+  buttons: Res<Input<GamepadButton>>,
+  mouse_input: Res<Input<MouseButton>>,
+  mut menu: ResMut<MenuInput>,
+) {
+  // どのコントローラーでも動かせる
+  let pad = |button: GamepadButtonType| {
+    buttons
+      .get_just_pressed()
+      .any(|pressed| pressed.1 == button)
+  };
+  let next = MenuInput {
+    up: keyboard_input.just_pressed(KeyCode::Up) || pad(GamepadButtonType::DPadUp),
+    down: keyboard_input.just_pressed(KeyCode::Down) || pad(GamepadButtonType::DPadDown),
+    left: keyboard_input.just_pressed(KeyCode::Left) || pad(GamepadButtonType::DPadLeft),
+    right: keyboard_input.just_pressed(KeyCode::Right) || pad(GamepadButtonType::DPadRight),
+    confirm: keyboard_input.just_pressed(KeyCode::Return) || pad(GamepadButtonType::South),
+    back: keyboard_input.just_pressed(KeyCode::Escape) || pad(GamepadButtonType::East),
+    clicked: mouse_input.just_pressed(MouseButton::Left),
+  };
+  // 何も押していないフレームで変更を知らせない
+  if *menu != next {
+    *menu = next;
+  }
+}
+
+// UI の座標はウィンドウの左下が原点で、カーソルと同じ向き
+fn hover_menus(
+  windows: Res<Windows>,
+  mut query: Query<(&mut MenuList, &Node, &GlobalTransform, &Text)>,
+) {
+  let cursor = windows.get_primary().and_then(|w| w.cursor_position());
+  for (mut list, node, transform, text) in query.iter_mut() {
+    let hovered = cursor.and_then(|cursor| {
+      let center = transform.translation.truncate();
+      let half = node.size / 2.;
+      if cursor.x < center.x - half.x || cursor.x > center.x + half.x {
+        return None;
+      }
+      let lines: usize = text
+        .sections
+        .iter()
+        .map(|s| s.value.matches('\n').count())
+        .sum::<usize>()
+        + 1;
+      let line_height = node.size.y / lines as f32;
+      menu_row(
+        center.y + half.y,
+        line_height,
+        cursor.y,
+        list.first,
+        list.rows,
+      )
+    });
+    if list.hovered != hovered {
+      list.hovered = hovered;
+      list.pointed = hovered;
+    } else if list.pointed.is_some() {
+      list.pointed = None;
+    }
+  }
+}
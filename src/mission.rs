@@ -0,0 +1,269 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::stats::Statistics;
+use crate::{
+  overlay_text, save, AppState, BlockStacked, Fonts, GameClock, GameReset, LinesCleared,
+};
+
+const SAVE_FILE: &str = "missions.ron";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Objective {
+  // 1回で lines 行消す (block_idx 指定時はそのミノで)
+  ClearLines { lines: u32, block_idx: Option<u32> },
+  TotalLines(u32),
+  StackBlocks(u32),
+  Survive(f64),
+  // 1回の T-spin で lines 行消す
+  TSpin { lines: u32 },
+  // level (1 から数える) に上がってから seconds 秒生き残る
+  SurviveAtLevel { seconds: f64, level: u32 },
+}
+
+pub struct Mission {
+  pub name: &'static str,
+  pub objective: Objective,
+}
+
+pub const MISSIONS: &[Mission] = &[
+  Mission {
+    name: "Stack 10 pieces",
+    objective: Objective::StackBlocks(10),
+  },
+  Mission {
+    name: "Clear 4 lines",
+    objective: Objective::TotalLines(4),
+  },
+  Mission {
+    name: "Clear 2 lines with a T piece",
+    objective: Objective::ClearLines {
+      lines: 2,
+      block_idx: Some(6),
+    },
+  },
+  Mission {
+    name: "Survive 60 s",
+    objective: Objective::Survive(60.),
+  },
+  Mission {
+    name: "Clear 3 lines at once",
+    objective: Objective::ClearLines {
+      lines: 3,
+      block_idx: None,
+    },
+  },
+  Mission {
+    name: "Clear 4 lines with an I piece",
+    objective: Objective::ClearLines {
+      lines: 4,
+      block_idx: Some(7),
+    },
+  },
+  Mission {
+    name: "Perform a T-spin double",
+    objective: Objective::TSpin { lines: 2 },
+  },
+  Mission {
+    name: "Survive 60 s at level 10",
+    objective: Objective::SurviveAtLevel {
+      seconds: 60.,
+      level: 10,
+    },
+  },
+];
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct MissionSave {
+  completed: Vec<String>,
+}
+
+// 現在のミッションの進捗
+#[derive(Default, Debug)]
+pub struct MissionProgress {
+  pub current: usize,
+  pub lines: u32,
+  pub blocks: u32,
+  pub started_at: f64,
+  last_block_idx: u32,
+  last_t_spin: bool,
+  cleared_at_once: bool,
+  // SurviveAtLevel のレベルに上がった時刻
+  level_reached_at: Option<f64>,
+}
+
+impl MissionProgress {
+  pub fn restart(&mut self, now: f64) {
+    *self = MissionProgress {
+      current: self.current,
+      started_at: now,
+      ..Default::default()
+    };
+  }
+
+  pub fn record_stack(&mut self, block_idx: u32, t_spin: bool) {
+    self.blocks += 1;
+    self.last_block_idx = block_idx;
+    self.last_t_spin = t_spin;
+  }
+
+  pub fn record_clear(&mut self, objective: &Objective, lines: u32) {
+    self.lines += lines;
+    let completed = match *objective {
+      Objective::ClearLines {
+        lines: target,
+        block_idx,
+      } => lines >= target && (block_idx.is_none() || block_idx == Some(self.last_block_idx)),
+      Objective::TSpin { lines: target } => lines >= target && self.last_t_spin,
+      _ => false,
+    };
+    if completed {
+      self.cleared_at_once = true;
+    }
+  }
+
+  // 今のレベル (1 から数える)。初めて目標のレベルに届いた時刻から数え始める
+  pub fn record_level(&mut self, objective: &Objective, level: u32, now: f64) {
+    if let Objective::SurviveAtLevel { level: target, .. } = *objective {
+      if level >= target && self.level_reached_at.is_none() {
+        self.level_reached_at = Some(now);
+      }
+    }
+  }
+
+  pub fn is_complete(&self, objective: &Objective, now: f64) -> bool {
+    match *objective {
+      Objective::ClearLines { .. } | Objective::TSpin { .. } => self.cleared_at_once,
+      Objective::TotalLines(n) => self.lines >= n,
+      Objective::StackBlocks(n) => self.blocks >= n,
+      Objective::Survive(secs) => now - self.started_at >= secs,
+      Objective::SurviveAtLevel { seconds, .. } => self
+        .level_reached_at
+        .is_some_and(|since| now - since >= seconds),
+    }
+  }
+
+  fn describe(&self, objective: &Objective, now: f64) -> String {
+    match *objective {
+      Objective::ClearLines { .. } | Objective::TSpin { .. } => String::new(),
+      Objective::TotalLines(n) => format!("{}/{}", self.lines.min(n), n),
+      Objective::StackBlocks(n) => format!("{}/{}", self.blocks.min(n), n),
+      Objective::Survive(secs) => format!("{:.0}/{:.0} s", (now - self.started_at).min(secs), secs),
+      Objective::SurviveAtLevel { seconds, level } => match self.level_reached_at {
+        Some(since) => format!("{:.0}/{:.0} s", (now - since).min(seconds), seconds),
+        None => format!("(reach level {})", level),
+      },
+    }
+  }
+}
+
+struct MissionText;
+
+pub struct MissionPlugin;
+
+impl Plugin for MissionPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(MissionProgress::default())
+      .insert_resource(save::load::<MissionSave>(SAVE_FILE))
+      .add_startup_system_to_stage(StartupStage::PostStartup, setup_mission_ui.system())
+      .add_system_set(SystemSet::on_exit(AppState::Title).with_system(reload_missions.system()))
+      .add_system_set(SystemSet::on_update(AppState::Playing).with_system(mission_input.system()))
+      .add_system(mission_check.system())
+      .add_system(mission_ui.system());
+  }
+}
+
+fn setup_mission_ui(mut commands: Commands, fonts: Res<Fonts>) {
+  commands
+    .spawn_bundle(overlay_text(&fonts))
+    .insert(MissionText);
+}
+
+fn reload_missions(mut data: ResMut<MissionSave>) {
+  *data = save::load(SAVE_FILE);
+}
+
+fn mission_input(
+  keyboard_input: Res<Input<KeyCode>>,
+  clock: Res<GameClock>,
+  mut progress: ResMut<MissionProgress>,
+) {
+  // やり直しは R の長押し (restart.rs)
+  if keyboard_input.just_pressed(KeyCode::N) && progress.current < MISSIONS.len() {
+    // skip
+    progress.current += 1;
+    progress.restart(clock.0);
+  }
+}
+
+fn mission_check(
+  clock: Res<GameClock>,
+  stats: Res<Statistics>,
+  mut progress: ResMut<MissionProgress>,
+  mut data: ResMut<MissionSave>,
+  mut stacked_events: EventReader<BlockStacked>,
+  mut cleared_events: EventReader<LinesCleared>,
+  mut reset_events: EventReader<GameReset>,
+) {
+  let now = clock.0;
+  if reset_events.iter().count() > 0 {
+    progress.restart(now);
+  }
+  let mission = match MISSIONS.get(progress.current) {
+    Some(mission) => mission,
+    None => return,
+  };
+
+  for event in stacked_events.iter() {
+    progress.record_stack(event.block_idx, event.t_spin);
+  }
+  for event in cleared_events.iter() {
+    progress.record_clear(&mission.objective, event.0);
+  }
+  progress.record_level(&mission.objective, stats.level() + 1, now);
+
+  if progress.is_complete(&mission.objective, now) {
+    if !data.completed.iter().any(|name| name == mission.name) {
+      data.completed.push(mission.name.to_string());
+      if let Err(e) = save::store(SAVE_FILE, &*data) {
+        error!("failed to save missions: {}", e);
+      }
+    }
+    progress.current += 1;
+    progress.restart(now);
+  }
+}
+
+fn mission_ui(
+  clock: Res<GameClock>,
+  progress: Res<MissionProgress>,
+  data: Res<MissionSave>,
+  mut query: Query<&mut Text, With<MissionText>>,
+) {
+  let now = clock.0;
+
+  let mut value = match MISSIONS.get(progress.current) {
+    Some(mission) => format!(
+      "MISSION {}/{}\n{} {}\n[N] skip  [hold R] retry\n\n",
+      progress.current + 1,
+      MISSIONS.len(),
+      mission.name,
+      progress.describe(&mission.objective, now)
+    ),
+    None => "ALL MISSIONS CLEAR\n\n".to_string(),
+  };
+  for (i, mission) in MISSIONS.iter().enumerate() {
+    let mark = if data.completed.iter().any(|name| name == mission.name) {
+      "[x]"
+    } else {
+      "[ ]"
+    };
+    let cursor = if i == progress.current { ">" } else { " " };
+    value.push_str(&format!("{}{} {}\n", cursor, mark, mission.name));
+  }
+
+  for mut text in query.iter_mut() {
+    text.sections[0].value = value.clone();
+  }
+}
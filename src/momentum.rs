@@ -0,0 +1,105 @@
+use std::collections::VecDeque;
+
+// 対戦の勢い。直近 WINDOW 秒に送った攻撃から APM を出し、綱引きの棒にする
+const WINDOW: f32 = 30.0;
+// 棒の文字数
+pub const BAR_WIDTH: usize = 24;
+
+#[derive(Default)]
+pub struct Momentum {
+  // プレイヤーごとの (送った時刻, 段数)
+  attacks: Vec<VecDeque<(f32, u32)>>,
+  // 前に見た送った合計
+  seen: Vec<u32>,
+}
+
+impl Momentum {
+  pub fn reset(&mut self, players: usize) {
+    self.attacks = vec![VecDeque::new(); players];
+    self.seen = vec![0; players];
+  }
+
+  pub fn is_current(&self, sent: &[u32]) -> bool {
+    self.seen == sent
+  }
+
+  // 送った合計の増えた分を now 秒の攻撃にする。通信先の分も盤面と一緒に届く合計で数える
+  pub fn observe(&mut self, now: f32, sent: &[u32]) {
+    if self.seen.len() != sent.len() {
+      self.reset(sent.len());
+    }
+    for (i, &total) in sent.iter().enumerate() {
+      let added = total.saturating_sub(self.seen[i]);
+      if added > 0 {
+        self.attacks[i].push_back((now, added));
+      }
+      self.seen[i] = total;
+    }
+    for attacks in self.attacks.iter_mut() {
+      while attacks.front().is_some_and(|&(at, _)| at < now - WINDOW) {
+        attacks.pop_front();
+      }
+    }
+  }
+
+  fn recent(&self, player: usize, now: f32) -> u32 {
+    self.attacks.get(player).map_or(0, |attacks| {
+      attacks
+        .iter()
+        .filter(|&&(at, _)| at >= now - WINDOW)
+        .map(|&(_, lines)| lines)
+        .sum()
+    })
+  }
+
+  // 直近の攻撃の毎分の段数。始まって間もないときは経った時間で割る
+  pub fn apm(&self, player: usize, now: f32) -> f32 {
+    let minutes = now.clamp(1., WINDOW) / 60.;
+    self.recent(player, now) as f32 / minutes
+  }
+
+  // 棒のうち各プレイヤーの取り分。誰も送っていなければ等分
+  pub fn shares(&self, now: f32) -> Vec<f32> {
+    let recent: Vec<u32> = (0..self.attacks.len())
+      .map(|i| self.recent(i, now))
+      .collect();
+    let total: u32 = recent.iter().sum();
+    recent
+      .iter()
+      .map(|&lines| {
+        if total == 0 {
+          1. / recent.len() as f32
+        } else {
+          lines as f32 / total as f32
+        }
+      })
+      .collect()
+  }
+}
+
+// 取り分の順にプレイヤーの番号を並べた棒。2 人なら真ん中からの綱引きになる
+pub fn tug_bar(shares: &[f32], width: usize) -> String {
+  let mut bar = String::new();
+  let mut filled = 0.;
+  for (i, share) in shares.iter().enumerate() {
+    filled += share;
+    let end = if i + 1 == shares.len() {
+      width
+    } else {
+      ((filled * width as f32).round() as usize).min(width)
+    };
+    let digit = std::char::from_digit(i as u32 + 1, 10).unwrap_or('?');
+    while bar.chars().count() < end {
+      bar.push(digit);
+    }
+  }
+  bar
+}
+
+pub fn momentum_text(momentum: &Momentum, now: f32) -> String {
+  let bar = tug_bar(&momentum.shares(now), BAR_WIDTH);
+  let apm: Vec<String> = (0..momentum.attacks.len())
+    .map(|i| format!("P{} {:.1}", i + 1, momentum.apm(i, now)))
+    .collect();
+  format!("[{}]  APM {}", bar, apm.join("  "))
+}
@@ -0,0 +1,705 @@
+use std::collections::VecDeque;
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use bevy::prelude::*;
+
+use crate::battle::Battle;
+use crate::connection::Connection;
+use crate::protocol::{
+  negotiate, Agreement, Hello, Message, NameTag, Snapshot, DEFAULT_PORT, FEATURE_ACTIVE_PIECE,
+  FEATURE_CHAT, FEATURE_HANDICAP, FEATURE_PING, FEATURE_PROFILE, FEATURE_RESUME, FEATURE_SERVER,
+};
+use crate::{overlay_text, AppState, Fonts};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+// 切れたあと相手が戻ってくるのを待つ秒数。過ぎたら相手の負け
+const GRACE_SECONDS: f64 = 30.0;
+// 参加した側がつなぎ直しを試す間隔と、1 回で待つ長さ
+const RETRY_SECONDS: f64 = 2.0;
+const RETRY_TIMEOUT: Duration = Duration::from_millis(300);
+// 盤面が変わらなくてもこの間隔で送り、これだけ何も届かなければ切れたとみなす
+const KEEPALIVE_SECONDS: f64 = 1.0;
+const SILENCE_SECONDS: f64 = 5.0;
+// Ping を送る間隔と、返事が来なければ届かなかったとみなす秒数
+const PING_SECONDS: f64 = 0.5;
+const PING_TIMEOUT: f64 = 2.0;
+// 往復時間の平均やジッタを出すのに使う最近の数
+const PING_SAMPLES: usize = 20;
+
+// tetris online host [port] / tetris online join <address>
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Role {
+  Host(u16),
+  Join(String),
+}
+
+impl Role {
+  fn from_args() -> Result<Self, String> {
+    let args: Vec<String> = std::env::args().skip(2).collect();
+    match args.first().map(|s| s.as_str()) {
+      Some("join") => match args.get(1) {
+        Some(address) => Ok(Role::Join(address.clone())),
+        None => Err("usage: tetris online join <address>".to_string()),
+      },
+      Some("host") | None => match args.get(1) {
+        Some(port) => port
+          .parse()
+          .map(Role::Host)
+          .map_err(|_| format!("bad port: {}", port)),
+        None => Ok(Role::Host(DEFAULT_PORT)),
+      },
+      Some(other) => Err(format!("unknown online role: {}", other)),
+    }
+  }
+}
+
+// Ping の往復時間と、返事が来なかった数
+#[derive(Default)]
+pub struct PingTracker {
+  next_id: u32,
+  // 返事を待っている番号と送った時刻
+  waiting: VecDeque<(u32, f64)>,
+  // 最近の往復時間 (秒)。古い順
+  samples: VecDeque<f64>,
+  last_ping: Option<f64>,
+  pub sent: u32,
+  pub lost: u32,
+}
+
+impl PingTracker {
+  // 送る時刻になっていたら番号を返す。返事の来ない古い Ping はここで数える
+  pub fn due(&mut self, now: f64) -> Option<u32> {
+    while let Some(&(_, at)) = self.waiting.front() {
+      if now - at < PING_TIMEOUT {
+        break;
+      }
+      self.waiting.pop_front();
+      self.lost += 1;
+    }
+    if self.last_ping.is_some_and(|at| now - at < PING_SECONDS) {
+      return None;
+    }
+    let id = self.next_id;
+    self.next_id = self.next_id.wrapping_add(1);
+    self.waiting.push_back((id, now));
+    self.last_ping = Some(now);
+    self.sent += 1;
+    Some(id)
+  }
+
+  pub fn pong(&mut self, id: u32, now: f64) {
+    let i = match self.waiting.iter().position(|&(waiting, _)| waiting == id) {
+      Some(i) => i,
+      None => return,
+    };
+    let (_, at) = self.waiting.remove(i).unwrap();
+    self.samples.push_back(now - at);
+    if self.samples.len() > PING_SAMPLES {
+      self.samples.pop_front();
+    }
+  }
+
+  // つなぎ直したら、前の接続で待っていた分は数えない
+  pub fn restart(&mut self) {
+    self.waiting.clear();
+    self.last_ping = None;
+  }
+
+  pub fn last(&self) -> Option<f64> {
+    self.samples.back().copied()
+  }
+
+  pub fn average(&self) -> Option<f64> {
+    if self.samples.is_empty() {
+      return None;
+    }
+    Some(self.samples.iter().sum::<f64>() / self.samples.len() as f64)
+  }
+
+  // 続けて測った往復時間の差の平均
+  pub fn jitter(&self) -> Option<f64> {
+    if self.samples.len() < 2 {
+      return None;
+    }
+    let diffs: Vec<f64> = self
+      .samples
+      .iter()
+      .zip(self.samples.iter().skip(1))
+      .map(|(a, b)| (b - a).abs())
+      .collect();
+    Some(diffs.iter().sum::<f64>() / diffs.len() as f64)
+  }
+
+  pub fn loss_percent(&self) -> f64 {
+    if self.sent == 0 {
+      return 0.;
+    }
+    self.lost as f64 * 100. / self.sent as f64
+  }
+}
+
+// 対戦中に出す接続の様子と、一時停止画面から開ける詳しい診断
+#[derive(Default)]
+pub struct NetDiagnostics {
+  pub summary: Option<String>,
+  pub report: String,
+}
+
+fn millis(seconds: Option<f64>) -> String {
+  seconds.map_or("-".to_string(), |s| format!("{:.0} ms", s * 1000.))
+}
+
+enum Link {
+  // つながっていない。ホストは接続を待ち、参加した側は切れた対戦があればつなぎ直す
+  Waiting,
+  // Hello を送って相手の Hello を待っている
+  Handshaking(Connection),
+  // ホストが、戻ってきた相手から Resume を待っている
+  Resuming(Connection, Agreement),
+  Connected(Connection, Agreement),
+  Closed(String),
+}
+
+struct Online {
+  role: Role,
+  link: Link,
+  // ホストはつなぎ直しに備えて待ち受けを閉じない
+  listener: Option<TcpListener>,
+  // ホストが決めた、この接続相手との対戦の番号
+  session: Option<u64>,
+  // 対戦中に切れた時刻
+  dropped: Option<f64>,
+  next_try: f64,
+  // 別のスレッドで試しているつなぎ直しの結果
+  retry: Option<Mutex<Receiver<Result<TcpStream, String>>>>,
+  // このフレームの時刻
+  now: f64,
+  last_heard: f64,
+  last_sent: Option<Snapshot>,
+  last_sent_at: f64,
+  pings: PingTracker,
+  // プロフィールの名札。つながるたびに送る
+  tag: NameTag,
+}
+
+impl Online {
+  fn status(&self) -> String {
+    if let Some(since) = self.dropped {
+      let left = (GRACE_SECONDS - (self.now - since)).max(0.);
+      return format!("connection lost, waiting to resume ({:.0}s left)", left);
+    }
+    match (&self.link, &self.role) {
+      (Link::Waiting, Role::Host(port)) => {
+        format!("hosting on port {}, waiting for an opponent", port)
+      }
+      (Link::Waiting, _) => "waiting for an opponent".to_string(),
+      (Link::Handshaking(_), _) | (Link::Resuming(..), _) => "checking versions".to_string(),
+      (Link::Connected(_, agreement), _) => format!("connected (protocol {})", agreement.version),
+      (Link::Closed(reason), _) => format!("disconnected: {}", reason),
+    }
+  }
+
+  fn diagnostics(&self) -> NetDiagnostics {
+    let (connection, agreement) = match &self.link {
+      Link::Connected(connection, agreement) => (connection, agreement),
+      _ => {
+        return NetDiagnostics {
+          summary: None,
+          report: format!("NETWORK\n\n{}", self.status()),
+        }
+      }
+    };
+    let pings = &self.pings;
+    let summary = if agreement.has(FEATURE_PING) {
+      Some(format!(
+        "rtt {}  jitter {}  lost {}",
+        millis(pings.last()),
+        millis(pings.jitter()),
+        pings.lost
+      ))
+    } else {
+      Some("rtt unknown (old opponent)".to_string())
+    };
+    let features: Vec<&str> = [
+      (FEATURE_ACTIVE_PIECE, "active piece"),
+      (FEATURE_RESUME, "resume"),
+      (FEATURE_SERVER, "server"),
+      (FEATURE_CHAT, "chat"),
+      (FEATURE_PING, "ping"),
+      (FEATURE_PROFILE, "name tags"),
+    ]
+    .iter()
+    .filter(|(feature, _)| agreement.has(*feature))
+    .map(|(_, name)| *name)
+    .collect();
+    let report = format!(
+      "NETWORK\n\n{}\nfeatures {}\n\nround trip  last {}  average {}\njitter {}\npings sent {}  lost {} ({:.1}%)\nbytes sent {}  received {}",
+      self.status(),
+      features.join(", "),
+      millis(pings.last()),
+      millis(pings.average()),
+      millis(pings.jitter()),
+      pings.sent,
+      pings.lost,
+      pings.loss_percent(),
+      connection.bytes_sent,
+      connection.bytes_received,
+    );
+    NetDiagnostics { summary, report }
+  }
+
+  // 待ち受けに来た接続を受けるか、切れた対戦につなぎ直す
+  fn wait(&mut self) -> Link {
+    if let Some(listener) = self.listener.as_ref() {
+      return match listener.accept() {
+        Ok((stream, addr)) => {
+          info!("opponent connected from {}", addr);
+          handshake(stream).unwrap_or_else(Link::Closed)
+        }
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => Link::Waiting,
+        Err(e) => Link::Closed(e.to_string()),
+      };
+    }
+    if let Some(retry) = self.retry.as_ref() {
+      let result = retry.lock().unwrap().try_recv();
+      let result = match result {
+        Err(TryRecvError::Empty) => return Link::Waiting,
+        Err(TryRecvError::Disconnected) => Err("reconnect thread stopped".to_string()),
+        Ok(result) => result,
+      };
+      self.retry = None;
+      // 試している間に待つのをやめていたら、つながっても使わない
+      if self.dropped.is_none() {
+        return Link::Waiting;
+      }
+      return match result.and_then(handshake) {
+        Ok(link) => link,
+        Err(e) => {
+          info!("reconnect failed: {}", e);
+          Link::Waiting
+        }
+      };
+    }
+    let address = match &self.role {
+      Role::Join(address) if self.dropped.is_some() && self.now >= self.next_try => address.clone(),
+      _ => return Link::Waiting,
+    };
+    self.next_try = self.now + RETRY_SECONDS;
+    // 名前解決と接続は待たされることがあるので、フレームを止めないよう別のスレッドで試す
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+      let _ = sender.send(open_stream(&address, RETRY_TIMEOUT));
+    });
+    self.retry = Some(Mutex::new(receiver));
+    Link::Waiting
+  }
+
+  fn handshake_done(
+    &mut self,
+    mut connection: Connection,
+    agreement: Agreement,
+    messages: Vec<Message>,
+    battle: &mut Battle,
+  ) -> Link {
+    self.pings.restart();
+    // 名札は Hello の形を変えずに、話がまとまった直後に送る
+    if agreement.has(FEATURE_PROFILE) {
+      connection.send(&Message::Profile {
+        seat: 0,
+        tag: self.tag.clone(),
+      });
+    }
+    battle.set_local_tag(self.tag.clone());
+    if self.dropped.is_some() {
+      if !agreement.has(FEATURE_RESUME) {
+        self.give_up(battle);
+        return Link::Closed("opponent's game cannot resume matches".to_string());
+      }
+      match (&self.role, self.session) {
+        (Role::Join(_), Some(id)) => {
+          connection.send(&Message::Resume { id });
+          self.resume(battle);
+        }
+        _ => return self.check_resume(connection, agreement, messages, battle),
+      }
+    } else if let Role::Host(_) = self.role {
+      battle.clear_tags();
+      // 勝敗の決め方と開始条件はホストに合わせる
+      if agreement.has(FEATURE_HANDICAP) {
+        let [host, guest] = battle.handicaps();
+        connection.send(&Message::Handicap { host, guest });
+      } else {
+        battle.clear_handicaps();
+      }
+      battle.take_handicap_changed();
+      let condition = battle.condition_index();
+      connection.send(&Message::Start {
+        condition: condition as u8,
+      });
+      if agreement.has(FEATURE_RESUME) {
+        let id = rand::random();
+        connection.send(&Message::Session { id });
+        self.session = Some(id);
+      }
+      self.last_sent = None;
+      battle.start_online(condition);
+    }
+    self.connected(connection, agreement, messages, battle)
+  }
+
+  // ホストは、戻ってきたのが同じ相手か確かめてから再開する
+  fn check_resume(
+    &mut self,
+    mut connection: Connection,
+    agreement: Agreement,
+    messages: Vec<Message>,
+    battle: &mut Battle,
+  ) -> Link {
+    let mut messages = messages.into_iter();
+    match messages.next() {
+      None => Link::Resuming(connection, agreement),
+      Some(Message::Resume { id }) if Some(id) == self.session => {
+        self.resume(battle);
+        self.connected(connection, agreement, messages.collect(), battle)
+      }
+      Some(_) => {
+        connection.send(&Message::Reject {
+          reason: "a match is already in progress".to_string(),
+        });
+        let _ = connection.pump();
+        Link::Waiting
+      }
+    }
+  }
+
+  fn connected(
+    &mut self,
+    mut connection: Connection,
+    agreement: Agreement,
+    messages: Vec<Message>,
+    battle: &mut Battle,
+  ) -> Link {
+    if !messages.is_empty() {
+      self.last_heard = self.now;
+    }
+    // 静かな相手が本当に切れているかは、送っても分からないことがある
+    if battle.is_playing() && self.now - self.last_heard > SILENCE_SECONDS {
+      return self.lost("opponent stopped responding".to_string(), battle);
+    }
+    let mut exchange = Exchange {
+      connection: &mut connection,
+      agreement,
+      now: self.now,
+      session: &mut self.session,
+      last_sent: &mut self.last_sent,
+      last_sent_at: &mut self.last_sent_at,
+      pings: &mut self.pings,
+      battle,
+    };
+    match exchange.run(messages) {
+      Ok(()) => Link::Connected(connection, agreement),
+      Err(e) => self.lost(e, battle),
+    }
+  }
+
+  // 対戦中ならしばらく止めて相手を待つ。そうでなければ終わり
+  fn lost(&mut self, reason: String, battle: &mut Battle) -> Link {
+    if self.dropped.is_some() {
+      return Link::Waiting;
+    }
+    // つなぎ直せない相手なら、その場で相手の負けにする
+    if self.session.is_none() {
+      self.give_up(battle);
+      return Link::Closed(reason);
+    }
+    if !battle.is_playing() {
+      return Link::Closed(reason);
+    }
+    info!("connection lost: {}", reason);
+    self.dropped = Some(self.now);
+    battle.set_paused(true);
+    Link::Waiting
+  }
+
+  fn resume(&mut self, battle: &mut Battle) {
+    self.dropped = None;
+    self.last_heard = self.now;
+    // 途中で届かなかった分を埋めるため盤面を送り直す
+    self.last_sent = None;
+    battle.set_paused(false);
+  }
+
+  fn give_up(&mut self, battle: &mut Battle) {
+    self.dropped = None;
+    battle.forfeit_remote();
+  }
+}
+
+pub struct OnlinePlugin;
+
+impl Plugin for OnlinePlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(NetDiagnostics::default())
+      .add_startup_system(open_link.system())
+      .add_system_set(SystemSet::on_enter(AppState::Battle).with_system(setup_net_overlay.system()))
+      // 一時停止画面を重ねている間も、つながりは保つ
+      .add_system_set(
+        SystemSet::on_in_stack_update(AppState::Battle).with_system(online_link.system()),
+      )
+      .add_system_set(SystemSet::on_update(AppState::Battle).with_system(net_overlay.system()));
+  }
+}
+
+fn open_link(mut commands: Commands, tag: Res<NameTag>) {
+  let mut online = Online {
+    role: Role::Host(DEFAULT_PORT),
+    link: Link::Waiting,
+    listener: None,
+    session: None,
+    dropped: None,
+    next_try: 0.,
+    retry: None,
+    now: 0.,
+    last_heard: 0.,
+    last_sent: None,
+    last_sent_at: 0.,
+    pings: PingTracker::default(),
+    tag: tag.clone(),
+  };
+  match Role::from_args() {
+    Ok(Role::Host(port)) => {
+      online.role = Role::Host(port);
+      match listen(port) {
+        Ok(listener) => online.listener = Some(listener),
+        Err(e) => online.link = Link::Closed(e),
+      }
+    }
+    Ok(Role::Join(address)) => {
+      online.link = connect(&address, CONNECT_TIMEOUT).unwrap_or_else(Link::Closed);
+      online.role = Role::Join(address);
+    }
+    Err(e) => online.link = Link::Closed(e),
+  }
+  commands.insert_resource(online);
+}
+
+struct NetOverlay;
+
+// 右上に小さく出す
+fn setup_net_overlay(mut commands: Commands, fonts: Res<Fonts>) {
+  let mut text = overlay_text(&fonts);
+  text.style.position = Rect {
+    top: Val::Px(5.0),
+    right: Val::Px(5.0),
+    ..Default::default()
+  };
+  text.text.sections[0].style.font_size = 14.0;
+  commands.spawn_bundle(text).insert(NetOverlay);
+}
+
+fn net_overlay(diagnostics: Res<NetDiagnostics>, mut query: Query<&mut Text, With<NetOverlay>>) {
+  let value = diagnostics.summary.clone().unwrap_or_default();
+  for mut text in query.iter_mut() {
+    if text.sections[0].value != value {
+      text.sections[0].value = value.clone();
+    }
+  }
+}
+
+fn listen(port: u16) -> Result<TcpListener, String> {
+  let listener = TcpListener::bind(("0.0.0.0", port)).map_err(|e| e.to_string())?;
+  listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+  Ok(listener)
+}
+
+fn connect(address: &str, timeout: Duration) -> Result<Link, String> {
+  open_stream(address, timeout).and_then(handshake)
+}
+
+fn open_stream(address: &str, timeout: Duration) -> Result<TcpStream, String> {
+  // ポートを省いたら既定のポートにする
+  let address = if address.contains(':') {
+    address.to_string()
+  } else {
+    format!("{}:{}", address, DEFAULT_PORT)
+  };
+  let addr: SocketAddr = address
+    .to_socket_addrs()
+    .map_err(|e| e.to_string())?
+    .next()
+    .ok_or_else(|| format!("unknown address: {}", address))?;
+  TcpStream::connect_timeout(&addr, timeout).map_err(|e| e.to_string())
+}
+
+fn handshake(stream: TcpStream) -> Result<Link, String> {
+  let mut connection = Connection::new(stream).map_err(|e| e.to_string())?;
+  connection.send(&Message::Hello(Hello::current()));
+  Ok(Link::Handshaking(connection))
+}
+
+fn online_link(
+  time: Res<Time>,
+  keyboard_input: Res<Input<KeyCode>>,
+  mut online: ResMut<Online>,
+  mut battle: ResMut<Battle>,
+  mut diagnostics: ResMut<NetDiagnostics>,
+) {
+  let online = &mut *online;
+  let battle = &mut *battle;
+  online.now = time.seconds_since_startup();
+  let link = std::mem::replace(&mut online.link, Link::Waiting);
+  online.link = match link {
+    Link::Waiting => online.wait(),
+    Link::Handshaking(mut connection) => match connection.pump() {
+      Ok(messages) if messages.is_empty() => Link::Handshaking(connection),
+      Ok(messages) => {
+        let mut messages = messages.into_iter();
+        match messages.next() {
+          Some(Message::Hello(remote)) => match negotiate(&Hello::current(), &remote) {
+            Ok(agreement) => {
+              online.last_heard = online.now;
+              online.handshake_done(connection, agreement, messages.collect(), battle)
+            }
+            Err(reason) => {
+              connection.send(&Message::Reject {
+                reason: reason.clone(),
+              });
+              let _ = connection.pump();
+              online.give_up(battle);
+              Link::Closed(reason)
+            }
+          },
+          // 相手の側から見た理由なので、そのまま出すと逆の意味になる
+          Some(Message::Reject { reason }) => Link::Closed(format!("opponent refused: {}", reason)),
+          _ => Link::Closed("opponent did not start with a handshake".to_string()),
+        }
+      }
+      Err(e) => online.lost(e, battle),
+    },
+    Link::Resuming(mut connection, agreement) => match connection.pump() {
+      Ok(messages) => online.check_resume(connection, agreement, messages, battle),
+      Err(_) => Link::Waiting,
+    },
+    Link::Connected(mut connection, agreement) => match connection.pump() {
+      Ok(mut messages) => {
+        // 決着後にどちらかが Enter を押したら、相手にも伝えて続けて対戦する。
+        // 対戦サーバーでは全員がそろってからサーバーが始める
+        if battle.is_over() && keyboard_input.just_pressed(KeyCode::Return) {
+          if !battle.on_server() {
+            messages.push(Message::Start {
+              condition: battle.condition_index() as u8,
+            });
+          }
+          connection.send(&Message::Start {
+            condition: battle.condition_index() as u8,
+          });
+        }
+        online.connected(connection, agreement, messages, battle)
+      }
+      Err(e) => online.lost(e, battle),
+    },
+    closed => closed,
+  };
+
+  if let Some(since) = online.dropped {
+    if online.now - since > GRACE_SECONDS {
+      online.give_up(battle);
+      online.link = Link::Closed("opponent did not come back in time".to_string());
+    }
+  }
+  let status = online.status();
+  let host = matches!(online.role, Role::Host(_));
+  battle.set_online(status, host);
+  *diagnostics = online.diagnostics();
+}
+
+// つながっている間の1フレーム分のやり取り
+struct Exchange<'a> {
+  connection: &'a mut Connection,
+  agreement: Agreement,
+  now: f64,
+  session: &'a mut Option<u64>,
+  last_sent: &'a mut Option<Snapshot>,
+  last_sent_at: &'a mut f64,
+  pings: &'a mut PingTracker,
+  battle: &'a mut Battle,
+}
+
+impl<'a> Exchange<'a> {
+  fn run(&mut self, messages: Vec<Message>) -> Result<(), String> {
+    for message in messages {
+      match message {
+        Message::Start { condition } => {
+          *self.last_sent = None;
+          self.battle.start_online(condition as usize);
+        }
+        Message::Session { id } => *self.session = Some(id),
+        Message::State(snapshot) => self.battle.apply_snapshot(snapshot),
+        Message::Garbage { lines } => self.battle.receive_garbage(lines),
+        Message::Welcome { seat, players } => {
+          self.battle.join_server(seat as usize, players as usize)
+        }
+        Message::Seat { seat, snapshot } => self.battle.apply_seat(seat as usize, snapshot),
+        Message::Chat { seat, phrase } => self.battle.receive_chat(seat, phrase),
+        Message::Ping { id } => self.connection.send(&Message::Pong { id }),
+        Message::Pong { id } => self.pings.pong(id, self.now),
+        Message::Handicap { host, guest } => self.battle.receive_handicap(host, guest),
+        Message::Profile { seat, tag } => self.battle.receive_tag(seat, tag),
+        Message::Reject { reason } => return Err(reason),
+        Message::Hello(_) | Message::Resume { .. } | Message::Input { .. } => {}
+      }
+    }
+    // ホストが対戦の合間に変えた開始条件
+    if self.battle.take_handicap_changed() {
+      if self.agreement.has(FEATURE_HANDICAP) {
+        let [host, guest] = self.battle.handicaps();
+        self.connection.send(&Message::Handicap { host, guest });
+      } else {
+        self.battle.clear_handicaps();
+      }
+    }
+    if self.agreement.has(FEATURE_PING) {
+      if let Some(id) = self.pings.due(self.now) {
+        self.connection.send(&Message::Ping { id });
+      }
+    }
+    // チャットに対応していない相手には送らずに捨てる
+    for phrase in self.battle.take_chat() {
+      if self.agreement.has(FEATURE_CHAT) {
+        self.connection.send(&Message::Chat { seat: 0, phrase });
+      }
+    }
+    // サーバーには操作だけ送る。攻撃と盤面はサーバーが配る
+    if self.battle.on_server() {
+      for action in self.battle.take_inputs() {
+        self.connection.send(&Message::Input { action });
+      }
+      return Ok(());
+    }
+    if !self.battle.is_playing() && !self.battle.is_over() {
+      return Ok(());
+    }
+
+    let lines = self.battle.take_outgoing();
+    if lines > 0 {
+      self.connection.send(&Message::Garbage { lines });
+    }
+    // 盤面が変わったときと、しばらく何も送っていないときに送る
+    let snapshot = self
+      .battle
+      .local_snapshot(self.agreement.has(FEATURE_ACTIVE_PIECE));
+    let quiet = self.now - *self.last_sent_at > KEEPALIVE_SECONDS;
+    if quiet || self.last_sent.as_ref() != Some(&snapshot) {
+      self.connection.send(&Message::State(snapshot.clone()));
+      *self.last_sent = Some(snapshot);
+      *self.last_sent_at = self.now;
+    }
+    Ok(())
+  }
+}
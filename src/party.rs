@@ -0,0 +1,170 @@
+use bevy::prelude::*;
+
+use crate::profile::{Controls, Preset};
+use crate::{overlay_text, AppState, BlockStacked, Fonts, GameReset, LinesCleared};
+
+pub const MIN_PLAYERS: usize = 2;
+pub const MAX_PLAYERS: usize = 4;
+const DEFAULT_PIECES_PER_TURN: u32 = 3;
+// 1 つのキーボードに並んで座れるよう、人ごとに違う場所のキー配置を使う
+const PARTY_PRESETS: [Preset; MAX_PLAYERS] = [
+  Preset::LeftHand,
+  Preset::RightHand,
+  Preset::Numpad,
+  Preset::Standard,
+];
+
+// 同じ盤面を数ミノずつ交代で操作する。番が替わるとキー配置をその人のものに差し替える
+#[derive(Clone, Debug)]
+pub struct HotSeat {
+  pub players: usize,
+  pub pieces_per_turn: u32,
+  pub turn: usize,
+  // 今の番で置いた数
+  placed: u32,
+  // 最後にミノを置いた人。消したラインはこの人の分
+  last_placer: usize,
+  // 人ごとに置いたミノと消したライン
+  pub pieces: Vec<u32>,
+  pub lines: Vec<u32>,
+}
+
+impl HotSeat {
+  pub fn new(players: usize, pieces_per_turn: u32) -> Self {
+    let players = players.clamp(MIN_PLAYERS, MAX_PLAYERS);
+    HotSeat {
+      players,
+      pieces_per_turn: pieces_per_turn.max(1),
+      turn: 0,
+      placed: 0,
+      last_placer: 0,
+      pieces: vec![0; players],
+      lines: vec![0; players],
+    }
+  }
+
+  // tetris party [人数] [1 人が続けて置く数]
+  fn from_args() -> Self {
+    let arg = |n: usize| std::env::args().nth(n).and_then(|s| s.parse().ok());
+    HotSeat::new(
+      arg(2).unwrap_or(MIN_PLAYERS),
+      arg(3).map_or(DEFAULT_PIECES_PER_TURN, |n: usize| n as u32),
+    )
+  }
+
+  fn restart(&mut self) {
+    *self = HotSeat::new(self.players, self.pieces_per_turn);
+  }
+
+  pub fn preset(&self, player: usize) -> Preset {
+    PARTY_PRESETS[player]
+  }
+
+  // 置いたミノを今の番の人に数える。番が替わったら true
+  pub fn record_piece(&mut self) -> bool {
+    self.pieces[self.turn] += 1;
+    self.last_placer = self.turn;
+    self.placed += 1;
+    if self.placed < self.pieces_per_turn {
+      return false;
+    }
+    self.placed = 0;
+    self.turn = (self.turn + 1) % self.players;
+    true
+  }
+
+  pub fn record_lines(&mut self, lines: u32) {
+    self.lines[self.last_placer] += lines;
+  }
+
+  pub fn summary(&self) -> String {
+    let rows: Vec<String> = (0..self.players)
+      .map(|p| {
+        format!(
+          "P{} ({})  pieces {}  lines {}",
+          p + 1,
+          self.preset(p).name(),
+          self.pieces[p],
+          self.lines[p]
+        )
+      })
+      .collect();
+    rows.join("\n")
+  }
+}
+
+struct PartyText;
+
+pub struct PartyPlugin;
+
+impl Plugin for PartyPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(HotSeat::from_args())
+      .add_startup_system_to_stage(StartupStage::PostStartup, setup_party.system())
+      .add_system_set(SystemSet::on_enter(AppState::Playing).with_system(party_start.system()))
+      .add_system_set(SystemSet::on_update(AppState::Playing).with_system(party_turns.system()))
+      .add_system(party_reset.system())
+      .add_system(party_ui.system());
+  }
+}
+
+fn setup_party(mut commands: Commands, fonts: Res<Fonts>) {
+  commands
+    .spawn_bundle(overlay_text(&fonts))
+    .insert(PartyText);
+}
+
+fn party_start(mut seat: ResMut<HotSeat>, mut controls: ResMut<Controls>) {
+  seat.restart();
+  controls.bindings = seat.preset(0).bindings();
+}
+
+fn party_reset(
+  mut seat: ResMut<HotSeat>,
+  mut controls: ResMut<Controls>,
+  mut reset_events: EventReader<GameReset>,
+) {
+  if reset_events.iter().count() > 0 {
+    seat.restart();
+    controls.bindings = seat.preset(0).bindings();
+  }
+}
+
+fn party_turns(
+  mut seat: ResMut<HotSeat>,
+  mut controls: ResMut<Controls>,
+  mut stacked_events: EventReader<BlockStacked>,
+  mut cleared_events: EventReader<LinesCleared>,
+) {
+  for _ in stacked_events.iter() {
+    if seat.record_piece() {
+      controls.bindings = seat.preset(seat.turn).bindings();
+    }
+  }
+  for event in cleared_events.iter() {
+    seat.record_lines(event.0);
+  }
+}
+
+fn party_ui(
+  state: Res<State<AppState>>,
+  seat: Res<HotSeat>,
+  mut query: Query<&mut Text, With<PartyText>>,
+) {
+  let value = if matches!(state.current(), AppState::Playing | AppState::Paused) {
+    format!(
+      "PARTY  P{}'s turn ({})\n{} left this turn",
+      seat.turn + 1,
+      seat.preset(seat.turn).name(),
+      seat.pieces_per_turn - seat.placed
+    )
+  } else {
+    String::new()
+  };
+  for mut text in query.iter_mut() {
+    if text.sections[0].value != value {
+      text.sections[0].value = value.clone();
+    }
+  }
+}
@@ -0,0 +1,220 @@
+use bevy::input::mouse::MouseMotion;
+use bevy::prelude::*;
+use bevy::window::WindowFocused;
+
+use crate::config::Config;
+use crate::hud::HUD_KEYS;
+use crate::layout::VIEW_KEYS;
+use crate::menu::MenuInput;
+use crate::online::NetDiagnostics;
+use crate::{overlay_text, AppState, Fonts, GameClock, GameMode, Materials, ResumeTime};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum PauseReason {
+  FocusLost,
+  Idle,
+  // 通信対戦で Esc を押した。対戦は止まらない
+  Online,
+}
+
+struct Pause {
+  reason: PauseReason,
+  // 最後に何か入力があった時刻
+  last_input: f64,
+  // 通信の詳しい診断を出している
+  diagnostics: bool,
+}
+
+struct PauseScreen;
+struct PauseText;
+
+pub struct PausePlugin;
+
+impl Plugin for PausePlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(Pause {
+        reason: PauseReason::FocusLost,
+        last_input: 0.,
+        diagnostics: false,
+      })
+      .add_system_set(SystemSet::on_enter(AppState::Playing).with_system(reset_idle.system()))
+      .add_system_set(SystemSet::on_resume(AppState::Playing).with_system(reset_idle.system()))
+      .add_system_set(
+        SystemSet::on_update(AppState::Playing)
+          .with_system(focus_pause.system())
+          .with_system(idle_pause.system()),
+      )
+      .add_system_set(SystemSet::on_update(AppState::Battle).with_system(online_pause.system()))
+      .add_system_set(SystemSet::on_enter(AppState::Paused).with_system(setup_pause.system()))
+      .add_system_set(
+        SystemSet::on_update(AppState::Paused)
+          .with_system(pause_input.system())
+          .with_system(pause_text.system()),
+      )
+      .add_system_set(SystemSet::on_exit(AppState::Paused).with_system(close_pause.system()));
+  }
+}
+
+// alt-tab などでフォーカスが外れたら止める
+fn focus_pause(
+  config: Res<Config>,
+  mut pause: ResMut<Pause>,
+  mut state: ResMut<State<AppState>>,
+  mut focused_events: EventReader<WindowFocused>,
+) {
+  let lost_focus = focused_events
+    .iter()
+    .any(|e| e.id.is_primary() && !e.focused);
+  if lost_focus && config.pause.on_focus_loss {
+    pause.reason = PauseReason::FocusLost;
+    if let Err(e) = state.push(AppState::Paused) {
+      warn!("cannot pause: {:?}", e);
+    }
+  }
+}
+
+// プレイに入ったところから放置時間を数える
+fn reset_idle(time: Res<Time>, mut pause: ResMut<Pause>) {
+  pause.last_input = time.seconds_since_startup();
+}
+
+// 一人用のプレイで放置されていたら止める
+fn idle_pause(
+  time: Res<Time>,
+  config: Res<Config>,
+  mode: Res<GameMode>,
+  keyboard_input: Res<Input<KeyCode>>,
+  mouse_input: Res<Input<MouseButton>>,
+  mut mouse_motion_events: EventReader<MouseMotion>,
+  mut pause: ResMut<Pause>,
+  mut state: ResMut<State<AppState>>,
+) {
+  let now = time.seconds_since_startup();
+  let has_input = keyboard_input.get_pressed().next().is_some()
+    || mouse_input.get_pressed().next().is_some()
+    || mouse_motion_events.iter().count() > 0;
+  if has_input {
+    pause.last_input = now;
+    return;
+  }
+
+  let limit = config.pause.idle_seconds;
+  if *mode == GameMode::Editor || limit <= 0. || now < pause.last_input + limit {
+    return;
+  }
+  pause.reason = PauseReason::Idle;
+  if let Err(e) = state.push(AppState::Paused) {
+    warn!("cannot pause: {:?}", e);
+  }
+}
+
+// 通信対戦は止められないので、Esc で開く画面は見るだけ
+fn online_pause(
+  mode: Res<GameMode>,
+  keyboard_input: Res<Input<KeyCode>>,
+  mut pause: ResMut<Pause>,
+  mut state: ResMut<State<AppState>>,
+) {
+  if *mode != GameMode::Online || !keyboard_input.just_pressed(KeyCode::Escape) {
+    return;
+  }
+  pause.reason = PauseReason::Online;
+  pause.diagnostics = false;
+  if let Err(e) = state.push(AppState::Paused) {
+    warn!("cannot pause: {:?}", e);
+  }
+}
+
+fn setup_pause(
+  mut commands: Commands,
+  fonts: Res<Fonts>,
+  materials: Res<Materials>,
+  pause: Res<Pause>,
+) {
+  let mut text = overlay_text(&fonts);
+  text.style.position = Rect {
+    top: Val::Px(200.0),
+    left: Val::Px(40.0),
+    ..Default::default()
+  };
+  text.text.sections[0].value = pause_message(&pause, None);
+
+  commands
+    .spawn_bundle(NodeBundle {
+      style: Style {
+        position_type: PositionType::Absolute,
+        size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+        ..Default::default()
+      },
+      material: materials.panel.clone(),
+      ..Default::default()
+    })
+    .insert(PauseScreen)
+    .with_children(|parent| {
+      parent.spawn_bundle(text).insert(PauseText);
+    });
+}
+
+fn pause_message(pause: &Pause, diagnostics: Option<&NetDiagnostics>) -> String {
+  if let (true, Some(diagnostics)) = (pause.diagnostics, diagnostics) {
+    return format!("{}\n\n[N] back", diagnostics.report);
+  }
+  let message = match pause.reason {
+    PauseReason::FocusLost => "PAUSED",
+    PauseReason::Idle => "PAUSED\npaused due to inactivity",
+    PauseReason::Online => "PAUSED\nthe online match keeps running\n\n[N] network diagnostics",
+  };
+  format!(
+    "{}\n\n[-] [=] zoom  arrows move board  [0] reset view\npress any other key, A/B on a gamepad\nor click to resume",
+    message
+  )
+}
+
+// 診断の数字は開いている間も変わる
+fn pause_text(
+  pause: Res<Pause>,
+  diagnostics: Option<Res<NetDiagnostics>>,
+  mut query: Query<&mut Text, With<PauseText>>,
+) {
+  let value = pause_message(&pause, diagnostics.as_deref());
+  for mut text in query.iter_mut() {
+    if text.sections[0].value != value {
+      text.sections[0].value = value.clone();
+    }
+  }
+}
+
+fn pause_input(
+  keyboard_input: Res<Input<KeyCode>>,
+  menu: Res<MenuInput>,
+  mut pause: ResMut<Pause>,
+  mut state: ResMut<State<AppState>>,
+) {
+  if pause.reason == PauseReason::Online && keyboard_input.just_pressed(KeyCode::N) {
+    pause.diagnostics = !pause.diagnostics;
+    return;
+  }
+  // F2 は設定画面を開くので、数字は HUD の切り替えなので、VIEW_KEYS は盤面の表示の調整なので除く
+  let resume = keyboard_input
+    .get_just_pressed()
+    .any(|&key| key != KeyCode::F2 && !HUD_KEYS.contains(&key) && !VIEW_KEYS.contains(&key))
+    || menu.confirm
+    || menu.back
+    || menu.clicked;
+  if resume {
+    state.pop().unwrap();
+  }
+}
+
+fn close_pause(
+  mut commands: Commands,
+  clock: Res<GameClock>,
+  mut resume_time: ResMut<ResumeTime>,
+  query: Query<Entity, With<PauseScreen>>,
+) {
+  for entity in query.iter() {
+    commands.entity(entity).despawn_recursive();
+  }
+  resume_time.0 = clock.0;
+}
@@ -0,0 +1,198 @@
+use bevy::prelude::*;
+
+use crate::ai::is_misdrop;
+use crate::board::Board;
+use crate::config::Config;
+use crate::snapshot::BoardImported;
+use crate::stats::Statistics;
+use crate::{
+  block_idx_from_name, block_name, overlay_text, spawn_stacked_block, ActiveBlock, AppState,
+  BlockStacked, Fonts, Label, Materials, PiecePhase, PieceQueue, Position, PrimitiveBlock,
+  StackedBlock,
+};
+
+// P キーで順番に選べる並び
+const PRESETS: [&str; 4] = ["IJLOSTZ", "I", "TSZ", "LJO"];
+// 置き間違いの警告を出しておく秒数と、点滅の間隔
+const MISDROP_SECONDS: f32 = 1.0;
+const MISDROP_BLINK: f32 = 0.1;
+
+struct Practice {
+  // 空ならランダム
+  sequence: Vec<u32>,
+  preset: usize,
+}
+
+struct PracticeText;
+struct MisdropText;
+
+// 置き間違いの警告の残り秒数
+#[derive(Default)]
+struct MisdropWarning(f32);
+
+pub struct PracticePlugin;
+
+impl Plugin for PracticePlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(Practice {
+        sequence: vec![],
+        preset: 0,
+      })
+      .add_system_set(SystemSet::on_enter(AppState::Setup).with_system(setup_practice.system()))
+      .add_system_set(SystemSet::on_update(AppState::Setup).with_system(practice_input.system()))
+      .add_system_set(SystemSet::on_exit(AppState::Setup).with_system(apply_practice.system()))
+      .insert_resource(MisdropWarning::default())
+      .add_startup_system_to_stage(StartupStage::PostStartup, setup_misdrops.system())
+      .add_system_set(
+        SystemSet::on_update(AppState::Playing)
+          .with_system(import_board.system())
+          // 積んだマスが盤面に入る前に、置く前の盤面と比べる
+          .with_system(detect_misdrops.system().after(Label::Stack)),
+      )
+      .add_system(misdrop_ui.system());
+  }
+}
+
+// "IJLOSTZ" のような文字列をミノの並びにする
+pub fn parse_sequence(src: &str) -> Option<Vec<u32>> {
+  src
+    .chars()
+    .filter(|c| !c.is_whitespace())
+    .map(block_idx_from_name)
+    .collect()
+}
+
+fn setup_practice(mut commands: Commands, fonts: Res<Fonts>) {
+  let mut text = overlay_text(&fonts);
+  text.style.position = Rect {
+    top: Val::Px(360.0),
+    left: Val::Px(40.0),
+    ..Default::default()
+  };
+  commands.spawn_bundle(text).insert(PracticeText);
+}
+
+fn practice_input(
+  mut practice: ResMut<Practice>,
+  mut char_events: EventReader<ReceivedCharacter>,
+  keyboard_input: Res<Input<KeyCode>>,
+  mut query: Query<&mut Text, With<PracticeText>>,
+) {
+  for event in char_events.iter() {
+    // 設定画面の H/G/D などはミノの名前と重ならない
+    if let Some(idx) = block_idx_from_name(event.char) {
+      practice.sequence.push(idx);
+    }
+  }
+  if keyboard_input.just_pressed(KeyCode::Back) {
+    practice.sequence.pop();
+  } else if keyboard_input.just_pressed(KeyCode::P) {
+    let preset = PRESETS[practice.preset % PRESETS.len()];
+    practice.sequence = parse_sequence(preset).unwrap();
+    practice.preset += 1;
+  } else if keyboard_input.just_pressed(KeyCode::Delete) {
+    practice.sequence.clear();
+  }
+
+  let sequence: String = practice
+    .sequence
+    .iter()
+    .map(|&idx| block_name(idx))
+    .collect();
+  let value = format!(
+    "PRACTICE\nsequence: {} (repeated)\n\n[OZSLJTI] add piece  [BS] remove\n[P] preset  [Del] random\n[F4] while playing: paste a board",
+    if sequence.is_empty() {
+      "random"
+    } else {
+      &sequence
+    }
+  );
+  for mut text in query.iter_mut() {
+    text.sections[0].value = value.clone();
+  }
+}
+
+fn apply_practice(
+  mut commands: Commands,
+  practice: Res<Practice>,
+  mut piece_queue: ResMut<PieceQueue>,
+  query: Query<Entity, With<PracticeText>>,
+) {
+  for entity in query.iter() {
+    commands.entity(entity).despawn();
+  }
+  piece_queue.set_pattern(practice.sequence.clone());
+}
+
+// 読み込んだ盤面に積み直し、操作中のミノは出し直す
+fn import_board(
+  mut commands: Commands,
+  materials: Res<Materials>,
+  mut active_block: ResMut<ActiveBlock>,
+  mut imported_events: EventReader<BoardImported>,
+  query: Query<Entity, Or<(With<PrimitiveBlock>, With<StackedBlock>)>>,
+) {
+  let cells = match imported_events.iter().last() {
+    Some(event) => event.0.clone(),
+    None => return,
+  };
+  for entity in query.iter() {
+    commands.entity(entity).despawn();
+  }
+  for cell in cells {
+    spawn_stacked_block(&mut commands, &materials, cell);
+  }
+  active_block.phase = PiecePhase::Spawning;
+}
+
+fn setup_misdrops(mut commands: Commands, fonts: Res<Fonts>) {
+  let mut text = overlay_text(&fonts);
+  text.style.position = Rect {
+    top: Val::Px(40.0),
+    left: Val::Px(5.0),
+    ..Default::default()
+  };
+  text.text.sections[0].style.color = Color::rgb(1.0, 0.3, 0.3);
+  commands.spawn_bundle(text).insert(MisdropText);
+}
+
+// 穴を作らずに置けたのに穴を作ったら数えて警告する
+fn detect_misdrops(
+  mut stats: ResMut<Statistics>,
+  mut warning: ResMut<MisdropWarning>,
+  mut stacked_events: EventReader<BlockStacked>,
+  stacked_query: Query<&Position, With<StackedBlock>>,
+) {
+  for event in stacked_events.iter() {
+    let board = Board::from_cells(stacked_query.iter());
+    if is_misdrop(&board, event.block_idx, &event.cells) {
+      stats.misdrops += 1;
+      warning.0 = MISDROP_SECONDS;
+    }
+  }
+}
+
+// 動きを減らす設定のときは点滅させない
+fn misdrop_ui(
+  time: Res<Time>,
+  config: Res<Config>,
+  state: Res<State<AppState>>,
+  stats: Res<Statistics>,
+  mut warning: ResMut<MisdropWarning>,
+  mut query: Query<&mut Text, With<MisdropText>>,
+) {
+  warning.0 = (warning.0 - time.delta_seconds()).max(0.);
+  let playing = matches!(state.current(), AppState::Playing | AppState::Paused);
+  let blink_on = config.reduce_motion || ((warning.0 / MISDROP_BLINK) as u32).is_multiple_of(2);
+  let value = match (playing, warning.0 > 0. && blink_on) {
+    (false, _) => String::new(),
+    (true, true) => format!("MISDROP\nmisdrops {}", stats.misdrops),
+    (true, false) => format!("\nmisdrops {}", stats.misdrops),
+  };
+  for mut text in query.iter_mut() {
+    if text.sections[0].value != value {
+      text.sections[0].value = value.clone();
+    }
+  }
+}
@@ -0,0 +1,665 @@
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::menu::{MenuInput, MenuList};
+use crate::protocol::{NameTag, MAX_TAG_NAME, TAG_COLORS};
+use crate::speed::TickRate;
+use crate::stats::Statistics;
+use crate::{overlay_text, save, AppState, Direction, Fonts, GameClock, GameMode};
+
+const LIST_FILE: &str = "profiles.ron";
+const CONTROLS_FILE: &str = "controls.ron";
+const STATS_FILE: &str = "stats.ron";
+const TAG_FILE: &str = "nametag.ron";
+// ディレクトリ名にも使うので短く、記号は - と _ だけ
+pub const MAX_NAME_LEN: usize = 16;
+// プレイヤーの一覧の下に並べる、キーを使わずに選べる項目
+const TITLE_ACTIONS: [&str; 4] = [
+  "+ new player",
+  "achievements",
+  "replay last game",
+  "online name tag",
+];
+// 名札の色。並びが通信で送る番号になるので、足すときは後ろに足す
+pub const TAG_PALETTE: [(&str, Color); TAG_COLORS as usize] = [
+  ("white", Color::rgb(1.0, 1.0, 1.0)),
+  ("red", Color::rgb(1.0, 0.4, 0.4)),
+  ("orange", Color::rgb(1.0, 0.65, 0.3)),
+  ("yellow", Color::rgb(1.0, 0.9, 0.35)),
+  ("green", Color::rgb(0.45, 0.9, 0.45)),
+  ("cyan", Color::rgb(0.4, 0.9, 1.0)),
+  ("blue", Color::rgb(0.5, 0.6, 1.0)),
+  ("purple", Color::rgb(0.8, 0.5, 1.0)),
+];
+
+pub fn is_valid_name(name: &str) -> bool {
+  !name.is_empty()
+    && name.chars().count() <= MAX_NAME_LEN
+    && name
+      .chars()
+      .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ProfileList {
+  pub names: Vec<String>,
+  pub current: usize,
+}
+
+impl Default for ProfileList {
+  fn default() -> Self {
+    Self {
+      names: vec!["Player".to_string()],
+      current: 0,
+    }
+  }
+}
+
+impl ProfileList {
+  // 最後に使ったプロフィールを選んだ状態で読み込む
+  pub fn load() -> Self {
+    let mut list: ProfileList = save::load_shared(LIST_FILE);
+    list.names.retain(|name| is_valid_name(name));
+    if list.names.is_empty() {
+      list = ProfileList::default();
+    }
+    list.current = list.current.min(list.names.len() - 1);
+    save::set_profile(list.current_name());
+    list
+  }
+
+  fn store(&self) {
+    if let Err(e) = save::store_shared(LIST_FILE, self) {
+      error!("failed to save profiles: {}", e);
+    }
+  }
+
+  pub fn current_name(&self) -> &str {
+    &self.names[self.current]
+  }
+
+  pub fn add(&mut self, name: &str) -> bool {
+    if !is_valid_name(name) || self.names.iter().any(|n| n.eq_ignore_ascii_case(name)) {
+      return false;
+    }
+    self.names.push(name.to_string());
+    self.current = self.names.len() - 1;
+    true
+  }
+
+  // 一覧から外すだけで、セーブファイルは残す
+  pub fn remove_current(&mut self) -> bool {
+    if self.names.len() <= 1 {
+      return false;
+    }
+    self.names.remove(self.current);
+    self.current = self.current.min(self.names.len() - 1);
+    true
+  }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeyBindings {
+  pub left: Vec<KeyCode>,
+  pub right: Vec<KeyCode>,
+  pub soft_drop: Vec<KeyCode>,
+  pub rotate_cw: Vec<KeyCode>,
+  pub rotate_ccw: Vec<KeyCode>,
+  pub hold: Vec<KeyCode>,
+}
+
+impl Default for KeyBindings {
+  fn default() -> Self {
+    Self {
+      left: vec![KeyCode::Left],
+      right: vec![KeyCode::Right],
+      soft_drop: vec![KeyCode::Down],
+      rotate_cw: vec![KeyCode::Up, KeyCode::X],
+      rotate_ccw: vec![KeyCode::Z, KeyCode::LControl],
+      hold: vec![KeyCode::C, KeyCode::LShift],
+    }
+  }
+}
+
+// キーを割り当てる操作
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Binding {
+  Left,
+  Right,
+  SoftDrop,
+  RotateCw,
+  RotateCcw,
+  Hold,
+}
+
+impl Binding {
+  pub const ALL: [Binding; 6] = [
+    Binding::Left,
+    Binding::Right,
+    Binding::SoftDrop,
+    Binding::RotateCw,
+    Binding::RotateCcw,
+    Binding::Hold,
+  ];
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      Binding::Left => "left",
+      Binding::Right => "right",
+      Binding::SoftDrop => "soft drop",
+      Binding::RotateCw => "rotate cw",
+      Binding::RotateCcw => "rotate ccw",
+      Binding::Hold => "hold",
+    }
+  }
+}
+
+// 別の操作で使っているキーを割り当てるときの片付け方
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Resolve {
+  // 相手の操作には、こちらが手放すキーを渡す
+  Swap,
+  // 相手の操作からそのキーを外す
+  Clear,
+}
+
+impl KeyBindings {
+  pub fn keys(&self, binding: Binding) -> &[KeyCode] {
+    match binding {
+      Binding::Left => &self.left,
+      Binding::Right => &self.right,
+      Binding::SoftDrop => &self.soft_drop,
+      Binding::RotateCw => &self.rotate_cw,
+      Binding::RotateCcw => &self.rotate_ccw,
+      Binding::Hold => &self.hold,
+    }
+  }
+
+  fn keys_mut(&mut self, binding: Binding) -> &mut Vec<KeyCode> {
+    match binding {
+      Binding::Left => &mut self.left,
+      Binding::Right => &mut self.right,
+      Binding::SoftDrop => &mut self.soft_drop,
+      Binding::RotateCw => &mut self.rotate_cw,
+      Binding::RotateCcw => &mut self.rotate_ccw,
+      Binding::Hold => &mut self.hold,
+    }
+  }
+
+  // binding 以外で key を使っている操作
+  pub fn owner(&self, key: KeyCode, except: Binding) -> Option<Binding> {
+    Binding::ALL
+      .iter()
+      .copied()
+      .find(|&b| b != except && self.keys(b).contains(&key))
+  }
+
+  // binding の 1 つ目のキーを key にする。別の操作で使っていれば何も変えずにその操作を返す
+  pub fn rebind(&mut self, binding: Binding, key: KeyCode) -> Result<(), Binding> {
+    if let Some(other) = self.owner(key, binding) {
+      return Err(other);
+    }
+    let keys = self.keys_mut(binding);
+    keys.retain(|&k| k != key);
+    match keys.first_mut() {
+      Some(first) => *first = key,
+      None => keys.push(key),
+    }
+    Ok(())
+  }
+
+  // 別の操作で使っているキーを、片付けてから割り当てる
+  pub fn rebind_resolving(&mut self, binding: Binding, key: KeyCode, resolve: Resolve) {
+    let released = self.keys(binding).first().copied();
+    if let Some(other) = self.owner(key, binding) {
+      let keys = self.keys_mut(other);
+      let i = keys.iter().position(|&k| k == key).unwrap();
+      match (resolve, released) {
+        (Resolve::Swap, Some(released)) if !keys.contains(&released) => keys[i] = released,
+        _ => {
+          keys.remove(i);
+        }
+      }
+    }
+    // 相手から外したので、ここでは重ならない
+    let _ = self.rebind(binding, key);
+  }
+
+  // 2 つの操作に割り当てられているキーと、その操作
+  pub fn conflicts(&self) -> Vec<(KeyCode, Binding, Binding)> {
+    let mut conflicts = vec![];
+    for (i, &a) in Binding::ALL.iter().enumerate() {
+      for &b in Binding::ALL[i + 1..].iter() {
+        for &key in self.keys(a).iter().filter(|k| self.keys(b).contains(k)) {
+          conflicts.push((key, a, b));
+        }
+      }
+    }
+    conflicts
+  }
+
+  // 保存して遊べる配置か。どの操作にもキーがあり、重なりがない
+  pub fn validate(&self) -> Result<(), String> {
+    if let Some(binding) = Binding::ALL.iter().find(|&&b| self.keys(b).is_empty()) {
+      return Err(format!("{} has no key", binding.name()));
+    }
+    match self.conflicts().first() {
+      Some((key, a, b)) => Err(format!(
+        "{:?} is bound to both {} and {}",
+        key,
+        a.name(),
+        b.name()
+      )),
+      None => Ok(()),
+    }
+  }
+}
+
+// 用意してあるキー配置。片手だけで回転とホールドまで届く
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Preset {
+  Standard,
+  LeftHand,
+  RightHand,
+  Numpad,
+}
+
+impl Preset {
+  pub const ALL: [Preset; 4] = [
+    Preset::Standard,
+    Preset::LeftHand,
+    Preset::RightHand,
+    Preset::Numpad,
+  ];
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      Preset::Standard => "standard",
+      Preset::LeftHand => "left hand",
+      Preset::RightHand => "right hand",
+      Preset::Numpad => "numpad",
+    }
+  }
+
+  pub fn bindings(&self) -> KeyBindings {
+    match self {
+      Preset::Standard => KeyBindings::default(),
+      // WASD に指を置いたまま、回転は上の段、ホールドは小指
+      Preset::LeftHand => KeyBindings {
+        left: vec![KeyCode::A],
+        right: vec![KeyCode::D],
+        soft_drop: vec![KeyCode::S],
+        rotate_cw: vec![KeyCode::W, KeyCode::E],
+        rotate_ccw: vec![KeyCode::Q],
+        hold: vec![KeyCode::LShift],
+      },
+      // JKL に指を置いたまま、回転は上の段、ホールドは小指
+      Preset::RightHand => KeyBindings {
+        left: vec![KeyCode::J],
+        right: vec![KeyCode::L],
+        soft_drop: vec![KeyCode::K],
+        rotate_cw: vec![KeyCode::I, KeyCode::O],
+        rotate_ccw: vec![KeyCode::U],
+        hold: vec![KeyCode::Semicolon],
+      },
+      // 4 5 6 に指を置き、回転は上の段、ホールドは親指の 0
+      Preset::Numpad => KeyBindings {
+        left: vec![KeyCode::Numpad4],
+        right: vec![KeyCode::Numpad6],
+        soft_drop: vec![KeyCode::Numpad5, KeyCode::Numpad2],
+        rotate_cw: vec![KeyCode::Numpad8, KeyCode::Numpad9],
+        rotate_ccw: vec![KeyCode::Numpad7],
+        hold: vec![KeyCode::Numpad0],
+      },
+    }
+  }
+
+  // 今の配置がどれかと同じなら、その配置。手で書き換えてあれば None
+  pub fn matching(bindings: &KeyBindings) -> Option<Preset> {
+    Preset::ALL
+      .iter()
+      .copied()
+      .find(|preset| preset.bindings() == *bindings)
+  }
+}
+
+pub fn any_pressed(input: &Input<KeyCode>, keys: &[KeyCode]) -> bool {
+  keys.iter().any(|&key| input.pressed(key))
+}
+
+pub fn any_just_pressed(input: &Input<KeyCode>, keys: &[KeyCode]) -> bool {
+  keys.iter().any(|&key| input.just_pressed(key))
+}
+
+// 左右を押しっぱなしにしたときの動き。ミリ秒で持ち、使うときに tick 数に直す
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Handling {
+  // 押してから連続で動き始めるまで (DAS)
+  pub das_ms: u32,
+  // 連続で動くときの間隔。0 なら毎フレーム (ARR)
+  pub arr_ms: u32,
+}
+
+impl Default for Handling {
+  fn default() -> Self {
+    Self {
+      das_ms: 170,
+      arr_ms: 50,
+    }
+  }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Controls {
+  pub bindings: KeyBindings,
+  pub handling: Handling,
+}
+
+impl Controls {
+  // 選んだ配置に差し替える。手で書き換えてあったものは次の配置から選び直す
+  pub fn cycle_preset(&mut self, forward: bool) {
+    let len = Preset::ALL.len();
+    let next = match Preset::matching(&self.bindings) {
+      Some(preset) => {
+        let i = Preset::ALL.iter().position(|&p| p == preset).unwrap();
+        if forward {
+          (i + 1) % len
+        } else {
+          (i + len - 1) % len
+        }
+      }
+      None => 0,
+    };
+    self.bindings = Preset::ALL[next].bindings();
+  }
+
+  // 遊べない配置は保存しない。次に読み込んだときに操作できなくなるので
+  pub fn store(&self) {
+    if let Err(e) = self.bindings.validate() {
+      warn!("not saving controls: {}", e);
+      return;
+    }
+    if let Err(e) = save::store(CONTROLS_FILE, self) {
+      error!("failed to save controls: {}", e);
+    }
+  }
+}
+
+// 左右の押しっぱなしを DAS/ARR に従って移動に変える。押している長さは tick で数える
+#[derive(Default)]
+pub struct AutoShift {
+  direction: Option<Direction>,
+  // tick に満たずに持ち越した秒数
+  pending: f32,
+  held: u32,
+  next: u32,
+}
+
+impl AutoShift {
+  // このフレームで1マス動かすかどうか
+  pub fn update(
+    &mut self,
+    direction: Option<Direction>,
+    dt: f32,
+    handling: &Handling,
+    rate: TickRate,
+  ) -> bool {
+    if direction != self.direction {
+      *self = AutoShift {
+        direction,
+        next: rate.ticks(handling.das_ms),
+        ..Default::default()
+      };
+      return direction.is_some();
+    }
+    if direction.is_none() {
+      return false;
+    }
+    self.pending += dt;
+    self.held += rate.take_ticks(&mut self.pending);
+    if self.held < self.next {
+      return false;
+    }
+    self.next += rate.ticks(handling.arr_ms);
+    true
+  }
+}
+
+pub fn tag_color(tag: &NameTag) -> Color {
+  TAG_PALETTE[tag.color as usize % TAG_PALETTE.len()].1
+}
+
+// 選択中のプロフィールの名札。名前を決めていなければプロフィールの名前を使う
+pub fn load_tag(profile: &str) -> NameTag {
+  let stored: NameTag = save::load(TAG_FILE);
+  let tag = NameTag::new(&stored.name, stored.color);
+  if tag.name.is_empty() {
+    NameTag::new(profile, tag.color)
+  } else {
+    tag
+  }
+}
+
+// プロフィールごとの通算記録
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LifetimeStats {
+  pub games: u32,
+  pub pieces: u32,
+  pub lines: u32,
+  // 秒
+  pub play_time: f64,
+  // モードごとの1ゲームの最多ライン数
+  pub best_lines: BTreeMap<String, u32>,
+}
+
+struct TitleText;
+
+// 新しいプロフィールの名前を入力中
+#[derive(Default)]
+struct Naming(Option<String>);
+
+// 選んでいるプロフィールの名札を書き換え中
+#[derive(Default)]
+struct Tagging(Option<NameTag>);
+
+pub struct ProfilePlugin;
+
+impl Plugin for ProfilePlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    // main で ProfileList::load 済みなので、選択中のプロフィールから読める
+    let profile = app
+      .world()
+      .get_resource::<ProfileList>()
+      .map_or(String::new(), |list| list.current_name().to_string());
+    app
+      .insert_resource(load_tag(&profile))
+      .insert_resource(Tagging::default())
+      .insert_resource(save::load::<Controls>(CONTROLS_FILE))
+      .insert_resource(save::load::<LifetimeStats>(STATS_FILE))
+      .insert_resource(Naming::default())
+      .add_system_set(SystemSet::on_enter(AppState::Title).with_system(setup_title.system()))
+      .add_system_set(SystemSet::on_update(AppState::Title).with_system(title_input.system()))
+      // リプレイを重ねている間は盤面が見えるように消しておく
+      .add_system_set(SystemSet::on_pause(AppState::Title).with_system(close_title.system()))
+      .add_system_set(SystemSet::on_resume(AppState::Title).with_system(setup_title.system()))
+      .add_system_set(
+        SystemSet::on_exit(AppState::Title)
+          .with_system(close_title.system())
+          .with_system(load_profile.system()),
+      )
+      .add_system_set(SystemSet::on_enter(AppState::Results).with_system(record_game.system()));
+  }
+}
+
+fn setup_title(mut commands: Commands, fonts: Res<Fonts>) {
+  let mut text = overlay_text(&fonts);
+  text.style.position = Rect {
+    top: Val::Px(200.0),
+    left: Val::Px(40.0),
+    ..Default::default()
+  };
+  // "PLAYER" と空行の後から選べる
+  commands
+    .spawn_bundle(text)
+    .insert(TitleText)
+    .insert(MenuList::new(2, 0));
+}
+
+// プレイヤーの後ろの項目を選んでいるときは action にその番号を持つ
+fn title_input(
+  mut keyboard_input: ResMut<Input<KeyCode>>,
+  menu: Res<MenuInput>,
+  mut action: Local<Option<usize>>,
+  mut char_events: EventReader<ReceivedCharacter>,
+  mut list: ResMut<ProfileList>,
+  mut naming: ResMut<Naming>,
+  mut tagging: ResMut<Tagging>,
+  mut state: ResMut<State<AppState>>,
+  mut query: Query<(&mut Text, &mut MenuList), With<TitleText>>,
+) {
+  let (pointed, hovered) = query
+    .iter_mut()
+    .next()
+    .map_or((None, None), |(_, menu_list)| {
+      (menu_list.pointed, menu_list.hovered)
+    });
+  if let Some(name) = naming.0.as_mut() {
+    for event in char_events.iter() {
+      if name.chars().count() < MAX_NAME_LEN && is_valid_name(&event.char.to_string()) {
+        name.push(event.char);
+      }
+    }
+    if keyboard_input.just_pressed(KeyCode::Back) {
+      name.pop();
+    } else if keyboard_input.just_pressed(KeyCode::Escape) {
+      naming.0 = None;
+    } else if keyboard_input.just_pressed(KeyCode::Return) && list.add(name) {
+      list.store();
+      naming.0 = None;
+    }
+  } else if let Some(tag) = tagging.0.as_mut() {
+    for event in char_events.iter() {
+      if tag.name.chars().count() < MAX_TAG_NAME && !event.char.is_control() {
+        tag.name.push(event.char);
+      }
+    }
+    let colors = TAG_COLORS as i32;
+    tag.color = (tag.color as i32 + menu.step()).rem_euclid(colors) as u8;
+    if keyboard_input.just_pressed(KeyCode::Back) {
+      tag.name.pop();
+    } else if keyboard_input.just_pressed(KeyCode::Escape) {
+      tagging.0 = None;
+    } else if keyboard_input.just_pressed(KeyCode::Return) {
+      // 空にしたらプロフィールの名前に戻す
+      save::set_profile(list.current_name());
+      if let Err(e) = save::store(TAG_FILE, &NameTag::new(&tag.name, tag.color)) {
+        error!("failed to save name tag: {}", e);
+      }
+      tagging.0 = None;
+    }
+  } else {
+    char_events.iter().for_each(drop);
+    let len = list.names.len();
+    let row = action.map_or(list.current, |i| len + i);
+    let row = menu.select(row, len + TITLE_ACTIONS.len(), pointed);
+    if row < len {
+      list.current = row;
+      *action = None;
+    } else {
+      *action = Some(row - len);
+    }
+    let chosen = menu.chosen(row, hovered);
+    let chosen_action = chosen.and_then(|i| i.checked_sub(len));
+    if keyboard_input.just_pressed(KeyCode::N) || chosen_action == Some(0) {
+      naming.0 = Some(String::new());
+    } else if keyboard_input.just_pressed(KeyCode::A) || chosen_action == Some(1) {
+      save::set_profile(list.current_name());
+      state.push(AppState::Achievements).unwrap();
+    } else if keyboard_input.just_pressed(KeyCode::V) || chosen_action == Some(2) {
+      state.push(AppState::Replay).unwrap();
+    } else if chosen_action == Some(3) {
+      save::set_profile(list.current_name());
+      tagging.0 = Some(load_tag(list.current_name()));
+    } else if keyboard_input.just_pressed(KeyCode::Delete) {
+      if list.remove_current() {
+        list.store();
+      }
+    } else if chosen.is_some_and(|i| i < len) {
+      list.store();
+      save::set_profile(list.current_name());
+      // 同じフレームで設定画面が Enter を拾わないように
+      keyboard_input.reset(KeyCode::Return);
+      state.set(AppState::Setup).unwrap();
+    }
+  }
+
+  let mut value = "PLAYER\n\n".to_string();
+  let selected = action.map_or(list.current, |i| list.names.len() + i);
+  let rows = list.names.iter().map(String::as_str).chain(TITLE_ACTIONS);
+  for (i, name) in rows.enumerate() {
+    let marker = if i == selected { ">" } else { " " };
+    value += &format!("{} {}\n", marker, name);
+  }
+  match (naming.0.as_ref(), tagging.0.as_ref()) {
+    (Some(name), _) => value += &format!("\nnew player: {}_\n[Enter] create  [Esc] cancel", name),
+    (None, Some(tag)) => {
+      value += &format!(
+        "\n{} name tag: {}_\ncolor {}  [Left/Right]\n[Enter] save  [Esc] cancel",
+        list.current_name(),
+        tag.name,
+        TAG_PALETTE[tag.color as usize].0
+      )
+    }
+    (None, None) => {
+      value += "\n[Up/Down] select  [Enter] play\n[N] new player  [Del] remove from list
+[A] achievements  [V] replay last game"
+    }
+  }
+  let rows = list.names.len() + TITLE_ACTIONS.len();
+  for (mut text, mut menu_list) in query.iter_mut() {
+    text.sections[0].value = value.clone();
+    if menu_list.rows != rows {
+      menu_list.rows = rows;
+    }
+  }
+}
+
+fn close_title(mut commands: Commands, query: Query<Entity, With<TitleText>>) {
+  for entity in query.iter() {
+    commands.entity(entity).despawn();
+  }
+}
+
+fn load_profile(mut controls: ResMut<Controls>, mut stats: ResMut<LifetimeStats>) {
+  *controls = save::load(CONTROLS_FILE);
+  if let Err(e) = controls.bindings.validate() {
+    warn!("{}: {}", CONTROLS_FILE, e);
+  }
+  *stats = save::load(STATS_FILE);
+  // 初めて使うプロフィールでも編集しやすいよう既定値を書き出しておく
+  controls.store();
+}
+
+fn record_game(
+  clock: Res<GameClock>,
+  mode: Res<GameMode>,
+  game: Res<Statistics>,
+  mut stats: ResMut<LifetimeStats>,
+) {
+  let lines: u32 = game.clears.iter().map(|&(_, lines)| lines).sum();
+  stats.games += 1;
+  stats.pieces += game.pieces.iter().sum::<u32>();
+  stats.lines += lines;
+  stats.play_time += clock.0 - game.start;
+  let best = stats.best_lines.entry(format!("{:?}", *mode)).or_default();
+  *best = (*best).max(lines);
+  if let Err(e) = save::store(STATS_FILE, &*stats) {
+    error!("failed to save stats: {}", e);
+  }
+}
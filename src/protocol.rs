@@ -0,0 +1,488 @@
+use serde::{Deserialize, Serialize};
+
+use crate::sim::Action;
+use crate::wire::{write_varint, Reader};
+use crate::ARENA_HEIGHT;
+
+// ホストと対戦サーバーが待ち受けるポート
+pub const DEFAULT_PORT: u16 = 47474;
+
+// 互換性の無い変更をしたら上げる
+pub const PROTOCOL_VERSION: u16 = 1;
+// これより古い相手とは対戦できない
+pub const MIN_PROTOCOL_VERSION: u16 = 1;
+
+// 任意の機能。両方が対応しているものだけ使う
+// 操作中のミノも State に載せる (無ければ積んだ盤面だけ)
+pub const FEATURE_ACTIVE_PIECE: u32 = 1 << 0;
+// 切れた対戦に Session の番号でつなぎ直す
+pub const FEATURE_RESUME: u32 = 1 << 1;
+// 対戦サーバーで遊ぶ。盤面はサーバーが動かし、手元からは操作だけ送る
+pub const FEATURE_SERVER: u32 = 1 << 2;
+// 定型のチャットとエモート
+pub const FEATURE_CHAT: u32 = 1 << 3;
+// Ping を送り合って往復時間を測る
+pub const FEATURE_PING: u32 = 1 << 4;
+// ホストが決めた、人ごとに違う開始条件
+pub const FEATURE_HANDICAP: u32 = 1 << 5;
+// 名前と色の名札を見せ合う
+pub const FEATURE_PROFILE: u32 = 1 << 6;
+pub const FEATURES: u32 = FEATURE_ACTIVE_PIECE
+  | FEATURE_RESUME
+  | FEATURE_SERVER
+  | FEATURE_CHAT
+  | FEATURE_PING
+  | FEATURE_HANDICAP
+  | FEATURE_PROFILE;
+
+// Chat で番号を送る定型文とエモート。並びが番号になるので、足すときは後ろに足す
+pub const QUICK_CHAT: [&str; 8] = [
+  "good luck!",
+  "gg",
+  "nice!",
+  "oops",
+  "thanks",
+  "\u{263a}",
+  "\u{2639}",
+  "\u{2665}",
+];
+
+// 他のプログラムからの接続と見分けるため Hello の先頭に付ける
+const MAGIC: &[u8; 4] = b"TTRS";
+// これより長いメッセージは壊れているとみなす
+const MAX_MESSAGE: u64 = 1 << 16;
+// Reject の理由の長さの上限 (バイト)
+const MAX_REASON: usize = 256;
+// 1 通で送れるせり上がりの段数。1 回の消去の攻撃はこれより少ない
+const MAX_GARBAGE: u64 = ARENA_HEIGHT as u64;
+
+const HELLO: u8 = 0;
+const REJECT: u8 = 1;
+const START: u8 = 2;
+const STATE: u8 = 3;
+const GARBAGE: u8 = 4;
+const SESSION: u8 = 5;
+const RESUME: u8 = 6;
+const WELCOME: u8 = 7;
+const INPUT: u8 = 8;
+const SEAT: u8 = 9;
+const CHAT: u8 = 10;
+const PING: u8 = 11;
+const PONG: u8 = 12;
+const HANDICAP: u8 = 13;
+const PROFILE: u8 = 14;
+
+// Input で送る操作。並びが番号になるので、足すときは後ろに足す
+const ACTIONS: [Action; 7] = [
+  Action::Left,
+  Action::Right,
+  Action::SoftDrop,
+  Action::HardDrop,
+  Action::RotateCw,
+  Action::RotateCcw,
+  Action::Hold,
+];
+
+// 接続して最初に互いに送る。どの版でも読めるよう、この形は変えない
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Hello {
+  pub version: u16,
+  pub min_version: u16,
+  pub features: u32,
+}
+
+impl Hello {
+  pub fn current() -> Self {
+    Hello {
+      version: PROTOCOL_VERSION,
+      min_version: MIN_PROTOCOL_VERSION,
+      features: FEATURES,
+    }
+  }
+}
+
+// 対戦を始めるときの条件。せり上がった状態から始めたり、見える次のミノを減らしたりする
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Handicap {
+  pub garbage: u8,
+  pub previews: u8,
+}
+
+pub const MAX_START_GARBAGE: u8 = 8;
+pub const MAX_PREVIEWS: u8 = 5;
+
+// 盤面の上に出す名前と色。色は手元の色の一覧の番号で、知らない番号は最初の色にする
+#[derive(Clone, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NameTag {
+  pub name: String,
+  pub color: u8,
+}
+
+pub const MAX_TAG_NAME: usize = 16;
+pub const TAG_COLORS: u8 = 8;
+
+impl NameTag {
+  // 届いたものも保存してあったものも、画面を崩す文字と長すぎる分は落とす
+  pub fn new(name: &str, color: u8) -> Self {
+    NameTag {
+      name: name
+        .trim()
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_TAG_NAME)
+        .collect(),
+      color: if color < TAG_COLORS { color } else { 0 },
+    }
+  }
+}
+
+impl Default for Handicap {
+  fn default() -> Self {
+    Handicap {
+      garbage: 0,
+      previews: MAX_PREVIEWS,
+    }
+  }
+}
+
+// 相手の盤面。受け取った側はこれをそのまま描く
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct Snapshot {
+  pub pieces: u32,
+  pub lines: u32,
+  pub tetrises: u32,
+  pub sent: u32,
+  pub game_over: bool,
+  // 下の行から順のビットマスク
+  pub rows: Vec<u16>,
+  // 操作中のミノの番号とマス
+  pub active: Option<(u32, Vec<(i32, i32)>)>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Message {
+  Hello(Hello),
+  // 対戦できない理由を伝えてから切る
+  Reject { reason: String },
+  // condition は勝敗の決め方の番号
+  Start { condition: u8 },
+  State(Snapshot),
+  Garbage { lines: u32 },
+  // ホストが決めた対戦の番号。つなぎ直すときに Resume で送り返す
+  Session { id: u64 },
+  Resume { id: u64 },
+  // サーバーが決めた自分の席と、対戦する人数
+  Welcome { seat: u8, players: u8 },
+  Input { action: Action },
+  // サーバーが動かしている、席 seat の盤面
+  Seat { seat: u8, snapshot: Snapshot },
+  // QUICK_CHAT の番号。seat は対戦サーバーが付ける送り主の席で、1 対 1 では使わない
+  Chat { seat: u8, phrase: u8 },
+  // 受け取ったら同じ番号の Pong をすぐ返す
+  Ping { id: u32 },
+  Pong { id: u32 },
+  // ホストが決めた開始条件。次の Start から使う
+  Handicap { host: Handicap, guest: Handicap },
+  // 自分の名札。seat は対戦サーバーが付ける送り主の席で、1 対 1 では使わない
+  Profile { seat: u8, tag: NameTag },
+}
+
+// 話し合って決まった版と、両方が対応している機能
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Agreement {
+  pub version: u16,
+  pub features: u32,
+}
+
+impl Agreement {
+  pub fn has(&self, feature: u32) -> bool {
+    self.features & feature != 0
+  }
+}
+
+// 両方が話せる一番新しい版に合わせる。合わなければ相手に見せる理由を返す
+pub fn negotiate(local: &Hello, remote: &Hello) -> Result<Agreement, String> {
+  let version = local.version.min(remote.version);
+  if version < local.min_version {
+    return Err(format!(
+      "opponent speaks protocol {} but this game needs {} or newer; update the opponent's game",
+      remote.version, local.min_version
+    ));
+  }
+  if version < remote.min_version {
+    return Err(format!(
+      "opponent needs protocol {} or newer but this game speaks {}; update this game",
+      remote.min_version, local.version
+    ));
+  }
+  Ok(Agreement {
+    version,
+    features: local.features & remote.features,
+  })
+}
+
+pub fn encode(message: &Message) -> Vec<u8> {
+  let mut out = vec![];
+  match message {
+    Message::Hello(hello) => {
+      out.push(HELLO);
+      out.extend_from_slice(MAGIC);
+      out.extend_from_slice(&hello.version.to_le_bytes());
+      out.extend_from_slice(&hello.min_version.to_le_bytes());
+      out.extend_from_slice(&hello.features.to_le_bytes());
+    }
+    Message::Reject { reason } => {
+      out.push(REJECT);
+      write_varint(&mut out, reason.len() as u64);
+      out.extend_from_slice(reason.as_bytes());
+    }
+    Message::Start { condition } => out.extend_from_slice(&[START, *condition]),
+    Message::State(snapshot) => {
+      out.push(STATE);
+      write_snapshot(&mut out, snapshot);
+    }
+    Message::Garbage { lines } => {
+      out.push(GARBAGE);
+      write_varint(&mut out, *lines as u64);
+    }
+    Message::Session { id } => {
+      out.push(SESSION);
+      write_varint(&mut out, *id);
+    }
+    Message::Resume { id } => {
+      out.push(RESUME);
+      write_varint(&mut out, *id);
+    }
+    Message::Welcome { seat, players } => out.extend_from_slice(&[WELCOME, *seat, *players]),
+    Message::Input { action } => {
+      let code = ACTIONS.iter().position(|a| a == action).unwrap_or(0);
+      out.extend_from_slice(&[INPUT, code as u8]);
+    }
+    Message::Seat { seat, snapshot } => {
+      out.extend_from_slice(&[SEAT, *seat]);
+      write_snapshot(&mut out, snapshot);
+    }
+    Message::Chat { seat, phrase } => out.extend_from_slice(&[CHAT, *seat, *phrase]),
+    Message::Ping { id } => {
+      out.push(PING);
+      write_varint(&mut out, *id as u64);
+    }
+    Message::Pong { id } => {
+      out.push(PONG);
+      write_varint(&mut out, *id as u64);
+    }
+    Message::Handicap { host, guest } => out.extend_from_slice(&[
+      HANDICAP,
+      host.garbage,
+      host.previews,
+      guest.garbage,
+      guest.previews,
+    ]),
+    Message::Profile { seat, tag } => {
+      out.extend_from_slice(&[PROFILE, *seat, tag.color]);
+      write_varint(&mut out, tag.name.len() as u64);
+      out.extend_from_slice(tag.name.as_bytes());
+    }
+  }
+  out
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Message, String> {
+  let mut reader = Reader::new(bytes);
+  let message = match reader.byte()? {
+    HELLO => {
+      if reader.take(MAGIC.len())? != MAGIC {
+        return Err("not a tetris connection".to_string());
+      }
+      Message::Hello(Hello {
+        version: u16::from_le_bytes([reader.byte()?, reader.byte()?]),
+        min_version: u16::from_le_bytes([reader.byte()?, reader.byte()?]),
+        features: u32::from_le_bytes([
+          reader.byte()?,
+          reader.byte()?,
+          reader.byte()?,
+          reader.byte()?,
+        ]),
+      })
+    }
+    REJECT => {
+      let reason = read_string(&mut reader, MAX_REASON)?;
+      Message::Reject { reason }
+    }
+    START => Message::Start {
+      condition: reader.byte()?,
+    },
+    STATE => Message::State(read_snapshot(&mut reader)?),
+    GARBAGE => {
+      let lines = reader.varint()?;
+      if lines > MAX_GARBAGE {
+        return Err(format!("too much garbage in one message: {}", lines));
+      }
+      Message::Garbage {
+        lines: lines as u32,
+      }
+    }
+    SESSION => Message::Session {
+      id: reader.varint()?,
+    },
+    RESUME => Message::Resume {
+      id: reader.varint()?,
+    },
+    WELCOME => Message::Welcome {
+      seat: reader.byte()?,
+      players: reader.byte()?,
+    },
+    INPUT => {
+      let code = reader.byte()?;
+      match ACTIONS.get(code as usize) {
+        Some(&action) => Message::Input { action },
+        None => return Err(format!("unknown action {}", code)),
+      }
+    }
+    SEAT => Message::Seat {
+      seat: reader.byte()?,
+      snapshot: read_snapshot(&mut reader)?,
+    },
+    CHAT => Message::Chat {
+      seat: reader.byte()?,
+      phrase: reader.byte()?,
+    },
+    PING => Message::Ping {
+      id: reader.varint()? as u32,
+    },
+    PONG => Message::Pong {
+      id: reader.varint()? as u32,
+    },
+    HANDICAP => {
+      let mut handicap = || -> Result<Handicap, String> {
+        let garbage = reader.byte()?.min(MAX_START_GARBAGE);
+        let previews = reader.byte()?.min(MAX_PREVIEWS);
+        Ok(Handicap { garbage, previews })
+      };
+      Message::Handicap {
+        host: handicap()?,
+        guest: handicap()?,
+      }
+    }
+    PROFILE => {
+      let seat = reader.byte()?;
+      let color = reader.byte()?;
+      // 1文字は UTF-8 で最大4バイト
+      let name = read_string(&mut reader, MAX_TAG_NAME * 4)?;
+      Message::Profile {
+        seat,
+        tag: NameTag::new(&name, color),
+      }
+    }
+    tag => return Err(format!("unknown message {}", tag)),
+  };
+  Ok(message)
+}
+
+// 長さ付きの文字列。上限を超える長さは読む前に断る
+fn read_string(reader: &mut Reader, max: usize) -> Result<String, String> {
+  let len = reader.varint()?;
+  if len > max as u64 {
+    return Err("string is too long".to_string());
+  }
+  String::from_utf8(reader.take(len as usize)?.to_vec()).map_err(|e| e.to_string())
+}
+
+fn write_snapshot(out: &mut Vec<u8>, snapshot: &Snapshot) {
+  for &n in [
+    snapshot.pieces,
+    snapshot.lines,
+    snapshot.tetrises,
+    snapshot.sent,
+  ]
+  .iter()
+  {
+    write_varint(out, n as u64);
+  }
+  out.push(snapshot.game_over as u8);
+  write_varint(out, snapshot.rows.len() as u64);
+  for row in snapshot.rows.iter() {
+    out.extend_from_slice(&row.to_le_bytes());
+  }
+  match &snapshot.active {
+    Some((block_idx, cells)) => {
+      out.extend_from_slice(&[1, *block_idx as u8, cells.len() as u8]);
+      for &(x, y) in cells.iter() {
+        out.extend_from_slice(&[x as u8, y as u8]);
+      }
+    }
+    None => out.push(0),
+  }
+}
+
+fn read_snapshot(reader: &mut Reader) -> Result<Snapshot, String> {
+  let pieces = reader.varint()? as u32;
+  let lines = reader.varint()? as u32;
+  let tetrises = reader.varint()? as u32;
+  let sent = reader.varint()? as u32;
+  let game_over = reader.byte()? != 0;
+  let count = reader.varint()?;
+  let rows = (0..count)
+    .map(|_| Ok(u16::from_le_bytes([reader.byte()?, reader.byte()?])))
+    .collect::<Result<Vec<_>, String>>()?;
+  let active = match reader.byte()? {
+    0 => None,
+    _ => {
+      let block_idx = reader.byte()? as u32;
+      let n = reader.byte()?;
+      let cells = (0..n)
+        .map(|_| Ok((reader.signed()?, reader.signed()?)))
+        .collect::<Result<Vec<_>, String>>()?;
+      Some((block_idx, cells))
+    }
+  };
+  Ok(Snapshot {
+    pieces,
+    lines,
+    tetrises,
+    sent,
+    game_over,
+    rows,
+    active,
+  })
+}
+
+// 長さを先に付けて送る
+pub fn frame(message: &Message) -> Vec<u8> {
+  let body = encode(message);
+  let mut out = vec![];
+  write_varint(&mut out, body.len() as u64);
+  out.extend(body);
+  out
+}
+
+// 受け取ったバイト列の先頭から1つ取り出す。まだ届ききっていなければ None
+pub fn unframe(buffer: &mut Vec<u8>) -> Result<Option<Message>, String> {
+  let mut len = 0;
+  let mut header = 0;
+  loop {
+    let byte = match buffer.get(header) {
+      Some(&byte) => byte,
+      None => return Ok(None),
+    };
+    len |= ((byte & 0x7f) as u64) << (7 * header);
+    header += 1;
+    if byte & 0x80 == 0 {
+      break;
+    }
+    if header >= 4 {
+      return Err("bad message length".to_string());
+    }
+  }
+  if len > MAX_MESSAGE {
+    return Err(format!("message too long ({} bytes)", len));
+  }
+  let end = header + len as usize;
+  if buffer.len() < end {
+    return Ok(None);
+  }
+  let message = decode(&buffer[header..end]);
+  buffer.drain(..end);
+  message.map(Some)
+}
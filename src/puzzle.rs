@@ -0,0 +1,367 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  block_idx_from_name, block_name, overlay_text, save, spawn_stacked_block, ActiveBlock, AppState,
+  BlockStacked, Fonts, GameReset, Label, LinesCleared, Materials, PiecePhase, PieceQueue, Position,
+  StackedBlock, ARENA_HEIGHT, ARENA_WIDTH,
+};
+
+pub const PUZZLE_DIR: &str = "assets/puzzles";
+const SAVE_FILE: &str = "puzzles.ron";
+
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum PuzzleGoal {
+  // 盤面をすべて消す
+  #[default]
+  ClearBoard,
+  // 指定ライン数を消す
+  ClearLines(u32),
+}
+
+impl PuzzleGoal {
+  pub fn describe(&self) -> String {
+    match *self {
+      PuzzleGoal::ClearBoard => "clear the board".to_string(),
+      PuzzleGoal::ClearLines(n) => format!("clear {} lines", n),
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+struct PuzzleFile {
+  name: String,
+  // 上の行から順に並べる。'.' が空きマス、それ以外は埋まっているマス
+  rows: Vec<String>,
+  // 使えるミノを順番に (例: "TOI")
+  queue: String,
+  #[serde(default)]
+  goal: PuzzleGoal,
+}
+
+pub struct Puzzle {
+  pub name: String,
+  pub cells: Vec<Position>,
+  pub queue: Vec<u32>,
+  pub goal: PuzzleGoal,
+}
+
+impl Puzzle {
+  pub fn parse(src: &str) -> Result<Puzzle, String> {
+    let file: PuzzleFile = ron::de::from_str(src).map_err(|e| e.to_string())?;
+    if file.rows.len() > ARENA_HEIGHT as usize {
+      return Err(format!("too many rows: {}", file.rows.len()));
+    }
+
+    let mut cells = vec![];
+    for (y, row) in file.rows.iter().rev().enumerate() {
+      if row.chars().count() != ARENA_WIDTH as usize {
+        return Err(format!("row {:?} must be {} cells wide", row, ARENA_WIDTH));
+      }
+      for (x, c) in row.chars().enumerate() {
+        if c != '.' {
+          cells.push(Position {
+            x: x as i32,
+            y: y as i32,
+          });
+        }
+      }
+    }
+
+    let queue = file
+      .queue
+      .chars()
+      .filter(|c| !c.is_whitespace())
+      .map(|c| block_idx_from_name(c).ok_or(format!("unknown piece: {}", c)))
+      .collect::<Result<Vec<_>, _>>()?;
+    if queue.is_empty() {
+      return Err("queue is empty".to_string());
+    }
+
+    Ok(Puzzle {
+      name: file.name,
+      cells,
+      queue,
+      goal: file.goal,
+    })
+  }
+
+  pub fn to_ron(&self) -> String {
+    let height = self.cells.iter().map(|p| p.y + 1).max().unwrap_or(0);
+    let rows = (0..height)
+      .rev()
+      .map(|y| {
+        (0..ARENA_WIDTH as i32)
+          .map(|x| {
+            if self.cells.contains(&Position { x, y }) {
+              'X'
+            } else {
+              '.'
+            }
+          })
+          .collect()
+      })
+      .collect();
+    let file = PuzzleFile {
+      name: self.name.clone(),
+      rows,
+      queue: self.queue.iter().map(|&idx| block_name(idx)).collect(),
+      goal: self.goal,
+    };
+    // PuzzleFile は String と enum だけなので失敗しない
+    ron::ser::to_string_pretty(&file, ron::ser::PrettyConfig::new()).unwrap()
+  }
+}
+
+fn load_puzzles(dir: &Path) -> Vec<Puzzle> {
+  let mut paths: Vec<_> = match fs::read_dir(dir) {
+    Ok(entries) => entries
+      .filter_map(|entry| entry.ok())
+      .map(|entry| entry.path())
+      .filter(|path| path.extension().and_then(|ext| ext.to_str()) == Some("ron"))
+      .collect(),
+    Err(e) => {
+      warn!("failed to read {}: {}", dir.display(), e);
+      vec![]
+    }
+  };
+  paths.sort();
+
+  paths
+    .iter()
+    .filter_map(|path| {
+      let puzzle = fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|s| Puzzle::parse(&s));
+      match puzzle {
+        Ok(puzzle) => Some(puzzle),
+        Err(e) => {
+          warn!("skipping puzzle {}: {}", path.display(), e);
+          None
+        }
+      }
+    })
+    .collect()
+}
+
+// 少ない挑戦回数で解くほど星が増える
+fn stars_for_attempts(attempts: u32) -> u8 {
+  match attempts {
+    0 | 1 => 3,
+    2 | 3 => 2,
+    _ => 1,
+  }
+}
+
+fn star_string(stars: u8) -> String {
+  (0..3).map(|i| if i < stars { '★' } else { '☆' }).collect()
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum PuzzleStatus {
+  Playing,
+  Solved,
+  Failed,
+}
+
+struct PuzzleState {
+  puzzles: Vec<Puzzle>,
+  current: usize,
+  status: PuzzleStatus,
+  attempts: u32,
+  stacked: u32,
+  lines: u32,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct PuzzleSave {
+  stars: BTreeMap<String, u8>,
+}
+
+struct PuzzleText;
+
+pub struct PuzzlePlugin;
+
+impl Plugin for PuzzlePlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(PuzzleState {
+        puzzles: load_puzzles(Path::new(PUZZLE_DIR)),
+        current: 0,
+        status: PuzzleStatus::Playing,
+        attempts: 1,
+        stacked: 0,
+        lines: 0,
+      })
+      .insert_resource(save::load::<PuzzleSave>(SAVE_FILE))
+      .add_startup_system_to_stage(StartupStage::PostStartup, setup_puzzle.system())
+      .add_system_set(SystemSet::on_exit(AppState::Title).with_system(reload_stars.system()))
+      .add_system_set(SystemSet::on_update(AppState::Playing).with_system(puzzle_input.system()))
+      // reset_game が盤面を片付けた後に置く
+      .add_system_to_stage(
+        CoreStage::PreUpdate,
+        puzzle_load.system().after(Label::Reset),
+      )
+      .add_system(puzzle_check.system())
+      .add_system(puzzle_ui.system());
+  }
+}
+
+fn load_board(
+  commands: &mut Commands,
+  materials: &Materials,
+  piece_queue: &mut PieceQueue,
+  puzzle: &Puzzle,
+) {
+  for cell in puzzle.cells.iter() {
+    spawn_stacked_block(commands, materials, cell.clone());
+  }
+  piece_queue.queue = puzzle.queue.iter().copied().collect();
+  piece_queue.fixed = true;
+}
+
+fn setup_puzzle(
+  mut commands: Commands,
+  fonts: Res<Fonts>,
+  materials: Res<Materials>,
+  mut piece_queue: ResMut<PieceQueue>,
+  state: Res<PuzzleState>,
+) {
+  commands
+    .spawn_bundle(overlay_text(&fonts))
+    .insert(PuzzleText);
+  piece_queue.fixed = true;
+  if let Some(puzzle) = state.puzzles.get(state.current) {
+    load_board(&mut commands, &materials, &mut piece_queue, puzzle);
+  }
+}
+
+fn reload_stars(mut data: ResMut<PuzzleSave>) {
+  *data = save::load(SAVE_FILE);
+}
+
+fn puzzle_input(
+  keyboard_input: Res<Input<KeyCode>>,
+  mut state: ResMut<PuzzleState>,
+  mut reset_events: EventWriter<GameReset>,
+) {
+  if state.puzzles.is_empty() {
+    return;
+  }
+  if keyboard_input.just_pressed(KeyCode::R) {
+    state.attempts += 1;
+  } else if keyboard_input.just_pressed(KeyCode::N) {
+    state.current = (state.current + 1) % state.puzzles.len();
+    state.attempts = 1;
+  } else if keyboard_input.just_pressed(KeyCode::P) {
+    state.current = (state.current + state.puzzles.len() - 1) % state.puzzles.len();
+    state.attempts = 1;
+  } else {
+    return;
+  }
+  reset_events.send(GameReset);
+}
+
+fn puzzle_load(
+  mut commands: Commands,
+  materials: Res<Materials>,
+  mut piece_queue: ResMut<PieceQueue>,
+  mut state: ResMut<PuzzleState>,
+  mut reset_events: EventReader<GameReset>,
+) {
+  if reset_events.iter().count() == 0 {
+    return;
+  }
+  state.status = PuzzleStatus::Playing;
+  state.stacked = 0;
+  state.lines = 0;
+  if let Some(puzzle) = state.puzzles.get(state.current) {
+    load_board(&mut commands, &materials, &mut piece_queue, puzzle);
+  }
+}
+
+fn puzzle_check(
+  active_block: Res<ActiveBlock>,
+  mut piece_queue: ResMut<PieceQueue>,
+  mut state: ResMut<PuzzleState>,
+  mut data: ResMut<PuzzleSave>,
+  mut stacked_events: EventReader<BlockStacked>,
+  mut cleared_events: EventReader<LinesCleared>,
+  stacked_block_query: Query<&StackedBlock>,
+) {
+  state.stacked += stacked_events.iter().count() as u32;
+  state.lines += cleared_events.iter().map(|e| e.0).sum::<u32>();
+  if state.status != PuzzleStatus::Playing {
+    return;
+  }
+  let (name, goal) = match state.puzzles.get(state.current) {
+    Some(puzzle) => (puzzle.name.clone(), puzzle.goal),
+    None => return,
+  };
+
+  let is_solved = match goal {
+    PuzzleGoal::ClearBoard => state.stacked > 0 && stacked_block_query.iter().next().is_none(),
+    PuzzleGoal::ClearLines(n) => state.lines >= n,
+  };
+  if is_solved {
+    state.status = PuzzleStatus::Solved;
+    piece_queue.queue.clear();
+
+    let stars = stars_for_attempts(state.attempts);
+    let best = data.stars.entry(name).or_insert(0);
+    if stars > *best {
+      *best = stars;
+      if let Err(e) = save::store(SAVE_FILE, &*data) {
+        error!("failed to save puzzles: {}", e);
+      }
+    }
+  } else if piece_queue.queue.is_empty() && active_block.phase == PiecePhase::Spawning {
+    // ミノを使い切ったのに盤面が残っている
+    state.status = PuzzleStatus::Failed;
+  }
+}
+
+fn puzzle_ui(
+  state: Res<PuzzleState>,
+  data: Res<PuzzleSave>,
+  piece_queue: Res<PieceQueue>,
+  mut query: Query<&mut Text, With<PuzzleText>>,
+) {
+  let value = match state.puzzles.get(state.current) {
+    Some(puzzle) => {
+      let best = data.stars.get(&puzzle.name).copied().unwrap_or(0);
+      let queue: String = piece_queue
+        .queue
+        .iter()
+        .map(|&idx| block_name(idx))
+        .collect();
+      let status = match state.status {
+        PuzzleStatus::Playing => format!("next: {}", queue),
+        PuzzleStatus::Solved => format!(
+          "SOLVED! {}",
+          star_string(stars_for_attempts(state.attempts))
+        ),
+        PuzzleStatus::Failed => "FAILED".to_string(),
+      };
+      format!(
+        "PUZZLE {}/{} {}\n{}: {}\ntry {}  {}\n[R] retry  [N] next  [P] prev",
+        state.current + 1,
+        state.puzzles.len(),
+        star_string(best),
+        puzzle.name,
+        puzzle.goal.describe(),
+        state.attempts,
+        status
+      )
+    }
+    None => format!("no puzzles found in {}", PUZZLE_DIR),
+  };
+
+  for mut text in query.iter_mut() {
+    text.sections[0].value = value.clone();
+  }
+}
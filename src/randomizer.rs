@@ -0,0 +1,230 @@
+use std::collections::VecDeque;
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+const PIECES: u32 = 7;
+// block_idx
+const O: u32 = 1;
+const Z: u32 = 2;
+const S: u32 = 3;
+const I: u32 = 7;
+
+// 次のミノ (block_idx) を決める
+pub trait Randomizer: Send + Sync {
+  fn next(&mut self) -> u32;
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum RandomizerKind {
+  // 7種を1袋にして順に出す
+  #[default]
+  Bag,
+  // 14個 (各2つ) で1袋
+  DoubleBag,
+  // 毎回完全にランダム
+  Memoryless,
+  // 直近4つと同じミノは4回まで引き直す
+  TgmHistory,
+}
+
+impl RandomizerKind {
+  pub const ALL: [RandomizerKind; 4] = [
+    RandomizerKind::Bag,
+    RandomizerKind::DoubleBag,
+    RandomizerKind::Memoryless,
+    RandomizerKind::TgmHistory,
+  ];
+
+  pub fn build(self, seed: u64) -> Box<dyn Randomizer> {
+    let rng = StdRng::seed_from_u64(seed);
+    match self {
+      RandomizerKind::Bag => Box::new(Bag::new(rng, 1)),
+      RandomizerKind::DoubleBag => Box::new(Bag::new(rng, 2)),
+      RandomizerKind::Memoryless => Box::new(Memoryless { rng }),
+      RandomizerKind::TgmHistory => Box::new(TgmHistory::new(rng)),
+    }
+  }
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      RandomizerKind::Bag => "7-bag",
+      RandomizerKind::DoubleBag => "14-bag",
+      RandomizerKind::Memoryless => "memoryless",
+      RandomizerKind::TgmHistory => "TGM history",
+    }
+  }
+}
+
+// 棒が出ないまま cap - 1 個続いたら、次は必ず棒にする。どの cap 個の並びにも棒が入る。
+// カジュアル向けのルールで、差し込んだ分は中のランダマイザーから引かない
+pub struct DroughtCap {
+  inner: Box<dyn Randomizer>,
+  cap: u32,
+  since: u32,
+}
+
+impl Randomizer for DroughtCap {
+  fn next(&mut self) -> u32 {
+    let idx = if self.since + 1 >= self.cap {
+      I
+    } else {
+      self.inner.next()
+    };
+    self.since = if idx == I { 0 } else { self.since + 1 };
+    idx
+  }
+}
+
+// cap が None か 0 ならそのまま返す
+pub fn with_drought_cap(randomizer: Box<dyn Randomizer>, cap: Option<u32>) -> Box<dyn Randomizer> {
+  match cap {
+    Some(cap) if cap > 0 => Box::new(DroughtCap {
+      inner: randomizer,
+      cap,
+      since: 0,
+    }),
+    _ => randomizer,
+  }
+}
+
+pub struct Bag {
+  rng: StdRng,
+  copies: u32,
+  bag: Vec<u32>,
+}
+
+impl Bag {
+  fn new(rng: StdRng, copies: u32) -> Self {
+    Bag {
+      rng,
+      copies,
+      bag: vec![],
+    }
+  }
+}
+
+impl Randomizer for Bag {
+  fn next(&mut self) -> u32 {
+    if self.bag.is_empty() {
+      self.bag = (0..self.copies).flat_map(|_| 1..=PIECES).collect();
+      self.bag.shuffle(&mut self.rng);
+    }
+    self.bag.pop().unwrap()
+  }
+}
+
+pub struct Memoryless {
+  rng: StdRng,
+}
+
+impl Randomizer for Memoryless {
+  fn next(&mut self) -> u32 {
+    self.rng.gen_range(1..=PIECES)
+  }
+}
+
+pub struct TgmHistory {
+  rng: StdRng,
+  history: VecDeque<u32>,
+  is_first: bool,
+}
+
+impl TgmHistory {
+  const ROLLS: u32 = 4;
+
+  fn new(rng: StdRng) -> Self {
+    TgmHistory {
+      rng,
+      history: vec![Z, Z, Z, Z].into_iter().collect(),
+      is_first: true,
+    }
+  }
+}
+
+impl Randomizer for TgmHistory {
+  fn next(&mut self) -> u32 {
+    let idx = if self.is_first {
+      // 最初のミノは S/Z/O にしない
+      self.is_first = false;
+      loop {
+        let idx = self.rng.gen_range(1..=PIECES);
+        if idx != O && idx != Z && idx != S {
+          break idx;
+        }
+      }
+    } else {
+      let mut idx = 0;
+      for _ in 0..Self::ROLLS {
+        idx = self.rng.gen_range(1..=PIECES);
+        if !self.history.contains(&idx) {
+          break;
+        }
+      }
+      idx
+    };
+    self.history.pop_front();
+    self.history.push_back(idx);
+    idx
+  }
+}
+
+// 配ったミノの数と、ミノごとに出なかった間隔。ランダマイザーの偏りを確かめる用
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct DealStats {
+  // block_idx - 1 の位置
+  pub counts: [u32; PIECES as usize],
+  // 最後に出てから配ったミノの数
+  pub since: [u32; PIECES as usize],
+  // いちばん長く出なかった間隔。今続いている分も含む
+  pub droughts: [u32; PIECES as usize],
+}
+
+impl DealStats {
+  pub fn record(&mut self, block_idx: u32) {
+    let dealt = (block_idx - 1) as usize;
+    for (i, since) in self.since.iter_mut().enumerate() {
+      *since = if i == dealt { 0 } else { *since + 1 };
+      self.droughts[i] = self.droughts[i].max(*since);
+    }
+    self.counts[dealt] += 1;
+  }
+
+  pub fn total(&self) -> u32 {
+    self.counts.iter().sum()
+  }
+}
+
+// ランダマイザーから引いたミノの並び。覗いた分だけ引いてためておくので、
+// 袋の残りより先を覗いても、後で pop したときに同じ順で出てくる
+pub struct PieceQueue {
+  queue: VecDeque<u32>,
+  randomizer: Box<dyn Randomizer>,
+}
+
+impl PieceQueue {
+  pub fn new(randomizer: Box<dyn Randomizer>) -> Self {
+    PieceQueue {
+      queue: VecDeque::new(),
+      randomizer,
+    }
+  }
+
+  pub fn pop(&mut self) -> u32 {
+    self
+      .queue
+      .pop_front()
+      .unwrap_or_else(|| self.randomizer.next())
+  }
+
+  // 次に出てくるミノを n 個。足りなければ引き足す
+  pub fn peek(&mut self, n: usize) -> Vec<u32> {
+    while self.queue.len() < n {
+      let next = self.randomizer.next();
+      self.queue.push_back(next);
+    }
+    self.queue.iter().take(n).copied().collect()
+  }
+}
@@ -0,0 +1,651 @@
+use bevy::prelude::*;
+
+use crate::eventlog::{GameLog, LogEvent, Note};
+use crate::menu::MenuInput;
+use crate::rotation::{rotated_cells, state_from_name};
+use crate::share;
+use crate::sim::{Action, GameState, Piece};
+use crate::{block_idx_from_name, overlay_text, spawn_cells, AppState, Fonts, Materials, Position};
+
+// この数のイベントごとに状態を覚えておき、シークはそこから計算し直す
+const KEYFRAME_INTERVAL: usize = 32;
+pub const SPEEDS: [f64; 5] = [0.25, 0.5, 1., 2., 4.];
+// 左右キーで飛ぶ秒数
+const SEEK_STEP: f64 = 5.;
+const TIMELINE_HEIGHT: f32 = 12.0;
+// メモを付けた時刻から出しておく秒数
+const NOTE_SECONDS: f64 = 3.;
+
+// あるイベントまで進めたときのゲーム。操作中のミノも含めて sim で動かす
+#[derive(Clone, PartialEq, Debug)]
+pub struct ReplayFrame {
+  pub state: GameState,
+  // 最後に出てきたミノ
+  pub piece: Option<char>,
+}
+
+impl Default for ReplayFrame {
+  fn default() -> Self {
+    // ミノはログの Spawn の順に出すので、キューは空のままにする
+    ReplayFrame {
+      state: GameState::new(vec![]),
+      piece: None,
+    }
+  }
+}
+
+impl ReplayFrame {
+  fn apply(&mut self, event: &LogEvent) {
+    match event {
+      LogEvent::Spawn { piece } => {
+        self.piece = Some(*piece);
+        // ホールドで入れ替わったときも、出てきたミノで置き直す
+        self.state.active = block_idx_from_name(*piece).map(|block_idx| Piece {
+          block_idx,
+          rotation: 0,
+          cells: spawn_cells(block_idx),
+        });
+      }
+      LogEvent::Move { dx, dy } => {
+        let action = if *dx < 0 { Action::Left } else { Action::Right };
+        for _ in 0..dx.abs() {
+          self.step(action);
+        }
+        for _ in 0..(-dy).max(0) {
+          self.step(Action::SoftDrop);
+        }
+      }
+      LogEvent::Fall => self.step(Action::SoftDrop),
+      // ゲームのキックテーブルは sim の SRS と違うことがあるので、記録したずらし方で回す
+      LogEvent::Rotate { from, to, kick } => {
+        let states = (state_from_name(*from), state_from_name(*to));
+        if let (Some(active), (Some(from), Some(to))) = (self.state.active.as_mut(), states) {
+          active.cells = rotated_cells(&active.cells, active.block_idx, from, to)
+            .into_iter()
+            .map(|p| Position {
+              x: p.x + kick.0,
+              y: p.y + kick.1,
+            })
+            .collect();
+          active.rotation = to;
+        }
+      }
+      // 固定した場所はログが正しいので、そこから落として置く。ライン消去も sim が行う
+      LogEvent::Lock { piece, cells } => {
+        self.state.active = Some(Piece {
+          block_idx: block_idx_from_name(*piece).unwrap_or(1),
+          rotation: 0,
+          cells: cells.iter().map(|&(x, y)| Position { x, y }).collect(),
+        });
+        self.step(Action::HardDrop);
+      }
+      LogEvent::Clear { .. } => {}
+    }
+  }
+
+  fn step(&mut self, action: Action) {
+    self.state = self.state.apply(action);
+  }
+}
+
+// イベントログからゲームを再現する。出てきたミノ、操作、自然落下、固定を順に sim に流し、
+// 一定の数のイベントごとに覚えておいた状態からシークする
+pub struct Replay {
+  pub log: GameLog,
+  // keyframes[k] は最初の k * KEYFRAME_INTERVAL 個を適用した状態
+  keyframes: Vec<ReplayFrame>,
+  // ミノを固定した直後のイベントの数。ミノ単位で前後に飛ぶのに使う
+  locks: Vec<usize>,
+}
+
+impl Replay {
+  pub fn new(log: GameLog) -> Self {
+    let mut frame = ReplayFrame::default();
+    let mut keyframes = vec![frame.clone()];
+    let mut locks = vec![];
+    for (i, record) in log.events.iter().enumerate() {
+      frame.apply(&record.event);
+      if (i + 1) % KEYFRAME_INTERVAL == 0 {
+        keyframes.push(frame.clone());
+      }
+      if let LogEvent::Lock { .. } = record.event {
+        locks.push(i + 1);
+      }
+    }
+    Replay {
+      log,
+      keyframes,
+      locks,
+    }
+  }
+
+  pub fn duration(&self) -> f64 {
+    self.log.events.last().map_or(0., |record| record.time)
+  }
+
+  // time 秒までに起きたイベントの数
+  pub fn events_until(&self, time: f64) -> usize {
+    self
+      .log
+      .events
+      .partition_point(|record| record.time <= time)
+  }
+
+  // n 個目のイベントが起きた時刻
+  pub fn event_time(&self, n: usize) -> f64 {
+    match n.min(self.log.events.len()) {
+      0 => 0.,
+      n => self.log.events[n - 1].time,
+    }
+  }
+
+  // 1イベント分進めた・戻した時刻。同じ時刻のイベントはまとめて扱う
+  pub fn step(&self, time: f64, forward: bool) -> f64 {
+    let n = self.events_until(time);
+    if forward {
+      return self
+        .log
+        .events
+        .get(n)
+        .map_or(self.duration(), |record| record.time);
+    }
+    let current = self.event_time(n);
+    self.event_time(
+      self
+        .log
+        .events
+        .partition_point(|record| record.time < current),
+    )
+  }
+
+  // time 秒の時点で出しておくメモ
+  pub fn notes_at(&self, time: f64) -> Vec<&Note> {
+    self
+      .log
+      .notes
+      .iter()
+      .filter(|note| note.time <= time && time < note.time + NOTE_SECONDS)
+      .collect()
+  }
+
+  // 次・前のミノを固定した直後の時刻。無ければ最後か最初へ
+  pub fn lock_step(&self, time: f64, forward: bool) -> f64 {
+    let mut times = self.locks.iter().map(|&n| self.event_time(n));
+    if forward {
+      times.find(|&t| t > time).unwrap_or_else(|| self.duration())
+    } else {
+      times.rev().find(|&t| t < time).unwrap_or(0.)
+    }
+  }
+
+  // 最初の n 個のイベントを適用した状態
+  pub fn frame(&self, n: usize) -> ReplayFrame {
+    let n = n.min(self.log.events.len());
+    let k = (n / KEYFRAME_INTERVAL).min(self.keyframes.len() - 1);
+    let mut frame = self.keyframes[k].clone();
+    for record in self.log.events[k * KEYFRAME_INTERVAL..n].iter() {
+      frame.apply(&record.event);
+    }
+    frame
+  }
+}
+
+struct ReplayViewer {
+  replay: Option<Replay>,
+  time: f64,
+  // SPEEDS の添字
+  speed: usize,
+  playing: bool,
+  // 盤面に出しているイベントの数とそのときの状態
+  shown: Option<usize>,
+  frame: ReplayFrame,
+  // 共有文字列を入力中
+  importing: Option<String>,
+  // 今の時刻に付けるメモを入力中
+  noting: Option<String>,
+  // 書き出し・読み込みの結果
+  message: String,
+}
+
+impl Default for ReplayViewer {
+  fn default() -> Self {
+    Self {
+      replay: None,
+      time: 0.,
+      speed: 2,
+      playing: true,
+      shown: None,
+      frame: ReplayFrame::default(),
+      importing: None,
+      noting: None,
+      message: String::new(),
+    }
+  }
+}
+
+struct ReplayBlock;
+struct ReplayText;
+struct Timeline;
+struct TimelineFill;
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(ReplayViewer::default())
+      .add_system_set(SystemSet::on_enter(AppState::Replay).with_system(setup_replay.system()))
+      .add_system_set(
+        SystemSet::on_update(AppState::Replay)
+          .with_system(replay_input.system())
+          .with_system(timeline_input.system())
+          .with_system(show_frame.system())
+          .with_system(replay_ui.system()),
+      )
+      .add_system_set(SystemSet::on_exit(AppState::Replay).with_system(close_replay.system()));
+  }
+}
+
+fn setup_replay(
+  mut commands: Commands,
+  fonts: Res<Fonts>,
+  materials: Res<Materials>,
+  mut viewer: ResMut<ReplayViewer>,
+) {
+  *viewer = ReplayViewer {
+    replay: GameLog::load_latest().map(Replay::new),
+    ..Default::default()
+  };
+
+  commands
+    .spawn_bundle(overlay_text(&fonts))
+    .insert(ReplayText);
+  commands
+    .spawn_bundle(NodeBundle {
+      style: Style {
+        position_type: PositionType::Absolute,
+        position: Rect {
+          bottom: Val::Px(10.0),
+          left: Val::Percent(5.0),
+          ..Default::default()
+        },
+        size: Size::new(Val::Percent(90.0), Val::Px(TIMELINE_HEIGHT)),
+        ..Default::default()
+      },
+      material: materials.panel.clone(),
+      ..Default::default()
+    })
+    .insert(Timeline)
+    .with_children(|parent| {
+      parent
+        .spawn_bundle(NodeBundle {
+          style: Style {
+            size: Size::new(Val::Percent(0.0), Val::Percent(100.0)),
+            ..Default::default()
+          },
+          material: materials.chart_bar.clone(),
+          ..Default::default()
+        })
+        .insert(TimelineFill);
+    });
+}
+
+impl ReplayViewer {
+  fn open(&mut self, log: GameLog) {
+    *self = ReplayViewer {
+      replay: Some(Replay::new(log)),
+      ..Default::default()
+    };
+  }
+
+  fn export(&mut self) {
+    let replay = match self.replay.as_ref() {
+      Some(replay) => replay,
+      None => return,
+    };
+    let code = share::encode(&replay.log);
+    info!("replay share string: {}", code);
+    self.message = match share::write_file(&code) {
+      Ok(path) => format!("saved {} chars to {}", code.len(), path.display()),
+      Err(e) => format!("could not save: {}", e),
+    };
+  }
+
+  // メモを足して、ログのファイルに書き直す
+  fn add_note(&mut self, text: String) {
+    let time = self.time;
+    let replay = match self.replay.as_mut() {
+      Some(replay) => replay,
+      None => return,
+    };
+    replay.log.add_note(time, text);
+    self.message = match replay.log.export() {
+      Ok(path) => format!("note saved to {}", path.display()),
+      Err(e) => format!("could not save note: {}", e),
+    };
+  }
+
+  // 今出しているメモのうち最後に付けたものを消す
+  fn remove_note(&mut self) {
+    let time = self.time;
+    let replay = match self.replay.as_mut() {
+      Some(replay) => replay,
+      None => return,
+    };
+    let shown = replay.notes_at(time).len();
+    if shown == 0 {
+      return;
+    }
+    let last = replay.log.notes.partition_point(|note| note.time <= time) - 1;
+    replay.log.notes.remove(last);
+    self.message = match replay.log.export() {
+      Ok(_) => "note removed".to_string(),
+      Err(e) => format!("could not save notes: {}", e),
+    };
+  }
+
+  fn import(&mut self, code: &str) {
+    match share::decode(code) {
+      Ok(log) => {
+        self.open(log);
+        self.message = "imported".to_string();
+      }
+      Err(e) => self.message = format!("could not import: {}", e),
+    }
+  }
+}
+
+fn replay_input(
+  time: Res<Time>,
+  keyboard_input: Res<Input<KeyCode>>,
+  menu: Res<MenuInput>,
+  mut char_events: EventReader<ReceivedCharacter>,
+  mut viewer: ResMut<ReplayViewer>,
+  mut state: ResMut<State<AppState>>,
+) {
+  if let Some(code) = viewer.importing.as_mut() {
+    for event in char_events.iter() {
+      if !event.char.is_control() {
+        code.push(event.char);
+      }
+    }
+    if keyboard_input.just_pressed(KeyCode::Back) {
+      code.pop();
+    } else if keyboard_input.just_pressed(KeyCode::Escape) {
+      viewer.importing = None;
+    } else if keyboard_input.just_pressed(KeyCode::Tab) {
+      match share::read_file() {
+        Ok(text) => *code = text.trim().to_string(),
+        Err(e) => viewer.message = format!("could not read: {}", e),
+      }
+    } else if keyboard_input.just_pressed(KeyCode::Return) {
+      let code = code.clone();
+      viewer.importing = None;
+      viewer.import(&code);
+    }
+    return;
+  }
+  if let Some(note) = viewer.noting.as_mut() {
+    for event in char_events.iter() {
+      if !event.char.is_control() {
+        note.push(event.char);
+      }
+    }
+    if keyboard_input.just_pressed(KeyCode::Back) {
+      note.pop();
+    } else if keyboard_input.just_pressed(KeyCode::Escape) {
+      viewer.noting = None;
+    } else if keyboard_input.just_pressed(KeyCode::Return) {
+      let note = note.trim().to_string();
+      viewer.noting = None;
+      if !note.is_empty() {
+        viewer.add_note(note);
+      }
+    }
+    return;
+  }
+  char_events.iter().for_each(drop);
+
+  if menu.back {
+    state.pop().unwrap();
+    return;
+  }
+  if keyboard_input.just_pressed(KeyCode::I) {
+    viewer.importing = Some(String::new());
+    viewer.message.clear();
+    return;
+  }
+  if keyboard_input.just_pressed(KeyCode::E) {
+    viewer.export();
+  }
+  // メモは止めた時刻に付ける
+  if keyboard_input.just_pressed(KeyCode::N) && viewer.replay.is_some() {
+    viewer.playing = false;
+    viewer.noting = Some(String::new());
+    viewer.message.clear();
+    return;
+  }
+  if keyboard_input.just_pressed(KeyCode::X) {
+    viewer.remove_note();
+  }
+  let duration = match viewer.replay.as_ref() {
+    Some(replay) => replay.duration(),
+    None => return,
+  };
+
+  if keyboard_input.just_pressed(KeyCode::Space) {
+    // 最後まで見たあとは頭から
+    if !viewer.playing && viewer.time >= duration {
+      viewer.time = 0.;
+    }
+    viewer.playing = !viewer.playing;
+  }
+  if keyboard_input.just_pressed(KeyCode::Minus) {
+    viewer.speed = viewer.speed.saturating_sub(1);
+  }
+  if keyboard_input.just_pressed(KeyCode::Equals) {
+    viewer.speed = (viewer.speed + 1).min(SPEEDS.len() - 1);
+  }
+  if keyboard_input.just_pressed(KeyCode::Left) {
+    viewer.time = (viewer.time - SEEK_STEP).max(0.);
+  }
+  if keyboard_input.just_pressed(KeyCode::Right) {
+    viewer.time = (viewer.time + SEEK_STEP).min(duration);
+  }
+  if keyboard_input.just_pressed(KeyCode::Home) {
+    viewer.time = 0.;
+  }
+  if keyboard_input.just_pressed(KeyCode::End) {
+    viewer.time = duration;
+  }
+
+  // コマ送りは1イベントずつ。止めてから動かす
+  let step_back = keyboard_input.just_pressed(KeyCode::Comma);
+  let step_forward = keyboard_input.just_pressed(KeyCode::Period);
+  if step_back || step_forward {
+    viewer.playing = false;
+    if let Some(replay) = viewer.replay.as_ref() {
+      viewer.time = replay.step(viewer.time, step_forward);
+    }
+  }
+  // [ ] はミノ 1 つずつ。固定した直後の盤面で止める
+  let piece_back = keyboard_input.just_pressed(KeyCode::LBracket);
+  let piece_forward = keyboard_input.just_pressed(KeyCode::RBracket);
+  if piece_back || piece_forward {
+    viewer.playing = false;
+    if let Some(replay) = viewer.replay.as_ref() {
+      viewer.time = replay.lock_step(viewer.time, piece_forward);
+    }
+  }
+
+  if viewer.playing {
+    viewer.time += time.delta_seconds_f64() * SPEEDS[viewer.speed];
+    if viewer.time >= duration {
+      viewer.time = duration;
+      viewer.playing = false;
+    }
+  }
+}
+
+// タイムラインをクリック・ドラッグした位置まで飛ぶ
+fn timeline_input(
+  windows: Res<Windows>,
+  mouse_input: Res<Input<MouseButton>>,
+  mut viewer: ResMut<ReplayViewer>,
+  query: Query<(&Node, &GlobalTransform), With<Timeline>>,
+) {
+  if !mouse_input.pressed(MouseButton::Left) {
+    return;
+  }
+  let cursor = match windows.get_primary().and_then(|w| w.cursor_position()) {
+    Some(cursor) => cursor,
+    None => return,
+  };
+  let duration = match viewer.replay.as_ref() {
+    Some(replay) => replay.duration(),
+    None => return,
+  };
+  for (node, transform) in query.iter() {
+    let min = transform.translation.truncate() - node.size / 2.;
+    // 細いので上下は少し余裕を持たせる
+    if cursor.y < min.y - TIMELINE_HEIGHT || cursor.y > min.y + node.size.y + TIMELINE_HEIGHT {
+      continue;
+    }
+    if node.size.x > 0. {
+      let ratio = ((cursor.x - min.x) / node.size.x).clamp(0., 1.);
+      viewer.time = ratio as f64 * duration;
+    }
+  }
+}
+
+fn show_frame(
+  mut commands: Commands,
+  materials: Res<Materials>,
+  mut viewer: ResMut<ReplayViewer>,
+  query: Query<Entity, With<ReplayBlock>>,
+) {
+  let (n, frame) = match viewer.replay.as_ref() {
+    Some(replay) => {
+      let n = replay.events_until(viewer.time);
+      if viewer.shown == Some(n) {
+        return;
+      }
+      (n, replay.frame(n))
+    }
+    None => return,
+  };
+  for entity in query.iter() {
+    commands.entity(entity).despawn();
+  }
+  let active = frame
+    .state
+    .active
+    .iter()
+    .flat_map(|piece| piece.cells.iter().cloned());
+  let cells = frame
+    .state
+    .board
+    .cells()
+    .into_iter()
+    .map(|position| (position, &materials.white_block))
+    .chain(active.map(|position| (position, &materials.gray_block)));
+  for (position, material) in cells {
+    commands
+      .spawn_bundle(SpriteBundle {
+        material: material.clone(),
+        ..Default::default()
+      })
+      .insert(ReplayBlock)
+      .insert(position)
+      .insert(crate::Size::square(0.8));
+  }
+  viewer.shown = Some(n);
+  viewer.frame = frame;
+}
+
+fn replay_ui(
+  viewer: Res<ReplayViewer>,
+  mut text_query: Query<&mut Text, With<ReplayText>>,
+  mut fill_query: Query<&mut Style, With<TimelineFill>>,
+) {
+  let (mut value, ratio) = match viewer.replay.as_ref() {
+    Some(replay) => {
+      let frame = &viewer.frame;
+      let duration = replay.duration();
+      (
+        format!(
+          "REPLAY {}  {} Hz\n{:.1} / {:.1}s  x{}{}\npieces {}  lines {}  next {}\n{}\n[Space] play/pause  [-/=] speed\n[,/.] step  [[/]] piece  [Left/Right] seek  [Home/End]\n[N] add note  [X] remove note  ({} notes)\n[E] export  [I] import  [Esc] back",
+          replay.log.mode,
+          replay.log.tick_rate.hz(),
+          viewer.time,
+          duration,
+          SPEEDS[viewer.speed],
+          if viewer.playing { "" } else { "  paused" },
+          frame.state.pieces,
+          frame.state.lines,
+          frame.piece.unwrap_or('-'),
+          replay
+            .notes_at(viewer.time)
+            .iter()
+            .map(|note| format!("\n> {:.1}s  {}", note.time, note.text))
+            .collect::<String>(),
+          replay.log.notes.len(),
+        ),
+        if duration > 0. {
+          (viewer.time / duration) as f32
+        } else {
+          0.
+        },
+      )
+    }
+    None => (
+      "REPLAY\n\nno saved games yet\n\n[I] import  [Esc] back".to_string(),
+      0.,
+    ),
+  };
+  if let Some(code) = viewer.importing.as_ref() {
+    // 長い文字列は末尾だけ見せる
+    let tail: String = code
+      .chars()
+      .rev()
+      .take(40)
+      .collect::<Vec<_>>()
+      .into_iter()
+      .rev()
+      .collect();
+    value = format!(
+      "IMPORT REPLAY\n\npaste or type a share string:\n{}{}_\n\n[Enter] import  [Tab] load {}\n[Esc] cancel",
+      if tail.len() < code.len() { "..." } else { "" },
+      tail,
+      share::SHARE_FILE,
+    );
+  }
+  if let Some(note) = viewer.noting.as_ref() {
+    value = format!(
+      "ADD NOTE at {:.1}s\n\n{}_\n\n[Enter] save  [Esc] cancel",
+      viewer.time, note
+    );
+  }
+  if !viewer.message.is_empty() {
+    value += &format!("\n\n{}", viewer.message);
+  }
+  for mut text in text_query.iter_mut() {
+    if text.sections[0].value != value {
+      text.sections[0].value = value.clone();
+    }
+  }
+  for mut style in fill_query.iter_mut() {
+    style.size.width = Val::Percent(ratio * 100.);
+  }
+}
+
+fn close_replay(
+  mut commands: Commands,
+  mut viewer: ResMut<ReplayViewer>,
+  query: Query<Entity, Or<(With<ReplayBlock>, With<ReplayText>, With<Timeline>)>>,
+) {
+  viewer.replay = None;
+  for entity in query.iter() {
+    commands.entity(entity).despawn_recursive();
+  }
+}
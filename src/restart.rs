@@ -0,0 +1,139 @@
+use bevy::prelude::*;
+
+use crate::config::Config;
+use crate::rules::Ruleset;
+use crate::{overlay_text, AppState, Fonts, GameMode, GameReset, PieceQueue};
+
+// 押し続けるとメニューを通らずに新しい種でやり直す
+const RESTART_KEY: KeyCode = KeyCode::R;
+// 押している間に出す棒の長さ
+const BAR_WIDTH: usize = 10;
+
+// 自前のやり直しがあるモードと、相手がいるモードでは使わない
+pub fn quick_restart_enabled(mode: GameMode) -> bool {
+  !matches!(
+    mode,
+    GameMode::Puzzle
+      | GameMode::Editor
+      | GameMode::Battle
+      | GameMode::Online
+      | GameMode::Exhibition
+  )
+}
+
+// R を押し続けている時間
+#[derive(Default)]
+pub struct RestartHold {
+  held: f32,
+  // 一度やり直したら、離すまでは次を数えない
+  fired: bool,
+}
+
+impl RestartHold {
+  // 毎フレーム呼ぶ。hold_seconds に届いたフレームだけ true
+  pub fn update(&mut self, pressed: bool, dt: f32, hold_seconds: f32) -> bool {
+    if !pressed || hold_seconds <= 0. {
+      *self = RestartHold::default();
+      return false;
+    }
+    if self.fired {
+      return false;
+    }
+    self.held += dt;
+    self.fired = self.held >= hold_seconds;
+    self.fired
+  }
+
+  // 0.0 から 1.0。やり直した後と押していないときは 0
+  pub fn progress(&self, hold_seconds: f32) -> f32 {
+    if self.fired || hold_seconds <= 0. {
+      return 0.;
+    }
+    (self.held / hold_seconds).clamp(0., 1.)
+  }
+}
+
+struct RestartText;
+
+pub struct RestartPlugin;
+
+impl Plugin for RestartPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(RestartHold::default())
+      .add_startup_system_to_stage(StartupStage::PostStartup, setup_restart.system())
+      .add_system_set(SystemSet::on_update(AppState::Playing).with_system(quick_restart.system()))
+      .add_system_set(SystemSet::on_enter(AppState::Playing).with_system(ignore_held_key.system()))
+      .add_system_set(SystemSet::on_exit(AppState::Playing).with_system(release_hold.system()))
+      .add_system(restart_ui.system());
+  }
+}
+
+fn setup_restart(mut commands: Commands, fonts: Res<Fonts>) {
+  let mut text = overlay_text(&fonts);
+  text.style.position = Rect {
+    bottom: Val::Px(40.0),
+    left: Val::Px(5.0),
+    ..Default::default()
+  };
+  commands.spawn_bundle(text).insert(RestartText);
+}
+
+// 一時停止や重力のスロー再生によらず、押した実時間で数える
+fn quick_restart(
+  time: Res<Time>,
+  keyboard_input: Res<Input<KeyCode>>,
+  config: Res<Config>,
+  mode: Res<GameMode>,
+  ruleset: Res<Ruleset>,
+  mut hold: ResMut<RestartHold>,
+  mut piece_queue: ResMut<PieceQueue>,
+  mut reset_events: EventWriter<GameReset>,
+) {
+  if !quick_restart_enabled(*mode) {
+    return;
+  }
+  let pressed = keyboard_input.pressed(RESTART_KEY);
+  if hold.update(pressed, time.delta_seconds(), config.restart.hold_seconds) {
+    piece_queue.reseed(&ruleset);
+    reset_events.send(GameReset);
+  }
+}
+
+// 結果画面の [R] で始めたときに、押したままの R で続けてやり直さない
+fn ignore_held_key(mut hold: ResMut<RestartHold>) {
+  *hold = RestartHold {
+    held: 0.,
+    fired: true,
+  };
+}
+
+fn release_hold(mut hold: ResMut<RestartHold>) {
+  *hold = RestartHold::default();
+}
+
+fn restart_ui(
+  config: Res<Config>,
+  hold: Res<RestartHold>,
+  mut query: Query<&mut Text, With<RestartText>>,
+) {
+  if !hold.is_changed() {
+    return;
+  }
+  let progress = hold.progress(config.restart.hold_seconds);
+  let value = if progress > 0. {
+    let filled = (progress * BAR_WIDTH as f32).round() as usize;
+    format!(
+      "RESTART [{}{}]",
+      "#".repeat(filled),
+      "-".repeat(BAR_WIDTH - filled)
+    )
+  } else {
+    String::new()
+  };
+  for mut text in query.iter_mut() {
+    if text.sections[0].value != value {
+      text.sections[0].value = value.clone();
+    }
+  }
+}
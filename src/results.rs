@@ -0,0 +1,497 @@
+use bevy::prelude::*;
+
+use crate::assist::AssistUsed;
+use crate::bests::PbRun;
+use crate::master::Master;
+use crate::menu::{MenuInput, MenuList};
+use crate::party::HotSeat;
+use crate::rules::Ruleset;
+use crate::splits::Splits;
+use crate::stats::{Sample, Statistics};
+use crate::summary::{render_summary, save_summary, summary_lines};
+use crate::survival::Survival;
+use crate::{
+  AppState, Fonts, GameClock, GameMode, GameReset, Materials, PieceQueue, Position, StackedBlock,
+  BLOCK_NAMES,
+};
+
+const CHART_HEIGHT: f32 = 80.0;
+// 横に並べる棒の最大数。多いときはまとめる
+const MAX_BARS: usize = 30;
+// 1分あたりのライン数を数える区間の長さ (秒)
+const CLEAR_BUCKET: f64 = 15.;
+// 折れ線の点の最大数
+const MAX_POINTS: usize = 120;
+// 結果画面で選べる項目と、同じことをするキー
+const RESULTS_ACTIONS: [(&str, &str); 3] = [
+  ("play again", "Enter"),
+  ("retry same seed", "R"),
+  ("save summary image", "S"),
+];
+const SAVE_ROW: usize = 2;
+
+struct ResultsScreen;
+// 画像を書き出した場所やエラーを出す
+struct ResultsMessage;
+struct ResultsMenuText;
+
+// 選んでいる項目と、画像の書き出しを頼まれたか
+#[derive(Default)]
+struct ResultsMenu {
+  row: usize,
+  save: bool,
+}
+
+pub struct ResultsPlugin;
+
+impl Plugin for ResultsPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(ResultsMenu::default())
+      .add_system_set(SystemSet::on_update(AppState::Playing).with_system(end_game_input.system()))
+      .add_system_set(SystemSet::on_enter(AppState::Results).with_system(setup_results.system()))
+      .add_system_set(
+        SystemSet::on_update(AppState::Results)
+          .with_system(results_input.system())
+          .with_system(summary_input.system()),
+      )
+      .add_system_set(SystemSet::on_exit(AppState::Results).with_system(close_results.system()));
+  }
+}
+
+// 区間ごとの最大値をとって n 本以下にまとめる
+pub fn downsample(values: &[f32], n: usize) -> Vec<f32> {
+  if values.len() <= n {
+    return values.to_vec();
+  }
+  let chunk = values.len().div_ceil(n);
+  values
+    .chunks(chunk)
+    .map(|c| c.iter().copied().fold(0., f32::max))
+    .collect()
+}
+
+fn end_game_input(keyboard_input: Res<Input<KeyCode>>, mut state: ResMut<State<AppState>>) {
+  if keyboard_input.just_pressed(KeyCode::Escape) {
+    state.overwrite_set(AppState::Results).unwrap();
+  }
+}
+
+fn spawn_chart(
+  parent: &mut ChildBuilder,
+  fonts: &Fonts,
+  materials: &Materials,
+  title: &str,
+  values: &[f32],
+) {
+  let max = values.iter().copied().fold(0., f32::max);
+  parent.spawn_bundle(TextBundle {
+    text: Text::with_section(
+      format!("{} (max {:.0})", title, max),
+      TextStyle {
+        font: fonts.main.clone(),
+        font_size: 14.0,
+        color: Color::WHITE,
+      },
+      Default::default(),
+    ),
+    ..Default::default()
+  });
+  parent
+    .spawn_bundle(NodeBundle {
+      style: Style {
+        size: Size::new(Val::Percent(100.0), Val::Px(CHART_HEIGHT)),
+        margin: Rect {
+          bottom: Val::Px(12.0),
+          ..Default::default()
+        },
+        // y 軸が上向きなので FlexStart で棒の根元が下にそろう
+        align_items: AlignItems::FlexStart,
+        ..Default::default()
+      },
+      material: materials.transparent.clone(),
+      ..Default::default()
+    })
+    .with_children(|parent| {
+      for &value in values.iter() {
+        let ratio = if max > 0. { value / max } else { 0. };
+        parent.spawn_bundle(NodeBundle {
+          style: Style {
+            size: Size::new(Val::Px(8.0), Val::Percent(ratio * 100.0)),
+            margin: Rect {
+              right: Val::Px(2.0),
+              ..Default::default()
+            },
+            ..Default::default()
+          },
+          material: materials.chart_bar.clone(),
+          ..Default::default()
+        });
+      }
+    });
+}
+
+// 折れ線のグラフ。前の点との間を縦につないだ階段状の線で描く
+fn spawn_line_chart(
+  parent: &mut ChildBuilder,
+  fonts: &Fonts,
+  materials: &Materials,
+  title: &str,
+  values: &[f32],
+  line: Handle<ColorMaterial>,
+) {
+  let max = values.iter().copied().fold(0., f32::max);
+  let width = 100. / values.len().max(1) as f32;
+  parent.spawn_bundle(TextBundle {
+    text: Text::with_section(
+      format!("{} (max {:.0})", title, max),
+      TextStyle {
+        font: fonts.main.clone(),
+        font_size: 14.0,
+        color: Color::WHITE,
+      },
+      Default::default(),
+    ),
+    ..Default::default()
+  });
+  parent
+    .spawn_bundle(NodeBundle {
+      style: Style {
+        size: Size::new(Val::Percent(100.0), Val::Px(CHART_HEIGHT)),
+        margin: Rect {
+          bottom: Val::Px(12.0),
+          ..Default::default()
+        },
+        ..Default::default()
+      },
+      material: materials.transparent.clone(),
+      ..Default::default()
+    })
+    .with_children(|parent| {
+      let ratio = |v: f32| if max > 0. { v / max * 100. } else { 0. };
+      for (i, &value) in values.iter().enumerate() {
+        let previous = values[i.saturating_sub(1)];
+        let (low, high) = (value.min(previous), value.max(previous));
+        // y 軸が上向きなので、下に空白を置いてから線を重ねる
+        parent
+          .spawn_bundle(NodeBundle {
+            style: Style {
+              size: Size::new(Val::Percent(width), Val::Percent(100.0)),
+              flex_direction: FlexDirection::Column,
+              ..Default::default()
+            },
+            material: materials.transparent.clone(),
+            ..Default::default()
+          })
+          .with_children(|parent| {
+            parent.spawn_bundle(NodeBundle {
+              style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(ratio(low))),
+                ..Default::default()
+              },
+              material: materials.transparent.clone(),
+              ..Default::default()
+            });
+            parent.spawn_bundle(NodeBundle {
+              style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(ratio(high - low))),
+                min_size: Size::new(Val::Auto, Val::Px(2.0)),
+                ..Default::default()
+              },
+              material: line.clone(),
+              ..Default::default()
+            });
+          });
+      }
+    });
+}
+
+fn setup_results(
+  mut commands: Commands,
+  clock: Res<GameClock>,
+  fonts: Res<Fonts>,
+  materials: Res<Materials>,
+  stats: Res<Statistics>,
+  piece_queue: Res<PieceQueue>,
+  ruleset: Res<Ruleset>,
+  assists: Res<AssistUsed>,
+  master: Option<Res<Master>>,
+  splits: Option<Res<Splits>>,
+  seat: Option<Res<HotSeat>>,
+  survival: Option<Res<Survival>>,
+  pb: Res<PbRun>,
+  mode: Res<GameMode>,
+) {
+  let elapsed = clock.0 - stats.start;
+  let pieces: Vec<f32> = stats.pieces.iter().map(|&n| n as f32).collect();
+  let columns: Vec<f32> = stats.columns.iter().map(|&n| n as f32).collect();
+  let clears = downsample(&stats.clears_per_minute(CLEAR_BUCKET, elapsed), MAX_BARS);
+  let timeline = |f: fn(&Sample) -> f32| -> Vec<f32> {
+    let values: Vec<f32> = stats.timeline.iter().map(f).collect();
+    downsample(&values, MAX_POINTS)
+  };
+  let heights = timeline(|s| s.height as f32);
+  let sent = timeline(|s| s.sent as f32);
+  let received = timeline(|s| s.received as f32);
+  let lines: u32 = stats.clears.iter().map(|&(_, n)| n).sum();
+  let piece_names: String = BLOCK_NAMES.iter().collect();
+  let assisted = assists
+    .describe()
+    .into_iter()
+    .chain(ruleset.drought_cap_note())
+    .map(|text| format!("{}\n", text))
+    .collect::<String>();
+  let master = master.map_or(String::new(), |m| format!("{}\n", m.summary()));
+  let splits = splits
+    .filter(|s| !s.current.is_empty())
+    .map_or(String::new(), |s| format!("{}\n", s.summary()));
+  let party = seat.map_or(String::new(), |s| format!("{}\n", s.summary()));
+  let survived = survival
+    .as_ref()
+    .map_or(String::new(), |s| format!("{}\n", s.summary()));
+  let garbage = survival.map(|s| downsample(&s.samples, MAX_BARS));
+  let misdrops = if *mode == GameMode::Practice {
+    format!("misdrops {}\n", stats.misdrops)
+  } else {
+    String::new()
+  };
+
+  commands
+    .spawn_bundle(NodeBundle {
+      style: Style {
+        position_type: PositionType::Absolute,
+        size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+        flex_direction: FlexDirection::ColumnReverse,
+        padding: Rect::all(Val::Px(20.0)),
+        ..Default::default()
+      },
+      material: materials.panel.clone(),
+      ..Default::default()
+    })
+    .insert(ResultsScreen)
+    .with_children(|parent| {
+      parent.spawn_bundle(TextBundle {
+        text: Text::with_section(
+          format!(
+            "RESULTS\ntime {:.1}s  pieces {}  lines {}\nseed {:016x}\n{}{}{}{}{}{}",
+            elapsed,
+            stats.pieces.iter().sum::<u32>(),
+            lines,
+            piece_queue.seed,
+            master,
+            splits,
+            party,
+            survived,
+            misdrops,
+            assisted,
+          ),
+          TextStyle {
+            font: fonts.main.clone(),
+            font_size: 18.0,
+            color: Color::WHITE,
+          },
+          Default::default(),
+        ),
+        ..Default::default()
+      });
+      // 自己ベストを更新したときは目立つ色で出す
+      if let Some(summary) = pb.summary() {
+        let color = if pb.is_new_best() {
+          Color::rgb(1.0, 0.8, 0.2)
+        } else {
+          Color::WHITE
+        };
+        parent.spawn_bundle(TextBundle {
+          text: Text::with_section(
+            summary,
+            TextStyle {
+              font: fonts.main.clone(),
+              font_size: 18.0,
+              color,
+            },
+            Default::default(),
+          ),
+          ..Default::default()
+        });
+      }
+      spawn_chart(
+        parent,
+        &fonts,
+        &materials,
+        &format!("pieces {}", piece_names),
+        &pieces,
+      );
+      spawn_chart(parent, &fonts, &materials, "cells per column", &columns);
+      spawn_chart(parent, &fonts, &materials, "lines per minute", &clears);
+      spawn_line_chart(
+        parent,
+        &fonts,
+        &materials,
+        "stack height",
+        &heights,
+        materials.chart_bar.clone(),
+      );
+      // 送り合いの無いモードでは出さない
+      if stats.sent > 0 || stats.received > 0 {
+        spawn_line_chart(
+          parent,
+          &fonts,
+          &materials,
+          "lines sent",
+          &sent,
+          materials.chart_sent.clone(),
+        );
+        spawn_line_chart(
+          parent,
+          &fonts,
+          &materials,
+          "lines received",
+          &received,
+          materials.chart_received.clone(),
+        );
+      }
+      if let Some(garbage) = garbage.as_ref() {
+        spawn_chart(parent, &fonts, &materials, "garbage on board", garbage);
+      }
+      parent
+        .spawn_bundle(TextBundle {
+          text: Text::with_section(
+            results_menu_text(0),
+            TextStyle {
+              font: fonts.main.clone(),
+              font_size: 16.0,
+              color: Color::WHITE,
+            },
+            Default::default(),
+          ),
+          ..Default::default()
+        })
+        .insert(ResultsMenuText)
+        .insert(MenuList::new(0, RESULTS_ACTIONS.len()));
+      parent
+        .spawn_bundle(TextBundle {
+          text: Text::with_section(
+            "",
+            TextStyle {
+              font: fonts.main.clone(),
+              font_size: 16.0,
+              color: Color::WHITE,
+            },
+            Default::default(),
+          ),
+          ..Default::default()
+        })
+        .insert(ResultsMessage);
+    });
+}
+
+pub fn results_menu_text(row: usize) -> String {
+  RESULTS_ACTIONS
+    .iter()
+    .enumerate()
+    .map(|(i, (name, key))| {
+      let cursor = if i == row { '>' } else { ' ' };
+      format!("{} {:<20} [{}]", cursor, name, key)
+    })
+    .collect::<Vec<_>>()
+    .join("\n")
+}
+
+fn color_bytes(color: Color) -> [u8; 3] {
+  let [r, g, b, _] = color.as_rgba_f32();
+  [r, g, b].map(|c| (c.clamp(0., 1.) * 255.).round() as u8)
+}
+
+// 最後の盤面と数字を PNG にして保存する。ブロックは今の見た目の色で塗る
+fn summary_input(
+  keyboard_input: Res<Input<KeyCode>>,
+  mut results_menu: ResMut<ResultsMenu>,
+  clock: Res<GameClock>,
+  fonts: Res<Fonts>,
+  font_assets: Res<Assets<Font>>,
+  color_materials: Res<Assets<ColorMaterial>>,
+  clear_color: Res<ClearColor>,
+  stats: Res<Statistics>,
+  piece_queue: Res<PieceQueue>,
+  mode: Res<GameMode>,
+  stacked_block_query: Query<(&Position, &Handle<ColorMaterial>), With<StackedBlock>>,
+  mut message_query: Query<&mut Text, With<ResultsMessage>>,
+) {
+  if !keyboard_input.just_pressed(KeyCode::S) && !results_menu.save {
+    return;
+  }
+  results_menu.save = false;
+  let message = match font_assets.get(&fonts.main) {
+    Some(font) => {
+      let cells: Vec<(Position, [u8; 3])> = stacked_block_query
+        .iter()
+        .map(|(position, material)| {
+          let color = color_materials
+            .get(material)
+            .map_or(Color::WHITE, |m| m.color);
+          (position.clone(), color_bytes(color))
+        })
+        .collect();
+      let lines = summary_lines(*mode, clock.0 - stats.start, &stats, piece_queue.seed);
+      let image = render_summary(&font.font, &cells, &lines, color_bytes(clear_color.0));
+      match save_summary(&image) {
+        Ok(path) => format!("saved {}", path.display()),
+        Err(e) => format!("could not save the image: {}", e),
+      }
+    }
+    None => "font is not loaded yet".to_string(),
+  };
+  for mut text in message_query.iter_mut() {
+    text.sections[0].value = message.clone();
+  }
+}
+
+fn results_input(
+  keyboard_input: Res<Input<KeyCode>>,
+  menu: Res<MenuInput>,
+  ruleset: Res<Ruleset>,
+  mut results_menu: ResMut<ResultsMenu>,
+  mut piece_queue: ResMut<PieceQueue>,
+  mut state: ResMut<State<AppState>>,
+  mut reset_events: EventWriter<GameReset>,
+  mut query: Query<(&mut Text, &MenuList), With<ResultsMenuText>>,
+) {
+  let (pointed, hovered) = query
+    .iter_mut()
+    .next()
+    .map_or((None, None), |(_, list)| (list.pointed, list.hovered));
+  let row = menu.select(results_menu.row, RESULTS_ACTIONS.len(), pointed);
+  if results_menu.row != row {
+    results_menu.row = row;
+    for (mut text, _) in query.iter_mut() {
+      text.sections[0].value = results_menu_text(row);
+    }
+  }
+  let chosen = menu.chosen(row, hovered);
+  if chosen == Some(SAVE_ROW) {
+    results_menu.save = true;
+    return;
+  }
+  if chosen == Some(0) {
+    piece_queue.reseed(&ruleset);
+  } else if keyboard_input.just_pressed(KeyCode::R) || chosen == Some(1) {
+    // 同じ種で引き直して、今のミノの並びをもう一度
+    let seed = piece_queue.seed;
+    piece_queue.restart(&ruleset, seed);
+  } else {
+    return;
+  }
+  reset_events.send(GameReset);
+  state.set(AppState::Playing).unwrap();
+}
+
+fn close_results(
+  mut commands: Commands,
+  mut results_menu: ResMut<ResultsMenu>,
+  query: Query<Entity, With<ResultsScreen>>,
+) {
+  *results_menu = ResultsMenu::default();
+  for entity in query.iter() {
+    commands.entity(entity).despawn_recursive();
+  }
+}
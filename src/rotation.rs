@@ -0,0 +1,299 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::board::Board;
+use crate::{block_idx_from_name, Position, BLOCKMAP, BLOCK_NAMES};
+
+pub const KICK_DIR: &str = "assets/kicks";
+// 1回の回転で試す位置の数と、ずらせる距離の上限
+const MAX_KICKS: usize = 8;
+const MAX_OFFSET: i32 = 3;
+
+// 回転の状態。出現時が 0 で、右回りに R, 2, L
+const STATE_NAMES: [char; 4] = ['0', 'R', '2', 'L'];
+
+type Kicks = Vec<(i32, i32)>;
+
+const JLSTZ_KICKS: [(&str, [(i32, i32); 5]); 8] = [
+  ("0>R", [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)]),
+  ("R>0", [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)]),
+  ("R>2", [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)]),
+  ("2>R", [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)]),
+  ("2>L", [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)]),
+  ("L>2", [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)]),
+  ("L>0", [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)]),
+  ("0>L", [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)]),
+];
+
+const I_KICKS: [(&str, [(i32, i32); 5]); 8] = [
+  ("0>R", [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)]),
+  ("R>0", [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)]),
+  ("R>2", [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)]),
+  ("2>R", [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)]),
+  ("2>L", [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)]),
+  ("L>2", [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)]),
+  ("L>0", [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)]),
+  ("0>L", [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)]),
+];
+
+// ルールで選ぶ回転法。Srs は config.ron の kick_table があればそれで上書きする
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum RotationSystem {
+  #[default]
+  Srs,
+  // 回せなければ右、左に 1 マスずらして試す。I はずらさない
+  Ars,
+  // ずらさない
+  Classic,
+}
+
+impl RotationSystem {
+  pub const ALL: [RotationSystem; 3] = [
+    RotationSystem::Srs,
+    RotationSystem::Ars,
+    RotationSystem::Classic,
+  ];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      RotationSystem::Srs => "SRS",
+      RotationSystem::Ars => "ARS-like",
+      RotationSystem::Classic => "classic",
+    }
+  }
+}
+
+// "0>R" を (0, 1) に
+fn parse_transition(key: &str) -> Option<(u8, u8)> {
+  let chars: Vec<char> = key.chars().collect();
+  let (from, to) = match chars.as_slice() {
+    [from, '>', to] => (state_from_name(*from)?, state_from_name(*to)?),
+    _ => return None,
+  };
+  // 90度ずつしか回さない
+  if (from + 1) % 4 != to && (to + 1) % 4 != from {
+    return None;
+  }
+  Some((from, to))
+}
+
+#[derive(Deserialize)]
+struct KickFile {
+  name: String,
+  // ミノの名前 -> "0>R" などの遷移 -> 試すずらし方 (x, y)
+  pieces: BTreeMap<char, BTreeMap<String, Kicks>>,
+}
+
+// 回転に失敗したとき、順番にずらして試す位置
+pub struct KickTable {
+  pub name: String,
+  kicks: HashMap<(u32, u8, u8), Kicks>,
+}
+
+impl KickTable {
+  pub fn srs() -> Self {
+    let mut kicks = HashMap::new();
+    for block_idx in 1..=BLOCK_NAMES.len() as u32 {
+      let table = match BLOCK_NAMES[(block_idx - 1) as usize] {
+        // O は回しても形が変わらないので動かさない
+        'O' => continue,
+        'I' => &I_KICKS,
+        _ => &JLSTZ_KICKS,
+      };
+      for (key, offsets) in table.iter() {
+        let (from, to) = parse_transition(key).unwrap();
+        kicks.insert((block_idx, from, to), offsets.to_vec());
+      }
+    }
+    KickTable {
+      name: "SRS".to_string(),
+      kicks,
+    }
+  }
+
+  // キックの無い遷移はその場でしか回さない
+  pub fn for_system(system: RotationSystem) -> Self {
+    let mut table = KickTable {
+      name: system.name().to_string(),
+      kicks: HashMap::new(),
+    };
+    match system {
+      RotationSystem::Srs => return KickTable::srs(),
+      RotationSystem::Ars => {
+        for block_idx in 1..=BLOCK_NAMES.len() as u32 {
+          if matches!(BLOCK_NAMES[(block_idx - 1) as usize], 'O' | 'I') {
+            continue;
+          }
+          for (key, _) in JLSTZ_KICKS.iter() {
+            let (from, to) = parse_transition(key).unwrap();
+            table
+              .kicks
+              .insert((block_idx, from, to), vec![(0, 0), (1, 0), (-1, 0)]);
+          }
+        }
+      }
+      RotationSystem::Classic => {}
+    }
+    table
+  }
+
+  // SRS をもとに、ファイルにあるミノ・遷移だけ差し替える
+  pub fn parse(src: &str) -> Result<Self, String> {
+    let file: KickFile = ron::de::from_str(src).map_err(|e| e.to_string())?;
+    let mut table = KickTable::srs();
+    table.name = file.name;
+    for (name, transitions) in file.pieces {
+      let block_idx = block_idx_from_name(name).ok_or(format!("unknown piece: {}", name))?;
+      for (key, offsets) in transitions {
+        let (from, to) =
+          parse_transition(&key).ok_or(format!("{}: bad transition {:?}", name, key))?;
+        if offsets.is_empty() || offsets.len() > MAX_KICKS {
+          return Err(format!(
+            "{} {}: needs 1 to {} offsets, got {}",
+            name,
+            key,
+            MAX_KICKS,
+            offsets.len()
+          ));
+        }
+        if let Some(&(x, y)) = offsets
+          .iter()
+          .find(|(x, y)| x.abs() > MAX_OFFSET || y.abs() > MAX_OFFSET)
+        {
+          return Err(format!(
+            "{} {}: offset ({}, {}) is farther than {}",
+            name, key, x, y, MAX_OFFSET
+          ));
+        }
+        table.kicks.insert((block_idx, from, to), offsets);
+      }
+    }
+    Ok(table)
+  }
+
+  pub fn load(name: &str) -> Result<Self, String> {
+    let path = Path::new(KICK_DIR).join(format!("{}.ron", name));
+    fs::read_to_string(&path)
+      .map_err(|e| e.to_string())
+      .and_then(|s| KickTable::parse(&s))
+      .map_err(|e| format!("{}: {}", path.display(), e))
+  }
+
+  fn offsets(&self, block_idx: u32, from: u8, to: u8) -> &[(i32, i32)] {
+    self
+      .kicks
+      .get(&(block_idx, from, to))
+      .map_or(&[(0, 0)], |kicks| kicks.as_slice())
+  }
+}
+
+// 回転の中心を2倍した座標で持つ (I と O はマスの角が中心になる)
+fn pivot2(block_idx: u32) -> (i32, i32) {
+  match BLOCK_NAMES[(block_idx - 1) as usize] {
+    'O' => (1, 1),
+    'I' => (1, 3),
+    'L' | 'J' => (0, 2),
+    _ => (0, 0),
+  }
+}
+
+fn rotate_cw((x, y): (i32, i32)) -> (i32, i32) {
+  (y, -x)
+}
+
+// 状態 state のときの形を中心からの相対位置 (2倍) で返す
+fn shape2(block_idx: u32, state: u8) -> Vec<(i32, i32)> {
+  let (px, py) = pivot2(block_idx);
+  BLOCKMAP[&block_idx]
+    .iter()
+    .map(|p| {
+      let mut rel = (p.x * 2 - px, p.y * 2 - py);
+      for _ in 0..state {
+        rel = rotate_cw(rel);
+      }
+      rel
+    })
+    .collect()
+}
+
+pub fn state_name(state: u8) -> char {
+  STATE_NAMES[(state % 4) as usize]
+}
+
+pub fn state_from_name(name: char) -> Option<u8> {
+  STATE_NAMES.iter().position(|&c| c == name).map(|i| i as u8)
+}
+
+// 状態 state のマスを、ずらさずにそのまま状態 to の形にしたもの
+pub fn rotated_cells(cells: &[Position], block_idx: u32, state: u8, to: u8) -> Vec<Position> {
+  // 今の形の外接矩形の左下と、実際のマスの左下を合わせて中心を求める
+  let current = shape2(block_idx, state);
+  let shape_min = (
+    current.iter().map(|p| p.0).min().unwrap(),
+    current.iter().map(|p| p.1).min().unwrap(),
+  );
+  let cells_min = (
+    cells.iter().map(|p| p.x).min().unwrap() * 2,
+    cells.iter().map(|p| p.y).min().unwrap() * 2,
+  );
+  let center = (cells_min.0 - shape_min.0, cells_min.1 - shape_min.1);
+
+  shape2(block_idx, to)
+    .into_iter()
+    .map(|(x, y)| Position {
+      x: (center.0 + x) / 2,
+      y: (center.1 + y) / 2,
+    })
+    .collect()
+}
+
+// 壁や積まれたブロックを避けながら回す。成功したら新しいマスと状態、使ったずらし方
+pub fn rotate(
+  board: &Board,
+  table: &KickTable,
+  cells: &[Position],
+  block_idx: u32,
+  state: u8,
+  clockwise: bool,
+) -> Option<(Vec<Position>, u8, (i32, i32))> {
+  rotate_with(|c| board.fits(c), table, cells, block_idx, state, clockwise)
+}
+
+// rotate の置けるかどうかの判定を差し替えたもの。Board に収まらない盤面から使う
+pub fn rotate_with(
+  fits: impl Fn(&[Position]) -> bool,
+  table: &KickTable,
+  cells: &[Position],
+  block_idx: u32,
+  state: u8,
+  clockwise: bool,
+) -> Option<(Vec<Position>, u8, (i32, i32))> {
+  if cells.is_empty() {
+    return None;
+  }
+  let to = if clockwise {
+    (state + 1) % 4
+  } else {
+    (state + 3) % 4
+  };
+
+  let rotated = rotated_cells(cells, block_idx, state, to);
+
+  table
+    .offsets(block_idx, state, to)
+    .iter()
+    .map(|&(dx, dy)| {
+      let candidate: Vec<Position> = rotated
+        .iter()
+        .map(|p| Position {
+          x: p.x + dx,
+          y: p.y + dy,
+        })
+        .collect();
+      (candidate, to, (dx, dy))
+    })
+    .find(|(candidate, _, _)| fits(candidate))
+}
@@ -0,0 +1,542 @@
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::attack::AttackTable;
+use crate::randomizer::{with_drought_cap, Randomizer, RandomizerKind};
+use crate::rotation::RotationSystem;
+use crate::speed::{SpeedTable, TickRate};
+use crate::stats::Scoring;
+use crate::{overlay_text, save, AppState, Fonts, GameMode, PieceQueue, LOCK_DELAY};
+
+const SAVE_FILE: &str = "rules.ron";
+// プリセットの Custom。プロフィールによらず config.ron と同じ場所に置く
+const CUSTOM_FILE: &str = "custom_rules.ron";
+pub const MAX_PREVIEWS: usize = 6;
+// [L] で順に切り替える固定までの猶予 (秒)
+const LOCK_DELAYS: [f32; 4] = [0.5, 1.0, 0.25, 0.];
+// [C] で順に切り替える、棒が必ず出るまでの個数
+const DROUGHT_CAPS: [Option<u32>; 3] = [None, Some(12), Some(7)];
+
+// モードごとのルール設定。大会形式によっては値が決められている
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Ruleset {
+  pub previews: usize,
+  pub hold: bool,
+  pub ghost: bool,
+  #[serde(default)]
+  pub randomizer: RandomizerKind,
+  // 盤面を左右反転して描く。ゲームの中身はそのままで、反転した定石の練習用
+  #[serde(default)]
+  pub mirror: bool,
+  // 左右の移動と回転の向きを入れ替える。描き方の反転とは別に選ぶ
+  #[serde(default)]
+  pub mirror_input: bool,
+  // 固定してから次のミノが出るまで (ARE)。ミリ秒で持ち、使うときに tick 数に直す
+  #[serde(default = "default_spawn_delay")]
+  pub spawn_delay_ms: u32,
+  // ラインを消したときに ARE に足す待ち (ミリ秒)
+  #[serde(default = "default_clear_delay")]
+  pub clear_delay_ms: u32,
+  #[serde(default)]
+  pub rotation: RotationSystem,
+  // 接地してから固定されるまでの秒数
+  #[serde(default = "default_lock_delay")]
+  pub lock_delay: f32,
+  // 重力の表。None なら config.ron の speed_table
+  #[serde(default)]
+  pub gravity: Option<SpeedTable>,
+  #[serde(default)]
+  pub scoring: Scoring,
+  #[serde(default)]
+  pub attack: AttackTable,
+  // 棒が必ずこの個数のうちに 1 つは出る。ふつうのルールと記録を比べられないので、結果にも出す
+  #[serde(default)]
+  pub drought_cap: Option<u32>,
+}
+
+fn default_spawn_delay() -> u32 {
+  Timing::Guideline.delays().0
+}
+
+fn default_clear_delay() -> u32 {
+  Timing::Guideline.delays().1
+}
+
+fn default_lock_delay() -> f32 {
+  LOCK_DELAY
+}
+
+// ARE とライン消去の待ちの組み合わせ
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Timing {
+  Guideline,
+  // NES くらいの間
+  Classic,
+  // 以前の既定。1 秒待ってから次のミノが出る
+  Relaxed,
+}
+
+impl Timing {
+  pub const ALL: [Timing; 3] = [Timing::Guideline, Timing::Classic, Timing::Relaxed];
+
+  // (ARE, ライン消去) のミリ秒
+  pub fn delays(self) -> (u32, u32) {
+    match self {
+      Timing::Guideline => (100, 500),
+      Timing::Classic => (167, 333),
+      Timing::Relaxed => (1000, 0),
+    }
+  }
+
+  pub fn name(self) -> &'static str {
+    match self {
+      Timing::Guideline => "guideline",
+      Timing::Classic => "classic",
+      Timing::Relaxed => "relaxed",
+    }
+  }
+
+  // ルールの待ちがどのプリセットと同じか。rules.ron で書き換えていれば None
+  pub fn of(ruleset: &Ruleset) -> Option<Timing> {
+    Timing::ALL
+      .iter()
+      .copied()
+      .find(|t| t.delays() == (ruleset.spawn_delay_ms, ruleset.clear_delay_ms))
+  }
+}
+
+impl Ruleset {
+  pub fn for_mode(mode: GameMode) -> Self {
+    let (spawn_delay_ms, clear_delay_ms) = Timing::Guideline.delays();
+    let ruleset = Ruleset {
+      previews: 5,
+      hold: true,
+      ghost: true,
+      randomizer: RandomizerKind::Bag,
+      mirror: false,
+      mirror_input: false,
+      spawn_delay_ms,
+      clear_delay_ms,
+      rotation: RotationSystem::Srs,
+      lock_delay: LOCK_DELAY,
+      gravity: None,
+      scoring: Scoring::Guideline,
+      attack: AttackTable::Guideline,
+      drought_cap: None,
+    };
+    match mode {
+      // パズルはキューをすべて表示するのでプレビューは不要
+      // 対戦は盤面ごとに自前で描くので、通常のプレビューとホールドは出さない
+      GameMode::Puzzle | GameMode::Battle | GameMode::Online | GameMode::Exhibition => Ruleset {
+        previews: 0,
+        hold: false,
+        ..ruleset
+      },
+      _ => ruleset,
+    }
+  }
+
+  // 種からこのルールのランダマイザーを作る
+  pub fn build_randomizer(&self, seed: u64) -> Box<dyn Randomizer> {
+    with_drought_cap(self.randomizer.build(seed), self.drought_cap)
+  }
+
+  // ふつうのルールと違う引き方をしているときの注記
+  pub fn drought_cap_note(&self) -> Option<String> {
+    self
+      .drought_cap
+      .map(|cap| format!("drought cap: I within every {} pieces", cap))
+  }
+
+  // 固定してから次のミノが出るまでの秒数。rate の tick 数に丸める
+  pub fn entry_seconds(&self, cleared: bool, rate: TickRate) -> f64 {
+    let ms = self.spawn_delay_ms + if cleared { self.clear_delay_ms } else { 0 };
+    rate.ticks(ms) as f64 / rate.hz() as f64
+  }
+}
+
+// ルール一式のプリセット。Custom は設定画面で作って保存したルール
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Preset {
+  Guideline,
+  Classic,
+  Tgm,
+  Custom,
+}
+
+impl Preset {
+  pub const ALL: [Preset; 4] = [
+    Preset::Guideline,
+    Preset::Classic,
+    Preset::Tgm,
+    Preset::Custom,
+  ];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      Preset::Guideline => "guideline",
+      Preset::Classic => "classic",
+      Preset::Tgm => "TGM",
+      Preset::Custom => "custom",
+    }
+  }
+
+  // ゴーストと左右反転は遊ぶ人の好みなので、今のルール base のまま変えない
+  // プレビューとホールドを出さないモードでは、どのプリセットでも出さない
+  pub fn apply(self, mode: GameMode, base: &Ruleset, custom: &Ruleset) -> Ruleset {
+    let defaults = Ruleset::for_mode(mode);
+    let ruleset = match self {
+      Preset::Guideline => defaults,
+      Preset::Classic => {
+        let (spawn_delay_ms, clear_delay_ms) = Timing::Classic.delays();
+        Ruleset {
+          previews: 1,
+          hold: false,
+          randomizer: RandomizerKind::Memoryless,
+          spawn_delay_ms,
+          clear_delay_ms,
+          rotation: RotationSystem::Classic,
+          lock_delay: 0.,
+          gravity: Some(SpeedTable::Nes),
+          scoring: Scoring::Classic,
+          attack: AttackTable::Classic,
+          ..defaults
+        }
+      }
+      Preset::Tgm => Ruleset {
+        previews: 1,
+        hold: false,
+        randomizer: RandomizerKind::TgmHistory,
+        spawn_delay_ms: 500,
+        clear_delay_ms: 683,
+        rotation: RotationSystem::Ars,
+        lock_delay: 0.5,
+        gravity: Some(SpeedTable::Tgm),
+        scoring: Scoring::Guideline,
+        attack: AttackTable::Classic,
+        ..defaults
+      },
+      Preset::Custom => *custom,
+    };
+    Ruleset {
+      previews: ruleset.previews.min(defaults.previews),
+      hold: ruleset.hold && defaults.hold,
+      ghost: base.ghost,
+      mirror: base.mirror,
+      mirror_input: base.mirror_input,
+      ..ruleset
+    }
+  }
+
+  // 今のルールと同じプリセット。どれとも違えば Custom
+  pub fn of(mode: GameMode, ruleset: &Ruleset) -> Preset {
+    Preset::ALL
+      .iter()
+      .copied()
+      .filter(|&p| p != Preset::Custom)
+      .find(|p| p.apply(mode, ruleset, ruleset) == *ruleset)
+      .unwrap_or(Preset::Custom)
+  }
+}
+
+// 保存した Custom のルール。無ければモードの既定値
+fn load_custom(mode: GameMode) -> Ruleset {
+  save::load_shared::<Option<Ruleset>>(CUSTOM_FILE).unwrap_or_else(|| Ruleset::for_mode(mode))
+}
+
+// 遊んでいる途中でかけ外しするルールの修飾
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Modifier {
+  Mirror,
+  HiddenNext,
+  FastGravity,
+  // 落ちているミノを大きく描いて、周りの積みを見えにくくする
+  Giant,
+  InvisibleStack,
+}
+
+impl Modifier {
+  pub const ALL: [Modifier; 5] = [
+    Modifier::Mirror,
+    Modifier::HiddenNext,
+    Modifier::FastGravity,
+    Modifier::Giant,
+    Modifier::InvisibleStack,
+  ];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      Modifier::Mirror => "MIRROR",
+      Modifier::HiddenNext => "NO NEXT",
+      Modifier::FastGravity => "FAST GRAVITY",
+      Modifier::Giant => "GIANT PIECES",
+      Modifier::InvisibleStack => "INVISIBLE STACK",
+    }
+  }
+
+  // Ruleset の値で表せるものはここで書き換える。他は Modifiers を見て各システムが扱う
+  fn apply(self, base: Ruleset) -> Ruleset {
+    match self {
+      // 見た目どおりに動かせるように入力も一緒に反転する
+      Modifier::Mirror => Ruleset {
+        mirror: !base.mirror,
+        mirror_input: !base.mirror_input,
+        ..base
+      },
+      Modifier::HiddenNext => Ruleset {
+        previews: 0,
+        ..base
+      },
+      _ => base,
+    }
+  }
+}
+
+// 今かかっている修飾と、かける前のルール
+#[derive(Default)]
+pub struct Modifiers {
+  pub active: Option<Modifier>,
+  base: Option<Ruleset>,
+}
+
+impl Modifiers {
+  // 前の修飾を外してから次をかける。None なら元のルールに戻す
+  pub fn switch(&mut self, ruleset: &mut Ruleset, next: Option<Modifier>) {
+    let base = *self.base.get_or_insert(*ruleset);
+    *ruleset = match next {
+      Some(modifier) => modifier.apply(base),
+      None => base,
+    };
+    if next.is_none() {
+      self.base = None;
+    }
+    self.active = next;
+  }
+
+  pub fn is_on(&self, modifier: Modifier) -> bool {
+    self.active == Some(modifier)
+  }
+
+  // 重力 (1 tick に落ちる段数) にかける倍率
+  pub fn gravity_scale(&self) -> f32 {
+    if self.is_on(Modifier::FastGravity) {
+      4.
+    } else {
+      1.
+    }
+  }
+
+  // 落ちているミノを描く大きさの倍率
+  pub fn piece_scale(&self) -> f32 {
+    if self.is_on(Modifier::Giant) {
+      1.8
+    } else {
+      1.
+    }
+  }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct RulesSave {
+  rules: BTreeMap<String, Ruleset>,
+}
+
+fn mode_key(mode: GameMode) -> String {
+  format!("{:?}", mode)
+}
+
+struct SetupText;
+
+pub struct RulesPlugin;
+
+impl Plugin for RulesPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(Modifiers::default())
+      .add_system_set(SystemSet::on_exit(AppState::Title).with_system(reload_rules.system()))
+      .add_system_set(SystemSet::on_enter(AppState::Results).with_system(clear_modifiers.system()))
+      .add_system_set(SystemSet::on_enter(AppState::Setup).with_system(setup_screen.system()))
+      .add_system_set(SystemSet::on_update(AppState::Setup).with_system(setup_input.system()))
+      .add_system_set(
+        SystemSet::on_exit(AppState::Setup)
+          .with_system(close_setup.system())
+          .with_system(apply_rules.system()),
+      );
+  }
+}
+
+// 前回そのモードで使った設定。無ければモードの既定値
+pub fn load_ruleset(mode: GameMode) -> Ruleset {
+  save::load::<RulesSave>(SAVE_FILE)
+    .rules
+    .get(&mode_key(mode))
+    .copied()
+    .unwrap_or_else(|| Ruleset::for_mode(mode))
+}
+
+// タイトル画面でプロフィールが変わったかもしれないので読み直す
+fn reload_rules(mode: Res<GameMode>, mut ruleset: ResMut<Ruleset>) {
+  *ruleset = load_ruleset(*mode);
+}
+
+// 修飾をかけたまま次のゲームやルール設定に持ち越さない
+fn clear_modifiers(mut modifiers: ResMut<Modifiers>, mut ruleset: ResMut<Ruleset>) {
+  if modifiers.active.is_some() {
+    modifiers.switch(&mut ruleset, None);
+  }
+}
+
+fn setup_screen(mut commands: Commands, fonts: Res<Fonts>) {
+  let mut text = overlay_text(&fonts);
+  text.style.position = Rect {
+    top: Val::Px(200.0),
+    left: Val::Px(40.0),
+    ..Default::default()
+  };
+  commands.spawn_bundle(text).insert(SetupText);
+}
+
+fn setup_input(
+  keyboard_input: Res<Input<KeyCode>>,
+  mode: Res<GameMode>,
+  mut ruleset: ResMut<Ruleset>,
+  mut state: ResMut<State<AppState>>,
+  mut query: Query<&mut Text, With<SetupText>>,
+) {
+  let digits = [
+    KeyCode::Key0,
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+  ];
+  for (n, &key) in digits.iter().enumerate() {
+    if keyboard_input.just_pressed(key) {
+      ruleset.previews = n;
+    }
+  }
+  if keyboard_input.just_pressed(KeyCode::H) {
+    ruleset.hold = !ruleset.hold;
+  }
+  if keyboard_input.just_pressed(KeyCode::G) {
+    ruleset.ghost = !ruleset.ghost;
+  }
+  if keyboard_input.just_pressed(KeyCode::M) {
+    ruleset.mirror = !ruleset.mirror;
+  }
+  if keyboard_input.just_pressed(KeyCode::I) {
+    ruleset.mirror_input = !ruleset.mirror_input;
+  }
+  if keyboard_input.just_pressed(KeyCode::R) {
+    let kinds = RandomizerKind::ALL;
+    let i = kinds.iter().position(|&k| k == ruleset.randomizer).unwrap();
+    ruleset.randomizer = kinds[(i + 1) % kinds.len()];
+  }
+  if keyboard_input.just_pressed(KeyCode::C) {
+    ruleset.drought_cap = next(&DROUGHT_CAPS, ruleset.drought_cap);
+  }
+  if keyboard_input.just_pressed(KeyCode::T) {
+    let i = Timing::of(&ruleset)
+      .and_then(|t| Timing::ALL.iter().position(|&k| k == t))
+      .map_or(0, |i| i + 1);
+    let (spawn_delay_ms, clear_delay_ms) = Timing::ALL[i % Timing::ALL.len()].delays();
+    ruleset.spawn_delay_ms = spawn_delay_ms;
+    ruleset.clear_delay_ms = clear_delay_ms;
+  }
+  if keyboard_input.just_pressed(KeyCode::O) {
+    ruleset.rotation = next(&RotationSystem::ALL, ruleset.rotation);
+  }
+  if keyboard_input.just_pressed(KeyCode::L) {
+    ruleset.lock_delay = next(&LOCK_DELAYS, ruleset.lock_delay);
+  }
+  if keyboard_input.just_pressed(KeyCode::V) {
+    let tables: Vec<Option<SpeedTable>> = std::iter::once(None)
+      .chain(SpeedTable::ALL.iter().copied().map(Some))
+      .collect();
+    ruleset.gravity = next(&tables, ruleset.gravity);
+  }
+  if keyboard_input.just_pressed(KeyCode::S) {
+    ruleset.scoring = next(&Scoring::ALL, ruleset.scoring);
+  }
+  if keyboard_input.just_pressed(KeyCode::A) {
+    ruleset.attack = next(&AttackTable::ALL, ruleset.attack);
+  }
+  if keyboard_input.just_pressed(KeyCode::P) {
+    let preset = next(&Preset::ALL, Preset::of(*mode, &ruleset));
+    *ruleset = preset.apply(*mode, &ruleset, &load_custom(*mode));
+  }
+  if keyboard_input.just_pressed(KeyCode::D) {
+    *ruleset = Ruleset::for_mode(*mode);
+  }
+
+  let preset = Preset::of(*mode, &ruleset);
+  if keyboard_input.just_pressed(KeyCode::Return) {
+    let mut data = save::load::<RulesSave>(SAVE_FILE);
+    data.rules.insert(mode_key(*mode), *ruleset);
+    if let Err(e) = save::store(SAVE_FILE, &data) {
+      error!("failed to save rules: {}", e);
+    }
+    // どのプリセットとも違うルールは Custom として次から選べるようにする
+    if preset == Preset::Custom {
+      if let Err(e) = save::store_shared(CUSTOM_FILE, &Some(*ruleset)) {
+        error!("failed to save custom rules: {}", e);
+      }
+    }
+    state.set(AppState::Playing).unwrap();
+    return;
+  }
+
+  let on_off = |b: bool| if b { "on" } else { "off" };
+  let timing = Timing::of(&ruleset).map_or("custom", Timing::name);
+  let gravity = ruleset.gravity.map_or("config", SpeedTable::name);
+  let value = format!(
+    "{:?} RULES  preset: {}\n\npreviews: {}\nhold: {}\nghost: {}\nrandomizer: {}\ndrought cap: {}\nmirror: board {} / input {}\ndelays: {} (ARE {} / line clear {} ms)\nrotation: {}\nlock delay: {:.2}s\ngravity: {}\nscoring: {}\nattack: {}\n\n[P] preset  [0-{}] previews  [H] hold  [G] ghost\n[M] mirror board  [I] mirror input  [R] randomizer  [C] drought cap  [T] delays\n[O] rotation  [L] lock delay  [V] gravity  [S] scoring  [A] attack\n[D] defaults  [Enter] start\ncustom rules are saved when you start",
+    *mode,
+    preset.name(),
+    ruleset.previews,
+    on_off(ruleset.hold),
+    on_off(ruleset.ghost),
+    ruleset.randomizer.name(),
+    ruleset
+      .drought_cap
+      .map_or("off".to_string(), |cap| format!("I every {}", cap)),
+    on_off(ruleset.mirror),
+    on_off(ruleset.mirror_input),
+    timing,
+    ruleset.spawn_delay_ms,
+    ruleset.clear_delay_ms,
+    ruleset.rotation.name(),
+    ruleset.lock_delay,
+    gravity,
+    ruleset.scoring.name(),
+    ruleset.attack.name(),
+    MAX_PREVIEWS
+  );
+  for mut text in query.iter_mut() {
+    text.sections[0].value = value.clone();
+  }
+}
+
+// 並びの次の値。並びに無い値なら最初
+fn next<T: Copy + PartialEq>(values: &[T], current: T) -> T {
+  let i = values
+    .iter()
+    .position(|&v| v == current)
+    .map_or(0, |i| i + 1);
+  values[i % values.len()]
+}
+
+// 設定画面で覗いたキューは捨てて、選んだ方式で引き直す
+fn apply_rules(ruleset: Res<Ruleset>, mut piece_queue: ResMut<PieceQueue>) {
+  piece_queue.reseed(&ruleset);
+}
+
+fn close_setup(mut commands: Commands, query: Query<Entity, With<SetupText>>) {
+  for entity in query.iter() {
+    commands.entity(entity).despawn();
+  }
+}
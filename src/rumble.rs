@@ -0,0 +1,119 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use gilrs::ff::{BaseEffect, BaseEffectType, Effect, EffectBuilder, Repeat, Replay, Ticks};
+use gilrs::Gilrs;
+
+use crate::config::Config;
+
+// コントローラーを振動させるきっかけ
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Pulse {
+  HardDrop,
+  // 消したライン数。4 ラインは一番強い
+  Clear(u32),
+  // せり上がった段数
+  Garbage(u32),
+}
+
+impl Pulse {
+  // 設定の強さを掛ける前の強さ (0.0 から 1.0) と長さ (ミリ秒)
+  pub fn shape(&self) -> (f32, u32) {
+    match *self {
+      Pulse::HardDrop => (0.25, 60),
+      Pulse::Clear(lines) if lines >= 4 => (1.0, 250),
+      Pulse::Clear(lines) => (0.3 + 0.1 * lines as f32, 120),
+      Pulse::Garbage(rows) => ((0.3 + 0.1 * rows as f32).min(0.8), 150),
+    }
+  }
+}
+
+// 振動の強さを gilrs のモーターの強さにする
+pub fn pulse_magnitude(pulse: Pulse, intensity: f32) -> u16 {
+  let (strength, _) = pulse.shape();
+  (strength * intensity.clamp(0., 1.) * u16::MAX as f32).round() as u16
+}
+
+// このフレームで鳴らす振動。番号は bevy の Gamepad の番号
+#[derive(Default)]
+pub struct Rumble {
+  pending: Vec<(usize, Pulse)>,
+}
+
+impl Rumble {
+  pub fn pulse(&mut self, pad: usize, pulse: Pulse) {
+    self.pending.push((pad, pulse));
+  }
+}
+
+// gilrs は手放した振動をすぐ止めるので、鳴り終わるまで持っておく
+#[derive(Default)]
+struct PlayingEffects(Vec<(Effect, Duration)>);
+
+pub struct RumblePlugin;
+
+impl Plugin for RumblePlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app.insert_resource(Rumble::default());
+    // コントローラーが使えない環境では振動を捨てるだけにする
+    if app.world().get_non_send_resource::<Gilrs>().is_none() {
+      app.add_system(drop_rumble.system());
+      return;
+    }
+    app
+      .insert_non_send_resource(PlayingEffects::default())
+      .add_system(play_rumble.system());
+  }
+}
+
+fn drop_rumble(mut rumble: ResMut<Rumble>) {
+  rumble.pending.clear();
+}
+
+// 振動に対応していないコントローラーや、つながっていない番号は飛ばす
+fn play_rumble(
+  time: Res<Time>,
+  config: Res<Config>,
+  mut rumble: ResMut<Rumble>,
+  mut gilrs: NonSendMut<Gilrs>,
+  mut playing: NonSendMut<PlayingEffects>,
+) {
+  let now = time.time_since_startup();
+  playing.0.retain(|(_, until)| *until > now);
+  let pending = std::mem::take(&mut rumble.pending);
+  if config.rumble.intensity <= 0. {
+    return;
+  }
+  for (pad, pulse) in pending {
+    let id = gilrs
+      .gamepads()
+      .find(|(id, gamepad)| Into::<usize>::into(*id) == pad && gamepad.is_ff_supported())
+      .map(|(id, _)| id);
+    let id = match id {
+      Some(id) => id,
+      None => continue,
+    };
+    let (_, millis) = pulse.shape();
+    let effect = EffectBuilder::new()
+      .add_effect(BaseEffect {
+        kind: BaseEffectType::Strong {
+          magnitude: pulse_magnitude(pulse, config.rumble.intensity),
+        },
+        scheduling: Replay {
+          play_for: Ticks::from_ms(millis),
+          ..Default::default()
+        },
+        envelope: Default::default(),
+      })
+      .repeat(Repeat::For(Ticks::from_ms(millis)))
+      .gamepads(&[id])
+      .finish(&mut gilrs);
+    match effect.and_then(|effect| effect.play().map(|_| effect)) {
+      Ok(effect) => {
+        let until = now + Duration::from_millis(millis as u64);
+        playing.0.push((effect, until));
+      }
+      Err(e) => warn!("rumble on gamepad {}: {}", pad, e),
+    }
+  }
+}
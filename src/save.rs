@@ -0,0 +1,75 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+use ron::ser::PrettyConfig;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+const SAVE_DIR: &str = "save";
+const PROFILE_DIR: &str = "profiles";
+
+lazy_static! {
+  // 選択中のプロフィール。load/store はこのプロフィールのディレクトリを読み書きする
+  static ref PROFILE: RwLock<Option<String>> = RwLock::new(None);
+}
+
+pub fn set_profile(name: &str) {
+  *PROFILE.write().unwrap() = Some(name.to_string());
+}
+
+pub fn profile_dir(name: &str) -> PathBuf {
+  PathBuf::from(SAVE_DIR).join(PROFILE_DIR).join(name)
+}
+
+fn save_dir() -> PathBuf {
+  match PROFILE.read().unwrap().as_ref() {
+    Some(name) => profile_dir(name),
+    None => PathBuf::from(SAVE_DIR),
+  }
+}
+
+fn read<T: DeserializeOwned>(path: PathBuf) -> Option<T> {
+  fs::read_to_string(path)
+    .ok()
+    .and_then(|s| ron::de::from_str(&s).ok())
+}
+
+// 一時ファイルに書いてから置き換えるので、途中で落ちても前の内容が残る
+fn write<T: Serialize>(dir: PathBuf, name: &str, value: &T) -> io::Result<()> {
+  let s = ron::ser::to_string_pretty(value, PrettyConfig::new()).map_err(io::Error::other)?;
+  fs::create_dir_all(&dir)?;
+  let tmp = dir.join(format!("{}.tmp", name));
+  fs::write(&tmp, s)?;
+  fs::rename(tmp, dir.join(name))
+}
+
+// `save/profiles/<profile>/<name>` を読み込む。ファイルが無い・壊れている場合はデフォルト値
+pub fn load<T: DeserializeOwned + Default>(name: &str) -> T {
+  load_opt(name).unwrap_or_default()
+}
+
+pub fn load_opt<T: DeserializeOwned>(name: &str) -> Option<T> {
+  read(save_dir().join(name))
+}
+
+pub fn store<T: Serialize>(name: &str, value: &T) -> io::Result<()> {
+  write(save_dir(), name, value)
+}
+
+pub fn remove(name: &str) -> io::Result<()> {
+  match fs::remove_file(save_dir().join(name)) {
+    Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+    _ => Ok(()),
+  }
+}
+
+// ウィンドウの設定など、プロフィールによらない `save/<name>`
+pub fn load_shared<T: DeserializeOwned + Default>(name: &str) -> T {
+  read(PathBuf::from(SAVE_DIR).join(name)).unwrap_or_default()
+}
+
+pub fn store_shared<T: Serialize>(name: &str, value: &T) -> io::Result<()> {
+  write(PathBuf::from(SAVE_DIR), name, value)
+}
@@ -0,0 +1,330 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::attack::{choose_target, AttackTracker, Targeting};
+use crate::garbage::{GarbageConfig, GarbageGenerator, GarbageQueue};
+use crate::protocol::Snapshot;
+use crate::randomizer::{PieceQueue, RandomizerKind};
+use crate::sim::{Action, GameState};
+use crate::victory::{LastStanding, MatchView, Standing, VictoryCondition};
+
+// 次に出すミノをいくつ先まで用意しておくか
+const QUEUE_LEN: usize = 5;
+// 1マス落ちるまでの秒数
+const GRAVITY: f32 = 0.8;
+// 1人が tick の間に送れる操作の数。これより速いのは人の手ではない
+pub const MAX_INPUTS_PER_TICK: u32 = 10;
+
+// 対戦中の出来事。頭から当て直せば同じ盤面になる
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum MatchEvent {
+  // 手元から届いた操作と、重力でサーバーが動かした分
+  Action { seat: usize, action: Action },
+  Garbage { seat: usize, holes: Vec<i32> },
+  // 接続が切れて負けになった
+  Forfeit { seat: usize },
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct MatchRecord {
+  pub seed: u64,
+  pub players: usize,
+  // 始まってからの秒数と出来事
+  pub events: Vec<(f32, MatchEvent)>,
+  // 決着が付いたときの 1 位から順の並び
+  pub placings: Vec<usize>,
+}
+
+struct Seat {
+  state: GameState,
+  // state.queue より先のミノ
+  pieces: PieceQueue,
+  garbage: GarbageGenerator,
+  tracker: AttackTracker,
+  gravity: f32,
+  incoming: GarbageQueue,
+  sent: u32,
+  tetrises: u32,
+  last_attacker: Option<usize>,
+  // この tick に受け付けた操作の数
+  inputs: u32,
+}
+
+impl Seat {
+  // ミノの並びは全員同じ、穴の位置は席ごとに変える
+  fn new(seed: u64, seat: usize) -> Self {
+    let mut pieces = PieceQueue::new(RandomizerKind::Bag.build(seed));
+    let queue: Vec<u32> = (0..=QUEUE_LEN).map(|_| pieces.pop()).collect();
+    let garbage_seed = seed.wrapping_add(seat as u64 + 1);
+    Seat {
+      state: GameState::new(queue),
+      pieces,
+      garbage: GarbageGenerator::new(&GarbageConfig::default(), garbage_seed),
+      tracker: AttackTracker::default(),
+      gravity: 0.,
+      incoming: GarbageQueue::default(),
+      sent: 0,
+      tetrises: 0,
+      last_attacker: None,
+      inputs: 0,
+    }
+  }
+
+  fn apply(&mut self, action: Action) {
+    self.state = self.state.apply(action);
+    while self.state.queue.len() < QUEUE_LEN {
+      let next = self.pieces.pop();
+      self.state.queue.push_back(next);
+    }
+  }
+
+  // 次に出てくるミノを n 個。見えている state.queue より先も覗ける
+  fn preview(&mut self, n: usize) -> Vec<u32> {
+    let extra = self.pieces.peek(n.saturating_sub(self.state.queue.len()));
+    self
+      .state
+      .queue
+      .iter()
+      .copied()
+      .chain(extra)
+      .take(n)
+      .collect()
+  }
+}
+
+// 対戦サーバーが進める対戦。盤面はすべてここで動かし、手元からは操作だけ受け取る
+pub struct Match {
+  seats: Vec<Seat>,
+  // 攻撃の送り先を選ぶ
+  rng: StdRng,
+  elapsed: f32,
+  // 脱落した順
+  eliminated: Vec<usize>,
+  condition: Box<dyn VictoryCondition>,
+  record: MatchRecord,
+}
+
+impl Match {
+  pub fn new(players: usize, seed: u64) -> Self {
+    Self::with_condition(players, seed, Box::new(LastStanding))
+  }
+
+  pub fn with_condition(players: usize, seed: u64, condition: Box<dyn VictoryCondition>) -> Self {
+    Match {
+      seats: (0..players).map(|i| Seat::new(seed, i)).collect(),
+      rng: StdRng::seed_from_u64(seed),
+      elapsed: 0.,
+      eliminated: vec![],
+      condition,
+      record: MatchRecord {
+        seed,
+        players,
+        events: vec![],
+        placings: vec![],
+      },
+    }
+  }
+
+  pub fn players(&self) -> usize {
+    self.seats.len()
+  }
+
+  pub fn is_over(&self) -> bool {
+    !self.record.placings.is_empty()
+  }
+
+  pub fn record(&self) -> &MatchRecord {
+    &self.record
+  }
+
+  pub fn elapsed(&self) -> f32 {
+    self.elapsed
+  }
+
+  pub fn state(&self, seat: usize) -> &GameState {
+    &self.seats[seat].state
+  }
+
+  // 次のミノの表示とボットの先読みはどちらもここから取る
+  pub fn preview(&mut self, seat: usize, n: usize) -> Vec<u32> {
+    self.seats[seat].preview(n)
+  }
+
+  // 相殺されずに次に積んだときせり上がる分
+  pub fn incoming(&self, seat: usize) -> u32 {
+    self.seats[seat].incoming.rows()
+  }
+
+  // 手元から届いた操作。脱落した席からのものや、速すぎるものは受け付けない
+  pub fn input(&mut self, seat: usize, action: Action) -> Result<(), String> {
+    if self.is_over() {
+      return Err("the match is over".to_string());
+    }
+    let player = self
+      .seats
+      .get_mut(seat)
+      .ok_or_else(|| format!("no seat {}", seat))?;
+    if player.state.game_over {
+      return Err(format!("seat {} is already out", seat));
+    }
+    if player.inputs >= MAX_INPUTS_PER_TICK {
+      return Err(format!("seat {} sent too many inputs", seat));
+    }
+    player.inputs += 1;
+    // 自分で落とした分だけ重力を待ち直す
+    if action == Action::SoftDrop {
+      player.gravity = 0.;
+    }
+    self.act(seat, action);
+    self.check();
+    Ok(())
+  }
+
+  pub fn forfeit(&mut self, seat: usize) {
+    if self.is_over() || self.seats[seat].state.game_over {
+      return;
+    }
+    self.seats[seat].state.game_over = true;
+    self
+      .record
+      .events
+      .push((self.elapsed, MatchEvent::Forfeit { seat }));
+    self.check();
+  }
+
+  // 重力で落として、決着を確かめる
+  pub fn tick(&mut self, dt: f32) {
+    if self.is_over() {
+      return;
+    }
+    self.elapsed += dt;
+    for seat in 0..self.seats.len() {
+      let player = &mut self.seats[seat];
+      player.inputs = 0;
+      if player.state.game_over {
+        continue;
+      }
+      player.gravity += dt;
+      if player.gravity < GRAVITY {
+        continue;
+      }
+      player.gravity -= GRAVITY;
+      // 落ちられなければその場で固定する
+      let active = player.state.active.clone();
+      self.act(seat, Action::SoftDrop);
+      if self.seats[seat].state.active == active {
+        self.act(seat, Action::HardDrop);
+      }
+    }
+    self.check();
+  }
+
+  pub fn snapshot(&self, seat: usize) -> Snapshot {
+    let player = &self.seats[seat];
+    Snapshot {
+      pieces: player.state.pieces,
+      lines: player.state.lines,
+      tetrises: player.tetrises,
+      sent: player.sent,
+      game_over: player.state.game_over,
+      rows: player.state.board.rows().to_vec(),
+      active: player.state.active.as_ref().map(|piece| {
+        let cells = piece.cells.iter().map(|p| (p.x, p.y)).collect();
+        (piece.block_idx, cells)
+      }),
+    }
+  }
+
+  fn act(&mut self, seat: usize, action: Action) {
+    self
+      .record
+      .events
+      .push((self.elapsed, MatchEvent::Action { seat, action }));
+    let player = &mut self.seats[seat];
+    let before = player.state.pieces;
+    let lines_before = player.state.lines;
+    player.apply(action);
+    if player.state.pieces == before {
+      return;
+    }
+    player.gravity = 0.;
+    player.tracker.record_lock();
+    let lines = player.state.lines - lines_before;
+    if lines == 0 {
+      // ラインを消さずに積んだので、来ている分をせり上げる
+      let rows = player.incoming.take();
+      if rows == 0 {
+        return;
+      }
+      let holes: Vec<i32> = (0..rows).map(|_| player.garbage.next_hole()).collect();
+      player.state.receive_garbage(&holes);
+      self
+        .record
+        .events
+        .push((self.elapsed, MatchEvent::Garbage { seat, holes }));
+      return;
+    }
+    let clear = player.tracker.record_clear(lines);
+    if lines >= 4 {
+      player.tetrises += 1;
+    }
+    // 攻撃はまず来ているせり上がりの相殺に使う
+    let sent = player.incoming.cancel(clear.attack);
+    if sent > 0 {
+      self.send(seat, sent);
+    }
+  }
+
+  fn send(&mut self, from: usize, amount: u32) {
+    let alive: Vec<bool> = self.seats.iter().map(|s| !s.state.game_over).collect();
+    let lines: Vec<u32> = self.seats.iter().map(|s| s.state.lines).collect();
+    let roll = self.rng.gen_range(0..self.seats.len());
+    let last_attacker = self.seats[from].last_attacker;
+    if let Some(to) = choose_target(Targeting::Random, from, &alive, &lines, last_attacker, roll) {
+      self.seats[from].sent += amount;
+      self.seats[to].incoming.push(amount);
+      self.seats[to].last_attacker = Some(from);
+    }
+  }
+
+  fn check(&mut self) {
+    for i in 0..self.seats.len() {
+      if self.seats[i].state.game_over && !self.eliminated.contains(&i) {
+        self.eliminated.push(i);
+      }
+    }
+    let standings: Vec<Standing> = self
+      .seats
+      .iter()
+      .map(|s| Standing {
+        alive: !s.state.game_over,
+        lines: s.state.lines,
+        tetrises: s.tetrises,
+      })
+      .collect();
+    let view = MatchView {
+      elapsed: self.elapsed,
+      standings: &standings,
+      eliminated: &self.eliminated,
+    };
+    if let Some(placings) = self.condition.check(&view) {
+      self.record.placings = placings;
+    }
+  }
+}
+
+// 記録を頭から当て直した、最後の盤面
+pub fn replay(record: &MatchRecord) -> Vec<GameState> {
+  let mut seats: Vec<Seat> = (0..record.players)
+    .map(|i| Seat::new(record.seed, i))
+    .collect();
+  for (_, event) in record.events.iter() {
+    match event {
+      MatchEvent::Action { seat, action } => seats[*seat].apply(*action),
+      MatchEvent::Garbage { seat, holes } => seats[*seat].state.receive_garbage(holes),
+      MatchEvent::Forfeit { seat } => seats[*seat].state.game_over = true,
+    }
+  }
+  seats.into_iter().map(|s| s.state).collect()
+}
@@ -0,0 +1,165 @@
+use std::convert::TryFrom;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use miniz_oxide::deflate::compress_to_vec;
+use miniz_oxide::inflate::decompress_to_vec;
+
+use tetris::wire::{write_varint, Reader};
+
+use crate::eventlog::{clear_kind, GameLog, LogEvent, LogRecord, Note};
+use crate::speed::TickRate;
+
+// 形式を変えたら上げる。2 でメモを末尾に足した。3 で tick の刻みを足した。4 で自然落下を足した
+const VERSION: u8 = 4;
+// チャットに貼ったときに分かるように付ける
+const PREFIX: &str = "tetris:";
+// チャットに貼りにくい環境向けに、書き出した文字列をファイルにも置く
+pub const SHARE_FILE: &str = "logs/share.txt";
+
+const SPAWN: u8 = 0;
+const MOVE: u8 = 1;
+const ROTATE: u8 = 2;
+const LOCK: u8 = 3;
+const CLEAR: u8 = 4;
+const FALL: u8 = 5;
+
+// リプレイを短い文字列にする。時刻はミリ秒単位の差分で持ち、圧縮して base64 にする
+pub fn encode(log: &GameLog) -> String {
+  let mut out = vec![VERSION];
+  let mode = log.mode.as_bytes();
+  out.push(mode.len().min(u8::MAX as usize) as u8);
+  out.extend_from_slice(&mode[..mode.len().min(u8::MAX as usize)]);
+  write_varint(&mut out, log.started_at);
+  write_varint(&mut out, log.seed);
+  write_varint(&mut out, log.events.len() as u64);
+
+  let mut last = 0;
+  for record in log.events.iter() {
+    let ms = (record.time * 1000.).round().max(0.) as u64;
+    write_varint(&mut out, ms.saturating_sub(last));
+    last = last.max(ms);
+    match &record.event {
+      LogEvent::Spawn { piece } => out.extend_from_slice(&[SPAWN, *piece as u8]),
+      LogEvent::Move { dx, dy } => out.extend_from_slice(&[MOVE, *dx as u8, *dy as u8]),
+      LogEvent::Rotate { from, to, kick } => {
+        out.extend_from_slice(&[ROTATE, *from as u8, *to as u8, kick.0 as u8, kick.1 as u8])
+      }
+      LogEvent::Lock { piece, cells } => {
+        out.extend_from_slice(&[LOCK, *piece as u8, cells.len() as u8]);
+        for &(x, y) in cells.iter() {
+          out.extend_from_slice(&[x as u8, y as u8]);
+        }
+      }
+      LogEvent::Clear { lines, .. } => out.extend_from_slice(&[CLEAR, *lines as u8]),
+      LogEvent::Fall => out.push(FALL),
+    }
+  }
+  write_varint(&mut out, log.notes.len() as u64);
+  for note in log.notes.iter() {
+    write_varint(&mut out, (note.time * 1000.).round().max(0.) as u64);
+    write_varint(&mut out, note.text.len() as u64);
+    out.extend_from_slice(note.text.as_bytes());
+  }
+  write_varint(&mut out, log.tick_rate.hz() as u64);
+  format!(
+    "{}{}",
+    PREFIX,
+    base64::encode_config(compress_to_vec(&out, 9), base64::URL_SAFE_NO_PAD)
+  )
+}
+
+pub fn decode(src: &str) -> Result<GameLog, String> {
+  // 貼り付けで入った空白や改行は無視する
+  let src: String = src.chars().filter(|c| !c.is_whitespace()).collect();
+  let body = src.strip_prefix(PREFIX).unwrap_or(&src);
+  let compressed =
+    base64::decode_config(body, base64::URL_SAFE_NO_PAD).map_err(|e| e.to_string())?;
+  let bytes = decompress_to_vec(&compressed).map_err(|e| format!("{:?}", e))?;
+
+  let mut reader = Reader::new(&bytes);
+  let version = reader.byte()?;
+  if version == 0 || version > VERSION {
+    return Err(format!("unsupported version {}", version));
+  }
+  let len = reader.byte()? as usize;
+  let mode = String::from_utf8(reader.take(len)?.to_vec()).map_err(|e| e.to_string())?;
+  let started_at = reader.varint()?;
+  let seed = reader.varint()?;
+  let count = reader.varint()?;
+
+  let mut log = GameLog::default();
+  log.mode = mode;
+  log.started_at = started_at;
+  log.seed = seed;
+  let mut ms: u64 = 0;
+  for _ in 0..count {
+    ms = ms.checked_add(reader.varint()?).ok_or("bad event time")?;
+    let event = match reader.byte()? {
+      SPAWN => LogEvent::Spawn {
+        piece: reader.byte()? as char,
+      },
+      MOVE => LogEvent::Move {
+        dx: reader.signed()?,
+        dy: reader.signed()?,
+      },
+      ROTATE => LogEvent::Rotate {
+        from: reader.byte()? as char,
+        to: reader.byte()? as char,
+        kick: (reader.signed()?, reader.signed()?),
+      },
+      LOCK => {
+        let piece = reader.byte()? as char;
+        let n = reader.byte()?;
+        let cells = (0..n)
+          .map(|_| Ok((reader.signed()?, reader.signed()?)))
+          .collect::<Result<Vec<_>, String>>()?;
+        LogEvent::Lock { piece, cells }
+      }
+      CLEAR => {
+        let lines = reader.byte()? as u32;
+        LogEvent::Clear {
+          lines,
+          kind: clear_kind(lines).to_string(),
+        }
+      }
+      FALL => LogEvent::Fall,
+      tag => return Err(format!("unknown event {}", tag)),
+    };
+    log.events.push(LogRecord {
+      time: ms as f64 / 1000.,
+      event,
+    });
+  }
+  // 1 のときはメモが無い
+  let notes = if version >= 2 { reader.varint()? } else { 0 };
+  for _ in 0..notes {
+    let time = reader.varint()? as f64 / 1000.;
+    let len = reader.varint()? as usize;
+    let text = String::from_utf8(reader.take(len)?.to_vec()).map_err(|e| e.to_string())?;
+    log.notes.push(Note { time, text });
+  }
+  // 2 までは 60 Hz しか無かった
+  if version >= 3 {
+    let hz = reader.varint()?;
+    log.tick_rate = u32::try_from(hz)
+      .ok()
+      .and_then(TickRate::from_hz)
+      .ok_or_else(|| format!("unsupported tick rate {}", hz))?;
+  }
+  Ok(log)
+}
+
+pub fn write_file(code: &str) -> io::Result<PathBuf> {
+  let path = PathBuf::from(SHARE_FILE);
+  if let Some(dir) = path.parent() {
+    fs::create_dir_all(dir)?;
+  }
+  fs::write(&path, code)?;
+  Ok(path)
+}
+
+pub fn read_file() -> io::Result<String> {
+  fs::read_to_string(SHARE_FILE)
+}
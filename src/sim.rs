@@ -0,0 +1,199 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::board::{fnv1a, Board};
+use crate::rotation::{rotate, KickTable};
+use crate::{spawn_cells, Position, ARENA_HEIGHT};
+
+lazy_static! {
+  static ref SRS: KickTable = KickTable::srs();
+}
+
+// 1回の操作。重力や固定までの猶予は無く、HardDrop で固定する
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Action {
+  Left,
+  Right,
+  SoftDrop,
+  HardDrop,
+  RotateCw,
+  RotateCcw,
+  Hold,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Piece {
+  pub block_idx: u32,
+  pub rotation: u8,
+  pub cells: Vec<Position>,
+}
+
+// 盤面の上でミノを 1 回動かす。固定やホールドのように盤面が変わる操作と、動けないときは None
+pub fn move_piece(board: &Board, piece: &Piece, action: Action) -> Option<Piece> {
+  match action {
+    Action::Left | Action::Right => {
+      let dx = if action == Action::Left { -1 } else { 1 };
+      let cells = board.shift(&piece.cells, dx)?;
+      Some(Piece {
+        cells,
+        ..piece.clone()
+      })
+    }
+    Action::SoftDrop => {
+      let cells: Vec<Position> = piece
+        .cells
+        .iter()
+        .map(|p| Position { x: p.x, y: p.y - 1 })
+        .collect();
+      if !board.fits(&cells) {
+        return None;
+      }
+      Some(Piece {
+        cells,
+        ..piece.clone()
+      })
+    }
+    Action::RotateCw | Action::RotateCcw => {
+      let clockwise = action == Action::RotateCw;
+      let (cells, rotation, _) = rotate(
+        board,
+        &SRS,
+        &piece.cells,
+        piece.block_idx,
+        piece.rotation,
+        clockwise,
+      )?;
+      Some(Piece {
+        block_idx: piece.block_idx,
+        rotation,
+        cells,
+      })
+    }
+    Action::HardDrop | Action::Hold => None,
+  }
+}
+
+// Bevy を通さずにゲームを進めるための状態。解析ツールやボット、テストから使う
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct GameState {
+  pub board: Board,
+  pub active: Option<Piece>,
+  pub hold: Option<u32>,
+  pub hold_used: bool,
+  // これから出てくるミノ。乱数は使わないので呼ぶ側で用意する
+  pub queue: VecDeque<u32>,
+  pub pieces: u32,
+  pub lines: u32,
+  pub game_over: bool,
+}
+
+impl GameState {
+  pub fn new(queue: impl IntoIterator<Item = u32>) -> Self {
+    let mut state = GameState {
+      board: Board::default(),
+      active: None,
+      hold: None,
+      hold_used: false,
+      queue: queue.into_iter().collect(),
+      pieces: 0,
+      lines: 0,
+      game_over: false,
+    };
+    state.spawn_next();
+    state
+  }
+
+  // 操作を1つ適用した次の状態。できない操作なら何も変わらない
+  pub fn apply(&self, action: Action) -> GameState {
+    let mut next = self.clone();
+    let active = match self.active.clone() {
+      Some(active) if !self.game_over => active,
+      _ => return next,
+    };
+
+    match action {
+      Action::Left | Action::Right | Action::SoftDrop | Action::RotateCw | Action::RotateCcw => {
+        if let Some(piece) = move_piece(&next.board, &active, action) {
+          next.active = Some(piece);
+        }
+      }
+      Action::HardDrop => {
+        let cells = next.board.drop(&active.cells);
+        next.board.place(&cells);
+        next.lines += next.board.clear_full_rows();
+        next.pieces += 1;
+        next.hold_used = false;
+        next.spawn_next();
+      }
+      Action::Hold => {
+        if next.hold_used {
+          return next;
+        }
+        // 初めてのホールドはキューから次のミノを出す
+        let held = next.hold.replace(active.block_idx);
+        match held {
+          Some(idx) => next.spawn(idx),
+          None => next.spawn_next(),
+        }
+        next.hold_used = true;
+      }
+    }
+    next
+  }
+
+  // 下からせり上がりを入れる。操作中のミノは重なるなら押し上げ、天井を越えたら負け
+  pub fn receive_garbage(&mut self, holes: &[i32]) {
+    if holes.is_empty() || self.game_over {
+      return;
+    }
+    self.board.add_garbage(holes);
+    if let Some(active) = self.active.as_mut() {
+      while !self.board.fits(&active.cells) {
+        for cell in active.cells.iter_mut() {
+          cell.y += 1;
+        }
+      }
+    }
+    if self.board.max_height() > ARENA_HEIGHT as i32 {
+      self.game_over = true;
+    }
+  }
+
+  fn spawn_next(&mut self) {
+    match self.queue.pop_front() {
+      Some(idx) => self.spawn(idx),
+      None => self.active = None,
+    }
+  }
+
+  fn spawn(&mut self, block_idx: u32) {
+    let cells = spawn_cells(block_idx);
+    if !self.board.fits(&cells) {
+      self.game_over = true;
+    }
+    self.active = Some(Piece {
+      block_idx,
+      rotation: 0,
+      cells,
+    });
+  }
+
+  // 盤面に加えて操作中のミノとホールドも含めたハッシュ。キューは含めない
+  pub fn stable_hash(&self) -> u64 {
+    let mut bytes = self.board.stable_hash().to_le_bytes().to_vec();
+    if let Some(active) = self.active.as_ref() {
+      bytes.push(active.block_idx as u8);
+      bytes.push(active.rotation);
+      let mut cells: Vec<(i32, i32)> = active.cells.iter().map(|p| (p.x, p.y)).collect();
+      cells.sort_unstable();
+      for (x, y) in cells {
+        bytes.extend_from_slice(&x.to_le_bytes());
+        bytes.extend_from_slice(&y.to_le_bytes());
+      }
+    }
+    bytes.push(self.hold.map_or(0, |idx| idx as u8));
+    bytes.push(self.hold_used as u8);
+    fnv1a(bytes)
+  }
+}
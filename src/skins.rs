@@ -0,0 +1,150 @@
+use std::fs;
+use std::path::Path;
+
+use bevy::prelude::*;
+use bevy::render::texture::ImageType;
+use serde::Deserialize;
+
+// 同梱の assets とは別に、プレイヤーが入れたブロックの見た目を置く場所
+pub const SKIN_DIR: &str = "skins";
+// 作り直したスキンをゲームを止めずに読み直す
+const REFRESH_KEY: KeyCode = KeyCode::F5;
+
+// skins/<名前>.ron。色は (r, g, b) で 0.0 から 1.0
+#[derive(Clone, PartialEq, Debug, Deserialize)]
+pub struct SkinFile {
+  pub active: (f32, f32, f32),
+  pub stacked: (f32, f32, f32),
+  #[serde(default)]
+  pub background: Option<(f32, f32, f32)>,
+  // skins/ からの相対パスの PNG。色と掛け合わせて貼るので、画像の色のままなら (1.0, 1.0, 1.0)
+  #[serde(default)]
+  pub texture: Option<String>,
+}
+
+pub fn parse_skin(text: &str) -> Result<SkinFile, String> {
+  ron::de::from_str(text).map_err(|e| e.to_string())
+}
+
+fn rgb((r, g, b): (f32, f32, f32)) -> Color {
+  Color::rgb(r, g, b)
+}
+
+pub struct UserSkin {
+  pub name: String,
+  pub active: Color,
+  pub stacked: Color,
+  pub background: Option<Color>,
+  pub texture: Option<Handle<Texture>>,
+}
+
+// skins/ から読み込んだスキン (名前順)
+#[derive(Default)]
+pub struct SkinLibrary {
+  pub skins: Vec<UserSkin>,
+}
+
+impl SkinLibrary {
+  pub fn get(&self, name: &str) -> Option<&UserSkin> {
+    self.skins.iter().find(|skin| skin.name == name)
+  }
+
+  pub fn names(&self) -> Vec<String> {
+    self.skins.iter().map(|skin| skin.name.clone()).collect()
+  }
+}
+
+// 選ばないところから順に、次のスキンの名前。最後の次は選ばない
+pub fn next_skin(names: &[String], current: Option<&str>) -> Option<String> {
+  // 選んでいたスキンが消えていたら最初から
+  let next = current
+    .and_then(|name| names.iter().position(|n| n == name))
+    .map_or(0, |i| i + 1);
+  names.get(next).cloned()
+}
+
+// 壊れたファイルは飛ばして、読めたものだけを返す
+fn read_skins(dir: &Path) -> Vec<(String, SkinFile)> {
+  let mut paths: Vec<_> = match fs::read_dir(dir) {
+    Ok(entries) => entries
+      .filter_map(|entry| entry.ok())
+      .map(|entry| entry.path())
+      .filter(|path| path.extension().and_then(|ext| ext.to_str()) == Some("ron"))
+      .collect(),
+    Err(_) => vec![],
+  };
+  paths.sort();
+
+  paths
+    .iter()
+    .filter_map(|path| {
+      let name = path.file_stem()?.to_str()?.to_string();
+      let skin = fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|s| parse_skin(&s));
+      match skin {
+        Ok(skin) => Some((name, skin)),
+        Err(e) => {
+          warn!("skipping skin {}: {}", path.display(), e);
+          None
+        }
+      }
+    })
+    .collect()
+}
+
+// AssetServer は一度読んだ画像を読み直さないので、ファイルから直接作る
+fn read_texture(path: &Path) -> Result<Texture, String> {
+  let bytes = fs::read(path).map_err(|e| e.to_string())?;
+  let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+  Texture::from_buffer(&bytes, ImageType::Extension(extension)).map_err(|e| e.to_string())
+}
+
+pub struct SkinsPlugin;
+
+impl Plugin for SkinsPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(SkinLibrary::default())
+      .add_system(load_skins.system());
+  }
+}
+
+// 起動したときと F5 を押したときに skins/ を読み直す
+fn load_skins(
+  mut loaded: Local<bool>,
+  keyboard_input: Res<Input<KeyCode>>,
+  mut library: ResMut<SkinLibrary>,
+  mut textures: ResMut<Assets<Texture>>,
+) {
+  if *loaded && !keyboard_input.just_pressed(REFRESH_KEY) {
+    return;
+  }
+  *loaded = true;
+  let dir = Path::new(SKIN_DIR);
+  let skins = read_skins(dir)
+    .into_iter()
+    .map(|(name, file)| {
+      let texture = file.texture.as_ref().and_then(|texture| {
+        let path = dir.join(texture);
+        match read_texture(&path) {
+          Ok(texture) => Some(textures.add(texture)),
+          Err(e) => {
+            warn!("skin {}: cannot load {}: {}", name, path.display(), e);
+            None
+          }
+        }
+      });
+      UserSkin {
+        name,
+        active: rgb(file.active),
+        stacked: rgb(file.stacked),
+        background: file.background.map(rgb),
+        texture,
+      }
+    })
+    .collect();
+  // 前に読んだ画像は、差し替えたマテリアルが手放せば消える
+  library.skins = skins;
+  info!("{} skins in {}/", library.skins.len(), SKIN_DIR);
+}
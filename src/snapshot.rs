@@ -0,0 +1,274 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+
+use crate::board::Board;
+use crate::{
+  block_idx_from_name, block_name, clipboard, overlay_text, ActiveBlock, AppState, Fonts, GameMode,
+  Position, PrimitiveBlock, StackedBlock, ARENA_HEIGHT, ARENA_WIDTH,
+};
+
+// クリップボードに入れられなかったときの置き場所
+pub const BOARD_FILE: &str = "logs/board.txt";
+pub const FILLED: char = 'X';
+pub const EMPTY: char = '.';
+const MESSAGE_SECONDS: f32 = 2.0;
+
+// 盤面を上の行から 1 行ずつの文字にする。積んだマスは X、操作中のミノはその名前の文字
+pub fn board_text(board: &Board, active: &[Position], piece: Option<char>) -> String {
+  let top = active
+    .iter()
+    .map(|p| p.y + 1)
+    .chain(std::iter::once(board.max_height()))
+    .max()
+    .unwrap_or(0)
+    .max(1);
+  let mut lines = vec![];
+  for y in (0..top).rev() {
+    let line: String = (0..ARENA_WIDTH as i32)
+      .map(|x| {
+        let pos = Position { x, y };
+        match piece {
+          Some(name) if active.contains(&pos) => name,
+          _ if board.is_filled(&pos) => FILLED,
+          _ => EMPTY,
+        }
+      })
+      .collect();
+    lines.push(line);
+  }
+  lines.join("\n")
+}
+
+// 盤面の文字から埋まっているマスを読む。fumen の文字列も受け付ける
+pub fn parse_board(text: &str) -> Result<Vec<Position>, String> {
+  let text = text.trim();
+  if let Some(data) = text.strip_prefix("v115@") {
+    return decode_fumen(data);
+  }
+  // チャットのコードブロックの ``` や空行は飛ばす
+  let lines: Vec<&str> = text
+    .lines()
+    .map(str::trim)
+    .filter(|line| !line.is_empty() && !line.starts_with("```"))
+    .collect();
+  if lines.is_empty() {
+    return Err("no board found".to_string());
+  }
+  if lines.len() > ARENA_HEIGHT as usize {
+    return Err(format!(
+      "{} rows, at most {} fit",
+      lines.len(),
+      ARENA_HEIGHT
+    ));
+  }
+  let mut cells = vec![];
+  for (i, line) in lines.iter().enumerate() {
+    let row = i + 1;
+    if line.chars().count() != ARENA_WIDTH as usize {
+      return Err(format!(
+        "row {} is {} wide, expected {}",
+        row,
+        line.chars().count(),
+        ARENA_WIDTH
+      ));
+    }
+    let y = (lines.len() - 1 - i) as i32;
+    for (x, c) in line.chars().enumerate() {
+      match c {
+        EMPTY | '_' => {}
+        FILLED | '#' | 'G' => cells.push(Position { x: x as i32, y }),
+        _ if block_idx_from_name(c).is_some() => cells.push(Position { x: x as i32, y }),
+        _ => return Err(format!("unexpected '{}' in row {}", c, row)),
+      }
+    }
+  }
+  Ok(cells)
+}
+
+const FUMEN_CHARS: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+// fumen の盤面は 10 x 24 (一番下はせり上がりの予告の行)
+const FUMEN_ROWS: usize = 24;
+const FUMEN_BLOCKS: usize = FUMEN_ROWS * 10;
+
+// fumen (v115) の最初のページの盤面だけを読む。ミノの種類は区別せず埋まったマスにする
+pub fn decode_fumen(data: &str) -> Result<Vec<Position>, String> {
+  let values: Vec<u32> = data
+    .chars()
+    .filter(|&c| c != '?')
+    .map(|c| {
+      FUMEN_CHARS
+        .find(c)
+        .map(|i| i as u32)
+        .ok_or_else(|| format!("unexpected '{}' in fumen", c))
+    })
+    .collect::<Result<_, _>>()?;
+  let mut field = [0u32; FUMEN_BLOCKS];
+  let mut index = 0;
+  let mut chunks = values.chunks(2);
+  while index < FUMEN_BLOCKS {
+    let value = match chunks.next() {
+      Some(&[low, high]) => low + high * 64,
+      _ => return Err("fumen ends before the board".to_string()),
+    };
+    // 前のページとの差分 (最初のページは空の盤面から) と、同じ値が続くマスの数
+    let block = (value / FUMEN_BLOCKS as u32) as i32 - 8;
+    let run = (value % FUMEN_BLOCKS as u32) as usize + 1;
+    if !(0..=8).contains(&block) || index + run > FUMEN_BLOCKS {
+      return Err("broken fumen board".to_string());
+    }
+    for cell in field[index..index + run].iter_mut() {
+      *cell = block as u32;
+    }
+    index += run;
+  }
+  let mut cells = vec![];
+  for (i, &block) in field.iter().enumerate() {
+    // 上から数えた行。最後の行はせり上がりの予告なので盤面には入れない
+    let row = i / 10;
+    if block == 0 || row == FUMEN_ROWS - 1 {
+      continue;
+    }
+    let y = (FUMEN_ROWS - 2 - row) as i32;
+    if y >= ARENA_HEIGHT as i32 {
+      return Err(format!("board is taller than {} rows", ARENA_HEIGHT));
+    }
+    cells.push(Position {
+      x: (i % 10) as i32,
+      y,
+    });
+  }
+  Ok(cells)
+}
+
+// クリップボードから読めなければ、書き出したときの置き場所を見る
+fn read_board() -> Result<Vec<Position>, String> {
+  let text = clipboard::paste()
+    .or_else(|_| fs::read_to_string(BOARD_FILE))
+    .map_err(|e| e.to_string())?;
+  parse_board(&text)
+}
+
+fn write_file(text: &str) -> io::Result<PathBuf> {
+  let path = PathBuf::from(BOARD_FILE);
+  if let Some(dir) = path.parent() {
+    fs::create_dir_all(dir)?;
+  }
+  fs::write(&path, text)?;
+  Ok(path)
+}
+
+// 読み込んだ盤面。練習モードとエディタがそれぞれ自分の盤面に置き直す
+pub struct BoardImported(pub Vec<Position>);
+
+// 画面の隅に出すコピーの結果と残り秒数
+#[derive(Default)]
+struct SnapshotMessage(Option<(String, f32)>);
+
+struct SnapshotText;
+
+pub struct SnapshotPlugin;
+
+impl Plugin for SnapshotPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(SnapshotMessage::default())
+      .add_event::<BoardImported>()
+      .add_startup_system_to_stage(StartupStage::PostStartup, setup_snapshot.system())
+      .add_system(copy_board.system())
+      .add_system(import_board.system())
+      .add_system(snapshot_ui.system());
+  }
+}
+
+fn setup_snapshot(mut commands: Commands, fonts: Res<Fonts>) {
+  let mut text = overlay_text(&fonts);
+  text.style.position = Rect {
+    bottom: Val::Px(5.0),
+    left: Val::Px(5.0),
+    ..Default::default()
+  };
+  commands.spawn_bundle(text).insert(SnapshotText);
+}
+
+// F3 で今の盤面を文字にしてコピーする。チャットで相談したり不具合を報告したりする用
+fn copy_board(
+  keyboard_input: Res<Input<KeyCode>>,
+  state: Res<State<AppState>>,
+  active_block: Res<ActiveBlock>,
+  mut message: ResMut<SnapshotMessage>,
+  stacked_query: Query<&Position, With<StackedBlock>>,
+  active_query: Query<&Position, With<PrimitiveBlock>>,
+) {
+  if !keyboard_input.just_pressed(KeyCode::F3)
+    || !matches!(state.current(), AppState::Playing | AppState::Paused)
+  {
+    return;
+  }
+  let board = Board::from_cells(stacked_query.iter());
+  let active: Vec<Position> = active_query.iter().cloned().collect();
+  let piece = Some(active_block.block_idx)
+    .filter(|_| active_block.is_on())
+    .map(block_name);
+  let text = board_text(&board, &active, piece);
+  let result = match clipboard::copy(&text) {
+    Ok(()) => "board copied to clipboard".to_string(),
+    Err(e) => {
+      warn!("could not copy board: {}", e);
+      match write_file(&text) {
+        Ok(path) => format!("board saved to {}", path.display()),
+        Err(e) => format!("could not save board: {}", e),
+      }
+    }
+  };
+  message.0 = Some((result, MESSAGE_SECONDS));
+}
+
+// F4 でクリップボードの盤面を読み込む。練習モードとエディタだけ
+fn import_board(
+  keyboard_input: Res<Input<KeyCode>>,
+  state: Res<State<AppState>>,
+  mode: Res<GameMode>,
+  mut message: ResMut<SnapshotMessage>,
+  mut imported_events: EventWriter<BoardImported>,
+) {
+  if !keyboard_input.just_pressed(KeyCode::F4)
+    || *state.current() != AppState::Playing
+    || !matches!(*mode, GameMode::Practice | GameMode::Editor)
+  {
+    return;
+  }
+  let result = match read_board() {
+    Ok(cells) => {
+      let text = format!("imported {} cells", cells.len());
+      imported_events.send(BoardImported(cells));
+      text
+    }
+    Err(e) => format!("could not import board: {}", e),
+  };
+  message.0 = Some((result, MESSAGE_SECONDS));
+}
+
+fn snapshot_ui(
+  time: Res<Time>,
+  mut message: ResMut<SnapshotMessage>,
+  mut query: Query<&mut Text, With<SnapshotText>>,
+) {
+  if let Some((_, remaining)) = message.0.as_mut() {
+    *remaining -= time.delta_seconds();
+    if *remaining <= 0. {
+      message.0 = None;
+    }
+  }
+  let value = message
+    .0
+    .as_ref()
+    .map_or(String::new(), |(text, _)| text.clone());
+  for mut text in query.iter_mut() {
+    if text.sections[0].value != value {
+      text.sections[0].value = value.clone();
+    }
+  }
+}
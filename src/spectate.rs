@@ -0,0 +1,299 @@
+use bevy::prelude::*;
+
+use crate::ai::{bot_styles, Lookahead, Personality, Rules, Weights, BOT_DIR};
+use crate::battle::Battle;
+use crate::caster::CasterView;
+use crate::exhibition::{Exhibition, TIME_LIMIT};
+use crate::victory::victory_conditions;
+use crate::AppState;
+
+// 眺めるときの速さの倍率と、始めの 1 倍速の位置
+const SPEEDS: [f32; 6] = [0.25, 0.5, 1., 2., 4., 8.];
+const NORMAL_SPEED: usize = 2;
+// 選べる先読み。深く広くすると置き場所を決めるのに時間がかかる
+const LOOKAHEADS: [Lookahead; 3] = [
+  Lookahead {
+    depth: 1,
+    width: 1,
+    hold: false,
+  },
+  Lookahead {
+    depth: 3,
+    width: 4,
+    hold: true,
+  },
+  Lookahead {
+    depth: 6,
+    width: 8,
+    hold: true,
+  },
+];
+
+// ボットどうしの対戦の設定と、進めている対戦
+struct Spectate {
+  // 選べる性格と、1P と 2P が使うものの番号
+  choices: Vec<Personality>,
+  // bots/ にあったのに読めなかったファイル
+  errors: Vec<String>,
+  styles: [usize; 2],
+  condition: usize,
+  speed: usize,
+  // LOOKAHEADS の番号
+  lookahead: usize,
+  game: Option<Exhibition>,
+  // 結果だけ出したときの知らせ
+  result: Option<String>,
+  // 配信向けの配置で眺める
+  caster: bool,
+}
+
+impl Default for Spectate {
+  fn default() -> Self {
+    let (choices, errors) = load_choices();
+    Self {
+      choices,
+      errors,
+      styles: [0, 1],
+      condition: 0,
+      speed: NORMAL_SPEED,
+      lookahead: 0,
+      game: None,
+      result: None,
+      caster: false,
+    }
+  }
+}
+
+impl Spectate {
+  fn new_game(&self) -> Exhibition {
+    let weights: Vec<Weights> = self
+      .styles
+      .iter()
+      .map(|&i| self.choices[i].weights)
+      .collect();
+    let rules: Vec<Rules> = self.styles.iter().map(|&i| self.choices[i].rules).collect();
+    let condition = victory_conditions().swap_remove(self.condition);
+    let mut game = Exhibition::new(&weights, rand::random(), condition);
+    game.set_lookahead(LOOKAHEADS[self.lookahead]);
+    game.set_rules(&rules);
+    game
+  }
+
+  // 対戦中はそれぞれが今取っている方針も出す
+  fn matchup(&self) -> String {
+    let strategies = self.game.as_ref().map_or(vec![], |game| game.strategies());
+    let names: Vec<String> = self
+      .styles
+      .iter()
+      .enumerate()
+      .map(|(i, &style)| {
+        let name = &self.choices[style].name;
+        match strategies.get(i) {
+          Some(Some(strategy)) => format!("P{} {} ({})", i + 1, name, strategy.name()),
+          _ => format!("P{} {}", i + 1, name),
+        }
+      })
+      .collect();
+    format!(
+      "{}  speed {}x  [Left/Right]  caster {}  [C]",
+      names.join(" vs "),
+      SPEEDS[self.speed],
+      on_off(self.caster)
+    )
+  }
+
+  fn status(&self, battle: &Battle) -> String {
+    if battle.is_playing() {
+      return format!("{}  [Esc] stop", self.matchup());
+    }
+    let mut value = if battle.is_over() {
+      self.matchup()
+    } else {
+      let mut value = format!(
+        "P1 {}  [1]\nP2 {}  [2]\nwin by {}  [V]\nlookahead {}  [L]\nspeed {}x  [Left/Right]\ncaster layout {}  [C]\n\n[Enter] watch  [I] result only  [R] reload {}/",
+        self.choices[self.styles[0]].name,
+        self.choices[self.styles[1]].name,
+        victory_conditions()[self.condition].name(),
+        describe_lookahead(&LOOKAHEADS[self.lookahead]),
+        SPEEDS[self.speed],
+        on_off(self.caster),
+        BOT_DIR,
+      );
+      for error in self.errors.iter() {
+        value += &format!("\nskipped {}", error);
+      }
+      value
+    };
+    if let Some(result) = self.result.as_ref() {
+      value += &format!("\n{}", result);
+    }
+    value
+  }
+}
+
+// ボットどうしの対戦を眺める。盤面は対戦画面で描く
+pub struct SpectatePlugin;
+
+impl Plugin for SpectatePlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app.insert_resource(Spectate::default()).add_system_set(
+      SystemSet::on_update(AppState::Battle)
+        .with_system(spectate_input.system())
+        .with_system(spectate_run.system()),
+    );
+  }
+}
+
+fn spectate_input(
+  keyboard_input: Res<Input<KeyCode>>,
+  mut spectate: ResMut<Spectate>,
+  mut battle: ResMut<Battle>,
+  mut caster: ResMut<CasterView>,
+) {
+  // 対戦中でも切り替えられる
+  if keyboard_input.just_pressed(KeyCode::C) {
+    spectate.caster = !spectate.caster;
+  }
+  battle.set_caster(spectate.caster);
+  if keyboard_input.just_pressed(KeyCode::Left) {
+    spectate.speed = spectate.speed.saturating_sub(1);
+  }
+  if keyboard_input.just_pressed(KeyCode::Right) {
+    spectate.speed = (spectate.speed + 1).min(SPEEDS.len() - 1);
+  }
+  if keyboard_input.just_pressed(KeyCode::Escape) {
+    spectate.game = None;
+    battle.reset();
+    return;
+  }
+  if battle.is_playing() {
+    return;
+  }
+  if !battle.is_over() {
+    let keys = [KeyCode::Key1, KeyCode::Key2];
+    for (i, &key) in keys.iter().enumerate() {
+      if keyboard_input.just_pressed(key) {
+        spectate.styles[i] = (spectate.styles[i] + 1) % spectate.choices.len();
+      }
+    }
+    if keyboard_input.just_pressed(KeyCode::V) {
+      spectate.condition = (spectate.condition + 1) % victory_conditions().len();
+    }
+    if keyboard_input.just_pressed(KeyCode::L) {
+      spectate.lookahead = (spectate.lookahead + 1) % LOOKAHEADS.len();
+    }
+    // 置き直したボットを読み直す。選んでいたものは名前で選び直す
+    if keyboard_input.just_pressed(KeyCode::R) {
+      let names = spectate.styles.map(|i| spectate.choices[i].name.clone());
+      let (choices, errors) = load_choices();
+      for (style, name) in spectate.styles.iter_mut().zip(names.iter()) {
+        *style = choices.iter().position(|c| &c.name == name).unwrap_or(0);
+      }
+      spectate.choices = choices;
+      spectate.errors = errors;
+    }
+  }
+  if keyboard_input.just_pressed(KeyCode::Return) {
+    caster.reset();
+    spectate.result = None;
+    spectate.game = Some(spectate.new_game());
+    battle.start_exhibition(spectate.condition);
+  } else if keyboard_input.just_pressed(KeyCode::I) {
+    // 画面に出さずに最後まで進めて、決着の盤面と結果だけ見せる
+    let mut game = spectate.new_game();
+    caster.reset();
+    let seconds = game.run().map(|placings| {
+      battle.start_exhibition(spectate.condition);
+      sync(&game, &mut battle);
+      battle.finish(placings);
+      game.game().elapsed()
+    });
+    spectate.result = Some(match seconds {
+      Some(seconds) => format!("result only: decided in {}", format_time(seconds)),
+      None => format!("result only: no winner in {}", format_time(TIME_LIMIT)),
+    });
+    spectate.game = Some(game);
+  }
+}
+
+// 速さに合わせて対戦を進め、盤面を対戦画面へ写す
+fn spectate_run(
+  time: Res<Time>,
+  mut spectate: ResMut<Spectate>,
+  mut battle: ResMut<Battle>,
+  mut caster: ResMut<CasterView>,
+) {
+  let dt = time.delta_seconds() * SPEEDS[spectate.speed];
+  let status = spectate.status(&battle);
+  let mut elapsed = 0.;
+  let spectate = &mut *spectate;
+  let (choices, styles) = (&spectate.choices, spectate.styles);
+  if let Some(game) = spectate.game.as_mut() {
+    if battle.is_playing() {
+      game.advance(dt);
+      sync(game, &mut battle);
+      feed_caster(choices, styles, game, &mut caster);
+      if game.game().is_over() {
+        battle.finish(game.game().record().placings.clone());
+      }
+    }
+    elapsed = game.game().elapsed();
+  }
+  battle.set_exhibition(status, elapsed);
+}
+
+fn load_choices() -> (Vec<Personality>, Vec<String>) {
+  let (choices, errors) = bot_styles();
+  for error in errors.iter() {
+    warn!("skipping bot {}", error);
+  }
+  (choices, errors)
+}
+
+fn sync(game: &Exhibition, battle: &mut Battle) {
+  let game = game.game();
+  for seat in 0..game.players() {
+    battle.apply_seat(seat, game.snapshot(seat));
+  }
+}
+
+// 実況の欄へ、席ごとの名前と盤面と送った合計を写す。結果だけ出したときは写さない
+fn feed_caster(
+  choices: &[Personality],
+  styles: [usize; 2],
+  game: &Exhibition,
+  caster: &mut CasterView,
+) {
+  let game = game.game();
+  for seat in 0..game.players() {
+    let name = &choices[styles[seat]].name;
+    caster.seat_mut(seat).update(
+      name,
+      game.state(seat),
+      game.incoming(seat),
+      game.snapshot(seat).sent,
+      game.elapsed(),
+    );
+  }
+}
+
+fn on_off(on: bool) -> &'static str {
+  if on {
+    "on"
+  } else {
+    "off"
+  }
+}
+
+fn describe_lookahead(lookahead: &Lookahead) -> String {
+  let mut value = format!("{} pieces, beam {}", lookahead.depth, lookahead.width);
+  if lookahead.hold {
+    value += ", hold";
+  }
+  value
+}
+
+fn format_time(seconds: f32) -> String {
+  let seconds = seconds.ceil() as u32;
+  format!("{}:{:02}", seconds / 60, seconds % 60)
+}
@@ -0,0 +1,234 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::rules::Ruleset;
+use crate::{GameMode, ARENA_HEIGHT, GRAVITY_STEP};
+
+// NES の 1 段落ちるまでのフレーム数 (レベル 0-29)
+const NES_FRAMES: [u32; 30] = [
+  48, 43, 38, 33, 28, 23, 18, 13, 8, 6, 5, 5, 5, 4, 4, 4, 3, 3, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1,
+];
+// TGM の重力 (1/256 段/フレーム)。最後は 20G
+const TGM_GRAVITY: [u32; 16] = [
+  4, 8, 12, 16, 32, 48, 64, 96, 128, 192, 256, 512, 768, 1024, 2560, 5120,
+];
+// 直線の表は 1 レベルごとにこれだけ速くし、ここより速くはしない
+const LINEAR_STEP: f32 = 0.04;
+const LINEAR_FASTEST: f32 = 0.05;
+
+// 重力の速さの表
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum SpeedTable {
+  // いつもの速さ。レベルが上がっても変えない
+  #[default]
+  Standard,
+  Tgm,
+  Nes,
+  Linear,
+  // config.ron の custom_gravity に書いた表
+  Custom,
+}
+
+impl SpeedTable {
+  pub const ALL: [SpeedTable; 5] = [
+    SpeedTable::Standard,
+    SpeedTable::Tgm,
+    SpeedTable::Nes,
+    SpeedTable::Linear,
+    SpeedTable::Custom,
+  ];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      SpeedTable::Standard => "standard",
+      SpeedTable::Tgm => "TGM-like",
+      SpeedTable::Nes => "NES-like",
+      SpeedTable::Linear => "linear",
+      SpeedTable::Custom => "custom",
+    }
+  }
+
+  pub fn cycle(self, forward: bool) -> Self {
+    let len = Self::ALL.len();
+    let i = Self::ALL.iter().position(|&t| t == self).unwrap_or(0);
+    let i = if forward { i + 1 } else { i + len - 1 };
+    Self::ALL[i % len]
+  }
+}
+
+// 重力の表の 1 コマ (60 Hz)。Gravity はこの長さあたりに落ちる量で表す
+pub const TICK_SECONDS: f32 = 1. / 60.;
+// 1 コマに 1 段落ちる重力 (1G)。1 段を 65536 に分けた固定小数点で数える
+pub const G: u32 = 1 << 16;
+// 浮動小数の誤差で tick を 1 つ取りこぼさないための余裕
+const TICK_EPSILON: f32 = 1e-4;
+
+// 入力と重力を進める刻み。速いほど操作の遅れが減るが、落ちる速さや DAS の実時間は変わらない
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum TickRate {
+  #[default]
+  Hz60,
+  Hz120,
+  Hz240,
+}
+
+impl TickRate {
+  pub const ALL: [TickRate; 3] = [TickRate::Hz60, TickRate::Hz120, TickRate::Hz240];
+
+  pub fn hz(self) -> u32 {
+    match self {
+      TickRate::Hz60 => 60,
+      TickRate::Hz120 => 120,
+      TickRate::Hz240 => 240,
+    }
+  }
+
+  pub fn from_hz(hz: u32) -> Option<Self> {
+    Self::ALL.iter().copied().find(|rate| rate.hz() == hz)
+  }
+
+  // 重力の表の 1 コマを何 tick に分けるか
+  fn per_frame(self) -> u32 {
+    self.hz() / 60
+  }
+
+  // ミリ秒で決めた長さを、いちばん近い tick 数にする
+  pub fn ticks(self, ms: u32) -> u32 {
+    (ms as f32 * self.hz() as f32 / 1000.).round() as u32
+  }
+
+  // pending 秒のうち、まるごと過ぎた tick の数を取り出す。端数は pending に残す
+  pub fn take_ticks(self, pending: &mut f32) -> u32 {
+    let hz = self.hz() as f32;
+    let ticks = (*pending * hz + TICK_EPSILON).floor().max(0.);
+    *pending = (*pending - ticks / hz).max(0.);
+    ticks as u32
+  }
+
+  pub fn cycle(self, forward: bool) -> Self {
+    let len = Self::ALL.len();
+    let i = Self::ALL.iter().position(|&r| r == self).unwrap_or(0);
+    let i = if forward { i + 1 } else { i + len - 1 };
+    Self::ALL[i % len]
+  }
+}
+// 急降下の速さ
+pub const SOFT_DROP: Gravity = Gravity(G);
+
+// 1 tick に落ちる段数の固定小数点。0.05G のような遅さも 20G のような速さも同じように扱う
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Gravity(pub u32);
+
+impl Gravity {
+  pub fn from_seconds_per_row(seconds: f32) -> Self {
+    Gravity(((TICK_SECONDS / seconds) * G as f32).round().max(1.) as u32)
+  }
+
+  pub fn scaled(self, scale: f32) -> Self {
+    Gravity((self.0 as f32 * scale).round() as u32)
+  }
+}
+
+// 経過時間を tick に区切り、tick ごとに重力をためて 1 段分ごとに落とす
+#[derive(Default)]
+pub struct DropAccumulator {
+  // tick に満たずに持ち越した秒数
+  pending: f32,
+  // 1 段に満たない落ちかけの量 (G × 1 コマの tick 数で 1 段)
+  fraction: u64,
+}
+
+impl DropAccumulator {
+  // dt 秒ぶん進めて、落とす段数を返す。止まっていた後でも盤面の高さより多くは落とさない。
+  // tick を細かくしても 1 段は G × 1 コマの tick 数なので、落ちる実時間は同じ
+  pub fn advance(&mut self, dt: f32, gravity: Gravity, rate: TickRate) -> u32 {
+    self.pending += dt;
+    let ticks = rate.take_ticks(&mut self.pending) as u64;
+    let row = G as u64 * rate.per_frame() as u64;
+    let total = self.fraction + ticks * gravity.0 as u64;
+    self.fraction = total % row;
+    (total / row).min(ARENA_HEIGHT as u64) as u32
+  }
+
+  pub fn reset(&mut self) {
+    *self = DropAccumulator::default();
+  }
+}
+
+// レベルごとの重力。表より先のレベルは最後の速さのまま
+#[derive(Clone, PartialEq, Debug)]
+pub struct GravityCurve(pub Vec<Gravity>);
+
+impl Default for GravityCurve {
+  fn default() -> Self {
+    GravityCurve(vec![Gravity::from_seconds_per_row(GRAVITY_STEP as f32)])
+  }
+}
+
+impl GravityCurve {
+  // 自作の表が空か、0 以下の秒数を含むときはいつもの速さにする
+  pub fn new(table: SpeedTable, custom: &[f32]) -> Self {
+    match table {
+      // NES は 1 段落ちるまでのフレーム数、TGM は 1/256 G なのでそのまま直す
+      SpeedTable::Nes => GravityCurve(NES_FRAMES.iter().map(|&f| Gravity(G / f)).collect()),
+      SpeedTable::Tgm => GravityCurve(TGM_GRAVITY.iter().map(|&g| Gravity(g << 8)).collect()),
+      SpeedTable::Linear => {
+        let levels = ((GRAVITY_STEP as f32 - LINEAR_FASTEST) / LINEAR_STEP).ceil() as usize;
+        GravityCurve(
+          (0..=levels)
+            .map(|level| (GRAVITY_STEP as f32 - level as f32 * LINEAR_STEP).max(LINEAR_FASTEST))
+            .map(Gravity::from_seconds_per_row)
+            .collect(),
+        )
+      }
+      SpeedTable::Custom if !custom.is_empty() && custom.iter().all(|&s| s > 0.) => GravityCurve(
+        custom
+          .iter()
+          .copied()
+          .map(Gravity::from_seconds_per_row)
+          .collect(),
+      ),
+      SpeedTable::Standard | SpeedTable::Custom => GravityCurve::default(),
+    }
+  }
+
+  pub fn gravity(&self, level: u32) -> Gravity {
+    let last = self.0.len().saturating_sub(1);
+    self
+      .0
+      .get((level as usize).min(last))
+      .copied()
+      .unwrap_or_else(|| Gravity::from_seconds_per_row(GRAVITY_STEP as f32))
+  }
+}
+
+pub struct SpeedPlugin;
+
+impl Plugin for SpeedPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(GravityCurve::default())
+      .add_system(update_gravity_curve.system());
+  }
+}
+
+fn update_gravity_curve(
+  config: Res<Config>,
+  ruleset: Res<Ruleset>,
+  mode: Res<GameMode>,
+  mut curve: ResMut<GravityCurve>,
+) {
+  if !config.is_changed() && !ruleset.is_changed() {
+    return;
+  }
+  // マスターモードは設定によらず TGM の速さ。ルールで決めていなければ config.ron の表
+  let table = match *mode {
+    GameMode::Master => SpeedTable::Tgm,
+    _ => ruleset.gravity.unwrap_or(config.speed_table),
+  };
+  let next = GravityCurve::new(table, &config.custom_gravity);
+  if *curve != next {
+    *curve = next;
+  }
+}
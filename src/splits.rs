@@ -0,0 +1,177 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::layout::{Anchor, Panel};
+use crate::stats::{Statistics, LINES_PER_LEVEL};
+use crate::{overlay_text, save, AppState, Fonts, GameClock, GameMode, GameReset};
+
+// HUD に並べる直近の区間の数
+const HUD_SPLITS: usize = 4;
+
+// 区間 (10 ライン) ごとの自己ベスト。区間ごとに一番速かったタイムを別々に持つ
+#[derive(Clone, Default, PartialEq, Debug, Serialize, Deserialize)]
+pub struct BestSplits(pub Vec<f64>);
+
+impl BestSplits {
+  fn file(mode: GameMode) -> String {
+    format!("splits_{:?}.ron", mode).to_lowercase()
+  }
+
+  // 速かった区間だけ書き換える。1 つでも更新したら true
+  pub fn merge(&mut self, splits: &[f64]) -> bool {
+    let mut improved = false;
+    for (i, &split) in splits.iter().enumerate() {
+      match self.0.get_mut(i) {
+        Some(best) if *best <= split => {}
+        Some(best) => {
+          *best = split;
+          improved = true;
+        }
+        None => {
+          self.0.push(split);
+          improved = true;
+        }
+      }
+    }
+    improved
+  }
+}
+
+// 消したラインの記録 (ゲーム開始からの秒数, ライン数) から、区間ごとにかかった秒数
+pub fn section_splits(clears: &[(f64, u32)]) -> Vec<f64> {
+  let mut splits = vec![];
+  let mut lines = 0;
+  let mut section_start = 0.;
+  for &(time, n) in clears.iter() {
+    lines += n;
+    if lines >= (splits.len() as u32 + 1) * LINES_PER_LEVEL {
+      splits.push(time - section_start);
+      section_start = time;
+    }
+  }
+  splits
+}
+
+// 自己ベストとの差。ベストが無い区間は空
+pub fn split_delta(split: f64, best: Option<f64>) -> String {
+  match best {
+    Some(best) => format!("{:+.1}", split - best),
+    None => String::new(),
+  }
+}
+
+fn split_line(i: usize, split: f64, best: Option<f64>) -> String {
+  format!("{:>2}  {:.1}s  {}", i + 1, split, split_delta(split, best))
+}
+
+// マラソンとマスターで、区間ごとのタイムを自己ベストと比べる
+#[derive(Default)]
+pub struct Splits {
+  pub best: BestSplits,
+  pub current: Vec<f64>,
+}
+
+impl Splits {
+  pub fn summary(&self) -> String {
+    let lines: Vec<String> = self
+      .current
+      .iter()
+      .enumerate()
+      .map(|(i, &split)| split_line(i, split, self.best.0.get(i).copied()))
+      .collect();
+    format!("splits\n{}", lines.join("\n"))
+  }
+}
+
+struct SplitsText;
+
+pub struct SplitsPlugin;
+
+impl Plugin for SplitsPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(Splits::default())
+      .add_startup_system_to_stage(StartupStage::PostStartup, setup_splits.system())
+      .add_system_set(SystemSet::on_enter(AppState::Playing).with_system(load_best.system()))
+      .add_system_set(SystemSet::on_enter(AppState::Results).with_system(record_best.system()))
+      .add_system(splits_reset.system())
+      .add_system(splits_update.system())
+      .add_system(splits_ui.system());
+  }
+}
+
+fn setup_splits(mut commands: Commands, fonts: Res<Fonts>) {
+  commands
+    .spawn_bundle(overlay_text(&fonts))
+    .insert(SplitsText)
+    .insert(Anchor(Panel::Stats));
+}
+
+fn load_best(mode: Res<GameMode>, mut splits: ResMut<Splits>) {
+  *splits = Splits {
+    best: save::load(&BestSplits::file(*mode)),
+    current: vec![],
+  };
+}
+
+// やり直したら、このゲームの区間だけ捨てる
+fn splits_reset(mut splits: ResMut<Splits>, mut reset_events: EventReader<GameReset>) {
+  if reset_events.iter().count() > 0 {
+    splits.current.clear();
+  }
+}
+
+fn splits_update(stats: Res<Statistics>, mut splits: ResMut<Splits>) {
+  if !stats.is_changed() {
+    return;
+  }
+  let current = section_splits(&stats.clears);
+  if splits.current != current {
+    splits.current = current;
+  }
+}
+
+// 結果画面の差は始めたときのベストと比べたいので、Splits のベストは書き換えずに保存だけする
+fn record_best(mode: Res<GameMode>, splits: Res<Splits>) {
+  let mut best = splits.best.clone();
+  if !best.merge(&splits.current) {
+    return;
+  }
+  if let Err(e) = save::store(&BestSplits::file(*mode), &best) {
+    error!("failed to save best splits: {}", e);
+  }
+}
+
+fn splits_ui(
+  clock: Res<GameClock>,
+  state: Res<State<AppState>>,
+  stats: Res<Statistics>,
+  splits: Res<Splits>,
+  mut query: Query<&mut Text, With<SplitsText>>,
+) {
+  let value = if matches!(state.current(), AppState::Playing | AppState::Paused) {
+    let done = splits.current.len();
+    let section_start = stats.start + splits.current.iter().sum::<f64>();
+    let mut lines: Vec<String> = splits
+      .current
+      .iter()
+      .enumerate()
+      .skip(done.saturating_sub(HUD_SPLITS))
+      .map(|(i, &split)| split_line(i, split, splits.best.0.get(i).copied()))
+      .collect();
+    // 今の区間は自己ベストを何秒残しているか
+    let running = clock.0 - section_start;
+    lines.push(match splits.best.0.get(done) {
+      Some(&best) => format!("{:>2}  {:.1}s  / {:.1}s", done + 1, running, best),
+      None => format!("{:>2}  {:.1}s", done + 1, running),
+    });
+    lines.join("\n")
+  } else {
+    String::new()
+  };
+  for mut text in query.iter_mut() {
+    if text.sections[0].value != value {
+      text.sections[0].value = value.clone();
+    }
+  }
+}
@@ -0,0 +1,180 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::eventlog::GameLog;
+use crate::replay::Replay;
+use crate::stats::Statistics;
+use crate::{overlay_text, save, share, AppState, Fonts, GameClock, Materials};
+
+pub const SPRINT_LINES: u32 = 40;
+const PB_FILE: &str = "sprint_pb.ron";
+const BAR_WIDTH: f32 = 160.0;
+const BAR_HEIGHT: f32 = 8.0;
+
+// 自己ベスト。リプレイは共有用の文字列のまま持つ
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SprintRecord {
+  pub time: f64,
+  pub replay: String,
+}
+
+// 自己ベストのリプレイを横で同時に進めて比べる
+#[derive(Default)]
+struct GhostRace {
+  pb: Option<(f64, Replay)>,
+  // 今のゲームで 40 ライン消したタイム
+  finished: Option<f64>,
+}
+
+struct SprintText;
+// 0: 自分, 1: 自己ベスト
+struct RaceBar(usize);
+
+pub struct SprintPlugin;
+
+impl Plugin for SprintPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(GhostRace::default())
+      .add_startup_system_to_stage(StartupStage::PostStartup, setup_sprint.system())
+      .add_system_set(SystemSet::on_enter(AppState::Playing).with_system(load_pb.system()))
+      .add_system_set(SystemSet::on_update(AppState::Playing).with_system(sprint_check.system()))
+      .add_system_set(SystemSet::on_enter(AppState::Results).with_system(record_pb.system()))
+      .add_system(sprint_ui.system());
+  }
+}
+
+// 同じ経過時間で自己ベストより何ライン多く消しているか
+pub fn lead(lines: u32, pb_lines: u32) -> String {
+  match lines as i32 - pb_lines as i32 {
+    0 => "even".to_string(),
+    d if d > 0 => format!("{} ahead", d),
+    d => format!("{} behind", -d),
+  }
+}
+
+fn setup_sprint(mut commands: Commands, fonts: Res<Fonts>, materials: Res<Materials>) {
+  commands
+    .spawn_bundle(overlay_text(&fonts))
+    .insert(SprintText);
+  // 自己ベストの方はゴーストと同じ半透明にする
+  let fills = [materials.chart_bar.clone(), materials.ghost_block.clone()];
+  for (i, fill) in fills.iter().enumerate() {
+    commands
+      .spawn_bundle(NodeBundle {
+        style: Style {
+          position_type: PositionType::Absolute,
+          position: Rect {
+            top: Val::Px(48.0 + i as f32 * (BAR_HEIGHT + 4.0)),
+            left: Val::Px(5.0),
+            ..Default::default()
+          },
+          size: Size::new(Val::Px(BAR_WIDTH), Val::Px(BAR_HEIGHT)),
+          ..Default::default()
+        },
+        material: materials.panel.clone(),
+        ..Default::default()
+      })
+      .with_children(|parent| {
+        parent
+          .spawn_bundle(NodeBundle {
+            style: Style {
+              size: Size::new(Val::Percent(0.0), Val::Percent(100.0)),
+              ..Default::default()
+            },
+            material: fill.clone(),
+            ..Default::default()
+          })
+          .insert(RaceBar(i));
+      });
+  }
+}
+
+fn load_pb(mut race: ResMut<GhostRace>) {
+  race.finished = None;
+  race.pb = save::load_opt::<SprintRecord>(PB_FILE).and_then(|record| {
+    match share::decode(&record.replay) {
+      Ok(log) => Some((record.time, Replay::new(log))),
+      Err(e) => {
+        warn!("failed to read sprint replay: {}", e);
+        None
+      }
+    }
+  });
+}
+
+fn sprint_check(
+  clock: Res<GameClock>,
+  stats: Res<Statistics>,
+  mut race: ResMut<GhostRace>,
+  mut state: ResMut<State<AppState>>,
+) {
+  let lines: u32 = stats.clears.iter().map(|&(_, n)| n).sum();
+  if lines >= SPRINT_LINES && race.finished.is_none() {
+    race.finished = Some(clock.0 - stats.start);
+    state.overwrite_set(AppState::Results).unwrap();
+  }
+}
+
+fn record_pb(race: Res<GhostRace>, log: Res<GameLog>) {
+  let time = match race.finished {
+    Some(time) => time,
+    None => return,
+  };
+  if race.pb.as_ref().is_some_and(|&(pb, _)| pb <= time) {
+    return;
+  }
+  let record = SprintRecord {
+    time,
+    replay: share::encode(&log),
+  };
+  match save::store(PB_FILE, &record) {
+    Ok(()) => info!("new sprint personal best: {:.2}s", time),
+    Err(e) => error!("failed to save sprint personal best: {}", e),
+  }
+}
+
+fn sprint_ui(
+  clock: Res<GameClock>,
+  state: Res<State<AppState>>,
+  stats: Res<Statistics>,
+  race: Res<GhostRace>,
+  mut text_query: Query<&mut Text, With<SprintText>>,
+  mut bar_query: Query<(&RaceBar, &mut Style)>,
+) {
+  let elapsed = match race.finished {
+    Some(finished) => finished,
+    None if matches!(state.current(), AppState::Playing | AppState::Paused) => {
+      clock.0 - stats.start
+    }
+    None => 0.,
+  };
+  let lines: u32 = stats.clears.iter().map(|&(_, n)| n).sum();
+  let lines = lines.min(SPRINT_LINES);
+  let pb_lines = race.pb.as_ref().map(|(_, replay)| {
+    replay
+      .frame(replay.events_until(elapsed))
+      .state
+      .lines
+      .min(SPRINT_LINES)
+  });
+
+  let mut value = format!("SPRINT  {}/{}  {:.2}s\n", lines, SPRINT_LINES, elapsed);
+  value += &match (race.pb.as_ref(), pb_lines) {
+    (Some(&(pb, _)), Some(pb_lines)) => format!("PB {:.2}s  {}", pb, lead(lines, pb_lines)),
+    _ => "no personal best yet".to_string(),
+  };
+  for mut text in text_query.iter_mut() {
+    if text.sections[0].value != value {
+      text.sections[0].value = value.clone();
+    }
+  }
+
+  for (bar, mut style) in bar_query.iter_mut() {
+    let n = match bar.0 {
+      0 => lines,
+      _ => pb_lines.unwrap_or(0),
+    };
+    style.size.width = Val::Percent(n as f32 / SPRINT_LINES as f32 * 100.);
+  }
+}
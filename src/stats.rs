@@ -0,0 +1,183 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::board::Board;
+use crate::rules::Ruleset;
+use crate::{
+  AppState, BlockStacked, GameClock, GameReset, LinesCleared, Position, StackedBlock, ARENA_WIDTH,
+};
+
+pub const LINES_PER_LEVEL: u32 = 10;
+// 時系列のグラフ用に記録する間隔 (秒)
+pub const SAMPLE_SECONDS: f64 = 1.;
+// 一度に消したライン数ごとの得点。消したときのレベル + 1 倍になる
+const LINE_SCORES: [u32; 5] = [0, 100, 300, 500, 800];
+const CLASSIC_LINE_SCORES: [u32; 5] = [0, 40, 100, 300, 1200];
+
+// ルールで選ぶ得点の表
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Scoring {
+  #[default]
+  Guideline,
+  // NES の表。テトリスの得点が大きい
+  Classic,
+}
+
+impl Scoring {
+  pub const ALL: [Scoring; 2] = [Scoring::Guideline, Scoring::Classic];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      Scoring::Guideline => "guideline",
+      Scoring::Classic => "classic",
+    }
+  }
+
+  fn line_scores(self) -> &'static [u32; 5] {
+    match self {
+      Scoring::Guideline => &LINE_SCORES,
+      Scoring::Classic => &CLASSIC_LINE_SCORES,
+    }
+  }
+}
+
+// SAMPLE_SECONDS ごとの盤面の高さと、それまでに送った・受けたライン数
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct Sample {
+  pub height: i32,
+  pub sent: u32,
+  pub received: u32,
+}
+
+// 結果画面のグラフ用に1ゲーム分の記録を集める
+#[derive(Default)]
+pub struct Statistics {
+  pub start: f64,
+  // ミノの種類ごとの個数 (block_idx - 1 の位置)
+  pub pieces: [u32; 7],
+  // 列ごとに置いたマスの数
+  pub columns: [u32; ARENA_WIDTH as usize],
+  // (ゲーム開始からの秒数, 消したライン数)
+  pub clears: Vec<(f64, u32)>,
+  // (ゲーム開始からの秒数, 積んだ直後の最大の高さ)
+  pub heights: Vec<(f64, i32)>,
+  // 練習モードで数えた置き間違い
+  pub misdrops: u32,
+  pub scoring: Scoring,
+  // 対戦の練習とサバイバルで、相殺した後に送ったライン数と、せり上がったライン数
+  pub sent: u32,
+  pub received: u32,
+  // せり上がった行のうち、まだ盤面に残っている行数
+  pub garbage_left: u32,
+  pub timeline: Vec<Sample>,
+}
+
+impl Statistics {
+  fn restart(&mut self, now: f64, scoring: Scoring) {
+    *self = Statistics {
+      start: now,
+      scoring,
+      ..Default::default()
+    };
+  }
+
+  pub fn lines(&self) -> u32 {
+    self.clears.iter().map(|&(_, lines)| lines).sum()
+  }
+
+  // 0 から始まるレベル
+  pub fn level(&self) -> u32 {
+    self.lines() / LINES_PER_LEVEL
+  }
+
+  pub fn score(&self) -> u32 {
+    let mut lines = 0;
+    let mut score = 0;
+    let table = self.scoring.line_scores();
+    for &(_, n) in self.clears.iter() {
+      score += table[n.min(4) as usize] * (lines / LINES_PER_LEVEL + 1);
+      lines += n;
+    }
+    score
+  }
+
+  // 次の記録を取る時刻になったか
+  pub fn sample_due(&self, elapsed: f64) -> bool {
+    self.timeline.len() as f64 * SAMPLE_SECONDS <= elapsed
+  }
+
+  // フレームが飛んで抜けた分も今の値で埋める
+  pub fn sample(&mut self, elapsed: f64, height: i32) {
+    while self.sample_due(elapsed) {
+      self.timeline.push(Sample {
+        height,
+        sent: self.sent,
+        received: self.received,
+      });
+    }
+  }
+
+  // bucket 秒ごとに区切った1分あたりのライン数
+  pub fn clears_per_minute(&self, bucket: f64, end: f64) -> Vec<f32> {
+    let len = (end / bucket).ceil().max(1.) as usize;
+    let mut res = vec![0.; len];
+    for &(t, lines) in self.clears.iter() {
+      let i = ((t / bucket) as usize).min(len - 1);
+      res[i] += lines as f32;
+    }
+    for value in res.iter_mut() {
+      *value *= (60. / bucket) as f32;
+    }
+    res
+  }
+}
+
+pub struct StatsPlugin;
+
+impl Plugin for StatsPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(Statistics::default())
+      .add_system_set(SystemSet::on_enter(AppState::Playing).with_system(stats_start.system()))
+      .add_system(stats_record.system());
+  }
+}
+
+fn stats_start(clock: Res<GameClock>, ruleset: Res<Ruleset>, mut stats: ResMut<Statistics>) {
+  stats.restart(clock.0, ruleset.scoring);
+}
+
+fn stats_record(
+  clock: Res<GameClock>,
+  ruleset: Res<Ruleset>,
+  mut stats: ResMut<Statistics>,
+  mut stacked_events: EventReader<BlockStacked>,
+  mut cleared_events: EventReader<LinesCleared>,
+  mut reset_events: EventReader<GameReset>,
+  stacked_block_query: Query<&Position, With<StackedBlock>>,
+) {
+  let now = clock.0;
+  if reset_events.iter().count() > 0 {
+    stats.restart(now, ruleset.scoring);
+  }
+  let elapsed = now - stats.start;
+
+  for event in stacked_events.iter() {
+    stats.pieces[(event.block_idx - 1) as usize] += 1;
+    for cell in event.cells.iter() {
+      if let Some(count) = stats.columns.get_mut(cell.x as usize) {
+        *count += 1;
+      }
+    }
+    // 積んだブロックはまだ Query に反映されていないので足して数える
+    let board = Board::from_cells(stacked_block_query.iter().chain(event.cells.iter()));
+    stats.heights.push((elapsed, board.max_height()));
+  }
+  for event in cleared_events.iter() {
+    stats.clears.push((elapsed, event.0));
+  }
+  if stats.sample_due(elapsed) {
+    let height = Board::from_cells(stacked_block_query.iter()).max_height();
+    stats.sample(elapsed, height);
+  }
+}
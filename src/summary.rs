@@ -0,0 +1,156 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use ab_glyph::{point, Font, PxScale, ScaleFont};
+use image::{Rgba, RgbaImage};
+
+use crate::stats::Statistics;
+use crate::GameMode;
+use tetris::{Position, ARENA_HEIGHT, ARENA_WIDTH};
+
+// 結果画面で書き出す、SNS に貼る用の画像を置く場所
+pub const SUMMARY_DIR: &str = "summaries";
+
+const CELL: u32 = 24;
+const MARGIN: u32 = 20;
+// 盤面の右に数字を並べる欄の幅
+const TEXT_WIDTH: u32 = 260;
+const TITLE_SIZE: f32 = 28.0;
+const TEXT_SIZE: f32 = 20.0;
+const LINE_GAP: f32 = 8.0;
+
+pub const IMAGE_WIDTH: u32 = MARGIN * 3 + CELL * ARENA_WIDTH + TEXT_WIDTH;
+pub const IMAGE_HEIGHT: u32 = MARGIN * 2 + CELL * ARENA_HEIGHT;
+
+// 右の欄に出す行。最初の行を見出しにする
+pub fn summary_lines(mode: GameMode, elapsed: f64, stats: &Statistics, seed: u64) -> Vec<String> {
+  let pieces: u32 = stats.pieces.iter().sum();
+  let pps = if elapsed > 0. {
+    pieces as f64 / elapsed
+  } else {
+    0.
+  };
+  vec![
+    format!("{:?}", mode).to_uppercase(),
+    format!("time {}:{:05.2}", (elapsed / 60.) as u32, elapsed % 60.),
+    format!("score {}", stats.score()),
+    format!("lines {}", stats.lines()),
+    format!("level {}", stats.level() + 1),
+    format!("pieces {}", pieces),
+    format!("{:.2} PPS", pps),
+    format!("seed {:016x}", seed),
+  ]
+}
+
+fn pixel([r, g, b]: [u8; 3]) -> Rgba<u8> {
+  Rgba([r, g, b, 255])
+}
+
+fn fill(image: &mut RgbaImage, x: u32, y: u32, width: u32, height: u32, color: Rgba<u8>) {
+  for py in y..(y + height).min(image.height()) {
+    for px in x..(x + width).min(image.width()) {
+      image.put_pixel(px, py, color);
+    }
+  }
+}
+
+// 左上 (x, y) から 1 行書く。色は背景と文字の濃さで混ぜる
+fn draw_text<F: Font>(
+  image: &mut RgbaImage,
+  font: &F,
+  size: f32,
+  (x, y): (f32, f32),
+  text: &str,
+  color: [u8; 3],
+) {
+  let font = font.as_scaled(PxScale::from(size));
+  let mut caret = point(x, y + font.ascent());
+  let mut last = None;
+  for c in text.chars() {
+    let id = font.glyph_id(c);
+    if let Some(last) = last {
+      caret.x += font.kern(last, id);
+    }
+    let glyph = id.with_scale_and_position(size, caret);
+    caret.x += font.h_advance(id);
+    last = Some(id);
+    let outlined = match font.outline_glyph(glyph) {
+      Some(outlined) => outlined,
+      None => continue,
+    };
+    let bounds = outlined.px_bounds();
+    outlined.draw(|gx, gy, coverage| {
+      let px = bounds.min.x as i32 + gx as i32;
+      let py = bounds.min.y as i32 + gy as i32;
+      if px < 0 || py < 0 || px as u32 >= image.width() || py as u32 >= image.height() {
+        return;
+      }
+      let under = image.get_pixel_mut(px as u32, py as u32);
+      for i in 0..3 {
+        let mixed = under[i] as f32 * (1. - coverage) + color[i] as f32 * coverage;
+        under[i] = mixed.round() as u8;
+      }
+    });
+  }
+}
+
+// 盤面は左下が原点なので、画像では上下を返す
+pub fn render_summary<F: Font>(
+  font: &F,
+  cells: &[(Position, [u8; 3])],
+  lines: &[String],
+  background: [u8; 3],
+) -> RgbaImage {
+  let mut image = RgbaImage::from_pixel(IMAGE_WIDTH, IMAGE_HEIGHT, pixel(background));
+  let well = [
+    background[0].saturating_add(16),
+    background[1].saturating_add(16),
+    background[2].saturating_add(16),
+  ];
+  fill(
+    &mut image,
+    MARGIN,
+    MARGIN,
+    CELL * ARENA_WIDTH,
+    CELL * ARENA_HEIGHT,
+    pixel(well),
+  );
+  for (position, color) in cells.iter() {
+    if position.x < 0
+      || position.x >= ARENA_WIDTH as i32
+      || position.y < 0
+      || position.y >= ARENA_HEIGHT as i32
+    {
+      continue;
+    }
+    let x = MARGIN + position.x as u32 * CELL;
+    let y = MARGIN + (ARENA_HEIGHT - 1 - position.y as u32) * CELL;
+    // ゲームと同じくマスの間を少し空ける
+    fill(&mut image, x + 1, y + 1, CELL - 2, CELL - 2, pixel(*color));
+  }
+
+  let x = (MARGIN * 2 + CELL * ARENA_WIDTH) as f32;
+  let mut y = MARGIN as f32;
+  for (i, line) in lines.iter().enumerate() {
+    let size = if i == 0 { TITLE_SIZE } else { TEXT_SIZE };
+    draw_text(&mut image, font, size, (x, y), line, [255, 255, 255]);
+    y += size + LINE_GAP;
+  }
+  image
+}
+
+// summary_01.png, summary_02.png, ... のうち空いている最初の名前
+fn free_summary_path(dir: &Path) -> PathBuf {
+  (1..)
+    .map(|i| dir.join(format!("summary_{:02}.png", i)))
+    .find(|path| !path.exists())
+    .unwrap()
+}
+
+pub fn save_summary(image: &RgbaImage) -> Result<PathBuf, String> {
+  let dir = Path::new(SUMMARY_DIR);
+  fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+  let path = free_summary_path(dir);
+  image.save(&path).map_err(|e| e.to_string())?;
+  Ok(path)
+}
@@ -0,0 +1,299 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::attack::AttackTracker;
+use crate::garbage::{self, GarbageConfig, GarbageGenerator, GarbageQueue};
+use crate::rules::Ruleset;
+use crate::stats::Statistics;
+use crate::{
+  overlay_text, save, spawn_stacked_block, AppState, BlockStacked, Fonts, GameReset, GarbageRaised,
+  LinesCleared, Materials, Position, StackedBlock,
+};
+
+const SAVE_FILE: &str = "survival.ron";
+const APM_STEP: u32 = 5;
+const MAX_APM: u32 = 200;
+// 結果画面のグラフ用に盤面のせり上がりの段数を記録する間隔 (秒)
+pub const SAMPLE_SECONDS: f64 = 5.;
+// 積んでからライン消去の結果を待つフレーム数
+const CLEAR_WAIT_FRAMES: u32 = 2;
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct SurvivalConfig {
+  // 1分あたりに送られてくるせり上がりのライン数
+  pub apm: u32,
+}
+
+impl Default for SurvivalConfig {
+  fn default() -> Self {
+    SurvivalConfig { apm: 30 }
+  }
+}
+
+// 決まった APM でせり上がりが来続ける中で、どれだけ生き延びられるか
+pub struct Survival {
+  pub apm: u32,
+  tracker: AttackTracker,
+  generator: GarbageGenerator,
+  // まだ 1 ラインにならない端数
+  pending: f64,
+  pub incoming: GarbageQueue,
+  // 盤面に残っているせり上がりの段数
+  pub garbage_rows: u32,
+  pub elapsed: f64,
+  // SAMPLE_SECONDS ごとの盤面のせり上がりの段数
+  pub samples: Vec<f32>,
+  // 積んでから待ったフレーム数。消去が来なければせり上がる
+  waiting: Option<u32>,
+}
+
+impl Survival {
+  pub fn new(config: &SurvivalConfig, seed: u64) -> Self {
+    Survival {
+      apm: config.apm,
+      tracker: AttackTracker::default(),
+      generator: GarbageGenerator::new(&GarbageConfig::default(), seed),
+      pending: 0.,
+      incoming: GarbageQueue::default(),
+      garbage_rows: 0,
+      elapsed: 0.,
+      samples: Vec::new(),
+      waiting: None,
+    }
+  }
+
+  pub fn tick(&mut self, delta: f64) {
+    let before = (self.elapsed / SAMPLE_SECONDS) as usize;
+    self.elapsed += delta;
+    if (self.elapsed / SAMPLE_SECONDS) as usize > before {
+      self.samples.push(self.garbage_rows as f32);
+    }
+    self.pending += self.apm as f64 / 60. * delta;
+    let whole = self.pending.floor();
+    self.pending -= whole;
+    self.incoming.push(whole as u32);
+  }
+
+  pub fn record_lock(&mut self) {
+    self.tracker.record_lock();
+  }
+
+  // 攻撃は来ているせり上がりの相殺に使う
+  pub fn record_clear(&mut self, lines: u32) {
+    let clear = self.tracker.record_clear(lines);
+    self.incoming.cancel(clear.attack);
+  }
+
+  pub fn dug(&self) -> u32 {
+    self.incoming.received().saturating_sub(self.garbage_rows)
+  }
+
+  // 消したラインのうち、せり上がりを掘った割合
+  pub fn efficiency(&self) -> f32 {
+    garbage::efficiency(
+      self.incoming.received(),
+      self.garbage_rows,
+      self.tracker.lines,
+    )
+  }
+
+  pub fn summary(&self) -> String {
+    format!(
+      "survived {:.1}s at {} APM\ngarbage dug {}/{}  downstack {:.0}%",
+      self.elapsed,
+      self.apm,
+      self.dug(),
+      self.incoming.received(),
+      self.efficiency() * 100.
+    )
+  }
+}
+
+// せり上がりとして送られてきたブロック
+struct SurvivalGarbage;
+
+struct SurvivalText;
+struct SurvivalSetupText;
+
+pub struct SurvivalPlugin;
+
+impl Plugin for SurvivalPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    let config = save::load::<SurvivalConfig>(SAVE_FILE);
+    app
+      .insert_resource(Survival::new(&config, 0))
+      .insert_resource(config)
+      .add_startup_system_to_stage(StartupStage::PostStartup, setup_survival.system())
+      .add_system_set(
+        SystemSet::on_exit(AppState::Title).with_system(reload_survival_options.system()),
+      )
+      .add_system_set(
+        SystemSet::on_enter(AppState::Setup).with_system(setup_survival_options.system()),
+      )
+      .add_system_set(
+        SystemSet::on_update(AppState::Setup).with_system(survival_options_input.system()),
+      )
+      .add_system_set(
+        SystemSet::on_exit(AppState::Setup).with_system(close_survival_options.system()),
+      )
+      .add_system_set(SystemSet::on_enter(AppState::Playing).with_system(survival_start.system()))
+      .add_system_set(SystemSet::on_update(AppState::Playing).with_system(survival_record.system()))
+      .add_system(survival_reset.system())
+      .add_system(survival_ui.system());
+  }
+}
+
+fn setup_survival(mut commands: Commands, fonts: Res<Fonts>) {
+  commands
+    .spawn_bundle(overlay_text(&fonts))
+    .insert(SurvivalText);
+}
+
+fn reload_survival_options(mut config: ResMut<SurvivalConfig>) {
+  *config = save::load(SAVE_FILE);
+}
+
+fn setup_survival_options(mut commands: Commands, fonts: Res<Fonts>) {
+  let mut text = overlay_text(&fonts);
+  text.style.position = Rect {
+    top: Val::Px(360.0),
+    left: Val::Px(40.0),
+    ..Default::default()
+  };
+  commands.spawn_bundle(text).insert(SurvivalSetupText);
+}
+
+fn survival_options_input(
+  keyboard_input: Res<Input<KeyCode>>,
+  mut config: ResMut<SurvivalConfig>,
+  mut query: Query<&mut Text, With<SurvivalSetupText>>,
+) {
+  if keyboard_input.just_pressed(KeyCode::Minus) {
+    config.apm = config.apm.saturating_sub(APM_STEP).max(APM_STEP);
+  }
+  if keyboard_input.just_pressed(KeyCode::Equals) {
+    config.apm = (config.apm + APM_STEP).min(MAX_APM);
+  }
+  let value = format!("SURVIVAL\ngarbage: {} APM\n\n[-] [=] APM", config.apm);
+  for mut text in query.iter_mut() {
+    text.sections[0].value = value.clone();
+  }
+}
+
+fn close_survival_options(
+  mut commands: Commands,
+  config: Res<SurvivalConfig>,
+  query: Query<Entity, With<SurvivalSetupText>>,
+) {
+  for entity in query.iter() {
+    commands.entity(entity).despawn();
+  }
+  if let Err(e) = save::store(SAVE_FILE, &*config) {
+    error!("failed to save survival config: {}", e);
+  }
+}
+
+fn survival_start(
+  config: Res<SurvivalConfig>,
+  ruleset: Res<Ruleset>,
+  mut survival: ResMut<Survival>,
+) {
+  *survival = Survival::new(&config, rand::random());
+  survival.tracker.table = ruleset.attack;
+}
+
+fn survival_reset(
+  config: Res<SurvivalConfig>,
+  ruleset: Res<Ruleset>,
+  mut survival: ResMut<Survival>,
+  mut reset_events: EventReader<GameReset>,
+) {
+  if reset_events.iter().count() > 0 {
+    *survival = Survival::new(&config, rand::random());
+    survival.tracker.table = ruleset.attack;
+  }
+}
+
+fn survival_record(
+  mut commands: Commands,
+  time: Res<Time>,
+  materials: Res<Materials>,
+  mut survival: ResMut<Survival>,
+  mut stats: ResMut<Statistics>,
+  mut stacked_events: EventReader<BlockStacked>,
+  mut cleared_events: EventReader<LinesCleared>,
+  mut raised_events: EventWriter<GarbageRaised>,
+  mut stacked_query: Query<&mut Position, With<StackedBlock>>,
+) {
+  survival.tick(time.delta_seconds_f64());
+
+  for _ in stacked_events.iter() {
+    survival.record_lock();
+    survival.waiting = Some(0);
+  }
+  for event in cleared_events.iter() {
+    survival.waiting = None;
+    survival.record_clear(event.0);
+  }
+
+  let waited = match survival.waiting.as_mut() {
+    Some(waited) => {
+      *waited += 1;
+      *waited
+    }
+    None => return,
+  };
+  if waited < CLEAR_WAIT_FRAMES {
+    return;
+  }
+  survival.waiting = None;
+
+  // ラインを消さずに積んだので、来ている分をせり上げる
+  let rows = survival.incoming.take();
+  if rows == 0 {
+    return;
+  }
+  stats.received += rows;
+  for mut position in stacked_query.iter_mut() {
+    position.y += rows as i32;
+  }
+  for y in 0..rows as i32 {
+    for cell in survival.generator.row(y) {
+      let entity = spawn_stacked_block(&mut commands, &materials, cell);
+      commands.entity(entity).insert(SurvivalGarbage);
+    }
+  }
+  raised_events.send(GarbageRaised(rows));
+}
+
+fn survival_ui(
+  state: Res<State<AppState>>,
+  mut survival: ResMut<Survival>,
+  mut stats: ResMut<Statistics>,
+  garbage_query: Query<&Position, With<SurvivalGarbage>>,
+  mut query: Query<&mut Text, With<SurvivalText>>,
+) {
+  let rows: HashSet<i32> = garbage_query.iter().map(|p| p.y).collect();
+  if survival.garbage_rows != rows.len() as u32 {
+    survival.garbage_rows = rows.len() as u32;
+    stats.garbage_left = survival.garbage_rows;
+  }
+  let value = if matches!(state.current(), AppState::Playing | AppState::Paused) {
+    format!(
+      "SURVIVAL  {} APM  {:.0}s\nincoming {}  downstack {:.0}%",
+      survival.apm,
+      survival.elapsed,
+      survival.incoming.rows(),
+      survival.efficiency() * 100.
+    )
+  } else {
+    String::new()
+  };
+  for mut text in query.iter_mut() {
+    if text.sections[0].value != value {
+      text.sections[0].value = value.clone();
+    }
+  }
+}
@@ -0,0 +1,62 @@
+use bevy::prelude::*;
+
+use crate::eventlog::GameLog;
+use crate::layout::{Anchor, Panel};
+use crate::{overlay_text, AppState, Fonts};
+
+// 並べるミノの数と、1 行に並べる数
+pub const TICKER_LEN: usize = 10;
+const PER_LINE: usize = 5;
+
+struct TickerText;
+
+// 盤面の下に、最近置いたミノを古い順に並べる。ラインを消したミノには消えた段数を付ける
+pub struct TickerPlugin;
+
+impl Plugin for TickerPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .add_startup_system_to_stage(StartupStage::PostStartup, setup_ticker.system())
+      .add_system(ticker_ui.system());
+  }
+}
+
+fn setup_ticker(mut commands: Commands, fonts: Res<Fonts>) {
+  commands
+    .spawn_bundle(overlay_text(&fonts))
+    .insert(TickerText)
+    .insert(Anchor(Panel::History));
+}
+
+// I4 のように、ラインを消したミノの後ろに段数を付ける
+pub fn ticker_text(drops: &[(char, u32)]) -> String {
+  let entries: Vec<String> = drops
+    .iter()
+    .map(|&(piece, lines)| match lines {
+      0 => piece.to_string(),
+      n => format!("{}{}", piece, n),
+    })
+    .collect();
+  entries
+    .chunks(PER_LINE)
+    .map(|line| line.join(" "))
+    .collect::<Vec<String>>()
+    .join("\n")
+}
+
+fn ticker_ui(
+  log: Res<GameLog>,
+  state: Res<State<AppState>>,
+  mut query: Query<&mut Text, With<TickerText>>,
+) {
+  if !log.is_changed() && !state.is_changed() {
+    return;
+  }
+  let value = match state.current() {
+    AppState::Playing | AppState::Paused => ticker_text(&log.recent_drops(TICKER_LEN)),
+    _ => String::new(),
+  };
+  for mut text in query.iter_mut() {
+    text.sections[0].value = value.clone();
+  }
+}
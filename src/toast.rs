@@ -0,0 +1,187 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use crate::achievements::{Achievement, AchievementUnlocked};
+use crate::config::Config;
+use crate::{Fonts, Materials};
+
+const TOAST_WIDTH: f32 = 220.0;
+const MARGIN: f32 = 10.0;
+// 出てくる・引っ込むのにかかる秒数と、止まって見せる秒数
+const SLIDE: f32 = 0.3;
+const HOLD: f32 = 2.5;
+
+// 画面の外から何割出ているか (0.0 から 1.0)
+pub fn toast_visibility(elapsed: f32) -> f32 {
+  let t = if elapsed < SLIDE {
+    elapsed / SLIDE
+  } else if elapsed < SLIDE + HOLD {
+    1.
+  } else {
+    1. - (elapsed - SLIDE - HOLD) / SLIDE
+  };
+  t.clamp(0., 1.)
+}
+
+fn toast_done(elapsed: f32) -> bool {
+  elapsed >= SLIDE * 2. + HOLD
+}
+
+// 表示待ちの実績。一度に1つずつ出す
+#[derive(Default)]
+struct ToastQueue {
+  pending: VecDeque<Achievement>,
+  elapsed: f32,
+}
+
+struct Toast {
+  // 動きを減らす設定のときに薄くする、このトースト専用の背景
+  material: Handle<ColorMaterial>,
+  alpha: f32,
+}
+
+// 薄くするときに色を変える文字
+struct ToastText;
+
+pub struct ToastPlugin;
+
+impl Plugin for ToastPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(ToastQueue::default())
+      .add_system(queue_toasts.system())
+      .add_system(show_toasts.system());
+  }
+}
+
+fn queue_toasts(
+  mut queue: ResMut<ToastQueue>,
+  mut unlocked_events: EventReader<AchievementUnlocked>,
+) {
+  for event in unlocked_events.iter() {
+    queue.pending.push_back(event.0);
+  }
+}
+
+fn spawn_toast(
+  commands: &mut Commands,
+  fonts: &Fonts,
+  material: Handle<ColorMaterial>,
+  alpha: f32,
+  achievement: Achievement,
+) {
+  let style = |size: f32, color: Color| TextStyle {
+    font: fonts.main.clone(),
+    font_size: size,
+    color,
+  };
+  commands
+    .spawn_bundle(NodeBundle {
+      style: Style {
+        position_type: PositionType::Absolute,
+        position: Rect {
+          top: Val::Px(MARGIN),
+          right: Val::Px(-TOAST_WIDTH),
+          ..Default::default()
+        },
+        size: Size::new(Val::Px(TOAST_WIDTH), Val::Auto),
+        padding: Rect::all(Val::Px(8.0)),
+        align_items: AlignItems::Center,
+        ..Default::default()
+      },
+      material: material.clone(),
+      ..Default::default()
+    })
+    .insert(Toast { material, alpha })
+    .with_children(|parent| {
+      parent
+        .spawn_bundle(TextBundle {
+          style: Style {
+            margin: Rect {
+              right: Val::Px(8.0),
+              ..Default::default()
+            },
+            ..Default::default()
+          },
+          text: Text::with_section(
+            "★",
+            style(28.0, Color::rgb(1.0, 0.8, 0.2)),
+            Default::default(),
+          ),
+          ..Default::default()
+        })
+        .insert(ToastText);
+      parent
+        .spawn_bundle(TextBundle {
+          text: Text {
+            sections: vec![
+              TextSection {
+                value: "Achievement unlocked\n".to_string(),
+                style: style(12.0, Color::rgb(0.7, 0.7, 0.7)),
+              },
+              TextSection {
+                value: format!("{}\n", achievement.name()),
+                style: style(16.0, Color::WHITE),
+              },
+              TextSection {
+                value: achievement.reward().name(),
+                style: style(12.0, Color::rgb(0.4, 0.7, 0.9)),
+              },
+            ],
+            ..Default::default()
+          },
+          ..Default::default()
+        })
+        .insert(ToastText);
+    });
+}
+
+// ゲームは止めずに、右上から出して引っ込める。動きを減らす設定ならその場で濃くして薄くする
+#[allow(clippy::too_many_arguments)]
+fn show_toasts(
+  mut commands: Commands,
+  time: Res<Time>,
+  config: Res<Config>,
+  fonts: Res<Fonts>,
+  materials: Res<Materials>,
+  mut color_materials: ResMut<Assets<ColorMaterial>>,
+  mut queue: ResMut<ToastQueue>,
+  mut query: Query<(Entity, &Toast, &mut Style)>,
+  mut text_query: Query<&mut Text, With<ToastText>>,
+) {
+  let (entity, toast, mut style) = match query.iter_mut().next() {
+    Some(toast) => toast,
+    None => {
+      if let Some(achievement) = queue.pending.pop_front() {
+        queue.elapsed = 0.;
+        let panel = color_materials
+          .get(&materials.panel)
+          .map_or(Color::BLACK, |m| m.color);
+        let material = color_materials.add(panel.into());
+        spawn_toast(&mut commands, &fonts, material, panel.a(), achievement);
+      }
+      return;
+    }
+  };
+  queue.elapsed += time.delta_seconds();
+  if toast_done(queue.elapsed) {
+    commands.entity(entity).despawn_recursive();
+    return;
+  }
+  let shown = toast_visibility(queue.elapsed);
+  let (slide, opacity) = if config.reduce_motion {
+    (1., shown)
+  } else {
+    (shown, 1.)
+  };
+  style.position.right = Val::Px(MARGIN - (TOAST_WIDTH + MARGIN) * (1. - slide));
+  if let Some(material) = color_materials.get_mut(&toast.material) {
+    material.color.set_a(toast.alpha * opacity);
+  }
+  for mut text in text_query.iter_mut() {
+    for section in text.sections.iter_mut() {
+      section.style.color.set_a(opacity);
+    }
+  }
+}
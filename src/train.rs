@@ -0,0 +1,115 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+
+use crate::ai::Weights;
+use crate::exhibition::Exhibition;
+use crate::victory::MostLines;
+
+// 自己対戦で評価の重みを調べるときの設定
+#[derive(Clone, Copy, Debug)]
+pub struct TrainConfig {
+  pub rounds: u32,
+  // 1 ラウンドで試す、今の最良を少し動かした重みの数
+  pub candidates: u32,
+  // 1 つの重みにつき今の最良と戦う数。席は 1 戦ごとに入れ替える
+  pub games: u32,
+  // 1 戦の長さ。この間に多くラインを消した方の勝ち
+  pub seconds: f32,
+  // 重みを動かす幅
+  pub step: f32,
+  pub seed: u64,
+}
+
+impl Default for TrainConfig {
+  fn default() -> Self {
+    Self {
+      rounds: 20,
+      candidates: 8,
+      games: 16,
+      seconds: 120.,
+      step: 0.1,
+      seed: 0,
+    }
+  }
+}
+
+// 1 ラウンドの結果。勝率は今の最良に対するもの
+#[derive(Clone, Copy, Debug)]
+pub struct Round {
+  pub round: u32,
+  pub best: Weights,
+  pub win_rate: f32,
+  pub improved: bool,
+}
+
+// challenger が champion に勝った割合。対戦は並べて進める
+pub fn win_rate(challenger: &Weights, champion: &Weights, config: &TrainConfig, seed: u64) -> f32 {
+  let wins: u32 = (0..config.games)
+    .into_par_iter()
+    .map(|game| {
+      // 同じ並びで先に置く側の有利が出ないよう、席を入れ替える
+      let seat = (game % 2) as usize;
+      let weights = if seat == 0 {
+        [*challenger, *champion]
+      } else {
+        [*champion, *challenger]
+      };
+      let condition = Box::new(MostLines {
+        seconds: config.seconds,
+      });
+      let mut exhibition = Exhibition::new(&weights, seed.wrapping_add(game as u64 / 2), condition);
+      let placings = exhibition.run().unwrap_or_default();
+      (placings.first() == Some(&seat)) as u32
+    })
+    .sum();
+  wins as f32 / config.games.max(1) as f32
+}
+
+// それぞれの重みを最大 step だけ動かす。符号が変わると意味が逆になるので 0 で止める
+pub fn mutate(weights: &Weights, step: f32, rng: &mut impl Rng) -> Weights {
+  let mut nudge = |value: f32| {
+    let next = value + rng.gen_range(-step..=step);
+    if value < 0. {
+      next.min(0.)
+    } else {
+      next.max(0.)
+    }
+  };
+  Weights {
+    height: nudge(weights.height),
+    lines: nudge(weights.lines),
+    holes: nudge(weights.holes),
+    bumpiness: nudge(weights.bumpiness),
+    tetris: nudge(weights.tetris),
+  }
+}
+
+// 山登り。毎ラウンド候補を作って今の最良と戦わせ、一番勝ち越した候補に置き換える
+pub fn train(start: Weights, config: &TrainConfig, mut report: impl FnMut(&Round)) -> Weights {
+  let mut rng = StdRng::seed_from_u64(config.seed);
+  let mut best = start;
+  for round in 0..config.rounds {
+    let candidates: Vec<Weights> = (0..config.candidates)
+      .map(|_| mutate(&best, config.step, &mut rng))
+      .collect();
+    // ラウンドごとに並びを変えて、たまたま強い並びに合わせ込まないようにする
+    let seed = rng.gen();
+    let results: Vec<(Weights, f32)> = candidates
+      .par_iter()
+      .map(|candidate| (*candidate, win_rate(candidate, &best, config, seed)))
+      .collect();
+    let (winner, rate) = results
+      .into_iter()
+      .fold((best, 0.5), |a, b| if b.1 > a.1 { b } else { a });
+    let improved = winner != best;
+    best = winner;
+    report(&Round {
+      round,
+      best,
+      win_rate: rate,
+      improved,
+    });
+  }
+  best
+}
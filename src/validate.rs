@@ -0,0 +1,124 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+
+use crate::board::Board;
+use crate::snapshot::board_text;
+use crate::{
+  block_name, layout, ActiveBlock, AppState, Position, PrimitiveBlock, StackedBlock, ARENA_WIDTH,
+};
+
+// 1 つのミノのマス数
+const PIECE_CELLS: usize = 4;
+
+// 盤面の決まりごとを確かめて、破れているものを並べる。空なら問題なし
+// just_spawned は出たばかりのミノ。積まれたブロックと重なっていたら次のフレームで top_out が拾う
+pub fn check_board(
+  stacked: &[Position],
+  active: &[Position],
+  is_on: bool,
+  just_spawned: bool,
+) -> Vec<String> {
+  let mut errors = vec![];
+  let mut seen = HashSet::new();
+  for pos in stacked.iter() {
+    if pos.x < 0 || pos.x >= ARENA_WIDTH as i32 || pos.y < 0 {
+      errors.push(format!(
+        "stacked cell ({}, {}) is outside the board",
+        pos.x, pos.y
+      ));
+    }
+    if !seen.insert(pos) {
+      errors.push(format!("two stacked cells at ({}, {})", pos.x, pos.y));
+    }
+  }
+  // 行のビットマスクに直したマスの数が entity の数と合うか
+  let board = Board::from_cells(stacked.iter());
+  let filled: u32 = board.rows().iter().map(|row| row.count_ones()).sum();
+  if filled as usize != stacked.len() {
+    errors.push(format!(
+      "board grid has {} cells but there are {} stacked entities",
+      filled,
+      stacked.len()
+    ));
+  }
+
+  let expected = if is_on { PIECE_CELLS } else { 0 };
+  if active.len() != expected {
+    errors.push(format!(
+      "active piece has {} cells, expected {}",
+      active.len(),
+      expected
+    ));
+  }
+  let mut active_seen = HashSet::new();
+  for pos in active.iter() {
+    if !active_seen.insert(pos) {
+      errors.push(format!("two active cells at ({}, {})", pos.x, pos.y));
+    }
+    if pos.x < 0 || pos.x >= ARENA_WIDTH as i32 || pos.y < 0 {
+      errors.push(format!(
+        "active cell ({}, {}) is outside the board",
+        pos.x, pos.y
+      ));
+    }
+    if seen.contains(pos) && !just_spawned {
+      errors.push(format!(
+        "active cell ({}, {}) overlaps the stack",
+        pos.x, pos.y
+      ));
+    }
+  }
+  errors
+}
+
+pub struct ValidatePlugin;
+
+impl Plugin for ValidatePlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    // コマンドと position_translation が済んだ後に見る
+    app.add_system_to_stage(CoreStage::Last, validate_board.system());
+  }
+}
+
+// debug ビルドだけで毎フレーム回す。壊れていたら盤面を書き出して止める
+fn validate_board(
+  state: Res<State<AppState>>,
+  layout: Res<layout::Layout>,
+  active_block: Res<ActiveBlock>,
+  spawned_query: Query<(), Added<PrimitiveBlock>>,
+  stacked_query: Query<(&Position, &Transform), With<StackedBlock>>,
+  active_query: Query<(&Position, &Transform), With<PrimitiveBlock>>,
+) {
+  if !matches!(state.current(), AppState::Playing | AppState::Paused) {
+    return;
+  }
+  let stacked: Vec<Position> = stacked_query.iter().map(|(p, _)| p.clone()).collect();
+  let active: Vec<Position> = active_query.iter().map(|(p, _)| p.clone()).collect();
+  let just_spawned = spawned_query.iter().next().is_some();
+  let mut errors = check_board(&stacked, &active, active_block.is_on(), just_spawned);
+  // 描いている位置が盤面の座標とずれていないか
+  for (pos, transform) in stacked_query.iter().chain(active_query.iter()) {
+    if transform.translation.truncate() != layout.world_position(pos) {
+      errors.push(format!(
+        "cell ({}, {}) is drawn at {:?}",
+        pos.x, pos.y, transform.translation
+      ));
+    }
+  }
+  if errors.is_empty() {
+    return;
+  }
+  let piece = Some(active_block.block_idx)
+    .filter(|_| active_block.is_on())
+    .map(block_name);
+  panic!(
+    "board invariant violated:\n  {}\nstacked: {:?}\nactive: {:?} (piece {:?}, rotation {})\n{}",
+    errors.join("\n  "),
+    stacked,
+    active,
+    piece,
+    active_block.rotation,
+    board_text(&Board::from_cells(stacked.iter()), &active, piece)
+  );
+}
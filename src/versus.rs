@@ -0,0 +1,212 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+use rand::Rng;
+
+use crate::attack::{AttackTracker, Clear};
+use crate::eventlog::clear_kind;
+use crate::garbage::{self, GarbageConfig, GarbageGenerator, GarbageQueue};
+use crate::rules::Ruleset;
+use crate::stats::Statistics;
+use crate::{
+  overlay_text, spawn_stacked_block, AppState, BlockStacked, Fonts, GameReset, GarbageRaised,
+  LinesCleared, Materials, Position, StackedBlock,
+};
+
+// ボットが攻撃してくる間隔 (秒) と、1回の最大ライン数
+const BOT_INTERVAL: f32 = 8.0;
+const BOT_MAX_LINES: u32 = 4;
+// 積んでからライン消去の結果を待つフレーム数
+const CLEAR_WAIT_FRAMES: u32 = 2;
+// 消去の判定を出しておく秒数
+const MESSAGE_SECONDS: f32 = 2.0;
+
+// ボットから送られたせり上がり
+struct VersusGarbage;
+
+struct VersusText;
+
+// ガベージを送ってくるボットとの練習試合
+struct Versus {
+  tracker: AttackTracker,
+  bot_timer: Timer,
+  generator: GarbageGenerator,
+  incoming: GarbageQueue,
+  sent: u32,
+  // 積んでから待ったフレーム数。消去が来なければせり上がる
+  waiting: Option<u32>,
+  message: Option<(String, f32)>,
+}
+
+impl Default for Versus {
+  fn default() -> Self {
+    Self {
+      tracker: AttackTracker::default(),
+      bot_timer: Timer::from_seconds(BOT_INTERVAL, true),
+      generator: GarbageGenerator::new(&GarbageConfig::default(), rand::random()),
+      incoming: GarbageQueue::default(),
+      sent: 0,
+      waiting: None,
+      message: None,
+    }
+  }
+}
+
+// 消去1回の判定。無駄なシングル・ダブルは目立たせる
+pub fn describe_clear(clear: &Clear) -> String {
+  let mut text = if clear.wasteful {
+    format!("wasteful {}", clear_kind(clear.lines))
+  } else {
+    clear_kind(clear.lines).to_string()
+  };
+  if clear.back_to_back {
+    text = format!("back-to-back {}", text);
+  }
+  if clear.combo > 0 {
+    text += &format!("  {} combo", clear.combo);
+  }
+  format!("{}  +{}", text, clear.attack)
+}
+
+pub struct VersusPlugin;
+
+impl Plugin for VersusPlugin {
+  fn build(&self, app: &mut AppBuilder) {
+    app
+      .insert_resource(Versus::default())
+      .add_startup_system_to_stage(StartupStage::PostStartup, setup_versus.system())
+      .add_system_set(SystemSet::on_enter(AppState::Playing).with_system(versus_start.system()))
+      .add_system_set(
+        SystemSet::on_update(AppState::Playing)
+          .with_system(bot_attack.system())
+          .with_system(versus_record.system()),
+      )
+      .add_system(versus_reset.system())
+      .add_system(versus_ui.system());
+  }
+}
+
+fn setup_versus(mut commands: Commands, fonts: Res<Fonts>) {
+  commands
+    .spawn_bundle(overlay_text(&fonts))
+    .insert(VersusText);
+}
+
+fn versus_start(ruleset: Res<Ruleset>, mut versus: ResMut<Versus>) {
+  *versus = Versus::default();
+  versus.tracker.table = ruleset.attack;
+}
+
+fn versus_reset(
+  ruleset: Res<Ruleset>,
+  mut versus: ResMut<Versus>,
+  mut reset_events: EventReader<GameReset>,
+) {
+  if reset_events.iter().count() > 0 {
+    *versus = Versus::default();
+    versus.tracker.table = ruleset.attack;
+  }
+}
+
+fn bot_attack(time: Res<Time>, mut versus: ResMut<Versus>) {
+  if versus.bot_timer.tick(time.delta()).just_finished() {
+    let rows = rand::thread_rng().gen_range(1..=BOT_MAX_LINES);
+    versus.incoming.push(rows);
+  }
+}
+
+fn versus_record(
+  mut commands: Commands,
+  time: Res<Time>,
+  materials: Res<Materials>,
+  mut versus: ResMut<Versus>,
+  mut stats: ResMut<Statistics>,
+  mut stacked_events: EventReader<BlockStacked>,
+  mut cleared_events: EventReader<LinesCleared>,
+  mut raised_events: EventWriter<GarbageRaised>,
+  mut stacked_query: Query<&mut Position, With<StackedBlock>>,
+) {
+  if let Some((_, remaining)) = versus.message.as_mut() {
+    *remaining -= time.delta_seconds();
+    if *remaining <= 0. {
+      versus.message = None;
+    }
+  }
+
+  for _ in stacked_events.iter() {
+    versus.tracker.record_lock();
+    versus.waiting = Some(0);
+  }
+  for event in cleared_events.iter() {
+    versus.waiting = None;
+    let clear = versus.tracker.record_clear(event.0);
+    // 攻撃はまず来ているせり上がりの相殺に使う
+    let sent = versus.incoming.cancel(clear.attack);
+    versus.sent += sent;
+    stats.sent += sent;
+    versus.message = Some((describe_clear(&clear), MESSAGE_SECONDS));
+  }
+
+  let waited = match versus.waiting.as_mut() {
+    Some(waited) => {
+      *waited += 1;
+      *waited
+    }
+    None => return,
+  };
+  if waited < CLEAR_WAIT_FRAMES {
+    return;
+  }
+  versus.waiting = None;
+
+  // ラインを消さずに積んだので、来ている分をせり上げる
+  let rows = versus.incoming.take();
+  if rows == 0 {
+    return;
+  }
+  stats.received += rows;
+  for mut position in stacked_query.iter_mut() {
+    position.y += rows as i32;
+  }
+  for y in 0..rows as i32 {
+    for cell in versus.generator.row(y) {
+      let entity = spawn_stacked_block(&mut commands, &materials, cell);
+      commands.entity(entity).insert(VersusGarbage);
+    }
+  }
+  raised_events.send(GarbageRaised(rows));
+}
+
+fn versus_ui(
+  versus: Res<Versus>,
+  mut stats: ResMut<Statistics>,
+  garbage_query: Query<&Position, With<VersusGarbage>>,
+  mut query: Query<&mut Text, With<VersusText>>,
+) {
+  // 消したラインのうち、せり上がりを掘った割合
+  let remaining: HashSet<i32> = garbage_query.iter().map(|p| p.y).collect();
+  if stats.garbage_left != remaining.len() as u32 {
+    stats.garbage_left = remaining.len() as u32;
+  }
+  let downstack = garbage::efficiency(
+    versus.incoming.received(),
+    stats.garbage_left,
+    versus.tracker.lines,
+  ) * 100.;
+  let mut value = format!(
+    "VERSUS PRACTICE\nincoming {}  sent {}\nAPL {:.2}  downstack {:.0}%\nwasteful clears {}",
+    versus.incoming.rows(),
+    versus.sent,
+    versus.tracker.attack_per_line(),
+    downstack,
+    versus.tracker.wasteful,
+  );
+  if let Some((message, _)) = versus.message.as_ref() {
+    value += &format!("\n\n{}", message);
+  }
+  for mut text in query.iter_mut() {
+    if text.sections[0].value != value {
+      text.sections[0].value = value.clone();
+    }
+  }
+}
@@ -0,0 +1,126 @@
+// 対戦の途中経過。勝敗の判定に使う
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct Standing {
+  pub alive: bool,
+  pub lines: u32,
+  pub tetrises: u32,
+}
+
+pub struct MatchView<'a> {
+  pub elapsed: f32,
+  pub standings: &'a [Standing],
+  // 脱落した順
+  pub eliminated: &'a [usize],
+}
+
+impl<'a> MatchView<'a> {
+  fn alive(&self) -> usize {
+    self.standings.iter().filter(|s| s.alive).count()
+  }
+
+  // 生き残っている方が上、脱落した中では後まで残った方が上
+  fn survival_order(&self) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..self.standings.len())
+      .filter(|&i| self.standings[i].alive)
+      .collect();
+    order.extend(self.eliminated.iter().rev());
+    order
+  }
+
+  // key の大きい順。同じなら生き残った順
+  fn order_by(&self, key: impl Fn(&Standing) -> u32) -> Vec<usize> {
+    let mut order = self.survival_order();
+    order.sort_by_key(|&i| std::cmp::Reverse(key(&self.standings[i])));
+    order
+  }
+}
+
+// 勝敗の決め方。対戦中は毎フレーム check して、決着が付いたら 1 位から順の並びを返す
+pub trait VictoryCondition: Send + Sync {
+  fn name(&self) -> String;
+  fn check(&self, view: &MatchView) -> Option<Vec<usize>>;
+  // 対戦中に出す残り時間や目標
+  fn status(&self, view: &MatchView) -> String;
+}
+
+// 最後まで残った人の勝ち
+pub struct LastStanding;
+
+impl VictoryCondition for LastStanding {
+  fn name(&self) -> String {
+    "last one standing".to_string()
+  }
+
+  fn check(&self, view: &MatchView) -> Option<Vec<usize>> {
+    if view.alive() > 1 {
+      return None;
+    }
+    Some(view.survival_order())
+  }
+
+  fn status(&self, view: &MatchView) -> String {
+    format!("{} left", view.alive())
+  }
+}
+
+// 時間内に一番ラインを消した人の勝ち。脱落してもそれまでのラインで数える
+pub struct MostLines {
+  pub seconds: f32,
+}
+
+impl VictoryCondition for MostLines {
+  fn name(&self) -> String {
+    format!("most lines in {}", format_time(self.seconds))
+  }
+
+  fn check(&self, view: &MatchView) -> Option<Vec<usize>> {
+    if view.elapsed < self.seconds && view.alive() > 1 {
+      return None;
+    }
+    Some(view.order_by(|s| s.lines))
+  }
+
+  fn status(&self, view: &MatchView) -> String {
+    format!("{} left", format_time(self.seconds - view.elapsed))
+  }
+}
+
+// 先に決まった回数テトリスを決めた人の勝ち。それまでに1人になったら残った人の勝ち
+pub struct FirstToTetrises {
+  pub count: u32,
+}
+
+impl VictoryCondition for FirstToTetrises {
+  fn name(&self) -> String {
+    format!("first to {} tetrises", self.count)
+  }
+
+  fn check(&self, view: &MatchView) -> Option<Vec<usize>> {
+    if view.standings.iter().any(|s| s.tetrises >= self.count) {
+      return Some(view.order_by(|s| s.tetrises));
+    }
+    if view.alive() <= 1 {
+      return Some(view.survival_order());
+    }
+    None
+  }
+
+  fn status(&self, view: &MatchView) -> String {
+    let best = view.standings.iter().map(|s| s.tetrises).max().unwrap_or(0);
+    format!("best {}/{} tetrises", best, self.count)
+  }
+}
+
+// 対戦の設定画面で選べる決め方
+pub fn victory_conditions() -> Vec<Box<dyn VictoryCondition>> {
+  vec![
+    Box::new(LastStanding),
+    Box::new(MostLines { seconds: 180.0 }),
+    Box::new(FirstToTetrises { count: 7 }),
+  ]
+}
+
+fn format_time(seconds: f32) -> String {
+  let seconds = seconds.max(0.).ceil() as u32;
+  format!("{}:{:02}", seconds / 60, seconds % 60)
+}
@@ -0,0 +1,53 @@
+// 記録の共有コードと通信のメッセージで使う、可変長の数値の読み書き
+
+// 7ビットずつ、続きがあれば最上位ビットを立てる
+pub fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+  while value >= 0x80 {
+    out.push((value as u8 & 0x7f) | 0x80);
+    value >>= 7;
+  }
+  out.push(value as u8);
+}
+
+pub struct Reader<'a> {
+  bytes: &'a [u8],
+  pos: usize,
+}
+
+impl<'a> Reader<'a> {
+  pub fn new(bytes: &'a [u8]) -> Self {
+    Reader { bytes, pos: 0 }
+  }
+
+  pub fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+    // 長さは相手から届いた数なので、足し算のあふれも切れたデータとして扱う
+    let end = self
+      .pos
+      .checked_add(n)
+      .filter(|&end| end <= self.bytes.len())
+      .ok_or("data is cut off")?;
+    let bytes = &self.bytes[self.pos..end];
+    self.pos = end;
+    Ok(bytes)
+  }
+
+  pub fn byte(&mut self) -> Result<u8, String> {
+    Ok(self.take(1)?[0])
+  }
+
+  pub fn signed(&mut self) -> Result<i32, String> {
+    Ok(self.byte()? as i8 as i32)
+  }
+
+  pub fn varint(&mut self) -> Result<u64, String> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+      let byte = self.byte()?;
+      value |= ((byte & 0x7f) as u64) << shift;
+      if byte & 0x80 == 0 {
+        return Ok(value);
+      }
+    }
+    Err("bad number".to_string())
+  }
+}